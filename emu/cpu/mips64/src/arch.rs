@@ -57,6 +57,7 @@ impl Arch for ArchI {
             return false;
         }
         // ArchII-only instructions not available in ArchI
+        #[allow(clippy::match_like_matches_macro)]
         match op {
             "beql" => false,
             "bnel" => false,
@@ -66,6 +67,18 @@ impl Arch for ArchI {
             "blezl" => false,
             "btlzall" => false,
             "bgezall" => false,
+            "tge" => false,
+            "tgeu" => false,
+            "tlt" => false,
+            "tltu" => false,
+            "teq" => false,
+            "tne" => false,
+            "tgei" => false,
+            "tgeiu" => false,
+            "tlti" => false,
+            "tltiu" => false,
+            "teqi" => false,
+            "tnei" => false,
             _ => true,
         }
     }
@@ -0,0 +1,153 @@
+use super::cpu::Cpu;
+use super::icache::OpFn;
+use super::Config;
+
+use emu::bus::be::Bus;
+use emu::dbg::CacheStats;
+use emu::int::Numerics;
+use emu::state;
+use std::rc::Rc;
+
+const PAGE_SHIFT: usize = 12;
+const PAGE_INSNS: usize = 1 << (PAGE_SHIFT - 2);
+
+/// A basic block: a sequence of decoded instructions, found at contiguous
+/// (physical) addresses.
+pub(crate) struct Block<C: Config> {
+    pub ops: Vec<(u32, OpFn<C>)>, // opcode, and the function executing it
+}
+
+/// BlockCache holds basic blocks of predecoded instructions, indexed by the
+/// (physical) address of their first instruction, and organized in pages of
+/// 4KB of memory like DecodeCache.
+///
+/// A block ends after a branch or jump and its delay slot, or at the end of
+/// a page, so that executing it does not require to fetch or decode any
+/// opcode. Unlike DecodeCache, entries are not checked against memory:
+/// each page holding blocks is watched on the bus (see Bus::watch_writes),
+/// and all its blocks are dropped when it is written, either by the CPU or
+/// by a DMA. All blocks are also dropped when the state is replaced
+/// (eg: loading a savestate).
+pub(crate) struct BlockCache<C: Config> {
+    pages: Vec<Option<Box<[Option<Rc<Block<C>>>]>>>,
+    state_id: u32, // state the blocks were decoded from
+    stats: CacheStats,
+}
+
+impl<C: Config> Default for BlockCache<C> {
+    fn default() -> Self {
+        BlockCache {
+            pages: Vec::new(),
+            state_id: state::state_id(),
+            stats: CacheStats::default(),
+        }
+    }
+}
+
+impl<C: Config> BlockCache<C> {
+    /// Return the block starting at the specified (physical) address,
+    /// decoding it if the cache does not hold it yet.
+    #[inline(always)]
+    pub fn get(&mut self, bus: &mut Bus, addr: u32) -> Rc<Block<C>> {
+        self.sync(bus);
+
+        let page = addr as usize >> PAGE_SHIFT;
+        let idx = (addr as usize >> 2) & (PAGE_INSNS - 1);
+        if let Some(Some(entries)) = self.pages.get(page) {
+            if let Some(block) = &entries[idx] {
+                self.stats.hits += 1;
+                return block.clone();
+            }
+        }
+
+        self.stats.misses += 1;
+        let block = Rc::new(Self::decode(bus, addr, PAGE_INSNS - idx));
+        self.stats.blocks += 1;
+        self.stats.insns += block.ops.len() as u64;
+
+        while page >= self.pages.len() {
+            self.pages.push(None);
+        }
+        let entries = self.pages[page].get_or_insert_with(|| {
+            bus.watch_writes(addr);
+            vec![None; PAGE_INSNS].into_boxed_slice()
+        });
+        entries[idx] = Some(block.clone());
+        block
+    }
+
+    /// Drop the blocks whose memory was written since the last call, and all
+    /// of them if the state was replaced.
+    pub fn sync(&mut self, bus: &mut Bus) {
+        let sid = state::state_id();
+        if sid != self.state_id {
+            self.state_id = sid;
+            bus.unwatch_all();
+            for page in 0..self.pages.len() {
+                self.invalidate_page(page);
+            }
+        } else if bus.has_written_pages() {
+            for addr in bus.take_written_pages() {
+                self.invalidate_page(addr as usize >> PAGE_SHIFT);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// Reset the hit/miss counters; the other statistics describe the
+    /// contents of the cache, so they are preserved.
+    pub fn reset_stats(&mut self) {
+        self.stats.hits = 0;
+        self.stats.misses = 0;
+        self.stats.invalidations = 0;
+    }
+
+    fn invalidate_page(&mut self, page: usize) {
+        let entries = match self.pages.get_mut(page).and_then(|p| p.take()) {
+            Some(entries) => entries,
+            None => return,
+        };
+        for block in entries.iter().flatten() {
+            self.stats.invalidations += 1;
+            self.stats.blocks -= 1;
+            self.stats.insns -= block.ops.len() as u64;
+        }
+    }
+
+    // Decode the block starting at the specified address, made of at most
+    // max_insns instructions.
+    fn decode(bus: &Bus, addr: u32, max_insns: usize) -> Block<C> {
+        let mem = bus.fetch_read::<u32>(addr);
+        let iter = mem
+            .iter()
+            .unwrap_or_else(|| panic!("jumped to non-linear memory: {}", addr.hex()));
+
+        let mut ops = Vec::new();
+        let mut delay_slot = false;
+        for op in iter.take(max_insns) {
+            ops.push((op, Cpu::<C>::decode_op(op)));
+            if delay_slot {
+                break;
+            }
+            delay_slot = ends_block(op);
+        }
+        Block { ops }
+    }
+}
+
+// Return true if the opcode is a branch or a jump, and thus ends a block
+// (after its delay slot).
+fn ends_block(opcode: u32) -> bool {
+    match opcode >> 26 {
+        0x00 => match opcode & 0x3F {
+            0x08 | 0x09 => true, // JR, JALR
+            _ => false,
+        },
+        0x01..=0x07 | 0x14..=0x17 => true,
+        0x11 => (opcode >> 21) & 0x1F == 0x08, // BC1
+        _ => false,
+    }
+}
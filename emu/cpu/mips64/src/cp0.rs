@@ -2,13 +2,16 @@ use bitfield::bitfield;
 
 use super::decode::{DecodedInsn, REG_NAMES};
 use super::{Cop, Cop0, CpuContext, Exception};
-use emu::dbg::{DebuggerRenderer, Operand, RegisterSize, RegisterView, Result, Tracer};
+#[cfg(feature = "debugger")]
+use emu::dbg::DebuggerRenderer;
+use emu::dbg::{Operand, RegisterSize, RegisterView, Result, Tracer};
 use emu::int::Numerics;
+use emu::irq::{InterruptController, IrqLine};
 use emu::state::Field;
+use emu::sync;
 use serde_derive::{Deserialize, Serialize};
-use slog;
 
-const COP0_REG_NAMES: [&'static str; 32] = [
+const COP0_REG_NAMES: [&str; 32] = [
     "Index",
     "Random",
     "EntryLo0",
@@ -86,14 +89,24 @@ struct Cp0Context {
     reg_entryhi: u64,
     reg_entrylo0: u64,
     reg_entrylo1: u64,
+    reg_wired: u32,
+    reg_context: u64,
+    reg_xcontext: u64,
+    reg_badvaddr: u64,
     reg_compare: u32,
+    random_clock: i64, // clock at which Random was last reset to 31
     last_count: u32,
     last_count_clock: i64,
     next_timer_interrupt: i64,
 }
 
+// Interrupt line of the COP0 timer (IP7).
+const TIMER_IRQ_LINE: usize = 7;
+
 pub struct Cp0 {
     ctx: Field<Cp0Context>,
+    irq: InterruptController, // Interrupt pending (IP) lines, masked by IM
+    hwint: Vec<(usize, sync::IrqLine)>, // Connected hardware interrupt lines
     logger: slog::Logger,
     name: &'static str,
 }
@@ -102,11 +115,55 @@ impl Cp0 {
     pub fn new(name: &'static str, logger: slog::Logger) -> Cp0 {
         Cp0 {
             ctx: Field::new(&("mips64::cp0::".to_owned() + name), Cp0Context::default()),
-            logger: logger,
+            irq: InterruptController::new(
+                name,
+                vec![
+                    IrqLine::level("IP0 (sw)"),
+                    IrqLine::level("IP1 (sw)"),
+                    IrqLine::level("IP2"),
+                    IrqLine::level("IP3"),
+                    IrqLine::level("IP4"),
+                    IrqLine::level("IP5"),
+                    IrqLine::level("IP6"),
+                    IrqLine::edge("IP7 (timer)"),
+                ],
+            ),
+            hwint: Vec::new(),
+            logger,
             name,
         }
     }
 
+    /// Report raised interrupts to the debugger (once per scanline).
+    pub fn trace_irq(&mut self, tracer: &Tracer) {
+        self.irq.trace(tracer);
+    }
+
+    // Reflect the pending interrupts into the IP field of Cause.
+    fn update_ip(&mut self) {
+        let ip = self.irq.pending();
+        self.ctx.reg_cause.set_ip(ip);
+    }
+
+    // Cause, with the current level of the connected hardware interrupt
+    // lines (which are otherwise sampled only when polling interrupts).
+    fn get_cause(&self) -> RegCause {
+        let mut cause = self.ctx.reg_cause;
+        let mut ip = cause.ip();
+        for (line, irq) in self.hwint.iter() {
+            ip = ip & !(1 << (line + 2)) | (irq.get() as u32) << (line + 2);
+        }
+        cause.set_ip(ip);
+        cause
+    }
+
+    // Random decrements at each cycle, from 31 down to Wired, and then wraps
+    // back to 31.
+    fn get_random(&self, cpu: &CpuContext) -> u32 {
+        let range = 32 - self.ctx.reg_wired.min(31) as i64;
+        31 - ((cpu.clock - self.ctx.random_clock).rem_euclid(range) as u32)
+    }
+
     fn get_count(&self, cpu: &CpuContext) -> u32 {
         self.ctx
             .last_count
@@ -126,8 +183,9 @@ impl Cp0 {
         self.ctx.reg_compare = val;
         self.update_timer_interrupt(cpu);
 
-        // Writing compare also clears the IRQ line (IP5 in Cause)
-        self.set_hwint_line(5, false);
+        // Writing compare also acknowledges the timer interrupt (IP7 in Cause)
+        self.irq.ack(TIMER_IRQ_LINE);
+        self.update_ip();
     }
 
     fn update_timer_interrupt(&mut self, cpu: &CpuContext) {
@@ -147,26 +205,34 @@ impl Cp0 {
 impl Cop0 for Cp0 {
     #[inline(always)]
     fn set_hwint_line(&mut self, line: usize, status: bool) {
-        let mut ip = self.ctx.reg_cause.ip();
-        let mask = 1 << (line + 2);
-        let val = (status as u32) << (line + 2);
+        // Hardware interrupts start at IP2.
+        self.irq.set_line(line + 2, status);
+        self.update_ip();
+    }
 
-        ip = (ip & !mask) | (val & mask);
-        self.ctx.reg_cause.set_ip(ip);
+    fn connect_hwint_line(&mut self, line: usize, irq: sync::IrqLine) {
+        self.hwint.push((line, irq));
     }
 
     #[inline(always)]
     fn poll_interrupts(&mut self, cpu: &mut CpuContext) {
         let ctx = unsafe { self.ctx.as_mut() };
+        if !self.hwint.is_empty() {
+            for (line, irq) in self.hwint.iter() {
+                self.irq.set_line(line + 2, irq.get());
+            }
+            self.update_ip();
+        }
         if cpu.clock >= ctx.next_timer_interrupt {
-            self.set_hwint_line(5, true);
+            self.irq.pulse(TIMER_IRQ_LINE);
+            self.update_ip();
             ctx.next_timer_interrupt += 0x8000_0000; // 2**32 / 2
             info!(self.logger, "COP0 timer IRQ raised");
         }
         if ctx.reg_status.ie()
             && !ctx.reg_status.erl()
             && !ctx.reg_status.exl()
-            && ctx.reg_cause.ip() & ctx.reg_status.im() != 0
+            && self.irq.asserted()
         {
             self.exception(cpu, Exception::Interrupt);
         }
@@ -178,10 +244,21 @@ impl Cop0 for Cp0 {
         info!(self.logger, "exception"; "exc" => ?exc);
         let ctx = unsafe { self.ctx.as_mut() };
 
+        // TLB exceptions report the faulting address, and prepare EntryHi and
+        // the page table pointers (Context/XContext) for the refill handler.
+        if let Some(vaddr) = exc.bad_vaddr() {
+            ctx.reg_badvaddr = vaddr;
+            ctx.reg_context = ctx.reg_context & !0x7F_FFFF | (vaddr >> 9) & 0x7F_FFF0;
+            ctx.reg_xcontext = ctx.reg_xcontext & 0xFFFF_FFFE_0000_0000
+                | (vaddr >> 62) << 31
+                | (vaddr >> 9) & 0x7FFF_FFF0;
+            ctx.reg_entryhi = vaddr & 0xC000_00FF_FFFF_E000 | ctx.reg_entryhi & 0xFF;
+        }
+
         match exc {
             ColdReset => {
-                // ctx.reg_random = 31;
-                // ctx.reg_wired = 0;
+                ctx.reg_wired = 0;
+                ctx.random_clock = cpu.clock;
                 // ctx.reg_config.set_k0(2);
                 // ctx.reg_config[0..3] should be configured as specified in MipsConfig
                 ctx.reg_status.set_rp(false);
@@ -209,7 +286,15 @@ impl Cop0 for Cp0 {
                 cpu.set_pc(0xFFFF_FFFF_BFC0_0000);
             }
             Nmi => {
-                error!(self.logger, "unimplemented exception type"; "exc" => ?exc);
+                // Like a soft reset, but the PC of the interrupted instruction
+                // is preserved in ErrorEPC, so that software can inspect it.
+                ctx.reg_status.set_bev(true);
+                ctx.reg_status.set_ts(false);
+                ctx.reg_status.set_sr(true);
+                ctx.reg_status.set_nmi(true);
+                ctx.reg_status.set_erl(true);
+                ctx.reg_errorepc = if cpu.delay_slot { cpu.pc - 4 } else { cpu.pc };
+                cpu.set_pc(0xFFFF_FFFF_BFC0_0000);
             }
             _ => {
                 // Standard exception
@@ -223,8 +308,8 @@ impl Cop0 for Cp0 {
                     }
 
                     match exc {
-                        TlbRefill => 0x0,
-                        XTlbRefill => 0x80,
+                        TlbRefill(..) => 0x0,
+                        XTlbRefill(..) => 0x80,
                         Interrupt if ctx.reg_cause.iv() => 0x200,
                         _ => 0x180,
                     }
@@ -233,7 +318,10 @@ impl Cop0 for Cp0 {
                 };
 
                 // Coprocessor unit number
-                ctx.reg_cause.set_ce(0);
+                ctx.reg_cause.set_ce(match exc {
+                    CoprocessorUnusable(cop) => cop as u32,
+                    _ => 0,
+                });
                 ctx.reg_cause.set_exc(exc.exc_code().unwrap_or(0));
                 ctx.reg_status.set_exl(true);
                 if ctx.reg_status.bev() {
@@ -244,21 +332,35 @@ impl Cop0 for Cp0 {
             }
         };
     }
+
+    fn asid(&self) -> u8 {
+        self.ctx.reg_entryhi as u8
+    }
+
+    fn cop_usable(&self, cop: usize) -> bool {
+        // COP0 is always usable in kernel mode (the only one emulated).
+        cop == 0 || self.ctx.reg_status.0 & (1 << (28 + cop)) != 0
+    }
 }
 
 impl Cop for Cp0 {
     fn reg(&self, cpu: &CpuContext, idx: usize) -> u128 {
         match idx {
             0 => self.ctx.reg_index as u128,
+            1 => self.get_random(cpu) as u128,
             2 => self.ctx.reg_entrylo0 as u128,
             3 => self.ctx.reg_entrylo1 as u128,
+            4 => self.ctx.reg_context as u128,
             5 => self.ctx.reg_pagemask as u128,
+            6 => self.ctx.reg_wired as u128,
+            8 => self.ctx.reg_badvaddr as u128,
             9 => self.get_count(cpu) as u128,
             10 => self.ctx.reg_entryhi as u128,
             11 => self.ctx.reg_compare as u128,
             12 => self.ctx.reg_status.0 as u128,
-            13 => self.ctx.reg_cause.0 as u128,
+            13 => self.get_cause().0 as u128,
             14 => self.ctx.reg_epc as u128,
+            20 => self.ctx.reg_xcontext as u128,
             30 => self.ctx.reg_errorepc as u128,
             _ => {
                 error!(
@@ -275,21 +377,44 @@ impl Cop for Cp0 {
         match idx {
             0 => self.ctx.reg_index = val as u32 & 0x3F,
             2 => self.ctx.reg_entrylo0 = val as u64,
+            1 => {} // Random is read-only
             3 => self.ctx.reg_entrylo1 = val as u64,
+            4 => {
+                // Only PTEBase is writable
+                let ctx = &mut self.ctx;
+                ctx.reg_context = ctx.reg_context & 0x7F_FFFF | val as u64 & !0x7F_FFFF;
+            }
             5 => self.ctx.reg_pagemask = val as u32,
+            6 => {
+                // Writing Wired also resets Random to 31
+                self.ctx.reg_wired = val as u32 & 0x3F;
+                self.ctx.random_clock = cpu.clock;
+            }
+            8 => {} // BadVAddr is read-only
             9 => self.set_count(cpu, val as u32),
             10 => self.ctx.reg_entryhi = val as u64,
             11 => self.set_compare(cpu, val as u32),
             12 => {
                 self.ctx.reg_status.0 = val as u32;
                 cpu.fpu64 = self.ctx.reg_status.fr();
+                self.irq.set_mask(self.ctx.reg_status.im());
                 cpu.tight_exit = true;
             }
             13 => {
-                self.ctx.reg_cause.0 = val as u32;
+                // Only the software interrupts (IP0/IP1) are writable.
+                let cause = RegCause(val as u32);
+                self.irq.set_line(0, cause.ip() & 1 != 0);
+                self.irq.set_line(1, cause.ip() & 2 != 0);
+                self.update_ip();
                 cpu.tight_exit = true;
             }
             14 => self.ctx.reg_epc = val as u64,
+            20 => {
+                // Only PTEBase is writable
+                let ctx = &mut self.ctx;
+                ctx.reg_xcontext =
+                    ctx.reg_xcontext & 0x1_FFFF_FFFF | val as u64 & 0xFFFF_FFFE_0000_0000;
+            }
             30 => self.ctx.reg_errorepc = val as u64,
             _ => {
                 error!(
@@ -345,6 +470,21 @@ impl Cop for Cp0 {
                         "idx" => ctx.reg_index,
                         "tlb" => ?cpu.mmu.read((ctx.reg_index & 0x1F) as usize));
                 }
+                0x06 => {
+                    // TLBWR
+                    let idx = self.get_random(cpu) as usize;
+                    cpu.mmu.write(
+                        idx,
+                        ctx.reg_pagemask,
+                        ctx.reg_entryhi,
+                        ctx.reg_entrylo0,
+                        ctx.reg_entrylo1,
+                    );
+
+                    info!(self.logger, "wrote random TLB entry";
+                        "idx" => idx,
+                        "tlb" => ?cpu.mmu.read(idx));
+                }
                 0x08 => {
                     // TLBP
                     match cpu.mmu.probe(ctx.reg_entryhi, ctx.reg_entryhi as u8) {
@@ -404,6 +544,7 @@ impl Cop for Cp0 {
             0x10..=0x1F => match func {
                 0x1 => DecodedInsn::new0("tlbr"),
                 0x2 => DecodedInsn::new0("tlbwi"),
+                0x6 => DecodedInsn::new0("tlbwr"),
                 0x8 => DecodedInsn::new0("tlbp"),
                 0x18 => DecodedInsn::new0("eret"),
                 _ => DecodedInsn::new1("cop0op?", Imm32(func)),
@@ -412,13 +553,14 @@ impl Cop for Cp0 {
         }
     }
 
-    fn render_debug(&mut self, dr: &DebuggerRenderer) {
+    #[cfg(feature = "debugger")]
+    fn render_debug(&mut self, dr: &mut DebuggerRenderer) {
         dr.render_regview(self);
     }
 }
 
 impl RegisterView for Cp0 {
-    const WINDOW_SIZE: (f32, f32) = (180.0, 400.0);
+    const WINDOW_SIZE: (f32, f32) = (180.0, 500.0);
     const COLUMNS: usize = 1;
 
     fn name(&self) -> &str {
@@ -457,6 +599,10 @@ impl RegisterView for Cp0 {
                 visit("EntryHi", Reg64(&mut ctx.reg_entryhi), None);
                 visit("EntryLo0", Reg64(&mut ctx.reg_entrylo0), None);
                 visit("EntryLo1", Reg64(&mut ctx.reg_entrylo1), None);
+                visit("Wired", Reg32(&mut ctx.reg_wired), None);
+                visit("Context", Reg64(&mut ctx.reg_context), None);
+                visit("XContext", Reg64(&mut ctx.reg_xcontext), None);
+                visit("BadVAddr", Reg64(&mut ctx.reg_badvaddr), None);
 
                 visit("Compare", Reg32(&mut ctx.reg_compare), None);
             }
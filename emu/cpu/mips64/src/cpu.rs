@@ -1,9 +1,13 @@
+use super::blockcache::BlockCache;
 use super::decode::decode;
-use super::mmu::Mmu;
+use super::icache::{DecodeCache, OpFn};
+use super::mmu::{is_mapped, Mmu, TlbError};
 use super::{Arch, Config, Cop, Cop0};
 
 use emu::bus::be::{Bus, MemIoR};
-use emu::dbg::{DebuggerRenderer, DisasmView, RegisterSize, RegisterView, Result, Tracer};
+#[cfg(feature = "debugger")]
+use emu::dbg::DebuggerRenderer;
+use emu::dbg::{CacheStats, CacheView, DisasmView, RegisterSize, RegisterView, Result, Tracer};
 use emu::int::Numerics;
 use emu::memint::MemInt;
 use emu::state::Field;
@@ -11,7 +15,6 @@ use emu::sync;
 
 use byteorder::ByteOrder;
 use serde_derive::{Deserialize, Serialize};
-use slog;
 
 #[derive(Copy, Clone, Debug)]
 pub enum Exception {
@@ -20,8 +23,14 @@ pub enum Exception {
     ColdReset,
     SoftReset,
     Nmi,
-    TlbRefill,
-    XTlbRefill,
+    TlbRefill(u64, bool),  // TLB miss (32-bit address): bad address, store
+    XTlbRefill(u64, bool), // TLB miss (64-bit address): bad address, store
+    TlbInvalid(u64, bool), // TLB entry not valid: bad address, store
+    TlbModified(u64),      // Store into a page not marked dirty: bad address
+    Syscall,
+    Trap,
+    Overflow,                   // Integer overflow
+    CoprocessorUnusable(usize), // Coprocessor disabled in Status: unit number
 }
 
 impl Exception {
@@ -32,8 +41,29 @@ impl Exception {
             Exception::ColdReset => None,
             Exception::Nmi => None,
             Exception::SoftReset => None,
-            Exception::TlbRefill => None,
-            Exception::XTlbRefill => None,
+            Exception::TlbModified(_) => Some(0x01),
+            Exception::TlbRefill(_, false) => Some(0x02),
+            Exception::XTlbRefill(_, false) => Some(0x02),
+            Exception::TlbInvalid(_, false) => Some(0x02),
+            Exception::TlbRefill(_, true) => Some(0x03),
+            Exception::XTlbRefill(_, true) => Some(0x03),
+            Exception::TlbInvalid(_, true) => Some(0x03),
+            Exception::Syscall => Some(0x08),
+            Exception::CoprocessorUnusable(_) => Some(0x0B),
+            Exception::Overflow => Some(0x0C),
+            Exception::Trap => Some(0x0D),
+        }
+    }
+
+    /// Return the virtual address that caused the exception, for the
+    /// exceptions that report it (BadVAddr).
+    pub(crate) fn bad_vaddr(&self) -> Option<u64> {
+        match *self {
+            Exception::TlbRefill(vaddr, _) => Some(vaddr),
+            Exception::XTlbRefill(vaddr, _) => Some(vaddr),
+            Exception::TlbInvalid(vaddr, _) => Some(vaddr),
+            Exception::TlbModified(vaddr) => Some(vaddr),
+            _ => None,
         }
     }
 }
@@ -50,6 +80,7 @@ pub struct CpuContext {
     pub lo: u64,          // LO mul register
     pub pc: u64,          // Program counter
     pub next_pc: u64,     // Next program counter (for jumps)
+    pub branch_pc: u64,   // Address of the last taken branch
     pub clock: i64,       // Current clock
     pub tight_exit: bool, // True if we need to exit the tight loop
     pub delay_slot: bool, // True if the current insn is a delay slot
@@ -72,6 +103,8 @@ pub struct Cpu<C: Config> {
     until: i64,
 
     last_busy_check: u64,
+    icache: DecodeCache<C>,
+    blocks: BlockCache<C>,
 }
 
 struct Mipsop<'a, C: Config> {
@@ -149,6 +182,37 @@ impl<'a, C: Config> Mipsop<'a, C> {
         let rd = self.rd();
         &mut self.ctx.regs[rd]
     }
+
+    // Raise a Trap exception if the condition of a trap instruction holds.
+    fn trap(&mut self, cond: bool) {
+        if cond {
+            self.cpu.insn_exception(Exception::Trap);
+        }
+    }
+
+    // Load from the effective address, and set RT to the value returned by
+    // f (called with the value loaded, the address and the current RT). RT
+    // is not modified if the load raises an exception.
+    fn load<U: MemInt, F: FnOnce(U, u32, u64) -> u64>(&mut self, t: &Tracer, f: F) -> Result<()> {
+        let ea = self.ea();
+        if let Some(val) = self.cpu.read::<U>(ea, t)? {
+            let rt = self.rt();
+            self.ctx.regs[rt] = f(val, ea, self.ctx.regs[rt]);
+        }
+        Ok(())
+    }
+
+    fn store<U: MemInt>(&mut self, val: U, t: &Tracer) -> Result<()> {
+        let ea = self.ea();
+        self.cpu.write::<U>(ea, val, t)
+    }
+
+    // Store the value returned by f, called with the value in memory and the
+    // effective address (partial stores: SWL, SWR, SDL, SDR).
+    fn store_merge<U: MemInt, F: FnOnce(U, u32) -> U>(&mut self, t: &Tracer, f: F) -> Result<()> {
+        let ea = self.ea();
+        self.cpu.write_merge::<U, _>(ea, t, |mem| f(mem, ea))
+    }
 }
 
 impl CpuContext {
@@ -156,6 +220,7 @@ impl CpuContext {
     pub fn branch(&mut self, cond: bool, tgt: u64, likely: bool) {
         if cond {
             self.next_pc = tgt;
+            self.branch_pc = self.pc - 4;
             self.delay_slot = true;
         } else if likely {
             // branch not taken; if likely, skip delay slot
@@ -172,10 +237,12 @@ impl CpuContext {
     }
 
     // Directly set PC to a specific value. Used at reset,
-    // ERET, and exceptions to do a non-delayed-slot branch.
+    // ERET, and exceptions to do a non-delayed-slot branch; a pending
+    // delay slot is cancelled.
     pub fn set_pc(&mut self, pc: u64) {
         self.pc = pc;
         self.next_pc = pc + 4;
+        self.delay_slot = false;
         self.tight_exit = true;
     }
 
@@ -232,9 +299,33 @@ macro_rules! check_overflow_sub {
     }};
 }
 
+// Abort the instruction with a Coprocessor Unusable exception, if Cop0
+// reports the coprocessor as disabled.
+macro_rules! cop_usable {
+    ($op:ident, cop0) => {
+        cop_usable!($op, 0usize)
+    };
+    ($op:ident, cop1) => {
+        cop_usable!($op, 1usize)
+    };
+    ($op:ident, cop2) => {
+        cop_usable!($op, 2usize)
+    };
+    ($op:ident, cop3) => {
+        cop_usable!($op, 3usize)
+    };
+    ($op:ident, $n:expr) => {
+        if !$op.cpu.cop0.cop_usable($n) {
+            $op.cpu.insn_exception(Exception::CoprocessorUnusable($n));
+            return Ok(());
+        }
+    };
+}
+
 macro_rules! if_cop {
     ($op:ident, $cop:ident, $do:expr) => {{
         if !$op.cpu.$cop.is_null_obj() {
+            cop_usable!($op, $cop);
             let $cop = &mut $op.cpu.$cop;
             $do
         } else {
@@ -246,12 +337,34 @@ macro_rules! if_cop {
     }};
 }
 
+// Coprocessor loads and stores are delegated to the coprocessor, which can
+// implement its own addressing (eg: RSP vector loads). With the TLB, they are
+// executed by the core instead, so that the address is translated.
 macro_rules! if_cop_loadstore {
     ($op:ident, $cop:ident, $loadstore:ident, $t:ident) => {{
         if_cop!($op, $cop, {
             return $cop.$loadstore($op.opcode, &mut $op.ctx, &mut $op.cpu.bus, $t);
         })
     }};
+    ($op:ident, $cop:ident, $loadstore:ident, $t:ident,load($ty:ty)) => {{
+        if C::TLB && !$op.cpu.$cop.is_null_obj() {
+            cop_usable!($op, $cop);
+            let (ea, rt) = ($op.ea(), $op.rt());
+            if let Some(val) = $op.cpu.read::<$ty>(ea, $t)? {
+                $op.cpu.$cop.set_reg(&mut $op.ctx, rt, val as u128);
+            }
+            return Ok(());
+        }
+        if_cop_loadstore!($op, $cop, $loadstore, $t)
+    }};
+    ($op:ident, $cop:ident, $loadstore:ident, $t:ident,store($ty:ty)) => {{
+        if C::TLB && !$op.cpu.$cop.is_null_obj() {
+            cop_usable!($op, $cop);
+            let val = $op.cpu.$cop.reg(&$op.ctx, $op.rt()) as $ty;
+            return $op.store::<$ty>(val, $t);
+        }
+        if_cop_loadstore!($op, $cop, $loadstore, $t)
+    }};
 }
 
 impl<C: Config> Cpu<C> {
@@ -263,15 +376,17 @@ impl<C: Config> Cpu<C> {
     ) -> Self {
         let mut cpu = Cpu {
             ctx: Field::new(&("mips64::".to_owned() + name), CpuContext::default()),
-            bus: bus,
+            bus,
             name: name.into(),
             cop0: cops.0,
             cop1: cops.1,
             cop2: cops.2,
             cop3: cops.3,
-            logger: logger,
+            logger,
             until: 0,
             last_busy_check: 0,
+            icache: DecodeCache::default(),
+            blocks: BlockCache::default(),
         };
         cpu.exception(Exception::ColdReset); // Trigger a reset exception at startup
         cpu
@@ -289,265 +404,420 @@ impl<C: Config> Cpu<C> {
         self.exception(Exception::SoftReset);
     }
 
+    /// Trigger a non-maskable interrupt (eg: the reset button on consoles).
+    pub fn nmi(&mut self) {
+        self.exception(Exception::Nmi);
+    }
+
     fn exception(&mut self, exc: Exception) {
         self.cop0.exception(&mut self.ctx, exc);
     }
 
+    // Raise an exception caused by the instruction being executed, which is
+    // aborted. PC is moved back to the instruction, as for exceptions raised
+    // on an instruction boundary (Cop0 then reports the branch as EPC, if the
+    // instruction is in a delay slot).
+    fn insn_exception(&mut self, exc: Exception) {
+        // While executing, tight_exit is only set for delay slots (see run()).
+        let ctx = &mut self.ctx;
+        ctx.delay_slot = ctx.tight_exit;
+        ctx.pc = if ctx.delay_slot {
+            ctx.branch_pc + 4
+        } else {
+            ctx.pc - 4
+        };
+        self.exception(exc);
+        self.ctx.delay_slot = false;
+    }
+
     fn trap_overflow(&mut self) {
-        unimplemented!();
+        self.insn_exception(Exception::Overflow);
     }
 
-    #[inline(never)]
-    fn op(&mut self, ctx: &mut CpuContext, opcode: u32, t: &Tracer) -> Result<()> {
-        ctx.clock += 1;
-        let mut op = Mipsop {
-            ctx,
-            opcode,
-            cpu: self,
-        };
+    /// Decode an opcode into the function that executes it. The interpreter
+    /// only calls it when the opcode is not in the decode cache; other
+    /// engines (see run_compiled) use it for the opcodes they don't handle.
+    pub fn decode_op(opcode: u32) -> OpFn<C> {
         let h = |s| C::Arch::has_op(s);
-        match op.op() {
+        let f: OpFn<C> = match opcode >> 26 {
             // SPECIAL
-            0x00 => match op.special() {
-                0x00 if h("sll") => *op.mrd64() = (op.rt32() << op.sa()).sx64(), // SLL
-                0x02 if h("srl") => *op.mrd64() = (op.rt32() >> op.sa()).sx64(), // SRL
-                0x03 if h("sra") => *op.mrd64() = (op.irt32() >> op.sa()).sx64(), // SRA
-                0x04 if h("sllv") => *op.mrd64() = (op.rt32() << (op.rs32() & 0x1F)).sx64(), // SLLV
-                0x06 if h("srll") => *op.mrd64() = (op.rt32() >> (op.rs32() & 0x1F)).sx64(), // SRLV
-                0x07 if h("srav") => *op.mrd64() = (op.irt32() >> (op.rs32() & 0x1F)).sx64(), // SRAV
-                0x08 if h("jr") => branch!(op, true, op.rs64(), link(false)),                 // JR
-                0x09 if h("jalr") => branch!(op, true, op.rs64(), link(true)), // JALR
-                0x0D if h("break") => op.cpu.exception(Exception::Breakpoint), // BREAK
-                0x0F if h("sync") => {}                                        // SYNC
-
-                0x10 if h("mfhi") => *op.mrd64() = op.ctx.hi, // MFHI
-                0x11 if h("mthi") => op.ctx.hi = op.rs64(),   // MTHI
-                0x12 if h("mflo") => *op.mrd64() = op.ctx.lo, // MFLO
-                0x13 if h("mtlo") => op.ctx.lo = op.rs64(),   // MTLO
-                0x14 if h("dsllv") => *op.mrd64() = op.rt64() << (op.rs32() & 0x3F), // DSLLV
-                0x16 if h("dsrlv") => *op.mrd64() = op.rt64() >> (op.rs32() & 0x3F), // DSRLV
-                0x17 if h("dsrav") => *op.mrd64() = (op.irt64() >> (op.rs32() & 0x3F)) as u64, // DSRAV
-                0x18 if h("mult") => {
-                    // MULT
-                    let (hi, lo) =
-                        (i64::wrapping_mul(op.rt32().isx64(), op.rs32().isx64()) as u64).hi_lo();
-                    op.ctx.lo = lo;
-                    op.ctx.hi = hi;
-                }
-                0x19 if h("multu") => {
-                    // MULTU
-                    let (hi, lo) = u64::wrapping_mul(op.rt32() as u64, op.rs32() as u64).hi_lo();
-                    op.ctx.lo = lo;
-                    op.ctx.hi = hi;
-                }
-                0x1A if h("div") => {
-                    // DIV
-                    op.ctx.lo = op.irs32().wrapping_div(op.irt32()).sx64();
-                    op.ctx.hi = op.irs32().wrapping_rem(op.irt32()).sx64();
-                }
-                0x1B if h("divu") => {
-                    // DIVU
-                    op.ctx.lo = op.rs32().wrapping_div(op.rt32()).sx64();
-                    op.ctx.hi = op.rs32().wrapping_rem(op.rt32()).sx64();
-                }
-                0x1C if h("dmult") => {
-                    // DMULT
-                    let (hi, lo) =
-                        i128::wrapping_mul(op.irt64() as i128, op.irs64() as i128).hi_lo();
-                    op.ctx.lo = lo as u64;
-                    op.ctx.hi = hi as u64;
-                }
-                0x1D if h("dmultu") => {
-                    // DMULTU
-                    let (hi, lo) = u128::wrapping_mul(op.rt64() as u128, op.rs64() as u128).hi_lo();
-                    op.ctx.lo = lo as u64;
-                    op.ctx.hi = hi as u64;
-                }
-                0x1E if h("ddiv") => {
-                    // DDIV
-                    op.ctx.lo = op.irs64().wrapping_div(op.irt64()) as u64;
-                    op.ctx.hi = op.irs64().wrapping_rem(op.irt64()) as u64;
-                }
-                0x1F if h("ddivu") => {
-                    // DDIVU
-                    op.ctx.lo = op.rs64().wrapping_div(op.rt64());
-                    op.ctx.hi = op.rs64().wrapping_rem(op.rt64());
-                }
-
-                0x20 if h("add") => check_overflow_add!(op, *op.mrd64(), op.irs32(), op.irt32()), // ADD
-                0x21 if h("addu") => *op.mrd64() = (op.rs32() + op.rt32()).sx64(), // ADDU
-                0x22 if h("sub") => check_overflow_sub!(op, *op.mrd64(), op.irs32(), op.irt32()), // SUB
-                0x23 if h("subu") => *op.mrd64() = (op.rs32() - op.rt32()).sx64(), // SUBU
-                0x24 if h("and") => *op.mrd64() = op.rs64() & op.rt64(),           // AND
-                0x25 if h("or") => *op.mrd64() = op.rs64() | op.rt64(),            // OR
-                0x26 if h("xor") => *op.mrd64() = op.rs64() ^ op.rt64(),           // XOR
-                0x27 if h("nor") => *op.mrd64() = !(op.rs64() | op.rt64()),        // NOR
-                0x2A if h("slt") => *op.mrd64() = (op.irs32() < op.irt32()) as u64, // SLT
-                0x2B if h("sltu") => *op.mrd64() = (op.rs32() < op.rt32()) as u64, // SLTU
-                0x2C if h("dadd") => check_overflow_add!(op, *op.mrd64(), op.irs64(), op.irt64()), // DADD
-                0x2D if h("daddu") => *op.mrd64() = op.rs64() + op.rt64(), // DADDU
-                0x2E if h("dsub") => check_overflow_sub!(op, *op.mrd64(), op.irs64(), op.irt64()), // DSUB
-                0x2F if h("dsubu") => *op.mrd64() = op.rs64() - op.rt64(), // DSUBU
-
-                0x38 if h("dsll") => *op.mrd64() = op.rt64() << op.sa(), // DSLL
-                0x3A if h("dsrl") => *op.mrd64() = op.rt64() >> op.sa(), // DSRL
-                0x3B if h("dsra") => *op.mrd64() = (op.irt64() >> op.sa()) as u64, // DSRA
-                0x3C if h("dsll32") => *op.mrd64() = op.rt64() << (op.sa() + 32), // DSLL32
-                0x3E if h("dsrl32") => *op.mrd64() = op.rt64() >> (op.sa() + 32), // DSRL32
-                0x3F if h("dsra32") => *op.mrd64() = (op.irt64() >> (op.sa() + 32)) as u64, // DSRA32
-
-                _ => {
-                    return t.panic(&format!(
-                        "unimplemented special opcode: func=0x{:x?}",
-                        op.special()
-                    ));
-                }
+            0x00 => match opcode & 0x3f {
+                0x00 if h("sll") => Self::op_sll,
+                0x02 if h("srl") => Self::op_srl,
+                0x03 if h("sra") => Self::op_sra,
+                0x04 if h("sllv") => Self::op_sllv,
+                0x06 if h("srll") => Self::op_srlv,
+                0x07 if h("srav") => Self::op_srav,
+                0x08 if h("jr") => Self::op_jr,
+                0x09 if h("jalr") => Self::op_jalr,
+                0x0C if h("syscall") => Self::op_syscall,
+                0x0D if h("break") => Self::op_break,
+                0x0F if h("sync") => Self::op_nop,
+
+                0x10 if h("mfhi") => Self::op_mfhi,
+                0x11 if h("mthi") => Self::op_mthi,
+                0x12 if h("mflo") => Self::op_mflo,
+                0x13 if h("mtlo") => Self::op_mtlo,
+                0x14 if h("dsllv") => Self::op_dsllv,
+                0x16 if h("dsrlv") => Self::op_dsrlv,
+                0x17 if h("dsrav") => Self::op_dsrav,
+                0x18 if h("mult") => Self::op_mult,
+                0x19 if h("multu") => Self::op_multu,
+                0x1A if h("div") => Self::op_div,
+                0x1B if h("divu") => Self::op_divu,
+                0x1C if h("dmult") => Self::op_dmult,
+                0x1D if h("dmultu") => Self::op_dmultu,
+                0x1E if h("ddiv") => Self::op_ddiv,
+                0x1F if h("ddivu") => Self::op_ddivu,
+
+                0x20 if h("add") => Self::op_add,
+                0x21 if h("addu") => Self::op_addu,
+                0x22 if h("sub") => Self::op_sub,
+                0x23 if h("subu") => Self::op_subu,
+                0x24 if h("and") => Self::op_and,
+                0x25 if h("or") => Self::op_or,
+                0x26 if h("xor") => Self::op_xor,
+                0x27 if h("nor") => Self::op_nor,
+                0x2A if h("slt") => Self::op_slt,
+                0x2B if h("sltu") => Self::op_sltu,
+                0x2C if h("dadd") => Self::op_dadd,
+                0x2D if h("daddu") => Self::op_daddu,
+                0x2E if h("dsub") => Self::op_dsub,
+                0x2F if h("dsubu") => Self::op_dsubu,
+
+                0x30 if h("tge") => Self::op_tge,
+                0x31 if h("tgeu") => Self::op_tgeu,
+                0x32 if h("tlt") => Self::op_tlt,
+                0x33 if h("tltu") => Self::op_tltu,
+                0x34 if h("teq") => Self::op_teq,
+                0x36 if h("tne") => Self::op_tne,
+
+                0x38 if h("dsll") => Self::op_dsll,
+                0x3A if h("dsrl") => Self::op_dsrl,
+                0x3B if h("dsra") => Self::op_dsra,
+                0x3C if h("dsll32") => Self::op_dsll32,
+                0x3E if h("dsrl32") => Self::op_dsrl32,
+                0x3F if h("dsra32") => Self::op_dsra32,
+
+                _ => Self::op_unimplemented,
             },
 
             // REGIMM
-            0x01 => match op.rt() {
-                0x00 if h("bltz") => {
-                    branch!(op, op.irs64() < 0, op.btgt(), link(false), likely(false))
-                }
-                0x01 if h("bgez") => {
-                    branch!(op, op.irs64() >= 0, op.btgt(), link(false), likely(false))
-                }
-                0x02 if h("btlzl") => {
-                    branch!(op, op.irs64() < 0, op.btgt(), link(false), likely(true))
-                }
-                0x03 if h("bgezl") => {
-                    branch!(op, op.irs64() >= 0, op.btgt(), link(false), likely(true))
-                }
-                0x10 if h("bltzal") => {
-                    branch!(op, op.irs64() < 0, op.btgt(), link(true), likely(false))
-                }
-                0x11 if h("bgezal") => {
-                    branch!(op, op.irs64() >= 0, op.btgt(), link(true), likely(false))
-                }
-                0x12 if h("bltzall") => {
-                    branch!(op, op.irs64() < 0, op.btgt(), link(true), likely(true))
-                }
-                0x13 if h("bgezall") => {
-                    branch!(op, op.irs64() >= 0, op.btgt(), link(true), likely(true))
-                }
-                _ => panic!(
-                    "unimplemented regimm opcode: func=0x{:x?} pc=0x{:x?}",
-                    op.rt(),
-                    op.ctx.pc - 4
-                ),
+            0x01 => match (opcode >> 16) & 0x1f {
+                0x00 if h("bltz") => Self::op_bltz,
+                0x01 if h("bgez") => Self::op_bgez,
+                0x02 if h("btlzl") => Self::op_bltzl,
+                0x03 if h("bgezl") => Self::op_bgezl,
+                0x08 if h("tgei") => Self::op_tgei,
+                0x09 if h("tgeiu") => Self::op_tgeiu,
+                0x0A if h("tlti") => Self::op_tlti,
+                0x0B if h("tltiu") => Self::op_tltiu,
+                0x0C if h("teqi") => Self::op_teqi,
+                0x0E if h("tnei") => Self::op_tnei,
+                0x10 if h("bltzal") => Self::op_bltzal,
+                0x11 if h("bgezal") => Self::op_bgezal,
+                0x12 if h("bltzall") => Self::op_bltzall,
+                0x13 if h("bgezall") => Self::op_bgezall,
+                _ => Self::op_unimplemented,
             },
 
-            0x02 if h("j") => branch!(op, true, op.jtgt(), link(false)), // J
-            0x03 if h("jal") => branch!(op, true, op.jtgt(), link(true)), // JAL
-            0x04 if h("beq") => branch!(op, op.rs64() == op.rt64(), op.btgt()), // BEQ
-            0x05 if h("bne") => branch!(op, op.rs64() != op.rt64(), op.btgt()), // BNE
-            0x06 if h("blez") => branch!(op, op.irs64() <= 0, op.btgt()), // BLEZ
-            0x07 if h("bgtz") => branch!(op, op.irs64() > 0, op.btgt()), // BGTZ
-            0x08 if h("addi") => check_overflow_add!(op, *op.mrt64(), op.irs32(), op.sximm32()), // ADDI
-            0x09 if h("addiu") => *op.mrt64() = (op.irs32() + op.sximm32()).sx64(), // ADDIU
-            0x0A if h("slti") => *op.mrt64() = (op.irs32() < op.sximm32()) as u64,  // SLTI
-            0x0B if h("sltiu") => *op.mrt64() = (op.rs32() < op.sximm32() as u32) as u64, // SLTIU
-            0x0C if h("andi") => *op.mrt64() = op.rs64() & op.imm64(),              // ANDI
-            0x0D if h("ori") => *op.mrt64() = op.rs64() | op.imm64(),               // ORI
-            0x0E if h("xori") => *op.mrt64() = op.rs64() ^ op.imm64(),              // XORI
-            0x0F if h("lui") => *op.mrt64() = (op.sximm32() << 16).sx64(),          // LUI
-
-            0x10 => if_cop!(op, cop0, { return cop0.op(&mut op.ctx, opcode, t) }), // COP0
-            0x11 => if_cop!(op, cop1, { return cop1.op(&mut op.ctx, opcode, t) }), // COP1
-            0x12 => if_cop!(op, cop2, { return cop2.op(&mut op.ctx, opcode, t) }), // COP2
-            0x13 => if_cop!(op, cop3, { return cop3.op(&mut op.ctx, opcode, t) }), // COP3
-            0x14 if h("beql") => branch!(op, op.rs64() == op.rt64(), op.btgt(), likely(true)), // BEQL
-            0x15 if h("bnel") => branch!(op, op.rs64() != op.rt64(), op.btgt(), likely(true)), // BNEL
-            0x16 if h("blezl") => branch!(op, op.irs64() <= 0, op.btgt(), likely(true)), // BLEZL
-            0x17 if h("bgtzl") => branch!(op, op.irs64() > 0, op.btgt(), likely(true)),  // BGTZL
-            0x18 if h("daddi") => check_overflow_add!(op, *op.mrt64(), op.irs64(), op.sximm64()), // DADDI
-            0x19 if h("daddiu") => *op.mrt64() = (op.irs64() + op.sximm64()) as u64, // DADDIU
-            0x1a if h("ldl") => *op.mrt64() = op.cpu.lwl::<u64>(op.ea(), op.rt64(), t)?, // LDL
-            0x1b if h("ldr") => *op.mrt64() = op.cpu.lwr::<u64>(op.ea(), op.rt64(), t)?, // LDR
-
-            0x20 if h("lb") => *op.mrt64() = op.cpu.read::<u8>(op.ea(), t)?.sx64(), // LB
-            0x21 if h("lh") => *op.mrt64() = op.cpu.read::<u16>(op.ea(), t)?.sx64(), // LH
-            0x22 if h("lwl") => *op.mrt64() = op.cpu.lwl::<u32>(op.ea(), op.rt32(), t)?.sx64(), // LWL
-            0x23 if h("lw") => *op.mrt64() = op.cpu.read::<u32>(op.ea(), t)?.sx64(), // LW
-            0x24 if h("lbu") => *op.mrt64() = op.cpu.read::<u8>(op.ea(), t)? as u64, // LBU
-            0x25 if h("lhu") => *op.mrt64() = op.cpu.read::<u16>(op.ea(), t)? as u64, // LHU
-            0x26 if h("lwr") => *op.mrt64() = op.cpu.lwr::<u32>(op.ea(), op.rt32(), t)?.sx64(), // LWR
-            0x27 if h("lwu") => *op.mrt64() = op.cpu.read::<u32>(op.ea(), t)? as u64, // LWU
-            0x28 if h("sb") => op.cpu.write::<u8>(op.ea(), op.rt32() as u8, t)?,      // SB
-            0x29 if h("sh") => op.cpu.write::<u16>(op.ea(), op.rt32() as u16, t)?,    // SH
-            0x2A if h("swl") => {
-                // SWL
-                op.cpu
-                    .write::<u32>(op.ea(), op.cpu.swl(op.ea(), op.rt32(), t)?, t)?
-            }
-            0x2B if h("sw") => op.cpu.write::<u32>(op.ea(), op.rt32(), t)?, // SW
-            0x2C if h("sdl") => {
-                // SDL
-                op.cpu
-                    .write::<u64>(op.ea(), op.cpu.swl(op.ea(), op.rt64(), t)?, t)?
-            }
-            0x2D if h("sdr") => {
-                // SDR
-                op.cpu
-                    .write::<u64>(op.ea(), op.cpu.swr(op.ea(), op.rt64(), t)?, t)?
-            }
-            0x2E if h("swr") => {
-                // SWR
-                op.cpu
-                    .write::<u32>(op.ea(), op.cpu.swr(op.ea(), op.rt32(), t)?, t)?
-            }
-            0x2F => {} // CACHE
-
-            0x31 if h("lwc1") => if_cop_loadstore!(op, cop1, lwc, t), // LWC1
-            0x32 if h("lwc2") => if_cop_loadstore!(op, cop2, lwc, t), // LWC2
-            0x35 if h("ldc1") => if_cop_loadstore!(op, cop1, ldc, t), // LDC1
-            0x36 if h("ldc2") => if_cop_loadstore!(op, cop2, ldc, t), // LDC2
-            0x37 if h("ld") => *op.mrt64() = op.cpu.read::<u64>(op.ea(), t)?, // LD
-            0x39 if h("swc1") => if_cop_loadstore!(op, cop1, swc, t), // SWC1
-            0x3A if h("swc2") => if_cop_loadstore!(op, cop2, swc, t), // SWC2
-            0x3D if h("sdc1") => if_cop_loadstore!(op, cop1, sdc, t), // SDC1
-            0x3E if h("sdc2") => if_cop_loadstore!(op, cop2, sdc, t), // SDC2
-            0x3F if h("sd") => op.cpu.write::<u64>(op.ea(), op.rt64(), t)?, // SD
-
-            _ => {
-                panic!(
-                    "unimplemented opcode: func=0x{:x?}, pc={}",
-                    op.op(),
-                    op.ctx.pc.hex()
-                );
-            }
+            0x02 if h("j") => Self::op_j,
+            0x03 if h("jal") => Self::op_jal,
+            0x04 if h("beq") => Self::op_beq,
+            0x05 if h("bne") => Self::op_bne,
+            0x06 if h("blez") => Self::op_blez,
+            0x07 if h("bgtz") => Self::op_bgtz,
+            0x08 if h("addi") => Self::op_addi,
+            0x09 if h("addiu") => Self::op_addiu,
+            0x0A if h("slti") => Self::op_slti,
+            0x0B if h("sltiu") => Self::op_sltiu,
+            0x0C if h("andi") => Self::op_andi,
+            0x0D if h("ori") => Self::op_ori,
+            0x0E if h("xori") => Self::op_xori,
+            0x0F if h("lui") => Self::op_lui,
+
+            0x10 => Self::op_cop0,
+            0x11 => Self::op_cop1,
+            0x12 => Self::op_cop2,
+            0x13 => Self::op_cop3,
+            0x14 if h("beql") => Self::op_beql,
+            0x15 if h("bnel") => Self::op_bnel,
+            0x16 if h("blezl") => Self::op_blezl,
+            0x17 if h("bgtzl") => Self::op_bgtzl,
+            0x18 if h("daddi") => Self::op_daddi,
+            0x19 if h("daddiu") => Self::op_daddiu,
+            0x1a if h("ldl") => Self::op_ldl,
+            0x1b if h("ldr") => Self::op_ldr,
+
+            0x20 if h("lb") => Self::op_lb,
+            0x21 if h("lh") => Self::op_lh,
+            0x22 if h("lwl") => Self::op_lwl,
+            0x23 if h("lw") => Self::op_lw,
+            0x24 if h("lbu") => Self::op_lbu,
+            0x25 if h("lhu") => Self::op_lhu,
+            0x26 if h("lwr") => Self::op_lwr,
+            0x27 if h("lwu") => Self::op_lwu,
+            0x28 if h("sb") => Self::op_sb,
+            0x29 if h("sh") => Self::op_sh,
+            0x2A if h("swl") => Self::op_swl,
+            0x2B if h("sw") => Self::op_sw,
+            0x2C if h("sdl") => Self::op_sdl,
+            0x2D if h("sdr") => Self::op_sdr,
+            0x2E if h("swr") => Self::op_swr,
+            0x2F => Self::op_nop, // CACHE
+
+            0x31 if h("lwc1") => Self::op_lwc1,
+            0x32 if h("lwc2") => Self::op_lwc2,
+            0x35 if h("ldc1") => Self::op_ldc1,
+            0x36 if h("ldc2") => Self::op_ldc2,
+            0x37 if h("ld") => Self::op_ld,
+            0x39 if h("swc1") => Self::op_swc1,
+            0x3A if h("swc2") => Self::op_swc2,
+            0x3D if h("sdc1") => Self::op_sdc1,
+            0x3E if h("sdc2") => Self::op_sdc2,
+            0x3F if h("sd") => Self::op_sd,
+
+            _ => Self::op_unimplemented,
         };
-        Ok(())
+        f
     }
 
-    fn lwl<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
-        let mem = self.read::<S>(addr, t)?;
-        let shift = (addr as usize & (S::SIZE - 1)) * 8;
-        let mask = S::truncate_from((1u64 << shift) - 1u64);
-        Ok((reg & mask) | ((mem << shift) & !mask))
+    // Execute an opcode that has no implementation (or is not available
+    // in this architecture).
+    fn op_unimplemented(
+        cpu: &mut Cpu<C>,
+        ctx: &mut CpuContext,
+        opcode: u32,
+        t: &Tracer,
+    ) -> Result<()> {
+        ctx.clock += 1;
+        let op = Mipsop { ctx, opcode, cpu };
+        match op.op() {
+            0x00 => t.panic(&format!(
+                "unimplemented special opcode: func=0x{:x?}",
+                op.special()
+            )),
+            0x01 => panic!(
+                "unimplemented regimm opcode: func=0x{:x?} pc=0x{:x?}",
+                op.rt(),
+                op.ctx.pc - 4
+            ),
+            _ => panic!(
+                "unimplemented opcode: func=0x{:x?}, pc={}",
+                op.op(),
+                op.ctx.pc.hex()
+            ),
+        }
     }
+}
 
-    fn lwr<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
-        let mem = self.read::<S>(addr, t)?;
-        let shift = (!addr as usize & (S::SIZE - 1)) * 8;
-        let mask = S::max_value() >> shift;
-        Ok((reg & !mask) | ((mem >> shift) & mask))
-    }
+// Define the functions implementing each opcode. Each function has the OpFn
+// signature, so that it can be stored in the decode cache.
+macro_rules! ops {
+    ($($name:ident($op:ident, $t:ident) $body:block)*) => {
+        impl<C: Config> Cpu<C> {
+            $(
+                #[allow(unused_mut, unused_variables)]
+                fn $name(cpu: &mut Cpu<C>, ctx: &mut CpuContext, opcode: u32, $t: &Tracer) -> Result<()> {
+                    ctx.clock += 1;
+                    {
+                        let mut $op = Mipsop { ctx: &mut *ctx, opcode, cpu };
+                        $body
+                    }
+                    // R0 is hardwired to zero: undo any write to it.
+                    ctx.regs[0] = 0;
+                    Ok(())
+                }
+            )*
+        }
+    };
+}
 
-    fn swl<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
-        let mem = self.read::<S>(addr, t)?;
-        let shift = (addr as usize & (S::SIZE - 1)) * 8;
-        let mask = S::max_value() >> shift;
-        Ok((mem & !mask) | ((reg >> shift) & mask))
+ops! {
+    // SPECIAL
+    op_nop(op, t) {}
+    op_sll(op, t) { *op.mrd64() = (op.rt32() << op.sa()).sx64() }
+    op_srl(op, t) { *op.mrd64() = (op.rt32() >> op.sa()).sx64() }
+    op_sra(op, t) { *op.mrd64() = (op.irt32() >> op.sa()).sx64() }
+    op_sllv(op, t) { *op.mrd64() = (op.rt32() << (op.rs32() & 0x1F)).sx64() }
+    op_srlv(op, t) { *op.mrd64() = (op.rt32() >> (op.rs32() & 0x1F)).sx64() }
+    op_srav(op, t) { *op.mrd64() = (op.irt32() >> (op.rs32() & 0x1F)).sx64() }
+    op_jr(op, t) { branch!(op, true, op.rs64(), link(false)) }
+    op_jalr(op, t) { branch!(op, true, op.rs64(), link(true)) }
+    op_syscall(op, t) { op.cpu.insn_exception(Exception::Syscall) }
+    op_break(op, t) { op.cpu.insn_exception(Exception::Breakpoint) }
+
+    op_mfhi(op, t) { *op.mrd64() = op.ctx.hi }
+    op_mthi(op, t) { op.ctx.hi = op.rs64() }
+    op_mflo(op, t) { *op.mrd64() = op.ctx.lo }
+    op_mtlo(op, t) { op.ctx.lo = op.rs64() }
+    op_dsllv(op, t) { *op.mrd64() = op.rt64() << (op.rs32() & 0x3F) }
+    op_dsrlv(op, t) { *op.mrd64() = op.rt64() >> (op.rs32() & 0x3F) }
+    op_dsrav(op, t) { *op.mrd64() = (op.irt64() >> (op.rs32() & 0x3F)) as u64 }
+    op_mult(op, t) {
+        let (hi, lo) = (i64::wrapping_mul(op.rt32().isx64(), op.rs32().isx64()) as u64).hi_lo();
+        op.ctx.lo = (lo as u32).sx64();
+        op.ctx.hi = (hi as u32).sx64();
     }
-
-    fn swr<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
-        let mem = self.read::<S>(addr, t)?;
-        let shift = (!addr as usize & (S::SIZE - 1)) * 8;
-        let mask = S::truncate_from((1 << shift) - 1);
-        Ok((mem & mask) | ((reg << shift) & !mask))
+    op_multu(op, t) {
+        let (hi, lo) = u64::wrapping_mul(op.rt32() as u64, op.rs32() as u64).hi_lo();
+        op.ctx.lo = (lo as u32).sx64();
+        op.ctx.hi = (hi as u32).sx64();
+    }
+    op_div(op, t) {
+        op.ctx.lo = op.irs32().wrapping_div(op.irt32()).sx64();
+        op.ctx.hi = op.irs32().wrapping_rem(op.irt32()).sx64();
+    }
+    op_divu(op, t) {
+        op.ctx.lo = op.rs32().wrapping_div(op.rt32()).sx64();
+        op.ctx.hi = op.rs32().wrapping_rem(op.rt32()).sx64();
+    }
+    op_dmult(op, t) {
+        let (hi, lo) = i128::wrapping_mul(op.irt64() as i128, op.irs64() as i128).hi_lo();
+        op.ctx.lo = lo as u64;
+        op.ctx.hi = hi as u64;
     }
+    op_dmultu(op, t) {
+        let (hi, lo) = u128::wrapping_mul(op.rt64() as u128, op.rs64() as u128).hi_lo();
+        op.ctx.lo = lo as u64;
+        op.ctx.hi = hi as u64;
+    }
+    op_ddiv(op, t) {
+        op.ctx.lo = op.irs64().wrapping_div(op.irt64()) as u64;
+        op.ctx.hi = op.irs64().wrapping_rem(op.irt64()) as u64;
+    }
+    op_ddivu(op, t) {
+        op.ctx.lo = op.rs64().wrapping_div(op.rt64());
+        op.ctx.hi = op.rs64().wrapping_rem(op.rt64());
+    }
+
+    op_add(op, t) { check_overflow_add!(op, *op.mrd64(), op.irs32(), op.irt32()) }
+    op_addu(op, t) { *op.mrd64() = (op.rs32() + op.rt32()).sx64() }
+    op_sub(op, t) { check_overflow_sub!(op, *op.mrd64(), op.irs32(), op.irt32()) }
+    op_subu(op, t) { *op.mrd64() = (op.rs32() - op.rt32()).sx64() }
+    op_and(op, t) { *op.mrd64() = op.rs64() & op.rt64() }
+    op_or(op, t) { *op.mrd64() = op.rs64() | op.rt64() }
+    op_xor(op, t) { *op.mrd64() = op.rs64() ^ op.rt64() }
+    op_nor(op, t) { *op.mrd64() = !(op.rs64() | op.rt64()) }
+    op_slt(op, t) { *op.mrd64() = (op.irs64() < op.irt64()) as u64 }
+    op_sltu(op, t) { *op.mrd64() = (op.rs64() < op.rt64()) as u64 }
+    op_dadd(op, t) { check_overflow_add!(op, *op.mrd64(), op.irs64(), op.irt64()) }
+    op_daddu(op, t) { *op.mrd64() = op.rs64() + op.rt64() }
+    op_dsub(op, t) { check_overflow_sub!(op, *op.mrd64(), op.irs64(), op.irt64()) }
+    op_dsubu(op, t) { *op.mrd64() = op.rs64() - op.rt64() }
+    op_tge(op, t) { op.trap(op.irs64() >= op.irt64()) }
+    op_tgeu(op, t) { op.trap(op.rs64() >= op.rt64()) }
+    op_tlt(op, t) { op.trap(op.irs64() < op.irt64()) }
+    op_tltu(op, t) { op.trap(op.rs64() < op.rt64()) }
+    op_teq(op, t) { op.trap(op.rs64() == op.rt64()) }
+    op_tne(op, t) { op.trap(op.rs64() != op.rt64()) }
+
+    op_dsll(op, t) { *op.mrd64() = op.rt64() << op.sa() }
+    op_dsrl(op, t) { *op.mrd64() = op.rt64() >> op.sa() }
+    op_dsra(op, t) { *op.mrd64() = (op.irt64() >> op.sa()) as u64 }
+    op_dsll32(op, t) { *op.mrd64() = op.rt64() << (op.sa() + 32) }
+    op_dsrl32(op, t) { *op.mrd64() = op.rt64() >> (op.sa() + 32) }
+    op_dsra32(op, t) { *op.mrd64() = (op.irt64() >> (op.sa() + 32)) as u64 }
+
+    // REGIMM
+    op_bltz(op, t) { branch!(op, op.irs64() < 0, op.btgt(), link(false), likely(false)) }
+    op_bgez(op, t) { branch!(op, op.irs64() >= 0, op.btgt(), link(false), likely(false)) }
+    op_bltzl(op, t) { branch!(op, op.irs64() < 0, op.btgt(), link(false), likely(true)) }
+    op_bgezl(op, t) { branch!(op, op.irs64() >= 0, op.btgt(), link(false), likely(true)) }
+    op_tgei(op, t) { op.trap(op.irs64() >= op.sximm64()) }
+    op_tgeiu(op, t) { op.trap(op.rs64() >= op.sximm64() as u64) }
+    op_tlti(op, t) { op.trap(op.irs64() < op.sximm64()) }
+    op_tltiu(op, t) { op.trap(op.rs64() < op.sximm64() as u64) }
+    op_teqi(op, t) { op.trap(op.irs64() == op.sximm64()) }
+    op_tnei(op, t) { op.trap(op.irs64() != op.sximm64()) }
+    op_bltzal(op, t) { branch!(op, op.irs64() < 0, op.btgt(), link(true), likely(false)) }
+    op_bgezal(op, t) { branch!(op, op.irs64() >= 0, op.btgt(), link(true), likely(false)) }
+    op_bltzall(op, t) { branch!(op, op.irs64() < 0, op.btgt(), link(true), likely(true)) }
+    op_bgezall(op, t) { branch!(op, op.irs64() >= 0, op.btgt(), link(true), likely(true)) }
+
+    op_j(op, t) { branch!(op, true, op.jtgt(), link(false)) }
+    op_jal(op, t) { branch!(op, true, op.jtgt(), link(true)) }
+    op_beq(op, t) { branch!(op, op.rs64() == op.rt64(), op.btgt()) }
+    op_bne(op, t) { branch!(op, op.rs64() != op.rt64(), op.btgt()) }
+    op_blez(op, t) { branch!(op, op.irs64() <= 0, op.btgt()) }
+    op_bgtz(op, t) { branch!(op, op.irs64() > 0, op.btgt()) }
+    op_addi(op, t) { check_overflow_add!(op, *op.mrt64(), op.irs32(), op.sximm32()) }
+    op_addiu(op, t) { *op.mrt64() = (op.irs32() + op.sximm32()).sx64() }
+    op_slti(op, t) { *op.mrt64() = (op.irs64() < op.sximm64()) as u64 }
+    op_sltiu(op, t) { *op.mrt64() = (op.rs64() < op.sximm64() as u64) as u64 }
+    op_andi(op, t) { *op.mrt64() = op.rs64() & op.imm64() }
+    op_ori(op, t) { *op.mrt64() = op.rs64() | op.imm64() }
+    op_xori(op, t) { *op.mrt64() = op.rs64() ^ op.imm64() }
+    op_lui(op, t) { *op.mrt64() = (op.sximm32() << 16).sx64() }
+
+    op_cop0(op, t) { if_cop!(op, cop0, return cop0.op(op.ctx, op.opcode, t) ) }
+    op_cop1(op, t) { if_cop!(op, cop1, return cop1.op(op.ctx, op.opcode, t) ) }
+    op_cop2(op, t) { if_cop!(op, cop2, return cop2.op(op.ctx, op.opcode, t) ) }
+    op_cop3(op, t) { if_cop!(op, cop3, return cop3.op(op.ctx, op.opcode, t) ) }
+    op_beql(op, t) { branch!(op, op.rs64() == op.rt64(), op.btgt(), likely(true)) }
+    op_bnel(op, t) { branch!(op, op.rs64() != op.rt64(), op.btgt(), likely(true)) }
+    op_blezl(op, t) { branch!(op, op.irs64() <= 0, op.btgt(), likely(true)) }
+    op_bgtzl(op, t) { branch!(op, op.irs64() > 0, op.btgt(), likely(true)) }
+    op_daddi(op, t) { check_overflow_add!(op, *op.mrt64(), op.irs64(), op.sximm64()) }
+    op_daddiu(op, t) { *op.mrt64() = (op.irs64() + op.sximm64()) as u64 }
+    op_ldl(op, t) { op.load::<u64, _>(t, |mem, ea, rt| lwl(ea, rt, mem))? }
+    op_ldr(op, t) { op.load::<u64, _>(t, |mem, ea, rt| lwr(ea, rt, mem))? }
+
+    op_lb(op, t) { op.load::<u8, _>(t, |mem, _, _| mem.sx64())? }
+    op_lh(op, t) { op.load::<u16, _>(t, |mem, _, _| mem.sx64())? }
+    op_lwl(op, t) { op.load::<u32, _>(t, |mem, ea, rt| lwl(ea, rt as u32, mem).sx64())? }
+    op_lw(op, t) { op.load::<u32, _>(t, |mem, _, _| mem.sx64())? }
+    op_lbu(op, t) { op.load::<u8, _>(t, |mem, _, _| mem as u64)? }
+    op_lhu(op, t) { op.load::<u16, _>(t, |mem, _, _| mem as u64)? }
+    op_lwr(op, t) { op.load::<u32, _>(t, |mem, ea, rt| lwr(ea, rt as u32, mem).sx64())? }
+    op_lwu(op, t) { op.load::<u32, _>(t, |mem, _, _| mem as u64)? }
+    op_sb(op, t) { op.store::<u8>(op.rt32() as u8, t)? }
+    op_sh(op, t) { op.store::<u16>(op.rt32() as u16, t)? }
+    op_swl(op, t) { let rt = op.rt32(); op.store_merge::<u32, _>(t, |mem, ea| swl(ea, rt, mem))? }
+    op_sw(op, t) { op.store::<u32>(op.rt32(), t)? }
+    op_sdl(op, t) { let rt = op.rt64(); op.store_merge::<u64, _>(t, |mem, ea| swl(ea, rt, mem))? }
+    op_sdr(op, t) { let rt = op.rt64(); op.store_merge::<u64, _>(t, |mem, ea| swr(ea, rt, mem))? }
+    op_swr(op, t) { let rt = op.rt32(); op.store_merge::<u32, _>(t, |mem, ea| swr(ea, rt, mem))? }
+
+    op_lwc1(op, t) { if_cop_loadstore!(op, cop1, lwc, t, load(u32)) }
+    op_lwc2(op, t) { if_cop_loadstore!(op, cop2, lwc, t, load(u32)) }
+    op_ldc1(op, t) { if_cop_loadstore!(op, cop1, ldc, t, load(u64)) }
+    op_ldc2(op, t) { if_cop_loadstore!(op, cop2, ldc, t, load(u64)) }
+    op_ld(op, t) { op.load::<u64, _>(t, |mem, _, _| mem)? }
+    op_swc1(op, t) { if_cop_loadstore!(op, cop1, swc, t, store(u32)) }
+    op_swc2(op, t) { if_cop_loadstore!(op, cop2, swc, t, store(u32)) }
+    op_sdc1(op, t) { if_cop_loadstore!(op, cop1, sdc, t, store(u64)) }
+    op_sdc2(op, t) { if_cop_loadstore!(op, cop2, sdc, t, store(u64)) }
+    op_sd(op, t) { op.store::<u64>(op.rt64(), t)? }
+}
+
+// Unaligned loads and stores: merge the value in memory (mem) and the
+// register (reg), according to the address.
+fn lwl<S: MemInt>(addr: u32, reg: S, mem: S) -> S {
+    let shift = (addr as usize & (S::SIZE - 1)) * 8;
+    let mask = S::truncate_from((1u64 << shift) - 1u64);
+    (reg & mask) | ((mem << shift) & !mask)
+}
+
+fn lwr<S: MemInt>(addr: u32, reg: S, mem: S) -> S {
+    let shift = (!addr as usize & (S::SIZE - 1)) * 8;
+    let mask = S::max_value() >> shift;
+    (reg & !mask) | ((mem >> shift) & mask)
+}
 
+fn swl<S: MemInt>(addr: u32, reg: S, mem: S) -> S {
+    let shift = (addr as usize & (S::SIZE - 1)) * 8;
+    let mask = S::max_value() >> shift;
+    (mem & !mask) | ((reg >> shift) & mask)
+}
+
+fn swr<S: MemInt>(addr: u32, reg: S, mem: S) -> S {
+    let shift = (!addr as usize & (S::SIZE - 1)) * 8;
+    let mask = S::truncate_from((1 << shift) - 1);
+    (mem & mask) | ((reg << shift) & !mask)
+}
+
+impl<C: Config> Cpu<C> {
     // Check if an opcode, when used as part of a loop, can produce different
     // results in different iterations. For instance, ADD RN,RN,RT changes
     // RN at each loop; AND RN,RN,RT doesn't.
@@ -561,21 +831,23 @@ impl<C: Config> Cpu<C> {
             0x01 | 0x02 | 0x03 | 0x04 | 0x05 | 0x06 | 0x07 | 0x14 | 0x15 | 0x16 | 0x17 => {
                 // Branch instructions are stable (they don't even modify
                 // registers)
-                return true;
+                true
             }
             0x0C | 0x0D | 0x0F => {
                 // ANDI/ORI/LUI cannot generate new register values over
                 // different loop iterations, so they are stable.
-                return true;
+                true
             }
-            0x20 | 0x21 | 0x22 | 0x23 | 0x24 | 0x25 | 0x26 | 0x27 => {
+            0x20..=0x27 => {
                 // Load opcode. Check if the address is raw memory, in which
                 // case we consider it stable.
                 let sximm32 = (opcode & 0xffff) as i16 as i32;
                 let rs = ((opcode >> 21) & 0x1f) as usize;
                 let ea = self.ctx.regs[rs] as u32 + sximm32 as u32;
-                let mem = self.bus.fetch_read_nolog::<u32>(C::addr_mask(ea));
-                return mem.is_mem();
+                match self.lookup(ea, false) {
+                    Some(addr) => self.bus.fetch_read_nolog::<u32>(addr).is_mem(),
+                    None => false,
+                }
             }
             0x28 | 0x29 | 0x2A | 0x2B | 0x2E => {
                 // Store opcode. Check if the address is raw memory, in which
@@ -583,16 +855,21 @@ impl<C: Config> Cpu<C> {
                 let sximm32 = (opcode & 0xffff) as i16 as i32;
                 let rs = ((opcode >> 21) & 0x1f) as usize;
                 let ea = self.ctx.regs[rs] as u32 + sximm32 as u32;
-                let mem = self.bus.fetch_write_nolog::<u32>(C::addr_mask(ea));
-                return mem.is_mem();
+                match self.lookup(ea, false) {
+                    Some(addr) => self.bus.fetch_write_nolog::<u32>(addr).is_mem(),
+                    None => false,
+                }
             }
             // All other opcodes by default are unstable
-            _ => return false,
+            _ => false,
         }
     }
 
     fn detect_busy_wait(&mut self, pc: u64, loop_len: usize) -> bool {
-        let mem = self.fetch(pc);
+        let mem = match self.lookup(pc as u32, true) {
+            Some(addr) => self.fetch(addr),
+            None => return false,
+        };
         let iter = mem.iter().unwrap();
 
         // FIXME: this is buggy if the memory area is shorter than the loop
@@ -602,32 +879,140 @@ impl<C: Config> Cpu<C> {
             }
         }
         self.ctx.clock = self.until;
-        return true;
+        true
+    }
+
+    // Fetch code at the specified physical address (see translate).
+    fn fetch(&mut self, addr: u32) -> MemIoR<u32> {
+        self.bus.fetch_read::<u32>(addr)
     }
 
-    fn fetch(&mut self, addr: u64) -> MemIoR<u32> {
-        self.bus.fetch_read::<u32>(C::pc_mask(addr as u32))
+    // Translate a virtual address into the address on the bus, for a data
+    // access or an instruction fetch. If the translation through the TLB
+    // fails, the exception is raised and None is returned.
+    #[inline(always)]
+    fn translate(&mut self, vaddr: u32, write: bool, fetch: bool) -> Option<u32> {
+        let addr = if C::TLB && is_mapped(vaddr) {
+            match self.tlb_translate(vaddr, write) {
+                Ok(addr) => addr,
+                Err(exc) => {
+                    if fetch {
+                        self.exception(exc);
+                    } else {
+                        self.insn_exception(exc);
+                    }
+                    return None;
+                }
+            }
+        } else {
+            vaddr
+        };
+        Some(if fetch {
+            C::pc_mask(addr)
+        } else {
+            C::addr_mask(addr)
+        })
     }
 
-    fn read<U: MemInt>(&self, addr: u32, t: &Tracer) -> Result<U> {
-        let val = self
-            .bus
-            .read::<U>(C::addr_mask(addr) & !(U::SIZE as u32 - 1));
+    // Like translate, but without side effects: exceptions are not raised.
+    fn lookup(&self, vaddr: u32, fetch: bool) -> Option<u32> {
+        let addr = if C::TLB && is_mapped(vaddr) {
+            self.tlb_translate(vaddr, false).ok()?
+        } else {
+            vaddr
+        };
+        Some(if fetch {
+            C::pc_mask(addr)
+        } else {
+            C::addr_mask(addr)
+        })
+    }
+
+    fn tlb_translate(&self, vaddr: u32, write: bool) -> std::result::Result<u32, Exception> {
+        // The core runs with 32-bit addresses, which are sign-extended.
+        let vaddr = vaddr as i32 as i64 as u64;
+        self.ctx
+            .mmu
+            .translate(vaddr, self.cop0.asid(), write)
+            .map_err(|err| match err {
+                TlbError::Miss => Exception::TlbRefill(vaddr, write),
+                TlbError::Invalid => Exception::TlbInvalid(vaddr, write),
+                TlbError::Modified => Exception::TlbModified(vaddr),
+            })
+    }
+
+    fn read<U: MemInt>(&mut self, addr: u32, t: &Tracer) -> Result<Option<U>> {
+        let paddr = match self.translate(addr, false, false) {
+            Some(paddr) => paddr,
+            None => return Ok(None),
+        };
+        let val = C::data_read::<U>(&self.bus, paddr & !(U::SIZE as u32 - 1));
         t.trace_mem_read(&self.name, addr.into(), U::ACCESS_SIZE, val.into())?;
-        Ok(val)
+        Ok(Some(val))
     }
 
     fn write<U: MemInt>(&mut self, addr: u32, val: U, t: &Tracer) -> Result<()> {
-        self.bus
-            .write::<U>(C::addr_mask(addr) & !(U::SIZE as u32 - 1), val);
+        let paddr = match self.translate(addr, true, false) {
+            Some(paddr) => paddr,
+            None => return Ok(()),
+        };
+        C::data_write::<U>(&mut self.bus, paddr & !(U::SIZE as u32 - 1), val);
         t.trace_mem_write(&self.name, addr.into(), U::ACCESS_SIZE, val.into())
     }
 
+    // Write the value returned by f, called with the value in memory at the
+    // same address.
+    fn write_merge<U: MemInt, F: FnOnce(U) -> U>(
+        &mut self,
+        addr: u32,
+        t: &Tracer,
+        f: F,
+    ) -> Result<()> {
+        let paddr = match self.translate(addr, true, false) {
+            Some(paddr) => paddr & !(U::SIZE as u32 - 1),
+            None => return Ok(()),
+        };
+        let mem = C::data_read::<U>(&self.bus, paddr);
+        t.trace_mem_read(&self.name, addr.into(), U::ACCESS_SIZE, mem.into())?;
+        let val = f(mem);
+        C::data_write::<U>(&mut self.bus, paddr, val);
+        t.trace_mem_write(&self.name, addr.into(), U::ACCESS_SIZE, val.into())
+    }
+
+    /// Predecode the specified number of bytes of code starting at the
+    /// specified address, so that the interpreter can directly dispatch them.
+    /// This is useful for small memories that are loaded by DMA right
+    /// before being executed (eg: RSP IMEM).
+    pub fn predecode(&mut self, pc: u32, len: usize) {
+        let pc = C::pc_mask(pc);
+        let mem = self.fetch(pc);
+        if let Some(iter) = mem.iter() {
+            self.icache.predecode(pc, iter.take(len / 4));
+        }
+    }
+
+    /// Read a word from memory as seen by the CPU, without side effects:
+    /// addresses not mapped to memory (eg: hardware registers, or pages not
+    /// mapped by the TLB) return None. This is meant for debugging tools.
+    pub fn peek(&self, addr: u64) -> Option<u32> {
+        let addr = self.lookup(addr as u32, false)?;
+        let mem = self.bus.fetch_read_nolog::<u32>(addr & !3);
+        if mem.is_mem() {
+            Some(mem.read())
+        } else {
+            None
+        }
+    }
+
     pub fn run(&mut self, until: i64, t: &Tracer) -> Result<()> {
+        if C::BLOCK_CACHE {
+            return self.run_compiled(until, t, |cpu, ctx, until, t| cpu.run_block(ctx, until, t));
+        }
         self.until = until;
 
         let ctx = unsafe { self.ctx.as_mut() };
-        let mut mem = self.fetch(ctx.pc);
+        let mut mem = None;
+        let mut mem_addr = 0;
         let mut last_mem_pc = ctx.pc;
 
         while ctx.clock < self.until {
@@ -640,28 +1025,94 @@ impl<C: Config> Cpu<C> {
             self.cop0.poll_interrupts(ctx);
 
             // Fetch the next memory area (unless we're looping, in which case
-            // we already have the memory pointer).
-            if ctx.pc != last_mem_pc {
-                mem = self.fetch(ctx.pc);
+            // we already have the memory pointer). With the TLB, the address
+            // is translated each time, as the mapping could have changed.
+            if mem.is_none() || ctx.pc != last_mem_pc || C::TLB {
+                mem_addr = match self.translate(ctx.pc as u32, false, true) {
+                    Some(addr) => addr,
+                    None => continue, // TLB exception: PC is at the handler
+                };
+                mem = Some(self.fetch(mem_addr));
                 last_mem_pc = ctx.pc;
             }
 
-            let mut iter = mem
+            let iter = mem
+                .as_ref()
+                .unwrap()
                 .iter()
                 .unwrap_or_else(|| panic!("jumped to non-linear memory: {}", ctx.pc.hex()));
+            let mut addr = mem_addr;
 
             // Tight loop: go through continuous memory, no branches, no IRQs
-            while let Some(op) = iter.next() {
+            for op in iter {
                 ctx.tight_exit = ctx.delay_slot;
                 ctx.delay_slot = false;
                 ctx.pc = ctx.next_pc;
                 ctx.next_pc += 4;
-                self.op(ctx, op, t)?;
+                let func = self.icache.get(addr, op);
+                addr += 4;
+                func(self, ctx, op, t)?;
                 t.trace_insn(&self.name, C::pc_mask(ctx.pc as u32) as u64)?;
                 if ctx.clock >= self.until || ctx.tight_exit {
                     break;
                 }
+                // Pages mapped by the TLB are not contiguous.
+                if C::TLB && addr & 0xFFF == 0 {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Execute the cached block at the current PC (see BlockCache). The block
+    // is stopped as soon as its code (or any other cached code) is
+    // overwritten, so that the following instructions are decoded again.
+    fn run_block(&mut self, ctx: &mut CpuContext, until: i64, t: &Tracer) -> Result<()> {
+        let addr = match self.translate(ctx.pc as u32, false, true) {
+            Some(addr) => addr,
+            None => return Ok(()), // TLB exception: PC is at the handler
+        };
+        let block = self.blocks.get(&mut self.bus, addr);
+        for &(op, func) in block.ops.iter() {
+            ctx.tight_exit = ctx.delay_slot;
+            ctx.delay_slot = false;
+            ctx.pc = ctx.next_pc;
+            ctx.next_pc += 4;
+            func(self, ctx, op, t)?;
+            t.trace_insn(&self.name, C::pc_mask(ctx.pc as u32) as u64)?;
+            if ctx.clock >= until || ctx.tight_exit || self.bus.has_written_pages() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the CPU until the specified clock, like run(), but delegating the
+    /// execution of the code to another engine (eg: a recompiler). exec is
+    /// called with the CPU on an instruction boundary (ctx.pc is the address
+    /// of the next instruction to fetch) and the target clock; it must
+    /// execute one or more instructions, going through the same sequence of
+    /// the interpreter for each of them, and return as soon as the clock
+    /// reaches the target or ctx.tight_exit is set.
+    pub fn run_compiled<F>(&mut self, until: i64, t: &Tracer, mut exec: F) -> Result<()>
+    where
+        F: FnMut(&mut Cpu<C>, &mut CpuContext, i64, &Tracer) -> Result<()>,
+    {
+        self.until = until;
+
+        let ctx = unsafe { self.ctx.as_mut() };
+        while ctx.clock < self.until {
+            if ctx.lines.halt {
+                ctx.clock = self.until;
+                return Ok(());
             }
+
+            // See if there are pending interrupts that COP0 can generate.
+            self.cop0.poll_interrupts(ctx);
+
+            let until = self.until;
+            exec(self, ctx, until, t)?;
         }
         Ok(())
     }
@@ -689,10 +1140,14 @@ impl<C: Config> sync::Subsystem for Cpu<C> {
     }
 }
 
+#[cfg(feature = "debugger")]
 impl<C: Config> Cpu<C> {
-    pub fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
+    pub fn render_debug<'a, 'ui>(&mut self, dr: &mut DebuggerRenderer<'a, 'ui>) {
         dr.render_disasmview(self);
         dr.render_regview(self);
+        if C::BLOCK_CACHE {
+            dr.render_cacheview(self);
+        }
 
         if !self.cop0.is_null_obj() {
             self.cop0.render_debug(dr);
@@ -709,6 +1164,20 @@ impl<C: Config> Cpu<C> {
     }
 }
 
+impl<C: Config> CacheView for Cpu<C> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        self.blocks.stats().clone()
+    }
+
+    fn reset_cache_stats(&mut self) {
+        self.blocks.reset_stats();
+    }
+}
+
 impl<C: Config> RegisterView for Cpu<C> {
     const WINDOW_SIZE: (f32, f32) = (380.0, 400.0);
     const COLUMNS: usize = 3;
@@ -772,9 +1241,7 @@ impl<C: Config> DisasmView for Cpu<C> {
         };
 
         while pc < pc_range.1 as u32 {
-            let mem = self
-                .bus
-                .fetch_read_nolog::<u32>(C::pc_mask(pc.into()) as u32);
+            let mem = self.bus.fetch_read_nolog::<u32>(C::pc_mask(pc));
 
             let iter = mem.iter();
             if iter.is_none() {
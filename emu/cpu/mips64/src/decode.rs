@@ -3,10 +3,10 @@ use super::{Arch, Config, Cop, Cpu};
 use emu::dbg::Operand;
 
 // Decoding format for arguments of load/store ops
-pub const MEMOP_FMT: &'static str = "{},{}({})";
+pub const MEMOP_FMT: &str = "{},{}({})";
 
 // Register names
-pub const REG_NAMES: [&'static str; 34] = [
+pub const REG_NAMES: [&str; 34] = [
     "zr", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
     "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra",
     "hi", "lo",
@@ -35,16 +35,16 @@ fn decode1<C: Config>(cpu: &Cpu<C>, opcode: u32, pc: u64) -> DecodedInsn {
     let special = opcode & 0x3f;
     let sa = ((opcode >> 6) & 0x1f) as u8;
     let vrt = (opcode >> 16) & 0x1f;
-    let rs = REG_NAMES[((opcode >> 21) & 0x1f) as usize].into();
-    let rt = REG_NAMES[((opcode >> 16) & 0x1f) as usize].into();
-    let rd = REG_NAMES[((opcode >> 11) & 0x1f) as usize].into();
+    let rs = REG_NAMES[((opcode >> 21) & 0x1f) as usize];
+    let rt = REG_NAMES[((opcode >> 16) & 0x1f) as usize];
+    let rd = REG_NAMES[((opcode >> 11) & 0x1f) as usize];
     let imm16 = (opcode & 0xffff) as u16;
     let sximm32 = (opcode & 0xffff) as i16 as i32 as u32;
     let sximm64 = (opcode & 0xffff) as i16 as i64;
     let btgt = (pc + 4 + sximm64 as u64 * 4) as u32;
     let jtgt = (((pc + 4) & 0xFFFF_FFFF_F000_0000) + ((opcode as u64 & 0x03FF_FFFF) * 4)) as u32;
-    let reghi = "hi".into();
-    let reglo = "lo".into();
+    let reghi = "hi";
+    let reglo = "lo";
 
     match op {
         0x00 => match special {
@@ -56,6 +56,7 @@ fn decode1<C: Config>(cpu: &Cpu<C>, opcode: u32, pc: u64) -> DecodedInsn {
             0x07 => DecodedInsn::new3("srav", OReg(rd), IReg(rt), IReg(rs)),
             0x08 => DecodedInsn::new1("jr", IReg(rs)),
             0x09 => DecodedInsn::new1("jalr", IReg(rs)),
+            0x0C => DecodedInsn::new0("syscall"),
             0x0D => DecodedInsn::new0("break"),
             0x0F => DecodedInsn::new0("sync"),
 
@@ -89,6 +90,12 @@ fn decode1<C: Config>(cpu: &Cpu<C>, opcode: u32, pc: u64) -> DecodedInsn {
             0x2D => DecodedInsn::new3("daddu", OReg(rd), IReg(rs), IReg(rt)),
             0x2E => DecodedInsn::new3("dsub", OReg(rd), IReg(rs), IReg(rt)),
             0x2F => DecodedInsn::new3("dsubu", OReg(rd), IReg(rs), IReg(rt)),
+            0x30 => DecodedInsn::new2("tge", IReg(rs), IReg(rt)),
+            0x31 => DecodedInsn::new2("tgeu", IReg(rs), IReg(rt)),
+            0x32 => DecodedInsn::new2("tlt", IReg(rs), IReg(rt)),
+            0x33 => DecodedInsn::new2("tltu", IReg(rs), IReg(rt)),
+            0x34 => DecodedInsn::new2("teq", IReg(rs), IReg(rt)),
+            0x36 => DecodedInsn::new2("tne", IReg(rs), IReg(rt)),
 
             _ => DecodedInsn::new1("unkspc", Imm32(special)),
         },
@@ -97,6 +104,12 @@ fn decode1<C: Config>(cpu: &Cpu<C>, opcode: u32, pc: u64) -> DecodedInsn {
             0x01 => DecodedInsn::new2("bgez", IReg(rs), Target(btgt.into())),
             0x02 => DecodedInsn::new2("bltzl", IReg(rs), Target(btgt.into())),
             0x03 => DecodedInsn::new2("bgezl", IReg(rs), Target(btgt.into())),
+            0x08 => DecodedInsn::new2("tgei", IReg(rs), Imm32(sximm32)),
+            0x09 => DecodedInsn::new2("tgeiu", IReg(rs), Imm32(sximm32)),
+            0x0A => DecodedInsn::new2("tlti", IReg(rs), Imm32(sximm32)),
+            0x0B => DecodedInsn::new2("tltiu", IReg(rs), Imm32(sximm32)),
+            0x0C => DecodedInsn::new2("teqi", IReg(rs), Imm32(sximm32)),
+            0x0E => DecodedInsn::new2("tnei", IReg(rs), Imm32(sximm32)),
             0x10 => DecodedInsn::new2("bltzal", IReg(rs), Target(btgt.into())),
             0x11 => DecodedInsn::new2("bgezal", IReg(rs), Target(btgt.into())),
             0x12 => DecodedInsn::new2("bltzall", IReg(rs), Target(btgt.into())),
@@ -167,7 +180,7 @@ fn decode1<C: Config>(cpu: &Cpu<C>, opcode: u32, pc: u64) -> DecodedInsn {
 fn humanize(insn: DecodedInsn) -> DecodedInsn {
     use self::Operand::*;
 
-    let zr = REG_NAMES[0].into();
+    let zr = REG_NAMES[0];
     let op0 = insn.args[0];
     let op1 = insn.args[1];
     let op2 = insn.args[2];
@@ -1,23 +1,24 @@
 use super::decode::{DecodedInsn, MEMOP_FMT, REG_NAMES};
 use super::{Cop, CpuContext};
 
-use emu::dbg::{DebuggerRenderer, Operand, RegisterSize, RegisterView, Result, Tracer};
+#[cfg(feature = "debugger")]
+use emu::dbg::DebuggerRenderer;
+use emu::dbg::{Operand, RegisterSize, RegisterView, Result, Tracer};
 use emu::int::Numerics;
 use emu::state::Field;
 
 use num::Float;
 use serde_derive::{Deserialize, Serialize};
-use slog;
 use slog::*;
 use std::marker::PhantomData;
 
-const FPU_REG_NAMES: [&'static str; 32] = [
+const FPU_REG_NAMES: [&str; 32] = [
     "f0", "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12", "f13", "f14",
     "f15", "f16", "f17", "f18", "f19", "f20", "f21", "f22", "f23", "f24", "f25", "f26", "f27",
     "f28", "f29", "f30", "f31",
 ];
 
-const FPU_CREG_NAMES: [&'static str; 32] = [
+const FPU_CREG_NAMES: [&str; 32] = [
     "?0?", "?1?", "?2?", "?3?", "?4?", "?5?", "?6?", "?7?", "?8?", "?9?", "?10?", "?11?", "?12?",
     "?13?", "?14?", "?15?", "?16?", "?17?", "?18?", "?19?", "?20?", "?21?", "?22?", "?23?", "?24?",
     "?25?", "?26?", "?27?", "?28?", "?29?", "?30?", "FCSR",
@@ -39,14 +40,14 @@ impl FpuContext {
         if self.fpu64 {
             self.regs[idx]
         } else {
-            (self.regs[idx + 0] & 0xFFFF_FFFF) | (self.regs[idx + 1] << 32)
+            (self.regs[idx] & 0xFFFF_FFFF) | (self.regs[idx + 1] << 32)
         }
     }
     fn set_fgr(&mut self, idx: usize, val: u64) {
         if self.fpu64 {
             self.regs[idx] = val;
         } else {
-            self.regs[idx + 0] = val & 0xFFFF_FFFF;
+            self.regs[idx] = val & 0xFFFF_FFFF;
             self.regs[idx + 1] = val >> 32;
         }
     }
@@ -89,7 +90,7 @@ impl FloatRawConvert for f32 {
         }
     }
     fn to_f32(self) -> f32 {
-        self as f32
+        self
     }
     fn to_f64(self) -> f64 {
         self as f64
@@ -118,7 +119,7 @@ impl FloatRawConvert for f64 {
         self as f32
     }
     fn to_f64(self) -> f64 {
-        self as f64
+        self
     }
     fn to_u64(self) -> u64 {
         self as u64
@@ -129,6 +130,7 @@ struct Fop<'a, F: Float + FloatRawConvert> {
     opcode: u32,
     fpu: &'a mut Fpu,
     ctx: &'a mut FpuContext,
+    #[allow(dead_code)]
     cpu: &'a mut CpuContext,
     phantom: PhantomData<F>,
 }
@@ -152,12 +154,14 @@ impl<'a, F: Float + FloatRawConvert> Fop<'a, F> {
     fn fs(&self) -> F {
         self.ctx.get_fpr(self.rs())
     }
+    #[allow(dead_code)]
     fn fgs(&self) -> u64 {
         self.ctx.get_fgr(self.rs())
     }
     fn ft(&self) -> F {
         self.ctx.get_fpr(self.rt())
     }
+    #[allow(dead_code)]
     fn fgt(&self) -> u64 {
         self.ctx.get_fgr(self.rt())
     }
@@ -246,7 +250,7 @@ impl Fpu {
             opcode,
             ctx: unsafe { self.ctx.as_mut() },
             fpu: self,
-            cpu: cpu,
+            cpu,
             phantom: PhantomData,
         };
         match op.func() {
@@ -290,14 +294,14 @@ impl Fpu {
                 let v = op.fs().neg();
                 op.set_fd(v)
             }
-            0x08 => approx!(op, t, bankers_round, to_i64, i64::max_value()), // ROUND.L.fmt
-            0x09 => approx!(op, t, trunc, to_i64, i64::max_value()),         // TRUNC.L.fmt
-            0x0A => approx!(op, t, ceil, to_i64, i64::max_value()),          // CEIL.L.fmt
-            0x0B => approx!(op, t, floor, to_i64, i64::max_value()),         // FLOOR.L.fmt
-            0x0C => approx!(op, t, bankers_round, to_i32, i32::max_value()), // ROUND.W.fmt
-            0x0D => approx!(op, t, trunc, to_i32, i32::max_value()),         // TRUNC.W.fmt
-            0x0E => approx!(op, t, ceil, to_i32, i32::max_value()),          // CEIL.W.fmt
-            0x0F => approx!(op, t, floor, to_i32, i32::max_value()),         // FLOOR.W.fmt
+            0x08 => approx!(op, t, bankers_round, to_i64, i64::MAX), // ROUND.L.fmt
+            0x09 => approx!(op, t, trunc, to_i64, i64::MAX),         // TRUNC.L.fmt
+            0x0A => approx!(op, t, ceil, to_i64, i64::MAX),          // CEIL.L.fmt
+            0x0B => approx!(op, t, floor, to_i64, i64::MAX),         // FLOOR.L.fmt
+            0x0C => approx!(op, t, bankers_round, to_i32, i32::MAX), // ROUND.W.fmt
+            0x0D => approx!(op, t, trunc, to_i32, i32::MAX),         // TRUNC.W.fmt
+            0x0E => approx!(op, t, ceil, to_i32, i32::MAX),          // CEIL.W.fmt
+            0x0F => approx!(op, t, floor, to_i32, i32::MAX),         // FLOOR.W.fmt
 
             0x20 => op.set_fgd(op.fs().to_f32().to_u64bits()), // CVT.S.fmt
             0x21 => op.set_fgd(op.fs().to_f64().to_u64bits()), // CVT.D.fmt
@@ -427,11 +431,11 @@ impl Cop for Fpu {
         match op {
             // Regular COP opcode. FPU is usually installed as COP1
             // (so we'll see 0x11), but let's be generic.
-            0x10 | 0x11 | 0x12 | 0x13 => {
+            0x10..=0x13 => {
                 let fmt = (opcode >> 21) & 0x1F;
-                let rt = REG_NAMES[((opcode >> 16) & 0x1f) as usize].into();
-                let fs = FPU_REG_NAMES[((opcode >> 11) & 0x1f) as usize].into();
-                let cfs = FPU_CREG_NAMES[((opcode >> 11) & 0x1f) as usize].into();
+                let rt = REG_NAMES[((opcode >> 16) & 0x1f) as usize];
+                let fs = FPU_REG_NAMES[((opcode >> 11) & 0x1f) as usize];
+                let cfs = FPU_CREG_NAMES[((opcode >> 11) & 0x1f) as usize];
                 match fmt {
                     0x0 => DecodedInsn::new2("mfc1", OReg(rt), IReg(fs)),
                     0x2 => DecodedInsn::new2("cfc1", OReg(rt), IReg(cfs)),
@@ -463,8 +467,8 @@ impl Cop for Fpu {
                     }
                     // Single/Double precision
                     0x10 | 0x11 => {
-                        let ft = FPU_REG_NAMES[((opcode >> 16) & 0x1f) as usize].into();
-                        let fd = FPU_REG_NAMES[((opcode >> 6) & 0x1f) as usize].into();
+                        let ft = FPU_REG_NAMES[((opcode >> 16) & 0x1f) as usize];
+                        let fd = FPU_REG_NAMES[((opcode >> 6) & 0x1f) as usize];
                         match func {
                             0x00 => DecodedInsn::new3(
                                 fp_suffix!("add", fmt),
@@ -544,7 +548,7 @@ impl Cop for Fpu {
                         }
                     }
                     0x14 | 0x15 => {
-                        let fd = FPU_REG_NAMES[((opcode >> 6) & 0x1f) as usize].into();
+                        let fd = FPU_REG_NAMES[((opcode >> 6) & 0x1f) as usize];
                         match func {
                             0x20 => DecodedInsn::new2(fp_suffix!("cvt.s", fmt), OReg(fd), IReg(fs)),
                             0x21 => DecodedInsn::new2(fp_suffix!("cvt.d", fmt), OReg(fd), IReg(fs)),
@@ -567,8 +571,8 @@ impl Cop for Fpu {
                     0x3E => "sdc2",
                     _ => unreachable!(),
                 };
-                let ft = FPU_REG_NAMES[((opcode >> 16) & 0x1f) as usize].into();
-                let rd = REG_NAMES[((opcode >> 21) & 0x1f) as usize].into();
+                let ft = FPU_REG_NAMES[((opcode >> 16) & 0x1f) as usize];
+                let rd = REG_NAMES[((opcode >> 21) & 0x1f) as usize];
                 let off = (opcode & 0xffff) as i16 as i32 as u32;
                 if op >= 0x39 {
                     DecodedInsn::new3(name, IReg(ft), Imm32(off), IReg(rd)).with_fmt(MEMOP_FMT)
@@ -580,7 +584,8 @@ impl Cop for Fpu {
         }
     }
 
-    fn render_debug(&mut self, dr: &DebuggerRenderer) {
+    #[cfg(feature = "debugger")]
+    fn render_debug(&mut self, dr: &mut DebuggerRenderer) {
         dr.render_regview(self);
     }
 }
@@ -626,9 +631,9 @@ impl RegisterView for Fpu {
                     format!("D:{:.5}", f64::from_u64bits(val))
                 };
 
-                let mut reg0 = self.ctx.regs[idx + 0] as u32;
+                let mut reg0 = self.ctx.regs[idx] as u32;
                 let mut reg1 = self.ctx.regs[idx + 1] as u32;
-                visit(FPU_REG_NAMES[idx + 0], Reg32(&mut reg0), None);
+                visit(FPU_REG_NAMES[idx], Reg32(&mut reg0), None);
                 visit(FPU_REG_NAMES[idx + 1], Reg32(&mut reg1), Some(&desc));
             }
         }
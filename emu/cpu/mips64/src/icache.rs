@@ -0,0 +1,77 @@
+use super::cpu::{Cpu, CpuContext};
+use super::Config;
+
+use emu::dbg::{Result, Tracer};
+
+/// OpFn is the function executing a specific opcode.
+pub type OpFn<C> = fn(&mut Cpu<C>, &mut CpuContext, u32, &Tracer) -> Result<()>;
+
+const PAGE_SHIFT: usize = 12;
+const PAGE_INSNS: usize = 1 << (PAGE_SHIFT - 2);
+
+// A decoded instruction: the function that executes it, tagged with the
+// opcode it was decoded from.
+struct DecodedOp<C: Config> {
+    opcode: u32,
+    func: OpFn<C>,
+}
+
+impl<C: Config> Clone for DecodedOp<C> {
+    fn clone(&self) -> Self {
+        DecodedOp {
+            opcode: self.opcode,
+            func: self.func,
+        }
+    }
+}
+
+/// DecodeCache holds predecoded instructions, organized in pages of 4KB of
+/// (physical) memory, which are allocated the first time code is executed
+/// within them.
+///
+/// Each entry is tagged with the opcode it was decoded from, and the tag
+/// is checked against the opcode fetched from memory before using the
+/// entry. This means that any write to memory (either by the CPU or by
+/// a DMA) implicitly invalidates the affected entries, without having to
+/// monitor all writes on the bus.
+pub(crate) struct DecodeCache<C: Config> {
+    pages: Vec<Option<Box<[Option<DecodedOp<C>>]>>>,
+}
+
+impl<C: Config> Default for DecodeCache<C> {
+    fn default() -> Self {
+        DecodeCache { pages: Vec::new() }
+    }
+}
+
+impl<C: Config> DecodeCache<C> {
+    /// Return the function executing the specified opcode, found at the
+    /// specified (physical) address. The opcode is decoded if the cache
+    /// does not hold it yet.
+    #[inline(always)]
+    pub fn get(&mut self, addr: u32, opcode: u32) -> OpFn<C> {
+        let page = addr as usize >> PAGE_SHIFT;
+        while page >= self.pages.len() {
+            self.pages.push(None);
+        }
+        let entries =
+            self.pages[page].get_or_insert_with(|| vec![None; PAGE_INSNS].into_boxed_slice());
+        let entry = &mut entries[(addr as usize >> 2) & (PAGE_INSNS - 1)];
+        match *entry {
+            Some(ref dec) if dec.opcode == opcode => dec.func,
+            _ => {
+                let func = Cpu::<C>::decode_op(opcode);
+                *entry = Some(DecodedOp { opcode, func });
+                func
+            }
+        }
+    }
+    /// Decode a block of contiguous instructions starting at the specified
+    /// (physical) address, so that they are ready to be dispatched when
+    /// executed.
+    pub fn predecode<I: Iterator<Item = u32>>(&mut self, addr: u32, ops: I) {
+        for (idx, op) in ops.enumerate() {
+            self.get(addr + idx as u32 * 4, op);
+        }
+    }
+}
@@ -1,8 +1,6 @@
-#![feature(nll)]
-#![feature(arbitrary_self_types)]
-#![feature(test)]
-#![feature(associated_type_defaults)]
-#![feature(concat_idents)]
+#![cfg_attr(feature = "nightly", feature(test))]
+// Stylistic lints that don't fit the low-level APIs of the crate.
+#![allow(clippy::type_complexity)]
 
 extern crate emu;
 extern crate num;
@@ -11,9 +9,11 @@ extern crate num;
 extern crate slog;
 
 mod arch;
+mod blockcache;
 mod cp0;
 mod cpu;
 mod fpu;
+mod icache;
 mod traits;
 
 pub(crate) mod decode;
@@ -24,4 +24,5 @@ pub use self::cp0::Cp0;
 pub use self::cpu::{Cpu, CpuContext, Exception};
 pub use self::decode::{DecodedInsn, REG_NAMES};
 pub use self::fpu::Fpu;
+pub use self::icache::OpFn;
 pub use self::traits::{Arch, Config, Cop, Cop0, CopNull};
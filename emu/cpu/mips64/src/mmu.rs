@@ -81,6 +81,17 @@ impl fmt::Debug for TlbEntry {
     }
 }
 
+/// The reason why a virtual address cannot be translated by the TLB.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TlbError {
+    /// No entry maps the address (TLB refill exception).
+    Miss,
+    /// The entry is not valid (TLB invalid exception).
+    Invalid,
+    /// The entry is not writable (TLB modified exception).
+    Modified,
+}
+
 // Memory mapping unit of a MIPS processor
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct Mmu([TlbEntry; 32]);
@@ -103,6 +114,28 @@ impl Mmu {
         None
     }
 
+    /// Translates a virtual address into a physical address, through the
+    /// entry that maps it for the specified ASID.
+    pub fn translate(&self, vaddr: u64, asid: u8, write: bool) -> Result<u32, TlbError> {
+        let entry = &self.0[self.probe(vaddr, asid).ok_or(TlbError::Miss)?];
+
+        // Each entry maps a pair of contiguous pages: the bit above the
+        // page offset selects the even (EntryLo0) or the odd one (EntryLo1).
+        let offset_mask = (entry.page_mask | 0x1FFF) >> 1;
+        let (lo, pfn) = if vaddr as u32 & (offset_mask + 1) == 0 {
+            (entry.lo0, entry.pfn0())
+        } else {
+            (entry.lo1, entry.pfn1())
+        };
+        if !lo.get_bit(1) {
+            return Err(TlbError::Invalid);
+        }
+        if write && !lo.get_bit(2) {
+            return Err(TlbError::Modified);
+        }
+        Ok(pfn & !offset_mask | vaddr as u32 & offset_mask)
+    }
+
     /// Reads a specific TLB index.
     pub fn read(&self, index: usize) -> &TlbEntry {
         &self.0[index]
@@ -120,7 +153,7 @@ impl Mmu {
         let entry = &mut self.0[index];
 
         entry.page_mask = page_mask;
-        entry.vpn2 = calc_vpn2(entry_hi);
+        entry.vpn2 = calc_vpn2(entry_hi) & !(page_mask >> 13);
 
         entry.asid = entry_hi as u8;
         entry.global = entry_lo0.get_bit(0) && entry_lo1.get_bit(0);
@@ -130,24 +163,28 @@ impl Mmu {
     }
 }
 
+/// Returns true if the (32-bit) virtual address belongs to a segment mapped
+/// through the TLB: kuseg, ksseg and kseg3. kseg0 and kseg1 are unmapped.
+#[inline(always)]
+pub fn is_mapped(vaddr: u32) -> bool {
+    !(0x8000_0000..0xC000_0000).contains(&vaddr)
+}
+
 pub fn calc_vpn2(addr: u64) -> u32 {
     (addr >> 35 & 0x1800_0000) as u32 | (addr >> 13 & 0x07FF_FFFF) as u32
 }
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "nightly")]
     extern crate test;
+    #[cfg(feature = "nightly")]
     use self::test::Bencher;
     use super::*;
 
-    // Available Page Masks
-    const PAGE_MASK_4_KB: u32 = 0b0000_0000_0000_0000_0000_0000;
-    const PAGE_MASK_16_KB: u32 = 0b0000_0000_0011_0000_0000_0000;
-    const PAGE_MASK_64_KB: u32 = 0b0000_0000_1111_0000_0000_0000;
-    const PAGE_MASK_256_KB: u32 = 0b0000_0011_1111_0000_0000_0000;
-    const PAGE_MASK_1_MB: u32 = 0b0000_1111_1111_0000_0000_0000;
-    const PAGE_MASK_4_MB: u32 = 0b0011_1111_1111_0000_0000_0000;
-    const PAGE_MASK_16_MB: u32 = 0b1111_1111_1111_0000_0000_0000;
+    // Available Page Masks (bits 24:13 of the PageMask register)
+    const PAGE_MASK_4_KB: u32 = 0b0000_0000_0000_0000_0000_0000_0000;
+    const PAGE_MASK_16_KB: u32 = 0b0000_0000_0000_0110_0000_0000_0000;
 
     #[test]
     fn test_mmu() {
@@ -215,6 +252,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_translate() {
+        let mut mmu = Mmu::default();
+
+        // 0x7F000000-0x7F001FFF -> 0x00200000 (read-only), 0x00300000 (dirty)
+        mmu.write(
+            0,
+            PAGE_MASK_4_KB,
+            0x7F00_0012,
+            (0x0020_0000 >> 12) << 6 | 0b010,
+            (0x0030_0000 >> 12) << 6 | 0b110,
+        );
+        assert_eq!(mmu.translate(0x7F00_0123, 0x12, false), Ok(0x0020_0123));
+        assert_eq!(mmu.translate(0x7F00_1FFC, 0x12, true), Ok(0x0030_0FFC));
+        assert_eq!(
+            mmu.translate(0x7F00_0123, 0x12, true),
+            Err(TlbError::Modified)
+        );
+        assert_eq!(mmu.translate(0x7F00_0123, 0x13, false), Err(TlbError::Miss));
+        assert_eq!(mmu.translate(0x7F00_2000, 0x12, false), Err(TlbError::Miss));
+
+        // kseg3, 16KB pages, global; the odd page is not valid
+        mmu.write(
+            1,
+            PAGE_MASK_16_KB,
+            0xFFFF_FFFF_C000_4000,
+            (0x0040_0000 >> 12) << 6 | 0b011,
+            0b001,
+        );
+        assert_eq!(
+            mmu.translate(0xFFFF_FFFF_C000_2345, 0x55, false),
+            Ok(0x0040_2345)
+        );
+        assert_eq!(
+            mmu.translate(0xFFFF_FFFF_C000_6345, 0x55, false),
+            Err(TlbError::Invalid)
+        );
+
+        assert!(is_mapped(0x7F00_0000));
+        assert!(!is_mapped(0x8000_0000));
+        assert!(!is_mapped(0xBFFF_FFFF));
+        assert!(is_mapped(0xC000_0000));
+    }
+
+    #[cfg(feature = "nightly")]
     #[bench]
     fn bench_tlb_probe_match(b: &mut Bencher) {
         let mut mmu = Mmu::default();
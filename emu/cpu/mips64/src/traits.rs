@@ -1,6 +1,10 @@
 use super::{CpuContext, DecodedInsn, Exception};
 use emu::bus::be::Bus;
-use emu::dbg::{DebuggerRenderer, Result, Tracer};
+#[cfg(feature = "debugger")]
+use emu::dbg::DebuggerRenderer;
+use emu::dbg::{Result, Tracer};
+use emu::memint::MemInt;
+use emu::sync::IrqLine;
 
 /// Arch is a trait that allows to customise the MIPS core at the opcode level.
 /// It is used to implement different MIPS variants (architecture levels).
@@ -9,7 +13,6 @@ pub trait Arch {
     // This is meant to be called always with literals and is expected to inline
     // and produce a compile-time boolean flag, which actually removes the
     // the opcode from the implementation.
-    #[inline(always)]
     fn has_op(op: &'static str) -> bool;
 }
 
@@ -25,6 +28,17 @@ pub trait Config {
     type Cop2: Cop;
     type Cop3: Cop;
 
+    /// Execute code through a cache of decoded basic blocks, rather than
+    /// fetching each instruction from memory. Blocks are invalidated by writes
+    /// through the CPU bus only, so this must be disabled for CPUs whose code
+    /// can be written through other buses.
+    const BLOCK_CACHE: bool = false;
+
+    /// Translate the addresses in the mapped segments (kuseg, ksseg and kseg3)
+    /// through the TLB, raising TLB exceptions when they are not mapped.
+    /// Otherwise, all addresses go straight through pc_mask and addr_mask.
+    const TLB: bool = false;
+
     // Mask PC before fetching from the bus. This should be reimplemented
     // by architectures that do not have a full 64-bit bus to simplify
     // bus mapping.
@@ -38,6 +52,19 @@ pub trait Config {
     fn addr_mask(addr: u32) -> u32 {
         addr & 0x1FFF_FFFF
     }
+
+    // Bus accesses of load and store instructions (at the physical address,
+    // aligned to the size of the access). Instruction fetches and DMAs go
+    // straight to the bus, so this can be reimplemented to emulate areas
+    // whose data accesses have side effects, while keeping them mapped as
+    // linear memory.
+    fn data_read<U: MemInt>(bus: &Bus, paddr: u32) -> U {
+        bus.read::<U>(paddr)
+    }
+
+    fn data_write<U: MemInt>(bus: &mut Bus, paddr: u32, val: U) {
+        bus.write::<U>(paddr, val)
+    }
 }
 
 /// Cop is a MIPS64 coprocessor that can be installed within the core.
@@ -61,7 +88,7 @@ pub trait Cop {
     fn ldc(&mut self, op: u32, ctx: &mut CpuContext, bus: &Bus, _t: &Tracer) -> Result<()> {
         let rt = ((op >> 16) & 0x1f) as usize;
         let ea = ctx.regs[((op >> 21) & 0x1f) as usize] as u32 + (op & 0xffff) as i16 as i32 as u32;
-        let val = bus.read::<u64>(ea & 0x1FFF_FFFC) as u64;
+        let val = bus.read::<u64>(ea & 0x1FFF_FFFC);
         self.set_reg(ctx, rt, val as u128);
         Ok(())
     }
@@ -83,7 +110,8 @@ pub trait Cop {
     }
 
     // Implement some debugger views
-    fn render_debug<'a, 'ui>(&mut self, _dr: &DebuggerRenderer<'a, 'ui>) {}
+    #[cfg(feature = "debugger")]
+    fn render_debug<'a, 'ui>(&mut self, _dr: &mut DebuggerRenderer<'a, 'ui>) {}
 
     // Internal check to efficiently handle empty coprocessors
     #[doc(hidden)]
@@ -104,6 +132,11 @@ pub trait Cop0: Cop {
     // (because IP0/IP1 are used for software interrupts).
     fn set_hwint_line(&mut self, line: usize, status: bool);
 
+    /// Connect an external interrupt line driven by another subsystem (same
+    /// numbering as set_hwint_line). Its level is sampled when polling
+    /// interrupts.
+    fn connect_hwint_line(&mut self, _line: usize, _irq: IrqLine) {}
+
     /// Poll pending interrupts. This function is called in the main interpreter
     /// loop very often, so that Cop0 has a chance of triggering interrupts
     /// when they are raised.
@@ -113,6 +146,17 @@ pub trait Cop0: Cop {
 
     /// Trigger the specified excepion.
     fn exception(&mut self, ctx: &mut CpuContext, exc: Exception);
+
+    /// Current address space identifier, used to match TLB entries.
+    fn asid(&self) -> u8 {
+        0
+    }
+
+    /// Return whether the specified coprocessor can be used. Otherwise, its
+    /// instructions raise a Coprocessor Unusable exception.
+    fn cop_usable(&self, _cop: usize) -> bool {
+        true
+    }
 }
 
 pub struct CopNull {}
@@ -1,4 +1,4 @@
-#![recursion_limit = "128"]
+#![recursion_limit = "256"]
 
 #[macro_use]
 extern crate synstructure;
@@ -13,23 +13,18 @@ use std::num::ParseIntError;
 use synstructure::BindStyle;
 use synstructure::ToTokens;
 
-decl_derive!([DeviceLE, attributes(reg, mem)] => derive_device_le);
-decl_derive!([DeviceBE, attributes(reg, mem)] => derive_device_be);
+decl_derive!([DeviceLE, attributes(reg, mem, device)] => derive_device_le);
+decl_derive!([DeviceBE, attributes(reg, mem, device)] => derive_device_be);
 
 // Copy of emu::bus::BusFill
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 enum BusFill {
+    #[default]
     None,
     Mirror,
     Fixed(u8),
 }
 
-impl Default for BusFill {
-    fn default() -> Self {
-        BusFill::None
-    }
-}
-
 impl ToTokens for BusFill {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         match *self {
@@ -70,16 +65,16 @@ struct MemAttributes {
 }
 
 fn parse_u32(s: &str) -> Result<u32, ParseIntError> {
-    if s.starts_with("0x") {
-        u32::from_str_radix(&s[2..].replace("_", ""), 16)
+    if let Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(&hex.replace('_', ""), 16)
     } else {
         s.parse::<u32>()
     }
 }
 
 fn parse_usize(s: &str) -> Result<usize, ParseIntError> {
-    if s.starts_with("0x") {
-        usize::from_str_radix(&s[2..].replace("_", ""), 16)
+    if let Some(hex) = s.strip_prefix("0x") {
+        usize::from_str_radix(&hex.replace('_', ""), 16)
     } else {
         s.parse::<usize>()
     }
@@ -92,77 +87,74 @@ fn parse_reg_attributes(varname: &str, attrs: &proc_macro2::TokenStream) -> RegA
     let allattrs = format!("{}", attrs);
     for attr in allattrs[1..allattrs.len() - 1].split(",") {
         let kv = attr.split("=").collect::<Vec<_>>();
-        match kv[0].trim().as_ref() {
+        match kv[0].trim() {
             "rwmask" => {
                 if kv.len() != 2 {
-                    panic!(format!("{}: no argument for rwmask", varname))
+                    panic!("{}: no argument for rwmask", varname)
                 }
                 ra.rwmask = kv[1].trim().to_string();
             }
             "init" => {
                 if kv.len() != 2 {
-                    panic!(format!("{}: no argument for init", varname))
+                    panic!("{}: no argument for init", varname)
                 }
                 ra.init = kv[1].trim().to_string();
             }
             "wcb" => {
                 if kv.len() != 1 {
-                    panic!(format!("{}: unexpected argument for wcb", varname))
+                    panic!("{}: unexpected argument for wcb", varname)
                 }
                 ra.wcb = true;
             }
             "rcb" => {
                 if kv.len() != 1 {
-                    panic!(format!("{}: unexpected argument for rcb", varname))
+                    panic!("{}: unexpected argument for rcb", varname)
                 }
                 ra.rcb = true;
             }
             "readonly" => {
                 if kv.len() != 1 {
-                    panic!(format!("{}: unexpected argument for readonly", varname))
+                    panic!("{}: unexpected argument for readonly", varname)
                 }
                 ra.readonly = true;
             }
             "writeonly" => {
                 if kv.len() != 1 {
-                    panic!(format!("{}: unexpected argument for writeonly", varname))
+                    panic!("{}: unexpected argument for writeonly", varname)
                 }
                 ra.writeonly = true;
             }
             "bank" => {
                 if kv.len() != 2 {
-                    panic!(format!("{}: no argument for bank", varname))
+                    panic!("{}: no argument for bank", varname)
                 }
                 ra.bank = kv[1]
                     .trim()
                     .parse::<usize>()
-                    .expect(&format!("cannot parse bank: {:?}", kv[1].trim()));
+                    .unwrap_or_else(|_| panic!("cannot parse bank: {:?}", kv[1].trim()));
             }
             "offset" => {
                 if kv.len() != 2 {
-                    panic!(format!("{}: no argument for offset", varname))
+                    panic!("{}: no argument for offset", varname)
                 }
                 ra.offset = parse_u32(kv[1].trim())
-                    .expect(&format!("cannot parse offset: {:?}", kv[1].trim()));
+                    .unwrap_or_else(|_| panic!("cannot parse offset: {:?}", kv[1].trim()));
                 offsetfound = true;
             }
-            _ => panic!(format!("{}: invalid attribute: {}", varname, kv[0].trim())),
+            _ => panic!("{}: invalid attribute: {}", varname, kv[0].trim()),
         }
     }
     if ra.readonly && ra.writeonly {
-        panic!(format!(
-            "{}: cannot be both readonly and writeonly",
-            varname
-        ));
+        panic!("{}: cannot be both readonly and writeonly", varname);
     }
     if ra.readonly && ra.wcb {
-        panic!(format!("{}: cannot specify wcb for readonly reg", varname));
+        panic!("{}: cannot specify wcb for readonly reg", varname);
     }
     if ra.writeonly && ra.rcb {
-        panic!(format!("{}: cannot specify rcb for writeonly reg", varname));
+        panic!("{}: cannot specify rcb for writeonly reg", varname);
     }
     if !offsetfound {
-        panic!(format!("{}: mandatory offset is missing", varname));
+        panic!("{}: mandatory offset is missing", varname);
     }
     if ra.init.is_empty() {
         ra.init = String::from("0");
@@ -170,7 +162,7 @@ fn parse_reg_attributes(varname: &str, attrs: &proc_macro2::TokenStream) -> RegA
     if ra.rwmask.is_empty() {
         ra.rwmask = String::from("4294967295");
     }
-    return ra;
+    ra
 }
 
 fn parse_mem_attributes(varname: &str, attrs: &proc_macro2::TokenStream) -> MemAttributes {
@@ -180,49 +172,49 @@ fn parse_mem_attributes(varname: &str, attrs: &proc_macro2::TokenStream) -> MemA
     let allattrs = format!("{}", attrs);
     for attr in allattrs[1..allattrs.len() - 1].split(",") {
         let kv = attr.split("=").collect::<Vec<_>>();
-        match kv[0].trim().as_ref() {
+        match kv[0].trim() {
             "size" => {
                 if kv.len() != 2 {
-                    panic!(format!("{}: no argument for size", varname))
+                    panic!("{}: no argument for size", varname)
                 }
 
                 ma.size = parse_usize(kv[1].trim())
-                    .expect(&format!("cannot parse size: {:?}", kv[1].trim()));
+                    .unwrap_or_else(|_| panic!("cannot parse size: {:?}", kv[1].trim()));
             }
             "readonly" => {
                 if kv.len() != 1 {
-                    panic!(format!("{}: unexpected argument for readonly", varname))
+                    panic!("{}: unexpected argument for readonly", varname)
                 }
                 ma.readonly = true;
             }
             "writeonly" => {
                 if kv.len() != 1 {
-                    panic!(format!("{}: unexpected argument for writeonly", varname))
+                    panic!("{}: unexpected argument for writeonly", varname)
                 }
                 ma.writeonly = true;
             }
             "bank" => {
                 if kv.len() != 2 {
-                    panic!(format!("{}: no argument for bank", varname))
+                    panic!("{}: no argument for bank", varname)
                 }
                 ma.bank = parse_usize(kv[1].trim())
-                    .expect(&format!("cannot parse bank: {:?}", kv[1].trim()));
+                    .unwrap_or_else(|_| panic!("cannot parse bank: {:?}", kv[1].trim()));
             }
             "offset" => {
                 if kv.len() != 2 {
-                    panic!(format!("{}: no argument for offset", varname))
+                    panic!("{}: no argument for offset", varname)
                 }
                 ma.offset = parse_u32(kv[1].trim())
-                    .expect(&format!("cannot parse offset: {:?}", kv[1].trim()));
+                    .unwrap_or_else(|_| panic!("cannot parse offset: {:?}", kv[1].trim()));
                 offsetfound = true;
             }
             "vsize" => {
                 if kv.len() != 2 {
-                    panic!(format!("{}: no argument for size", varname))
+                    panic!("{}: no argument for size", varname)
                 }
 
                 ma.vsize = parse_u32(kv[1].trim())
-                    .expect(&format!("cannot parse vsize: {:?}", kv[1].trim()));
+                    .unwrap_or_else(|_| panic!("cannot parse vsize: {:?}", kv[1].trim()));
             }
             "fill" => {
                 let fillval = kv[1].replace(" ", "");
@@ -233,10 +225,10 @@ fn parse_mem_attributes(varname: &str, attrs: &proc_macro2::TokenStream) -> MemA
                     "\"Mirror\"" => BusFill::Mirror,
                     _ => {
                         if fillval.starts_with("\"Fixed(") && fillval.ends_with(")\"") {
-                            let fixed = parse_u32(&fillval[7..fillval.len() - 2]).expect(&format!(
-                                "{}: cannot parse fixed-size variant value",
-                                varname
-                            ));
+                            let fixed =
+                                parse_u32(&fillval[7..fillval.len() - 2]).unwrap_or_else(|_| {
+                                    panic!("{}: cannot parse fixed-size variant value", varname)
+                                });
                             if fixed >= 256 {
                                 panic!("{}: fixed-size value is too big", varname);
                             }
@@ -247,23 +239,20 @@ fn parse_mem_attributes(varname: &str, attrs: &proc_macro2::TokenStream) -> MemA
                     }
                 };
             }
-            _ => panic!(format!("{}: invalid attribute: {}", varname, kv[0].trim())),
+            _ => panic!("{}: invalid attribute: {}", varname, kv[0].trim()),
         }
     }
 
     if !offsetfound {
-        panic!(format!("{}: mandatory offset is missing", varname));
+        panic!("{}: mandatory offset is missing", varname);
     }
     if ma.vsize == 0 {
         ma.vsize = ma.size as u32;
     }
     if ma.readonly && ma.writeonly {
-        panic!(format!(
-            "{}: cannot be both readonly and writeonly",
-            varname
-        ));
+        panic!("{}: cannot be both readonly and writeonly", varname);
     }
-    return ma;
+    ma
 }
 
 fn expand_reg_devinit(
@@ -272,6 +261,7 @@ fn expand_reg_devinit(
     dev_name: &str,
     varname: &str,
     ra: &RegAttributes,
+    flags: &proc_macro2::TokenStream,
 ) -> proc_macro2::TokenStream {
     let mut qrcb = quote! {None};
     let mut qwcb = quote! {None};
@@ -298,9 +288,10 @@ fn expand_reg_devinit(
         }
     }
 
-    let init = parse_u32(&ra.init).expect(&format!("cannot parse init value: {:?}", ra.init));
-    let rwmask =
-        parse_u32(&ra.rwmask).expect(&format!("cannot parse rwmask value: {:?}", ra.rwmask));
+    let init =
+        parse_u32(&ra.init).unwrap_or_else(|_| panic!("cannot parse init value: {:?}", ra.init));
+    let rwmask = parse_u32(&ra.rwmask)
+        .unwrap_or_else(|_| panic!("cannot parse rwmask value: {:?}", ra.rwmask));
     let read = !ra.writeonly;
     let write = !ra.readonly;
     quote! {
@@ -308,7 +299,7 @@ fn expand_reg_devinit(
             concat!(#dev_name, "::", #varname),
             #init,
             #rwmask,
-            RegFlags::new(#read, #write),
+            RegFlags::new(#read, #write) #flags,
             #qwcb,
             #qrcb,
         );
@@ -361,6 +352,25 @@ fn expand_reg_devmap(
     }
 }
 
+fn expand_reg_info(varname: &str, ra: &RegAttributes) -> proc_macro2::TokenStream {
+    let bank = ra.bank;
+    let off = ra.offset;
+    let field = Ident::new(varname, Span::call_site());
+    quote! {
+        regs.push(RegInfo::new(#varname, #bank, #off, &self. #field));
+    }
+}
+
+fn expand_mem_info(varname: &str, ma: &MemAttributes) -> proc_macro2::TokenStream {
+    let bank = ma.bank;
+    let off = ma.offset;
+    let vsize = ma.vsize;
+    let field = Ident::new(varname, Span::call_site());
+    quote! {
+        mems.push(MemInfo::new(#varname, #bank, #off, #vsize, &self. #field));
+    }
+}
+
 fn expand_mem_devmap(
     _fi: &synstructure::BindingInfo,
     varname: &str,
@@ -378,19 +388,79 @@ fn expand_mem_devmap(
     }
 }
 
+#[derive(Default, Debug)]
+struct DeviceAttributes {
+    reset: bool,           // the device has a reset callback
+    cpu: bool,             // the device is a CPU (dereferencing to a Subsystem)
+    subword: &'static str, // RegFlags policy for narrow writes to registers
+}
+
+// Parse the attributes of the device struct (#[device(...)]).
+fn parse_device_attributes(dev_name: &str, attrs: &[syn::Attribute]) -> DeviceAttributes {
+    let mut da = DeviceAttributes::default();
+    for attr in attrs {
+        if attr.path.segments.last().unwrap().value().ident != "device" {
+            continue;
+        }
+        let allattrs = format!("{}", attr.tts);
+        for arg in allattrs[1..allattrs.len() - 1].split(",") {
+            let kv = arg.split("=").collect::<Vec<_>>();
+            match kv[0].trim() {
+                "reset" if kv.len() == 1 => da.reset = true,
+                "cpu" if kv.len() == 1 => da.cpu = true,
+                "subword" if kv.len() == 2 => {
+                    da.subword = match kv[1].trim() {
+                        "\"merge\"" => "",
+                        "\"shift\"" => "SUBWORD_SHIFT",
+                        "\"ignore\"" => "SUBWORD_IGNORE",
+                        _ => panic!(
+                            "{}: invalid subword policy: {} (must be merge, shift or ignore)",
+                            dev_name,
+                            kv[1].trim()
+                        ),
+                    }
+                }
+                _ => panic!("{}: invalid attribute: {}", dev_name, arg.trim()),
+            }
+        }
+    }
+    da
+}
+
 fn derive_device(mut s: synstructure::Structure, bigendian: bool) -> proc_macro2::TokenStream {
-    s.filter(|fi| fi.ast().attrs.len() != 0);
+    s.filter(|fi| !fi.ast().attrs.is_empty());
     s.bind_with(|_fi| BindStyle::RefMut);
 
     let dev_ident = s.ast().ident.clone();
     let dev_name = s.ast().ident.to_string();
+    let da = parse_device_attributes(&dev_name, &s.ast().attrs);
+    let dev_reset = if da.reset {
+        quote! { self.cb_reset(hard); }
+    } else {
+        quote! {}
+    };
+    let reg_flags = if da.subword.is_empty() {
+        quote! {}
+    } else {
+        let flag = Ident::new(da.subword, Span::call_site());
+        quote! { | RegFlags:: #flag }
+    };
+    let dev_cpu = if da.cpu {
+        quote! {{
+            use emu::sync::Subsystem;
+            Some(self.name().to_owned())
+        }}
+    } else {
+        quote! { None }
+    };
     let mut dev_map = quote! {};
+    let mut dev_info = quote! {};
     let dev_init = s.each(|fi| {
         let varname = fi.ast().ident.as_ref().unwrap().to_string();
 
         let attrs = &fi.ast().attrs;
         if attrs.len() != 1 {
-            panic!(format!("{}: too many attributes", varname));
+            panic!("{}: too many attributes", varname);
         }
 
         match attrs[0]
@@ -411,7 +481,12 @@ fn derive_device(mut s: synstructure::Structure, bigendian: bool) -> proc_macro2
                     #dev_map
                     #dm;
                 };
-                expand_reg_devinit(fi, &dev_ident, &dev_name, &varname, &ra)
+                let di = expand_reg_info(&varname, &ra);
+                dev_info = quote! {
+                    #dev_info
+                    #di
+                };
+                expand_reg_devinit(fi, &dev_ident, &dev_name, &varname, &ra, &reg_flags)
             }
 
             "mem" => {
@@ -422,6 +497,11 @@ fn derive_device(mut s: synstructure::Structure, bigendian: bool) -> proc_macro2
                     #dev_map
                     #dm;
                 };
+                let di = expand_mem_info(&varname, &ma);
+                dev_info = quote! {
+                    #dev_info
+                    #di
+                };
                 expand_mem_devinit(fi, &dev_name, &varname, &ma)
             }
             _ => unreachable!(),
@@ -436,16 +516,18 @@ fn derive_device(mut s: synstructure::Structure, bigendian: bool) -> proc_macro2
         },
         Span::call_site(),
     );
-    s.gen_impl(quote! {
+    // gen_impl wraps the impl into a named const, which newer compilers
+    // report as a non-local definition.
+    let imp = s.gen_impl(quote! {
         use ::std::result::Result;
         use ::std::cell::{RefCell};
         use ::std::rc::{Rc};
         use ::std::pin::{Pin};
-        use emu::bus::{CurrentDeviceMap, Bus, Device, BusFill};
+        use emu::bus::{CurrentDeviceMap, Bus, Device, BusFill, DeviceInfo};
         use byteorder:: #endian;
 
         #[allow(unused_imports)]
-        use emu::bus::{Reg, RegFlags, Mem, MemFlags};
+        use emu::bus::{Reg, RegFlags, Mem, MemFlags, RegInfo, MemInfo};
 
         gen impl Device for @Self {
             type Order = #endian;
@@ -467,8 +549,30 @@ fn derive_device(mut s: synstructure::Structure, bigendian: bool) -> proc_macro2
                 #dev_map
                 Ok(())
             }
+
+            #[allow(unused_variables)]
+            fn reset(&mut self, hard: bool) {
+                #dev_reset
+            }
+
+            #[allow(unused_mut)]
+            fn info(&self) -> DeviceInfo {
+                let mut regs = Vec::new();
+                let mut mems = Vec::new();
+                #dev_info
+                DeviceInfo {
+                    name: #dev_name,
+                    cpu: #dev_cpu,
+                    regs,
+                    mems,
+                }
+            }
         }
-    })
+    });
+    quote! {
+        #[allow(unknown_lints, non_local_definitions)]
+        #imp
+    }
 }
 
 fn derive_device_le(s: synstructure::Structure) -> proc_macro2::TokenStream {
@@ -2,6 +2,7 @@ use super::device::Device;
 use super::mem::Mem;
 use super::radix::RadixTree;
 use super::regs::Reg;
+use crate::errors::DeviceError;
 use crate::memint::{AccessSize, ByteOrderCombiner, MemInt};
 use crate::state::ArrayField;
 
@@ -21,7 +22,7 @@ use std::slice;
 
 pub(crate) enum HwIoR {
     Mem(ArrayField<u8>, u32),
-    Func(Rc<Fn(u32) -> u64>),
+    Func(Rc<dyn Fn(u32) -> u64>),
 }
 
 impl Clone for HwIoR {
@@ -36,7 +37,7 @@ impl Clone for HwIoR {
 
 pub(crate) enum HwIoW {
     Mem(ArrayField<u8>, u32),
-    Func(Rc<RefCell<FnMut(u32, u64)>>),
+    Func(Rc<RefCell<dyn FnMut(u32, u64)>>),
 }
 
 impl Clone for HwIoW {
@@ -84,7 +85,7 @@ impl HwIoW {
             }
             HwIoW::Func(ref mut f) => {
                 let mut func = f.borrow_mut();
-                (&mut *func)(addr, val.into());
+                (*func)(addr, val.into());
             }
         }
     }
@@ -123,7 +124,7 @@ impl<O: ByteOrder, U: MemInt> MemIoR<O, U> {
     // If MemIoR points to a memory area, returns an iterator over it
     // that yields consecutive elements of type U.
     // Otherwise, returns None.
-    pub fn iter(&self) -> Option<MemIoRIterator<U>> {
+    pub fn iter(&self) -> Option<MemIoRIterator<'_, U>> {
         //impl Iterator<Item = U>> {
         match &self.hwio {
             HwIoR::Mem(buf, mask) => Some(
@@ -153,15 +154,11 @@ impl<O: ByteOrder, U: MemInt> io::Read for MemIoR<O, U> {
             HwIoR::Mem(buf, mask) => {
                 (&buf[(self.addr & mask) as usize..])
                     .read(out)
-                    .and_then(|sz| {
+                    .inspect(|&sz| {
                         self.addr += sz as u32;
-                        Ok(sz)
                     })
             }
-            HwIoR::Func(_) => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "memory area is not a linearly mapped",
-            )),
+            HwIoR::Func(_) => Err(io::Error::other("memory area is not a linearly mapped")),
         }
     }
 }
@@ -203,10 +200,11 @@ impl<O: ByteOrder> MemIoW<O, u8> {
 /// [`Bus::map_mem()`](struct.Bus.html#method.map_mem).
 ///
 /// BusFill implements the `Default` trait with its `None` variant.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub enum BusFill {
     /// `None` means that no behavior is specified because the mapping should
     /// not exceed the physical size. If this is not true, a panic will be raised.
+    #[default]
     None,
 
     /// `Mirror` means that the bus should mirror the bus object over the virtual
@@ -221,16 +219,10 @@ pub enum BusFill {
     Fixed(u8),
 }
 
-impl Default for BusFill {
-    fn default() -> Self {
-        BusFill::None
-    }
-}
-
 pub(crate) fn unmapped_area_r() -> HwIoR {
     thread_local!(
-        static FN: Rc<Fn(u32)->u64> = Rc::new(|_| {
-            return 0xffff_ffff_ffff_ffff;
+        static FN: Rc<dyn Fn(u32)->u64> = Rc::new(|_| {
+            0xffff_ffff_ffff_ffff
         })
     );
     HwIoR::Func(FN.with(|c| c.clone()))
@@ -238,11 +230,15 @@ pub(crate) fn unmapped_area_r() -> HwIoR {
 
 pub(crate) fn unmapped_area_w() -> HwIoW {
     thread_local!(
-        static FN: Rc<RefCell<FnMut(u32,u64)>> = Rc::new(RefCell::new(|_,_| {}))
+        static FN: Rc<RefCell<dyn FnMut(u32,u64)>> = Rc::new(RefCell::new(|_,_| {}))
     );
     HwIoW::Func(FN.with(|c| c.clone()))
 }
 
+/// Size of the pages whose writes can be watched (see Bus::watch_writes).
+pub const WATCH_PAGE_SIZE: u32 = 1 << WATCH_PAGE_SHIFT;
+const WATCH_PAGE_SHIFT: u32 = 12;
+
 pub struct Bus<Order: ByteOrderCombiner> {
     reads: EnumMap<AccessSize, Box<RadixTree<HwIoR>>>,
     writes: EnumMap<AccessSize, Box<RadixTree<HwIoW>>>,
@@ -250,6 +246,10 @@ pub struct Bus<Order: ByteOrderCombiner> {
     fillers: [ArrayField<u8>; 256],
     unmap_r: HwIoR,
     unmap_w: HwIoW,
+    mirrors: Vec<(u32, u32, u32)>, // begin, end, mask
+
+    watched: Vec<u64>, // bitmap of the watched pages (empty if none)
+    written: Vec<u32>, // watched pages written since take_written_pages
 
     logger: slog::Logger,
 
@@ -280,52 +280,141 @@ where
             fillers: array![|idx| ArrayField::internal_new(&format!("Bus::filler{}", idx), idx as u8, 64, false); 256],
             unmap_r: unmapped_area_r(),
             unmap_w: unmapped_area_w(),
-            logger: logger,
+            mirrors: Vec::new(),
+            watched: Vec::new(),
+            written: Vec::new(),
+            logger,
             phantom: PhantomData,
         })
     }
 
     pub fn read<U: MemInt + 'a>(&self, addr: u32) -> U {
-        self.internal_fetch_read::<U>(addr, true)
-            .read::<Order, U>(addr)
+        let (hwio, addr) = self.internal_fetch_read::<U>(addr, true);
+        hwio.read::<Order, U>(addr)
     }
 
     pub fn write<U: MemInt + 'a>(&mut self, addr: u32, val: U) {
-        self.internal_fetch_write::<U>(addr, true)
-            .write::<Order, U>(addr, val);
+        self.notify_write(addr, U::SIZE);
+        let (hwio, addr) = self.internal_fetch_write::<U>(addr, true);
+        hwio.write::<Order, U>(addr, val);
     }
 
     #[inline(never)]
     pub fn fetch_read<U: MemInt + 'a>(&self, addr: u32) -> MemIoR<Order, U> {
-        self.internal_fetch_read::<U>(addr, true).at(addr)
+        let (hwio, addr) = self.internal_fetch_read::<U>(addr, true);
+        hwio.at(addr)
     }
 
     #[inline(never)]
     pub fn fetch_write<U: MemInt + 'a>(&mut self, addr: u32) -> MemIoW<Order, U> {
-        self.internal_fetch_write::<U>(addr, true).at(addr)
+        self.notify_write(addr, U::SIZE);
+        let (hwio, addr) = self.internal_fetch_write::<U>(addr, true);
+        hwio.at(addr)
     }
 
     #[inline(never)]
     pub fn fetch_read_nolog<U: MemInt + 'a>(&self, addr: u32) -> MemIoR<Order, U> {
-        self.internal_fetch_read::<U>(addr, false).at(addr)
+        let (hwio, addr) = self.internal_fetch_read::<U>(addr, false);
+        hwio.at(addr)
     }
 
     #[inline(never)]
     pub fn fetch_write_nolog<U: MemInt + 'a>(&mut self, addr: u32) -> MemIoW<Order, U> {
-        self.internal_fetch_write::<U>(addr, false).at(addr)
+        let (hwio, addr) = self.internal_fetch_write::<U>(addr, false);
+        hwio.at(addr)
+    }
+
+    /// Watch the writes into the page (of WATCH_PAGE_SIZE bytes) containing
+    /// the specified address. The first write into the page is recorded and
+    /// returned by take_written_pages, after which the page is not watched
+    /// anymore. This is used by CPUs caching decoded code, to discard it when
+    /// it is overwritten, either by the CPU or by a DMA.
+    ///
+    /// Only the first word of a MemIoW returned by fetch_write is
+    /// recorded, and nothing for fetch_write_nolog (which is also used to
+    /// probe the bus without writing): code writing through them (eg: a DMA
+    /// copying a whole row) must call notify_write with the full length.
+    /// Writes through a mirror of the page (see map_mirror) are not detected.
+    pub fn watch_writes(&mut self, addr: u32) {
+        if self.watched.is_empty() {
+            self.watched = vec![0; (1 << (32 - WATCH_PAGE_SHIFT)) / 64];
+        }
+        let page = (addr >> WATCH_PAGE_SHIFT) as usize;
+        self.watched[page / 64] |= 1 << (page % 64);
     }
 
+    /// Stop watching all pages, and forget the pending written pages.
+    pub fn unwatch_all(&mut self) {
+        self.watched = Vec::new();
+        self.written.clear();
+    }
+
+    /// Return true if some watched pages were written since the last call to
+    /// take_written_pages.
     #[inline(always)]
-    fn internal_fetch_read<U: MemInt + 'a>(&'b self, addr: u32, unmapped_log: bool) -> &'b HwIoR {
-        self.reads[U::ACCESS_SIZE]
-            .lookup(addr)
-            .or_else(|| {
-                if unmapped_log {
-                    error!(self.logger, "unmapped bus read"; o!("addr" => format!("0x{:x}", addr), "size" => U::SIZE));
-                }
-                Some(&self.unmap_r)
-            })
-            .unwrap()
+    pub fn has_written_pages(&self) -> bool {
+        !self.written.is_empty()
+    }
+
+    /// Return the base addresses of the watched pages that were written since
+    /// the last call.
+    pub fn take_written_pages(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.written)
+    }
+
+    /// Notify that len bytes at the specified address are being written,
+    /// recording the watched pages they cover (see watch_writes).
+    #[inline(always)]
+    pub fn notify_write(&mut self, addr: u32, len: usize) {
+        if !self.watched.is_empty() && len > 0 {
+            self.mark_written(addr, len);
+        }
+    }
+
+    fn mark_written(&mut self, addr: u32, len: usize) {
+        let first = addr >> WATCH_PAGE_SHIFT;
+        let last = addr.saturating_add(len as u32 - 1) >> WATCH_PAGE_SHIFT;
+        for page in first..=last {
+            let (word, bit) = (page as usize / 64, 1 << (page % 64));
+            if self.watched[word] & bit != 0 {
+                self.watched[word] &= !bit;
+                self.written.push(page << WATCH_PAGE_SHIFT);
+            }
+        }
+    }
+
+    // Translate an address falling within a mirror window (see map_mirror)
+    // into the address of the first copy of the window.
+    #[inline(always)]
+    fn mirror(&self, addr: u32) -> Option<u32> {
+        self.mirrors
+            .iter()
+            .find(|m| addr >= m.0 && addr <= m.1)
+            .map(|m| m.0 | (addr & m.2))
+    }
+
+    // Lookup the object mapped at the specified address, and return it with
+    // the address to use to access it, which differs from the requested one
+    // if the access hit a mirror.
+    #[inline(always)]
+    fn internal_fetch_read<U: MemInt + 'a>(
+        &'b self,
+        addr: u32,
+        unmapped_log: bool,
+    ) -> (&'b HwIoR, u32) {
+        let tree = &self.reads[U::ACCESS_SIZE];
+        if let Some(hwio) = tree.lookup(addr) {
+            return (hwio, addr);
+        }
+        if let Some(maddr) = self.mirror(addr) {
+            if let Some(hwio) = tree.lookup(maddr) {
+                return (hwio, maddr);
+            }
+        }
+        if unmapped_log {
+            error!(self.logger, "unmapped bus read"; o!("addr" => format!("0x{:x}", addr), "size" => U::SIZE));
+        }
+        (&self.unmap_r, addr)
     }
 
     #[inline(always)]
@@ -333,23 +422,28 @@ where
         &'b mut self,
         addr: u32,
         unmapped_log: bool,
-    ) -> &'b mut HwIoW {
-        if let Some(hwio) = self.writes[U::ACCESS_SIZE].lookup_mut(addr) {
-            return hwio;
+    ) -> (&'b mut HwIoW, u32) {
+        // The borrow checker (NLL) rejects a second lookup in the tree after
+        // conditionally returning the first one, so go through a pointer:
+        // the borrow is either returned, or not used anymore.
+        let tree: *mut RadixTree<HwIoW> = &mut *self.writes[U::ACCESS_SIZE];
+        if let Some(hwio) = unsafe { (*tree).lookup_mut(addr) } {
+            return (hwio, addr);
+        }
+        if let Some(maddr) = self.mirror(addr) {
+            if let Some(hwio) = unsafe { (*tree).lookup_mut(maddr) } {
+                return (hwio, maddr);
+            }
         }
         if unmapped_log {
             error!(self.logger, "unmapped bus write"; o!("addr" => format!("0x{:x}", addr), "size" => U::SIZE));
         }
-        &mut self.unmap_w
+        (&mut self.unmap_w, addr)
     }
 
-    fn mapreg_partial<U: 'static, S>(
-        &mut self,
-        addr: u32,
-        reg: &Reg<Order, U>,
-    ) -> Result<(), &'static str>
+    fn mapreg_partial<U, S>(&mut self, addr: u32, reg: &Reg<Order, U>) -> Result<(), &'static str>
     where
-        U: MemInt,
+        U: MemInt + 'static,
         S: MemInt + Into<U>,
     {
         self.reads[S::ACCESS_SIZE].insert_range(
@@ -430,7 +524,7 @@ where
                 }
             }
             Mirror => {
-                if vsize % psize != 0 {
+                if !vsize.is_multiple_of(psize) {
                     return Err(
                         "BusFill::Mirror requires virtual size to be a multiple of physical size",
                     );
@@ -473,14 +567,88 @@ where
         self.writes[Size32].insert_range(begin, end, mem.hwio_w::<u32>(), false)?;
         self.writes[Size64].insert_range(begin, end, mem.hwio_w::<u64>(), false)?;
 
-        return Ok(());
+        Ok(())
     }
 
-    pub fn map_device<T>(&'b mut self, base: u32, device: &T, bank: usize) -> Result<(), &'s str>
+    /// Map an I/O area handled by custom functions into the bus. `begin`/`end`
+    /// is the **inclusive** virtual address range. Both functions receive the
+    /// accessed address and the size of the access (in bytes); the read
+    /// function returns the value, right-aligned.
+    ///
+    /// This is meant for areas whose behavior depends on the address of the
+    /// access (eg: open bus), as memory areas and registers are much faster.
+    pub fn map_io<R, W>(&mut self, begin: u32, end: u32, read: R, write: W) -> Result<(), &'s str>
+    where
+        R: Fn(u32, usize) -> u64 + 'static,
+        W: FnMut(u32, u64, usize) + 'static,
+    {
+        use self::AccessSize::*;
+
+        if end < begin {
+            return Err("Bus::map_io: invalid arguments: end must be bigger than begin");
+        }
+
+        let read = Rc::new(read);
+        let write = Rc::new(RefCell::new(write));
+        for &(size, nbytes) in [(Size8, 1), (Size16, 2), (Size32, 4), (Size64, 8)].iter() {
+            let r = read.clone();
+            let w = write.clone();
+            self.reads[size].insert_range(
+                begin,
+                end,
+                HwIoR::Func(Rc::new(move |addr| r(addr, nbytes))),
+                false,
+            )?;
+            self.writes[size].insert_range(
+                begin,
+                end,
+                HwIoW::Func(Rc::new(RefCell::new(move |addr, val| {
+                    (*w.borrow_mut())(addr, val, nbytes)
+                }))),
+                false,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Declare a mirror window: the inclusive address range `begin`/`end` is
+    /// made of copies of its first `size` bytes, as the hardware only decodes
+    /// the lower address bits. Accesses to addresses in the window that are
+    /// not directly mapped are redirected to the corresponding address of the
+    /// first copy, so objects can be mapped (before or after) just once.
+    ///
+    /// `size` must be a power of two, and the window must be aligned to it.
+    pub fn map_mirror(&mut self, begin: u32, end: u32, size: u32) -> Result<(), &'static str> {
+        if end < begin {
+            return Err("Bus::map_mirror: invalid arguments: end must be bigger than begin");
+        }
+        if !size.is_power_of_two()
+            || begin & (size - 1) != 0
+            || end.wrapping_add(1) & (size - 1) != 0
+        {
+            return Err("Bus::map_mirror: window must be aligned to a power-of-two size");
+        }
+        if self.mirrors.iter().any(|m| begin <= m.1 && end >= m.0) {
+            return Err("Bus::map_mirror: window overlaps an existing mirror");
+        }
+        self.mirrors.push((begin, end, size - 1));
+        Ok(())
+    }
+
+    /// Map a bank of a [`Device`](trait.Device.html) into the bus, starting
+    /// at the specified base address.
+    pub fn map_device<T>(&mut self, base: u32, device: &T, bank: usize) -> Result<(), DeviceError>
     where
         T: Device<Order = Order>,
     {
-        device.dev_map(self, bank, base)
+        device
+            .dev_map(self, bank, base)
+            .map_err(|reason| DeviceError::BusMapping {
+                device: T::tag(),
+                bank,
+                base,
+                reason,
+            })
     }
 
     // Add a memory map for a "combiner": that is, an internal function that combines two
@@ -598,18 +766,14 @@ mod tests {
         let mut bus = Bus::<LittleEndian>::new(logger());
 
         // Mapping a vsize which is not a multiple of psize returns an error
-        assert_eq!(
-            bus.map_mem(0x0400_0000, 0x0600_0000, &ram1, BusFill::Mirror)
-                .is_ok(),
-            false
-        );
+        assert!(bus
+            .map_mem(0x0400_0000, 0x0600_0000, &ram1, BusFill::Mirror)
+            .is_err());
 
         // Correct mirror mapping
-        assert_eq!(
-            bus.map_mem(0x0400_0000, 0x05FF_FFFF, &ram1, BusFill::Mirror)
-                .is_ok(),
-            true
-        );
+        assert!(bus
+            .map_mem(0x0400_0000, 0x05FF_FFFF, &ram1, BusFill::Mirror)
+            .is_ok());
         bus.write::<u32>(0x04000123, 0xaabbccdd);
         assert_eq!(bus.read::<u32>(0x04000123), 0xaabbccdd);
         assert_eq!(bus.read::<u32>(0x05aaa123), 0xaabbccdd);
@@ -621,18 +785,14 @@ mod tests {
         let ram1 = Mem::new("mem", 1024, MemFlags::default());
         let mut bus = Bus::<LittleEndian>::new(logger());
 
-        assert_eq!(
-            bus.map_mem(0x0400_0000, 0x0600_0000, &ram1, BusFill::None)
-                .is_ok(),
-            false
-        );
+        assert!(bus
+            .map_mem(0x0400_0000, 0x0600_0000, &ram1, BusFill::None)
+            .is_err());
 
         // Smaller size is OK
-        assert_eq!(
-            bus.map_mem(0x0400_0000, 0x0400_00FF, &ram1, BusFill::None)
-                .is_ok(),
-            true
-        );
+        assert!(bus
+            .map_mem(0x0400_0000, 0x0400_00FF, &ram1, BusFill::None)
+            .is_ok());
 
         bus.write::<u32>(0x0400_0020, 0xaabbccdd);
         assert_eq!(bus.read::<u32>(0x0400_0020), 0xaabb_ccdd);
@@ -640,11 +800,9 @@ mod tests {
         assert_eq!(bus.read::<u32>(0x0400_0100), 0xffff_ffff); // open bus
 
         // Correct size
-        assert_eq!(
-            bus.map_mem(0x0500_0000, 0x0500_03FF, &ram1, BusFill::None)
-                .is_ok(),
-            true
-        );
+        assert!(bus
+            .map_mem(0x0500_0000, 0x0500_03FF, &ram1, BusFill::None)
+            .is_ok());
 
         bus.write::<u32>(0x0500_0300, 0xaabbccdd);
         assert_eq!(bus.read::<u32>(0x05000300), 0xaabbccdd);
@@ -656,11 +814,9 @@ mod tests {
         let mut bus = Bus::<LittleEndian>::new(logger());
 
         // Shorter mapping
-        assert_eq!(
-            bus.map_mem(0x0400_0000, 0x0400_000F, &ram1, BusFill::None)
-                .is_ok(),
-            true
-        );
+        assert!(bus
+            .map_mem(0x0400_0000, 0x0400_000F, &ram1, BusFill::None)
+            .is_ok());
 
         bus.write::<u32>(0x0400_0008, 0xaabbccdd);
         assert_eq!(bus.read::<u32>(0x0400_0008), 0xaabb_ccdd);
@@ -669,11 +825,9 @@ mod tests {
         assert_eq!(bus.read::<u32>(0x0400_0010), 0xffff_ffff);
 
         // Longer mapping
-        assert_eq!(
-            bus.map_mem(0x0500_0000, 0x05ff_ffff, &ram1, BusFill::Fixed(0x6C))
-                .is_ok(),
-            true
-        );
+        assert!(bus
+            .map_mem(0x0500_0000, 0x05ff_ffff, &ram1, BusFill::Fixed(0x6C))
+            .is_ok());
 
         bus.write::<u32>(0x0500_0100, 0xaabbccdd);
         assert_eq!(bus.read::<u32>(0x0500_0100), 0xaabb_ccdd);
@@ -682,6 +836,27 @@ mod tests {
         assert_eq!(bus.read::<u32>(0x0500_1000), 0x6c6c_6c6c);
     }
 
+    #[test]
+    fn basic_io() {
+        let mut bus = Bus::<LittleEndian>::new(logger());
+        let last = Rc::new(RefCell::new((0, 0, 0)));
+        let last2 = last.clone();
+
+        bus.map_io(
+            0x0400_0000,
+            0x0400_FFFF,
+            |addr, size| (addr as u64) << 8 | size as u64,
+            move |addr, val, size| *last2.borrow_mut() = (addr, val, size),
+        )
+        .unwrap();
+
+        assert_eq!(bus.read::<u32>(0x0400_1234), 0x0012_3404);
+        assert_eq!(bus.read::<u8>(0x0400_1235), 0x01);
+        bus.write::<u16>(0x0400_0010, 0xaabb);
+        assert_eq!(*last.borrow(), (0x0400_0010, 0xaabb, 2));
+        assert_eq!(bus.read::<u32>(0x0401_0000), 0xffff_ffff);
+    }
+
     #[test]
     fn basic_reg() {
         let mut reg1 = Reg32::new_basic("reg1");
@@ -693,18 +868,18 @@ mod tests {
             0xffff0000,
             RegFlags::default(),
             None,
-            Some(Rc::new(box |x| x | 0xf0)),
+            Some(Rc::new(Box::new(|x| x | 0xf0))),
         );
 
         let mut bus = Bus::<LittleEndian>::new(logger());
-        assert_eq!(bus.map_reg(0x04000120, &reg1).is_ok(), true);
-        assert_eq!(bus.map_reg(0x04000124, &reg2).is_ok(), true);
+        assert!(bus.map_reg(0x04000120, &reg1).is_ok());
+        assert!(bus.map_reg(0x04000124, &reg2).is_ok());
 
-        assert_eq!(bus.fetch_read::<u32>(0x04000120).is_mem(), true);
-        assert_eq!(bus.fetch_read::<u16>(0x04000122).is_mem(), true);
-        assert_eq!(bus.fetch_read::<u8>(0x04000121).is_mem(), true);
-        assert_eq!(bus.fetch_read::<u32>(0x04000124).is_mem(), false); // callback
-        assert_eq!(bus.fetch_read::<u8>(0x04000125).is_mem(), false); // callback
+        assert!(bus.fetch_read::<u32>(0x04000120).is_mem());
+        assert!(bus.fetch_read::<u16>(0x04000122).is_mem());
+        assert!(bus.fetch_read::<u8>(0x04000121).is_mem());
+        assert!(!bus.fetch_read::<u32>(0x04000124).is_mem()); // callback
+        assert!(!bus.fetch_read::<u8>(0x04000125).is_mem()); // callback
 
         assert_eq!(bus.read::<u32>(0x04000120), 0x12345678);
         assert_eq!(bus.read::<u16>(0x04000122), 0x1234);
@@ -722,6 +897,63 @@ mod tests {
         assert_eq!(bus.read::<u32>(0x04000124), 0x000056f8);
     }
 
+    #[test]
+    fn mirror_window() {
+        let ram1 = Mem::new("mem", 0x100, MemFlags::default());
+        let mut reg1 = Reg32::new_basic("reg1");
+        reg1.set(0x12345678);
+        let reg2 = Reg32::new_basic("reg2");
+
+        let mut bus = Bus::<LittleEndian>::new(logger());
+
+        // Invalid windows
+        assert!(bus.map_mirror(0x0400_0000, 0x0400_FFFF, 0x300).is_err());
+        assert!(bus.map_mirror(0x0400_0080, 0x0400_FFFF, 0x100).is_err());
+        assert!(bus.map_mirror(0x0400_0000, 0x0400_FF7F, 0x100).is_err());
+
+        assert!(bus.map_mirror(0x0400_0000, 0x0400_FFFF, 0x200).is_ok());
+        assert!(bus.map_mirror(0x0400_8000, 0x0401_FFFF, 0x200).is_err());
+        assert!(bus.map_mirror(0x0401_0000, 0x0401_FFFF, 0x10).is_ok());
+
+        // Memory in the first half of the window, registers in the second
+        // half, and a hole.
+        bus.map_mem(0x0400_0000, 0x0400_00FF, &ram1, BusFill::None)
+            .unwrap();
+        bus.map_reg(0x0400_0100, &reg1).unwrap();
+        bus.map_reg(0x0401_0004, &reg2).unwrap();
+
+        bus.write::<u32>(0x0400_0010, 0xaabbccdd);
+        assert_eq!(bus.read::<u32>(0x0400_4210), 0xaabb_ccdd);
+        assert_eq!(bus.read::<u8>(0x0400_FE11), 0xcc);
+        bus.write::<u16>(0x0400_3A12, 0x1122);
+        assert_eq!(bus.read::<u32>(0x0400_0010), 0x1122_ccdd);
+        assert!(bus.fetch_read::<u32>(0x0400_6010).is_mem());
+
+        assert_eq!(bus.read::<u32>(0x0400_0300), 0x1234_5678);
+        assert_eq!(bus.read::<u16>(0x0400_FF02), 0x1234);
+        bus.write::<u32>(0x0400_7700, 0xcafe_babe);
+        assert_eq!(reg1.get(), 0xcafe_babe);
+
+        // Holes are mirrored as well
+        assert_eq!(bus.read::<u32>(0x0400_0180), 0xffff_ffff);
+        assert_eq!(bus.read::<u32>(0x0400_0380), 0xffff_ffff);
+
+        bus.write::<u32>(0x0401_0FF4, 0x55aa_55aa);
+        assert_eq!(reg2.get(), 0x55aa_55aa);
+        assert_eq!(bus.read::<u32>(0x0401_0014), 0x55aa_55aa);
+
+        // Outside of any window
+        assert_eq!(bus.read::<u32>(0x0402_0004), 0xffff_ffff);
+
+        // Direct mappings take precedence over mirrors
+        let ram2 = Mem::new("mem2", 0x100, MemFlags::default());
+        bus.map_mem(0x0400_8000, 0x0400_80FF, &ram2, BusFill::None)
+            .unwrap();
+        bus.write::<u32>(0x0400_8010, 0x0bad_f00d);
+        assert_eq!(bus.read::<u32>(0x0400_8010), 0x0bad_f00d);
+        assert_eq!(bus.read::<u32>(0x0400_0010), 0x1122_ccdd);
+    }
+
     #[test]
     fn combiner_le() {
         let reg1 = Reg32::new_basic("reg1");
@@ -730,10 +962,10 @@ mod tests {
         let reg4 = Reg8::new_basic("reg4");
 
         let mut bus = Bus::<LittleEndian>::new(logger());
-        assert_eq!(bus.map_reg(0xFF000000, &reg1).is_ok(), true);
-        assert_eq!(bus.map_reg(0xFF000004, &reg2).is_ok(), true);
-        assert_eq!(bus.map_reg(0xFF000006, &reg3).is_ok(), true);
-        assert_eq!(bus.map_reg(0xFF000007, &reg4).is_ok(), true);
+        assert!(bus.map_reg(0xFF000000, &reg1).is_ok());
+        assert!(bus.map_reg(0xFF000004, &reg2).is_ok());
+        assert!(bus.map_reg(0xFF000006, &reg3).is_ok());
+        assert!(bus.map_reg(0xFF000007, &reg4).is_ok());
 
         bus.write::<u64>(0xFF000000, 0xaabbccdd11223344);
         assert_eq!(reg1.get(), 0x11223344);
@@ -771,10 +1003,10 @@ mod tests {
 
         let mut bus = Bus::<BigEndian>::new(logger());
 
-        assert_eq!(bus.map_reg(0xFF000000, &reg1).is_ok(), true);
-        assert_eq!(bus.map_reg(0xFF000004, &reg2).is_ok(), true);
-        assert_eq!(bus.map_reg(0xFF000006, &reg3).is_ok(), true);
-        assert_eq!(bus.map_reg(0xFF000007, &reg4).is_ok(), true);
+        assert!(bus.map_reg(0xFF000000, &reg1).is_ok());
+        assert!(bus.map_reg(0xFF000004, &reg2).is_ok());
+        assert!(bus.map_reg(0xFF000006, &reg3).is_ok());
+        assert!(bus.map_reg(0xFF000007, &reg4).is_ok());
 
         bus.write::<u64>(0xFF000000, 0xaabbccdd11223344);
         assert_eq!(reg1.get(), 0xaabbccdd);
@@ -801,4 +1033,43 @@ mod tests {
         assert_eq!(bus.read::<u8>(0xFF000006), 0x11);
         assert_eq!(bus.read::<u8>(0xFF000007), 0x22);
     }
+
+    #[test]
+    fn watch_writes() {
+        let ram1 = Mem::new("mem", 0x4000, MemFlags::default());
+        let mut bus = Bus::<LittleEndian>::new(logger());
+        assert!(bus
+            .map_mem(0x0400_0000, 0x0400_3FFF, &ram1, BusFill::None)
+            .is_ok());
+
+        // Writes into pages that are not watched are not recorded.
+        bus.write::<u32>(0x0400_0000, 1);
+        assert!(!bus.has_written_pages());
+
+        bus.watch_writes(0x0400_1234);
+        bus.watch_writes(0x0400_2000);
+        bus.write::<u32>(0x0400_0FFC, 1);
+        assert!(!bus.has_written_pages());
+        bus.write::<u8>(0x0400_1FFF, 1);
+        bus.write::<u8>(0x0400_1000, 1);
+        assert_eq!(bus.take_written_pages(), vec![0x0400_1000]);
+        assert!(!bus.has_written_pages());
+
+        // A page is not watched anymore after being written.
+        bus.write::<u32>(0x0400_1000, 1);
+        assert!(!bus.has_written_pages());
+
+        // Writes through fetch_write record the first word only; longer
+        // writes are reported with notify_write.
+        bus.watch_writes(0x0400_1000);
+        bus.fetch_write::<u32>(0x0400_0FFC).write(1);
+        assert!(!bus.has_written_pages());
+        bus.notify_write(0x0400_0FFC, 0x1008);
+        assert_eq!(bus.take_written_pages(), vec![0x0400_1000, 0x0400_2000]);
+
+        bus.watch_writes(0x0400_3000);
+        bus.unwatch_all();
+        bus.write::<u32>(0x0400_3000, 1);
+        assert!(!bus.has_written_pages());
+    }
 }
@@ -1,5 +1,9 @@
 use super::bus::Bus;
-use crate::memint::ByteOrderCombiner;
+use super::mem::Mem;
+use super::regs::Reg;
+use crate::errors::DeviceError;
+use crate::memint::{ByteOrderCombiner, MemInt};
+use crate::state::CurrentState;
 use hashbrown::HashMap;
 use std::any::Any;
 use std::cell::RefCell;
@@ -20,34 +24,176 @@ pub trait Device: Sized {
 
     fn tag() -> &'static str;
 
+    /// Reset the device. A hard reset is a power cycle, while a soft reset is
+    /// what happens when the console's reset button is pressed, so devices
+    /// may preserve part of their state (eg: memory contents).
+    ///
+    /// The derive macros implement it as a no-op, unless the device is marked
+    /// with `#[device(reset)]`, in which case it calls `self.cb_reset(hard)`.
+    fn reset(&mut self, hard: bool);
+
+    /// Describe the device: its register banks and memory regions, and
+    /// whether it is a CPU (so that debuggers can discover it).
+    ///
+    /// The derive macros implement it from the `#[reg]` and `#[mem]`
+    /// attributes. Devices marked with `#[device(cpu)]` must dereference to
+    /// a [`Subsystem`](../sync/trait.Subsystem.html), whose name is used as
+    /// the name of the CPU.
+    fn info(&self) -> DeviceInfo;
+
     fn get() -> &'static Self {
         CurrentDeviceMap().get::<Self>().unwrap()
     }
     fn get_mut() -> &'static mut Self {
         CurrentDeviceMap().get_mut::<Self>().unwrap()
     }
+
+    /// Like `get()`, but returns an error instead of panicking if the
+    /// device was not registered.
+    fn try_get() -> Result<&'static Self, DeviceError> {
+        CurrentDeviceMap()
+            .get::<Self>()
+            .ok_or(DeviceError::NotRegistered {
+                device: Self::tag(),
+            })
+    }
+}
+
+/// The part of the current [`State`](../state/struct.State.html) owned by a
+/// device: the fields whose name starts with the device tag (eg: "Mi::"),
+/// which include all its registers and memory areas.
+///
+/// It is implemented for all devices, so a new device is automatically part of
+/// savestates, as long as it keeps its state in fields named after its tag.
+pub trait Snapshot: Device {
+    /// Return the prefix of the names of the fields owned by the device.
+    fn state_prefix() -> String {
+        format!("{}::", Self::tag())
+    }
+
+    /// Return the names of the fields owned by the device.
+    fn state_fields(&self) -> Vec<String> {
+        CurrentState().field_names(&Self::state_prefix())
+    }
+
+    /// Format the fields owned by the device as text (see
+    /// [`State::dump`](../state/struct.State.html#method.dump)).
+    fn state_dump(&self) -> String {
+        CurrentState().dump(&Self::state_prefix())
+    }
+}
+
+impl<D: Device> Snapshot for D {}
+
+/// A register of a device, as described by
+/// [`Device::info`](trait.Device.html#tymethod.info).
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegInfo {
+    pub name: &'static str,
+    pub bank: usize,
+    pub offset: u32, // Offset within the bank
+    pub size: usize, // Size in bytes
+    pub value: u64,  // Current value (without invoking read callbacks)
+}
+
+impl RegInfo {
+    pub fn new<O, U>(name: &'static str, bank: usize, offset: u32, reg: &Reg<O, U>) -> RegInfo
+    where
+        O: ByteOrderCombiner + 'static,
+        U: MemInt + 'static,
+    {
+        RegInfo {
+            name,
+            bank,
+            offset,
+            size: U::SIZE,
+            value: reg.get().into(),
+        }
+    }
+}
+
+/// A memory region of a device, as described by
+/// [`Device::info`](trait.Device.html#tymethod.info).
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemInfo {
+    pub name: &'static str,
+    pub bank: usize,
+    pub offset: u32, // Offset within the bank
+    pub size: usize, // Physical size in bytes
+    pub vsize: u32,  // Size of the mapped window (the memory is mirrored in it)
+}
+
+impl MemInfo {
+    pub fn new(name: &'static str, bank: usize, offset: u32, vsize: u32, mem: &Mem) -> MemInfo {
+        MemInfo {
+            name,
+            bank,
+            offset,
+            size: mem.len(),
+            vsize,
+        }
+    }
+}
+
+/// Description of a device, as returned by
+/// [`Device::info`](trait.Device.html#tymethod.info).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+    pub name: &'static str,  // Device tag
+    pub cpu: Option<String>, // Name of the CPU, if the device is a CPU
+    pub regs: Vec<RegInfo>,
+    pub mems: Vec<MemInfo>,
 }
 
 type PinnedDevice = Pin<Box<dyn Any + Unpin>>;
 
+type ResetFn = fn(&mut DeviceMap, bool);
+type InfoFn = fn(&DeviceMap) -> DeviceInfo;
+
 #[derive(Default)]
 pub struct DeviceMap {
     devices: HashMap<&'static str, PinnedDevice>,
+    resets: Vec<(&'static str, ResetFn)>, // In registration order
+    infos: Vec<(&'static str, InfoFn)>,   // In registration order
 }
 
 impl DeviceMap {
     pub fn register<D: 'static + Device + Unpin>(&mut self, o: Pin<Box<D>>) {
         self.devices.insert(D::tag(), o);
+        self.resets.retain(|(tag, _)| *tag != D::tag());
+        let reset: ResetFn = |map, hard| map.get_mut::<D>().unwrap().reset(hard);
+        self.resets.push((D::tag(), reset));
+        self.infos.retain(|(tag, _)| *tag != D::tag());
+        let info: InfoFn = |map| map.get::<D>().unwrap().info();
+        self.infos.push((D::tag(), info));
+    }
+
+    /// Describe all registered devices, in the same order in which they were
+    /// registered. See [`Device::info`](trait.Device.html#tymethod.info).
+    pub fn info(&self) -> Vec<DeviceInfo> {
+        self.infos.iter().map(|(_, info)| info(self)).collect()
+    }
+
+    /// Reset all registered devices, in the same order in which they were
+    /// registered. See [`Device::reset`](trait.Device.html#tymethod.reset).
+    pub fn reset(&mut self, hard: bool) {
+        // Devices can access other devices while resetting (through
+        // CurrentDeviceMap), so iterate over a copy of the list.
+        for (_, reset) in self.resets.clone() {
+            reset(self, hard);
+        }
     }
 
     pub fn get_by_tag<D: 'static + Device>(&self, tag: &'static str) -> Option<&D> {
-        self.devices
-            .get(tag)
-            .map(|v| (Pin::get_ref(v.as_ref()) as &Any).downcast_ref().unwrap())
+        self.devices.get(tag).map(|v| {
+            (Pin::get_ref(v.as_ref()) as &dyn Any)
+                .downcast_ref()
+                .unwrap()
+        })
     }
     pub fn get_mut_by_tag<D: 'static + Device>(&mut self, tag: &'static str) -> Option<&mut D> {
         self.devices.get_mut(tag).map(|v| {
-            (Pin::get_mut(v.as_mut()) as &mut Any)
+            (Pin::get_mut(v.as_mut()) as &mut dyn Any)
                 .downcast_mut()
                 .unwrap()
         })
@@ -23,13 +23,13 @@ impl MemFlags {
         if !write {
             flags.remove(MemFlags::WRITEACCESS);
         }
-        return flags;
+        flags
     }
 }
 
 impl Default for MemFlags {
     fn default() -> MemFlags {
-        return MemFlags::READACCESS | MemFlags::WRITEACCESS;
+        MemFlags::READACCESS | MemFlags::WRITEACCESS
     }
 }
 
@@ -54,7 +54,7 @@ impl Mem {
         Self {
             name,
             buf: ArrayField::internal_new(name, 0, psize, serialized),
-            flags: flags,
+            flags,
             mask: psize.next_power_of_two() as u32 - 1,
         }
     }
@@ -63,8 +63,10 @@ impl Mem {
         self.buf.len()
     }
 
+    // S is unused, but keeps the same interface of Reg::hwio_r.
+    #[allow(clippy::extra_unused_type_parameters)]
     pub(crate) fn hwio_r<S: MemInt>(&self) -> HwIoR {
-        if self.name == "" {
+        if self.name.is_empty() {
             panic!("uninitialized Mem in hwio_r");
         }
         if !self.flags.contains(MemFlags::READACCESS) {
@@ -74,8 +76,9 @@ impl Mem {
         HwIoR::Mem(unsafe { self.buf.clone() }, self.mask)
     }
 
+    #[allow(clippy::extra_unused_type_parameters)]
     pub(crate) fn hwio_w<S: MemInt>(&self) -> HwIoW {
-        if self.name == "" {
+        if self.name.is_empty() {
             panic!("uninitialized Mem in hwio_w");
         }
         if !self.flags.contains(MemFlags::WRITEACCESS) {
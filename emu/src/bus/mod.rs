@@ -1,3 +1,11 @@
+//! Memory buses, memory areas and hardware registers.
+//!
+//! A [`Bus`](struct.Bus.html) maps virtual addresses to
+//! [`Mem`](struct.Mem.html) areas and [`Reg`](struct.Reg.html) registers.
+//! Devices describe their memory map with `#[derive(DeviceLE)]` or
+//! `#[derive(DeviceBE)]` (from `emu_derive`); the submodules `le` and `be`
+//! define type aliases for the two endiannesses.
+
 extern crate byteorder;
 
 mod bus;
@@ -6,14 +14,16 @@ mod mem;
 mod radix;
 mod regs;
 
-pub use self::bus::{Bus, BusFill, MemIoR, MemIoRIterator, MemIoW};
-pub use self::device::{CurrentDeviceMap, Device, DeviceMap};
+pub use self::bus::{Bus, BusFill, MemIoR, MemIoRIterator, MemIoW, WATCH_PAGE_SIZE};
+pub use self::device::{
+    CurrentDeviceMap, Device, DeviceInfo, DeviceMap, MemInfo, RegInfo, Snapshot,
+};
 pub use self::mem::{Mem, MemFlags};
 pub use self::regs::{Reg, RegDeref, RegFlags, RegRef};
 
 pub mod le {
     use super::byteorder::LittleEndian;
-    pub use super::{BusFill, Device, Mem, MemFlags, RegDeref, RegFlags};
+    pub use super::{BusFill, Device, Mem, MemFlags, RegDeref, RegFlags, Snapshot};
     pub type Bus = super::Bus<LittleEndian>;
     pub type Reg8 = super::Reg<LittleEndian, u8>;
     pub type Reg16 = super::Reg<LittleEndian, u16>;
@@ -26,7 +36,7 @@ pub mod le {
 
 pub mod be {
     use super::byteorder::BigEndian;
-    pub use super::{BusFill, Device, Mem, MemFlags, RegDeref, RegFlags};
+    pub use super::{BusFill, Device, Mem, MemFlags, RegDeref, RegFlags, Snapshot};
     pub type Bus = super::Bus<BigEndian>;
     pub type Reg8 = super::Reg<BigEndian, u8>;
     pub type Reg16 = super::Reg<BigEndian, u16>;
@@ -2,7 +2,7 @@ use array_macro::array;
 
 const RADIX_BITS: usize = 11;
 const RADIX_BREADTH: usize = 1 << RADIX_BITS;
-const RADIX_DEPTH: usize = (32 + RADIX_BITS - 1) / RADIX_BITS;
+const RADIX_DEPTH: usize = 32_usize.div_ceil(RADIX_BITS);
 const RADIX_FIRST_SHIFT: usize = 32 - RADIX_BITS;
 const RADIX_MASK: u32 = (1 << RADIX_BITS as u32) - 1;
 
@@ -37,19 +37,19 @@ impl<T: Clone> Node<T> {
         if self.leaf().is_some() {
             *self = Node::Internal(RadixTree::new_with_node(self.clone().unwrap()));
         }
-        return self;
+        self
     }
 }
 
-pub struct RadixTree<T: Clone> {
+pub(crate) struct RadixTree<T: Clone> {
     nodes: [Node<T>; RADIX_BREADTH],
 }
 
 impl<T: Clone> RadixTree<T> {
-    pub fn new() -> Box<RadixTree<T>> {
-        return box RadixTree {
+    pub(crate) fn new() -> Box<RadixTree<T>> {
+        Box::new(RadixTree {
             nodes: array![Node::Leaf(None); RADIX_BREADTH],
-        };
+        })
     }
 
     fn new_with_node(n: Node<T>) -> Box<RadixTree<T>> {
@@ -57,7 +57,7 @@ impl<T: Clone> RadixTree<T> {
         for tn in t.nodes.iter_mut() {
             *tn = n.clone().unwrap();
         }
-        return t;
+        t
     }
 
     fn iter_range<'a>(
@@ -65,7 +65,7 @@ impl<T: Clone> RadixTree<T> {
         beg: u32,
         end: u32,
         shift: usize,
-    ) -> Box<'a + Iterator<Item = &mut Node<T>>> {
+    ) -> Box<dyn 'a + Iterator<Item = &'a mut Node<T>>> {
         let idx1 = (beg >> shift) as usize;
         let idx2 = (end >> shift) as usize;
         let mask = (1 << shift) - 1;
@@ -74,12 +74,12 @@ impl<T: Clone> RadixTree<T> {
 
         // We're on the bottom level, we can't recurse anymore.
         if shift == 0 {
-            return box self.nodes[idx1..=idx2].iter_mut();
+            return Box::new(self.nodes[idx1..=idx2].iter_mut());
         }
 
         // See if we're spanning full nodes, in which case we don't need to recurse
         if beg == 0 && end == mask {
-            return box self.nodes[idx1..=idx2].iter_mut();
+            return Box::new(self.nodes[idx1..=idx2].iter_mut());
         }
 
         let nshift = shift.saturating_sub(RADIX_BITS);
@@ -98,34 +98,36 @@ impl<T: Clone> RadixTree<T> {
         let (first, mid) = self.nodes[idx1..=idx2].split_at_mut(1);
         let (mid, last) = mid.split_at_mut(idx2 - idx1 - 1);
 
-        let mut iter: Box<Iterator<Item = &mut Node<T>>> = box mid.iter_mut();
+        let mut iter: Box<dyn Iterator<Item = &mut Node<T>>> = Box::new(mid.iter_mut());
 
         if beg == 0 {
-            iter = box iter.chain(box first.iter_mut());
+            iter = Box::new(iter.chain(first.iter_mut()));
         } else {
-            iter = box iter.chain(first[0].split().internal().unwrap().iter_range(
+            iter = Box::new(iter.chain(first[0].split().internal().unwrap().iter_range(
                 beg as u32,
                 mask as u32,
                 nshift,
-            ));
+            )));
         }
 
         if end == mask {
-            iter = box iter.chain(box last.iter_mut());
+            iter = Box::new(iter.chain(last.iter_mut()));
         } else {
-            iter = box iter.chain(
-                last[0]
-                    .split()
-                    .internal()
-                    .unwrap()
-                    .iter_range(0 as u32, end as u32, nshift),
+            iter = Box::new(
+                iter.chain(
+                    last[0]
+                        .split()
+                        .internal()
+                        .unwrap()
+                        .iter_range(0_u32, end as u32, nshift),
+                ),
             );
         }
 
-        return iter;
+        iter
     }
 
-    pub fn insert_range<'s, 'r>(
+    pub(crate) fn insert_range<'s, 'r>(
         &'s mut self,
         begin: u32,
         end: u32,
@@ -149,7 +151,7 @@ impl<T: Clone> RadixTree<T> {
         Ok(())
     }
 
-    pub fn lookup(&self, mut key: u32) -> Option<&T> {
+    pub(crate) fn lookup(&self, mut key: u32) -> Option<&T> {
         let mut nodes = &self.nodes;
         let mut shift = RADIX_FIRST_SHIFT;
         for i in 0..RADIX_DEPTH {
@@ -168,7 +170,7 @@ impl<T: Clone> RadixTree<T> {
         None
     }
 
-    pub fn lookup_mut(&mut self, mut key: u32) -> Option<&mut T> {
+    pub(crate) fn lookup_mut(&mut self, mut key: u32) -> Option<&mut T> {
         let mut nodes = &mut self.nodes;
         let mut shift = RADIX_FIRST_SHIFT;
         for i in 0..RADIX_DEPTH {
@@ -194,24 +196,15 @@ mod tests {
 
     fn lookup(t: &RadixTree<u8>, key: u32) -> u8 {
         println!("lookup: {:x}", key);
-        *t.lookup(key).or_else(|| Some(&0)).unwrap()
+        *t.lookup(key).unwrap_or(&0)
     }
 
     #[test]
     fn big_spans() {
         let mut t = RadixTree::<u8>::new();
-        assert_eq!(
-            t.insert_range(0x04000000, 0x04ffffff, 1, false).is_err(),
-            false
-        );
-        assert_eq!(
-            t.insert_range(0x05000000, 0x05ffffff, 2, false).is_err(),
-            false
-        );
-        assert_eq!(
-            t.insert_range(0x06000000, 0x09ffffff, 3, false).is_err(),
-            false
-        );
+        assert!(t.insert_range(0x04000000, 0x04ffffff, 1, false).is_ok());
+        assert!(t.insert_range(0x05000000, 0x05ffffff, 2, false).is_ok());
+        assert!(t.insert_range(0x06000000, 0x09ffffff, 3, false).is_ok());
         assert_eq!(lookup(&t, 0x04000000), 1);
         assert_eq!(lookup(&t, 0x04111111), 1);
         assert_eq!(lookup(&t, 0x05111111), 2);
@@ -223,10 +216,7 @@ mod tests {
     #[test]
     fn large_uneven() {
         let mut t = RadixTree::<u8>::new();
-        assert_eq!(
-            t.insert_range(0x040000F0, 0x07ffffef, 1, false).is_err(),
-            false
-        );
+        assert!(t.insert_range(0x040000F0, 0x07ffffef, 1, false).is_ok());
         assert_eq!(lookup(&t, 0x04000000), 0);
         assert_eq!(lookup(&t, 0x040000ef), 0);
         assert_eq!(lookup(&t, 0x040000f0), 1);
@@ -243,23 +233,14 @@ mod tests {
     #[test]
     fn insert_deep() {
         let mut t = RadixTree::<u8>::new();
-        assert_eq!(
-            t.insert_range(0x04000501, 0x04000505, 1, false).is_err(),
-            false
-        );
+        assert!(t.insert_range(0x04000501, 0x04000505, 1, false).is_ok());
         assert_eq!(lookup(&t, 0x04000500), 0);
         assert_eq!(lookup(&t, 0x04000501), 1);
         assert_eq!(lookup(&t, 0x04000502), 1);
         assert_eq!(lookup(&t, 0x04000504), 1);
         assert_eq!(lookup(&t, 0x04000505), 1);
         assert_eq!(lookup(&t, 0x04000506), 0);
-        assert_eq!(
-            t.insert_range(0x04000501, 0x04000505, 2, false).is_err(),
-            true
-        );
-        assert_eq!(
-            t.insert_range(0x04000500, 0x04000502, 2, false).is_err(),
-            true
-        );
+        assert!(t.insert_range(0x04000501, 0x04000505, 2, false).is_err());
+        assert!(t.insert_range(0x04000500, 0x04000502, 2, false).is_err());
     }
 }
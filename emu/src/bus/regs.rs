@@ -14,6 +14,13 @@ bitflags! {
    pub struct RegFlags: u8 {
         const READACCESS = 0b00000001;
         const WRITEACCESS = 0b00000010;
+
+        // Policy for writes narrower than the register (by default, only
+        // the bytes being written are modified). Narrower reads always
+        // return the addressed bytes, and wider accesses are split into
+        // accesses to each register, in address order.
+        const SUBWORD_SHIFT = 0b00000100; // write the whole register, with the value shifted into its lane and the other lanes cleared
+        const SUBWORD_IGNORE = 0b00001000; // drop the write
     }
 }
 
@@ -26,18 +33,18 @@ impl RegFlags {
         if !write {
             rf.remove(RegFlags::WRITEACCESS);
         }
-        return rf;
+        rf
     }
 }
 
 impl Default for RegFlags {
     fn default() -> RegFlags {
-        return RegFlags::READACCESS | RegFlags::WRITEACCESS;
+        RegFlags::READACCESS | RegFlags::WRITEACCESS
     }
 }
 
-type Wcb<U> = Option<Rc<Box<Fn(U, U)>>>;
-type Rcb<U> = Option<Rc<Box<Fn(U) -> U>>>;
+type Wcb<U> = Option<Rc<Box<dyn Fn(U, U)>>>;
+type Rcb<U> = Option<Rc<Box<dyn Fn(U) -> U>>>;
 
 #[derive(Default)]
 pub struct Reg<O, U>
@@ -68,7 +75,7 @@ where
         rcb: Rcb<U>,
     ) -> Self {
         Self {
-            name: name,
+            name,
             raw: EndianField::new(name, init),
             romask: !rwmask,
             flags,
@@ -131,7 +138,7 @@ where
 
         match self.rcb {
             Some(ref rcb) => {
-                let rcb = Rc::downgrade(&rcb);
+                let rcb = Rc::downgrade(rcb);
                 let raw = unsafe { self.raw.clone() };
                 HwIoR::Func(Rc::new(move |addr: u32| {
                     let rcb = rcb.upgrade().unwrap();
@@ -154,17 +161,41 @@ where
             return unmapped_area_w();
         }
 
-        if self.romask == U::zero() && self.wcb.is_none() {
+        let subword = S::SIZE < U::SIZE;
+        if subword && self.flags.contains(RegFlags::SUBWORD_IGNORE) {
+            return unmapped_area_w();
+        }
+        let shift_lane = subword && self.flags.contains(RegFlags::SUBWORD_SHIFT);
+
+        if self.romask == U::zero() && self.wcb.is_none() && !shift_lane {
             HwIoW::Mem(self.raw.as_array_field(), (U::SIZE - 1) as u32)
         } else {
             let mut raw = unsafe { self.raw.clone() };
             let wcb = self.wcb.clone().map(|f| Rc::downgrade(&f));
             let romask = self.romask;
+            let name = self.name;
+            // Registers with a write callback (often with all bits read-only,
+            // like the status registers acked by writing to them) and shifted
+            // lanes give their own meaning to the written value, so only plain
+            // masked registers are checked for writes to read-only bits.
+            let check_ro = wcb.is_none() && !shift_lane;
             HwIoW::Func(Rc::new(RefCell::new(move |addr: u32, val64: u64| {
                 let off = (addr as usize) & (U::SIZE - 1);
                 let (mut mask, shift) = O::subint_mask::<U, S>(off);
+                if shift_lane {
+                    mask = U::max_value();
+                }
                 let mut val = U::truncate_from(val64) << shift;
                 let old = raw.get();
+                crate::paranoid_check!(
+                    "bus",
+                    !check_ro || (val ^ old) & mask & romask == U::zero(),
+                    "write to read-only bits of {}: old={:x} new={:x} romask={:x}",
+                    name,
+                    Into::<u64>::into(old),
+                    Into::<u64>::into(val),
+                    Into::<u64>::into(romask)
+                );
                 mask = !mask | romask;
                 val = (val & !mask) | (old & mask);
                 raw.set(val);
@@ -190,7 +221,7 @@ impl<O: ByteOrderCombiner + 'static, U: MemInt + 'static> fmt::Debug for Reg<O,
         let name = if self.name.is_empty() {
             "Reg"
         } else {
-            &self.name
+            self.name
         };
         let val: u64 = self.get().into();
         let rwmask: u64 = (!self.romask).into();
@@ -348,10 +379,43 @@ mod tests {
         assert_eq!(r.get(), 0xdd89bb78);
     }
 
+    #[test]
+    fn reg32le_paranoid() {
+        let bus = FakeBus::default();
+        let mut r = le::Reg32::new_basic("reg32").with_rwmask(0x0000ffff);
+        r.set(0xddccbbaa);
+        crate::paranoid::set_enabled(true);
+        bus.write::<u32>(&r, 0, 0xddcc1234);
+        bus.write::<u16>(&r, 0, 0x5678);
+        assert_eq!(crate::paranoid::take_violations(), vec![]);
+        bus.write::<u16>(&r, 2, 0x1234);
+        let v = crate::paranoid::take_violations();
+        assert_eq!(v.len(), 1);
+        assert!(v[0].msg.contains("reg32"));
+        crate::paranoid::set_enabled(false);
+    }
+
+    #[test]
+    fn reg32le_paranoid_wcb() {
+        // Writes to registers with a callback are not checked, even if all
+        // their bits are read-only (eg: interrupt acks).
+        let bus = FakeBus::default();
+        let mut r = le::Reg32::new_basic("reg32")
+            .with_rwmask(0)
+            .with_wcb(Some(Rc::new(Box::new(move |_old, _val| {}))));
+        r.set(0xddccbbaa);
+        crate::paranoid::set_enabled(true);
+        bus.write::<u32>(&r, 0, 0x1234_5678);
+        bus.write::<u16>(&r, 2, 0x1234);
+        assert_eq!(crate::paranoid::take_violations(), vec![]);
+        crate::paranoid::set_enabled(false);
+    }
+
     #[test]
     fn reg32le_cb() {
         let bus = FakeBus::default();
-        let mut r = le::Reg32::new_basic("reg32").with_rcb(Some(Rc::new(box move |val| val | 0x1)));
+        let mut r =
+            le::Reg32::new_basic("reg32").with_rcb(Some(Rc::new(Box::new(move |val| val | 0x1))));
         r.set(0x12345678);
         assert_eq!(bus.read::<u32>(&r, 0), 0x12345679);
         bus.write::<u16>(&r, 0, 0x6788);
@@ -359,6 +423,47 @@ mod tests {
         assert_eq!(r.get(), 0x12346788);
     }
 
+    #[test]
+    fn reg32be_subword() {
+        let bus = FakeBus::default();
+
+        let mut r = be::Reg32::new_basic("merge");
+        r.set(0xaabbccdd);
+        bus.write::<u8>(&r, 1, 0x12);
+        assert_eq!(r.get(), 0xaa12ccdd);
+
+        let mut r =
+            be::Reg32::new_basic("shift").with_flags(RegFlags::default() | RegFlags::SUBWORD_SHIFT);
+        r.set(0xaabbccdd);
+        bus.write::<u8>(&r, 1, 0x12);
+        assert_eq!(r.get(), 0x00120000);
+        bus.write::<u16>(&r, 2, 0x3456);
+        assert_eq!(r.get(), 0x00003456);
+        bus.write::<u16>(&r, 0, 0x789a);
+        assert_eq!(r.get(), 0x789a0000);
+        bus.write::<u32>(&r, 0, 0x12345678);
+        assert_eq!(r.get(), 0x12345678);
+        assert_eq!(bus.read::<u8>(&r, 2), 0x56);
+
+        // Read-only bits are preserved
+        let mut r = be::Reg32::new_basic("shift_ro")
+            .with_rwmask(0x0000ffff)
+            .with_flags(RegFlags::default() | RegFlags::SUBWORD_SHIFT);
+        r.set(0xaabbccdd);
+        bus.write::<u8>(&r, 2, 0x12);
+        assert_eq!(r.get(), 0xaabb1200);
+
+        let mut r = be::Reg32::new_basic("ignore")
+            .with_flags(RegFlags::default() | RegFlags::SUBWORD_IGNORE);
+        r.set(0xaabbccdd);
+        bus.write::<u8>(&r, 3, 0x12);
+        bus.write::<u16>(&r, 0, 0x3456);
+        assert_eq!(r.get(), 0xaabbccdd);
+        assert_eq!(bus.read::<u16>(&r, 0), 0xaabb);
+        bus.write::<u32>(&r, 0, 0x12345678);
+        assert_eq!(r.get(), 0x12345678);
+    }
+
     #[test]
     fn reg32le_rowo() {
         let bus = FakeBus::default();
@@ -380,8 +485,8 @@ mod tests {
         let _bus = FakeBus::<BigEndian, u32>::default();
         let r = be::Reg32::new_basic("reg32")
             .with_rwmask(0x0000_ffff)
-            .with_wcb(Some(Rc::new(box move |_old, _val| {})));
-        assert_eq!(r.reader().is_mem(), true);
-        assert_eq!(r.writer().is_mem(), false);
+            .with_wcb(Some(Rc::new(Box::new(move |_old, _val| {}))));
+        assert!(r.reader().is_mem());
+        assert!(!r.writer().is_mem());
     }
 }
@@ -0,0 +1,137 @@
+//! Clock domains.
+//!
+//! Emulated hardware is usually driven by a few oscillators, and each device
+//! runs on a clock derived from one of them through multipliers and dividers.
+//! A [`ClockDomain`](struct.ClockDomain.html) keeps track of its frequency as
+//! an exact fraction, so that converting cycles between domains never
+//! accumulates rounding errors, however long the emulation runs.
+//!
+//! ```
+//! use emu::clock::ClockDomain;
+//!
+//! const RCP: ClockDomain = ClockDomain::new("RCP", 62_500_000);
+//! const CPU: ClockDomain = RCP.scale("CPU", 3, 2);
+//!
+//! assert_eq!(CPU.hz(), 93_750_000.0);
+//! assert_eq!(RCP.to(&CPU, 2), 3);
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+/// A clock domain: a named frequency, expressed in Hz as the fraction
+/// `num / den`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ClockDomain {
+    name: &'static str,
+    num: i64,
+    den: i64,
+}
+
+impl ClockDomain {
+    /// Create a clock domain running at the specified integral frequency
+    /// (eg: an oscillator).
+    pub const fn new(name: &'static str, hz: i64) -> ClockDomain {
+        ClockDomain {
+            name,
+            num: hz,
+            den: 1,
+        }
+    }
+
+    /// Derive a new clock domain from this one, through a multiplier and a
+    /// divider (eg: a PLL). The resulting frequency is exact.
+    pub const fn scale(&self, name: &'static str, mul: i64, div: i64) -> ClockDomain {
+        ClockDomain {
+            name,
+            num: self.num * mul,
+            den: self.den * div,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Return the frequency in Hz. This is meant for display purposes; use
+    /// [`to`](#method.to) to convert cycles between domains.
+    pub fn hz(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// Convert a number of cycles of this domain into the number of cycles
+    /// of the `dst` domain that elapse in the same time, rounding down.
+    pub fn to(&self, dst: &ClockDomain, cycles: i64) -> i64 {
+        let (n, d) = self.ratio(dst);
+        let c = cycles as i128 * n;
+        // Floor division, so that negative cycles are also rounded down.
+        let q = c / d;
+        (if c % d < 0 { q - 1 } else { q }) as i64
+    }
+
+    /// Like [`to`](#method.to), but rounding up. This is useful to
+    /// compute the first cycle of the `dst` domain which is not before the
+    /// specified cycle of this domain.
+    pub fn to_ceil(&self, dst: &ClockDomain, cycles: i64) -> i64 {
+        -self.to(dst, -cycles)
+    }
+
+    /// Return the time elapsed in the specified number of cycles of this
+    /// domain (rounded down to the nanosecond).
+    pub fn duration(&self, cycles: i64) -> Duration {
+        const NANOS: ClockDomain = ClockDomain::new("ns", 1_000_000_000);
+        Duration::from_nanos(self.to(&NANOS, cycles.max(0)) as u64)
+    }
+
+    // Ratio between the frequency of dst and the frequency of self.
+    fn ratio(&self, dst: &ClockDomain) -> (i128, i128) {
+        (
+            dst.num as i128 * self.den as i128,
+            dst.den as i128 * self.num as i128,
+        )
+    }
+}
+
+impl fmt::Display for ClockDomain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:.4} MHz)", self.name, self.hz() / 1_000_000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X1: ClockDomain = ClockDomain::new("X1", 14_705_000);
+    const RCP: ClockDomain = X1.scale("RCP", 17, 4);
+    const CPU: ClockDomain = RCP.scale("CPU", 3, 2);
+    const VCLK: ClockDomain = ClockDomain::new("X2", 14_318_000).scale("VCLK", 17, 5);
+
+    #[test]
+    fn exact() {
+        assert_eq!(RCP.hz(), 62_496_250.0);
+        assert_eq!(CPU.hz(), 93_744_375.0);
+        assert_eq!(RCP.to(&CPU, 2), 3);
+        assert_eq!(RCP.to(&CPU, 3), 4);
+        assert_eq!(RCP.to_ceil(&CPU, 3), 5);
+        assert_eq!(CPU.to(&RCP, 3), 2);
+
+        // One hour of emulation: no drift.
+        let secs = 3600;
+        let vclk = 48_681_200 * secs;
+        assert_eq!(VCLK.to(&RCP, vclk), 62_496_250 * secs);
+        assert_eq!(RCP.to(&VCLK, 62_496_250 * secs), vclk);
+    }
+
+    #[test]
+    fn rounding() {
+        let a = ClockDomain::new("A", 3);
+        let b = ClockDomain::new("B", 2);
+        assert_eq!(a.to(&b, 1), 0);
+        assert_eq!(a.to_ceil(&b, 1), 1);
+        assert_eq!(a.to(&b, -1), -1);
+        assert_eq!(a.to_ceil(&b, -1), 0);
+        assert_eq!(a.to(&b, 0), 0);
+        assert_eq!(a.to_ceil(&b, 0), 0);
+    }
+}
@@ -0,0 +1,201 @@
+//! Random memory corruption.
+//!
+//! A [`Corruptor`](struct.Corruptor.html) flips random bits in emulated
+//! memory areas (ROM, RAM) at a configurable rate while the emulation runs.
+//! It is a cheap fuzzer for the emulated devices, which must survive garbage
+//! input without panicking; and it makes for funny glitches, too.
+//!
+//! The random sequence only depends on the seed, so a corrupted run can be
+//! reproduced exactly.
+
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// A memory area to corrupt, and how much.
+///
+/// It can be parsed from a string with the syntax `NAME[@START-END]=RATE`,
+/// where `START` and `END` are hexadecimal offsets within the memory area,
+/// and `RATE` is the probability of flipping a bit in each frame, between 0
+/// and 1 (eg: `rdram=0.1` flips a bit every 10 frames).
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorruptTarget {
+    pub name: String,
+    pub range: Option<Range<usize>>,
+    pub rate: f64,
+}
+
+impl FromStr for CorruptTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CorruptTarget, String> {
+        let err = || {
+            format!(
+                "invalid corruption target: {:?} (expected NAME[@START-END]=RATE)",
+                s
+            )
+        };
+        let hex =
+            |v: &str| usize::from_str_radix(v.trim_start_matches("0x"), 16).map_err(|_| err());
+
+        let mut parts = s.splitn(2, '=');
+        let target = parts.next().ok_or_else(err)?;
+        let rate: f64 = parts.next().ok_or_else(err)?.parse().map_err(|_| err())?;
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(err());
+        }
+
+        let mut parts = target.splitn(2, '@');
+        let name = parts.next().ok_or_else(err)?;
+        let range = match parts.next() {
+            Some(range) => {
+                let mut bounds = range.splitn(2, '-');
+                let start = hex(bounds.next().ok_or_else(err)?)?;
+                let end = hex(bounds.next().ok_or_else(err)?)?;
+                if start >= end {
+                    return Err(err());
+                }
+                Some(start..end)
+            }
+            None => None,
+        };
+        if name.is_empty() {
+            return Err(err());
+        }
+
+        Ok(CorruptTarget {
+            name: name.to_owned(),
+            range,
+            rate,
+        })
+    }
+}
+
+impl fmt::Display for CorruptTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(range) = &self.range {
+            write!(f, "@{:x}-{:x}", range.start, range.end)?;
+        }
+        write!(f, "={}", self.rate)
+    }
+}
+
+/// Flips random bits in memory areas, according to a list of
+/// [`CorruptTarget`](struct.CorruptTarget.html)s.
+pub struct Corruptor {
+    targets: Vec<CorruptTarget>,
+    pending: Vec<f64>, // Fractional flips accumulated for each target
+    rng: u64,
+    flips: u64,
+}
+
+impl Corruptor {
+    pub fn new(seed: u64, targets: Vec<CorruptTarget>) -> Corruptor {
+        Corruptor {
+            pending: vec![0.0; targets.len()],
+            targets,
+            // xorshift cannot start from zero
+            rng: seed | 1,
+            flips: 0,
+        }
+    }
+
+    pub fn targets(&self) -> &[CorruptTarget] {
+        &self.targets
+    }
+
+    /// Return the total number of bits flipped so far.
+    pub fn flips(&self) -> u64 {
+        self.flips
+    }
+
+    // xorshift64*
+    fn next_u64(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Corrupt the memory area with the specified name, for one frame worth
+    /// of time. It should be called once per frame for each memory area
+    /// that can be corrupted. Returns the number of bits flipped.
+    pub fn corrupt(&mut self, name: &str, mem: &mut [u8]) -> usize {
+        let mut flipped = 0;
+        for idx in 0..self.targets.len() {
+            if self.targets[idx].name != name {
+                continue;
+            }
+            let range = match &self.targets[idx].range {
+                Some(r) => r.start.min(mem.len())..r.end.min(mem.len()),
+                None => 0..mem.len(),
+            };
+            self.pending[idx] += self.targets[idx].rate;
+            while self.pending[idx] >= 1.0 {
+                self.pending[idx] -= 1.0;
+                if range.start == range.end {
+                    continue;
+                }
+                let r = self.next_u64();
+                let off = range.start + (r >> 3) as usize % (range.end - range.start);
+                mem[off] ^= 1 << (r & 7);
+                flipped += 1;
+            }
+        }
+        self.flips += flipped as u64;
+        flipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "rdram@100-0x200=0.25".parse(),
+            Ok(CorruptTarget {
+                name: "rdram".into(),
+                range: Some(0x100..0x200),
+                rate: 0.25,
+            })
+        );
+        assert_eq!("rom=1".parse::<CorruptTarget>().map(|t| t.range), Ok(None));
+        assert!("rom".parse::<CorruptTarget>().is_err());
+        assert!("rom@200-100=1".parse::<CorruptTarget>().is_err());
+        assert!("=1".parse::<CorruptTarget>().is_err());
+        assert!("rom=-1".parse::<CorruptTarget>().is_err());
+        assert!("rom=1.5".parse::<CorruptTarget>().is_err());
+        assert!("rom=inf".parse::<CorruptTarget>().is_err());
+        assert!("rom=NaN".parse::<CorruptTarget>().is_err());
+    }
+
+    #[test]
+    fn corrupt() {
+        let targets = vec!["ram@10-20=0.5".parse().unwrap(), "rom=0".parse().unwrap()];
+        let mut c = Corruptor::new(1234, targets);
+        let mut ram = vec![0u8; 0x100];
+        let mut rom = vec![0u8; 0x100];
+
+        let mut flips = 0;
+        for _ in 0..100 {
+            flips += c.corrupt("ram", &mut ram);
+            c.corrupt("rom", &mut rom);
+        }
+        assert_eq!(flips, 50);
+        assert_eq!(c.flips(), 50);
+        assert!(rom.iter().all(|&b| b == 0));
+        assert!(ram[..0x10].iter().chain(&ram[0x20..]).all(|&b| b == 0));
+        assert!(ram[0x10..0x20].iter().any(|&b| b != 0));
+
+        // Same seed, same corruption
+        let mut c2 = Corruptor::new(1234, c.targets().to_vec());
+        let mut ram2 = vec![0u8; 0x100];
+        for _ in 0..100 {
+            c2.corrupt("ram", &mut ram2);
+        }
+        assert_eq!(ram, ram2);
+    }
+}
@@ -1,16 +1,40 @@
+//! The interactive debugger.
+//!
+//! Emulators implement [`DebuggerModel`](trait.DebuggerModel.html) to expose
+//! their subsystems; views are rendered through
+//! [`DebuggerRenderer`](struct.DebuggerRenderer.html) by implementing the
+//! traits of each view (eg: [`RegisterView`](trait.RegisterView.html),
+//! [`DisasmView`](trait.DisasmView.html)) for the emulated devices.
+
+use crate::bus::{CurrentDeviceMap, DeviceInfo};
 use crate::gfx::{GfxBufferMutLE, Rgb888};
+#[cfg(feature = "frontend")]
 use crate::hw::glutils::Texture;
+#[cfg(feature = "frontend")]
+use crate::hw::{
+    FontConfig, FontGlyphs, HotkeyAction, HotkeyConfig, InputMapping, InputProfile, OutputProducer,
+    ThemeConfig,
+};
 use crate::snd::{SampleFormat, SndBufferMut};
 
+#[cfg(feature = "debugger")]
 use imgui::*;
+#[cfg(feature = "frontend")]
 use imgui_opengl_renderer::Renderer;
+#[cfg(feature = "frontend")]
 use imgui_sdl2::ImguiSdl2;
+#[cfg(feature = "frontend")]
 use imgui_sys::{igSetNextWindowSizeConstraints, ImGuiSizeCallbackData};
-use sdl2::keyboard::Scancode;
+#[cfg(feature = "debugger")]
 mod uisupport;
+#[cfg(feature = "frontend")]
+use self::uisupport::keys;
 
-use std::cell::RefCell;
-use std::rc::Rc;
+#[cfg(feature = "frontend")]
+use slog::{info, warn};
+#[cfg(feature = "frontend")]
+use std::path::PathBuf;
+#[cfg(feature = "frontend")]
 use std::time::{Duration, Instant};
 
 // Views
@@ -18,19 +42,80 @@ mod regview;
 pub use self::regview::*;
 mod disasmview;
 pub use self::disasmview::*;
+mod dualmemview;
+pub use self::dualmemview::*;
+mod memview;
+pub use self::memview::*;
+mod packetview;
+pub use self::packetview::*;
+mod audioview;
+pub use self::audioview::*;
+mod videoview;
+pub use self::videoview::*;
+mod heapview;
+pub use self::heapview::*;
+mod storageview;
+pub use self::storageview::*;
+mod cacheview;
+pub use self::cacheview::*;
+#[cfg(feature = "frontend")]
+mod movieview;
+#[cfg(feature = "frontend")]
+use self::movieview::{MovieCommand, MovieEditor};
 mod decoding;
 pub use self::decoding::*;
 mod tracer;
 pub use self::tracer::*;
+#[cfg(feature = "debugger")]
 mod uictx;
+#[cfg(feature = "debugger")]
 pub(crate) use self::uictx::*;
+#[cfg(feature = "frontend")]
 mod miscview;
+#[cfg(feature = "frontend")]
 pub(crate) use self::miscview::*;
+#[cfg(any(feature = "frontend", test))]
+mod session;
+mod symbols;
+#[cfg(feature = "frontend")]
+use self::session::DebuggerSession;
+pub use self::symbols::Symbols;
+#[cfg(any(feature = "debugger", test))]
+mod annotations;
+#[cfg(any(feature = "debugger", test))]
+pub(crate) use self::annotations::*;
+mod timeline;
+pub use self::timeline::MemProbe;
+mod chrometrace;
+pub use self::chrometrace::ChromeTrace;
+mod gdbstub;
+pub use self::gdbstub::*;
+#[cfg(feature = "frontend")]
+mod inputview;
+#[cfg(feature = "frontend")]
+use self::inputview::render_inputview;
+#[cfg(feature = "frontend")]
+mod deviceview;
+#[cfg(feature = "frontend")]
+use self::deviceview::render_deviceview;
 
 pub trait DebuggerModel {
-    /// Return a vector of the name of all CPUS.
-    /// TODO: the debugger could autodiscover the CPUs while rendering.
-    fn all_cpus(&self) -> Vec<String>;
+    /// Describe the emulated machine: all its devices, with their register
+    /// banks and memory regions. By default, it returns the devices
+    /// registered in the current device map, so new devices automatically
+    /// appear in the debugger.
+    fn devices(&self) -> Vec<DeviceInfo> {
+        CurrentDeviceMap().info()
+    }
+
+    /// Return a vector of the name of all CPUs. By default, they are
+    /// discovered among the devices (see `#[device(cpu)]`).
+    fn all_cpus(&self) -> Vec<String> {
+        self.devices()
+            .into_iter()
+            .filter_map(|dev| dev.cpu)
+            .collect()
+    }
 
     // Return the total elapsed cycles since the beginning of emulation
     fn cycles(&self) -> i64;
@@ -62,78 +147,382 @@ pub trait DebuggerModel {
     /// Reset the emulator.
     fn reset(&mut self, hard: bool);
 
-    fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>);
+    #[cfg(feature = "debugger")]
+    fn render_debug<'a, 'ui>(&mut self, dr: &mut DebuggerRenderer<'a, 'ui>);
+
+    /// Convert an address of the code running on a CPU (eg: a virtual
+    /// address from a symbol file) into the program counter that the CPU
+    /// reports to the tracer, so that a breakpoint can be set on it. By
+    /// default, addresses are not converted.
+    fn cpu_breakpoint_addr(&self, _cpu_name: &str, addr: u64) -> u64 {
+        addr
+    }
+
+    /// Return the memory probes to install at startup. Reads of the probed
+    /// addresses are plotted in the debugger timeline.
+    fn mem_probes(&self) -> Vec<MemProbe> {
+        vec![]
+    }
+
+    /// Return a string that uniquely identifies the software being emulated
+    /// (eg: the ROM), if any. It is used to persist the debugger session
+    /// (breakpoints, watchpoints, etc.) across different runs.
+    fn session_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Return a badge describing the software being emulated (eg: its
+    /// compatibility status), to be displayed in the menu bar.
+    fn status_badge(&self) -> Option<StatusBadge> {
+        None
+    }
+
+    /// Return the primitives of the last frame that touched a pixel of the
+    /// screen (in coordinates of the screen buffer), in drawing order. It is
+    /// used when clicking on the screen window.
+    fn query_pixel(&self, _x: usize, _y: usize) -> Vec<PixelHit> {
+        vec![]
+    }
+
+    /// Return the raw value of the framebuffer pixel shown at the specified
+    /// coordinates of the screen buffer, if any. It is displayed by the
+    /// magnifier of the screen window.
+    fn framebuffer_pixel(&self, _x: usize, _y: usize) -> Option<RawPixel> {
+        None
+    }
+
+    /// Return the size of the picture output by the emulated video hardware
+    /// in the last frame, if known. The picture is scaled to the screen
+    /// buffer, so this gives the aspect ratio of the screen window.
+    fn output_size(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Return the names of the hardware events reported through
+    /// Tracer::trace_hw_event (eg: a DMA completion), on which the user can
+    /// select to break.
+    fn hw_events(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Return the current value of a register of a CPU, given its name as
+    /// displayed in the register view. It is used to expand tracepoints.
+    fn cpu_register(&mut self, _cpu_name: &str, _reg: &str) -> Option<u64> {
+        None
+    }
+
+    /// Read a 32-bit word at the specified address, as seen by a CPU,
+    /// without side effects (eg: None for hardware registers). It is used to
+    /// expand tracepoints.
+    fn cpu_peek(&mut self, _cpu_name: &str, _addr: u64) -> Option<u32> {
+        None
+    }
+}
+
+/// A short colored label displayed in the debugger menu bar, with optional
+/// details shown when hovering it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusBadge {
+    pub label: String,
+    pub color: (u8, u8, u8),
+    pub details: Vec<String>,
 }
 
-pub struct DebuggerUI {
-    imgui: Rc<RefCell<ImGui>>,
+/// A primitive that touched a pixel, as returned by
+/// DebuggerModel::query_pixel: its description, and the state (eg: of the
+/// graphics pipeline) used to draw it, as pairs of labels and values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PixelHit {
+    pub name: String,
+    pub details: Vec<(String, String)>,
+}
+
+/// The imgui context of the debugger, together with its SDL and OpenGL
+/// backends. It is owned by the frontend event loop, which feeds it the SDL
+/// events and lends it to DebuggerUI to render each frame, so that the UI
+/// state never needs to be shared.
+#[cfg(feature = "frontend")]
+pub(crate) struct UiHost {
+    imgui: ImGui,
     imgui_sdl2: ImguiSdl2,
     backend: Renderer,
     hidpi_factor: f32,
+    base_style: ImGuiStyle, // unscaled style, to apply the UI scale to
+    warnings: Vec<String>,  // problems found during initialization, to be shown in the UI
+}
+
+#[cfg(feature = "frontend")]
+impl UiHost {
+    pub(crate) fn new(
+        video: sdl2::VideoSubsystem,
+        window: &sdl2::video::Window,
+        font: &FontConfig,
+        theme: &ThemeConfig,
+    ) -> Self {
+        // Scale the UI according to the DPI of the display the window is on
+        // (96 DPI is the reference), unless the user forced a specific scale.
+        let hidpi_factor = font.scale.unwrap_or_else(|| {
+            window
+                .display_index()
+                .and_then(|idx| video.display_dpi(idx))
+                .map(|(ddpi, _, _)| (ddpi / 96.0).max(1.0))
+                .unwrap_or(1.0)
+        });
+
+        let mut imgui = ImGui::init();
+        imgui.set_ini_filename(Some(im_str!("debug.ini").to_owned()));
+        let font_err = Self::load_font(&mut imgui, font, hidpi_factor).err();
+        let base_style = imgui.style().clone();
+        let unknown_colors = apply_theme(&mut imgui, theme, &base_style, hidpi_factor);
+
+        let imgui_sdl2 = ImguiSdl2::new(&mut imgui);
+        let backend = Renderer::new(&mut imgui, move |s| video.gl_get_proc_address(s) as _);
+
+        let mut warnings = Vec::new();
+        if let Some(err) = font_err {
+            warnings.push(format!("Cannot load font, using default:\n{}", err));
+        }
+        if !unknown_colors.is_empty() {
+            warnings.push(format!(
+                "Unknown theme colors in configuration:\n{}",
+                unknown_colors.join(", ")
+            ));
+        }
+
+        Self {
+            imgui,
+            imgui_sdl2,
+            backend,
+            hidpi_factor,
+            base_style,
+            warnings,
+        }
+    }
+
+    // Load the UI font into imgui's atlas. This must happen before the
+    // renderer is created, as it uploads the atlas texture. On error, the
+    // embedded font is loaded instead, so that the UI is always usable.
+    fn load_font(
+        imgui: &mut ImGui,
+        font: &FontConfig,
+        scale: f32,
+    ) -> std::result::Result<(), String> {
+        let cfg = || {
+            ImFontConfig::new()
+                .oversample_h(1)
+                .pixel_snap_h(true)
+                .size_pixels(font.size * scale)
+        };
+
+        let res = match font.path {
+            Some(ref path) => match std::fs::read(path) {
+                Ok(data) => {
+                    let range = match font.glyphs {
+                        FontGlyphs::Default => FontGlyphRange::default(),
+                        FontGlyphs::Japanese => FontGlyphRange::japanese(),
+                        FontGlyphs::ChineseFull => FontGlyphRange::chinese_full(),
+                        FontGlyphs::ChineseSimplified => {
+                            FontGlyphRange::chinese_simplified_common()
+                        }
+                        FontGlyphs::Korean => FontGlyphRange::korean(),
+                        FontGlyphs::Cyrillic => FontGlyphRange::cyrillic(),
+                    };
+                    imgui.fonts().add_font_with_config(&data, cfg(), &range);
+                    return Ok(());
+                }
+                Err(err) => Err(format!("{}: {}", path, err)),
+            },
+            None => Ok(()),
+        };
+
+        imgui.fonts().add_default_font_with_config(cfg());
+        res
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &sdl2::event::Event) {
+        self.imgui_sdl2.handle_event(&mut self.imgui, event);
+    }
+}
+
+#[cfg(feature = "frontend")]
+pub struct DebuggerUI {
     tex_screen: Texture,
     screen_size: (usize, usize),
+    output_size: (usize, usize), // size of the emulated picture (for the aspect ratio)
+    screen_pixels: Vec<u8>,      // copy of the last frame (RGBX), for the magnifier
 
     pub dbg: Debugger,
-    uictx: RefCell<UiCtx>,
+    uictx: UiCtx,
+    session_id: Option<String>,
 
     paused: bool,
     last_render: Instant, // last instant the debugger refreshed its UI
+    movie: MovieEditor,
+    chrome_trace_path: Option<PathBuf>, // where to save the running Chrome trace capture
+    pixel_query: Option<((usize, usize), Vec<PixelHit>)>, // last pixel clicked on the screen
+    pixel_cursor: (usize, usize),       // pixel selected on the screen with the keyboard
+    magnifier: bool,                    // show the magnifier window
+    magnifier_size: i32,                // screen pixels per side shown by the magnifier
+    gdb: Option<GdbStub>,               // remote debugger stub, if enabled
+    rewind_request: bool,               // "Rewind" selected in the menu
+    logger: slog::Logger,
 }
 
+#[cfg(feature = "frontend")]
 impl DebuggerUI {
-    pub(crate) fn new<T: DebuggerModel>(video: sdl2::VideoSubsystem, producer: &mut T) -> Self {
-        let hidpi_factor = 1.0;
-
-        let mut imgui = ImGui::init();
-        imgui.set_ini_filename(Some(im_str!("debug.ini").to_owned()));
-
-        let imgui_sdl2 = ImguiSdl2::new(&mut imgui);
-        let backend = Renderer::new(&mut imgui, move |s| video.gl_get_proc_address(s) as _);
-
-        let mut uictx = UiCtx::default();
-        uictx.cpus = producer.all_cpus();
+    pub(crate) fn new<T: DebuggerModel>(
+        host: &mut UiHost,
+        producer: &mut T,
+        logger: slog::Logger,
+    ) -> Self {
+        let mut uictx = UiCtx {
+            cpus: producer.all_cpus(),
+            ..UiCtx::default()
+        };
         for idx in 0..uictx.cpus.len() {
             let name = &uictx.cpus[idx];
-            uictx.disasm.insert(name.clone(), UiCtxDisasm::default());
+            uictx
+                .disasm
+                .insert((name.clone(), 0), UiCtxDisasm::default());
         }
 
         // Initial event
-        uictx.event = Some((box TraceEvent::Paused(), Instant::now()));
+        uictx.event = Some((Box::new(TraceEvent::Paused()), Instant::now()));
+        for msg in host.warnings.drain(..) {
+            uictx.add_flash_msg(&msg);
+        }
+
+        // Restore the previous debugging session for this software (if any)
+        let mut dbg = Debugger::new(&uictx.cpus);
+        for probe in producer.mem_probes() {
+            dbg.add_mem_probe(probe);
+        }
+        dbg.set_hw_events(producer.hw_events());
+        let session_id = producer.session_id();
+        if let Some(ref id) = session_id {
+            match DebuggerSession::load(id) {
+                Ok(mut session) => {
+                    uictx.annotations = std::mem::take(&mut session.annotations);
+                    for err in dbg.load_session(session) {
+                        uictx.add_flash_msg(&err);
+                    }
+                }
+                Err(err) => uictx.add_flash_msg(&format!("Cannot load debugger session:\n{}", err)),
+            }
+        }
 
         Self {
-            imgui: Rc::new(RefCell::new(imgui)),
-            imgui_sdl2,
-            backend,
-            hidpi_factor,
             tex_screen: Texture::new(),
             screen_size: (320, 240),
-            dbg: Debugger::new(&uictx.cpus),
-            uictx: RefCell::new(uictx),
+            output_size: (320, 240),
+            screen_pixels: Vec::new(),
+            dbg,
+            uictx,
+            session_id,
             paused: true,
             last_render: Instant::now(),
+            movie: MovieEditor::default(),
+            chrome_trace_path: None,
+            pixel_query: None,
+            pixel_cursor: (0, 0),
+            magnifier: false,
+            magnifier_size: 16,
+            gdb: None,
+            rewind_request: false,
+            logger,
         }
     }
 
-    pub(crate) fn handle_event(&mut self, event: &sdl2::event::Event) {
-        let imgui = self.imgui.clone();
-        let mut imgui = imgui.borrow_mut();
-        self.imgui_sdl2.handle_event(&mut imgui, &event);
+    pub(crate) fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub(crate) fn add_flash_msg(&mut self, msg: &str) {
+        self.uictx.add_flash_msg(msg);
+    }
+
+    /// Return true (once) if a rewind step was requested from the menu.
+    pub(crate) fn take_rewind_request(&mut self) -> bool {
+        std::mem::replace(&mut self.rewind_request, false)
+    }
+
+    /// Start listening for a remote debugger (GDB remote protocol) on the
+    /// specified address.
+    pub(crate) fn start_gdb_stub(&mut self, addr: &str) -> std::io::Result<()> {
+        let logger = self.logger.clone();
+        self.gdb = Some(GdbStub::listen(addr, &self.uictx.cpus, logger)?);
+        Ok(())
+    }
+
+    // Process the requests of the remote debugger (if any), and report to it
+    // when the emulation stops.
+    fn poll_gdb<T: DebuggerModel>(&mut self, producer: &mut T) {
+        let gdb = match self.gdb.as_mut() {
+            Some(gdb) => gdb,
+            None => return,
+        };
+        let uictx = &mut self.uictx;
+        match gdb.poll(&mut self.dbg, producer) {
+            Some(GdbRequest::Continue) => self.paused = false,
+            Some(GdbRequest::Step(cpu_name)) => {
+                let _ = producer.trace_step(&cpu_name, &Tracer::null());
+                self.paused = true;
+                uictx.event = Some((Box::new(TraceEvent::Stepped()), Instant::now()));
+            }
+            Some(GdbRequest::Interrupt) if !self.paused => {
+                self.paused = true;
+                uictx.event = Some((Box::new(TraceEvent::Paused()), Instant::now()));
+            }
+            Some(GdbRequest::Interrupt) | None => {}
+        }
+        if self.paused {
+            match uictx.event {
+                Some((ref event, _)) => gdb.stopped(&self.dbg, event),
+                None => gdb.stopped(&self.dbg, &TraceEvent::Paused()),
+            }
+        }
+    }
+
+    /// Start capturing a Chrome trace. It is saved into the specified file
+    /// when the capture is stopped (or the debugger is closed); if no file
+    /// is specified, a new file is created in the current directory.
+    pub(crate) fn start_chrome_trace(&mut self, path: Option<PathBuf>) {
+        self.chrome_trace_path = path;
+        self.dbg.start_chrome_trace();
+    }
+
+    fn save_chrome_trace(&mut self) -> Option<std::result::Result<PathBuf, String>> {
+        let ct = self.dbg.stop_chrome_trace()?;
+        let path = self.chrome_trace_path.take().unwrap_or_else(|| {
+            (0..)
+                .map(|idx| PathBuf::from(format!("trace-{:04}.json", idx)))
+                .find(|path| !path.exists())
+                .unwrap()
+        });
+        Some(ct.save(&path).map(|_| path).map_err(|err| err.to_string()))
+    }
+
+    /// Return true if the debugger is consuming keyboard input (eg: the user
+    /// is typing into a text field), so that hotkeys should not be processed.
+    pub(crate) fn wants_text_input(&mut self) -> bool {
+        self.uictx.hotkey_capture.is_some() || unsafe { (*imgui_sys::igGetIO()).want_text_input }
     }
 
     /// Run an emulator (DebuggerModel) under the debugger for a little while.
     /// Returns true if during this call the emulator completed a frame, or false otherwise.
-    pub(crate) fn trace<T: DebuggerModel, SF: SampleFormat>(
+    pub(crate) fn trace<T: DebuggerModel + OutputProducer, SF: SampleFormat>(
         &mut self,
         producer: &mut T,
         screen: &mut GfxBufferMutLE<Rgb888>,
         sound: &mut SndBufferMut<SF>,
     ) -> bool {
+        self.poll_gdb(producer);
+
         // If the emulation core is paused, we can simply wait here to avoid hogging CPU.
         // Refresh every 16ms / 60FPS.
         if self.paused {
-            match Duration::from_millis(16).checked_sub(self.last_render.elapsed()) {
-                Some(d) => std::thread::sleep(d),
-                None => {}
+            if let Some(d) = Duration::from_millis(16).checked_sub(self.last_render.elapsed()) {
+                std::thread::sleep(d)
             }
             return false;
         }
@@ -142,78 +531,142 @@ impl DebuggerUI {
         let trace_until = self.last_render + Duration::from_millis(50);
         self.dbg.set_poll_event(trace_until);
 
-        match producer.trace_frame(screen, sound, &self.dbg.new_tracer()) {
+        // Record or play back the input movie
+        let frame = producer.frames();
+        if let Some(im) = producer.input_manager() {
+            self.movie.begin_frame(frame, im);
+        }
+
+        // Tracepoints don't stop emulation: log them and resume immediately.
+        let mut res = producer.trace_frame(screen, sound, &self.dbg.new_tracer());
+        while let Err(TraceEvent::Tracepoint(cpu_name, idx, pc)) = res.as_ref().map_err(|e| &**e) {
+            let format = self.dbg.tracepoint_format(cpu_name, *idx).to_owned();
+            let line = format_tracepoint(&format, producer, cpu_name, self.dbg.symbols());
+            self.dbg.log(format!("[{}] {:x}: {}", cpu_name, pc, line));
+            res = producer.trace_frame(screen, sound, &self.dbg.new_tracer());
+        }
+
+        match res {
             Ok(()) => {
                 // A frame is finished. Copy it into the texture so that it's available
                 // starting from next render().
                 self.tex_screen.copy_from_buffer_mut(screen);
                 self.screen_size = (screen.width(), screen.height());
-                return true;
+                self.output_size = producer.output_size().unwrap_or(self.screen_size);
+                let (w, h) = self.screen_size;
+                let (pixels, pitch) = screen.raw();
+                self.screen_pixels.clear();
+                for y in 0..h {
+                    self.screen_pixels
+                        .extend_from_slice(&pixels[y * pitch..][..w * 4]);
+                }
+                true
             }
             Err(event) => {
-                self.uictx.get_mut().event = Some((event.clone(), Instant::now()));
+                self.uictx.event = Some((event.clone(), Instant::now()));
                 match *event {
-                    TraceEvent::Poll() => return false, // Polling
+                    TraceEvent::Poll() => false, // Polling
                     TraceEvent::Breakpoint(_, _, _) => {
                         self.paused = true;
                         self.dbg.disable_breakpoint_oneshot();
-                        return false;
+                        false
                     }
                     TraceEvent::WatchpointRead(cpu_name, _) => {
                         self.paused = true;
                         self.dbg.disable_breakpoint_oneshot();
                         self.uictx
-                            .get_mut()
                             .add_flash_msg(&format!("Watchpoint (read) hit on {}", cpu_name));
-                        return false;
+                        false
                     }
                     TraceEvent::WatchpointWrite(cpu_name, _) => {
                         self.paused = true;
                         self.dbg.disable_breakpoint_oneshot();
                         self.uictx
-                            .get_mut()
                             .add_flash_msg(&format!("Watchpoint (write) hit on {}", cpu_name));
-                        return false;
+                        false
                     }
                     TraceEvent::BreakpointOneShot(_, _) => {
                         self.paused = true;
                         self.dbg.disable_breakpoint_oneshot();
-                        return false;
+                        false
                     }
                     TraceEvent::GenericBreak(msg) => {
                         self.paused = true;
                         self.dbg.disable_breakpoint_oneshot();
                         self.uictx
-                            .get_mut()
                             .add_flash_msg(&format!("Emulation stopped:\n{}", msg));
-                        return false;
+                        false
+                    }
+                    TraceEvent::HwEvent(name) => {
+                        self.paused = true;
+                        self.dbg.disable_breakpoint_oneshot();
+                        self.uictx
+                            .add_flash_msg(&format!("Hardware event: {}", name));
+                        false
+                    }
+                    TraceEvent::PausePoint(idx) => {
+                        self.paused = true;
+                        self.dbg.disable_breakpoint_oneshot();
+                        let pp = self.dbg.remove_pause_point(idx);
+                        self.uictx
+                            .add_flash_msg(&format!("Pause point reached: {}", pp));
+                        false
                     }
                     _ => unimplemented!(),
                 }
             }
-        };
+        }
     }
 
     /// Render the current debugger UI.
-    pub(crate) fn render<T: DebuggerModel>(
+    pub(crate) fn render<T: DebuggerModel + OutputProducer>(
         &mut self,
+        host: &mut UiHost,
         window: &sdl2::video::Window,
         event_pump: &sdl2::EventPump,
         model: &mut T,
+        hotkeys: &mut HotkeyConfig,
+        theme: &mut ThemeConfig,
+        profile: &mut InputProfile,
+        input: Option<&mut InputMapping>,
     ) {
-        let imgui = self.imgui.clone();
-        let mut imgui = imgui.borrow_mut();
-        let ui = self.imgui_sdl2.frame(&window, &mut imgui, &event_pump);
+        let ui = host
+            .imgui_sdl2
+            .frame(window, &mut host.imgui, &event_pump.mouse_state());
 
-        self.render_main(&ui, model);
+        self.render_main(&ui, model, hotkeys);
+        let theme_changed = render_settings(&ui, theme);
+        if let Some(im) = model.input_manager() {
+            let raw_keys: Vec<String> = event_pump
+                .keyboard_state()
+                .pressed_scancodes()
+                .map(|sc| sc.name().to_owned())
+                .collect();
+            render_inputview(&ui, &raw_keys, im, profile, input);
+        }
+        render_deviceview(&ui, &model.devices());
+        if let Some(MovieCommand::Seek(frame)) = self.movie.render(&ui, model.frames(), self.paused)
+        {
+            let uictx = &mut self.uictx;
+            match self.movie.seek(frame) {
+                Some(sframe) => {
+                    // Emulate from the savestate up to the requested frame
+                    if sframe < frame {
+                        self.dbg.add_pause_point(PausePoint::Frame(frame));
+                        self.paused = false;
+                    }
+                }
+                None => uictx.add_flash_msg(&format!("No savestate before frame {}", frame)),
+            }
+        }
         ui.show_demo_window(&mut true);
 
         {
-            let dr = DebuggerRenderer {
+            let mut dr = DebuggerRenderer {
                 ui: &ui,
-                ctx: &self.uictx,
+                ctx: &mut self.uictx,
             };
-            model.render_debug(&dr);
+            model.render_debug(&mut dr);
         }
 
         // Actually flush commands batched in imgui to OpenGL
@@ -222,10 +675,13 @@ impl DebuggerUI {
             gl::Clear(gl::COLOR_BUFFER_BIT);
         }
 
-        self.backend.render(ui);
+        host.backend.render(ui);
         self.last_render = Instant::now();
+        if theme_changed {
+            apply_theme(&mut host.imgui, theme, &host.base_style, host.hidpi_factor);
+        }
 
-        let uictx = self.uictx.get_mut();
+        let uictx = &mut self.uictx;
         uictx.event = None;
         match uictx.command {
             Some(UiCommand::Pause(paused)) => self.paused = paused,
@@ -235,29 +691,50 @@ impl DebuggerUI {
                 self.paused = false;
             }
             Some(UiCommand::CpuStep(ref cpu_name)) => {
-                let _ = model.trace_step(&cpu_name, &Tracer::null());
+                let _ = model.trace_step(cpu_name, &Tracer::null());
                 self.paused = true;
-                uictx.event = Some((box TraceEvent::Stepped(), Instant::now()));
+                uictx.event = Some((Box::new(TraceEvent::Stepped()), Instant::now()));
             }
             None => {}
         };
         uictx.command = None;
     }
 
-    fn render_main<'ui, T: DebuggerModel>(&mut self, ui: &Ui<'ui>, model: &mut T) {
-        if ui.imgui().is_key_pressed(Scancode::Space as _) {
+    fn render_main<'ui, T: DebuggerModel>(
+        &mut self,
+        ui: &Ui<'ui>,
+        model: &mut T,
+        hotkeys: &mut HotkeyConfig,
+    ) {
+        self.uictx.frame = model.frames();
+        let capturing = self.uictx.hotkey_capture.is_some();
+        let pressed = |action| {
+            !capturing
+                && hotkeys
+                    .scancode(action)
+                    .is_some_and(|sc| ui.imgui().is_key_pressed(sc as _))
+        };
+
+        if pressed(HotkeyAction::Pause) {
             self.paused = !self.paused;
             if self.paused {
-                self.uictx.get_mut().event = Some((box TraceEvent::Paused(), Instant::now()));
+                self.uictx.event = Some((Box::new(TraceEvent::Paused()), Instant::now()));
             }
         }
+        if pressed(HotkeyAction::FrameAdvance) {
+            // Run until the beginning of next frame
+            self.dbg
+                .add_pause_point(PausePoint::Frame(model.frames() + 1));
+            self.paused = false;
+        }
 
-        render_flash_msgs(ui, self.uictx.get_mut());
+        render_flash_msgs(ui, &mut self.uictx);
 
-        let help = render_help(ui);
-        if ui.imgui().is_key_pressed(Scancode::H as _) {
+        let help = render_help(ui, hotkeys);
+        if pressed(HotkeyAction::DebuggerHelp) {
             ui.open_popup(&help);
         }
+        render_hotkeys(ui, &mut self.uictx, hotkeys);
 
         ui.main_menu_bar(|| {
             ui.menu(im_str!("Emulation")).build(|| {
@@ -267,6 +744,28 @@ impl DebuggerUI {
                 if ui.menu_item(im_str!("Hard Reset")).build() {
                     model.reset(true);
                 }
+                if ui.menu_item(im_str!("Rewind")).build() {
+                    self.rewind_request = true;
+                }
+                ui.separator();
+                if !self.dbg.is_capturing_chrome_trace() {
+                    if ui.menu_item(im_str!("Start Chrome Trace")).build() {
+                        self.start_chrome_trace(None);
+                    }
+                } else if ui.menu_item(im_str!("Stop Chrome Trace")).build() {
+                    if let Some(res) = self.save_chrome_trace() {
+                        let msg = match res {
+                            Ok(path) => format!("Chrome trace saved:\n{}", path.display()),
+                            Err(err) => format!("Cannot save Chrome trace:\n{}", err),
+                        };
+                        self.uictx.add_flash_msg(&msg);
+                    }
+                }
+            });
+            ui.menu(im_str!("View")).build(|| {
+                ui.menu_item(im_str!("Magnifier"))
+                    .selected(&mut self.magnifier)
+                    .build();
             });
 
             ui.same_line(200.0);
@@ -280,7 +779,7 @@ impl DebuggerUI {
                 ui.text(im_str!("RUNNING"));
                 if ui.button(im_str!("Pause"), (40.0, 20.0)) {
                     self.paused = true;
-                    self.uictx.get_mut().event = Some((box TraceEvent::Paused(), Instant::now()));
+                    self.uictx.event = Some((Box::new(TraceEvent::Paused()), Instant::now()));
                 }
             }
 
@@ -290,6 +789,18 @@ impl DebuggerUI {
                 model.cycles(),
                 model.frames()
             ));
+
+            if let Some(badge) = model.status_badge() {
+                let (r, g, b) = badge.color;
+                ui.same_line(650.0);
+                ui.text_colored(
+                    (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0),
+                    im_str!("[{}]", badge.label),
+                );
+                if ui.is_item_hovered() && !badge.details.is_empty() {
+                    ui.tooltip_text(im_str!("{}", badge.details.join("\n")));
+                }
+            }
         });
 
         unsafe {
@@ -298,42 +809,267 @@ impl DebuggerUI {
                 (100.0, 100.0).into(),
                 (10000.0, 10000.0).into(),
                 Some(screen_resize_callback),
-                (&mut self.screen_size as *mut (usize, usize)) as *mut ::std::ffi::c_void,
+                (&mut self.output_size as *mut (usize, usize)) as *mut ::std::ffi::c_void,
             );
         }
+        let mut clicked = None;
+        let mut hovered = None;
         ui.window(im_str!("Screen"))
             .size((320.0, 240.0), ImGuiCond::FirstUseEver)
             .build(|| {
                 let tsid = self.tex_screen.id();
+                let pos = ui.get_cursor_screen_pos();
                 let reg = ui.get_content_region_avail();
                 let image = Image::new(ui, tsid.into(), reg);
                 image.build();
+
+                // Click on a pixel to query which primitives touched it
+                let (w, h) = self.screen_size;
+                if ui.is_item_hovered() {
+                    let (mx, my) = ui.imgui().mouse_pos();
+                    let x = ((mx - pos.0).max(0.0) / reg.0.max(1.0) * w as f32) as usize;
+                    let y = ((my - pos.1).max(0.0) / reg.1.max(1.0) * h as f32) as usize;
+                    hovered = Some((x.min(w - 1), y.min(h - 1)));
+                    if ui.imgui().is_mouse_clicked(ImMouseButton::Left) {
+                        clicked = hovered;
+                        self.pixel_cursor = clicked.unwrap();
+                    }
+                }
+
+                // Or select it with the keyboard, while the window is focused
+                if ui.is_window_focused() {
+                    let step = if ui.imgui().key_shift() { 8 } else { 1 };
+                    let (x, y) = &mut self.pixel_cursor;
+                    let key = |k: usize| ui.imgui().is_key_pressed(k as _);
+                    if key(keys::LEFT) {
+                        *x = x.saturating_sub(step);
+                    }
+                    if key(keys::RIGHT) {
+                        *x += step;
+                    }
+                    if key(keys::UP) {
+                        *y = y.saturating_sub(step);
+                    }
+                    if key(keys::DOWN) {
+                        *y += step;
+                    }
+                    *x = (*x).min(w - 1);
+                    *y = (*y).min(h - 1);
+                    if key(keys::RETURN) {
+                        clicked = Some((*x, *y));
+                    }
+
+                    let (sx, sy) = (reg.0 / w as f32, reg.1 / h as f32);
+                    let cx = pos.0 + *x as f32 * sx;
+                    let cy = pos.1 + *y as f32 * sy;
+                    let c = ImVec4::new(0.98, 0.15, 0.45, 0.8);
+                    ui.get_window_draw_list().add_rect_filled_multicolor(
+                        (cx, cy),
+                        (cx + sx.max(2.0), cy + sy.max(2.0)),
+                        c,
+                        c,
+                        c,
+                        c,
+                    );
+                }
             });
+        if let Some((x, y)) = clicked {
+            self.pixel_query = Some(((x, y), model.query_pixel(x, y)));
+        }
+        self.render_pixel_query(ui);
+        if self.magnifier {
+            // Follow the mouse over the screen, or else the keyboard cursor
+            let center = hovered.unwrap_or(self.pixel_cursor);
+            let raw = model.framebuffer_pixel(center.0, center.1);
+            self.render_magnifier(ui, center, raw);
+        }
 
-        self.dbg.render_main(ui, self.uictx.get_mut());
+        self.dbg.render_main(ui, &mut self.uictx);
+        self.dbg.render_watches(ui, &mut self.uictx, model);
     }
 }
 
+#[cfg(feature = "frontend")]
+impl DebuggerUI {
+    // Render the result of the last pixel query, if any.
+    fn render_pixel_query(&mut self, ui: &Ui<'_>) {
+        let mut opened = self.pixel_query.is_some();
+        if let Some(((x, y), hits)) = &self.pixel_query {
+            ui.window(im_str!("Pixel Query"))
+                .size((450.0, 300.0), ImGuiCond::FirstUseEver)
+                .opened(&mut opened)
+                .build(|| {
+                    ui.text(im_str!("Pixel: {}, {} (last frame)", x, y));
+                    ui.separator();
+                    if hits.is_empty() {
+                        ui.text_disabled(im_str!("No primitives touched this pixel"));
+                    }
+                    for (idx, hit) in hits.iter().enumerate() {
+                        if ui
+                            .collapsing_header(im_str!("{}###pixq#{}", hit.name, idx))
+                            .default_open(idx + 1 == hits.len())
+                            .build()
+                        {
+                            for (label, value) in &hit.details {
+                                ui.bullet_text(im_str!("{}:", label));
+                                ui.same_line(120.0);
+                                ui.text(im_str!("{}", value));
+                            }
+                        }
+                    }
+                });
+        }
+        if !opened {
+            self.pixel_query = None;
+        }
+    }
+
+    // Render a zoomed view of the last frame around a pixel of the screen,
+    // with the raw value of the framebuffer pixel shown there.
+    fn render_magnifier(&mut self, ui: &Ui<'_>, (x, y): (usize, usize), raw: Option<RawPixel>) {
+        let (w, h) = self.screen_size;
+        let pixels = &self.screen_pixels;
+        let size = &mut self.magnifier_size;
+        ui.window(im_str!("Magnifier"))
+            .size((300.0, 420.0), ImGuiCond::FirstUseEver)
+            .opened(&mut self.magnifier)
+            .build(|| {
+                ui.with_item_width(150.0, || {
+                    ui.slider_int(im_str!("Pixels"), size, 4, 64).build();
+                });
+                ui.text(im_str!("Screen: {}, {}", x, y));
+                match raw {
+                    Some(ref raw) => {
+                        let (r, g, b, a) = raw.components();
+                        ui.text(im_str!(
+                            "Framebuffer: {}, {} @ {:08x}",
+                            raw.x,
+                            raw.y,
+                            raw.addr
+                        ));
+                        match raw.format {
+                            PixelFormat::Rgba5551 => ui.text(im_str!("Raw: {:04x}", raw.value)),
+                            PixelFormat::Rgba8888 => ui.text(im_str!("Raw: {:08x}", raw.value)),
+                        }
+                        ui.text(im_str!(
+                            "{}: r={} g={} b={} a={}",
+                            raw.format.name(),
+                            r,
+                            g,
+                            b,
+                            a
+                        ));
+                    }
+                    None => ui.text_disabled(im_str!("No framebuffer pixel")),
+                }
+                ui.separator();
+
+                // One square per pixel, with the selected pixel outlined in
+                // the middle.
+                let n = (*size).max(1) as usize;
+                let avail = ui.get_content_region_avail();
+                let cell = (avail.0.min(avail.1) / n as f32).floor().max(1.0);
+                let pos = ui.get_cursor_screen_pos();
+                let dl = ui.get_window_draw_list();
+                let bg = ImVec4::new(0.15, 0.16, 0.13, 1.0);
+                for j in 0..n {
+                    for i in 0..n {
+                        let px = (x + i).checked_sub(n / 2).filter(|&px| px < w);
+                        let py = (y + j).checked_sub(n / 2).filter(|&py| py < h);
+                        let off = px.and_then(|px| py.map(|py| (py * w + px) * 4));
+                        let c = match off.and_then(|off| pixels.get(off..off + 3)) {
+                            Some(p) => ImVec4::new(
+                                p[0] as f32 / 255.0,
+                                p[1] as f32 / 255.0,
+                                p[2] as f32 / 255.0,
+                                1.0,
+                            ),
+                            None => bg,
+                        };
+                        let (cx, cy) = (pos.0 + i as f32 * cell, pos.1 + j as f32 * cell);
+                        dl.add_rect_filled_multicolor((cx, cy), (cx + cell, cy + cell), c, c, c, c);
+                    }
+                }
+                let (cx, cy) = (pos.0 + (n / 2) as f32 * cell, pos.1 + (n / 2) as f32 * cell);
+                dl.add_rect(
+                    (cx, cy),
+                    (cx + cell, cy + cell),
+                    ImVec4::new(0.98, 0.15, 0.45, 1.0),
+                )
+                .thickness(2.0)
+                .build();
+                ui.invisible_button(im_str!("###magnifier"), (cell * n as f32, cell * n as f32));
+            });
+    }
+}
+
+#[cfg(feature = "frontend")]
+impl Drop for DebuggerUI {
+    fn drop(&mut self) {
+        // Persist the debugging session, so that it is restored next time
+        // the same software is debugged.
+        if let Some(ref id) = self.session_id {
+            let mut session = self.dbg.save_session();
+            session.annotations = self.uictx.annotations.clone();
+            if let Err(err) = session.save(id) {
+                warn!(self.logger, "cannot save debugger session: {}", err);
+            }
+        }
+        match self.save_chrome_trace() {
+            Some(Ok(path)) => info!(self.logger, "chrome trace saved: {}", path.display()),
+            Some(Err(err)) => warn!(self.logger, "cannot save chrome trace: {}", err),
+            None => {}
+        }
+    }
+}
+
+#[cfg(feature = "frontend")]
 extern "C" fn screen_resize_callback(data: *mut ImGuiSizeCallbackData) {
     unsafe {
-        // Constraint the screen window to the ratio of the actual framebuffer
-        // (as stored in the last frame).
+        // Constraint the screen window to the ratio of the picture output
+        // by the emulated hardware in the last frame.
         let screen_size = (*data).user_data as *mut (usize, usize);
         let ratio = ((*screen_size).1 as f32) / ((*screen_size).0 as f32);
         (*data).desired_size.y = (*data).desired_size.x * ratio;
     }
 }
 
+#[cfg(feature = "debugger")]
 pub struct DebuggerRenderer<'a, 'ui> {
     ui: &'a Ui<'ui>,
-    ctx: &'a RefCell<UiCtx>,
+    ctx: &'a mut UiCtx,
 }
 
+#[cfg(feature = "debugger")]
 impl<'a, 'ui> DebuggerRenderer<'a, 'ui> {
-    pub fn render_regview<V: RegisterView>(&self, v: &mut V) {
-        render_regview(self.ui, &mut self.ctx.borrow_mut(), v)
+    pub fn render_regview<V: RegisterView>(&mut self, v: &mut V) {
+        render_regview(self.ui, self.ctx, v)
+    }
+    pub fn render_disasmview<V: DisasmView>(&mut self, v: &mut V) {
+        render_disasmview(self.ui, self.ctx, v)
+    }
+    pub fn render_dualmemview<V: DualMemView>(&mut self, v: &mut V) {
+        render_dualmemview(self.ui, self.ctx, v)
+    }
+    pub fn render_memview<V: MemoryView>(&mut self, v: &mut V) {
+        render_memview(self.ui, self.ctx, v)
+    }
+    pub fn render_packetview<V: PacketView>(&mut self, v: &mut V) {
+        render_packetview(self.ui, self.ctx, v)
+    }
+    pub fn render_audioview<V: AudioView>(&mut self, v: &mut V) {
+        render_audioview(self.ui, self.ctx, v)
+    }
+    pub fn render_videoview<V: VideoView>(&mut self, v: &mut V) {
+        render_videoview(self.ui, self.ctx, v)
+    }
+    pub fn render_heapview<V: HeapView>(&mut self, v: &mut V) {
+        render_heapview(self.ui, self.ctx, v)
+    }
+    pub fn render_storageview<V: StorageView>(&mut self, v: &mut V) {
+        render_storageview(self.ui, self.ctx, v)
     }
-    pub fn render_disasmview<V: DisasmView>(&self, v: &mut V) {
-        render_disasmview(self.ui, &mut self.ctx.borrow_mut(), v)
+    pub fn render_cacheview<V: CacheView>(&mut self, v: &mut V) {
+        render_cacheview(self.ui, self.ctx, v)
     }
 }
@@ -0,0 +1,149 @@
+#[cfg(feature = "debugger")]
+use super::UiCtx;
+#[cfg(feature = "debugger")]
+use imgui::*;
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+/// User annotations (bookmarks and comments) attached to the addresses
+/// of a debugger view. Views are identified by name, so that
+/// annotations can be persisted in the debugger session.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub(crate) struct ViewAnnotations {
+    pub bookmarks: BTreeMap<u64, String>,
+    pub comments: BTreeMap<u64, String>,
+}
+
+#[cfg(feature = "debugger")]
+impl ViewAnnotations {
+    pub(crate) fn bookmark(&self, addr: u64) -> Option<&str> {
+        self.bookmarks.get(&addr).map(|s| s.as_str())
+    }
+
+    pub(crate) fn comment(&self, addr: u64) -> Option<&str> {
+        self.comments.get(&addr).map(|s| s.as_str())
+    }
+
+    // Set (or clear, if empty) the bookmark at the specified address.
+    pub(crate) fn set_bookmark(&mut self, addr: u64, name: &str) {
+        if name.is_empty() {
+            self.bookmarks.remove(&addr);
+        } else {
+            self.bookmarks.insert(addr, name.to_owned());
+        }
+    }
+
+    // Set (or clear, if empty) the comment at the specified address.
+    pub(crate) fn set_comment(&mut self, addr: u64, text: &str) {
+        if text.is_empty() {
+            self.comments.remove(&addr);
+        } else {
+            self.comments.insert(addr, text.to_owned());
+        }
+    }
+}
+
+/// Open the popup to edit the comment of the specified address.
+/// The popup must then be drawn with render_annotation_popups().
+#[cfg(feature = "debugger")]
+pub(crate) fn open_comment_popup(ui: &Ui<'_>, ctx: &mut UiCtx, view: &str, addr: u64) {
+    let text = ctx
+        .annotations
+        .get(view)
+        .and_then(|a| a.comment(addr))
+        .unwrap_or("");
+    ctx.annotation_edit = ImString::with_capacity(256);
+    ctx.annotation_edit.push_str(text);
+    ctx.annotation_addr = addr;
+    ui.open_popup(im_str!("###annotation#comment#{}", view));
+}
+
+/// Open the popup to edit the bookmark of the specified address.
+/// The popup must then be drawn with render_annotation_popups().
+#[cfg(feature = "debugger")]
+pub(crate) fn open_bookmark_popup(ui: &Ui<'_>, ctx: &mut UiCtx, view: &str, addr: u64) {
+    let text = ctx
+        .annotations
+        .get(view)
+        .and_then(|a| a.bookmark(addr))
+        .unwrap_or("");
+    ctx.annotation_edit = ImString::with_capacity(64);
+    ctx.annotation_edit.push_str(text);
+    ctx.annotation_addr = addr;
+    ui.open_popup(im_str!("###annotation#bookmark#{}", view));
+}
+
+/// Draw the popups that edit annotations, and the popup that lists all
+/// bookmarks of a view. Returns the address of the bookmark selected by the
+/// user (if any), so that the view can jump to it.
+#[cfg(feature = "debugger")]
+pub(crate) fn render_annotation_popups(ui: &Ui<'_>, ctx: &mut UiCtx, view: &str) -> Option<u64> {
+    let mut goto = None;
+
+    ui.popup(im_str!("###annotation#comment#{}", view), || {
+        ui.text(im_str!("Comment at {:08x}:", ctx.annotation_addr));
+        if ui
+            .input_text(
+                im_str!("###annotation#comment#input"),
+                &mut ctx.annotation_edit,
+            )
+            .enter_returns_true(true)
+            .auto_select_all(true)
+            .build()
+        {
+            let text = ctx.annotation_edit.to_str().to_owned();
+            ctx.annotations
+                .entry(view.to_owned())
+                .or_default()
+                .set_comment(ctx.annotation_addr, &text);
+            ui.close_current_popup();
+        }
+    });
+
+    ui.popup(im_str!("###annotation#bookmark#{}", view), || {
+        ui.text(im_str!("Bookmark at {:08x}:", ctx.annotation_addr));
+        if ui
+            .input_text(
+                im_str!("###annotation#bookmark#input"),
+                &mut ctx.annotation_edit,
+            )
+            .enter_returns_true(true)
+            .auto_select_all(true)
+            .build()
+        {
+            let text = ctx.annotation_edit.to_str().to_owned();
+            ctx.annotations
+                .entry(view.to_owned())
+                .or_default()
+                .set_bookmark(ctx.annotation_addr, &text);
+            ui.close_current_popup();
+        }
+    });
+
+    ui.popup(im_str!("###annotation#list#{}", view), || {
+        match ctx.annotations.get(view) {
+            Some(a) if !a.bookmarks.is_empty() => {
+                for (addr, name) in &a.bookmarks {
+                    if ui.selectable(
+                        im_str!("{:08x}  {}", addr, name),
+                        false,
+                        ImGuiSelectableFlags::empty(),
+                        (0.0, 0.0),
+                    ) {
+                        goto = Some(*addr);
+                    }
+                }
+            }
+            _ => ui.text(im_str!("No bookmarks")),
+        };
+    });
+
+    goto
+}
+
+/// Open the popup that lists all bookmarks of the view.
+#[cfg(feature = "debugger")]
+pub(crate) fn open_bookmark_list(ui: &Ui<'_>, view: &str) {
+    ui.open_popup(im_str!("###annotation#list#{}", view));
+}
@@ -0,0 +1,91 @@
+#[cfg(feature = "debugger")]
+use imgui::*;
+
+#[cfg(feature = "debugger")]
+use super::UiCtx;
+
+/// A DMA buffer queued in an audio device.
+pub struct AudioDma {
+    pub addr: u64,      // Current source address
+    pub len: u64,       // Total length of the buffer (in bytes)
+    pub remaining: u64, // Bytes still to be played
+    pub playing: bool,  // True if this is the buffer currently being played
+}
+
+/// A trait for an audio device that plays back samples through a queue of
+/// DMA buffers.
+pub trait AudioView {
+    /// Return the name of this object. The name will be composed
+    /// as "\[NAME\] Audio".
+    fn name(&self) -> &str;
+
+    /// Return the state of all the DMA slots of the device; None means
+    /// that the slot is empty.
+    fn dma_queue(&self) -> Vec<Option<AudioDma>>;
+
+    /// Visit additional information about the device state (eg: sample rate),
+    /// as pairs of labels and values.
+    fn visit_info<F: FnMut(&str, String)>(&self, visit: F);
+
+    /// Return the last emitted samples, as interleaved stereo 16-bit samples.
+    fn last_samples(&self) -> &[i16];
+}
+
+#[cfg(feature = "debugger")]
+pub(crate) fn render_audioview<'a, 'ui, AV: AudioView>(
+    ui: &'a Ui<'ui>,
+    _ctx: &mut UiCtx,
+    v: &mut AV,
+) {
+    ui.window(im_str!("[{}] Audio", v.name()))
+        .size((400.0, 300.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            v.visit_info(|label, value| {
+                ui.text(im_str!("{}:", label));
+                ui.same_line(140.0);
+                ui.text(im_str!("{}", value));
+            });
+            ui.separator();
+
+            ui.text(im_str!("DMA queue:"));
+            for (idx, slot) in v.dma_queue().iter().enumerate() {
+                match slot {
+                    Some(dma) => {
+                        let text = ImString::new(format!(
+                            "{} {:08x} len:{:x} remaining:{:x}",
+                            idx, dma.addr, dma.len, dma.remaining
+                        ));
+                        if dma.playing {
+                            ui.text_colored((0.65, 0.88, 0.18, 1.0), &text);
+                        } else {
+                            ui.text(&text);
+                        }
+                        if dma.len != 0 {
+                            ui.progress_bar(1.0 - dma.remaining as f32 / dma.len as f32)
+                                .size((-1.0, 4.0))
+                                .overlay_text(im_str!(""))
+                                .build();
+                        }
+                    }
+                    None => ui.text_disabled(im_str!("{} <empty>", idx)),
+                }
+            }
+            ui.separator();
+
+            // Waveform of the last samples, one plot per channel
+            let samples = v.last_samples();
+            for (ch, name) in ["Left", "Right"].iter().enumerate() {
+                let values: Vec<f32> = samples
+                    .iter()
+                    .skip(ch)
+                    .step_by(2)
+                    .map(|s| *s as f32)
+                    .collect();
+                ui.plot_lines(im_str!("{}", name), &values)
+                    .scale_min(i16::MIN as f32)
+                    .scale_max(i16::MAX as f32)
+                    .graph_size((0.0, 60.0))
+                    .build();
+            }
+        });
+}
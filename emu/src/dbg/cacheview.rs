@@ -0,0 +1,76 @@
+#[cfg(feature = "debugger")]
+use imgui::*;
+
+#[cfg(feature = "debugger")]
+use super::UiCtx;
+
+/// Statistics of a cache of decoded code.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,          // Lookups that found a decoded block
+    pub misses: u64,        // Lookups that had to decode a block
+    pub invalidations: u64, // Blocks dropped because their code was written
+    pub blocks: u64,        // Blocks currently cached
+    pub insns: u64,         // Instructions in the cached blocks
+}
+
+impl CacheStats {
+    /// Return the ratio of lookups that hit the cache (between 0 and 1).
+    pub fn hit_ratio(&self) -> f32 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f32 / lookups as f32
+    }
+}
+
+/// A trait for a CPU that executes code through a cache of decoded blocks,
+/// whose efficiency can be monitored from the debugger.
+pub trait CacheView {
+    /// Return the name of this object. The name will be composed
+    /// as "\[NAME\] Code cache".
+    fn name(&self) -> &str;
+
+    /// Return the current statistics.
+    fn cache_stats(&self) -> CacheStats;
+
+    /// Reset the counters of the statistics (eg: to measure a specific scene).
+    fn reset_cache_stats(&mut self);
+}
+
+#[cfg(feature = "debugger")]
+pub(crate) fn render_cacheview<'a, 'ui, CV: CacheView>(
+    ui: &'a Ui<'ui>,
+    _ctx: &mut UiCtx,
+    v: &mut CV,
+) {
+    ui.window(im_str!("[{}] Code cache", v.name()))
+        .size((300.0, 170.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            let stats = v.cache_stats();
+            let rows = [
+                ("Hits", stats.hits),
+                ("Misses", stats.misses),
+                ("Invalidations", stats.invalidations),
+                ("Blocks", stats.blocks),
+                ("Instructions", stats.insns),
+            ];
+            for (label, value) in rows.iter() {
+                ui.text(im_str!("{}:", label));
+                ui.same_line(140.0);
+                ui.text(im_str!("{}", value));
+            }
+
+            ui.text(im_str!("Hit ratio:"));
+            ui.same_line(140.0);
+            ui.progress_bar(stats.hit_ratio())
+                .size((-1.0, 0.0))
+                .overlay_text(im_str!("{:.2}%", stats.hit_ratio() * 100.0))
+                .build();
+
+            if ui.small_button(im_str!("Reset")) {
+                v.reset_cache_stats();
+            }
+        });
+}
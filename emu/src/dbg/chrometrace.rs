@@ -0,0 +1,324 @@
+use crate::clock::ClockDomain;
+use serde_json::json;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Maximum number of events kept in a capture; after that, the capture is
+/// truncated (to avoid exhausting memory if it is left running).
+const MAX_EVENTS: usize = 4_000_000;
+
+// Process ID used for all the events; the whole emulator is shown as a
+// single process, with one thread per track.
+const PID: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Category {
+    Frame,     // Emulated frames
+    Scheduler, // Time slices for which a subsystem was run by the scheduler
+    Activity,  // Activity tracks (as in the frame graph), including DMA channels
+    Signal,    // Timeline signals, exported as counters
+}
+
+impl Category {
+    fn name(self) -> &'static str {
+        match self {
+            Category::Frame => "frame",
+            Category::Scheduler => "scheduler",
+            Category::Activity => "activity",
+            Category::Signal => "signal",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Slice(i64), // Span of time, until the specified cycle
+    Counter(u64),
+}
+
+// A single recorded event. Timestamps are in cycles of the main clock.
+struct Event {
+    track: usize,
+    ts: i64,
+    kind: Kind,
+    label: Option<String>,
+}
+
+struct Track {
+    name: String,
+    cat: Category,
+    last: Option<usize>, // index of the last event of the track
+    marked: i64,         // position of the last scanline marked as active
+}
+
+/// A capture of tracer events in the Chrome trace event format, that can be
+/// loaded into chrome://tracing or the Perfetto UI to explore the timeline
+/// of frames with zoom and search.
+///
+/// The capture contains the frames, the time slices run by the scheduler for
+/// each subsystem, the activity tracks of the frame graph (RSP tasks, DMA
+/// channels, interrupts, etc.) and the timeline signals (as counters).
+/// Activities and signals are recorded at scanline resolution.
+pub struct ChromeTrace {
+    clock: Option<ClockDomain>,
+    tracks: Vec<Track>,
+    events: Vec<Event>,
+    frame: Option<i64>,
+    pos: i64,         // cycles at the beginning of the current scanline
+    prev_pos: i64,    // cycles at the beginning of the previous scanline
+    line_cycles: i64, // length of a scanline in cycles
+    truncated: bool,
+}
+
+impl Default for ChromeTrace {
+    fn default() -> Self {
+        Self {
+            clock: None,
+            tracks: Vec::new(),
+            events: Vec::new(),
+            frame: None,
+            pos: 0,
+            prev_pos: i64::MIN,
+            line_cycles: 0,
+            truncated: false,
+        }
+    }
+}
+
+impl ChromeTrace {
+    fn track(&mut self, name: &str, cat: Category) -> usize {
+        match self
+            .tracks
+            .iter()
+            .position(|t| t.cat == cat && t.name == name)
+        {
+            Some(idx) => idx,
+            None => {
+                self.tracks.push(Track {
+                    name: name.to_owned(),
+                    cat,
+                    last: None,
+                    marked: i64::MIN,
+                });
+                self.tracks.len() - 1
+            }
+        }
+    }
+
+    fn push(&mut self, track: usize, ts: i64, kind: Kind, label: Option<String>) {
+        if self.events.len() >= MAX_EVENTS {
+            self.truncated = true;
+            return;
+        }
+        self.tracks[track].last = Some(self.events.len());
+        self.events.push(Event {
+            track,
+            ts,
+            kind,
+            label,
+        });
+    }
+
+    // Return the end of the last slice of the track (if any), so that it
+    // can be extended.
+    fn last_slice_end(&mut self, track: usize) -> Option<&mut i64> {
+        let idx = self.tracks[track].last?;
+        match &mut self.events[idx].kind {
+            Kind::Slice(end) => Some(end),
+            _ => None,
+        }
+    }
+
+    /// Return the number of recorded events.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // Update the current emulation position; called once per scanline.
+    pub(crate) fn set_position(&mut self, frame: i64, cycles: i64) {
+        if cycles > self.pos {
+            self.line_cycles = cycles - self.pos;
+        }
+        self.prev_pos = self.pos;
+        self.pos = cycles;
+
+        // Extend the current frame until the current position, and open
+        // a new one if the frame changed.
+        let track = self.track("Frames", Category::Frame);
+        if let Some(end) = self.last_slice_end(track) {
+            *end = cycles;
+        }
+        if self.frame != Some(frame) {
+            self.frame = Some(frame);
+            let label = Some(format!("Frame {}", frame));
+            self.push(track, cycles, Kind::Slice(cycles), label);
+        }
+    }
+
+    // Record a time slice during which the scheduler ran a subsystem.
+    // start and end are in cycles of the specified clock (the main clock).
+    pub(crate) fn slice(&mut self, name: &str, clock: &ClockDomain, start: i64, end: i64) {
+        if self.clock.is_none() {
+            self.clock = Some(*clock);
+        }
+        let track = self.track(name, Category::Scheduler);
+        self.push(track, start, Kind::Slice(end), None);
+    }
+
+    // Mark an activity track as active for the current scanline, extending
+    // the previous span if the track was also active on the previous line.
+    pub(crate) fn mark(&mut self, name: &str) {
+        let (pos, end) = (self.pos, self.pos + self.line_cycles);
+        let track = self.track(name, Category::Activity);
+        let marked = std::mem::replace(&mut self.tracks[track].marked, pos);
+        if marked == pos {
+            return;
+        }
+        if marked == self.prev_pos {
+            if let Some(last) = self.last_slice_end(track) {
+                *last = end;
+                return;
+            }
+        }
+        self.push(track, pos, Kind::Slice(end), None);
+    }
+
+    // Record a sample of a signal; only changes of value are recorded.
+    pub(crate) fn record(&mut self, signal: &str, value: u64) {
+        let pos = self.pos;
+        let track = self.track(signal, Category::Signal);
+        if let Some(idx) = self.tracks[track].last {
+            if let Kind::Counter(last) = self.events[idx].kind {
+                if last == value {
+                    return;
+                }
+            }
+        }
+        self.push(track, pos, Kind::Counter(value), None);
+    }
+
+    /// Write the capture as JSON (Chrome trace event format).
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        // Without scheduler events, the clock is unknown: use one cycle
+        // per microsecond, so that the timeline is still meaningful.
+        let hz = self.clock.map_or(1_000_000.0, |c| c.hz());
+        let us = |cycles: i64| cycles as f64 * 1_000_000.0 / hz;
+
+        let mut events = vec![json!({
+            "name": "process_name", "ph": "M", "pid": PID,
+            "args": {"name": "r64emu"},
+        })];
+        for (tid, t) in self.tracks.iter().enumerate() {
+            if t.cat == Category::Signal {
+                continue; // counters are not displayed as threads
+            }
+            events.push(json!({
+                "name": "thread_name", "ph": "M", "pid": PID, "tid": tid,
+                "args": {"name": t.name},
+            }));
+            events.push(json!({
+                "name": "thread_sort_index", "ph": "M", "pid": PID, "tid": tid,
+                "args": {"sort_index": t.cat as usize * 1000 + tid},
+            }));
+        }
+        if self.truncated {
+            events.push(json!({
+                "name": "capture truncated", "ph": "i", "s": "g", "pid": PID,
+                "ts": self.events.last().map_or(0.0, |e| us(e.ts)),
+            }));
+        }
+
+        w.write_all(b"{\"displayTimeUnit\":\"ns\",\"traceEvents\":[\n")?;
+        let mut first = true;
+        let meta = events.iter();
+        let recorded = self.events.iter().map(|evt| {
+            let t = &self.tracks[evt.track];
+            let (name, cat) = (evt.label.as_ref().unwrap_or(&t.name), t.cat);
+            match evt.kind {
+                Kind::Slice(end) => json!({
+                    "name": name, "cat": cat.name(), "ph": "X", "pid": PID,
+                    "tid": evt.track, "ts": us(evt.ts), "dur": us(end - evt.ts),
+                }),
+                Kind::Counter(value) => json!({
+                    "name": name, "cat": cat.name(), "ph": "C", "pid": PID,
+                    "ts": us(evt.ts), "args": {"value": value},
+                }),
+            }
+        });
+        for evt in meta.cloned().chain(recorded) {
+            if !first {
+                w.write_all(b",\n")?;
+            }
+            first = false;
+            serde_json::to_writer(&mut w, &evt)?;
+        }
+        w.write_all(b"\n]}\n")?;
+        Ok(())
+    }
+
+    /// Save the capture into the specified file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn export() {
+        let clock = ClockDomain::new("main", 1_000_000);
+        let mut ct = ChromeTrace::default();
+        for line in 0..4 {
+            ct.set_position(0, line * 10);
+            ct.slice("CPU", &clock, line * 10, line * 10 + 12);
+            if line != 2 {
+                ct.mark("DMA");
+            }
+            ct.record("IRQ", (line / 2) as u64);
+        }
+        ct.set_position(1, 40);
+
+        let mut out = Vec::new();
+        ct.write(&mut out).unwrap();
+        let v: Value = serde_json::from_slice(&out).unwrap();
+        let evts: Vec<&Value> = v["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["ph"] != "M")
+            .collect();
+        let find = |name: &str| -> Vec<(f64, f64)> {
+            evts.iter()
+                .filter(|e| e["name"] == name)
+                .map(|e| {
+                    (
+                        e["ts"].as_f64().unwrap(),
+                        e["dur"]
+                            .as_f64()
+                            .unwrap_or(e["args"]["value"].as_f64().unwrap_or(0.0)),
+                    )
+                })
+                .collect()
+        };
+
+        assert_eq!(find("Frame 0"), vec![(0.0, 40.0)]);
+        assert_eq!(find("Frame 1"), vec![(40.0, 0.0)]);
+        assert_eq!(find("CPU").len(), 4);
+        assert_eq!(find("CPU")[1], (10.0, 12.0));
+        // DMA active on lines 0-1 and 3
+        assert_eq!(find("DMA"), vec![(0.0, 20.0), (30.0, 10.0)]);
+        // Only changes of value are recorded
+        assert_eq!(find("IRQ"), vec![(0.0, 0.0), (20.0, 1.0)]);
+    }
+}
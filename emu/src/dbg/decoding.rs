@@ -1,7 +1,6 @@
 /// Helper classes to write a CPU decoder / disassembler.
-use runtime_fmt::rt_format_args;
-
 use std::fmt;
+use std::fmt::Write;
 
 const MAX_OPERANDS_PER_INSN: usize = 4;
 
@@ -95,12 +94,10 @@ where
 {
     fn is_hidden(self) -> bool {
         use self::Operand::*;
-        match self {
-            HidIReg(_) | HidOReg(_) => true,
-            _ => false,
-        }
+        matches!(self, HidIReg(_) | HidOReg(_))
     }
 
+    #[allow(dead_code)]
     fn input(self) -> Option<Reg> {
         use self::Operand::*;
         match self {
@@ -109,6 +106,7 @@ where
         }
     }
 
+    #[allow(dead_code)]
     fn output(self) -> Option<Reg> {
         use self::Operand::*;
         match self {
@@ -186,7 +184,7 @@ where
         op
     }
 
-    pub fn with_fmt(mut self: Self, fmt: &str) -> Self {
+    pub fn with_fmt(mut self, fmt: &str) -> Self {
         self.fmt = Some(fmt.to_owned());
         self
     }
@@ -202,25 +200,11 @@ where
         let args = self.args().filter(|o| !o.is_hidden()).collect::<Vec<_>>();
 
         if let Some(ref f) = self.fmt {
-            // Custom formatting strings. Use rt_format
-            match args.len() {
-                4 => rt_format_args!(f, args[0], args[1], args[2], args[3])
-                    .unwrap_or(rt_format_args!("<INVALID ARGUMENTS>").unwrap())
-                    .with(|args| format!("{}\t{}", self.op, args)),
-                3 => rt_format_args!(f, args[0], args[1], args[2])
-                    .unwrap_or(rt_format_args!("<INVALID ARGUMENTS>").unwrap())
-                    .with(|args| format!("{}\t{}", self.op, args)),
-                2 => rt_format_args!(f, args[0], args[1])
-                    .unwrap_or(rt_format_args!("<INVALID ARGUMENTS>").unwrap())
-                    .with(|args| format!("{}\t{}", self.op, args)),
-                1 => rt_format_args!(f, args[0])
-                    .unwrap_or(rt_format_args!("<INVALID ARGUMENTS>").unwrap())
-                    .with(|args| format!("{}\t{}", self.op, args)),
-                0 => rt_format_args!(f)
-                    .unwrap_or(rt_format_args!("<INVALID ARGUMENTS>").unwrap())
-                    .with(|args| format!("{}\t{}", self.op, args)),
-                _ => unreachable!(),
-            }
+            // Custom formatting strings, whose "{}" are replaced by the
+            // arguments in order.
+            let args =
+                format_operands(f, &args).unwrap_or_else(|| "<INVALID ARGUMENTS>".to_owned());
+            format!("{}\t{}", self.op, args)
         } else {
             // Standard formatting with commas. Use compile-time formatting.
             match args.len() {
@@ -237,3 +221,52 @@ where
         }
     }
 }
+
+// Format the operands with a custom formatting string, in which each "{}"
+// is replaced by the next operand ("{{" and "}}" are escapes). Return None
+// if the string does not use exactly all the operands.
+fn format_operands<T: fmt::Display>(fmt: &str, args: &[T]) -> Option<String> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => match chars.next() {
+                Some('{') => out.push('{'),
+                Some('}') => write!(out, "{}", args.next()?).ok()?,
+                _ => return None,
+            },
+            '}' => match chars.next() {
+                Some('}') => out.push('}'),
+                _ => return None,
+            },
+            c => out.push(c),
+        }
+    }
+    if args.next().is_some() {
+        return None;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_format() {
+        let insn: DecodedInsn<&str, &str> = DecodedInsn::new3(
+            "lw",
+            Operand::OReg("t0"),
+            Operand::Imm32(4),
+            Operand::IReg("sp"),
+        )
+        .with_fmt("{},{}({})");
+        assert_eq!(insn.disasm(), "lw\tt0,0x4(sp)");
+
+        assert_eq!(format_operands("{{{}}}", &[1]), Some("{1}".to_owned()));
+        assert_eq!(format_operands("{},{}", &[1]), None);
+        assert_eq!(format_operands("{}", &[1, 2]), None);
+        assert_eq!(format_operands("{:x}", &[1]), None);
+    }
+}
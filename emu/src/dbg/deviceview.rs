@@ -0,0 +1,49 @@
+use crate::bus::DeviceInfo;
+use imgui::*;
+
+// Render the list of the devices of the emulated machine, with their
+// registers (and current values) and memory regions. Addresses are offsets
+// within each bank, as the same bank can be mapped at different addresses.
+pub(crate) fn render_deviceview(ui: &Ui<'_>, devices: &[DeviceInfo]) {
+    ui.window(im_str!("Devices"))
+        .size((360.0, 400.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            for dev in devices {
+                let title = match dev.cpu {
+                    Some(ref cpu) => im_str!("{} (CPU: {})###dev#{}", dev.name, cpu, dev.name),
+                    None => im_str!("{}###dev#{}", dev.name, dev.name),
+                };
+                if !ui.collapsing_header(title).build() {
+                    continue;
+                }
+                if dev.regs.is_empty() && dev.mems.is_empty() {
+                    ui.text_disabled(im_str!("No registers or memories"));
+                    continue;
+                }
+
+                ui.columns(3, im_str!("devices#{}", dev.name), true);
+                for m in &dev.mems {
+                    ui.text(im_str!("{}", m.name));
+                    ui.next_column();
+                    ui.text(im_str!(
+                        "{}:{:04x}-{:04x}",
+                        m.bank,
+                        m.offset,
+                        m.offset + m.vsize.max(1) - 1
+                    ));
+                    ui.next_column();
+                    ui.text_disabled(im_str!("{} bytes", m.size));
+                    ui.next_column();
+                }
+                for r in &dev.regs {
+                    ui.text(im_str!("{}", r.name));
+                    ui.next_column();
+                    ui.text(im_str!("{}:{:04x}", r.bank, r.offset));
+                    ui.next_column();
+                    ui.text(im_str!("{:01$x}", r.value, r.size * 2));
+                    ui.next_column();
+                }
+                ui.columns(1, im_str!("devices#{}#end", dev.name), false);
+            }
+        });
+}
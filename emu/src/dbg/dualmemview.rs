@@ -0,0 +1,288 @@
+#[cfg(feature = "debugger")]
+use imgui::*;
+#[cfg(feature = "debugger")]
+use imgui_sys;
+
+#[cfg(feature = "debugger")]
+use super::uisupport::*;
+#[cfg(feature = "debugger")]
+use super::{
+    new_view_instance, open_bookmark_list, open_bookmark_popup, open_comment_popup,
+    render_annotation_popups, view_instances, UiCtx, ViewAnnotations,
+};
+
+/// The kind of overlay drawn on top of a memory range in a dual memory view.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MemHighlight {
+    DmaSource, // Memory being read by a DMA transfer
+    DmaTarget, // Memory being written by a DMA transfer
+    Pc,        // Instruction currently being executed
+}
+
+#[cfg(feature = "debugger")]
+impl MemHighlight {
+    fn color(self) -> ImVec4 {
+        match self {
+            MemHighlight::DmaSource => color(102, 217, 239),
+            MemHighlight::DmaTarget => color(249, 38, 114),
+            MemHighlight::Pc => color(165, 224, 46),
+        }
+    }
+
+    fn legend(self) -> &'static str {
+        match self {
+            MemHighlight::DmaSource => "DMA source",
+            MemHighlight::DmaTarget => "DMA target",
+            MemHighlight::Pc => "PC",
+        }
+    }
+}
+
+/// A trait for an object that can display two related memory buffers
+/// side by side (eg: data and instruction memory of a coprocessor),
+/// overlaying the ranges currently involved in DMA transfers and
+/// the program counter.
+pub trait DualMemView {
+    /// Return the name of this object. The name will be composed
+    /// as "\[NAME\] Memory".
+    fn name(&self) -> &str;
+
+    /// Return the name and the contents of one of the two memory buffers.
+    /// idx is 0 for the left buffer, and 1 for the right buffer.
+    fn mem(&self, idx: usize) -> (&str, &[u8]);
+
+    /// Visit all the ranges that must be highlighted. The callback receives
+    /// the index of the buffer, the range of offsets within the buffer
+    /// (start inclusive, end exclusive), and the kind of highlight.
+    fn visit_highlights<F: FnMut(usize, (usize, usize), MemHighlight)>(&self, visit: F);
+}
+
+#[cfg(feature = "debugger")]
+fn color(r: usize, g: usize, b: usize) -> ImVec4 {
+    ImVec4::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0)
+}
+
+#[cfg(feature = "debugger")]
+const BYTES_PER_LINE: usize = 16;
+
+#[cfg(feature = "debugger")]
+pub(crate) fn render_dualmemview<'a, 'ui, MV: DualMemView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    v: &mut MV,
+) {
+    // Collect highlights once per frame; they are shared by all columns
+    // and windows.
+    let mut highlights: Vec<(usize, (usize, usize), MemHighlight)> = Vec::new();
+    v.visit_highlights(|idx, range, kind| highlights.push((idx, range, kind)));
+
+    let view_name = v.name().to_owned();
+    for inst in view_instances(&mut ctx.dualmem, &view_name) {
+        render_dualmem_instance(ui, ctx, v, &highlights, inst);
+    }
+}
+
+#[cfg(feature = "debugger")]
+fn render_dualmem_instance<'a, 'ui, MV: DualMemView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    v: &mut MV,
+    highlights: &[(usize, (usize, usize), MemHighlight)],
+    inst: usize,
+) {
+    let view_name = v.name().to_owned();
+    let key = (view_name.clone(), inst);
+
+    let title = match inst {
+        0 => im_str!("[{}] Memory", view_name),
+        _ => im_str!("[{}] Memory #{}", view_name, inst + 1),
+    };
+    let mut opened = true;
+    let mut window = ui
+        .window(title)
+        .size((760.0, 400.0), ImGuiCond::FirstUseEver);
+    if inst != 0 {
+        window = window.opened(&mut opened);
+    }
+    window.build(|| {
+        // Legend
+        for (i, kind) in [
+            MemHighlight::DmaSource,
+            MemHighlight::DmaTarget,
+            MemHighlight::Pc,
+        ]
+        .iter()
+        .enumerate()
+        {
+            if i != 0 {
+                ui.same_line(0.0);
+            }
+            ui.text_colored(kind.color(), im_str!("[{}]", kind.legend()));
+        }
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("New window")) {
+            // Open another window at the same lines
+            let cursor = ctx.dualmem[&key].cursor;
+            let state = new_view_instance(&mut ctx.dualmem, &view_name);
+            state.cursor = cursor;
+            state.anchor = cursor;
+            state.force_line = cursor;
+        }
+        ui.separator();
+
+        ui.columns(2, im_str!("columns"), true);
+        for idx in 0..2 {
+            let (name, mem) = v.mem(idx);
+            // Annotations are attached to each buffer separately
+            let ann_name = format!("{}:{}", view_name, name);
+
+            if let Some(addr) = render_annotation_popups(ui, ctx, &ann_name) {
+                let line = addr as usize / BYTES_PER_LINE;
+                let state = ctx.dualmem.get_mut(&key).unwrap();
+                state.cursor[idx] = Some(line);
+                state.anchor[idx] = Some(line);
+                state.force_line[idx] = Some(line);
+            }
+
+            ui.text(im_str!("{}", name));
+            let cursor = ctx.dualmem[&key].cursor[idx];
+            if let Some(line) = cursor {
+                let addr = (line * BYTES_PER_LINE) as u64;
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Comment###comment{}", idx)) {
+                    open_comment_popup(ui, ctx, &ann_name, addr);
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Bookmark###bookmark{}", idx)) {
+                    open_bookmark_popup(ui, ctx, &ann_name, addr);
+                }
+            }
+
+            // Selected range of lines (inclusive), between the anchor and the cursor.
+            let anchor = ctx.dualmem[&key].anchor[idx];
+            let selection = cursor.map(|c| {
+                let a = anchor.unwrap_or(c);
+                (c.min(a), c.max(a))
+            });
+            if let Some((first, last)) = selection {
+                let start = first * BYTES_PER_LINE;
+                let end = ((last + 1) * BYTES_PER_LINE).min(mem.len());
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Copy###copy{}", idx)) {
+                    set_clipboard_text(&hexdump(start as u64, &mem[start..end]));
+                    ctx.add_flash_msg(&format!("Copied {} bytes", end - start));
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Dump...###dump{}", idx)) {
+                    ctx.dump_filename = ImString::with_capacity(256);
+                    ctx.dump_filename
+                        .push_str(&format!("{}-{:04x}-{:04x}.bin", name, start, end));
+                    ui.open_popup(im_str!("###dump{}", idx));
+                }
+                ui.popup(im_str!("###dump{}", idx), || {
+                    ui.text(im_str!("Dump {:04x}-{:04x} to file:", start, end));
+                    if ui
+                        .input_text(im_str!("###dump#input"), &mut ctx.dump_filename)
+                        .enter_returns_true(true)
+                        .auto_select_all(true)
+                        .build()
+                    {
+                        let fname = ctx.dump_filename.to_str().to_owned();
+                        match std::fs::write(&fname, &mem[start..end]) {
+                            Ok(()) => ctx.add_flash_msg(&format!("Saved to {}", fname)),
+                            Err(err) => {
+                                ctx.add_flash_msg(&format!("Cannot write {}:\n{}", fname, err))
+                            }
+                        }
+                        ui.close_current_popup();
+                    }
+                });
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Bookmarks...###bookmarks{}", idx)) {
+                open_bookmark_list(ui, &ann_name);
+            }
+
+            let no_annotations = ViewAnnotations::default();
+            let annotations = ctx.annotations.get(&ann_name).unwrap_or(&no_annotations);
+            let force_line = ctx.dualmem.get_mut(&key).unwrap().force_line[idx].take();
+            let mut clicked = None;
+
+            ui.child_frame(im_str!("###scrolling{}", idx), (0.0, 0.0))
+                .always_show_vertical_scroll_bar(true)
+                .build(|| {
+                    let row_height = ui.get_text_line_height_with_spacing();
+                    if let Some(line) = force_line {
+                        unsafe {
+                            imgui_sys::igSetScrollY(row_height * line.saturating_sub(4) as f32);
+                        }
+                    }
+
+                    let num_lines = mem.len().div_ceil(BYTES_PER_LINE);
+                    ImGuiListClipper::new(num_lines).build(|start, end| {
+                        for line in start as usize..end as usize {
+                            let off = line * BYTES_PER_LINE;
+
+                            // Highlight the selected lines
+                            if selection.is_some_and(|(f, l)| line >= f && line <= l) {
+                                let wsize = ui.get_content_region_avail();
+                                let dl = ui.get_window_draw_list();
+                                let pos = ui.get_cursor_screen_pos();
+                                let end = (pos.0 + wsize.0, pos.1 + 15.0);
+                                let c1 = color(151, 39, 77);
+                                dl.add_rect_filled_multicolor(pos, end, c1, c1, c1, c1);
+                            }
+
+                            let addr_color = match annotations.bookmark(off as u64) {
+                                Some(_) => color(253, 151, 31),
+                                None => color(174, 129, 255),
+                            };
+                            ui.text_colored(addr_color, im_str!("{:04x}", off));
+                            if ui.is_item_hovered() {
+                                if let Some(name) = annotations.bookmark(off as u64) {
+                                    ui.tooltip_text(im_str!("{}", name));
+                                }
+                                if ui.imgui().is_mouse_clicked(ImMouseButton::Left) {
+                                    clicked = Some(line);
+                                }
+                            }
+
+                            for (i, byte) in mem[off..].iter().take(BYTES_PER_LINE).enumerate() {
+                                let addr = off + i;
+                                let hl = highlights.iter().find(|(hidx, range, _)| {
+                                    *hidx == idx && addr >= range.0 && addr < range.1
+                                });
+
+                                ui.same_line(50.0 + i as f32 * 20.0);
+                                match hl {
+                                    Some((_, _, kind)) => {
+                                        ui.text_colored(kind.color(), im_str!("{:02x}", byte))
+                                    }
+                                    None => {
+                                        ui.text_colored(color(102, 99, 83), im_str!("{:02x}", byte))
+                                    }
+                                }
+                            }
+
+                            if let Some(text) = annotations.comment(off as u64) {
+                                ui.same_line(50.0 + BYTES_PER_LINE as f32 * 20.0 + 10.0);
+                                ui.text_colored(color(117, 113, 94), im_str!("; {}", text));
+                            }
+                        }
+                    });
+                });
+
+            if let Some(line) = clicked {
+                let state = ctx.dualmem.get_mut(&key).unwrap();
+                if !ui.imgui().key_shift() || state.cursor[idx].is_none() {
+                    state.anchor[idx] = Some(line);
+                }
+                state.cursor[idx] = Some(line);
+            }
+            ui.next_column();
+        }
+    });
+    if !opened {
+        ctx.dualmem.remove(&key);
+    }
+}
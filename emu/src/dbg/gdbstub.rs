@@ -0,0 +1,509 @@
+//! GDB remote protocol stub.
+//!
+//! [`GdbStub`](struct.GdbStub.html) listens on a TCP port for a remote
+//! debugger speaking the GDB remote serial protocol (eg: `gdb-multiarch`,
+//! IDA), and translates its requests into operations on the
+//! [`Debugger`](struct.Debugger.html): breakpoints and watchpoints are
+//! installed as for the imgui debugger, and stops are reported from the
+//! same [`TraceEvent`](enum.TraceEvent.html)s.
+//!
+//! Each CPU of the emulator is exposed as a thread, numbered from 1 in the
+//! order of `DebuggerModel::all_cpus` (eg: 1 for the main CPU, 2 for the
+//! RSP). Registers are sent in the layout of the MIPS target of GDB, so the
+//! architecture must be selected before connecting:
+//!
+//! ```text
+//! (gdb) set architecture mips:4300
+//! (gdb) target remote localhost:9123
+//! ```
+//!
+//! Only reading registers and memory is supported: writes are refused.
+
+use super::{Debugger, DebuggerModel, TraceEvent, WatchpointType};
+use slog::warn;
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+// Description of the breakpoints and watchpoints installed by the remote
+// debugger, to tell them apart from the ones of the user.
+const DESCRIPTION: &str = "gdb";
+
+// Maximum size of a packet, as advertised to the remote debugger.
+const PACKET_SIZE: usize = 0x1000;
+
+// Registers sent in the "g" packet, in the order of the MIPS target of GDB
+// (the floating point registers that follow are omitted), as named in the
+// register views. Registers that the model doesn't expose are reported as
+// unavailable.
+const REGISTERS: [&str; 38] = [
+    "zr", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra",
+    "Status", "lo", "hi", "BadVAddr", "Cause", "pc",
+];
+
+// Signals reported in the stop replies.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// A request of the remote debugger that must be carried out by the
+/// emulation loop.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GdbRequest {
+    Continue,     // resume the emulation
+    Step(String), // execute a single instruction of the CPU (name)
+    Interrupt,    // stop the emulation
+}
+
+// Data received from the remote debugger.
+#[derive(Debug, PartialEq)]
+enum Incoming {
+    Packet(String), // a packet with a valid checksum
+    BadPacket,      // a packet with an invalid checksum
+    Interrupt,      // Ctrl-C
+}
+
+pub struct GdbStub {
+    logger: slog::Logger,
+    listener: TcpListener,
+    conn: Option<TcpStream>,
+    rxbuf: Vec<u8>, // data received but not processed yet
+    cpus: Vec<String>,
+    gcpu: usize,   // CPU selected for registers, memory and breakpoints (Hg)
+    ccpu: usize,   // CPU selected for stepping (Hc)
+    running: bool, // the remote debugger is waiting for a stop reply
+}
+
+impl GdbStub {
+    /// Listen for a remote debugger on the specified address, exposing the
+    /// specified CPUs. Connection errors are reported through the logger.
+    pub fn listen<A: ToSocketAddrs>(
+        addr: A,
+        cpus: &[String],
+        logger: slog::Logger,
+    ) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            logger,
+            listener,
+            conn: None,
+            rxbuf: Vec::new(),
+            cpus: cpus.to_vec(),
+            gcpu: 0,
+            ccpu: 0,
+            running: false,
+        })
+    }
+
+    /// Return the address the stub is listening on.
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Return true if a remote debugger is connected.
+    pub fn is_attached(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    /// Process the packets received from the remote debugger (if any),
+    /// without blocking. Queries are answered immediately, while requests
+    /// that affect the emulation are returned to the caller, which must
+    /// call stopped() once the emulation is stopped again.
+    pub fn poll<T: DebuggerModel>(
+        &mut self,
+        dbg: &mut Debugger,
+        model: &mut T,
+    ) -> Option<GdbRequest> {
+        if self.conn.is_none() {
+            self.accept();
+        }
+        self.receive();
+
+        while let Some(incoming) = parse_packet(&mut self.rxbuf) {
+            match incoming {
+                Incoming::Interrupt => {
+                    if self.running {
+                        return Some(GdbRequest::Interrupt);
+                    }
+                }
+                Incoming::BadPacket => self.send_raw(b"-"),
+                Incoming::Packet(pkt) => {
+                    self.send_raw(b"+");
+                    if let Some(req) = self.handle_packet(&pkt, dbg, model) {
+                        return Some(req);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Notify that the emulation is stopped because of the specified event.
+    /// A stop reply is sent only if the remote debugger is waiting for one
+    /// (after a continue or step request), so it's fine to call this
+    /// repeatedly while the emulation is paused.
+    pub fn stopped(&mut self, dbg: &Debugger, event: &TraceEvent) {
+        if !self.running || self.conn.is_none() {
+            return;
+        }
+        self.running = false;
+
+        let (cpu_name, signal, watch) = match event {
+            TraceEvent::Breakpoint(cpu_name, _, _) | TraceEvent::BreakpointOneShot(cpu_name, _) => {
+                (Some(cpu_name), SIGTRAP, None)
+            }
+            TraceEvent::WatchpointWrite(cpu_name, idx) => (
+                Some(cpu_name),
+                SIGTRAP,
+                Some(("watch", dbg.watchpoint_addr(cpu_name, *idx))),
+            ),
+            TraceEvent::WatchpointRead(cpu_name, idx) => (
+                Some(cpu_name),
+                SIGTRAP,
+                Some(("rwatch", dbg.watchpoint_addr(cpu_name, *idx))),
+            ),
+            TraceEvent::Stepped() => (None, SIGTRAP, None),
+            TraceEvent::Paused() | TraceEvent::Poll() => (None, SIGINT, None),
+            _ => (None, SIGTRAP, None),
+        };
+        if let Some(idx) = cpu_name.and_then(|name| self.cpus.iter().position(|n| n == name)) {
+            self.gcpu = idx;
+        }
+
+        let mut reply = format!("T{:02x}", signal);
+        if let Some((kind, addr)) = watch {
+            reply += &format!("{}:{:x};", kind, addr);
+        }
+        reply += &format!("thread:{:x};", self.gcpu + 1);
+        self.send(&reply);
+    }
+
+    fn accept(&mut self) {
+        match self.listener.accept() {
+            Ok((stream, _)) => {
+                if stream.set_nonblocking(true).is_ok() {
+                    let _ = stream.set_nodelay(true);
+                    self.conn = Some(stream);
+                    self.rxbuf.clear();
+                    self.gcpu = 0;
+                    self.ccpu = 0;
+                    self.running = false;
+                }
+            }
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(err) => warn!(self.logger, "gdb stub: cannot accept connection: {}", err),
+        }
+    }
+
+    fn receive(&mut self) {
+        let mut buf = [0u8; 4096];
+        loop {
+            let res = match self.conn.as_mut() {
+                Some(conn) => conn.read(&mut buf),
+                None => return,
+            };
+            match res {
+                Ok(0) => return self.disconnect(),
+                Ok(n) => self.rxbuf.extend_from_slice(&buf[..n]),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return self.disconnect(),
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.conn = None;
+        self.rxbuf.clear();
+        self.running = false;
+    }
+
+    fn send(&mut self, data: &str) {
+        self.send_raw(&encode_packet(data));
+    }
+
+    fn send_raw(&mut self, data: &[u8]) {
+        let res = match self.conn.as_mut() {
+            // Replies are short: block until they are sent.
+            Some(conn) => conn
+                .set_nonblocking(false)
+                .and_then(|_| conn.write_all(data))
+                .and_then(|_| conn.set_nonblocking(true)),
+            None => return,
+        };
+        if res.is_err() {
+            self.disconnect();
+        }
+    }
+
+    // Parse a thread ID into a CPU index (None for "any" or "all" threads).
+    fn thread(&self, id: &str) -> Result<Option<usize>, ()> {
+        match id {
+            "-1" | "0" => Ok(None),
+            _ => match usize::from_str_radix(id, 16) {
+                Ok(n) if n >= 1 && n <= self.cpus.len() => Ok(Some(n - 1)),
+                _ => Err(()),
+            },
+        }
+    }
+
+    fn handle_packet<T: DebuggerModel>(
+        &mut self,
+        pkt: &str,
+        dbg: &mut Debugger,
+        model: &mut T,
+    ) -> Option<GdbRequest> {
+        let cpu = self.cpus[self.gcpu].clone();
+        let (cmd, args) = pkt.split_at(pkt.chars().next().map_or(0, char::len_utf8));
+        let reply = match cmd {
+            // Reason of the stop: stop the emulation (if it is running), and
+            // report it like an interruption.
+            "?" => {
+                self.running = true;
+                return Some(GdbRequest::Interrupt);
+            }
+            "c" => {
+                self.running = true;
+                return Some(GdbRequest::Continue);
+            }
+            "s" => {
+                self.running = true;
+                return Some(GdbRequest::Step(self.cpus[self.ccpu].clone()));
+            }
+            "D" => {
+                self.send("OK");
+                self.disconnect();
+                return Some(GdbRequest::Continue);
+            }
+            "k" => {
+                self.disconnect();
+                return None;
+            }
+            "g" => REGISTERS
+                .iter()
+                .map(|name| format_register(model.cpu_register(&cpu, name)))
+                .collect(),
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTERS.len() => {
+                    format_register(model.cpu_register(&cpu, REGISTERS[n]))
+                }
+                Ok(_) => format_register(None),
+                Err(_) => "E01".into(),
+            },
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) => read_memory(model, &cpu, addr, len.min(PACKET_SIZE / 2)),
+                None => "E01".into(),
+            },
+            "H" => match args.get(1..).map(|id| self.thread(id)) {
+                Some(Ok(idx)) => {
+                    match (args.get(..1), idx) {
+                        (Some("g"), Some(idx)) => self.gcpu = idx,
+                        (Some("c"), Some(idx)) => self.ccpu = idx,
+                        _ => {}
+                    }
+                    "OK".into()
+                }
+                _ => "E01".into(),
+            },
+            "T" => match self.thread(args) {
+                Ok(_) => "OK".into(),
+                Err(()) => "E01".into(),
+            },
+            "Z" | "z" => self.handle_break(cmd == "Z", args, &cpu, dbg, model),
+            "q" => self.handle_query(args),
+            _ => "".into(),
+        };
+        self.send(&reply);
+        None
+    }
+
+    fn handle_query(&self, query: &str) -> String {
+        let (name, args) = match query.find([':', ',']) {
+            Some(idx) => (&query[..idx], &query[idx + 1..]),
+            None => (query, ""),
+        };
+        match name {
+            "Supported" => format!("PacketSize={:x}", PACKET_SIZE),
+            "Attached" => "1".into(),
+            "C" => format!("QC{:x}", self.gcpu + 1),
+            "fThreadInfo" => {
+                let ids: Vec<String> = (1..=self.cpus.len()).map(|n| format!("{:x}", n)).collect();
+                format!("m{}", ids.join(","))
+            }
+            "sThreadInfo" => "l".into(),
+            "ThreadExtraInfo" => match self.thread(args) {
+                Ok(Some(idx)) => encode_hex(self.cpus[idx].as_bytes()),
+                _ => "E01".into(),
+            },
+            _ => "".into(),
+        }
+    }
+
+    // Insert or remove a breakpoint or watchpoint (Z/z packets).
+    fn handle_break<T: DebuggerModel>(
+        &self,
+        insert: bool,
+        args: &str,
+        cpu: &str,
+        dbg: &mut Debugger,
+        model: &mut T,
+    ) -> String {
+        let mut fields = args.splitn(2, ',');
+        let (kind, addr) = match (fields.next(), fields.next().and_then(parse_addr_len)) {
+            (Some(kind), Some((addr, _))) => (kind, addr),
+            _ => return "E01".into(),
+        };
+        let wtype = match kind {
+            "0" | "1" => {
+                let pc = model.cpu_breakpoint_addr(cpu, addr);
+                if insert {
+                    dbg.add_breakpoint(cpu, pc, DESCRIPTION);
+                } else {
+                    dbg.remove_breakpoint(cpu, pc, DESCRIPTION);
+                }
+                return "OK".into();
+            }
+            "2" => WatchpointType::Write,
+            "3" => WatchpointType::Read,
+            _ => return "".into(),
+        };
+        // Watchpoints are checked against the 32-bit addresses of the
+        // memory accesses.
+        let addr = addr as u32 as u64;
+        if insert {
+            dbg.add_watchpoint(cpu, addr, wtype, DESCRIPTION);
+        } else {
+            dbg.remove_watchpoint(cpu, addr, wtype, DESCRIPTION);
+        }
+        "OK".into()
+    }
+}
+
+// Extract the next complete packet (or interrupt) from the received data.
+fn parse_packet(buf: &mut Vec<u8>) -> Option<Incoming> {
+    loop {
+        // Skip acknowledgments and garbage before the start of a packet.
+        let start = match buf.iter().position(|&b| b == b'$' || b == 0x03) {
+            Some(start) => start,
+            None => {
+                buf.clear();
+                return None;
+            }
+        };
+        if buf[start] == 0x03 {
+            buf.drain(..=start);
+            return Some(Incoming::Interrupt);
+        }
+        let end = start + buf[start..].iter().position(|&b| b == b'#')?;
+        if buf.len() < end + 3 {
+            buf.drain(..start);
+            return None;
+        }
+
+        let data = buf[start + 1..end].to_vec();
+        let sum = std::str::from_utf8(&buf[end + 1..end + 3])
+            .ok()
+            .and_then(|s| u8::from_str_radix(s, 16).ok());
+        buf.drain(..end + 3);
+        if sum != Some(checksum(&data)) {
+            return Some(Incoming::BadPacket);
+        }
+        if !data.is_empty() {
+            return Some(Incoming::Packet(
+                String::from_utf8_lossy(&data).into_owned(),
+            ));
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn encode_packet(data: &str) -> Vec<u8> {
+    format!("${}#{:02x}", data, checksum(data.as_bytes())).into_bytes()
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Registers are sent in target byte order (big-endian), so the hex dump of a
+// register is its value.
+fn format_register(val: Option<u64>) -> String {
+    match val {
+        Some(val) => format!("{:016x}", val),
+        None => "xx".repeat(8),
+    }
+}
+
+// Parse the "ADDR,LENGTH" argument of memory and breakpoint packets.
+fn parse_addr_len(args: &str) -> Option<(u64, usize)> {
+    let mut fields = args.splitn(2, ',');
+    let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let len = usize::from_str_radix(fields.next()?, 16).ok()?;
+    Some((addr, len))
+}
+
+// Read memory through DebuggerModel::cpu_peek, stopping at the first
+// unreadable word (as allowed by the protocol).
+fn read_memory<T: DebuggerModel>(model: &mut T, cpu: &str, addr: u64, len: usize) -> String {
+    let mut data = Vec::with_capacity(len);
+    let mut word = None;
+    for a in (0..len as u64).map(|off| addr.wrapping_add(off)) {
+        if word.is_none() || (a & 3) == 0 {
+            word = match model.cpu_peek(cpu, a & !3) {
+                Some(w) => Some(w),
+                None => break,
+            };
+        }
+        data.push((word.unwrap() >> (24 - (a & 3) * 8)) as u8);
+    }
+    if data.is_empty() && len > 0 {
+        return "E01".into();
+    }
+    encode_hex(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets() {
+        let mut buf = b"+$qSupported:multiprocess+#c6$?#3f$g#00".to_vec();
+        assert_eq!(
+            parse_packet(&mut buf),
+            Some(Incoming::Packet("qSupported:multiprocess+".into()))
+        );
+        assert_eq!(parse_packet(&mut buf), Some(Incoming::Packet("?".into())));
+        assert_eq!(parse_packet(&mut buf), Some(Incoming::BadPacket));
+        assert_eq!(parse_packet(&mut buf), None);
+        assert!(buf.is_empty());
+
+        // Incomplete packets are kept until the rest is received.
+        buf.extend_from_slice(b"+$m80000400,4#");
+        assert_eq!(parse_packet(&mut buf), None);
+        buf.extend_from_slice(b"59\x03");
+        assert_eq!(
+            parse_packet(&mut buf),
+            Some(Incoming::Packet("m80000400,4".into()))
+        );
+        assert_eq!(parse_packet(&mut buf), Some(Incoming::Interrupt));
+        assert_eq!(parse_packet(&mut buf), None);
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode_packet("OK"), b"$OK#9a".to_vec());
+        assert_eq!(encode_hex(b"RSP"), "525350");
+        assert_eq!(format_register(Some(0xA4000040)), "00000000a4000040");
+        assert_eq!(format_register(None), "xxxxxxxxxxxxxxxx");
+        assert_eq!(
+            parse_addr_len("ffffffff80000400,10"),
+            Some((0xFFFF_FFFF_8000_0400, 16))
+        );
+        assert_eq!(parse_addr_len("80000400"), None);
+    }
+}
@@ -0,0 +1,112 @@
+#[cfg(feature = "debugger")]
+use imgui::*;
+
+#[cfg(feature = "debugger")]
+use super::UiCtx;
+
+/// A block of memory managed by a heap, as decoded by a
+/// [`HeapView`](trait.HeapView.html).
+pub struct HeapBlock {
+    pub addr: u64,              // Address of the block (as seen by the CPU)
+    pub size: u64,              // Size of the block (in bytes)
+    pub free: bool,             // True if the block is not allocated
+    pub origin: Option<String>, // Allocation site (caller PC or source line), if known
+}
+
+/// A memory allocator found in guest memory.
+pub struct Heap {
+    pub kind: String, // Kind of allocator (eg: name of the control structure)
+    pub addr: u64,    // Address of the control structure
+    pub base: u64,    // Start of the managed memory
+    pub size: u64,    // Size of the managed memory (in bytes)
+    pub blocks: Vec<HeapBlock>,
+}
+
+impl Heap {
+    /// Return the number of allocated bytes.
+    pub fn used(&self) -> u64 {
+        self.blocks.iter().filter(|b| !b.free).map(|b| b.size).sum()
+    }
+}
+
+/// A trait for an object that can recognize the memory allocators used by
+/// the guest software (eg: those of the system libraries), and decode their
+/// allocations. Decoding happens every time the view is drawn, so it is
+/// always up to date while the emulation is paused.
+pub trait HeapView {
+    /// Return the name of this object. The name will be composed
+    /// as "\[NAME\] Heaps".
+    fn name(&self) -> &str;
+
+    /// Search memory for allocator control structures, and return their
+    /// addresses. This can be slow, so it is only called when requested.
+    fn find_heaps(&self) -> Vec<u64>;
+
+    /// Decode the allocator whose control structure is at the specified
+    /// address. Returns None if there is no (longer a) valid allocator there.
+    fn heap(&self, addr: u64) -> Option<Heap>;
+}
+
+#[cfg(feature = "debugger")]
+pub(crate) fn render_heapview<'a, 'ui, HV: HeapView>(ui: &'a Ui<'ui>, ctx: &mut UiCtx, v: &mut HV) {
+    ui.window(im_str!("[{}] Heaps", v.name()))
+        .size((500.0, 400.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            let hctx = ctx.heapview.entry(v.name().to_owned()).or_default();
+            if ui.small_button(im_str!("Scan memory")) {
+                hctx.heaps = v.find_heaps();
+                hctx.sel = hctx.heaps.first().cloned();
+            }
+            if hctx.heaps.is_empty() {
+                ui.text(im_str!("No heaps found (scan memory to search again)"));
+                return;
+            }
+
+            // List of heaps, with their usage
+            let heaps: Vec<_> = hctx.heaps.iter().filter_map(|a| v.heap(*a)).collect();
+            for heap in &heaps {
+                if ui.selectable(
+                    im_str!("{} @ {:08x}###heap{:x}", heap.kind, heap.addr, heap.addr),
+                    hctx.sel == Some(heap.addr),
+                    ImGuiSelectableFlags::empty(),
+                    (0.0, 0.0),
+                ) {
+                    hctx.sel = Some(heap.addr);
+                }
+                let used = heap.used();
+                ui.progress_bar(used as f32 / heap.size.max(1) as f32)
+                    .size((-1.0, 0.0))
+                    .overlay_text(im_str!("{:x} / {:x} bytes", used, heap.size))
+                    .build();
+            }
+            ui.separator();
+
+            let heap = match heaps.iter().find(|h| Some(h.addr) == hctx.sel) {
+                Some(heap) => heap,
+                None => return,
+            };
+            ui.child_frame(im_str!("###blocks"), (0.0, 0.0)).build(|| {
+                ui.columns(4, im_str!("blocks#columns"), true);
+                for title in ["Address", "Size", "State", "Origin"].iter() {
+                    ui.text(im_str!("{}", title));
+                    ui.next_column();
+                }
+                ui.separator();
+                for b in &heap.blocks {
+                    ui.text(im_str!("{:08x}", b.addr));
+                    ui.next_column();
+                    ui.text(im_str!("{:x}", b.size));
+                    ui.next_column();
+                    if b.free {
+                        ui.text_disabled(im_str!("free"));
+                    } else {
+                        ui.text(im_str!("used"));
+                    }
+                    ui.next_column();
+                    ui.text(im_str!("{}", b.origin.as_ref().map_or("-", |s| s.as_str())));
+                    ui.next_column();
+                }
+                ui.columns(1, im_str!("blocks#end"), false);
+            });
+        });
+}
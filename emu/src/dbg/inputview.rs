@@ -0,0 +1,196 @@
+use crate::hw::{InputMapping, InputProfile, ProfileAction, StickConfig, StickGate, StickResponse};
+use crate::input::{InputManager, InputValue};
+use imgui::*;
+
+fn color(r: usize, g: usize, b: usize) -> ImVec4 {
+    ImVec4::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0)
+}
+
+// Render the settings of an emulated analog stick, and a widget showing the
+// gate, the position of the host stick (scaled to the gate) and the
+// resulting emulated position.
+fn render_stick(
+    ui: &Ui<'_>,
+    dev: &str,
+    cfg: &mut StickConfig,
+    host: Option<(f32, f32)>,
+    out: (f32, f32),
+) {
+    let mut gate = StickGate::ALL.iter().position(|g| *g == cfg.gate).unwrap() as i32;
+    let gates: Vec<ImString> = StickGate::ALL
+        .iter()
+        .map(|g| ImString::new(g.name()))
+        .collect();
+    let gates: Vec<&ImStr> = gates.iter().map(|g| g.as_ref()).collect();
+    let mut resp = StickResponse::ALL
+        .iter()
+        .position(|r| *r == cfg.response)
+        .unwrap() as i32;
+    let resps: Vec<ImString> = StickResponse::ALL
+        .iter()
+        .map(|r| ImString::new(r.name()))
+        .collect();
+    let resps: Vec<&ImStr> = resps.iter().map(|r| r.as_ref()).collect();
+    ui.with_item_width(150.0, || {
+        if ui.combo(im_str!("Gate###stick#gate#{}", dev), &mut gate, &gates, 0) {
+            cfg.gate = StickGate::ALL[gate as usize];
+        }
+        if ui.combo(
+            im_str!("Response###stick#resp#{}", dev),
+            &mut resp,
+            &resps,
+            0,
+        ) {
+            cfg.response = StickResponse::ALL[resp as usize];
+        }
+        ui.slider_float(
+            im_str!("Range###stick#range#{}", dev),
+            &mut cfg.range,
+            40.0,
+            127.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Deadzone###stick#dz#{}", dev),
+            &mut cfg.deadzone,
+            0.0,
+            0.5,
+        )
+        .build();
+    });
+
+    const SIZE: f32 = 128.0;
+    let pos = ui.get_cursor_screen_pos();
+    let center = (pos.0 + SIZE / 2.0, pos.1 + SIZE / 2.0);
+    let scale = SIZE / 2.0 / 128.0;
+    let point = |(x, y): (f32, f32)| (center.0 + x * scale, center.1 - y * scale);
+    let dl = ui.get_window_draw_list();
+    let bg = color(39, 40, 34);
+    dl.add_rect_filled_multicolor(pos, (pos.0 + SIZE, pos.1 + SIZE), bg, bg, bg, bg);
+    let axis = color(73, 72, 62);
+    dl.add_line((pos.0, center.1), (pos.0 + SIZE, center.1), axis)
+        .build();
+    dl.add_line((center.0, pos.1), (center.0, pos.1 + SIZE), axis)
+        .build();
+    let outline = cfg.gate();
+    for (idx, p) in outline.iter().enumerate() {
+        let next = outline[(idx + 1) % outline.len()];
+        dl.add_line(point(*p), point(next), color(230, 219, 116))
+            .build();
+    }
+    if let Some((x, y)) = host {
+        let host = point((x * cfg.range, y * cfg.range));
+        dl.add_circle(host, 3.0, color(117, 113, 94)).build();
+    }
+    dl.add_circle(point(out), 3.0, color(165, 224, 46))
+        .filled(true)
+        .build();
+    ui.invisible_button(im_str!("###stick#view#{}", dev), (SIZE, SIZE));
+    ui.same_line(0.0);
+    ui.group(|| {
+        ui.text(im_str!("X: {:4.0}", out.0));
+        ui.text(im_str!("Y: {:4.0}", out.1));
+        if host.is_none() {
+            ui.text_disabled(im_str!("no controller"));
+        }
+    });
+}
+
+// Render the input diagnostics window: the input profile in use, the
+// settings of the analog sticks, raw host keyboard state, the state of all
+// emulated input devices (after mapping), and the measured latency between an
+// input event and the emulated software polling it.
+pub(crate) fn render_inputview(
+    ui: &Ui<'_>,
+    raw_keys: &[String],
+    im: &InputManager,
+    profile: &mut InputProfile,
+    mut input: Option<&mut InputMapping>,
+) {
+    ui.window(im_str!("Input"))
+        .size((300.0, 400.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            ui.text(im_str!("Profile:"));
+            ui.same_line(0.0);
+            match profile.game.as_ref() {
+                Some(game) if profile.per_game => ui.text(im_str!("{} (game)", game)),
+                _ => ui.text(im_str!("global")),
+            }
+            if profile.game.is_some() {
+                if ui.small_button(im_str!("Copy current profile to this game")) {
+                    profile.action = Some(ProfileAction::CopyToGame);
+                }
+                if profile.per_game {
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Remove game profile")) {
+                        profile.action = Some(ProfileAction::RemoveGame);
+                    }
+                }
+            }
+            ui.separator();
+
+            if let Some(map) = input.as_mut() {
+                map.visit_sticks(|dev, cfg, host, out| {
+                    if ui
+                        .collapsing_header(im_str!("{} analog stick", dev))
+                        .default_open(false)
+                        .build()
+                    {
+                        render_stick(ui, dev, cfg, host, out);
+                    }
+                });
+                ui.separator();
+            }
+
+            ui.text(im_str!("Host keyboard:"));
+            ui.same_line(0.0);
+            if raw_keys.is_empty() {
+                ui.text_disabled(im_str!("<none>"));
+            } else {
+                ui.text(im_str!("{}", raw_keys.join(" ")));
+            }
+            ui.separator();
+
+            let lat = im.latencies();
+            match lat.back() {
+                Some(last) => {
+                    let avg = lat.iter().sum::<usize>() as f32 / lat.len() as f32;
+                    let max = lat.iter().max().unwrap();
+                    ui.text(im_str!(
+                        "Input latency: {} frames (avg: {:.1}, max: {})",
+                        last,
+                        avg,
+                        max
+                    ));
+                    let values: Vec<f32> = lat.iter().map(|v| *v as f32).collect();
+                    ui.plot_histogram(im_str!("###input#latency"), &values)
+                        .scale_min(0.0)
+                        .graph_size((0.0, 40.0))
+                        .build();
+                }
+                None => ui.text(im_str!("Input latency: no measurement yet")),
+            }
+            ui.separator();
+
+            im.visit(|dev| {
+                if ui
+                    .collapsing_header(im_str!("{}", dev.name()))
+                    .default_open(false)
+                    .build()
+                {
+                    dev.visit(|inp| {
+                        ui.bullet_text(im_str!("{}", inp.name()));
+                        ui.same_line(120.0);
+                        match inp.value() {
+                            InputValue::Digital(true) => {
+                                ui.text_colored((0.65, 0.88, 0.18, 1.0), im_str!("pressed"))
+                            }
+                            InputValue::Digital(false) => ui.text_disabled(im_str!("released")),
+                            InputValue::Analog(v) => ui.text(im_str!("{}", v)),
+                            InputValue::Coordinate(v) => ui.text(im_str!("{}", v)),
+                        }
+                    });
+                }
+            });
+        });
+}
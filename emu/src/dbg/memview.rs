@@ -0,0 +1,189 @@
+#[cfg(feature = "debugger")]
+use imgui::*;
+#[cfg(feature = "debugger")]
+use imgui_sys;
+
+#[cfg(feature = "debugger")]
+use super::uisupport::*;
+#[cfg(feature = "debugger")]
+use super::UiCtx;
+
+/// A trait for an object that exposes one or more memory buffers (eg: RDRAM,
+/// or DMEM and IMEM), to be inspected and modified in a hex editor.
+pub trait MemoryView {
+    /// Return the name of this object. The name will be composed
+    /// as "\[NAME\] Hex Editor".
+    fn name(&self) -> &str;
+
+    /// Return the number of memory buffers.
+    fn mem_count(&self) -> usize {
+        1
+    }
+
+    /// Return the name, the base address (as seen by the CPU) and the
+    /// contents of one of the memory buffers.
+    fn mem(&self, idx: usize) -> (&str, u64, &[u8]);
+
+    /// Return the contents of one of the memory buffers, for modification.
+    fn mem_mut(&mut self, idx: usize) -> &mut [u8];
+}
+
+#[cfg(feature = "debugger")]
+fn color(r: usize, g: usize, b: usize) -> ImVec4 {
+    ImVec4::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0)
+}
+
+#[cfg(feature = "debugger")]
+const BYTES_PER_LINE: usize = 16;
+
+// Horizontal position of a byte within a line, grouping bytes in words.
+#[cfg(feature = "debugger")]
+fn byte_pos(i: usize) -> f32 {
+    80.0 + i as f32 * 20.0 + (i / 4) as f32 * 6.0
+}
+
+#[cfg(feature = "debugger")]
+pub(crate) fn render_memview<'a, 'ui, MV: MemoryView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    v: &mut MV,
+) {
+    let view_name = v.name().to_owned();
+    let frame = ctx.frame;
+    let mut msg = None;
+
+    ui.window(im_str!("[{}] Hex Editor", view_name))
+        .size((560.0, 400.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            let state = ctx.memview.entry(view_name.clone()).or_default();
+
+            // Memory buffer selection
+            if v.mem_count() > 1 {
+                let names: Vec<ImString> = (0..v.mem_count())
+                    .map(|idx| ImString::new(v.mem(idx).0))
+                    .collect();
+                let names: Vec<&ImStr> = names.iter().map(|n| n.as_ref()).collect();
+                let mut region = state.region as i32;
+                ui.with_item_width(80.0, || {
+                    if ui.combo(
+                        im_str!("###region"),
+                        &mut region,
+                        &names,
+                        names.len() as i32,
+                    ) {
+                        state.region = region as usize;
+                        state.cursor = None;
+                    }
+                });
+                ui.same_line(0.0);
+            }
+            let idx = state.region.min(v.mem_count() - 1);
+            let (_, base, mem) = v.mem(idx);
+
+            // Keep the contents at the last two frames seen by the debugger,
+            // to highlight the bytes that changed.
+            if state.frame != frame || state.snapshot_region != idx || state.curr.len() != mem.len()
+            {
+                if state.snapshot_region == idx && state.curr.len() == mem.len() {
+                    std::mem::swap(&mut state.prev, &mut state.curr);
+                    state.curr.copy_from_slice(mem);
+                } else {
+                    state.prev = mem.to_vec();
+                    state.curr = mem.to_vec();
+                }
+                state.frame = frame;
+                state.snapshot_region = idx;
+            }
+
+            ui.text(im_str!("Goto:"));
+            ui.same_line(0.0);
+            if imgui_input_hex(ui, im_str!("###goto"), &mut state.goto_addr, true) {
+                let addr = state.goto_addr;
+                if addr >= base && addr - base < mem.len() as u64 {
+                    let off = (addr - base) as usize;
+                    state.cursor = Some(off);
+                    state.force_line = Some(off / BYTES_PER_LINE);
+                } else {
+                    msg = Some(format!("Address {:x} is out of range", addr));
+                }
+            }
+
+            // Editor of the selected byte
+            let mut write = None;
+            if let Some(off) = state.cursor.filter(|&off| off < mem.len()) {
+                ui.same_line(0.0);
+                ui.text(im_str!("Value at {:08x}:", base + off as u64));
+                ui.same_line(0.0);
+                let mut val = mem[off];
+                if imgui_input_hex(ui, im_str!("###value"), &mut val, true) {
+                    write = Some((off, val));
+                }
+            }
+            ui.separator();
+
+            let force_line = state.force_line.take();
+            let mut clicked = None;
+            ui.child_frame(im_str!("###hex"), (0.0, 0.0))
+                .always_show_vertical_scroll_bar(true)
+                .build(|| {
+                    let row_height = ui.get_text_line_height_with_spacing();
+                    if let Some(line) = force_line {
+                        unsafe {
+                            imgui_sys::igSetScrollY(row_height * line.saturating_sub(4) as f32);
+                        }
+                    }
+
+                    let num_lines = mem.len().div_ceil(BYTES_PER_LINE);
+                    ImGuiListClipper::new(num_lines).build(|start, end| {
+                        for line in start as usize..end as usize {
+                            let off = line * BYTES_PER_LINE;
+                            let bytes = &mem[off..(off + BYTES_PER_LINE).min(mem.len())];
+                            ui.text_colored(
+                                color(174, 129, 255),
+                                im_str!("{:08x}", base + off as u64),
+                            );
+
+                            for (i, byte) in bytes.iter().enumerate() {
+                                let pos = off + i;
+                                let col = if state.cursor == Some(pos) {
+                                    color(230, 219, 116)
+                                } else if state.prev[pos] != state.curr[pos] {
+                                    color(249, 38, 114)
+                                } else {
+                                    color(102, 99, 83)
+                                };
+                                ui.same_line(byte_pos(i));
+                                ui.text_colored(col, im_str!("{:02x}", byte));
+                                if ui.is_item_hovered()
+                                    && ui.is_window_focused()
+                                    && ui.imgui().is_mouse_clicked(ImMouseButton::Left)
+                                {
+                                    clicked = Some(pos);
+                                }
+                            }
+
+                            let text: String = bytes
+                                .iter()
+                                .map(|&b| match b {
+                                    0x20..=0x7E => b as char,
+                                    _ => '.',
+                                })
+                                .collect();
+                            ui.same_line(byte_pos(BYTES_PER_LINE) + 10.0);
+                            ui.text_colored(color(117, 113, 94), im_str!("{}", text));
+                        }
+                    });
+                });
+
+            if let Some(pos) = clicked {
+                state.cursor = Some(pos);
+            }
+            if let Some((off, val)) = write {
+                v.mem_mut(idx)[off] = val;
+            }
+        });
+
+    if let Some(msg) = msg {
+        ctx.add_flash_msg(&msg);
+    }
+}
@@ -1,22 +1,22 @@
 use super::UiCtx;
+use crate::hw::{HotkeyAction, HotkeyConfig, Theme, ThemeConfig};
 use imgui::*;
 use imgui_sys::*;
+use sdl2::keyboard::Scancode;
 use std::time::Duration;
 
 // Rendere the help tooltip showing keyboard shortcuts
-pub(crate) fn render_help(ui: &Ui<'_>) -> ImString {
+pub(crate) fn render_help(ui: &Ui<'_>, hotkeys: &HotkeyConfig) -> ImString {
     let title = ImString::new("Keyboard shortcuts");
     ui.popup_modal(&title).resizable(false).build(|| {
-        ui.text("General:");
+        ui.text("General (configurable in Hotkeys window):");
         ui.separator();
 
-        ui.bullet_text(im_str!("ESC"));
-        ui.same_line(90.0);
-        ui.text("Enter/exit debugger");
-
-        ui.bullet_text(im_str!("SPACE"));
-        ui.same_line(90.0);
-        ui.text("Start/stop emulation");
+        for action in HotkeyAction::ALL.iter() {
+            ui.bullet_text(im_str!("{}", hotkeys.key_name(*action).to_uppercase()));
+            ui.same_line(90.0);
+            ui.text(action.desc());
+        }
 
         ui.spacing();
         ui.spacing();
@@ -39,6 +39,48 @@ pub(crate) fn render_help(ui: &Ui<'_>) -> ImString {
         ui.same_line(90.0);
         ui.text("Run to selection");
 
+        ui.bullet_text(im_str!(";"));
+        ui.same_line(90.0);
+        ui.text("Comment");
+
+        ui.bullet_text(im_str!("B"));
+        ui.same_line(90.0);
+        ui.text("Bookmark");
+
+        ui.spacing();
+        ui.spacing();
+        ui.text("Screen:");
+        ui.separator();
+
+        ui.bullet_text(im_str!("ARROWS"));
+        ui.same_line(90.0);
+        ui.text("Move pixel cursor (SHIFT: 8 pixels)");
+
+        ui.bullet_text(im_str!("ENTER"));
+        ui.same_line(90.0);
+        ui.text("Query pixel");
+
+        ui.spacing();
+        ui.spacing();
+        ui.text("Navigation (if enabled in Settings):");
+        ui.separator();
+
+        ui.bullet_text(im_str!("CTRL+TAB"));
+        ui.same_line(90.0);
+        ui.text("Cycle window focus");
+
+        ui.bullet_text(im_str!("ARROWS"));
+        ui.same_line(90.0);
+        ui.text("Move between widgets");
+
+        ui.bullet_text(im_str!("SPACE"));
+        ui.same_line(90.0);
+        ui.text("Activate widget / open header");
+
+        ui.bullet_text(im_str!("ESC"));
+        ui.same_line(90.0);
+        ui.text("Leave widget / close popup");
+
         ui.spacing();
         ui.spacing();
         if ui.button(im_str!("Close"), (80.0, 30.0)) {
@@ -48,6 +90,144 @@ pub(crate) fn render_help(ui: &Ui<'_>) -> ImString {
     title
 }
 
+// Render the hotkeys configuration window. To change a binding, click on it
+// and then press the new key.
+pub(crate) fn render_hotkeys(ui: &Ui<'_>, ctx: &mut UiCtx, hotkeys: &mut HotkeyConfig) {
+    ui.window(im_str!("Hotkeys"))
+        .size((300.0, 260.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            for action in HotkeyAction::ALL.iter() {
+                let label = if ctx.hotkey_capture == Some(*action) {
+                    "<press a key>"
+                } else {
+                    hotkeys.key_name(*action)
+                };
+                if ui.button(im_str!("{}###hotkey#{:?}", label, action), (110.0, 0.0)) {
+                    ctx.hotkey_capture = Some(*action);
+                }
+                ui.same_line(0.0);
+                ui.text(action.desc());
+            }
+        });
+
+    // Capture the next key pressed, if requested.
+    if let Some(action) = ctx.hotkey_capture {
+        for code in 0..512 {
+            if ui.imgui().is_key_pressed(code) {
+                if let Some(scancode) = Scancode::from_i32(code as i32) {
+                    hotkeys.set(action, scancode);
+                    ctx.hotkey_capture = None;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Apply the configured theme to the imgui style: the base theme, the color
+// overrides, the UI scale (applied to the unscaled base style, together with
+// the display DPI scale) and the keyboard navigation. Returns the names of the
+// overridden colors that are unknown to imgui (and were thus ignored).
+pub(crate) fn apply_theme(
+    imgui: &mut ImGui,
+    theme: &ThemeConfig,
+    base: &ImGuiStyle,
+    dpi_scale: f32,
+) -> Vec<String> {
+    let style = imgui.style_mut();
+    *style = base.clone();
+    unsafe {
+        ImGuiStyle_ScaleAllSizes(style, dpi_scale * theme.ui_scale);
+        match theme.theme {
+            Theme::Dark => igStyleColorsDark(style),
+            Theme::Light => igStyleColorsLight(style),
+            Theme::Classic => igStyleColorsClassic(style),
+        }
+    }
+
+    let mut unknown = Vec::new();
+    for (name, c) in &theme.colors {
+        match ImGuiCol::VARIANTS
+            .iter()
+            .find(|col| format!("{:?}", col) == *name)
+        {
+            Some(col) => style.colors[*col as usize] = ImVec4::new(c[0], c[1], c[2], c[3]),
+            None => unknown.push(name.clone()),
+        }
+    }
+
+    imgui.set_font_global_scale(theme.font_scale * theme.ui_scale);
+    unsafe {
+        let io = &mut *igGetIO();
+        if theme.keyboard_nav {
+            io.config_flags |= ImGuiConfigFlags::NavEnableKeyboard;
+        } else {
+            io.config_flags &= !ImGuiConfigFlags::NavEnableKeyboard;
+        }
+    }
+    unknown
+}
+
+// Render the settings window, to change the theme at runtime. Returns true
+// if the theme was changed; it must then be applied with apply_theme()
+// after the current frame is rendered.
+pub(crate) fn render_settings(ui: &Ui<'_>, theme: &mut ThemeConfig) -> bool {
+    let mut changed = false;
+    ui.window(im_str!("Settings"))
+        .size((350.0, 400.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            let mut idx = Theme::ALL.iter().position(|t| *t == theme.theme).unwrap() as i32;
+            if ui.combo(
+                im_str!("Theme"),
+                &mut idx,
+                &[im_str!("Dark"), im_str!("Light"), im_str!("Classic")],
+                0,
+            ) {
+                theme.theme = Theme::ALL[idx as usize];
+                changed = true;
+            }
+            if ui
+                .slider_float(im_str!("UI scale"), &mut theme.ui_scale, 0.5, 3.0)
+                .build()
+            {
+                changed = true;
+            }
+            if ui
+                .slider_float(im_str!("Font scale"), &mut theme.font_scale, 0.5, 3.0)
+                .build()
+            {
+                changed = true;
+            }
+            if ui.checkbox(im_str!("Keyboard navigation"), &mut theme.keyboard_nav) {
+                changed = true;
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text(im_str!(
+                    "Ctrl+Tab: cycle windows, arrows: move, Space: select"
+                ));
+            }
+
+            if ui.collapsing_header(im_str!("Colors")).build() {
+                if ui.small_button(im_str!("Reset to theme")) {
+                    theme.colors.clear();
+                    changed = true;
+                }
+                // Edited colors are saved as overrides of the theme.
+                let colors = ui.imgui().style().colors;
+                for col in ImGuiCol::VARIANTS.iter() {
+                    let name = format!("{:?}", col);
+                    let c = colors[*col as usize];
+                    let mut rgba = [c.x, c.y, c.z, c.w];
+                    if ui.color_edit(im_str!("{}", name), &mut rgba).build() {
+                        theme.colors.insert(name, rgba);
+                        changed = true;
+                    }
+                }
+            }
+        });
+    changed
+}
+
 // Render the flash messages
 pub(crate) fn render_flash_msgs(ui: &Ui<'_>, ctx: &mut UiCtx) {
     if ctx.flash_msg.is_none() {
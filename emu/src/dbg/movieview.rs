@@ -0,0 +1,196 @@
+use super::uisupport::*;
+use crate::input::{InputManager, InputMovie, InputValue};
+use crate::state::{CompressedState, CurrentState, State};
+use imgui::*;
+
+use std::collections::BTreeMap;
+
+/// Interval (in frames) between savestates kept in the greenzone.
+const GREENZONE_INTERVAL: i64 = 30;
+
+/// Maximum number of savestates kept in the greenzone; when the limit is
+/// reached, the oldest ones are discarded.
+const GREENZONE_MAX: usize = 32;
+
+pub(crate) enum MovieCommand {
+    Seek(i64), // Rewind/advance emulation to the specified frame
+}
+
+/// MovieEditor records the inputs of the emulated software into an
+/// InputMovie and plays them back. Recorded frames can be edited in a
+/// piano-roll view; to be able to re-run the emulation after an edit,
+/// savestates are periodically taken while recording (the "greenzone"),
+/// so that it's possible to seek to any frame by reloading the closest
+/// savestate and emulating up to that frame.
+#[derive(Default)]
+pub(crate) struct MovieEditor {
+    movie: Option<InputMovie>,
+    recording: bool,
+    greenzone: BTreeMap<i64, CompressedState>,
+    last_frame: Option<i64>,
+    selected: Option<usize>,
+}
+
+impl MovieEditor {
+    /// Must be called before tracing the emulation; applies or records the
+    /// inputs when a new frame begins.
+    pub(crate) fn begin_frame(&mut self, frame: i64, im: &mut InputManager) {
+        if self.last_frame == Some(frame) {
+            return;
+        }
+        self.last_frame = Some(frame);
+        if !self.recording {
+            return;
+        }
+
+        let movie = self.movie.get_or_insert_with(|| InputMovie::new(im));
+        if !movie.apply(frame as usize, im) {
+            movie.record(frame as usize, im);
+        }
+
+        if frame % GREENZONE_INTERVAL == 0 && !self.greenzone.contains_key(&frame) {
+            self.greenzone
+                .insert(frame, CurrentState().clone().into_compressed());
+            if self.greenzone.len() > GREENZONE_MAX {
+                let oldest = *self.greenzone.keys().next().unwrap();
+                self.greenzone.remove(&oldest);
+            }
+        }
+    }
+
+    /// Reload the greenzone savestate closest to the specified frame
+    /// (but not after it). Returns the frame of the savestate.
+    pub(crate) fn seek(&mut self, frame: i64) -> Option<i64> {
+        let (sframe, state) = self.greenzone.range(..=frame).next_back()?;
+        let state: State = state.decompress();
+        state.make_current();
+        self.last_frame = None;
+        Some(*sframe)
+    }
+
+    // Discard savestates taken after the specified frame, as the inputs
+    // that led to them have changed.
+    fn invalidate(&mut self, frame: i64) {
+        let stale: Vec<i64> = self.greenzone.range(frame + 1..).map(|(f, _)| *f).collect();
+        for f in stale {
+            self.greenzone.remove(&f);
+        }
+    }
+
+    pub(crate) fn render(&mut self, ui: &Ui<'_>, frame: i64, paused: bool) -> Option<MovieCommand> {
+        let mut cmd = None;
+        ui.window(im_str!("Input movie"))
+            .size((500.0, 400.0), ImGuiCond::FirstUseEver)
+            .build(|| {
+                ui.checkbox(im_str!("Record"), &mut self.recording);
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Truncate")) {
+                    if let Some(ref mut movie) = self.movie {
+                        movie.truncate(frame as usize);
+                    }
+                    self.invalidate(frame);
+                }
+                if let Some(sel) = self.selected {
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Seek to {}", sel)) {
+                        cmd = Some(MovieCommand::Seek(sel as i64));
+                    }
+                }
+                ui.same_line(0.0);
+                ui.text(im_str!(
+                    "Frame: {}, recorded: {}, greenzone: {} states",
+                    frame,
+                    self.movie.as_ref().map_or(0, |m| m.len()),
+                    self.greenzone.len()
+                ));
+                if !paused {
+                    ui.text_disabled(im_str!("Pause emulation to edit future inputs"));
+                }
+                ui.separator();
+
+                let movie = match self.movie {
+                    Some(ref mut movie) => movie,
+                    None => {
+                        ui.text(im_str!("No movie recorded yet"));
+                        return;
+                    }
+                };
+
+                // Piano roll: one row per frame, one column per input.
+                let cols = movie.columns().len();
+                let names: Vec<String> =
+                    movie.columns().iter().map(|(_, inp)| inp.clone()).collect();
+                ui.columns(cols as i32 + 1, im_str!("movie#header"), true);
+                ui.text(im_str!("Frame"));
+                ui.next_column();
+                for name in &names {
+                    ui.text(im_str!("{}", name));
+                    ui.next_column();
+                }
+                ui.columns(1, im_str!("movie#headerend"), false);
+                ui.separator();
+
+                let mut edit = None;
+                let mut selected = self.selected;
+                ui.child_frame(im_str!("###movie#rows"), (0.0, 0.0))
+                    .build(|| {
+                        ui.columns(cols as i32 + 1, im_str!("movie#rows"), true);
+                        ImGuiListClipper::new(movie.len()).build(|start, end| {
+                            for f in start as usize..end as usize {
+                                if f as i64 == frame {
+                                    ui.text_colored((0.65, 0.88, 0.18, 1.0), im_str!("{}", f));
+                                } else if ui.selectable(
+                                    im_str!("{}###movie#frame{}", f, f),
+                                    selected == Some(f),
+                                    ImGuiSelectableFlags::empty(),
+                                    (0.0, 0.0),
+                                ) {
+                                    selected = Some(f);
+                                }
+                                ui.next_column();
+
+                                // Only future frames can be edited, and only
+                                // while paused.
+                                let editable = paused && f as i64 >= frame;
+                                for (col, val) in movie.frame(f).unwrap().iter().enumerate() {
+                                    match *val {
+                                        InputValue::Digital(pressed) => {
+                                            let mark = if pressed { "X" } else { "." };
+                                            if editable {
+                                                if ui.small_button(im_str!(
+                                                    "{}###movie#{}#{}",
+                                                    mark,
+                                                    f,
+                                                    col
+                                                )) {
+                                                    edit = Some((
+                                                        f,
+                                                        col,
+                                                        InputValue::Digital(!pressed),
+                                                    ));
+                                                }
+                                            } else if pressed {
+                                                ui.text(im_str!("{}", mark));
+                                            } else {
+                                                ui.text_disabled(im_str!("{}", mark));
+                                            }
+                                        }
+                                        InputValue::Analog(v) => ui.text(im_str!("{}", v)),
+                                        InputValue::Coordinate(v) => ui.text(im_str!("{}", v)),
+                                    }
+                                    ui.next_column();
+                                }
+                            }
+                        });
+                        ui.columns(1, im_str!("movie#rowsend"), false);
+                    });
+
+                self.selected = selected;
+                if let Some((f, col, val)) = edit {
+                    movie.set(f, col, val);
+                    self.invalidate(f as i64);
+                }
+            });
+        cmd
+    }
+}
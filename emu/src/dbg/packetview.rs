@@ -0,0 +1,130 @@
+#[cfg(feature = "debugger")]
+use imgui::*;
+
+#[cfg(feature = "debugger")]
+use super::uisupport::*;
+#[cfg(feature = "debugger")]
+use super::UiCtx;
+
+/// A single packet (command + response) of a serial protocol transaction,
+/// as decoded by a [`PacketView`](trait.PacketView.html).
+pub struct Packet<'a> {
+    pub channel: usize, // Channel (port) the packet is addressed to
+    pub offset: usize,  // Offset of the packet within the transaction buffer
+    pub tx: &'a [u8],   // Bytes sent to the device
+    pub rx: &'a [u8],   // Bytes received from the device
+    pub desc: String,   // Human-readable description of the command
+    pub status: String, // Human-readable description of the status flags
+}
+
+/// A trait for an object that exchanges data with external devices through
+/// a packet-based protocol, using a shared memory buffer (eg: a serial bus).
+/// The view shows the last transactions, and decodes them into packets.
+pub trait PacketView {
+    /// Return the name of this object. The name will be composed
+    /// as "\[NAME\] Packets".
+    fn name(&self) -> &str;
+
+    /// Return the number of recorded transactions.
+    fn num_transactions(&self) -> usize;
+
+    /// Return the buffer of the specified transaction (0 = oldest), before
+    /// and after it was processed, plus a short label for it.
+    fn transaction(&self, idx: usize) -> (String, &[u8], &[u8]);
+
+    /// Decode a transaction into packets, given the buffers before and after
+    /// it was processed.
+    fn decode<'a>(&self, before: &'a [u8], after: &'a [u8]) -> Vec<Packet<'a>>;
+}
+
+#[cfg(feature = "debugger")]
+struct HexBytes<'a>(&'a [u8]);
+
+#[cfg(feature = "debugger")]
+impl<'a> std::fmt::Display for HexBytes<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "debugger")]
+pub(crate) fn render_packetview<'a, 'ui, PV: PacketView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    v: &mut PV,
+) {
+    ui.window(im_str!("[{}] Packets", v.name()))
+        .size((600.0, 400.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            let num = v.num_transactions();
+            if num == 0 {
+                ui.text(im_str!("No transactions recorded yet"));
+                return;
+            }
+
+            // List of transactions (newest first)
+            let sel = ctx
+                .packetview_sel
+                .entry(v.name().to_owned())
+                .or_insert(num - 1);
+            *sel = (*sel).min(num - 1);
+            ui.child_frame(im_str!("###transactions"), (150.0, 0.0))
+                .build(|| {
+                    for idx in (0..num).rev() {
+                        let (label, _, _) = v.transaction(idx);
+                        if ui.selectable(
+                            im_str!("{}###txn{}", label, idx),
+                            *sel == idx,
+                            ImGuiSelectableFlags::empty(),
+                            (0.0, 0.0),
+                        ) {
+                            *sel = idx;
+                        }
+                    }
+                });
+            ui.same_line(0.0);
+
+            let (_, before, after) = v.transaction(*sel);
+            let packets = v.decode(before, after);
+            ui.child_frame(im_str!("###packets"), (0.0, 0.0)).build(|| {
+                ui.columns(5, im_str!("packets#columns"), true);
+                for title in ["Ch", "Command", "TX", "RX", "Status"].iter() {
+                    ui.text(im_str!("{}", title));
+                    ui.next_column();
+                }
+                ui.separator();
+                for p in &packets {
+                    ui.text(im_str!("{}", p.channel));
+                    ui.next_column();
+                    ui.text(im_str!("{}", p.desc));
+                    ui.next_column();
+                    ui.text(im_str!("[{}] {}", p.tx.len(), HexBytes(p.tx)));
+                    ui.next_column();
+                    ui.text(im_str!("[{}] {}", p.rx.len(), HexBytes(p.rx)));
+                    ui.next_column();
+                    ui.text(im_str!("{}", p.status));
+                    ui.next_column();
+                }
+                ui.columns(1, im_str!("packets#end"), false);
+
+                ui.separator();
+                ui.text(im_str!("Before:"));
+                ui.text(im_str!("{}", hexdump(0, before)));
+                ui.text(im_str!("After:"));
+                ui.text(im_str!("{}", hexdump(0, after)));
+                if ui.small_button(im_str!("Copy")) {
+                    set_clipboard_text(&format!(
+                        "before:\n{}after:\n{}",
+                        hexdump(0, before),
+                        hexdump(0, after)
+                    ));
+                }
+            });
+        });
+}
@@ -1,5 +1,8 @@
+#[cfg(feature = "debugger")]
 use super::uisupport::*;
+#[cfg(feature = "debugger")]
 use super::UiCtx;
+#[cfg(feature = "debugger")]
 use imgui::*;
 
 pub enum RegisterSize<'a> {
@@ -14,12 +17,33 @@ pub enum RegisterSize<'a> {
 pub trait RegisterView {
     const WINDOW_SIZE: (f32, f32);
     const COLUMNS: usize;
-    fn name<'a>(&'a self) -> &'a str;
+    fn name(&self) -> &str;
     fn visit_regs<'s, F>(&'s mut self, col: usize, visit: F)
     where
         F: for<'a> FnMut(&'a str, RegisterSize<'a>, Option<&str>);
 }
 
+/// Return the value of the register with the specified name (as displayed
+/// in the register view), if any.
+pub fn read_register<RV: RegisterView>(v: &mut RV, name: &str) -> Option<u64> {
+    let mut found = None;
+    for col in 0..RV::COLUMNS {
+        v.visit_regs(col, |n, val, _| {
+            use self::RegisterSize::*;
+            if found.is_none() && n.eq_ignore_ascii_case(name) {
+                found = Some(match val {
+                    Reg8(v) => *v as u64,
+                    Reg16(v) => *v as u64,
+                    Reg32(v) => *v as u64,
+                    Reg64(v) => *v,
+                });
+            }
+        });
+    }
+    found
+}
+
+#[cfg(feature = "debugger")]
 pub(crate) fn render_regview<'a, 'ui, RV: RegisterView>(
     ui: &'a Ui<'ui>,
     _ctx: &mut UiCtx,
@@ -28,6 +52,11 @@ pub(crate) fn render_regview<'a, 'ui, RV: RegisterView>(
     ui.window(im_str!("[{}] Registers", v.name()))
         .size(RV::WINDOW_SIZE, ImGuiCond::FirstUseEver)
         .build(|| {
+            if ui.small_button(im_str!("Copy")) {
+                set_clipboard_text(&format_regs(v));
+            }
+            ui.separator();
+
             ui.columns(RV::COLUMNS as _, im_str!("columns"), true);
             for col in 0..RV::COLUMNS {
                 v.visit_regs(col, |name, val, desc| {
@@ -47,3 +76,25 @@ pub(crate) fn render_regview<'a, 'ui, RV: RegisterView>(
             }
         });
 }
+
+// Format all registers as plain text (one register per line), for exporting.
+#[cfg(feature = "debugger")]
+fn format_regs<RV: RegisterView>(v: &mut RV) -> String {
+    let mut out = String::new();
+    for col in 0..RV::COLUMNS {
+        v.visit_regs(col, |name, val, desc| {
+            use self::RegisterSize::*;
+            out += &match val {
+                Reg8(v) => format!("{:<8} {:02x}", name, v),
+                Reg16(v) => format!("{:<8} {:04x}", name, v),
+                Reg32(v) => format!("{:<8} {:08x}", name, v),
+                Reg64(v) => format!("{:<8} {:016x}", name, v),
+            };
+            if let Some(desc) = desc {
+                out += &format!("  {}", desc);
+            }
+            out += "\n";
+        });
+    }
+    out
+}
@@ -0,0 +1,108 @@
+use super::{Breakpoint, PausePoint, Tracepoint, ViewAnnotations, Watchpoint};
+#[cfg(feature = "frontend")]
+use failure::Error;
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+#[cfg(feature = "frontend")]
+use std::fs;
+use std::path::PathBuf;
+
+/// Directory where debugger sessions are saved (relative to the current
+/// directory, like imgui's debug.ini).
+#[cfg(feature = "frontend")]
+const SESSION_DIR: &str = "debug-sessions";
+
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct CpuSession {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    #[serde(default)]
+    pub tracepoints: Vec<Tracepoint>,
+    #[serde(default)]
+    pub watches: Vec<String>, // watch expressions
+}
+
+/// A DebuggerSession is the persisted state of a debugging session, that is
+/// all the information configured by the user while debugging a specific
+/// software (eg: a ROM). It is saved automatically when the debugger exits,
+/// and restored when the same software is debugged again.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct DebuggerSession {
+    pub cpus: HashMap<String, CpuSession>,
+    pub pause_points: Vec<PausePoint>,
+    #[serde(default)]
+    pub annotations: HashMap<String, ViewAnnotations>,
+    #[serde(default)]
+    pub hw_event_breaks: Vec<String>, // hardware events to break on
+    #[serde(default)]
+    pub symbol_files: Vec<PathBuf>,
+}
+
+#[cfg(feature = "frontend")]
+impl DebuggerSession {
+    fn path(id: &str) -> PathBuf {
+        // Make sure the identifier can be safely used as a filename.
+        let fname: String = id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        PathBuf::from(SESSION_DIR).join(fname + ".json")
+    }
+
+    /// Load the session with the specified identifier. If the session
+    /// doesn't exist yet, an empty session is returned.
+    pub(crate) fn load(id: &str) -> Result<DebuggerSession, Error> {
+        let path = Self::path(id);
+        if !path.exists() {
+            return Ok(DebuggerSession::default());
+        }
+        let data = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    /// Save the session with the specified identifier.
+    pub(crate) fn save(&self, id: &str) -> Result<(), Error> {
+        fs::create_dir_all(SESSION_DIR)?;
+        fs::write(Self::path(id), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Debugger;
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn round_trip() {
+        let symfile = std::env::temp_dir().join("r64emu-session-round-trip.sym");
+        fs::write(&symfile, "80001000 B gPlayer\n").unwrap();
+
+        let cpus = vec!["cpu".to_owned()];
+        let mut dbg = Debugger::new(&cpus);
+        dbg.add_pause_point(PausePoint::Frame(100));
+        dbg.add_watch("cpu", "a0");
+        dbg.add_watch("cpu", "[gPlayer+4]:d");
+        dbg.load_symbols(&symfile).unwrap();
+        let json = serde_json::to_string(&dbg.save_session()).unwrap();
+
+        let mut dbg = Debugger::new(&cpus);
+        let errors = dbg.load_session(serde_json::from_str(&json).unwrap());
+        assert!(errors.is_empty());
+        assert_eq!(dbg.pause_points(), &[PausePoint::Frame(100)]);
+        assert_eq!(dbg.watches("cpu"), &["a0", "[gPlayer+4]:d"]);
+        assert_eq!(dbg.symbols().files(), [symfile.as_path()]);
+        assert_eq!(dbg.symbols().lookup("gPlayer"), Some(0x8000_1000));
+        assert_eq!(serde_json::to_string(&dbg.save_session()).unwrap(), json);
+
+        // Symbol files that cannot be loaded anymore are reported, and the
+        // rest of the session is restored.
+        fs::remove_file(&symfile).unwrap();
+        let mut dbg = Debugger::new(&cpus);
+        let errors = dbg.load_session(serde_json::from_str(&json).unwrap());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(dbg.watches("cpu").len(), 2);
+    }
+}
@@ -0,0 +1,139 @@
+#[cfg(feature = "debugger")]
+use imgui::*;
+
+#[cfg(feature = "debugger")]
+use super::UiCtx;
+
+/// A trait for an object that stores files in a small file system (eg: a
+/// memory card), which can be browsed and edited from the debugger.
+///
+/// Entries are identified by their position in the list returned by
+/// `entries()`, which must be stable as long as the storage is not modified.
+pub trait StorageView {
+    /// Return the name of this object. The name will be composed
+    /// as "\[NAME\] Files".
+    fn name(&self) -> &str;
+
+    /// Return the titles of the columns describing each entry.
+    fn columns(&self) -> &[&str];
+
+    /// Return the list of entries, one string per column.
+    fn entries(&self) -> Vec<Vec<String>>;
+
+    /// Return a description of the space still available.
+    fn free_space(&self) -> String;
+
+    /// Delete the specified entry.
+    fn delete(&mut self, idx: usize) -> Result<(), String>;
+
+    /// Export the specified entry. Returns a suggested file name, and the
+    /// contents of the file.
+    fn export(&self, idx: usize) -> (String, Vec<u8>);
+
+    /// Import an entry from the contents of a file previously exported.
+    fn import(&mut self, data: &[u8]) -> Result<(), String>;
+}
+
+#[cfg(feature = "debugger")]
+pub(crate) fn render_storageview<'a, 'ui, SV: StorageView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    v: &mut SV,
+) {
+    ui.window(im_str!("[{}] Files", v.name()))
+        .size((450.0, 300.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            let entries = v.entries();
+            let mut sel = ctx.storageview.get(v.name()).cloned().unwrap_or_default();
+
+            ui.text(im_str!("{} entries, {}", entries.len(), v.free_space()));
+            if ui.small_button(im_str!("Import...")) {
+                ctx.dump_filename = ImString::with_capacity(256);
+                ui.open_popup(im_str!("###import"));
+            }
+            ui.popup(im_str!("###import"), || {
+                ui.text(im_str!("Import from file:"));
+                if ui
+                    .input_text(im_str!("###import#input"), &mut ctx.dump_filename)
+                    .enter_returns_true(true)
+                    .auto_select_all(true)
+                    .build()
+                {
+                    let fname = ctx.dump_filename.to_str().to_owned();
+                    let res = std::fs::read(&fname)
+                        .map_err(|err| err.to_string())
+                        .and_then(|data| v.import(&data));
+                    match res {
+                        Ok(()) => ctx.add_flash_msg(&format!("Imported {}", fname)),
+                        Err(err) => {
+                            ctx.add_flash_msg(&format!("Cannot import {}:\n{}", fname, err))
+                        }
+                    }
+                    ui.close_current_popup();
+                }
+            });
+            if let Some(idx) = sel.filter(|&idx| idx < entries.len()) {
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Export...")) {
+                    ctx.dump_filename = ImString::with_capacity(256);
+                    ctx.dump_filename.push_str(&v.export(idx).0);
+                    ui.open_popup(im_str!("###export"));
+                }
+                ui.popup(im_str!("###export"), || {
+                    ui.text(im_str!("Export to file:"));
+                    if ui
+                        .input_text(im_str!("###export#input"), &mut ctx.dump_filename)
+                        .enter_returns_true(true)
+                        .auto_select_all(true)
+                        .build()
+                    {
+                        let fname = ctx.dump_filename.to_str().to_owned();
+                        match std::fs::write(&fname, v.export(idx).1) {
+                            Ok(()) => ctx.add_flash_msg(&format!("Saved to {}", fname)),
+                            Err(err) => {
+                                ctx.add_flash_msg(&format!("Cannot write {}:\n{}", fname, err))
+                            }
+                        }
+                        ui.close_current_popup();
+                    }
+                });
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Delete")) {
+                    match v.delete(idx) {
+                        Ok(()) => sel = None,
+                        Err(err) => ctx.add_flash_msg(&format!("Cannot delete:\n{}", err)),
+                    }
+                }
+            }
+            ui.separator();
+
+            let columns = v.columns();
+            ui.columns(columns.len() as i32, im_str!("files#columns"), true);
+            for title in columns {
+                ui.text(im_str!("{}", title));
+                ui.next_column();
+            }
+            ui.separator();
+            for (idx, entry) in entries.iter().enumerate() {
+                for (col, field) in entry.iter().enumerate() {
+                    // The first column spans the whole row, to select it.
+                    if col == 0 {
+                        if ui.selectable(
+                            im_str!("{}###file{}", field, idx),
+                            sel == Some(idx),
+                            ImGuiSelectableFlags::SpanAllColumns,
+                            (0.0, 0.0),
+                        ) {
+                            sel = Some(idx);
+                        }
+                    } else {
+                        ui.text(im_str!("{}", field));
+                    }
+                    ui.next_column();
+                }
+            }
+            ui.columns(1, im_str!("files#end"), false);
+
+            ctx.storageview.insert(v.name().to_owned(), sel);
+        });
+}
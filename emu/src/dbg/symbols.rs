@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Symbols of the emulated software, loaded from symbol files in the format
+/// of nm (eg: `mips64-elf-nm game.elf > game.sym`): each line is
+/// `ADDRESS [TYPE] NAME`, with the address in hex.
+///
+/// Symbols can be used in place of addresses in the expressions of the
+/// debugger (tracepoints and watch expressions).
+#[derive(Default)]
+pub struct Symbols {
+    files: Vec<PathBuf>,
+    addrs: HashMap<String, u64>,
+}
+
+impl Symbols {
+    /// Load a symbol file, adding its symbols to the table. Loading again a
+    /// file already loaded refreshes its symbols.
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        self.parse(&text);
+        if !self.files.iter().any(|f| f == path) {
+            self.files.push(path.to_owned());
+        }
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) {
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 2 {
+                continue;
+            }
+            if let Ok(addr) = u64::from_str_radix(fields[0], 16) {
+                self.addrs.insert(fields[fields.len() - 1].to_owned(), addr);
+            }
+        }
+    }
+
+    /// Return the files that were loaded, in loading order.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Return the address of the specified symbol.
+    pub fn lookup(&self, name: &str) -> Option<u64> {
+        self.addrs.get(name).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let mut syms = Symbols::default();
+        syms.parse(
+            "80000400 T main\n\
+             80001000 B gPlayerState\n\
+             U printf\n",
+        );
+        assert_eq!(syms.lookup("main"), Some(0x8000_0400));
+        assert_eq!(syms.lookup("gPlayerState"), Some(0x8000_1000));
+        assert_eq!(syms.lookup("printf"), None);
+    }
+}
@@ -0,0 +1,313 @@
+#[cfg(feature = "frontend")]
+use imgui::*;
+
+#[cfg(feature = "frontend")]
+use super::UiCtx;
+
+use std::collections::VecDeque;
+
+/// Number of complete frames kept in the timeline history.
+const HISTORY_FRAMES: usize = 16;
+
+/// A memory probe records every read of a specific address performed
+/// by a CPU as a sample of a timeline signal (the value read, masked with
+/// mask). It is used to visualize polling loops (eg: a CPU spinning on a
+/// status register).
+#[derive(Clone, Debug)]
+pub struct MemProbe {
+    pub cpu: String,
+    pub addr: u64,
+    pub mask: u64,
+    pub signal: String,
+}
+
+// Samples of all signals during a single frame. Each signal holds, for each
+// scanline, the last value sampled during that line (if any).
+// Activity tracks (for the frame graph) hold the spans of lines (first, last)
+// during which a subsystem was marked as active.
+struct TimelineFrame {
+    frame: i64,
+    lines: usize,
+    signals: Vec<(String, Vec<Option<u64>>)>,
+    tracks: Vec<(String, Vec<(usize, usize)>)>,
+}
+
+impl TimelineFrame {
+    fn new(frame: i64) -> Self {
+        Self {
+            frame,
+            lines: 0,
+            signals: Vec::new(),
+            tracks: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.signals.is_empty() && self.tracks.is_empty()
+    }
+
+    fn mark(&mut self, track: &str, line: usize) {
+        let idx = match self.tracks.iter().position(|(name, _)| name == track) {
+            Some(idx) => idx,
+            None => {
+                self.tracks.push((track.to_owned(), Vec::new()));
+                self.tracks.len() - 1
+            }
+        };
+        let spans = &mut self.tracks[idx].1;
+        match spans.last_mut() {
+            // Extend the last span if the track was active until the previous line
+            Some((_, last)) if *last + 1 >= line => *last = line,
+            _ => spans.push((line, line)),
+        }
+    }
+
+    fn record(&mut self, signal: &str, line: usize, value: u64) {
+        let idx = match self.signals.iter().position(|(name, _)| name == signal) {
+            Some(idx) => idx,
+            None => {
+                self.signals.push((signal.to_owned(), Vec::new()));
+                self.signals.len() - 1
+            }
+        };
+        let lines = &mut self.signals[idx].1;
+        if lines.len() <= line {
+            lines.resize(line + 1, None);
+        }
+        lines[line] = Some(value);
+    }
+}
+
+/// A Timeline collects samples of named signals at scanline resolution,
+/// as reported through Tracer::trace_signal() or memory probes. Samples are
+/// drawn in two colors depending on whether their value is zero or not.
+pub(crate) struct Timeline {
+    line: usize,
+    current: TimelineFrame,
+    history: VecDeque<TimelineFrame>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            line: 0,
+            current: TimelineFrame::new(0),
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl Timeline {
+    // Update the current emulation position; called once per scanline.
+    pub(crate) fn set_position(&mut self, frame: i64, line: usize) {
+        if frame != self.current.frame {
+            let old = std::mem::replace(&mut self.current, TimelineFrame::new(frame));
+            if !old.is_empty() {
+                self.history.push_back(old);
+                if self.history.len() > HISTORY_FRAMES {
+                    self.history.pop_front();
+                }
+            }
+        }
+        self.line = line;
+        self.current.lines = self.current.lines.max(line + 1);
+    }
+
+    pub(crate) fn record(&mut self, signal: &str, value: u64) {
+        let line = self.line;
+        self.current.record(signal, line, value);
+    }
+
+    pub(crate) fn mark(&mut self, track: &str) {
+        let line = self.line;
+        self.current.mark(track, line);
+    }
+}
+
+#[cfg(feature = "frontend")]
+fn color(r: usize, g: usize, b: usize) -> ImVec4 {
+    ImVec4::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0)
+}
+
+#[cfg(feature = "frontend")]
+pub(crate) fn render_timeline(ui: &Ui<'_>, ctx: &mut UiCtx, timeline: &Timeline) {
+    ui.window(im_str!("Timeline"))
+        .size((600.0, 200.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            if timeline.history.is_empty() {
+                ui.text(im_str!("No complete frame traced yet"));
+                return;
+            }
+
+            // Select which frame to display (0 = last complete frame)
+            let max = timeline.history.len() as i32 - 1;
+            ctx.timeline_frame = ctx.timeline_frame.min(max).max(0);
+            ui.slider_int(im_str!("Frames ago"), &mut ctx.timeline_frame, 0, max)
+                .build();
+            let tf = &timeline.history[(max - ctx.timeline_frame) as usize];
+            ui.same_line(0.0);
+            ui.text(im_str!("(frame {})", tf.frame));
+
+            ui.text_colored(color(165, 224, 46), im_str!("[zero]"));
+            ui.same_line(0.0);
+            ui.text_colored(color(249, 38, 114), im_str!("[non-zero]"));
+            ui.separator();
+
+            let num_lines = tf
+                .signals
+                .iter()
+                .map(|(_, lines)| lines.len())
+                .max()
+                .unwrap_or(0)
+                .max(1);
+
+            const LABEL_WIDTH: f32 = 180.0;
+            const TRACK_HEIGHT: f32 = 14.0;
+            for (name, lines) in &tf.signals {
+                ui.text(im_str!("{}", name));
+                ui.same_line(LABEL_WIDTH);
+
+                let pos = ui.get_cursor_screen_pos();
+                let width = ui.get_content_region_avail().0.max(1.0);
+                let lw = width / num_lines as f32;
+                let dl = ui.get_window_draw_list();
+                let bg = color(39, 40, 34);
+                dl.add_rect_filled_multicolor(
+                    pos,
+                    (pos.0 + width, pos.1 + TRACK_HEIGHT),
+                    bg,
+                    bg,
+                    bg,
+                    bg,
+                );
+                for (line, val) in lines.iter().enumerate() {
+                    if let Some(val) = val {
+                        let c = if *val == 0 {
+                            color(165, 224, 46)
+                        } else {
+                            color(249, 38, 114)
+                        };
+                        let x = pos.0 + line as f32 * lw;
+                        dl.add_rect_filled_multicolor(
+                            (x, pos.1),
+                            (x + lw.max(1.0), pos.1 + TRACK_HEIGHT),
+                            c,
+                            c,
+                            c,
+                            c,
+                        );
+                    }
+                }
+
+                // Tooltip with the scanline under the mouse
+                ui.invisible_button(im_str!("###timeline#{}", name), (width, TRACK_HEIGHT));
+                if ui.is_item_hovered() {
+                    let mx = ui.imgui().mouse_pos().0;
+                    let line = ((mx - pos.0) / lw) as usize;
+                    match lines.get(line).cloned().unwrap_or(None) {
+                        Some(val) => {
+                            ui.tooltip_text(im_str!("line {}: {:x}", line, val));
+                        }
+                        None => ui.tooltip_text(im_str!("line {}: no samples", line)),
+                    }
+                }
+            }
+        });
+}
+
+// Palette used for the frame graph tracks.
+#[cfg(feature = "frontend")]
+const TRACK_COLORS: [(usize, usize, usize); 6] = [
+    (102, 217, 239),
+    (165, 224, 46),
+    (249, 38, 114),
+    (253, 151, 31),
+    (174, 129, 255),
+    (230, 219, 116),
+];
+
+/// Render the frame graph: a Gantt-style chart showing, for each subsystem,
+/// during which part of the frame it was active (as reported through
+/// Tracer::trace_activity()).
+#[cfg(feature = "frontend")]
+pub(crate) fn render_frame_graph(ui: &Ui<'_>, ctx: &mut UiCtx, timeline: &Timeline) {
+    ui.window(im_str!("Frame graph"))
+        .size((600.0, 200.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            let frames: Vec<&TimelineFrame> = timeline
+                .history
+                .iter()
+                .filter(|tf| !tf.tracks.is_empty())
+                .collect();
+            if frames.is_empty() {
+                ui.text(im_str!("No complete frame traced yet"));
+                return;
+            }
+
+            let max = frames.len() as i32 - 1;
+            ctx.framegraph_frame = ctx.framegraph_frame.min(max).max(0);
+            ui.slider_int(im_str!("Frames ago"), &mut ctx.framegraph_frame, 0, max)
+                .build();
+            let tf = frames[(max - ctx.framegraph_frame) as usize];
+            ui.same_line(0.0);
+            ui.text(im_str!("(frame {}, {} lines)", tf.frame, tf.lines));
+            ui.separator();
+
+            const LABEL_WIDTH: f32 = 180.0;
+            const TRACK_HEIGHT: f32 = 14.0;
+            let num_lines = tf.lines.max(1);
+            for (idx, (name, spans)) in tf.tracks.iter().enumerate() {
+                let busy: usize = spans.iter().map(|(first, last)| last - first + 1).sum();
+                ui.text(im_str!(
+                    "{} ({:.0}%)",
+                    name,
+                    busy as f32 * 100.0 / num_lines as f32
+                ));
+                ui.same_line(LABEL_WIDTH);
+
+                let pos = ui.get_cursor_screen_pos();
+                let width = ui.get_content_region_avail().0.max(1.0);
+                let lw = width / num_lines as f32;
+                let dl = ui.get_window_draw_list();
+                let bg = color(39, 40, 34);
+                dl.add_rect_filled_multicolor(
+                    pos,
+                    (pos.0 + width, pos.1 + TRACK_HEIGHT),
+                    bg,
+                    bg,
+                    bg,
+                    bg,
+                );
+                let (r, g, b) = TRACK_COLORS[idx % TRACK_COLORS.len()];
+                let c = color(r, g, b);
+                for (first, last) in spans {
+                    let x0 = pos.0 + *first as f32 * lw;
+                    let x1 = pos.0 + (*last + 1) as f32 * lw;
+                    dl.add_rect_filled_multicolor(
+                        (x0, pos.1),
+                        (x1.max(x0 + 1.0), pos.1 + TRACK_HEIGHT),
+                        c,
+                        c,
+                        c,
+                        c,
+                    );
+                }
+
+                // Tooltip with the span under the mouse
+                ui.invisible_button(im_str!("###framegraph#{}", name), (width, TRACK_HEIGHT));
+                if ui.is_item_hovered() {
+                    let mx = ui.imgui().mouse_pos().0;
+                    let line = ((mx - pos.0) / lw) as usize;
+                    match spans
+                        .iter()
+                        .find(|(first, last)| *first <= line && line <= *last)
+                    {
+                        Some((first, last)) => {
+                            ui.tooltip_text(im_str!("lines {}-{}", first, last));
+                        }
+                        None => ui.tooltip_text(im_str!("line {}: idle", line)),
+                    }
+                }
+            }
+        });
+}
@@ -1,16 +1,34 @@
-use super::uisupport::imgui_input_hex;
+use super::chrometrace::ChromeTrace;
+#[cfg(any(feature = "frontend", test))]
+use super::session::{CpuSession, DebuggerSession};
+use super::symbols::Symbols;
+#[cfg(feature = "frontend")]
+use super::timeline::{render_frame_graph, render_timeline};
+use super::timeline::{MemProbe, Timeline};
+#[cfg(feature = "frontend")]
+use super::uisupport::{imgui_input_hex, set_clipboard_text};
+#[cfg(feature = "frontend")]
+use super::DebuggerModel;
+#[cfg(feature = "frontend")]
 use super::UiCtx;
 use array_macro::array;
 use bitflags::bitflags;
+#[cfg(feature = "frontend")]
 use imgui::*;
+use serde_derive::{Deserialize, Serialize};
 
+use crate::clock::ClockDomain;
 use crate::memint::{AccessSize, MemInt};
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
 use std::time::Instant;
 
+// Maximum number of lines kept in the log window.
+const MAX_LOG_LINES: usize = 10000;
+
 #[derive(Debug, Clone)]
 pub enum TraceEvent {
     Poll(),    // Fake event used to poll back into the tracer to improve responsiveness
@@ -21,6 +39,41 @@ pub enum TraceEvent {
     WatchpointWrite(String, usize), // A watchpoint was hit during a write (cpu_idx, wp_idx)
     WatchpointRead(String, usize), // A watchpoint was hit during a read (cpu_idx, wp_idx)
     GenericBreak(String), // Another kind of condition was hit, and we want to stop the tracing.
+    PausePoint(usize), // A pause point was reached (pp_idx)
+    HwEvent(String), // A hardware event selected for breaking was reported (name)
+    Tracepoint(String, usize, u64), // A tracepoint was hit (cpu_idx, tp_idx, pc)
+}
+
+/// A point in emulated time at which the debugger stops emulation.
+///
+/// Pause points are checked once per scanline, so cycle-based pause points
+/// will stop emulation at the first scanline boundary after the requested
+/// cycle. They are one-shot: they are removed as soon as they are hit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PausePoint {
+    Cycle(i64),      // Absolute number of cycles since the beginning of emulation
+    Frame(i64),      // Absolute number of frames since the beginning of emulation
+    Scanline(usize), // Next time the video emulation reaches this scanline
+}
+
+impl PausePoint {
+    fn check(&self, frame: i64, line: usize, cycles: i64) -> bool {
+        match *self {
+            PausePoint::Cycle(c) => cycles >= c,
+            PausePoint::Frame(f) => frame >= f,
+            PausePoint::Scanline(l) => line == l,
+        }
+    }
+}
+
+impl std::fmt::Display for PausePoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            PausePoint::Cycle(c) => write!(f, "cycle {}", c),
+            PausePoint::Frame(fr) => write!(f, "frame {}", fr),
+            PausePoint::Scanline(l) => write!(f, "scanline {}", l),
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Box<TraceEvent>>;
@@ -60,12 +113,19 @@ impl Tracer<'_> {
         }
     }
 
+    /// Return true if the tracer is connected to a real debugger, so that
+    /// information useful only for debugging can be collected.
+    #[inline(always)]
+    pub fn is_active(&self) -> bool {
+        self.dbg.is_some()
+    }
+
     #[inline(always)]
     pub fn break_here(&self, msg: &str) -> Result<()> {
         if self.dbg.is_none() {
             return Ok(());
         }
-        Err(box TraceEvent::GenericBreak(msg.to_owned()))
+        Err(Box::new(TraceEvent::GenericBreak(msg.to_owned())))
     }
 
     #[inline(always)]
@@ -73,12 +133,58 @@ impl Tracer<'_> {
         if self.dbg.is_none() {
             panic!("{}", msg);
         }
-        Err(box TraceEvent::GenericBreak(msg.to_owned()))
+        Err(Box::new(TraceEvent::GenericBreak(msg.to_owned())))
+    }
+
+    #[inline(always)]
+    pub fn trace_gpu(&self, frame: i64, line: usize, cycles: i64) -> Result<()> {
+        self.dbg
+            .map(|t| t.trace_gpu(frame, line, cycles))
+            .unwrap_or(Ok(()))
+    }
+
+    /// Record a sample of a named signal into the debugger timeline, at the
+    /// current scanline.
+    #[inline(always)]
+    pub fn trace_signal(&self, signal: &str, value: u64) {
+        if let Some(dbg) = self.dbg {
+            dbg.record_signal(signal, value);
+        }
+    }
+
+    /// Mark a track of the frame graph as active at the current scanline.
+    /// Call it once per scanline for activities that span over time (eg: a
+    /// running RSP task), or once for instantaneous events (eg: an interrupt).
+    #[inline(always)]
+    pub fn trace_activity(&self, track: &str) {
+        if let Some(dbg) = self.dbg {
+            dbg.timeline.borrow_mut().mark(track);
+            if let Some(ct) = dbg.chrome_trace.borrow_mut().as_mut() {
+                ct.mark(track);
+            }
+        }
+    }
+
+    /// Report that the scheduler ran a subsystem from cycle start to cycle
+    /// end (in the specified clock domain). It is only recorded while
+    /// capturing a Chrome trace.
+    #[inline(always)]
+    pub fn trace_subsystem(&self, name: &str, clock: &ClockDomain, start: i64, end: i64) {
+        if let Some(dbg) = self.dbg {
+            if let Some(ct) = dbg.chrome_trace.borrow_mut().as_mut() {
+                ct.slice(name, clock, start, end);
+            }
+        }
     }
 
+    /// Report a hardware event (eg: a DMA transfer completed). Emulation
+    /// stops if the user selected to break on it.
     #[inline(always)]
-    pub fn trace_gpu(&self, line: usize) -> Result<()> {
-        self.dbg.map(|t| t.trace_gpu(line)).unwrap_or(Ok(()))
+    pub fn trace_hw_event(&self, name: &str) -> Result<()> {
+        match self.dbg {
+            Some(dbg) => dbg.trace_hw_event(name),
+            None => Ok(()),
+        }
     }
 
     #[inline(always)]
@@ -130,7 +236,7 @@ impl Tracer<'_> {
     }
 }
 
-#[derive(Eq)]
+#[derive(Clone, Eq, Serialize, Deserialize)]
 pub(crate) struct Breakpoint {
     active: bool,
     pc: u64,
@@ -155,13 +261,113 @@ impl PartialEq for Breakpoint {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, PartialOrd, Eq, Ord)]
+/// A tracepoint is like a breakpoint, but instead of stopping emulation it
+/// appends a line to the log, built from a format string that can reference
+/// registers and memory (see format_tracepoint).
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Tracepoint {
+    active: bool,
+    pc: u64,
+    format: String,
+}
+
+/// Expand the format string of a tracepoint. Placeholders are:
+///
+///   * `{reg}`: value of a register, in hex (eg: `{a0}`); append `:d` for
+///     decimal (eg: `{a0:d}`).
+///   * `{[expr]}`: 32-bit word in memory at the specified address, which can
+///     be a sum of registers and hex values (eg: `{[sp+10]}`, `{[80001000]}`);
+///     `:d` can be used as well.
+///   * `{{` and `}}`: literal braces.
+///
+/// Registers and memory are read from the specified CPU of the model, and
+/// symbols can be used in place of hex values. Placeholders that cannot be
+/// evaluated are replaced by `??`.
+#[cfg(feature = "frontend")]
+pub(crate) fn format_tracepoint<T: DebuggerModel>(
+    format: &str,
+    model: &mut T,
+    cpu_name: &str,
+    symbols: &Symbols,
+) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let expr: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let (expr, dec) = match expr.trim().rsplitn(2, ':').collect::<Vec<_>>()[..] {
+                    ["d", expr] => (expr, true),
+                    _ => (expr.trim(), false),
+                };
+                let val = if expr.starts_with('[') && expr.ends_with(']') {
+                    eval_address(&expr[1..expr.len() - 1], model, cpu_name, symbols)
+                        .and_then(|addr| model.cpu_peek(cpu_name, addr))
+                        .map(|v| v as u64)
+                } else {
+                    model.cpu_register(cpu_name, expr)
+                };
+                out += &match val {
+                    Some(v) if dec => format!("{}", v as i64),
+                    Some(v) => format!("{:x}", v),
+                    None => "??".to_owned(),
+                };
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Evaluate an address expression of a tracepoint: a sum (or difference) of
+// registers, symbols and hex values.
+#[cfg(feature = "frontend")]
+fn eval_address<T: DebuggerModel>(
+    expr: &str,
+    model: &mut T,
+    cpu_name: &str,
+    symbols: &Symbols,
+) -> Option<u64> {
+    let mut addr = 0u64;
+    let mut neg = false;
+    let mut term = String::new();
+    for c in expr.chars().chain(Some('+')) {
+        match c {
+            '+' | '-' => {
+                let term = std::mem::take(&mut term);
+                let term = term.trim();
+                let val = model
+                    .cpu_register(cpu_name, term)
+                    .or_else(|| symbols.lookup(term))
+                    .or_else(|| u64::from_str_radix(term.trim_start_matches("0x"), 16).ok())?;
+                addr = if neg {
+                    addr.wrapping_sub(val)
+                } else {
+                    addr.wrapping_add(val)
+                };
+                neg = c == '-';
+            }
+            c => term.push(c),
+        }
+    }
+    Some(addr)
+}
+
+#[derive(Copy, Clone, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
 pub(crate) enum WatchpointType {
     Read,
     Write,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) enum WatchpointCondition {
     Always,
     Eq(u64), // equal to
@@ -188,7 +394,7 @@ impl WatchpointCondition {
     }
 }
 
-#[derive(Eq)]
+#[derive(Clone, Eq, Serialize, Deserialize)]
 pub(crate) struct Watchpoint {
     active: bool,
     addr: u64,
@@ -197,13 +403,14 @@ pub(crate) struct Watchpoint {
     description: String,
 }
 
+#[cfg(feature = "frontend")]
 impl Watchpoint {
     fn cond_to_string(&self) -> String {
         use self::WatchpointCondition::*;
         use self::WatchpointType::*;
         match self.wtype {
             Read => match self.condition {
-                Always => format!("Any read"),
+                Always => "Any read".to_string(),
                 Eq(cmp) => format!("Value read == 0x{:x}", cmp),
                 Ne(cmp) => format!("Value read != 0x{:x}", cmp),
                 Gt(cmp) => format!("Value read > 0x{:x}", cmp),
@@ -212,7 +419,7 @@ impl Watchpoint {
                 Le(cmp) => format!("Value read <= 0x{:x}", cmp),
             },
             Write => match self.condition {
-                Always => format!("Any write"),
+                Always => "Any write".to_string(),
                 Eq(cmp) => format!("Value written == 0x{:x}", cmp),
                 Ne(cmp) => format!("Value written != 0x{:x}", cmp),
                 Gt(cmp) => format!("Value written > 0x{:x}", cmp),
@@ -248,18 +455,23 @@ impl PartialEq for Watchpoint {
 struct DbgCpu {
     breakpoints: Vec<Breakpoint>,
     watchpoints: Vec<Watchpoint>,
+    tracepoints: Vec<Tracepoint>,
+    watches: Vec<String>, // watch expressions (see format_tracepoint)
 
     bp_oneshot: Option<u64>, // Special one-shot breakpoint
 
     bp_fastmap: IntHashMap<u64, usize>,
     wp_fastmap: IntHashMap<u64, usize>,
+    tp_fastmap: IntHashMap<u64, usize>,
+
+    probes: IntHashMap<u64, (u64, String)>, // Memory probes (addr -> mask, timeline signal)
 }
 
 impl DbgCpu {
     fn add_breakpoint(&mut self, pc: u64, description: &str) {
         self.breakpoints.push(Breakpoint {
             active: true,
-            pc: pc,
+            pc,
             description: description.to_owned(),
         });
         self.update_bp_fastmap();
@@ -269,6 +481,15 @@ impl DbgCpu {
         self.bp_oneshot = pc;
     }
 
+    fn add_tracepoint(&mut self, pc: u64, format: &str) {
+        self.tracepoints.push(Tracepoint {
+            active: true,
+            pc,
+            format: format.to_owned(),
+        });
+        self.update_tp_fastmap();
+    }
+
     fn add_watchpoint(
         &mut self,
         addr: u64,
@@ -306,11 +527,27 @@ impl DbgCpu {
             .map(|(idx, wp)| (wp.addr, idx))
             .collect();
     }
+    fn update_tp_fastmap(&mut self) {
+        self.tracepoints.sort_by_key(|tp| tp.pc);
+        self.tp_fastmap = self
+            .tracepoints
+            .iter()
+            .enumerate()
+            .filter(|(_, tp)| tp.active)
+            .map(|(idx, tp)| (tp.pc, idx))
+            .collect();
+    }
 }
 
 pub struct Debugger {
     cpus: HashMap<String, DbgCpu>,
+    pause_points: Vec<PausePoint>,
+    hw_events: Vec<(String, bool)>, // hardware events = break on them
+    log: VecDeque<String>,          // lines appended by tracepoints
     next_poll: Cell<Option<Instant>>,
+    timeline: RefCell<Timeline>,
+    chrome_trace: RefCell<Option<ChromeTrace>>,
+    symbols: Symbols,
 }
 
 impl Debugger {
@@ -322,7 +559,13 @@ impl Debugger {
 
         Self {
             cpus: cpumap,
+            pause_points: Vec::new(),
+            hw_events: Vec::new(),
+            log: VecDeque::new(),
             next_poll: Cell::new(None),
+            timeline: RefCell::new(Timeline::default()),
+            chrome_trace: RefCell::new(None),
+            symbols: Symbols::default(),
         }
     }
 
@@ -334,7 +577,7 @@ impl Debugger {
     }
 
     pub fn disable_breakpoint_oneshot(&mut self) {
-        for (_, cpu) in &mut self.cpus {
+        for cpu in self.cpus.values_mut() {
             cpu.set_breakpoint_oneshot(None);
         }
     }
@@ -349,15 +592,221 @@ impl Debugger {
             .unwrap()
             .add_breakpoint(pc, description);
     }
+
+    /// Remove the breakpoints at the specified pc that were added with the
+    /// specified description (eg: by a remote debugger), if any.
+    pub fn remove_breakpoint(&mut self, cpu_name: &str, pc: u64, description: &str) {
+        let cpu = self.cpus.get_mut(cpu_name).unwrap();
+        cpu.breakpoints
+            .retain(|bp| bp.pc != pc || bp.description != description);
+        cpu.update_bp_fastmap();
+    }
+
+    pub(crate) fn add_watchpoint(
+        &mut self,
+        cpu_name: &str,
+        addr: u64,
+        wtype: WatchpointType,
+        description: &str,
+    ) {
+        self.cpus.get_mut(cpu_name).unwrap().add_watchpoint(
+            addr,
+            description,
+            wtype,
+            WatchpointCondition::Always,
+        );
+    }
+
+    pub(crate) fn remove_watchpoint(
+        &mut self,
+        cpu_name: &str,
+        addr: u64,
+        wtype: WatchpointType,
+        description: &str,
+    ) {
+        let cpu = self.cpus.get_mut(cpu_name).unwrap();
+        cpu.watchpoints
+            .retain(|wp| wp.addr != addr || wp.wtype != wtype || wp.description != description);
+        cpu.update_wp_fastmap();
+    }
+
+    /// Return the address of a watchpoint, as reported by
+    /// TraceEvent::WatchpointRead and TraceEvent::WatchpointWrite.
+    pub fn watchpoint_addr(&self, cpu_name: &str, idx: usize) -> u64 {
+        self.cpus[cpu_name].watchpoints[idx].addr
+    }
+
+    pub fn add_tracepoint(&mut self, cpu_name: &str, pc: u64, format: &str) {
+        self.cpus
+            .get_mut(cpu_name)
+            .unwrap()
+            .add_tracepoint(pc, format);
+    }
+
+    /// Return the format string of a tracepoint, as reported by
+    /// TraceEvent::Tracepoint.
+    pub fn tracepoint_format(&self, cpu_name: &str, idx: usize) -> &str {
+        &self.cpus[cpu_name].tracepoints[idx].format
+    }
+
+    /// Append a line to the log window. Older lines are discarded when the
+    /// log grows too much.
+    pub fn log(&mut self, line: String) {
+        if self.log.len() == MAX_LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+
+    /// Load a symbol file (see [`Symbols`](struct.Symbols.html)).
+    pub fn load_symbols(&mut self, path: &Path) -> std::io::Result<()> {
+        self.symbols.load(path)
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Add a watch expression, displayed in the watch window of the
+    /// specified CPU. The syntax is that of the placeholders of tracepoints,
+    /// without the braces (eg: `a0`, `[sp+10]:d`).
+    pub fn add_watch(&mut self, cpu_name: &str, expr: &str) {
+        self.cpus
+            .get_mut(cpu_name)
+            .unwrap()
+            .watches
+            .push(expr.to_owned());
+    }
+
+    pub fn watches(&self, cpu_name: &str) -> &[String] {
+        &self.cpus[cpu_name].watches
+    }
+
+    pub fn add_mem_probe(&mut self, probe: MemProbe) {
+        self.cpus
+            .get_mut(&probe.cpu)
+            .unwrap()
+            .probes
+            .insert(probe.addr, (probe.mask, probe.signal));
+    }
+
+    pub fn add_pause_point(&mut self, pp: PausePoint) {
+        self.pause_points.push(pp);
+    }
+
+    pub fn remove_pause_point(&mut self, idx: usize) -> PausePoint {
+        self.pause_points.remove(idx)
+    }
+
+    pub fn pause_points(&self) -> &[PausePoint] {
+        &self.pause_points
+    }
+
+    /// Set the names of the hardware events reported by the emulator, on
+    /// which the user can select to break.
+    pub fn set_hw_events(&mut self, names: Vec<String>) {
+        self.hw_events = names.into_iter().map(|name| (name, false)).collect();
+    }
+
+    /// Select whether to break on the specified hardware event.
+    pub fn set_hw_event_break(&mut self, name: &str, enabled: bool) {
+        for (evt, brk) in self.hw_events.iter_mut() {
+            if evt == name {
+                *brk = enabled;
+            }
+        }
+    }
+
+    /// Start capturing tracer events into a Chrome trace. If a capture is
+    /// already running, it is discarded.
+    pub fn start_chrome_trace(&mut self) {
+        *self.chrome_trace.get_mut() = Some(ChromeTrace::default());
+    }
+
+    /// Stop capturing tracer events, and return the capture (if any).
+    pub fn stop_chrome_trace(&mut self) -> Option<ChromeTrace> {
+        self.chrome_trace.get_mut().take()
+    }
+
+    pub fn is_capturing_chrome_trace(&self) -> bool {
+        self.chrome_trace.borrow().is_some()
+    }
+
+    fn record_signal(&self, signal: &str, value: u64) {
+        self.timeline.borrow_mut().record(signal, value);
+        if let Some(ct) = self.chrome_trace.borrow_mut().as_mut() {
+            ct.record(signal, value);
+        }
+    }
+
+    /// Create a session object containing all the user-configured state
+    /// of the debugger, that can be persisted.
+    #[cfg(any(feature = "frontend", test))]
+    pub(crate) fn save_session(&self) -> DebuggerSession {
+        let mut session = DebuggerSession::default();
+        for (name, cpu) in &self.cpus {
+            session.cpus.insert(
+                name.clone(),
+                CpuSession {
+                    breakpoints: cpu.breakpoints.clone(),
+                    watchpoints: cpu.watchpoints.clone(),
+                    tracepoints: cpu.tracepoints.clone(),
+                    watches: cpu.watches.clone(),
+                },
+            );
+        }
+        session.pause_points = self.pause_points.clone();
+        session.symbol_files = self.symbols.files().to_vec();
+        session.hw_event_breaks = self
+            .hw_events
+            .iter()
+            .filter(|(_, brk)| *brk)
+            .map(|(name, _)| name.clone())
+            .collect();
+        session
+    }
+
+    /// Restore the state of the debugger from a session object. Information
+    /// about CPUs that don't exist (anymore) is ignored, as are symbol files
+    /// that cannot be loaded (anymore): their errors are returned.
+    #[cfg(any(feature = "frontend", test))]
+    pub(crate) fn load_session(&mut self, session: DebuggerSession) -> Vec<String> {
+        for (name, cs) in session.cpus {
+            if let Some(cpu) = self.cpus.get_mut(&name) {
+                cpu.breakpoints = cs.breakpoints;
+                cpu.watchpoints = cs.watchpoints;
+                cpu.tracepoints = cs.tracepoints;
+                cpu.watches = cs.watches;
+                cpu.update_bp_fastmap();
+                cpu.update_wp_fastmap();
+                cpu.update_tp_fastmap();
+            }
+        }
+        self.pause_points = session.pause_points;
+        for name in session.hw_event_breaks {
+            self.set_hw_event_break(&name, true);
+        }
+
+        let mut errors = Vec::new();
+        for path in session.symbol_files {
+            if let Err(err) = self.symbols.load(&path) {
+                errors.push(format!("cannot load symbols {}: {}", path.display(), err));
+            }
+        }
+        errors
+    }
 }
 
 impl Debugger {
-    pub fn new_tracer(&self) -> Tracer {
+    pub fn new_tracer(&self) -> Tracer<'_> {
         let mut trace_guards = array![TraceGuard::empty(); 256];
-        for (_, cpu) in &self.cpus {
+        for cpu in self.cpus.values() {
             for bp in &cpu.breakpoints {
                 trace_guards[TraceGuard::index(bp.pc)].insert(TraceGuard::INSN);
             }
+            for tp in &cpu.tracepoints {
+                trace_guards[TraceGuard::index(tp.pc)].insert(TraceGuard::INSN);
+            }
             if let Some(pc) = cpu.bp_oneshot {
                 trace_guards[TraceGuard::index(pc)].insert(TraceGuard::INSN);
             }
@@ -367,33 +816,61 @@ impl Debugger {
                     WatchpointType::Write => TraceGuard::MEM_WRITE,
                 });
             }
+            for addr in cpu.probes.keys() {
+                trace_guards[TraceGuard::index(*addr)].insert(TraceGuard::MEM_READ);
+            }
         }
         Tracer {
-            dbg: Some(&self),
-            trace_guards: trace_guards,
+            dbg: Some(self),
+            trace_guards,
         }
     }
 
     fn trace_insn(&self, cpu_name: &str, pc: u64) -> Result<()> {
         let cpu = &self.cpus[cpu_name];
         match cpu.bp_fastmap.get(&pc) {
-            Some(idx) => Err(box TraceEvent::Breakpoint(cpu_name.to_owned(), *idx, pc)),
+            Some(idx) => Err(Box::new(TraceEvent::Breakpoint(
+                cpu_name.to_owned(),
+                *idx,
+                pc,
+            ))),
             None => match cpu.bp_oneshot {
-                Some(bp_pc) if bp_pc == pc => {
-                    Err(box TraceEvent::BreakpointOneShot(cpu_name.to_owned(), pc))
-                }
-                _ => Ok(()),
+                Some(bp_pc) if bp_pc == pc => Err(Box::new(TraceEvent::BreakpointOneShot(
+                    cpu_name.to_owned(),
+                    pc,
+                ))),
+                _ => match cpu.tp_fastmap.get(&pc) {
+                    Some(idx) => Err(Box::new(TraceEvent::Tracepoint(
+                        cpu_name.to_owned(),
+                        *idx,
+                        pc,
+                    ))),
+                    None => Ok(()),
+                },
             },
         }
     }
 
+    fn trace_hw_event(&self, name: &str) -> Result<()> {
+        if self.hw_events.iter().any(|(evt, brk)| *brk && evt == name) {
+            return Err(Box::new(TraceEvent::HwEvent(name.to_owned())));
+        }
+        Ok(())
+    }
+
     fn trace_mem_read(&self, cpu_name: &str, addr: u64, _size: AccessSize, val: u64) -> Result<()> {
         let cpu = &self.cpus[cpu_name];
+        if let Some((mask, signal)) = cpu.probes.get(&addr) {
+            self.record_signal(signal, val & mask);
+        }
         match cpu.wp_fastmap.get(&addr) {
             Some(idx) => {
                 let wp = &cpu.watchpoints[*idx];
                 if wp.wtype == WatchpointType::Read && wp.condition.check(val) {
-                    Err(box TraceEvent::WatchpointRead(cpu_name.to_owned(), *idx))
+                    Err(Box::new(TraceEvent::WatchpointRead(
+                        cpu_name.to_owned(),
+                        *idx,
+                    )))
                 } else {
                     Ok(())
                 }
@@ -414,7 +891,10 @@ impl Debugger {
             Some(idx) => {
                 let wp = &cpu.watchpoints[*idx];
                 if wp.wtype == WatchpointType::Write && wp.condition.check(val) {
-                    Err(box TraceEvent::WatchpointWrite(cpu_name.to_owned(), *idx))
+                    Err(Box::new(TraceEvent::WatchpointWrite(
+                        cpu_name.to_owned(),
+                        *idx,
+                    )))
                 } else {
                     Ok(())
                 }
@@ -423,20 +903,39 @@ impl Debugger {
         }
     }
 
-    fn trace_gpu(&self, _line: usize) -> Result<()> {
+    fn trace_gpu(&self, frame: i64, line: usize, cycles: i64) -> Result<()> {
+        self.timeline.borrow_mut().set_position(frame, line);
+        if let Some(ct) = self.chrome_trace.borrow_mut().as_mut() {
+            ct.set_position(frame, cycles);
+        }
+
+        if let Some(v) = crate::paranoid::take_violations().first() {
+            return Err(Box::new(TraceEvent::GenericBreak(format!(
+                "invariant violated: {}",
+                v
+            ))));
+        }
+
+        for (idx, pp) in self.pause_points.iter().enumerate() {
+            if pp.check(frame, line, cycles) {
+                return Err(Box::new(TraceEvent::PausePoint(idx)));
+            }
+        }
+
         // Check if the polling interval is elapsed. Do this only every line
         // (not every insn or memory access, since otherwise the overhead is
         // too big).
         if let Some(poll_when) = self.next_poll.get() {
             if poll_when <= Instant::now() {
                 self.next_poll.set(None);
-                return Err(box TraceEvent::Poll());
+                return Err(Box::new(TraceEvent::Poll()));
             }
         }
         Ok(())
     }
 }
 
+#[cfg(feature = "frontend")]
 impl Debugger {
     fn render_breakpoints(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx, cpu_name: &str) {
         let cpu = self.cpus.get_mut(cpu_name).unwrap();
@@ -623,6 +1122,112 @@ impl Debugger {
         }
     }
 
+    fn render_tracepoints(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx, cpu_name: &str) {
+        let cpu = self.cpus.get_mut(cpu_name).unwrap();
+
+        ui.popup(im_str!("##tp#new"), || {
+            ui.text(im_str!("PC:"));
+            ui.same_line(60.0);
+            imgui_input_hex(ui, im_str!("###tp#new_pc"), &mut ctx.new_tp_pc, false);
+
+            ui.text(im_str!("Format:"));
+            ui.same_line(60.0);
+            ui.input_text(im_str!("###tp#new_format"), &mut ctx.new_tp_format)
+                .auto_select_all(true)
+                .build();
+            if ui.is_item_hovered() {
+                ui.tooltip_text(im_str!(
+                    "{reg}: register (eg: {a0})\n\
+                     {[expr]}: word in memory (eg: {[sp+10]})\n\
+                     Append :d for decimal (eg: {a0:d})"
+                ));
+            }
+
+            if ui.button(im_str!("Add"), (40.0, 20.0)) {
+                let format = ctx.new_tp_format.to_str().to_owned();
+                cpu.add_tracepoint(ctx.new_tp_pc, &format);
+                ui.close_current_popup();
+            }
+        });
+        if ui.small_button(im_str!("New TP")) {
+            ctx.new_tp_pc = 0;
+            ctx.new_tp_format = ImString::with_capacity(256);
+            ctx.new_tp_format.push_str("pc={pc} a0={a0}");
+            ui.open_popup(im_str!("##tp#new"));
+        }
+
+        let mut tp_changed = false;
+
+        ui.columns(3, im_str!(""), true);
+        ui.set_column_offset(1, 30.0);
+        ui.set_column_offset(2, 110.0);
+        for (idx, tp) in cpu.tracepoints.iter_mut().enumerate() {
+            let name = im_str!("###tracepoints#active#{}", idx);
+            if ui.checkbox(name, &mut tp.active) {
+                // Changing activation requires update to fastmap
+                tp_changed = true;
+            }
+            ui.next_column();
+
+            let name = im_str!("###tracepoints#pc#{}", idx);
+            if imgui_input_hex(ui, name, &mut tp.pc, true) {
+                // Changing PC requires update to fastmap
+                tp_changed = true;
+            }
+            ui.next_column();
+
+            let name = im_str!("###tracepoints#format#{}", idx);
+            let mut sformat = ImString::with_capacity(256);
+            sformat.push_str(&tp.format);
+            if ui
+                .input_text(name, &mut sformat)
+                .enter_returns_true(true)
+                .auto_select_all(true)
+                .build()
+            {
+                tp.format = sformat.to_str().to_owned();
+            }
+            ui.next_column();
+        }
+        ui.columns(1, im_str!(""), false);
+
+        // Refresh tracepoint hashmap if required
+        if tp_changed {
+            cpu.update_tp_fastmap();
+        }
+    }
+
+    fn render_log(&mut self, ui: &Ui<'_>) {
+        ui.window(im_str!("Log"))
+            .size((500.0, 200.0), ImGuiCond::FirstUseEver)
+            .build(|| {
+                if ui.small_button(im_str!("Clear")) {
+                    self.log.clear();
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Copy")) {
+                    let text: Vec<&str> = self.log.iter().map(|l| l.as_str()).collect();
+                    set_clipboard_text(&text.join("\n"));
+                }
+                ui.separator();
+
+                let log = &self.log;
+                ui.child_frame(im_str!("##log#lines"), (0.0, 0.0))
+                    .build(|| {
+                        for line in log {
+                            ui.text(im_str!("{}", line));
+                        }
+                        // Keep following the end of the log, unless the user
+                        // scrolled up.
+                        unsafe {
+                            if imgui_sys::igGetScrollY() >= imgui_sys::igGetScrollMaxY() {
+                                imgui_sys::igSetScrollHereY(1.0);
+                            }
+                        }
+                    });
+            });
+    }
+
     fn render_points(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx) {
         for idx in 0..ctx.cpus.len() {
             let cpu_name = ctx.cpus[idx].clone();
@@ -644,12 +1249,170 @@ impl Debugger {
                     {
                         self.render_watchpoints(ui, ctx, &cpu_name);
                     }
+                    if ui
+                        .collapsing_header(im_str!("Tracepoints"))
+                        .default_open(true)
+                        .build()
+                    {
+                        self.render_tracepoints(ui, ctx, &cpu_name);
+                    }
                 });
         }
     }
 
+    fn render_pause_points(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx) {
+        ui.window(im_str!("Pause points"))
+            .size((250.0, 200.0), ImGuiCond::FirstUseEver)
+            .build(|| {
+                ui.popup(im_str!("##pp#new"), || {
+                    ui.text(im_str!("Type:"));
+                    ui.same_line(60.0);
+                    ui.combo(
+                        im_str!("###pp#new_type"),
+                        &mut ctx.new_pp_type,
+                        &[im_str!("Frame"), im_str!("Scanline"), im_str!("Cycle")],
+                        0,
+                    );
+
+                    ui.text(im_str!("Value:"));
+                    ui.same_line(60.0);
+                    ui.input_text(im_str!("###pp#new_value"), &mut ctx.new_pp_value)
+                        .chars_decimal(true)
+                        .auto_select_all(true)
+                        .build();
+
+                    if ui.button(im_str!("Add"), (40.0, 20.0)) {
+                        if let Ok(val) = ctx.new_pp_value.to_str().parse::<i64>() {
+                            self.add_pause_point(match ctx.new_pp_type {
+                                0 => PausePoint::Frame(val),
+                                1 => PausePoint::Scanline(val as usize),
+                                2 => PausePoint::Cycle(val),
+                                _ => unreachable!(),
+                            });
+                        }
+                        ui.close_current_popup();
+                    }
+                });
+                if ui.small_button(im_str!("New PP")) {
+                    ctx.new_pp_type = 0;
+                    ctx.new_pp_value = ImString::with_capacity(20);
+                    ui.open_popup(im_str!("##pp#new"));
+                }
+
+                let mut remove = None;
+                for (idx, pp) in self.pause_points.iter().enumerate() {
+                    if ui.small_button(im_str!("X###pausepoints#del#{}", idx)) {
+                        remove = Some(idx);
+                    }
+                    ui.same_line(0.0);
+                    ui.text(im_str!("Pause at {}", pp));
+                }
+                if let Some(idx) = remove {
+                    self.remove_pause_point(idx);
+                }
+
+                if !self.hw_events.is_empty()
+                    && ui
+                        .collapsing_header(im_str!("Break on hardware events"))
+                        .default_open(true)
+                        .build()
+                {
+                    for (idx, (name, brk)) in self.hw_events.iter_mut().enumerate() {
+                        ui.checkbox(im_str!("{}###hwevents#{}", name, idx), brk);
+                    }
+                }
+            });
+    }
+
+    // Render a watch window for each CPU, with the current values of its
+    // watch expressions, and the window of the symbol files.
+    pub(crate) fn render_watches<T: DebuggerModel>(
+        &mut self,
+        ui: &Ui<'_>,
+        ctx: &mut UiCtx,
+        model: &mut T,
+    ) {
+        for idx in 0..ctx.cpus.len() {
+            let cpu_name = ctx.cpus[idx].clone();
+            let symbols = &self.symbols;
+            let cpu = self.cpus.get_mut(&cpu_name).unwrap();
+
+            ui.window(im_str!("[{}] Watch", cpu_name))
+                .size((250.0, 200.0), ImGuiCond::FirstUseEver)
+                .build(|| {
+                    ui.popup(im_str!("##watch#new"), || {
+                        ui.text(im_str!("Expr:"));
+                        ui.same_line(60.0);
+                        ui.input_text(im_str!("###watch#new_expr"), &mut ctx.new_watch_expr)
+                            .auto_select_all(true)
+                            .build();
+                        if ui.is_item_hovered() {
+                            ui.tooltip_text(im_str!(
+                                "reg: register (eg: a0)\n\
+                                 [expr]: word in memory (eg: [sp+10], [gPlayer+4])\n\
+                                 Append :d for decimal (eg: a0:d)"
+                            ));
+                        }
+
+                        if ui.button(im_str!("Add"), (40.0, 20.0)) {
+                            cpu.watches.push(ctx.new_watch_expr.to_str().to_owned());
+                            ui.close_current_popup();
+                        }
+                    });
+                    if ui.small_button(im_str!("New watch")) {
+                        ctx.new_watch_expr = ImString::with_capacity(256);
+                        ui.open_popup(im_str!("##watch#new"));
+                    }
+
+                    let mut remove = None;
+                    for (idx, expr) in cpu.watches.iter().enumerate() {
+                        if ui.small_button(im_str!("X###watch#del#{}", idx)) {
+                            remove = Some(idx);
+                        }
+                        ui.same_line(0.0);
+                        let val =
+                            format_tracepoint(&format!("{{{}}}", expr), model, &cpu_name, symbols);
+                        ui.text(im_str!("{} = {}", expr, val));
+                    }
+                    if let Some(idx) = remove {
+                        cpu.watches.remove(idx);
+                    }
+                });
+        }
+
+        ui.window(im_str!("Symbols"))
+            .size((250.0, 100.0), ImGuiCond::FirstUseEver)
+            .build(|| {
+                ui.popup(im_str!("##symbols#load"), || {
+                    ui.text(im_str!("File:"));
+                    ui.same_line(60.0);
+                    ui.input_text(im_str!("###symbols#path"), &mut ctx.symbols_path)
+                        .auto_select_all(true)
+                        .build();
+                    if ui.button(im_str!("Load"), (40.0, 20.0)) {
+                        let path = Path::new(ctx.symbols_path.to_str());
+                        if let Err(err) = self.symbols.load(path) {
+                            ctx.add_flash_msg(&format!("Cannot load symbols:\n{}", err));
+                        }
+                        ui.close_current_popup();
+                    }
+                });
+                if ui.small_button(im_str!("Load symbols")) {
+                    ctx.symbols_path = ImString::with_capacity(256);
+                    ui.open_popup(im_str!("##symbols#load"));
+                }
+                for path in self.symbols.files() {
+                    ui.text(im_str!("{}", path.display()));
+                }
+            });
+    }
+
     pub(crate) fn render_main(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx) {
         self.render_points(ui, ctx);
+        self.render_pause_points(ui, ctx);
+        self.render_log(ui);
+        render_timeline(ui, ctx, &self.timeline.borrow());
+        render_frame_graph(ui, ctx, &self.timeline.borrow());
     }
 }
 
@@ -659,7 +1422,7 @@ mod inthashmap {
     // https://users.rust-lang.org/t/hashmap-performance/6476/14
     // https://gist.github.com/arthurprs/88eef0b57b9f8341c54e2d82ec775698
     use std::hash::Hasher;
-    pub struct SimpleHasher(u64);
+    pub(crate) struct SimpleHasher(u64);
 
     #[inline]
     fn load_u64_le(buf: &[u8], len: usize) -> u64 {
@@ -693,5 +1456,5 @@ mod inthashmap {
 
     use std::collections::HashMap;
     use std::hash::BuildHasherDefault;
-    pub type IntHashMap<K, V> = HashMap<K, V, BuildHasherDefault<SimpleHasher>>;
+    pub(crate) type IntHashMap<K, V> = HashMap<K, V, BuildHasherDefault<SimpleHasher>>;
 }
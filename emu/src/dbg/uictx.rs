@@ -1,4 +1,6 @@
-use super::TraceEvent;
+use super::{TraceEvent, ViewAnnotations};
+#[cfg(feature = "frontend")]
+use crate::hw::HotkeyAction;
 use imgui::ImString;
 
 use std::collections::HashMap;
@@ -6,16 +8,77 @@ use std::time::Instant;
 
 // UiCommand is an action triggered by the GUI that is executed
 // by the main debugger loop (cannot be done while drawing the window)
+#[cfg(feature = "frontend")]
 pub(crate) enum UiCommand {
     BreakpointOneShot(String, u64), // Run with a temporary breakpoint set
     CpuStep(String),                // Step a single opcode for the specified CPU
-    Pause(bool),                    // Set global pause status
+    #[allow(dead_code)]
+    Pause(bool), // Set global pause status
+}
+
+// Views that can be opened in multiple windows are keyed by view name and
+// instance id: instance 0 is the main window (always open, and following
+// the debugger events), while the others are opened by the user, eg: to
+// watch both the source and the destination of a DMA transfer.
+pub(crate) type ViewInstance = (String, usize);
+
+// Return the instances of the specified view, sorted by id (the main window
+// is always included).
+pub(crate) fn view_instances<T: Default>(
+    map: &mut HashMap<ViewInstance, T>,
+    name: &str,
+) -> Vec<usize> {
+    map.entry((name.to_owned(), 0)).or_default();
+    let mut ids: Vec<usize> = map
+        .keys()
+        .filter(|(n, _)| n == name)
+        .map(|(_, id)| *id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+// Open a new instance of the specified view, and return its state.
+pub(crate) fn new_view_instance<'a, T: Default>(
+    map: &'a mut HashMap<ViewInstance, T>,
+    name: &str,
+) -> &'a mut T {
+    let id = view_instances(map, name).last().unwrap() + 1;
+    map.entry((name.to_owned(), id)).or_default()
 }
 
 #[derive(Default)]
 pub(crate) struct UiCtxDisasm {
     pub blink_pc: Option<(u64, Instant)>,
     pub cursor_pc: Option<u64>,
+    pub anchor_pc: Option<u64>, // other end of the selection (shift+click)
+    pub center_pc: Option<u64>, // if Some, the listing is centered here instead of PC
+    pub force_pc: Option<u64>,  // if Some, scroll to this PC
+}
+
+#[derive(Default)]
+pub(crate) struct UiCtxDualMem {
+    pub cursor: [Option<usize>; 2],     // selected line in each column
+    pub anchor: [Option<usize>; 2],     // other end of the selection (shift+click)
+    pub force_line: [Option<usize>; 2], // if Some, scroll to this line
+}
+
+#[derive(Default)]
+pub(crate) struct UiCtxMem {
+    pub region: usize,             // selected memory buffer
+    pub cursor: Option<usize>,     // offset of the selected byte
+    pub force_line: Option<usize>, // if Some, scroll to this line
+    pub goto_addr: u64,
+    pub frame: i64,             // frame at which the contents were last copied
+    pub snapshot_region: usize, // memory buffer that was copied
+    pub prev: Vec<u8>,          // contents at the previous frame
+    pub curr: Vec<u8>,          // contents at the current frame
+}
+
+#[derive(Default)]
+pub(crate) struct UiCtxHeap {
+    pub heaps: Vec<u64>,  // addresses of the heaps found by the last scan
+    pub sel: Option<u64>, // selected heap
 }
 
 // Global state shared by all debugger UIs, passed to all rendere functions.
@@ -26,34 +89,102 @@ pub(crate) struct UiCtxDisasm {
 // 2) Propagate cross-window information (eg: specific events that affect multiple windows).
 #[derive(Default)]
 pub(crate) struct UiCtx {
+    #[cfg(feature = "frontend")]
     pub cpus: Vec<String>,
 
+    // Current emulated frame, updated before rendering the views.
+    pub frame: i64,
+
     // An event that was just triggered. This is kept only for one frame.
     pub event: Option<(Box<TraceEvent>, Instant)>,
 
     // A command requested by the UI to the debugger
+    #[cfg(feature = "frontend")]
     pub command: Option<UiCommand>,
 
-    // Disasm views
-    pub disasm: HashMap<String, UiCtxDisasm>,
+    // Disasm views (keyed by CPU name and instance)
+    pub disasm: HashMap<ViewInstance, UiCtxDisasm>,
+
+    // Dual memory views (keyed by view name and instance)
+    pub dualmem: HashMap<ViewInstance, UiCtxDualMem>,
+
+    // Hex editors (keyed by view name)
+    pub memview: HashMap<String, UiCtxMem>,
+
+    // Packet views: selected transaction (keyed by view name)
+    pub packetview_sel: HashMap<String, usize>,
+
+    // Heap views (keyed by view name)
+    pub heapview: HashMap<String, UiCtxHeap>,
+
+    // Storage views: selected entry (keyed by view name)
+    pub storageview: HashMap<String, Option<usize>>,
+
+    // Bookmarks and comments, keyed by view name. These are persisted
+    // in the debugger session.
+    pub annotations: HashMap<String, ViewAnnotations>,
 
     // Flash messages (auto-hide after 2s)
     pub flash_msg: Option<(String, Instant)>,
 
     // Popup "New breakpoint": local state
+    #[cfg(feature = "frontend")]
     pub new_bp_pc: u64,
+    #[cfg(feature = "frontend")]
     pub new_bp_desc: ImString,
 
     // Popup "New watchpoint": local state
+    #[cfg(feature = "frontend")]
     pub new_wp_addr: u64,
+    #[cfg(feature = "frontend")]
     pub new_wp_desc: ImString,
+    #[cfg(feature = "frontend")]
     pub new_wp_type: i32,
+    #[cfg(feature = "frontend")]
     pub new_wp_cond: i32,
+    #[cfg(feature = "frontend")]
     pub new_wp_value: u64,
+
+    // Popup "New tracepoint": local state
+    #[cfg(feature = "frontend")]
+    pub new_tp_pc: u64,
+    #[cfg(feature = "frontend")]
+    pub new_tp_format: ImString,
+
+    // Popup "New watch": local state
+    #[cfg(feature = "frontend")]
+    pub new_watch_expr: ImString,
+
+    // Popup "Load symbols": local state
+    #[cfg(feature = "frontend")]
+    pub symbols_path: ImString,
+
+    // Popup "New pause point": local state
+    #[cfg(feature = "frontend")]
+    pub new_pp_type: i32,
+    #[cfg(feature = "frontend")]
+    pub new_pp_value: ImString,
+
+    // Popups "Comment" / "Bookmark": local state
+    pub annotation_addr: u64,
+    pub annotation_edit: ImString,
+
+    // Hotkeys window: action waiting for a new key to be pressed
+    #[cfg(feature = "frontend")]
+    pub hotkey_capture: Option<HotkeyAction>,
+
+    // Timeline window: selected frame (number of frames ago)
+    #[cfg(feature = "frontend")]
+    pub timeline_frame: i32,
+    #[cfg(feature = "frontend")]
+    pub framegraph_frame: i32,
+
+    // Popup "Dump to file": local state
+    pub dump_filename: ImString,
 }
 
 impl UiCtx {
-    pub fn add_flash_msg(&mut self, msg: &str) {
+    pub(crate) fn add_flash_msg(&mut self, msg: &str) {
         self.flash_msg = Some((msg.to_owned(), Instant::now()));
     }
 }
@@ -11,23 +11,25 @@ pub(crate) struct ImGuiListClipper {
 impl ImGuiListClipper {
     pub(crate) fn new(items_count: usize) -> Self {
         Self {
-            items_count: items_count,
+            items_count,
             items_height: -1.0,
         }
     }
 
+    #[allow(dead_code)]
     pub(crate) fn items_count(mut self, items_count: usize) -> Self {
         self.items_count = items_count;
         self
     }
 
+    #[allow(dead_code)]
     pub(crate) fn items_height(mut self, items_height: f32) -> Self {
         self.items_height = items_height;
         self
     }
 
     pub(crate) fn build<F: FnMut(isize, isize)>(self, mut f: F) {
-        let mut clip: sys::ImGuiListClipper = unsafe { ::std::mem::uninitialized() };
+        let mut clip: sys::ImGuiListClipper = unsafe { ::std::mem::zeroed() };
         unsafe {
             sys::ImGuiListClipper_Begin(&mut clip, self.items_count as _, self.items_height as _);
         }
@@ -46,7 +48,26 @@ impl ImGuiListClipper {
     }
 }
 
-pub trait HexableInt: Copy + fmt::Display {
+/// Indices of the keys checked by the views through `is_key_pressed()`.
+/// The frontend feeds imgui with SDL scancodes (which are USB HID usage IDs),
+/// so that views do not need to depend on SDL.
+pub(crate) mod keys {
+    pub(crate) const B: usize = 5;
+    pub(crate) const C: usize = 6;
+    #[cfg(feature = "frontend")]
+    pub(crate) const S: usize = 22;
+    #[cfg(feature = "frontend")]
+    pub(crate) const RETURN: usize = 40;
+    pub(crate) const SEMICOLON: usize = 51;
+    #[cfg(feature = "frontend")]
+    pub(crate) const RIGHT: usize = 79;
+    #[cfg(feature = "frontend")]
+    pub(crate) const LEFT: usize = 80;
+    pub(crate) const DOWN: usize = 81;
+    pub(crate) const UP: usize = 82;
+}
+
+pub(crate) trait HexableInt: Copy + fmt::Display {
     const HEX_DIGITS: usize;
     fn format(self) -> String;
     fn parse(s: &str) -> Option<Self>;
@@ -89,7 +110,7 @@ impl HexableInt for u64 {
     }
 }
 
-pub fn imgui_input_hex<T: HexableInt>(
+pub(crate) fn imgui_input_hex<T: HexableInt>(
     ui: &Ui<'_>,
     name: &ImStr,
     val: &mut T,
@@ -123,7 +144,7 @@ fn interp4(a: ImVec4, b: ImVec4, d: f32) -> ImVec4 {
     )
 }
 
-pub fn blink_color(base: ImVec4, start: Instant) -> Option<ImVec4> {
+pub(crate) fn blink_color(base: ImVec4, start: Instant) -> Option<ImVec4> {
     let elapsed = start.elapsed();
     let white = ImVec4::new(1.0, 1.0, 1.0, 1.0);
     let end = Duration::from_millis(1000);
@@ -139,3 +160,57 @@ pub fn blink_color(base: ImVec4, start: Instant) -> Option<ImVec4> {
         None
     }
 }
+
+/// Copy the specified text into the system clipboard (through imgui, that
+/// forwards it to the platform backend).
+pub(crate) fn set_clipboard_text(text: &str) {
+    // Interior NULs cannot be represented in a C string; drop them.
+    let text = std::ffi::CString::new(text.replace('\0', "")).unwrap();
+    unsafe {
+        sys::igSetClipboardText(text.as_ptr());
+    }
+}
+
+/// Format a memory buffer as a classic hexdump (offset, hex bytes and ASCII),
+/// with 16 bytes per line. base is the address of the first byte.
+pub(crate) fn hexdump(base: u64, data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, chunk) in data.chunks(16).enumerate() {
+        out += &format!("{:08x}  ", base + i as u64 * 16);
+        for j in 0..16 {
+            match chunk.get(j) {
+                Some(b) => out += &format!("{:02x} ", b),
+                None => out += "   ",
+            }
+        }
+        out += " |";
+        for b in chunk {
+            out.push(if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            });
+        }
+        out += "|\n";
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hexdump;
+
+    #[test]
+    fn test_hexdump() {
+        let data: Vec<u8> = (0x40..0x52).collect();
+        assert_eq!(
+            hexdump(0x1000, &data),
+            "00001000  40 41 42 43 44 45 46 47 48 49 4a 4b 4c 4d 4e 4f  |@ABCDEFGHIJKLMNO|\n\
+             00001010  50 51                                            |PQ|\n"
+        );
+        assert_eq!(
+            hexdump(0, &[0, 0x7f, b' ']),
+            format!("00000000  00 7f 20 {} |.. |\n", " ".repeat(39))
+        );
+    }
+}
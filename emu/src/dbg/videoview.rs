@@ -0,0 +1,198 @@
+#[cfg(feature = "debugger")]
+use imgui::*;
+
+#[cfg(feature = "debugger")]
+use super::UiCtx;
+
+/// Raster position of a video device, expressed in lines of the current
+/// field (or half-lines, depending on the hardware).
+pub struct RasterPos {
+    pub line: usize,                   // Line currently being output
+    pub total_lines: usize,            // Total number of lines per field
+    pub interrupt_line: Option<usize>, // Line that triggers the interrupt (if any)
+}
+
+/// Format of a framebuffer pixel in memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba5551,
+    Rgba8888,
+}
+
+impl PixelFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Rgba5551 => "RGBA5551",
+            PixelFormat::Rgba8888 => "RGBA8888",
+        }
+    }
+}
+
+/// A pixel of the framebuffer, as stored in memory: its coordinates within
+/// the framebuffer, its address, and its raw value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawPixel {
+    pub x: usize,
+    pub y: usize,
+    pub addr: u64,
+    pub value: u32,
+    pub format: PixelFormat,
+}
+
+impl RawPixel {
+    /// Split the raw value into its components (r, g, b, a), each one with
+    /// the number of bits of the pixel format.
+    pub fn components(&self) -> (u32, u32, u32, u32) {
+        let v = self.value;
+        match self.format {
+            PixelFormat::Rgba5551 => ((v >> 11) & 0x1F, (v >> 6) & 0x1F, (v >> 1) & 0x1F, v & 1),
+            PixelFormat::Rgba8888 => (v >> 24, (v >> 16) & 0xFF, (v >> 8) & 0xFF, v & 0xFF),
+        }
+    }
+}
+
+/// A trait for a video output device that scans out a framebuffer.
+pub trait VideoView {
+    /// Return the name of this object. The name will be composed
+    /// as "\[NAME\] Video".
+    fn name(&self) -> &str;
+
+    /// Visit all the registers of the device. For each register, the visitor
+    /// receives its name, its raw value, and a list of decoded fields
+    /// (as pairs of labels and values).
+    fn visit_regs<F: FnMut(&str, u64, &[(&str, String)])>(&self, visit: F);
+
+    /// Return the current raster position.
+    fn raster(&self) -> RasterPos;
+
+    /// Dump the framebuffer currently scanned out by the device to an image
+    /// file. Returns the name of the file that was written.
+    fn dump_framebuffer(&self) -> Result<String, String>;
+
+    /// Return the names of the debug display modes of the device, which
+    /// show other buffers (eg: depth) on the screen instead of the
+    /// framebuffer. The first mode is the normal output.
+    fn display_modes(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// Return the current display mode (index in display_modes).
+    fn display_mode(&self) -> usize {
+        0
+    }
+
+    /// Change the display mode (index in display_modes).
+    fn set_display_mode(&mut self, _mode: usize) {}
+
+    /// Return the names of the post-processing filters of the device (eg:
+    /// anti-aliasing), and whether each one is enabled.
+    fn filters(&self) -> Vec<(&'static str, bool)> {
+        vec![]
+    }
+
+    /// Enable or disable a filter (index in filters). Disabling all the
+    /// filters shows the framebuffer as it was rendered.
+    fn set_filter(&mut self, _idx: usize, _enabled: bool) {}
+}
+
+#[cfg(feature = "debugger")]
+fn color(r: usize, g: usize, b: usize) -> ImVec4 {
+    ImVec4::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0)
+}
+
+#[cfg(feature = "debugger")]
+pub(crate) fn render_videoview<'a, 'ui, VV: VideoView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    v: &mut VV,
+) {
+    ui.window(im_str!("[{}] Video", v.name()))
+        .size((450.0, 500.0), ImGuiCond::FirstUseEver)
+        .build(|| {
+            let raster = v.raster();
+            ui.text(im_str!("Line: {} / {}", raster.line, raster.total_lines));
+            ui.same_line(0.0);
+            match raster.interrupt_line {
+                Some(line) => ui.text_colored(color(249, 38, 114), im_str!("IRQ line: {}", line)),
+                None => ui.text_disabled(im_str!("IRQ line: none")),
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Dump framebuffer")) {
+                match v.dump_framebuffer() {
+                    Ok(fname) => ctx.add_flash_msg(&format!("Framebuffer saved: {}", fname)),
+                    Err(err) => ctx.add_flash_msg(&format!("Cannot dump framebuffer:\n{}", err)),
+                }
+            }
+
+            // Debug display modes (the first one is the normal output)
+            let modes: Vec<ImString> = v
+                .display_modes()
+                .iter()
+                .map(|&m| ImString::new(m))
+                .collect();
+            if !modes.is_empty() {
+                let names: Vec<&ImStr> = modes.iter().map(|m| m.as_ref()).collect();
+                let mut mode = v.display_mode() as i32;
+                ui.with_item_width(150.0, || {
+                    if ui.combo(im_str!("Display"), &mut mode, &names, names.len() as i32) {
+                        v.set_display_mode(mode as usize);
+                    }
+                });
+            }
+
+            // Post-processing filters
+            let filters = v.filters();
+            if !filters.is_empty() {
+                ui.text(im_str!("Filters:"));
+                for (idx, (name, enabled)) in filters.into_iter().enumerate() {
+                    let mut enabled = enabled;
+                    ui.same_line(0.0);
+                    if ui.checkbox(im_str!("{}###vi#filter#{}", name, idx), &mut enabled) {
+                        v.set_filter(idx, enabled);
+                    }
+                }
+            }
+
+            // Raster indicator: a bar representing the whole field, with
+            // markers for the current line and the interrupt line.
+            const BAR_HEIGHT: f32 = 14.0;
+            let pos = ui.get_cursor_screen_pos();
+            let width = ui.get_content_region_avail().0.max(1.0);
+            let total = raster.total_lines.max(1) as f32;
+            let dl = ui.get_window_draw_list();
+            let bg = color(39, 40, 34);
+            dl.add_rect_filled_multicolor(pos, (pos.0 + width, pos.1 + BAR_HEIGHT), bg, bg, bg, bg);
+            let marker = |line: usize, c: ImVec4| {
+                let x = pos.0 + (line as f32 / total).min(1.0) * width;
+                dl.add_rect_filled_multicolor(
+                    (x, pos.1),
+                    (x + 2.0, pos.1 + BAR_HEIGHT),
+                    c,
+                    c,
+                    c,
+                    c,
+                );
+            };
+            if let Some(line) = raster.interrupt_line {
+                marker(line, color(249, 38, 114));
+            }
+            marker(raster.line, color(165, 224, 46));
+            ui.invisible_button(im_str!("###vi#raster"), (width, BAR_HEIGHT));
+            ui.separator();
+
+            v.visit_regs(|name, val, fields| {
+                if ui
+                    .collapsing_header(im_str!("{}###vireg#{}", name, name))
+                    .default_open(true)
+                    .build()
+                {
+                    ui.text(im_str!("raw: {:08x}", val));
+                    for (label, value) in fields {
+                        ui.bullet_text(im_str!("{}:", label));
+                        ui.same_line(160.0);
+                        ui.text(im_str!("{}", value));
+                    }
+                }
+            });
+        });
+}
@@ -0,0 +1,600 @@
+//! A generic DMA engine.
+//!
+//! Most DMA controllers follow the same pattern: they copy a block of
+//! memory between two bus addresses (possibly as a sequence of rows,
+//! skipping some bytes between each row), they are busy for a certain
+//! number of cycles, and they raise an interrupt when the transfer is
+//! complete. [`DmaXfer`](struct.DmaXfer.html) describes a transfer, and
+//! [`Dma`](struct.Dma.html) is a DMA channel that executes them, so that
+//! devices only have to decode their registers.
+//!
+//! Address counters of DMA controllers are usually narrower than the bus
+//! (eg: 24 bits for RDRAM addresses, 12 bits for a 4 KiB memory), so a
+//! transfer that runs past the end of the addressable range wraps around
+//! rather than spilling into the next area of the bus. Devices describe this
+//! with the `src_wrap` / `dst_wrap` windows of the transfer. Transfers that
+//! wrap are almost always a bug in the emulated software (or in the
+//! emulator), so the channel also latches them, and reports them to the
+//! debugger as a hardware event (see [`oob_event`](fn.oob_event.html)).
+
+use crate::bus::Bus;
+use crate::dbg::{Result, Tracer};
+use crate::memint::{ByteOrderCombiner, MemInt};
+use crate::state::Field;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// A DMA transfer: `count` rows of `width` bytes each. After each row, the
+/// source and destination addresses are further incremented by `src_skip`
+/// and `dst_skip` bytes.
+///
+/// If `src_wrap` (or `dst_wrap`) is not zero, it is the size of the aligned
+/// window (a power of two) in which the source (or destination) address
+/// wraps around, as the address counter of the controller would.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DmaXfer {
+    pub src: u32,
+    pub dst: u32,
+    pub width: usize,
+    pub count: usize,
+    pub src_skip: usize,
+    pub dst_skip: usize,
+    #[serde(default)]
+    pub src_wrap: u32,
+    #[serde(default)]
+    pub dst_wrap: u32,
+}
+
+// Return the number of bytes from addr to the end of its wrapping window.
+fn wrap_room(addr: u32, wrap: u32) -> usize {
+    if wrap == 0 {
+        return usize::MAX;
+    }
+    (wrap - (addr & (wrap - 1))) as usize
+}
+
+// Advance addr by len bytes within its wrapping window. Returns the new
+// address, and whether it wrapped around.
+fn wrap_add(addr: u32, len: usize, wrap: u32) -> (u32, bool) {
+    if wrap == 0 {
+        return (addr.wrapping_add(len as u32), false);
+    }
+    let mask = wrap - 1;
+    let off = (addr & mask) as usize + len;
+    ((addr & !mask) | (off as u32 & mask), off > mask as usize)
+}
+
+/// Return the name of the hardware event reported when a transfer of the
+/// specified DMA channel goes out of bounds (eg: "PI DMA: out-of-bounds
+/// transfer").
+pub fn oob_event(channel: &str) -> String {
+    format!("{}: out-of-bounds transfer", channel)
+}
+
+impl DmaXfer {
+    /// Create a transfer of a single contiguous block of memory.
+    pub fn linear(src: u32, dst: u32, len: usize) -> DmaXfer {
+        DmaXfer {
+            src,
+            dst,
+            width: len,
+            count: if len != 0 { 1 } else { 0 },
+            ..DmaXfer::default()
+        }
+    }
+
+    /// Return the number of bytes still to be transferred.
+    pub fn len(&self) -> usize {
+        self.width * self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Create a copy of the transfer, with the specified wrapping windows.
+    pub fn wrap(self, src_wrap: u32, dst_wrap: u32) -> DmaXfer {
+        debug_assert!(src_wrap == 0 || src_wrap.is_power_of_two());
+        debug_assert!(dst_wrap == 0 || dst_wrap.is_power_of_two());
+        DmaXfer {
+            src_wrap,
+            dst_wrap,
+            ..self
+        }
+    }
+
+    // Advance the transfer by one row. Returns true if the next row starts
+    // after wrapping around.
+    fn next_row(&mut self) -> bool {
+        let (src, src_wrapped) = wrap_add(self.src, self.width + self.src_skip, self.src_wrap);
+        let (dst, dst_wrapped) = wrap_add(self.dst, self.width + self.dst_skip, self.dst_wrap);
+        self.src = src;
+        self.dst = dst;
+        self.count -= 1;
+        self.count != 0 && (src_wrapped || dst_wrapped)
+    }
+}
+
+/// Timing of a DMA channel, used to compute how many cycles the channel
+/// stays busy for each transfer. The default timing (all zeros) describes
+/// an instantaneous DMA.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DmaTiming {
+    pub setup: i64,           // Fixed cost of each transfer, in cycles
+    pub per_row: i64,         // Additional cost of each row, in cycles
+    pub bytes_per_cycle: i64, // Bandwidth (0 means infinite)
+}
+
+impl DmaTiming {
+    /// Return the number of cycles required to execute the transfer.
+    pub fn cost(&self, xfer: &DmaXfer) -> i64 {
+        let mut cycles = self.setup + self.per_row * xfer.count as i64;
+        if self.bytes_per_cycle != 0 {
+            let len = xfer.len() as i64;
+            cycles += (len + self.bytes_per_cycle - 1) / self.bytes_per_cycle;
+        }
+        cycles
+    }
+}
+
+/// A DMA channel.
+///
+/// Data is copied as soon as a transfer is started (with a fast path for
+/// linearly-mapped memory, and single bus accesses otherwise), while the
+/// channel stays busy for the number of cycles specified by its
+/// [`DmaTiming`](struct.DmaTiming.html). The owning device is expected to
+/// advance the channel with [`run`](struct.Dma.html#method.run), and raise
+/// its completion interrupt when the channel becomes idle.
+pub struct Dma {
+    name: &'static str,
+    timing: DmaTiming,
+    busy: Field<i64>, // Cycles until the current transfer completes
+    active: bool,     // A transfer was started since the last call to trace()
+    oob: bool,        // A transfer went out of bounds since the last call to trace_oob()
+    oob_event: String,
+}
+
+impl Dma {
+    /// Create a DMA channel. The name is also used as track in the debugger
+    /// frame graph (eg: "PI DMA").
+    pub fn new(name: &'static str, timing: DmaTiming) -> Dma {
+        Dma {
+            name,
+            timing,
+            busy: Field::new(&format!("Dma::{}::busy", name), 0),
+            active: false,
+            oob: false,
+            oob_event: oob_event(name),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// Return true if a transfer is in progress.
+    pub fn busy(&self) -> bool {
+        *self.busy > 0
+    }
+
+    /// Return true if a transfer went out of bounds (that is, it wrapped
+    /// around, or crossed the end of a memory area) and it was not yet
+    /// reported with [`trace_oob`](struct.Dma.html#method.trace_oob).
+    pub fn out_of_bounds(&self) -> bool {
+        self.oob
+    }
+
+    /// Latch an out-of-bounds transfer detected by the device itself (eg:
+    /// an address outside of the memory reachable by the channel, which the
+    /// device clamped before starting the transfer).
+    pub fn set_out_of_bounds(&mut self) {
+        self.oob = true;
+    }
+
+    /// Execute a transfer on the specified bus. Returns the transfer as it
+    /// is after completion: that is, with source and destination addresses
+    /// pointing after the last row, which is what most DMA controllers
+    /// expose in their address registers.
+    pub fn xfer<O: ByteOrderCombiner + 'static>(
+        &mut self,
+        bus: &mut Bus<O>,
+        mut xfer: DmaXfer,
+    ) -> DmaXfer {
+        *self.busy += self.timing.cost(&xfer);
+        self.active = true;
+
+        while xfer.count != 0 {
+            self.copy_wrapped_row(bus, &xfer);
+            if xfer.next_row() {
+                self.oob = true;
+            }
+        }
+        xfer
+    }
+
+    // Copy the current row of a transfer, splitting it where either address
+    // wraps around.
+    fn copy_wrapped_row<O: ByteOrderCombiner + 'static>(
+        &mut self,
+        bus: &mut Bus<O>,
+        xfer: &DmaXfer,
+    ) {
+        let (mut src, mut dst) = (xfer.src, xfer.dst);
+        let mut width = xfer.width;
+        loop {
+            let len = width
+                .min(wrap_room(src, xfer.src_wrap))
+                .min(wrap_room(dst, xfer.dst_wrap));
+            if !Self::copy_row(bus, src, dst, len) {
+                self.oob = true;
+            }
+            width -= len;
+            if width == 0 {
+                break;
+            }
+            src = wrap_add(src, len, xfer.src_wrap).0;
+            dst = wrap_add(dst, len, xfer.dst_wrap).0;
+            self.oob = true;
+        }
+    }
+
+    // Copy a row between two bus addresses. Returns false if the row crosses
+    // the end of a memory area.
+    fn copy_row<O: ByteOrderCombiner + 'static>(
+        bus: &mut Bus<O>,
+        src: u32,
+        dst: u32,
+        width: usize,
+    ) -> bool {
+        bus.notify_write(dst, width);
+        let src_io = bus.fetch_read_nolog::<u8>(src);
+        let mut dst_io = bus.fetch_write_nolog::<u8>(dst);
+        let mut inbounds = true;
+        if let (Some(src_mem), Some(dst_mem)) = (src_io.mem(), dst_io.mem()) {
+            if src_mem.len() >= width && dst_mem.len() >= width {
+                dst_mem[..width].copy_from_slice(&src_mem[..width]);
+                return true;
+            }
+            inbounds = false;
+            crate::paranoid_check!(
+                "dma",
+                false,
+                "row crosses the end of a memory area: src={:x} dst={:x} width={:x}",
+                src,
+                dst,
+                width
+            );
+        }
+
+        // Slow path: at least one side is not linear memory (or the row
+        // crosses the end of a memory area), so go through the bus.
+        if (src | dst | width as u32) & 3 == 0 {
+            Self::copy_row_by::<O, u32>(bus, src, dst, width);
+        } else {
+            Self::copy_row_by::<O, u8>(bus, src, dst, width);
+        }
+        inbounds
+    }
+
+    fn copy_row_by<O: ByteOrderCombiner + 'static, U: MemInt + 'static>(
+        bus: &mut Bus<O>,
+        src: u32,
+        dst: u32,
+        width: usize,
+    ) {
+        for off in (0..width as u32).step_by(U::SIZE) {
+            let val = bus.read::<U>(src.wrapping_add(off));
+            bus.write::<U>(dst.wrapping_add(off), val);
+        }
+    }
+
+    /// Keep the channel busy for the specified number of cycles, in addition
+    /// to its own timing: for transfers whose duration depends on the
+    /// configuration of the device (eg: its timing registers), or which are
+    /// executed by the device itself.
+    pub fn add_busy(&mut self, cycles: i64) {
+        *self.busy += cycles;
+        self.active = true;
+    }
+
+    /// Cancel the transfer in progress, if any (eg: when the device is reset).
+    /// Its completion will not be reported by [`run`](Dma::run).
+    pub fn abort(&mut self) {
+        *self.busy = 0;
+    }
+
+    /// Read the next word of a streaming transfer (eg: audio samples, which
+    /// are fetched one at a time as they are played), advancing it. Streaming
+    /// is only supported for linear transfers. Returns None if the transfer
+    /// is complete.
+    pub fn stream<O: ByteOrderCombiner + 'static, U: MemInt + 'static>(
+        &mut self,
+        bus: &Bus<O>,
+        xfer: &mut DmaXfer,
+    ) -> Option<U> {
+        if xfer.width < U::SIZE || xfer.count == 0 {
+            return None;
+        }
+        let val = bus.read::<U>(xfer.src);
+        let (src, wrapped) = wrap_add(xfer.src, U::SIZE, xfer.src_wrap);
+        xfer.src = src;
+        xfer.width -= U::SIZE;
+        if xfer.width == 0 {
+            xfer.count -= 1;
+        }
+        if wrapped && !xfer.is_empty() {
+            self.oob = true;
+        }
+        self.active = true;
+        Some(val)
+    }
+
+    /// Advance emulated time by the specified number of cycles. Returns true
+    /// if a transfer completed during this time, in which case the device
+    /// should raise its completion interrupt.
+    pub fn run(&mut self, cycles: i64) -> bool {
+        if *self.busy <= 0 {
+            return false;
+        }
+        *self.busy -= cycles;
+        if *self.busy <= 0 {
+            *self.busy = 0;
+            return true;
+        }
+        false
+    }
+
+    /// Report the activity of the channel to the debugger frame graph. It
+    /// should be called once per scanline.
+    pub fn trace(&mut self, tracer: &Tracer) {
+        if self.active || self.busy() {
+            tracer.trace_activity(self.name);
+        }
+        self.active = false;
+    }
+
+    /// Report a latched out-of-bounds transfer to the debugger, as the
+    /// hardware event returned by [`oob_event`](fn.oob_event.html) for this
+    /// channel. Devices should call it when they are run by the scheduler.
+    pub fn trace_oob(&mut self, tracer: &Tracer) -> Result<()> {
+        if !self.oob {
+            return Ok(());
+        }
+        self.oob = false;
+        tracer.trace_hw_event(&self.oob_event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::le::{Bus, BusFill, Mem, MemFlags, Reg32};
+    use crate::dbg::{Debugger, TraceEvent};
+    use crate::log::new_console_logger;
+
+    fn bus_with_ram(ram: &Mem) -> Box<Bus> {
+        let mut bus = Bus::new(new_console_logger());
+        bus.map_mem(0x0000_0000, 0x0000_0FFF, ram, BusFill::None)
+            .unwrap();
+        bus
+    }
+
+    #[test]
+    fn linear() {
+        let ram = Mem::new("dma::linear", 0x1000, MemFlags::default());
+        let mut bus = bus_with_ram(&ram);
+        let mut dma = Dma::new("linear", DmaTiming::default());
+
+        for i in 0..16 {
+            bus.write::<u8>(0x100 + i, i as u8);
+        }
+        let end = dma.xfer(&mut bus, DmaXfer::linear(0x100, 0x200, 16));
+        for i in 0..16 {
+            assert_eq!(bus.read::<u8>(0x200 + i), i as u8);
+        }
+        assert_eq!((end.src, end.dst), (0x110, 0x210));
+        assert!(end.is_empty());
+        assert!(!dma.busy());
+    }
+
+    #[test]
+    fn rows() {
+        let ram = Mem::new("dma::rows", 0x1000, MemFlags::default());
+        let mut bus = bus_with_ram(&ram);
+        let mut dma = Dma::new("rows", DmaTiming::default());
+
+        for i in 0..32 {
+            bus.write::<u8>(0x100 + i, i as u8);
+        }
+        dma.xfer(
+            &mut bus,
+            DmaXfer {
+                src: 0x100,
+                dst: 0x200,
+                width: 4,
+                count: 4,
+                src_skip: 4,
+                ..DmaXfer::default()
+            },
+        );
+        assert_eq!(bus.read::<u32>(0x200), 0x0302_0100);
+        assert_eq!(bus.read::<u32>(0x204), 0x0B0A_0908);
+        assert_eq!(bus.read::<u32>(0x208), 0x1312_1110);
+        assert_eq!(bus.read::<u32>(0x20C), 0x1B1A_1918);
+    }
+
+    #[test]
+    fn registers() {
+        let ram = Mem::new("dma::registers", 0x1000, MemFlags::default());
+        let mut bus = bus_with_ram(&ram);
+        let reg = Reg32::new_basic("dma::reg");
+        bus.map_reg(0x8000, &reg).unwrap();
+        let mut dma = Dma::new("registers", DmaTiming::default());
+
+        // Registers are not linear memory, so the slow path is used.
+        bus.write::<u32>(0x100, 0xAABB_CCDD);
+        dma.xfer(&mut bus, DmaXfer::linear(0x100, 0x8000, 4));
+        assert_eq!(reg.get(), 0xAABB_CCDD);
+    }
+
+    #[test]
+    fn timing() {
+        let ram = Mem::new("dma::timing", 0x1000, MemFlags::default());
+        let mut bus = bus_with_ram(&ram);
+        let timing = DmaTiming {
+            setup: 10,
+            per_row: 0,
+            bytes_per_cycle: 4,
+        };
+        let mut dma = Dma::new("timing", timing);
+
+        dma.xfer(&mut bus, DmaXfer::linear(0x100, 0x200, 64));
+        assert!(dma.busy());
+        assert!(!dma.run(20));
+        assert!(dma.run(6));
+        assert!(!dma.busy());
+        assert!(!dma.run(100));
+
+        // Duration computed by the device.
+        dma.add_busy(30);
+        assert!(dma.busy());
+        assert!(!dma.run(29));
+        assert!(dma.run(1));
+
+        // An aborted transfer never completes.
+        dma.add_busy(30);
+        dma.abort();
+        assert!(!dma.busy());
+        assert!(!dma.run(30));
+    }
+
+    #[test]
+    fn stream() {
+        let ram = Mem::new("dma::stream", 0x1000, MemFlags::default());
+        let bus = bus_with_ram(&ram);
+        let mut dma = Dma::new("stream", DmaTiming::default());
+
+        let mut xfer = DmaXfer::linear(0x100, 0, 8);
+        assert!(dma.stream::<_, u32>(&bus, &mut xfer).is_some());
+        assert_eq!(xfer.len(), 4);
+        assert!(dma.stream::<_, u32>(&bus, &mut xfer).is_some());
+        assert!(xfer.is_empty());
+        assert!(dma.stream::<_, u32>(&bus, &mut xfer).is_none());
+    }
+
+    #[test]
+    fn wrap_in_row() {
+        let ram = Mem::new("dma::wrap_in_row", 0x1000, MemFlags::default());
+        let mut bus = bus_with_ram(&ram);
+        let mut dma = Dma::new("wrap_in_row", DmaTiming::default());
+
+        for i in 0..16 {
+            bus.write::<u8>(0x100 + i, i as u8);
+        }
+
+        // The destination window is 0x800..0xFFF: the row continues at 0x800.
+        let end = dma.xfer(&mut bus, DmaXfer::linear(0x100, 0xFF8, 16).wrap(0, 0x800));
+        assert_eq!(bus.read::<u32>(0xFF8), 0x0302_0100);
+        assert_eq!(bus.read::<u32>(0x800), 0x0B0A_0908);
+        assert_eq!(bus.read::<u32>(0x000), 0);
+        assert_eq!((end.src, end.dst), (0x110, 0x808));
+        assert!(dma.out_of_bounds());
+    }
+
+    #[test]
+    fn wrap_between_rows() {
+        let ram = Mem::new("dma::wrap_between_rows", 0x1000, MemFlags::default());
+        let mut bus = bus_with_ram(&ram);
+        let mut dma = Dma::new("wrap_between_rows", DmaTiming::default());
+
+        bus.write::<u32>(0x7F0, 0x1111_1111);
+        bus.write::<u32>(0x000, 0x2222_2222);
+
+        // The skip of the first row moves the source past the end of the
+        // 0x000..0x7FF window.
+        dma.xfer(
+            &mut bus,
+            DmaXfer {
+                src: 0x7F0,
+                dst: 0x900,
+                width: 4,
+                count: 2,
+                src_skip: 12,
+                ..DmaXfer::default()
+            }
+            .wrap(0x800, 0),
+        );
+        assert_eq!(bus.read::<u32>(0x900), 0x1111_1111);
+        assert_eq!(bus.read::<u32>(0x904), 0x2222_2222);
+        assert!(dma.out_of_bounds());
+    }
+
+    #[test]
+    fn wrap_at_end() {
+        let ram = Mem::new("dma::wrap_at_end", 0x1000, MemFlags::default());
+        let mut bus = bus_with_ram(&ram);
+        let mut dma = Dma::new("wrap_at_end", DmaTiming::default());
+
+        // A transfer ending exactly at the end of the window is in bounds,
+        // even if the final address wraps around.
+        let end = dma.xfer(&mut bus, DmaXfer::linear(0x100, 0x7F0, 16).wrap(0, 0x800));
+        assert_eq!(end.dst, 0x000);
+        assert!(!dma.out_of_bounds());
+    }
+
+    #[test]
+    fn end_of_memory() {
+        let ram = Mem::new("dma::end_of_memory", 0x1000, MemFlags::default());
+        let mut bus = bus_with_ram(&ram);
+        let mut dma = Dma::new("end_of_memory", DmaTiming::default());
+
+        // The row crosses the end of RAM: the part within RAM is copied,
+        // the rest goes to the unmapped area, without panicking.
+        for i in 0..16 {
+            bus.write::<u8>(0x100 + i, i as u8);
+        }
+        dma.xfer(&mut bus, DmaXfer::linear(0x100, 0xFF8, 16));
+        assert_eq!(bus.read::<u32>(0xFFC), 0x0706_0504);
+        assert!(dma.out_of_bounds());
+    }
+
+    #[test]
+    fn stream_wrap() {
+        let ram = Mem::new("dma::stream_wrap", 0x1000, MemFlags::default());
+        let mut bus = bus_with_ram(&ram);
+        let mut dma = Dma::new("stream_wrap", DmaTiming::default());
+
+        bus.write::<u32>(0xFFC, 0x1111_1111);
+        bus.write::<u32>(0x000, 0x2222_2222);
+        let mut xfer = DmaXfer::linear(0xFFC, 0, 8).wrap(0x1000, 0);
+        assert_eq!(dma.stream::<_, u32>(&bus, &mut xfer), Some(0x1111_1111));
+        assert_eq!(xfer.src, 0x000);
+        assert!(dma.out_of_bounds());
+        assert_eq!(dma.stream::<_, u32>(&bus, &mut xfer), Some(0x2222_2222));
+    }
+
+    #[test]
+    fn trace_oob() {
+        let ram = Mem::new("dma::trace_oob", 0x1000, MemFlags::default());
+        let mut bus = bus_with_ram(&ram);
+        let mut dma = Dma::new("OOB DMA", DmaTiming::default());
+
+        let mut dbg = Debugger::new(&vec![]);
+        dbg.set_hw_events(vec![oob_event("OOB DMA")]);
+        dbg.set_hw_event_break(&oob_event("OOB DMA"), true);
+        let tracer = dbg.new_tracer();
+
+        dma.xfer(&mut bus, DmaXfer::linear(0x100, 0x200, 16));
+        assert!(dma.trace_oob(&tracer).is_ok());
+
+        dma.xfer(&mut bus, DmaXfer::linear(0x100, 0x7F8, 16).wrap(0, 0x800));
+        match dma.trace_oob(&tracer).map_err(|evt| *evt) {
+            Err(TraceEvent::HwEvent(name)) => assert_eq!(name, "OOB DMA: out-of-bounds transfer"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // The event is reported only once.
+        assert!(!dma.out_of_bounds());
+        assert!(dma.trace_oob(&tracer).is_ok());
+    }
+}
@@ -0,0 +1,28 @@
+//! Error types shared by all emulators built on this crate.
+
+// failure_derive defines its impls inside named consts.
+#![allow(unknown_lints, non_local_definitions)]
+
+use failure::Fail;
+
+/// An error happened while setting up an emulated device (eg: mapping it
+/// into a bus).
+#[derive(Debug, Fail)]
+pub enum DeviceError {
+    /// A bank of a device could not be mapped into a bus, usually because
+    /// it overlaps with something else that was already mapped.
+    #[fail(
+        display = "cannot map device {} (bank {}) at 0x{:08x}: {}",
+        device, bank, base, reason
+    )]
+    BusMapping {
+        device: &'static str,
+        bank: usize,
+        base: u32,
+        reason: &'static str,
+    },
+
+    /// A device was accessed before being registered.
+    #[fail(display = "device not registered: {}", device)]
+    NotRegistered { device: &'static str },
+}
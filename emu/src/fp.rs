@@ -1,14 +1,16 @@
+//! Generic fixed-point arithmetic, parametrized on the underlying integer
+//! type and on the number of fractional bits.
+
 extern crate num;
 extern crate typenum;
 use self::num::cast::NumCast;
 use self::num::{PrimInt, ToPrimitive, Zero};
 use self::typenum::{Unsigned, U0, U128, U16, U32, U64, U8};
 use std::fmt;
-use std::iter;
 use std::marker::PhantomData;
 use std::ops;
 
-pub trait FixedPointInt: PrimInt + ToPrimitive + iter::Step {
+pub trait FixedPointInt: PrimInt + ToPrimitive {
     type DoubleInt: FixedPointInt;
     type Len: Unsigned;
 
@@ -116,7 +118,7 @@ impl<FP: FixedPoint> Q<FP> {
         if bits >> FP::shift() != v {
             panic!("fixed point overflow")
         }
-        Self { bits: bits }
+        Self { bits }
     }
 
     #[inline(always)]
@@ -126,12 +128,12 @@ impl<FP: FixedPoint> Q<FP> {
         if (int >> FP::shift()) as f32 != v.floor() {
             panic!("fixed point overflow")
         }
-        Self { bits: bits }
+        Self { bits }
     }
 
     #[inline(always)]
     pub fn from_bits(bits: FP::BITS) -> Self {
-        Self { bits: bits }
+        Self { bits }
     }
 
     #[inline(always)]
@@ -176,7 +178,7 @@ impl<FP: FixedPoint> Q<FP> {
         let bits: FP::BITS = if FP2::shift() > FP::shift() {
             (fp.bits >> (FP2::shift() - FP::shift())).cast()
         } else {
-            (fp.bits.cast::<FP::BITS>() << (FP::shift() - FP2::shift()))
+            fp.bits.cast::<FP::BITS>() << (FP::shift() - FP2::shift())
         };
         let q = Self::from_bits(bits);
         if q.floor().to_i64().unwrap() != fp.floor().to_i64().unwrap() {
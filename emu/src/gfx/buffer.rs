@@ -167,7 +167,6 @@ impl<'a: 's, 's, CF: ColorFormat, O: ByteOrder> GfxBufferMut<'a, CF, O> {
 }
 
 pub trait BufferLineGetter<CF: ColorFormat> {
-    #[inline(always)]
     fn get(&self, x: usize) -> Color<CF>;
     #[inline(always)]
     fn get2(&self, x: usize) -> (Color<CF>, Color<CF>) {
@@ -184,7 +183,6 @@ pub trait BufferLineGetter<CF: ColorFormat> {
 }
 
 pub trait BufferLineSetter<CF: ColorFormat> {
-    #[inline(always)]
     fn set(&mut self, x: usize, c: Color<CF>);
     #[inline(always)]
     fn set2(&mut self, x: usize, c1: Color<CF>, c2: Color<CF>) {
@@ -198,54 +196,38 @@ pub trait BufferLineSetter<CF: ColorFormat> {
     }
 }
 
-impl<'a, CF, O> BufferLineGetter<CF> for GfxLine<'a, CF, O>
-where
-    CF: ColorFormat,
-    O: ByteOrder,
-{
-    #[inline(always)]
-    default fn get(&self, x: usize) -> Color<CF> {
-        Color::from_bits(CF::U::endian_read_from::<O>(
-            &self.mem[x * CF::BITS::to_usize() / 8..],
-        ))
+// Return the shift of the nibble holding pixel x in a 4-bit buffer. Within
+// each byte, little-endian buffers store the even pixel in the low nibble,
+// while big-endian buffers store it in the high nibble.
+#[inline(always)]
+fn nibble_shift<O: ByteOrder>(x: usize) -> usize {
+    if O::read_u16(&[1, 0]) == 1 {
+        (x & 1) * 4
+    } else {
+        (!x & 1) * 4
     }
 }
 
-impl<'a, CF> BufferLineGetter<CF> for GfxLine<'a, CF, LittleEndian>
-where
-    CF: ColorFormat<BITS = U4>,
-{
-    #[inline(always)]
-    fn get(&self, x: usize) -> Color<CF> {
-        let val = self.mem[x / 2] >> ((x & 1) * 4);
-        Color::from_bits(CF::U::truncate_from(val.into()))
-    }
-    #[inline(always)]
-    fn get2(&self, x: usize) -> (Color<CF>, Color<CF>) {
-        let val = self.mem[x / 2];
-        (
-            Color::from_bits(CF::U::truncate_from((val & 0xF).into())),
-            Color::from_bits(CF::U::truncate_from((val >> 4).into())),
-        )
+#[inline(always)]
+fn line_get<CF: ColorFormat, O: ByteOrder>(mem: &[u8], x: usize) -> Color<CF> {
+    if CF::BITS::to_usize() == 4 {
+        let val = mem[x / 2] >> nibble_shift::<O>(x);
+        Color::from_bits(CF::U::truncate_from((val & 0xF).into()))
+    } else {
+        Color::from_bits(CF::U::endian_read_from::<O>(
+            &mem[x * CF::BITS::to_usize() / 8..],
+        ))
     }
 }
 
-impl<'a, CF> BufferLineGetter<CF> for GfxLine<'a, CF, BigEndian>
+impl<'a, CF, O> BufferLineGetter<CF> for GfxLine<'a, CF, O>
 where
-    CF: ColorFormat<BITS = U4>,
+    CF: ColorFormat,
+    O: ByteOrder,
 {
     #[inline(always)]
     fn get(&self, x: usize) -> Color<CF> {
-        let val = self.mem[x / 2] >> ((!x & 1) * 4);
-        Color::from_bits(CF::U::truncate_from(val.into()))
-    }
-    #[inline(always)]
-    fn get2(&self, x: usize) -> (Color<CF>, Color<CF>) {
-        let val = self.mem[x / 2];
-        (
-            Color::from_bits(CF::U::truncate_from((val >> 4).into())),
-            Color::from_bits(CF::U::truncate_from((val & 0xF).into())),
-        )
+        line_get::<CF, O>(self.mem, x)
     }
 }
 
@@ -253,34 +235,10 @@ impl<'a, CF, O> BufferLineGetter<CF> for GfxLineMut<'a, CF, O>
 where
     CF: ColorFormat,
     O: ByteOrder,
-{
-    #[inline(always)]
-    default fn get(&self, x: usize) -> Color<CF> {
-        Color::from_bits(CF::U::endian_read_from::<O>(
-            &self.mem[x * CF::BITS::to_usize() / 8..],
-        ))
-    }
-}
-
-impl<'a, CF> BufferLineGetter<CF> for GfxLineMut<'a, CF, LittleEndian>
-where
-    CF: ColorFormat<BITS = U4>,
 {
     #[inline(always)]
     fn get(&self, x: usize) -> Color<CF> {
-        let val = self.mem[x / 2] >> ((x & 1) * 4);
-        Color::from_bits(CF::U::truncate_from(val.into()))
-    }
-}
-
-impl<'a, CF> BufferLineGetter<CF> for GfxLineMut<'a, CF, BigEndian>
-where
-    CF: ColorFormat<BITS = U4>,
-{
-    #[inline(always)]
-    fn get(&self, x: usize) -> Color<CF> {
-        let val = self.mem[x / 2] >> ((!x & 1) * 4);
-        Color::from_bits(CF::U::truncate_from(val.into()))
+        line_get::<CF, O>(self.mem, x)
     }
 }
 
@@ -288,46 +246,16 @@ impl<'a, CF, O> BufferLineSetter<CF> for GfxLineMut<'a, CF, O>
 where
     CF: ColorFormat,
     O: ByteOrder,
-{
-    #[inline(always)]
-    default fn set(&mut self, x: usize, c: Color<CF>) {
-        CF::U::endian_write_to::<O>(&mut self.mem[x * CF::BITS::to_usize() / 8..], c.to_bits());
-    }
-}
-
-impl<'a, CF> BufferLineSetter<CF> for GfxLineMut<'a, CF, LittleEndian>
-where
-    CF: ColorFormat<BITS = U4>,
-{
-    #[inline(always)]
-    fn set(&mut self, x: usize, c: Color<CF>) {
-        let val = self.mem[x / 2];
-        let mask = 0xF0 >> ((x & 1) * 4);
-        let val = (val & mask) | (c.to_bits().into() << ((x & 1) * 4)) as u8;
-        self.mem[x / 2] = val as u8;
-    }
-    #[inline(always)]
-    fn set2(&mut self, x: usize, c1: Color<CF>, c2: Color<CF>) {
-        let val = c1.to_bits().into() | (c2.to_bits().into() << 4);
-        self.mem[x / 2] = val as u8;
-    }
-}
-
-impl<'a, CF> BufferLineSetter<CF> for GfxLineMut<'a, CF, BigEndian>
-where
-    CF: ColorFormat<BITS = U4>,
 {
     #[inline(always)]
     fn set(&mut self, x: usize, c: Color<CF>) {
-        let val = self.mem[x / 2];
-        let mask = 0xF0 >> ((!x & 1) * 4);
-        let val = (val & mask) | (c.to_bits().into() << ((!x & 1) * 4)) as u8;
-        self.mem[x / 2] = val;
-    }
-    #[inline(always)]
-    fn set2(&mut self, x: usize, c1: Color<CF>, c2: Color<CF>) {
-        let val = c2.to_bits().into() | (c1.to_bits().into() << 4);
-        self.mem[x / 2] = val as u8;
+        if CF::BITS::to_usize() == 4 {
+            let shift = nibble_shift::<O>(x);
+            let val = self.mem[x / 2] & (0xF0 >> shift);
+            self.mem[x / 2] = val | (c.to_bits().into() << shift) as u8;
+        } else {
+            CF::U::endian_write_to::<O>(&mut self.mem[x * CF::BITS::to_usize() / 8..], c.to_bits());
+        }
     }
 }
 
@@ -357,8 +285,7 @@ impl<CF: ColorFormat, O: ByteOrder> OwnedGfxBuffer<CF, O> {
     }
 
     pub fn new(width: usize, height: usize) -> OwnedGfxBuffer<CF, O> {
-        let mut v = Vec::new();
-        v.resize(width * height * CF::BITS::to_usize() / 8, 0);
+        let v = vec![0; width * height * CF::BITS::to_usize() / 8];
         OwnedGfxBuffer {
             mem: v,
             width,
@@ -408,14 +335,8 @@ mod tests {
         v1.resize(128 * 128 * 2, 0);
         v2.resize(128 * 128 * 4, 0);
 
-        assert_eq!(
-            GfxBuffer::<Rgb888, LittleEndian>::new(&mut v1, 128, 128, 256).is_ok(),
-            false
-        );
-        assert_eq!(
-            GfxBuffer::<Rgb565, LittleEndian>::new(&mut v1, 128, 128, 256).is_ok(),
-            true
-        );
+        assert!(GfxBuffer::<Rgb888, LittleEndian>::new(&v1, 128, 128, 256).is_err());
+        assert!(GfxBuffer::<Rgb565, LittleEndian>::new(&v1, 128, 128, 256).is_ok());
 
         {
             let mut buf1 =
@@ -457,14 +378,8 @@ mod tests {
         v1.resize(128 * 128 / 2, 0);
         v2.resize(128 * 128 / 2, 0);
 
-        assert_eq!(
-            GfxBuffer::<I4, LittleEndian>::new(&mut v1, 128, 128, 63).is_ok(),
-            false
-        );
-        assert_eq!(
-            GfxBuffer::<I4, LittleEndian>::new(&mut v1, 128, 128, 64).is_ok(),
-            true
-        );
+        assert!(GfxBuffer::<I4, LittleEndian>::new(&v1, 128, 128, 63).is_err());
+        assert!(GfxBuffer::<I4, LittleEndian>::new(&v1, 128, 128, 64).is_ok());
 
         {
             let mut buf1 = GfxBufferMut::<I4, LittleEndian>::new(&mut v1, 128, 128, 64).unwrap();
@@ -485,7 +400,7 @@ mod tests {
 
         {
             let mut buf1 = GfxBufferMut::<I4, LittleEndian>::new(&mut v2, 128, 128, 64).unwrap();
-            let buf2 = GfxBuffer::<I4, LittleEndian>::new(&mut v1, 128, 128, 64).unwrap();
+            let buf2 = GfxBuffer::<I4, LittleEndian>::new(&v1, 128, 128, 64).unwrap();
             for y in 0..128 {
                 let mut dst = buf1.line(y);
                 let src = buf2.line(y);
@@ -502,8 +417,7 @@ mod tests {
 
     #[test]
     fn endian() {
-        let mut v1 = Vec::<u8>::new();
-        v1.resize(128 * 128 * 4, 0);
+        let mut v1 = vec![0; 128 * 128 * 4];
 
         let c = Color::new_clamped(0x12, 0x34, 0x56, 0x78);
         {
@@ -512,15 +426,15 @@ mod tests {
             assert_eq!(buf1.line(4).get(4), c);
         }
         {
-            let buf1 = GfxBufferBE::<Abgr8888>::new(&mut v1, 128, 128, 128 * 4).unwrap();
+            let buf1 = GfxBufferBE::<Abgr8888>::new(&v1, 128, 128, 128 * 4).unwrap();
             assert_eq!(buf1.line(4).get(4), c);
         }
         {
-            let buf1 = GfxBufferLE::<Rgba8888>::new(&mut v1, 128, 128, 128 * 4).unwrap();
+            let buf1 = GfxBufferLE::<Rgba8888>::new(&v1, 128, 128, 128 * 4).unwrap();
             assert_eq!(buf1.line(4).get(4), c.cconv());
         }
         {
-            let buf1 = GfxBufferBE::<I4>::new(&mut v1, 128 * 8, 128, 128 * 4).unwrap();
+            let buf1 = GfxBufferBE::<I4>::new(&v1, 128 * 8, 128, 128 * 4).unwrap();
             assert_eq!(buf1.line(4).get(32), Color::new_clamped(0x1, 0, 0, 0));
             assert_eq!(buf1.line(4).get(33), Color::new_clamped(0x2, 0, 0, 0));
             assert_eq!(buf1.line(4).get(36), Color::new_clamped(0x5, 0, 0, 0));
@@ -543,7 +457,7 @@ mod tests {
             );
         }
         {
-            let buf1 = GfxBufferLE::<I4>::new(&mut v1, 128 * 8, 128, 128 * 4).unwrap();
+            let buf1 = GfxBufferLE::<I4>::new(&v1, 128 * 8, 128, 128 * 4).unwrap();
             assert_eq!(buf1.line(4).get(32), Color::new_clamped(0x2, 0, 0, 0));
             assert_eq!(buf1.line(4).get(33), Color::new_clamped(0x1, 0, 0, 0));
             assert_eq!(buf1.line(4).get(36), Color::new_clamped(0x6, 0, 0, 0));
@@ -30,7 +30,7 @@ impl<U: MemInt, N: Unsigned, S: Unsigned> Value<U, N, S> {
     }
 
     fn new<W: Into<i32>>(val: W) -> Option<Self> {
-        let val = val.into() as i32;
+        let val = val.into();
         if val < 0 || val > Self::max() {
             None
         } else {
@@ -42,7 +42,7 @@ impl<U: MemInt, N: Unsigned, S: Unsigned> Value<U, N, S> {
     }
 
     fn new_clamped<W: Into<i32>>(val: W) -> Self {
-        let mut val = val.into() as i32;
+        let mut val = val.into();
         if val < 0 {
             val = 0;
         } else if val > Self::max() {
@@ -54,12 +54,8 @@ impl<U: MemInt, N: Unsigned, S: Unsigned> Value<U, N, S> {
         }
     }
 
-    fn from_f32_clamped(mut val: f32) -> Self {
-        if val > 1.0 {
-            val = 1.0;
-        } else if val < 0.0 {
-            val = 0.0;
-        }
+    fn from_f32_clamped(val: f32) -> Self {
+        let val = val.clamp(0.0, 1.0);
         let max = Self::max() as f32;
         Self {
             val: (val * max) as i32,
@@ -97,7 +93,7 @@ impl<U: MemInt, N: Unsigned, S: Unsigned> Value<U, N, S> {
 
                 // Normal cases
                 (f, t) if f == t => v.val,
-                (f, t) if f >= t => (v.val >> (f - t)),
+                (f, t) if f >= t => v.val >> (f - t),
                 (f, t) if f < t => {
                     // TODO: this formula doesn't work for all bit sizes, but only for common one.
                     // We're basically doing (v<<3)|(v>>2) when converting 5 bits to 8 bits.
@@ -201,22 +197,10 @@ pub struct Color<CF: ColorFormat> {
 impl<CF: ColorFormat> Color<CF> {
     pub fn new<W: Into<i32>>(r: W, g: W, b: W, a: W) -> Option<Self> {
         Some(Self {
-            r: match Value::new(r) {
-                Some(r) => r,
-                None => return None,
-            },
-            g: match Value::new(g) {
-                Some(r) => r,
-                None => return None,
-            },
-            b: match Value::new(b) {
-                Some(b) => b,
-                None => return None,
-            },
-            a: match Value::new(a) {
-                Some(a) => a,
-                None => return None,
-            },
+            r: Value::new(r)?,
+            g: Value::new(g)?,
+            b: Value::new(b)?,
+            a: Value::new(a)?,
         })
     }
 
@@ -286,59 +270,49 @@ pub type Rgba8888 = cf<u32, U32, U8, U0, U8, U8, U8, U16, U8, U24>;
 pub type Abgr8888 = cf<u32, U32, U8, U24, U8, U16, U8, U8, U8, U0>;
 
 pub trait ColorConverter<CF2: ColorFormat>: Sized {
-    #[inline(always)]
     fn cconv(self) -> Color<CF2>;
 }
 
+// Greyscale formats (eg: I4, I8) store the intensity in the red component,
+// and have no green and blue components.
+#[inline(always)]
+fn is_greyscale<CF: ColorFormat>() -> bool {
+    CF::RN::to_usize() != 0 && CF::GN::to_usize() == 0 && CF::BN::to_usize() == 0
+}
+
 impl<CF1: ColorFormat, CF2: ColorFormat> ColorConverter<CF2> for Color<CF1> {
     #[inline(always)]
-    default fn cconv(self) -> Color<CF2> {
-        Color {
-            r: self.r.into(),
-            g: self.g.into(),
-            b: self.b.into(),
-            a: self.a.into(),
+    fn cconv(self) -> Color<CF2> {
+        match (is_greyscale::<CF1>(), is_greyscale::<CF2>()) {
+            (true, false) => Color {
+                r: self.r.into(),
+                g: self.r.into(),
+                b: self.r.into(),
+                a: Value::new_clamped(255),
+            },
+            (false, true) => {
+                let i = self.r.as_f32() * 0.30 + self.g.as_f32() * 0.59 + self.b.as_f32() * 0.11;
+                Color::from_f32_clamped(i, 0.0, 0.0, 0.0)
+            }
+            _ => Color {
+                r: self.r.into(),
+                g: self.g.into(),
+                b: self.b.into(),
+                a: self.a.into(),
+            },
         }
     }
 }
 
-macro_rules! define_greyscale_conversions {
-    ($cfgrey:ident, $($cf2:ident),+) => {
-        $(
-            impl ColorConverter<$cf2> for Color<$cfgrey> {
-                #[inline(always)]
-                fn cconv(self) -> Color<$cf2> {
-                    Color {
-                        r: self.r.into(),
-                        g: self.r.into(),
-                        b: self.r.into(),
-                        a: Value::new_clamped(255),
-                    }
-                }
-            }
-            impl ColorConverter<$cfgrey> for Color<$cf2> {
-                #[inline(always)]
-                fn cconv(self) -> Color<$cfgrey> {
-                    let i = self.r.as_f32() * 0.30 + self.g.as_f32() * 0.59 + self.b.as_f32() * 0.11;
-                    Color::from_f32_clamped(i, 0.0, 0.0, 0.0)
-                }
-            }
-        )+
-    };
-}
-
-define_greyscale_conversions!(I4, Rgba8888, Rgba5551, Rgb888, Rgb565, Rgb555);
-define_greyscale_conversions!(I8, Rgba8888, Rgba5551, Rgb888, Rgb565, Rgb555);
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn color() {
-        assert_eq!(Color::<Rgb565>::new(0x10, 0x10, 0x10, 0).is_some(), true);
-        assert_eq!(Color::<Rgb565>::new(0x1F, 0x3F, 0x1F, 0).is_some(), true);
-        assert_eq!(Color::<Rgb565>::new(0x1F, 0x40, 0x1F, 0).is_some(), false);
+        assert!(Color::<Rgb565>::new(0x10, 0x10, 0x10, 0).is_some());
+        assert!(Color::<Rgb565>::new(0x1F, 0x3F, 0x1F, 0).is_some());
+        assert!(Color::<Rgb565>::new(0x1F, 0x40, 0x1F, 0).is_none());
 
         let c1 = Color::<Rgb888>::new_clamped(0xAA, 0x77, 0x33, 0);
         let c2: Color<Rgb555> = c1.cconv();
@@ -370,16 +344,13 @@ mod tests {
 
     #[test]
     fn alpha() {
-        assert_eq!(Color::<Rgb555>::new(0x10, 0x10, 0x10, 0).is_some(), true);
-        assert_eq!(Color::<Rgb555>::new(0x10, 0x10, 0x10, 1).is_none(), true);
-        assert_eq!(Color::<Rgba5551>::new(0x10, 0x10, 0x10, 1).is_some(), true);
-        assert_eq!(Color::<Rgba5551>::new(0x10, 0x10, 0x10, 2).is_none(), true);
+        assert!(Color::<Rgb555>::new(0x10, 0x10, 0x10, 0).is_some());
+        assert!(Color::<Rgb555>::new(0x10, 0x10, 0x10, 1).is_none());
+        assert!(Color::<Rgba5551>::new(0x10, 0x10, 0x10, 1).is_some());
+        assert!(Color::<Rgba5551>::new(0x10, 0x10, 0x10, 2).is_none());
 
-        assert_eq!(Color::<Rgb888>::new(0x10, 0x10, 0x10, 0x10).is_none(), true);
-        assert_eq!(
-            Color::<Rgba8888>::new(0x10, 0x10, 0x10, 0x10).is_some(),
-            true
-        );
+        assert!(Color::<Rgb888>::new(0x10, 0x10, 0x10, 0x10).is_none());
+        assert!(Color::<Rgba8888>::new(0x10, 0x10, 0x10, 0x10).is_some());
 
         // Check conversions semantics for 1-bit alpha
         assert_eq!(
@@ -466,10 +437,10 @@ mod tests {
 
     #[test]
     fn intensity() {
-        assert_eq!(Color::<I8>::new(0x80, 0x0, 0x0, 0x0).is_some(), true);
-        assert_eq!(Color::<I8>::new(0x80, 0x0, 0x1, 0x0).is_some(), false);
-        assert_eq!(Color::<I8>::new(0x80, 0x1, 0x0, 0x0).is_some(), false);
-        assert_eq!(Color::<I8>::new(0x80, 0x0, 0x0, 0x1).is_some(), false);
+        assert!(Color::<I8>::new(0x80, 0x0, 0x0, 0x0).is_some());
+        assert!(Color::<I8>::new(0x80, 0x0, 0x1, 0x0).is_none());
+        assert!(Color::<I8>::new(0x80, 0x1, 0x0, 0x0).is_none());
+        assert!(Color::<I8>::new(0x80, 0x0, 0x0, 0x1).is_none());
 
         let v1 = Color::<I8>::new(0x80, 0x0, 0x0, 0x0).unwrap();
         let v2: Color<Rgb888> = v1.cconv();
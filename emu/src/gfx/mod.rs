@@ -1,3 +1,6 @@
+//! Graphic buffers, parametrized on the pixel format (see
+//! [`ColorFormat`](trait.ColorFormat.html)), and simple geometry types.
+
 mod buffer;
 mod color;
 mod geom;
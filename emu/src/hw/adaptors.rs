@@ -0,0 +1,402 @@
+//! Original controllers connected through USB adaptors: raphnet N64-to-USB
+//! adapters and the official GameCube adapter (WUP-028).
+//!
+//! Both are accessed in raw mode, bypassing the operating system joystick
+//! layer: raphnet adapters are polled with N64 protocol commands (so the
+//! analog stick is reported with its true range, without the scaling
+//! applied by the adapter firmware), while the GameCube adapter is not a
+//! HID device and is driven through libusb.
+//!
+//! Controller states are converted into
+//! [`InputEvent`](../input/enum.InputEvent.html)s for the emulated joysticks,
+//! using the names of the N64 controller inputs ("A", "c-up", "X", ...):
+//! inputs missing in the emulated device are ignored.
+
+use crate::input::{InputDeviceKind, InputEvent, InputManager};
+
+use bitflags::bitflags;
+use failure::{format_err, Error};
+use rusb::UsbContext;
+use slog::{info, warn};
+
+use std::time::Duration;
+
+bitflags! {
+    /// Buttons of a N64 controller, in the order of the N64 protocol
+    /// (response to the "read buttons" command).
+    #[derive(Default)]
+    pub struct PadButtons: u16 {
+        const A =       0x8000;
+        const B =       0x4000;
+        const Z =       0x2000;
+        const START =   0x1000;
+        const UP =      0x0800;
+        const DOWN =    0x0400;
+        const LEFT =    0x0200;
+        const RIGHT =   0x0100;
+        const L =       0x0020;
+        const R =       0x0010;
+        const C_UP =    0x0008;
+        const C_DOWN =  0x0004;
+        const C_LEFT =  0x0002;
+        const C_RIGHT = 0x0001;
+    }
+}
+
+const BUTTON_NAMES: [(&str, PadButtons); 14] = [
+    ("A", PadButtons::A),
+    ("B", PadButtons::B),
+    ("Z", PadButtons::Z),
+    ("S", PadButtons::START),
+    ("up", PadButtons::UP),
+    ("down", PadButtons::DOWN),
+    ("left", PadButtons::LEFT),
+    ("right", PadButtons::RIGHT),
+    ("L", PadButtons::L),
+    ("R", PadButtons::R),
+    ("c-up", PadButtons::C_UP),
+    ("c-down", PadButtons::C_DOWN),
+    ("c-left", PadButtons::C_LEFT),
+    ("c-right", PadButtons::C_RIGHT),
+];
+
+/// State of a controller, in N64 format: the stick position is the raw
+/// value reported by the controller (about ±80 on original controllers).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PadState {
+    pub buttons: PadButtons,
+    pub x: i8,
+    pub y: i8,
+}
+
+impl PadState {
+    /// Decode the 4-byte response to the N64 "read buttons" command.
+    pub fn from_n64(resp: &[u8]) -> PadState {
+        PadState {
+            buttons: PadButtons::from_bits_truncate((resp[0] as u16) << 8 | resp[1] as u16),
+            x: resp[2] as i8,
+            y: resp[3] as i8,
+        }
+    }
+
+    // Generate the events to move the inputs of an emulated joystick from
+    // the state prev to self.
+    fn diff(&self, prev: &PadState, device: &str) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        for (name, b) in BUTTON_NAMES.iter() {
+            let pressed = self.buttons.contains(*b);
+            if pressed != prev.buttons.contains(*b) {
+                events.push(InputEvent::Digital(device.into(), (*name).into(), pressed));
+            }
+        }
+        // Analog inputs are full-range 16-bit values: the high byte is the
+        // value seen by the emulated software.
+        if self.x != prev.x {
+            events.push(InputEvent::Analog(
+                device.into(),
+                "X".into(),
+                (self.x as i16) << 8,
+            ));
+        }
+        if self.y != prev.y {
+            events.push(InputEvent::Analog(
+                device.into(),
+                "Y".into(),
+                (self.y as i16) << 8,
+            ));
+        }
+        events
+    }
+}
+
+/// A USB adaptor, with one or more controller ports.
+pub trait ControllerAdaptor {
+    fn name(&self) -> &str;
+
+    /// Read the state of all ports (None if no controller is connected).
+    fn poll(&mut self) -> Result<Vec<Option<PadState>>, Error>;
+}
+
+/// Open all the supported adaptors connected to the host. Adaptors that
+/// cannot be opened are reported through the logger, and skipped.
+pub fn open_adaptors(logger: &slog::Logger) -> Vec<Box<dyn ControllerAdaptor>> {
+    let mut adaptors: Vec<Box<dyn ControllerAdaptor>> = Vec::new();
+    match Raphnet::open_all(logger) {
+        Ok(r) => adaptors.extend(
+            r.into_iter()
+                .map(|a| Box::new(a) as Box<dyn ControllerAdaptor>),
+        ),
+        Err(err) => warn!(logger, "cannot enumerate raphnet adapters: {}", err),
+    }
+    match GcAdapter::open() {
+        Ok(Some(a)) => adaptors.push(Box::new(a)),
+        Ok(None) => {}
+        Err(err) => warn!(logger, "cannot open GameCube adapter: {}", err),
+    }
+    adaptors
+}
+
+// raphnet-tech adapters. Raw access is done through HID feature reports,
+// with a block I/O request that forwards a command to a controller port and
+// returns its response.
+const RAPHNET_VID: u16 = 0x289B;
+const RQ_GCN64_BLOCK_IO: u8 = 0x04;
+const BIO_RX_LEN_TIMEDOUT: u8 = 0x80;
+const BIO_RX_LEN_MASK: u8 = 0x3F;
+const RAPHNET_REPORT_SIZE: usize = 64;
+const N64_CMD_READ_BUTTONS: u8 = 0x01;
+
+/// A raphnet N64-to-USB adapter (single or dual port).
+pub struct Raphnet {
+    name: String,
+    dev: hidapi::HidDevice,
+    ports: usize,
+}
+
+impl Raphnet {
+    /// Open all the raphnet N64 adapters connected to the host. Adapters
+    /// that cannot be opened are reported through the logger, and skipped.
+    pub fn open_all(logger: &slog::Logger) -> Result<Vec<Raphnet>, Error> {
+        // HidApi must outlive the devices, and it is a process-wide
+        // resource anyway.
+        let api: &'static hidapi::HidApi = Box::leak(Box::new(
+            hidapi::HidApi::new().map_err(|err| format_err!("{}", err))?,
+        ));
+
+        let mut adaptors = Vec::new();
+        for info in api.devices() {
+            let product = info.product_string.clone().unwrap_or_default();
+            if info.vendor_id != RAPHNET_VID || !product.contains("N64") {
+                continue;
+            }
+            match api.open_path(&info.path) {
+                Ok(dev) => adaptors.push(Raphnet {
+                    ports: if product.contains("Dual") { 2 } else { 1 },
+                    name: format!("{} ({:04x})", product, info.product_id),
+                    dev,
+                }),
+                Err(err) => warn!(logger, "cannot open {}: {}", product, err),
+            }
+        }
+        Ok(adaptors)
+    }
+
+    // Send a N64 command to a controller port, and return its response (None
+    // if no controller answered).
+    fn block_io(&self, port: usize, cmd: &[u8], rxlen: usize) -> Result<Option<Vec<u8>>, Error> {
+        let mut buf = [0u8; RAPHNET_REPORT_SIZE];
+        buf[1] = RQ_GCN64_BLOCK_IO;
+        buf[2] = port as u8;
+        buf[3] = cmd.len() as u8;
+        buf[4] = rxlen as u8;
+        buf[5..5 + cmd.len()].copy_from_slice(cmd);
+        self.dev
+            .send_feature_report(&buf)
+            .map_err(|err| format_err!("{}", err))?;
+        self.dev
+            .get_feature_report(&mut buf)
+            .map_err(|err| format_err!("{}", err))?;
+
+        if buf[1] != RQ_GCN64_BLOCK_IO || buf[4] & BIO_RX_LEN_TIMEDOUT != 0 {
+            return Ok(None);
+        }
+        let len = (buf[4] & BIO_RX_LEN_MASK) as usize;
+        if len < rxlen {
+            return Ok(None);
+        }
+        Ok(Some(buf[5..5 + len].to_vec()))
+    }
+}
+
+impl ControllerAdaptor for Raphnet {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn poll(&mut self) -> Result<Vec<Option<PadState>>, Error> {
+        (0..self.ports)
+            .map(|port| {
+                let resp = self.block_io(port, &[N64_CMD_READ_BUTTONS], 4)?;
+                Ok(resp.map(|r| PadState::from_n64(&r)))
+            })
+            .collect()
+    }
+}
+
+// Official GameCube adapter (WUP-028). After the initialization command,
+// each interrupt transfer returns the state of the four ports.
+const GC_VID: u16 = 0x057E;
+const GC_PID: u16 = 0x0337;
+const GC_EP_IN: u8 = 0x81;
+const GC_EP_OUT: u8 = 0x02;
+const GC_CMD_INIT: u8 = 0x13;
+const GC_REPORT_STATE: u8 = 0x21;
+const GC_TIMEOUT: Duration = Duration::from_millis(16);
+
+// Stick deflection of GameCube and N64 controllers (from the center), used
+// to scale the GameCube sticks to the N64 range.
+const GC_STICK_RANGE: i32 = 100;
+const N64_STICK_RANGE: i32 = 80;
+
+// Deflection of the C-stick that presses the C buttons.
+const GC_CSTICK_THRESHOLD: i32 = 50;
+
+/// The official GameCube adapter. Controllers are mapped to the N64 layout:
+/// the C-stick drives the C buttons, and X/Y are not used.
+pub struct GcAdapter {
+    handle: rusb::DeviceHandle<rusb::Context>,
+    last: Vec<Option<PadState>>, // state of the ports in the last report
+}
+
+impl GcAdapter {
+    /// Open the adapter, if it is connected.
+    pub fn open() -> Result<Option<GcAdapter>, Error> {
+        let ctx = rusb::Context::new()?;
+        let mut handle = match ctx.open_device_with_vid_pid(GC_VID, GC_PID) {
+            Some(handle) => handle,
+            None => return Ok(None),
+        };
+        // On Linux, the kernel driver (if any) must be detached.
+        if handle.kernel_driver_active(0).unwrap_or(false) {
+            handle.detach_kernel_driver(0)?;
+        }
+        handle.claim_interface(0)?;
+        handle.write_interrupt(GC_EP_OUT, &[GC_CMD_INIT], GC_TIMEOUT)?;
+        Ok(Some(GcAdapter {
+            handle,
+            last: vec![None; 4],
+        }))
+    }
+
+    fn stick(val: u8) -> i8 {
+        let v = (val as i32 - 128) * N64_STICK_RANGE / GC_STICK_RANGE;
+        v.max(-128).min(127) as i8
+    }
+
+    // Decode the 9-byte state of a port.
+    fn decode(port: &[u8]) -> Option<PadState> {
+        // Upper nibble: 1 = wired controller, 2 = wireless
+        if port[0] >> 4 == 0 {
+            return None;
+        }
+        let mut buttons = PadButtons::empty();
+        let map = [
+            (port[1], 0x01, PadButtons::A),
+            (port[1], 0x02, PadButtons::B),
+            (port[1], 0x10, PadButtons::LEFT),
+            (port[1], 0x20, PadButtons::RIGHT),
+            (port[1], 0x40, PadButtons::DOWN),
+            (port[1], 0x80, PadButtons::UP),
+            (port[2], 0x01, PadButtons::START),
+            (port[2], 0x02, PadButtons::Z),
+            (port[2], 0x04, PadButtons::R),
+            (port[2], 0x08, PadButtons::L),
+        ];
+        for (byte, mask, b) in map.iter() {
+            if byte & mask != 0 {
+                buttons.insert(*b);
+            }
+        }
+        let (cx, cy) = (port[5] as i32 - 128, port[6] as i32 - 128);
+        buttons.set(PadButtons::C_RIGHT, cx > GC_CSTICK_THRESHOLD);
+        buttons.set(PadButtons::C_LEFT, cx < -GC_CSTICK_THRESHOLD);
+        buttons.set(PadButtons::C_UP, cy > GC_CSTICK_THRESHOLD);
+        buttons.set(PadButtons::C_DOWN, cy < -GC_CSTICK_THRESHOLD);
+
+        Some(PadState {
+            buttons,
+            x: Self::stick(port[3]),
+            y: Self::stick(port[4]),
+        })
+    }
+}
+
+impl ControllerAdaptor for GcAdapter {
+    fn name(&self) -> &str {
+        "GameCube adapter"
+    }
+
+    fn poll(&mut self) -> Result<Vec<Option<PadState>>, Error> {
+        let mut buf = [0u8; 37];
+        // A timeout means that no new report arrived within the frame: the
+        // ports keep the state of the last report.
+        let n = match self.handle.read_interrupt(GC_EP_IN, &mut buf, GC_TIMEOUT) {
+            Ok(n) => n,
+            Err(rusb::Error::Timeout) => return Ok(self.last.clone()),
+            Err(err) => return Err(err.into()),
+        };
+        if n != buf.len() || buf[0] != GC_REPORT_STATE {
+            return Err(format_err!("unexpected report from GameCube adapter"));
+        }
+        self.last = buf[1..].chunks_exact(9).map(GcAdapter::decode).collect();
+        Ok(self.last.clone())
+    }
+}
+
+/// AdaptorInput polls the connected adaptors, and converts the state of their
+/// controllers into events for the emulated joysticks, which are assigned to
+/// the adaptor ports in order.
+pub(crate) struct AdaptorInput {
+    logger: slog::Logger,
+    adaptors: Vec<Box<dyn ControllerAdaptor>>,
+    joysticks: Vec<String>,
+    last: Vec<PadState>,
+}
+
+impl AdaptorInput {
+    pub(crate) fn new(im: &InputManager, logger: slog::Logger) -> AdaptorInput {
+        let mut joysticks = Vec::new();
+        im.visit(|dev| {
+            if dev.kind() == InputDeviceKind::Joystick {
+                joysticks.push(dev.name().to_owned());
+            }
+        });
+        let adaptors = open_adaptors(&logger);
+        for a in adaptors.iter() {
+            info!(logger, "controller adaptor: {}", a.name());
+        }
+        AdaptorInput {
+            logger,
+            last: vec![PadState::default(); joysticks.len()],
+            adaptors,
+            joysticks,
+        }
+    }
+
+    /// Poll the adaptors (once per frame). Adaptors that fail are closed.
+    pub(crate) fn poll(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let mut joy = 0;
+        let (logger, joysticks, last) = (&self.logger, &self.joysticks, &mut self.last);
+        let mut failed = Vec::new();
+        for (idx, a) in self.adaptors.iter_mut().enumerate() {
+            let ports = match a.poll() {
+                Ok(ports) => ports,
+                Err(err) => {
+                    warn!(logger, "{} disconnected: {}", a.name(), err);
+                    failed.push(idx);
+                    continue;
+                }
+            };
+            for state in ports {
+                if joy >= joysticks.len() {
+                    break;
+                }
+                // A disconnected controller releases all the inputs.
+                let state = state.unwrap_or_default();
+                events.extend(state.diff(&last[joy], &joysticks[joy]));
+                last[joy] = state;
+                joy += 1;
+            }
+        }
+        // Joysticks without an adaptor port (eg: after a disconnection).
+        for (joy, dev) in joysticks.iter().enumerate().skip(joy) {
+            events.extend(PadState::default().diff(&last[joy], dev));
+            last[joy] = PadState::default();
+        }
+        for idx in failed.into_iter().rev() {
+            self.adaptors.remove(idx);
+        }
+        events
+    }
+}
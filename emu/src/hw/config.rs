@@ -0,0 +1,176 @@
+use super::hotkeys::HotkeyConfig;
+use super::input_mapping::InputConfig;
+use failure::{format_err, Error};
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Configuration file (relative to the current directory, like imgui's
+/// debug.ini).
+const CONFIG_FILE: &str = "config.json";
+
+/// Backup of a configuration file that could not be loaded.
+const CONFIG_BACKUP: &str = "config.json.bak";
+
+/// UserConfig holds the user preferences of the frontend. It is loaded at
+/// startup and saved when the frontend exits. Missing fields are filled
+/// with defaults, so that older configuration files keep working.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct UserConfig {
+    #[serde(default)]
+    pub hotkeys: HotkeyConfig,
+    #[serde(default)]
+    pub window: WindowConfig,
+    #[serde(default)]
+    pub font: FontConfig,
+    #[serde(default)]
+    pub theme: ThemeConfig,
+    // Input bindings (including turbo and macros); None until the
+    // emulator runs for the first time.
+    #[serde(default)]
+    pub(crate) input: Option<InputConfig>,
+    // Input profiles associated with specific games (by game code), which
+    // are used in place of the global one when the game is loaded.
+    #[serde(default)]
+    pub(crate) game_input: BTreeMap<String, InputConfig>,
+}
+
+/// How the fullscreen hotkey switches the window to fullscreen.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum FullscreenMode {
+    #[default]
+    Borderless, // Fullscreen window at desktop resolution
+    Exclusive, // Real fullscreen mode (changes video mode)
+}
+
+/// Position and size of a window.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct WindowConfig {
+    pub fullscreen_mode: FullscreenMode,
+
+    // Geometry of the main window, remembered separately for each monitor
+    // (identified by its name), and the monitor where the window was last
+    // seen. This allows to restore the window on the correct monitor, and
+    // to move it back and forth between monitors of different size.
+    pub geometry: HashMap<String, WindowGeometry>,
+    pub last_display: Option<String>,
+}
+
+/// Glyph ranges to load from the debugger font, on top of basic latin.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum FontGlyphs {
+    #[default]
+    Default,
+    Japanese,
+    ChineseFull,
+    ChineseSimplified,
+    Korean,
+    Cyrillic,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FontConfig {
+    // Path to a TTF font file; if missing, imgui's embedded font is used,
+    // which only covers basic latin (so glyphs is ignored).
+    pub path: Option<String>,
+    // Font size in pixels at 96 DPI.
+    pub size: f32,
+    pub glyphs: FontGlyphs,
+    // Override the scale factor computed from the display DPI.
+    pub scale: Option<f32>,
+}
+
+impl Default for FontConfig {
+    fn default() -> Self {
+        FontConfig {
+            path: None,
+            size: 13.0,
+            glyphs: FontGlyphs::Default,
+            scale: None,
+        }
+    }
+}
+
+/// Base color theme of the debugger UI (the builtin imgui styles).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+    Classic,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Dark, Theme::Light, Theme::Classic];
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub theme: Theme,
+    // Colors overriding the theme, indexed by imgui color name (eg:
+    // "WindowBg", "Text"), as RGBA components in the range 0-1.
+    pub colors: BTreeMap<String, [f32; 4]>,
+    // Scale of the UI font, on top of the scale computed from the display
+    // DPI. Unlike the font size, it can be changed at runtime.
+    pub font_scale: f32,
+    // Scale of the whole UI (fonts, spacing, widgets), on top of the scale
+    // computed from the display DPI.
+    pub ui_scale: f32,
+    // Navigation of the debugger windows with the keyboard (Ctrl+Tab to
+    // cycle the focus, arrows to move between widgets).
+    pub keyboard_nav: bool,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        ThemeConfig {
+            theme: Theme::default(),
+            colors: BTreeMap::new(),
+            font_scale: 1.0,
+            ui_scale: 1.0,
+            keyboard_nav: true,
+        }
+    }
+}
+
+impl UserConfig {
+    /// Load the configuration file. If it doesn't exist yet, the default
+    /// configuration is returned. If it cannot be loaded, it is moved to
+    /// config.json.bak, so that the settings of the user are not lost when
+    /// the configuration is saved.
+    pub fn load() -> Result<UserConfig, Error> {
+        if !UserConfig::exists() {
+            return Ok(UserConfig::default());
+        }
+        let res = fs::read_to_string(CONFIG_FILE)
+            .map_err(Error::from)
+            .and_then(|data| Ok(serde_json::from_str(&data)?));
+        if let Err(err) = res {
+            fs::rename(CONFIG_FILE, CONFIG_BACKUP)?;
+            return Err(format_err!("{} (moved to {})", err, CONFIG_BACKUP));
+        }
+        res
+    }
+
+    /// Return true if the configuration file exists.
+    pub fn exists() -> bool {
+        Path::new(CONFIG_FILE).exists()
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        fs::write(CONFIG_FILE, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
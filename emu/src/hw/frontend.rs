@@ -0,0 +1,1068 @@
+#[cfg(feature = "adaptors")]
+use super::adaptors::AdaptorInput;
+use super::config::{FullscreenMode, UserConfig, WindowConfig, WindowGeometry};
+use super::glutils::SurfaceRenderer;
+use super::hotkeys::HotkeyAction;
+use super::input_mapping::{HostPads, InputConfig, InputMapping, InputProfile, ProfileAction};
+use super::{OutputProducer, Rewind, RunAhead};
+
+use crate::dbg::{DebuggerModel, DebuggerUI, PausePoint, UiHost};
+use crate::gfx::{BufferLineGetter, GfxBufferLE, OwnedGfxBufferLE, Rgb888};
+use crate::input::{InputEvent, InputManager};
+use crate::snd::{OwnedSndBuffer, SampleFormat, SampleInt, SndBuffer};
+use crate::state::{CurrentState, State};
+
+use byteorder::NativeEndian;
+use sdl2::audio::{AudioFormatNum, AudioQueue, AudioSpecDesired};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::video::{FullscreenType, GLContext, GLProfile, Window};
+use sdl2::{AudioSubsystem, VideoSubsystem};
+use slog::{info, warn};
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How far back the rewind goes, in seconds of emulation.
+const REWIND_SECONDS: usize = 30;
+
+pub struct VideoConfig {
+    pub window_title: String,
+    pub width: isize,
+    pub height: isize,
+    pub fps: isize,
+}
+
+pub struct AudioConfig {
+    pub frequency: isize,
+}
+
+struct Video {
+    video: VideoSubsystem,
+    window: Window,
+    renderer: SurfaceRenderer,
+    gl_context: GLContext,
+    game_window: Option<GameWindow>,
+
+    cfg: Arc<VideoConfig>,
+    fps_clock: Instant,
+    fps_counter: isize,
+}
+
+impl Video {
+    fn new(
+        cfg: Arc<VideoConfig>,
+        context: &sdl2::Sdl,
+        wcfg: &WindowConfig,
+    ) -> Result<Video, String> {
+        let video = context
+            .video()
+            .map_err(|e| format!("error creating video subsystem: {:?}", e))?;
+
+        // Request OpenGL Core profile (for GL 3.2 extensions, required by imgui-opengl-renderer).
+        {
+            let gl_attr = video.gl_attr();
+            gl_attr.set_context_profile(GLProfile::Core);
+            gl_attr.set_context_version(3, 0);
+        }
+
+        let mut window = video
+            .window(&cfg.window_title, 640 * 2, 480 * 2)
+            .resizable()
+            .position_centered()
+            .opengl()
+            .allow_highdpi()
+            .build()
+            .map_err(|e| format!("error creating window: {:?}", e))?;
+
+        // Restore the window geometry on the monitor where it was last seen,
+        // if that monitor is still connected.
+        if let Some(ref display) = wcfg.last_display {
+            let connected = (0..video.num_video_displays().unwrap_or(0))
+                .any(|idx| video.display_name(idx).ok().as_ref() == Some(display));
+            if let (true, Some(geo)) = (connected, wcfg.geometry.get(display)) {
+                window.set_position(
+                    sdl2::video::WindowPos::Positioned(geo.x),
+                    sdl2::video::WindowPos::Positioned(geo.y),
+                );
+                let _ = window.set_size(geo.width, geo.height);
+            }
+        }
+
+        let gl_context = window
+            .gl_create_context()
+            .expect("couldn't create GL context");
+
+        let video2 = video.clone();
+        let renderer = SurfaceRenderer::new(move |s| video2.gl_get_proc_address(s) as _);
+
+        Ok(Video {
+            cfg,
+            video,
+            window,
+            renderer,
+            gl_context,
+            game_window: None,
+            fps_clock: Instant::now(),
+            fps_counter: 0,
+        })
+    }
+
+    fn render_frame(&mut self, frame: &GfxBufferLE<Rgb888>) {
+        self.renderer.render(frame);
+    }
+
+    // Remember the current window geometry for the monitor the window is on.
+    fn save_geometry(&self, wcfg: &mut WindowConfig) {
+        if self.window.fullscreen_state() != FullscreenType::Off {
+            return;
+        }
+        let display = match self.window.display_index() {
+            Ok(idx) => match self.video.display_name(idx) {
+                Ok(name) => name,
+                Err(_) => return,
+            },
+            Err(_) => return,
+        };
+        let (x, y) = self.window.position();
+        let (width, height) = self.window.size();
+        wcfg.geometry.insert(
+            display.clone(),
+            WindowGeometry {
+                x,
+                y,
+                width,
+                height,
+            },
+        );
+        wcfg.last_display = Some(display);
+    }
+
+    fn update_fps(&mut self) {
+        self.fps_counter += 1;
+        if self.fps_clock.elapsed() >= Duration::new(1, 0) {
+            self.window
+                .set_title(&format!(
+                    "{} - {} FPS",
+                    &self.cfg.window_title, self.fps_counter
+                ))
+                .unwrap();
+            self.fps_counter = 0;
+            self.fps_clock += Duration::new(1, 0);
+        }
+    }
+}
+
+// Switch a window in or out of fullscreen, using the specified mode.
+fn toggle_fullscreen(window: &mut Window, mode: FullscreenMode) -> Result<(), String> {
+    let ft = match (window.fullscreen_state(), mode) {
+        (FullscreenType::Off, FullscreenMode::Borderless) => FullscreenType::Desktop,
+        (FullscreenType::Off, FullscreenMode::Exclusive) => FullscreenType::True,
+        _ => FullscreenType::Off,
+    };
+    window.set_fullscreen(ft)
+}
+
+/// Configuration of the separate game window, used to display the emulated
+/// screen in its own window while the debugger is active.
+pub struct GameWindowConfig {
+    pub scale: u32,  // initial window size, as a multiple of the screen size
+    pub vsync: bool, // synchronize the game window with the monitor refresh
+}
+
+struct GameWindow {
+    window: Window,
+    renderer: SurfaceRenderer,
+    gl_context: GLContext,
+}
+
+impl GameWindow {
+    fn new(
+        video: &VideoSubsystem,
+        title: &str,
+        size: (usize, usize),
+        cfg: &GameWindowConfig,
+    ) -> Result<GameWindow, String> {
+        let window = video
+            .window(
+                &format!("{} - Game", title),
+                size.0 as u32 * cfg.scale,
+                size.1 as u32 * cfg.scale,
+            )
+            .resizable()
+            .opengl()
+            .allow_highdpi()
+            .build()
+            .map_err(|e| format!("error creating game window: {:?}", e))?;
+
+        // Creating the context also makes it current.
+        let gl_context = window.gl_create_context()?;
+        let _ = video.gl_set_swap_interval(cfg.vsync as i32);
+
+        let video2 = video.clone();
+        let renderer = SurfaceRenderer::new(move |s| video2.gl_get_proc_address(s) as _);
+
+        Ok(GameWindow {
+            window,
+            renderer,
+            gl_context,
+        })
+    }
+
+    // Render a frame into the game window, keeping the aspect ratio of the
+    // emulated screen (with black bars if required). Afterwards, the
+    // specified context (the main window one) is made current again.
+    fn render_frame(&mut self, frame: &GfxBufferLE<Rgb888>, main: (&Window, &GLContext)) {
+        if self.window.gl_make_current(&self.gl_context).is_err() {
+            return;
+        }
+
+        let (ww, wh) = self.window.drawable_size();
+        let scale = (ww as f32 / frame.width() as f32).min(wh as f32 / frame.height() as f32);
+        let (vw, vh) = (
+            (frame.width() as f32 * scale) as i32,
+            (frame.height() as f32 * scale) as i32,
+        );
+        unsafe {
+            gl::Viewport(0, 0, ww as i32, wh as i32);
+            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            gl::Viewport((ww as i32 - vw) / 2, (wh as i32 - vh) / 2, vw, vh);
+        }
+        self.renderer.render(frame);
+        self.window.gl_swap_window();
+
+        let _ = main.0.gl_make_current(main.1);
+    }
+}
+
+struct Audio<SI: SampleInt + AudioFormatNum, SF: SampleFormat<ORDER = NativeEndian, SAMPLE = SI>> {
+    #[allow(dead_code)] // keeps the subsystem alive
+    audio: AudioSubsystem,
+    queue: AudioQueue<SI>,
+    frame_size: usize,
+    phantom: PhantomData<SF>,
+}
+
+impl<SI, SF> Audio<SI, SF>
+where
+    SI: SampleInt + AudioFormatNum,
+    SF: SampleFormat<ORDER = NativeEndian, SAMPLE = SI>,
+{
+    fn new(context: &sdl2::Sdl, fps: isize, acfg: Arc<AudioConfig>) -> Self {
+        let audio = context
+            .audio()
+            .map_err(|e| format!("error creating audio subsystem: {:?}", e))
+            .unwrap();
+
+        if acfg.frequency % fps != 0 {
+            // We need to generate the exact number of samples per frame, so for
+            // now only allows exact multiples. This is not impossible to make it
+            // work more generally (we should request a possible different amount
+            // of samples each frame), but let's punt for now.
+            panic!("audio frequency not a perfect multiple of framerate");
+        }
+
+        let nsamples_per_frame = (acfg.frequency / fps) as usize;
+        let spec = AudioSpecDesired {
+            freq: Some(acfg.frequency as i32),
+            channels: Some(SF::CHANNELS as u8),
+            samples: Some(nsamples_per_frame as u16),
+        };
+        let queue = audio.open_queue(None, &spec).unwrap();
+        queue.resume();
+
+        Self {
+            audio,
+            queue,
+            frame_size: nsamples_per_frame * SF::frame_size(),
+            phantom: PhantomData,
+        }
+    }
+
+    fn samples_per_frame(&self) -> usize {
+        self.frame_size / SF::frame_size()
+    }
+
+    fn render_frame(&mut self, buf: &SndBuffer<SF>, throttle: bool) {
+        if throttle {
+            // Wait until the queue is less than one frame small. This
+            // crates one buffer worth of lag, but should keep the audio
+            // playing with no cracks.
+            while self.queue.size() > self.frame_size as u32 * 2 {
+                std::thread::sleep(Duration::from_micros(100));
+            }
+            self.queue.queue(buf.as_ref());
+        } else {
+            // If we're not throttling there are two possibilities:
+            // we're either running too slow (in which case, there would be
+            // audio cracks), or too fast; in the latter case, we want to skip
+            // some audio frames to avoid desyncing audio and video.
+            if self.queue.size() < self.frame_size as u32 {
+                self.queue.queue(buf.as_ref());
+            }
+        }
+    }
+}
+
+// Save a screenshot as a binary PPM file, returning the filename.
+fn save_screenshot(screen: &GfxBufferLE<Rgb888>) -> io::Result<String> {
+    let mut idx = 0;
+    let fname = loop {
+        let fname = format!("screenshot-{:04}.ppm", idx);
+        if !std::path::Path::new(&fname).exists() {
+            break fname;
+        }
+        idx += 1;
+    };
+
+    let mut f = io::BufWriter::new(File::create(&fname)?);
+    write!(f, "P6\n{} {}\n255\n", screen.width(), screen.height())?;
+    for y in 0..screen.height() {
+        let line = screen.line(y);
+        for x in 0..screen.width() {
+            let (r, g, b, _) = line.get(x).components();
+            f.write_all(&[r as u8, g as u8, b as u8])?;
+        }
+    }
+    Ok(fname)
+}
+
+pub struct Output {
+    logger: slog::Logger,
+    vcfg: Arc<VideoConfig>,
+    acfg: Arc<AudioConfig>,
+    context: sdl2::Sdl,
+    video: Option<Video>,
+    audio: bool,
+    debug: bool,
+    quit: bool,
+    framecount: i64,
+    pause_points: Vec<PausePoint>,
+    break_at: Option<(String, u64)>, // one-shot breakpoint (cpu, pc)
+    chrome_trace: Option<PathBuf>,
+    symbols: Vec<PathBuf>, // symbol files loaded into the debugger
+    gdb: Option<String>,   // address of the GDB stub
+    run_ahead: usize,      // frames emulated ahead (0: disabled)
+    rewind: usize,         // frames between rewind snapshots (0: disabled)
+    config: UserConfig,
+    save_config: bool, // false if the configuration file must not be overwritten
+    profile: InputProfile, // input profile in use
+
+    // Hotkey state
+    paused: bool,
+    frame_advance: bool,
+    fast_forward: bool,
+    screenshot: bool,
+    record_macro: bool,
+    save_slot: Option<State>,
+    rewind_step: bool,
+    local: bool, // emulator runs on this thread (required by debugger and savestates)
+    flash_msg: Option<String>, // message to show in the debugger
+}
+
+impl Output {
+    pub fn new(
+        logger: slog::Logger,
+        vcfg: VideoConfig,
+        acfg: AudioConfig,
+    ) -> Result<Output, String> {
+        // If the configuration file cannot be loaded, and it could not be
+        // moved aside either, it is not overwritten on exit.
+        let (config, save_config) = match UserConfig::load() {
+            Ok(config) => (config, true),
+            Err(err) => {
+                warn!(logger, "cannot load configuration, using defaults: {}", err);
+                (UserConfig::default(), !UserConfig::exists())
+            }
+        };
+        Ok(Output {
+            logger,
+            vcfg: Arc::new(vcfg),
+            acfg: Arc::new(acfg),
+            context: sdl2::init()?,
+            video: None,
+            audio: false,
+            debug: true,
+            quit: false,
+            framecount: 0,
+            pause_points: Vec::new(),
+            break_at: None,
+            chrome_trace: None,
+            symbols: Vec::new(),
+            gdb: None,
+            run_ahead: 0,
+            rewind: 0,
+            config,
+            save_config,
+            profile: InputProfile::default(),
+            paused: false,
+            frame_advance: false,
+            fast_forward: false,
+            screenshot: false,
+            record_macro: false,
+            save_slot: None,
+            rewind_step: false,
+            local: false,
+            flash_msg: None,
+        })
+    }
+
+    pub fn enable_video(&mut self) -> Result<(), String> {
+        self.video = Some(Video::new(
+            self.vcfg.clone(),
+            &self.context,
+            &self.config.window,
+        )?);
+        Ok(())
+    }
+
+    /// Open a separate window that displays the emulated screen while the
+    /// debugger is active, so that the game and the debugger can be placed
+    /// on different monitors. Must be called after enable_video().
+    pub fn enable_game_window(&mut self, cfg: GameWindowConfig) -> Result<(), String> {
+        let size = (self.vcfg.width as usize, self.vcfg.height as usize);
+        let v = self
+            .video
+            .as_mut()
+            .ok_or_else(|| "video not enabled".to_owned())?;
+        v.game_window = Some(GameWindow::new(&v.video, &v.cfg.window_title, size, &cfg)?);
+        v.window.gl_make_current(&v.gl_context)?;
+        Ok(())
+    }
+
+    pub fn enable_audio(&mut self) -> Result<(), String> {
+        self.audio = true;
+        Ok(())
+    }
+
+    /// Request the debugger to pause emulation at the specified point.
+    /// If any pause point is configured, the emulation starts running
+    /// immediately when entering the debugger, instead of being paused.
+    pub fn add_pause_point(&mut self, pp: PausePoint) {
+        self.pause_points.push(pp);
+    }
+
+    /// Request the debugger to stop the first time the specified CPU executes
+    /// the instruction at addr (see DebuggerModel::cpu_breakpoint_addr). As
+    /// with pause points, the emulation starts running immediately.
+    pub fn set_break_at(&mut self, cpu_name: &str, addr: u64) {
+        self.break_at = Some((cpu_name.to_owned(), addr));
+    }
+
+    /// Request the debugger to capture a Chrome trace from the start of the
+    /// emulation, and to save it into the specified file on exit. As with
+    /// pause points, the emulation starts running immediately.
+    pub fn capture_chrome_trace(&mut self, path: PathBuf) {
+        self.chrome_trace = Some(path);
+    }
+
+    /// Request the debugger to load the specified symbol file (see
+    /// [`Symbols`](../dbg/struct.Symbols.html)).
+    pub fn load_symbols(&mut self, path: PathBuf) {
+        self.symbols.push(path);
+    }
+
+    /// Request the debugger to listen for a remote debugger (GDB remote
+    /// protocol) on the specified address (eg: "127.0.0.1:9123").
+    pub fn enable_gdb_stub(&mut self, addr: &str) {
+        self.gdb = Some(addr.to_owned());
+    }
+
+    /// Emulate the specified number of frames ahead, to reduce the perceived
+    /// input lag (see [`RunAhead`](struct.RunAhead.html)). It is only used
+    /// by run_threaded, and only if the emulation is deterministic and
+    /// savestates are fast enough; otherwise, a warning is printed.
+    pub fn set_run_ahead(&mut self, frames: usize) {
+        self.run_ahead = frames;
+    }
+
+    /// Take a rewind snapshot every interval frames, so that the emulation
+    /// can be stepped backwards with the rewind hotkey (see
+    /// [`Rewind`](struct.Rewind.html)). Like savestates, it is only available
+    /// in run_and_debug.
+    pub fn set_rewind(&mut self, interval: usize) {
+        self.rewind = interval;
+    }
+
+    // Input configuration for the specified InputManager: the saved one, or
+    // the default one if it was saved for a different emulator.
+    fn input_config(&self, im: &InputManager) -> InputConfig {
+        match self.config.input.as_ref() {
+            Some(cfg) if cfg.matches(im) => cfg.clone(),
+            _ => InputConfig::default(im),
+        }
+    }
+
+    // Select the input profile for the running game: its own profile if it
+    // has one, or the global one.
+    fn select_input_profile(&mut self, im: &InputManager, game: Option<String>) -> InputConfig {
+        let game_cfg = game
+            .as_ref()
+            .and_then(|g| self.config.game_input.get(g))
+            .filter(|cfg| cfg.matches(im))
+            .cloned();
+        self.profile = InputProfile {
+            game,
+            per_game: game_cfg.is_some(),
+            action: None,
+        };
+        let mut cfg = game_cfg.unwrap_or_else(|| self.input_config(im));
+        cfg.add_missing_devices(im);
+        cfg
+    }
+
+    // Save the bindings back into the profile they were loaded from.
+    fn save_input_profile(&mut self, cfg: &InputConfig) {
+        match self.profile.game.as_ref() {
+            Some(game) if self.profile.per_game => {
+                self.config.game_input.insert(game.clone(), cfg.clone());
+            }
+            _ => self.config.input = Some(cfg.clone()),
+        }
+    }
+
+    // Apply the profile action requested from the debugger UI (if any).
+    fn apply_profile_action(&mut self, map: &mut InputMapping, im: &InputManager) {
+        let game = match self.profile.game.clone() {
+            Some(game) => game,
+            None => return,
+        };
+        match self.profile.action.take() {
+            Some(ProfileAction::CopyToGame) => {
+                self.config.game_input.insert(game, map.config().clone());
+                self.profile.per_game = true;
+            }
+            Some(ProfileAction::RemoveGame) => {
+                self.config.game_input.remove(&game);
+                self.profile.per_game = false;
+                let cfg = self.input_config(im);
+                *map = InputMapping::new(cfg, im, self.vcfg.fps, self.logger.clone());
+            }
+            None => {}
+        }
+    }
+
+    // Return true if the event is the press or release of a hotkey; these
+    // are not passed to the input mapping.
+    fn is_hotkey(&self, event: &Event) -> bool {
+        match event {
+            Event::KeyDown {
+                scancode: Some(scode),
+                ..
+            }
+            | Event::KeyUp {
+                scancode: Some(scode),
+                ..
+            } => self.config.hotkeys.action(*scode).is_some(),
+            _ => false,
+        }
+    }
+
+    fn process_event(&mut self, event: &Event) {
+        match event {
+            Event::KeyDown {
+                scancode: Some(scode),
+                repeat: false,
+                ..
+            } => {
+                if let Some(action) = self.config.hotkeys.action(*scode) {
+                    self.process_hotkey(action)
+                }
+            }
+            Event::KeyUp {
+                scancode: Some(scode),
+                ..
+            } if self.config.hotkeys.action(*scode) == Some(HotkeyAction::FastForward) => {
+                self.fast_forward = false;
+            }
+            Event::Window {
+                win_event: WindowEvent::Moved(..),
+                ..
+            }
+            | Event::Window {
+                win_event: WindowEvent::SizeChanged(..),
+                ..
+            } => {
+                if let Some(v) = self.video.as_ref() {
+                    v.save_geometry(&mut self.config.window);
+                }
+            }
+            Event::Quit { .. } => {
+                self.quit = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn process_hotkey(&mut self, action: HotkeyAction) {
+        use self::HotkeyAction::*;
+        match action {
+            ToggleDebugger if self.local => self.debug = !self.debug,
+            ToggleDebugger => {}
+            FastForward => self.fast_forward = true,
+            Screenshot => self.screenshot = true,
+            RecordMacro => self.record_macro = true,
+            Fullscreen => {
+                // While debugging, the game window (if any) goes fullscreen
+                // so that the debugger stays usable on another monitor.
+                let mode = self.config.window.fullscreen_mode;
+                let res = match self.video.as_mut() {
+                    Some(v) => match v.game_window.as_mut() {
+                        Some(gw) if self.debug => toggle_fullscreen(&mut gw.window, mode),
+                        _ => toggle_fullscreen(&mut v.window, mode),
+                    },
+                    None => Ok(()),
+                };
+                if let Err(err) = res {
+                    self.notify(format!("Cannot change fullscreen mode: {}", err));
+                }
+            }
+            SaveState | LoadState if !self.local => {
+                self.notify("Savestates are not supported in this mode".into())
+            }
+            SaveState => self.save_slot = Some(CurrentState().clone()),
+            LoadState => match self.save_slot {
+                Some(ref state) => {
+                    state.clone().make_current();
+                }
+                None => self.notify("No state saved yet".into()),
+            },
+            Rewind if !self.local => self.notify("Rewind is not supported in this mode".into()),
+            Rewind => self.rewind_step = true,
+
+            // While the debugger is active, these are handled by the debugger itself.
+            Pause if !self.debug => self.paused = !self.paused,
+            FrameAdvance if !self.debug => {
+                self.paused = true;
+                self.frame_advance = true;
+            }
+            Pause | FrameAdvance | DebuggerHelp => {}
+        }
+    }
+
+    fn take_screenshot(&mut self, screen: &GfxBufferLE<Rgb888>) {
+        if self.screenshot {
+            self.screenshot = false;
+            match save_screenshot(screen) {
+                Ok(fname) => self.notify(format!("Screenshot saved: {}", fname)),
+                Err(err) => self.notify(format!("Cannot save screenshot: {}", err)),
+            }
+        }
+    }
+
+    // Report a message to the user: it is logged, and also shown by the
+    // debugger (if active).
+    fn notify(&mut self, msg: String) {
+        info!(self.logger, "{}", msg);
+        if self.debug {
+            self.flash_msg = Some(msg);
+        }
+    }
+
+    pub fn run_and_debug<SI, SF, P>(&mut self, producer: &mut P)
+    where
+        SI: SampleInt + AudioFormatNum,
+        SF: SampleFormat<SAMPLE = SI, ORDER = NativeEndian>,
+        P: OutputProducer<AudioSampleFormat = SF> + DebuggerModel,
+    {
+        let width = self.vcfg.width as usize;
+        let height = self.vcfg.height as usize;
+        assert!(self.video.is_some()); // TODO: debugger could work without video as well
+        let v = self.video.as_ref().unwrap();
+        let mut host = UiHost::new(
+            v.video.clone(),
+            &v.window,
+            &self.config.font,
+            &self.config.theme,
+        );
+        let mut dbg_ui = DebuggerUI::new(&mut host, producer, self.logger.clone());
+        self.local = true;
+        if !self.pause_points.is_empty() {
+            for pp in self.pause_points.drain(..) {
+                dbg_ui.dbg.add_pause_point(pp);
+            }
+            dbg_ui.set_paused(false);
+        }
+        if let Some((cpu_name, addr)) = self.break_at.take() {
+            let pc = producer.cpu_breakpoint_addr(&cpu_name, addr);
+            dbg_ui.dbg.set_breakpoint_oneshot(&cpu_name, Some(pc));
+            dbg_ui.set_paused(false);
+        }
+        if let Some(path) = self.chrome_trace.take() {
+            dbg_ui.start_chrome_trace(Some(path));
+            dbg_ui.set_paused(false);
+        }
+        for path in std::mem::take(&mut self.symbols) {
+            if let Err(err) = dbg_ui.dbg.load_symbols(&path) {
+                self.notify(format!("Cannot load symbols {}: {}", path.display(), err));
+            }
+        }
+        if let Some(addr) = self.gdb.take() {
+            match dbg_ui.start_gdb_stub(&addr) {
+                Ok(()) => self.notify(format!("GDB stub listening on {}", addr)),
+                Err(err) => self.notify(format!("Cannot start GDB stub on {}: {}", addr, err)),
+            }
+        }
+
+        let mut audio = Audio::<SI, SF>::new(&self.context, self.vcfg.fps, self.acfg.clone());
+        let mut audio_buf = OwnedSndBuffer::with_capacity(audio.samples_per_frame());
+
+        let mut event_pump = self.context.event_pump().unwrap();
+        let mut screen = OwnedGfxBufferLE::<Rgb888>::new(width, height);
+
+        let game = producer.game_id();
+        let mut input = match producer.input_manager() {
+            Some(im) => {
+                let cfg = self.select_input_profile(im, game);
+                let logger = self.logger.clone();
+                Some(InputMapping::new(cfg, im, self.vcfg.fps, logger))
+            }
+            None => None,
+        };
+        #[cfg(feature = "adaptors")]
+        let mut adaptors = producer
+            .input_manager()
+            .map(|im| AdaptorInput::new(im, self.logger.clone()));
+        let mut pads = HostPads::new(&self.context, self.logger.clone());
+        let mut rewind = match self.rewind {
+            0 => None,
+            interval => {
+                let snapshots = REWIND_SECONDS * self.vcfg.fps.max(1) as usize / interval;
+                Some(Rewind::new(interval, snapshots.max(1)))
+            }
+        };
+
+        while !self.quit {
+            for event in event_pump.poll_iter() {
+                pads.handle_event(&event);
+                host.handle_event(&event);
+                // Don't trigger hotkeys while typing into the debugger
+                if !(self.debug && dbg_ui.wants_text_input()) {
+                    self.process_event(&event);
+                }
+
+                if let (Some(map), false) = (input.as_mut(), self.is_hotkey(&event)) {
+                    if let Some(im) = producer.input_manager() {
+                        if let Some(evt) = map.map_event(&event) {
+                            im.process_event(evt);
+                        };
+                    }
+                }
+            }
+            #[cfg(feature = "adaptors")]
+            {
+                if let (Some(a), Some(im)) = (adaptors.as_mut(), producer.input_manager()) {
+                    for evt in a.poll() {
+                        im.process_event(evt);
+                    }
+                }
+            }
+            if let (Some(map), Some(im)) = (input.as_mut(), producer.input_manager()) {
+                if std::mem::replace(&mut self.record_macro, false) {
+                    map.toggle_recording();
+                }
+                if self.debug || !self.paused || self.frame_advance {
+                    for evt in map.tick() {
+                        im.process_event(evt);
+                    }
+                }
+            }
+
+            self.take_screenshot(&screen.buf());
+
+            // Rewind: the screen is updated by the next emulated frame.
+            let menu_rewind = dbg_ui.take_rewind_request();
+            if std::mem::replace(&mut self.rewind_step, false) || menu_rewind {
+                match rewind.as_mut().map(|rw| rw.step_back()) {
+                    Some(true) => {}
+                    Some(false) => self.notify("No rewind snapshot available".into()),
+                    None => self.notify("Rewind is not enabled".into()),
+                }
+            }
+            if let Some(msg) = self.flash_msg.take() {
+                dbg_ui.add_flash_msg(&msg);
+            }
+
+            let v = self.video.as_mut().unwrap();
+            if !self.debug {
+                if !self.paused || self.frame_advance {
+                    self.frame_advance = false;
+                    producer.render_frame(&mut screen.buf_mut(), &mut audio_buf.buf_mut());
+                    audio.render_frame(&audio_buf.buf(), !self.fast_forward);
+                    v.update_fps();
+                    if let Some(rw) = rewind.as_mut() {
+                        rw.end_frame();
+                    }
+                } else {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                v.render_frame(&screen.buf());
+            } else {
+                if dbg_ui.trace(producer, &mut screen.buf_mut(), &mut audio_buf.buf_mut()) {
+                    v.update_fps();
+                    if let Some(rw) = rewind.as_mut() {
+                        rw.end_frame();
+                    }
+                }
+                if let Some(gw) = v.game_window.as_mut() {
+                    gw.render_frame(&screen.buf(), (&v.window, &v.gl_context));
+                }
+                dbg_ui.render(
+                    &mut host,
+                    &v.window,
+                    &event_pump,
+                    producer,
+                    &mut self.config.hotkeys,
+                    &mut self.config.theme,
+                    &mut self.profile,
+                    input.as_mut(),
+                );
+            }
+
+            v.window.gl_swap_window();
+
+            if self.debug {
+                if let (Some(map), Some(im)) = (input.as_mut(), producer.input_manager()) {
+                    self.apply_profile_action(map, im);
+                }
+            }
+
+            self.framecount += 1;
+        }
+
+        if let Some(map) = input {
+            self.save_input_profile(map.config());
+        }
+    }
+
+    /// Run a blocking loop in which output is produced by a OutputProducer,
+    /// until the producer exits by itself, or the user closes the window.
+    /// The OutputProducer is run in a background thread, so to parallelize
+    /// display visualization and vsync with actual output generation.
+    ///
+    /// create is a FnOnce callback that creates a OutputProducer, and is invoked
+    /// in the background thread so that OutputProducer needs not to implement
+    /// Send. If it fails, the error is returned.
+    pub fn run_threaded<F, P, SI, SF>(&mut self, create: F) -> Result<(), String>
+    where
+        SI: SampleInt + AudioFormatNum,
+        SF: SampleFormat<SAMPLE = SI, ORDER = NativeEndian>,
+        P: OutputProducer<AudioSampleFormat = SF>,
+        F: FnOnce() -> Result<Box<P>, String> + Send + 'static,
+    {
+        let width = self.vcfg.width as usize;
+        let height = self.vcfg.height as usize;
+        let (tx_frame, rx_frame) = mpsc::sync_channel(3);
+        let (tx_event, rx_event) = mpsc::sync_channel::<Vec<InputEvent>>(3);
+        let (tx_input, rx_input) = mpsc::sync_channel(1);
+
+        self.debug = false;
+        let mut audio = Audio::new(&self.context, self.vcfg.fps, self.acfg.clone());
+        let audio_frame_size = audio.samples_per_frame();
+
+        let mut event_pump = self.context.event_pump().unwrap();
+        let (run_ahead, fps) = (self.run_ahead, self.vcfg.fps);
+
+        thread::spawn(move || {
+            let mut producer = match create() {
+                Ok(producer) => producer,
+                Err(err) => {
+                    tx_input.send(Err(err)).ok();
+                    return;
+                }
+            };
+
+            // Savestates are thread-local, so run-ahead is set up (and
+            // checked) in the emulation thread.
+            let mut run_ahead_err = None;
+            let mut run_ahead = match run_ahead {
+                0 => None,
+                _ if !producer.deterministic() => {
+                    run_ahead_err = Some("the emulation is not deterministic".to_owned());
+                    None
+                }
+                frames => {
+                    let ra = RunAhead::new(frames);
+                    match ra.check_performance(fps) {
+                        Ok(()) => Some(ra),
+                        Err(err) => {
+                            run_ahead_err = Some(err);
+                            None
+                        }
+                    }
+                }
+            };
+
+            // Send a clone of the input manager and the game identifier to
+            // the main thread, for input mapping initialization, with the
+            // reason why run-ahead was disabled (the logger is not Send).
+            let game = producer.game_id();
+            let im = producer.input_manager().map(|im| im.clone());
+            let _ = tx_input.send(Ok((im, game, run_ahead_err)));
+
+            loop {
+                // If we received any input event from the main thread, process
+                // them through the input manager.
+                let mut input_changed = false;
+                if let Ok(evts) = rx_event.try_recv() {
+                    if let Some(im) = producer.input_manager() {
+                        for e in evts.iter() {
+                            im.process_event(e.clone());
+                        }
+                        input_changed = !evts.is_empty();
+                    }
+                }
+
+                let mut sound = OwnedSndBuffer::with_capacity(audio_frame_size);
+                let mut screen = OwnedGfxBufferLE::<Rgb888>::new(width, height);
+                match run_ahead.as_mut() {
+                    Some(ra) => ra.render_frame(
+                        &mut *producer,
+                        &mut screen.buf_mut(),
+                        &mut sound,
+                        input_changed,
+                    ),
+                    None => producer.render_frame(&mut screen.buf_mut(), &mut sound.buf_mut()),
+                }
+
+                if tx_frame.send((screen, sound)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        // Initialize input mapping, using the saved config for the game (or
+        // the global one, or the default one for the current input manager).
+        let (im, game, run_ahead_err) = match rx_input.recv() {
+            Ok(Ok(v)) => v,
+            Ok(Err(err)) => return Err(err),
+            Err(_) => return Err("emulation thread exited during initialization".into()),
+        };
+        if let Some(err) = run_ahead_err {
+            warn!(self.logger, "run-ahead disabled: {}", err);
+        }
+        let fps = self.vcfg.fps;
+        let mut input = match im.as_ref() {
+            Some(im) => {
+                let cfg = self.select_input_profile(im, game);
+                Some(InputMapping::new(cfg, im, fps, self.logger.clone()))
+            }
+            None => None,
+        };
+        #[cfg(feature = "adaptors")]
+        let mut adaptors = im
+            .as_ref()
+            .map(|im| AdaptorInput::new(im, self.logger.clone()));
+        let mut pads = HostPads::new(&self.context, self.logger.clone());
+
+        let polling_interval = Duration::from_millis(20);
+        while !self.quit {
+            let mut events = Vec::new();
+            for event in event_pump.poll_iter() {
+                pads.handle_event(&event);
+                self.process_event(&event);
+
+                // Try to pass the even through the input mapping.
+                // If it's mapped to an emulator input, accumulate
+                // to send it
+                if let (Some(map), false) = (input.as_mut(), self.is_hotkey(&event)) {
+                    if let Some(evt) = map.map_event(&event) {
+                        events.push(evt);
+                    }
+                }
+            }
+            #[cfg(feature = "adaptors")]
+            {
+                if let Some(a) = adaptors.as_mut() {
+                    events.extend(a.poll());
+                }
+            }
+            if let Some(map) = input.as_mut() {
+                if std::mem::replace(&mut self.record_macro, false) {
+                    map.toggle_recording();
+                }
+                if !self.paused || self.frame_advance {
+                    events.extend(map.tick());
+                }
+            }
+            if !events.is_empty() {
+                let _ = tx_event.send(events);
+            }
+
+            if self.paused && !self.frame_advance {
+                std::thread::sleep(polling_interval);
+                continue;
+            }
+            self.frame_advance = false;
+
+            match rx_frame.recv_timeout(polling_interval) {
+                Ok((ref screen, ref sound)) => {
+                    self.take_screenshot(&screen.buf());
+                    self.render_frame(&screen.buf());
+                    audio.render_frame(&sound.buf(), !self.fast_forward);
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+            }
+        }
+
+        if let Some(map) = input {
+            self.save_input_profile(map.config());
+        }
+        Ok(())
+    }
+
+    /// Return the user configuration (loaded from the configuration file).
+    pub fn config(&self) -> &UserConfig {
+        &self.config
+    }
+
+    /// Return the user configuration for modification. Changes are persisted
+    /// to the configuration file on exit.
+    pub fn config_mut(&mut self) -> &mut UserConfig {
+        &mut self.config
+    }
+
+    /// Switch the video output in or out of fullscreen, using the fullscreen
+    /// mode selected in the configuration.
+    pub fn toggle_fullscreen(&mut self) {
+        self.process_hotkey(HotkeyAction::Fullscreen);
+    }
+
+    /// Render a single frame to the video output.
+    pub fn render_frame(&mut self, screen: &GfxBufferLE<Rgb888>) {
+        if let Some(v) = self.video.as_mut() {
+            v.render_frame(screen);
+            v.window.gl_swap_window();
+            v.update_fps();
+        }
+    }
+}
+
+/// Show a modal dialog box with an error message. This can be used to report
+/// fatal errors to the user, also when the video output was not created.
+/// The dialog cannot be shown without a display, so the caller should report
+/// the error on the console as well.
+pub fn show_error_dialog(title: &str, message: &str) {
+    let flags = sdl2::messagebox::MessageBoxFlag::ERROR;
+    let _ = sdl2::messagebox::show_simple_message_box(flags, title, message, None);
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some(v) = self.video.as_ref() {
+            v.save_geometry(&mut self.config.window);
+        }
+        if !self.save_config {
+            return;
+        }
+        if let Err(err) = self.config.save() {
+            warn!(self.logger, "cannot save configuration: {}", err);
+        }
+    }
+}
@@ -4,18 +4,19 @@ use self::gl::types::*;
 use super::super::gfx::{ColorFormat, GfxBufferLE, GfxBufferMutLE, Rgb888, Rgba8888};
 use std::ffi;
 
-fn return_param<T, F>(f: F) -> T
+fn return_param<T: Default, F>(f: F) -> T
 where
     F: FnOnce(&mut T),
 {
-    let mut val = unsafe { ::std::mem::uninitialized() };
+    let mut val = T::default();
     f(&mut val);
     val
 }
 
-pub trait ColorForTexture: ColorFormat {
+pub(crate) trait ColorForTexture: ColorFormat {
     fn src_format() -> GLenum;
     fn dst_format() -> GLenum;
+    #[allow(dead_code)]
     fn byte_size() -> i32;
 }
 
@@ -27,7 +28,7 @@ impl ColorForTexture for Rgb888 {
         gl::RGB
     }
     fn byte_size() -> i32 {
-        return 3;
+        3
     }
 }
 
@@ -39,27 +40,32 @@ impl ColorForTexture for Rgba8888 {
         gl::RGBA
     }
     fn byte_size() -> i32 {
-        return 4;
+        4
     }
 }
 
-pub struct Texture {
+pub(crate) struct Texture {
     id: GLuint,
 }
 
 impl Texture {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         unsafe {
             let id = return_param(|x| gl::GenTextures(1, x as *mut u32));
             Self { id }
         }
     }
 
-    pub fn id(&self) -> usize {
+    pub(crate) fn id(&self) -> usize {
         self.id as usize
     }
 
-    pub fn copy_from<CF: ColorForTexture>(&self, pixels: &[u8], width: usize, height: usize) {
+    pub(crate) fn copy_from<CF: ColorForTexture>(
+        &self,
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+    ) {
         unsafe {
             gl::BindTexture(gl::TEXTURE_2D, self.id);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
@@ -80,12 +86,15 @@ impl Texture {
         }
     }
 
-    pub fn copy_from_buffer<CF: ColorForTexture>(&self, buffer: &GfxBufferLE<CF>) {
+    pub(crate) fn copy_from_buffer<CF: ColorForTexture>(&self, buffer: &GfxBufferLE<CF>) {
         let (pixels, _pitch) = buffer.raw();
         self.copy_from::<CF>(pixels, buffer.width(), buffer.height())
     }
 
-    pub fn copy_from_buffer_mut<CF: ColorForTexture>(&self, buffer: &mut GfxBufferMutLE<CF>) {
+    pub(crate) fn copy_from_buffer_mut<CF: ColorForTexture>(
+        &self,
+        buffer: &mut GfxBufferMutLE<CF>,
+    ) {
         let (width, heigth) = (buffer.width(), buffer.height());
         let (pixels, _pitch) = buffer.raw();
         self.copy_from::<CF>(pixels, width, heigth)
@@ -147,6 +156,34 @@ impl Program {
         let id = gl::CreateProgram();
         Self { id }
     }
+
+    // Compile and link a program from the sources of its vertex and fragment
+    // shaders (NUL-terminated).
+    unsafe fn link(vert_source: &[u8], frag_source: &[u8]) -> Self {
+        let program = Program::new();
+        let vert_shader = gl::CreateShader(gl::VERTEX_SHADER);
+        let frag_shader = gl::CreateShader(gl::FRAGMENT_SHADER);
+        gl::ShaderSource(
+            vert_shader,
+            1,
+            &(vert_source.as_ptr() as *const GLchar),
+            &(vert_source.len() as GLint),
+        );
+        gl::ShaderSource(
+            frag_shader,
+            1,
+            &(frag_source.as_ptr() as *const GLchar),
+            &(frag_source.len() as GLint),
+        );
+        gl::CompileShader(vert_shader);
+        gl::CompileShader(frag_shader);
+        gl::AttachShader(program.id, vert_shader);
+        gl::AttachShader(program.id, frag_shader);
+        gl::LinkProgram(program.id);
+        gl::DeleteShader(vert_shader);
+        gl::DeleteShader(frag_shader);
+        program
+    }
 }
 
 impl Drop for Program {
@@ -157,7 +194,7 @@ impl Drop for Program {
     }
 }
 
-pub struct SurfaceRenderer {
+pub(crate) struct SurfaceRenderer {
     vao: VertexArray,
     _vbo_pos: VertexBuffer, // saved here for Drop
     _vbo_tex: VertexBuffer, // saved here for Drop
@@ -170,7 +207,7 @@ pub struct SurfaceRenderer {
 }
 
 impl SurfaceRenderer {
-    pub fn new<F>(load_fn: F) -> Self
+    pub(crate) fn new<F>(load_fn: F) -> Self
     where
         F: FnMut(&'static str) -> *const ::std::os::raw::c_void,
     {
@@ -197,28 +234,7 @@ impl SurfaceRenderer {
                 }
             \0";
 
-            let program = Program::new();
-            let vert_shader = gl::CreateShader(gl::VERTEX_SHADER);
-            let frag_shader = gl::CreateShader(gl::FRAGMENT_SHADER);
-            gl::ShaderSource(
-                vert_shader,
-                1,
-                &(vert_source.as_ptr() as *const GLchar),
-                &(vert_source.len() as GLint),
-            );
-            gl::ShaderSource(
-                frag_shader,
-                1,
-                &(frag_source.as_ptr() as *const GLchar),
-                &(frag_source.len() as GLint),
-            );
-            gl::CompileShader(vert_shader);
-            gl::CompileShader(frag_shader);
-            gl::AttachShader(program.id, vert_shader);
-            gl::AttachShader(program.id, frag_shader);
-            gl::LinkProgram(program.id);
-            gl::DeleteShader(vert_shader);
-            gl::DeleteShader(frag_shader);
+            let program = Program::link(vert_source, frag_source);
 
             let loc_u_texture =
                 gl::GetUniformLocation(program.id, b"u_texture\0".as_ptr() as _) as u32;
@@ -276,21 +292,19 @@ impl SurfaceRenderer {
             gl::EnableVertexAttribArray(loc_a_position);
             gl::EnableVertexAttribArray(loc_a_texcoord);
 
-            let surf = Self {
+            Self {
                 tex: Texture::new(),
-                vao: vao,
+                vao,
                 _vbo_pos: vbo_pos,
                 _vbo_tex: vbo_tex,
                 _pos_data: pos_data,
                 _tex_data: tex_data,
-                program: program,
-            };
-
-            surf
+                program,
+            }
         }
     }
 
-    pub fn render<C: ColorForTexture>(&self, buffer: &GfxBufferLE<C>) {
+    pub(crate) fn render<C: ColorForTexture>(&self, buffer: &GfxBufferLE<C>) {
         unsafe {
             gl::UseProgram(self.program.id);
             gl::ActiveTexture(gl::TEXTURE0);
@@ -302,3 +316,405 @@ impl SurfaceRenderer {
         }
     }
 }
+
+struct Framebuffer {
+    id: GLuint,
+}
+
+impl Framebuffer {
+    unsafe fn new() -> Self {
+        let id = return_param(|x| gl::GenFramebuffers(1, x as *mut u32));
+        Self { id }
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.id);
+        }
+    }
+}
+
+struct Renderbuffer {
+    id: GLuint,
+}
+
+impl Renderbuffer {
+    unsafe fn new() -> Self {
+        let id = return_param(|x| gl::GenRenderbuffers(1, x as *mut u32));
+        Self { id }
+    }
+}
+
+impl Drop for Renderbuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteRenderbuffers(1, &self.id);
+        }
+    }
+}
+
+/// A vertex drawn by [`TriangleRenderer`](struct.TriangleRenderer.html).
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct TriVertex {
+    pub pos: [f32; 3],   // X, Y in pixels; depth (0.0 - 1.0)
+    pub color: [f32; 4], // RGBA (0.0 - 1.0)
+    pub tex: [f32; 2],   // texture coordinates, in texels
+}
+
+/// An input of the color combiner of
+/// [`TriangleRenderer`](struct.TriangleRenderer.html). The `*Alpha` inputs
+/// replicate the alpha component of the corresponding color.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CombinerInput {
+    Zero = 0,
+    One,
+    Texel,
+    Shade,
+    Prim,
+    Env,
+    TexelAlpha,
+    ShadeAlpha,
+    PrimAlpha,
+    EnvAlpha,
+}
+
+/// How a texture is sampled outside of its size, along one axis.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureWrap {
+    Clamp,
+    Repeat,
+    Mirror,
+}
+
+impl TextureWrap {
+    fn gl_param(self) -> i32 {
+        (match self {
+            TextureWrap::Clamp => gl::CLAMP_TO_EDGE,
+            TextureWrap::Repeat => gl::REPEAT,
+            TextureWrap::Mirror => gl::MIRRORED_REPEAT,
+        }) as i32
+    }
+}
+
+/// The state of the pipeline for a draw call of
+/// [`TriangleRenderer`](struct.TriangleRenderer.html). The color combiner
+/// computes `(A - B) * C + D`, separately for the RGB and the alpha
+/// components.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DrawState {
+    pub rgb: [CombinerInput; 4],
+    pub alpha: [CombinerInput; 4],
+    pub prim: [f32; 4],
+    pub env: [f32; 4],
+    pub depth_test: bool,  // draw if the depth is less or equal
+    pub depth_write: bool, // update the depth buffer
+    pub blend: bool,       // blend with the target, using the combined alpha
+}
+
+/// Draw triangles with OpenGL into an offscreen target, whose content is
+/// uploaded from (and read back into) a buffer of RGBA8888 pixels, so that
+/// an emulator can use the GPU to draw into its emulated video memory.
+///
+/// The GL functions must have been loaded (eg: by creating the frontend),
+/// and the GL context must be current on the calling thread. The GL state
+/// changed while drawing (target, viewport, blending, depth testing) is
+/// restored by `end`.
+pub struct TriangleRenderer {
+    program: Program,
+    vao: VertexArray,
+    vbo: VertexBuffer,
+    fbo: Framebuffer,
+    depth: Renderbuffer,
+    target: Texture,
+    tex: Texture,
+    size: (usize, usize),
+    saved: Option<SavedState>,
+
+    loc_u_size: i32,
+    loc_u_tex_size: i32,
+    loc_u_rgb: i32,
+    loc_u_alpha: i32,
+    loc_u_prim: i32,
+    loc_u_env: i32,
+}
+
+// GL state changed by TriangleRenderer, restored at the end of drawing.
+struct SavedState {
+    framebuffer: GLint,
+    viewport: [GLint; 4],
+    blend: bool,
+    depth_test: bool,
+    scissor_test: bool,
+}
+
+impl Default for TriangleRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TriangleRenderer {
+    pub fn new() -> Self {
+        unsafe {
+            let vert_source = b"
+                #version 150
+                uniform vec2 u_size;
+                in vec3 a_position;
+                in vec4 a_color;
+                in vec2 a_texcoord;
+                out vec4 v_color;
+                out vec2 v_texcoord;
+                void main() {
+                    vec2 pos = a_position.xy / u_size * 2.0 - 1.0;
+                    gl_Position = vec4(pos, a_position.z * 2.0 - 1.0, 1.0);
+                    v_color = a_color;
+                    v_texcoord = a_texcoord;
+                }
+            \0";
+
+            let frag_source = b"
+                #version 150
+                uniform sampler2D u_texture;
+                uniform vec2 u_tex_size;
+                uniform int u_rgb[4];
+                uniform int u_alpha[4];
+                uniform vec4 u_prim;
+                uniform vec4 u_env;
+                in vec4 v_color;
+                in vec2 v_texcoord;
+                out vec4 v_fragcolor;
+
+                vec4 source(int sel, vec4 texel) {
+                    if (sel == 1) return vec4(1.0);
+                    if (sel == 2) return texel;
+                    if (sel == 3) return v_color;
+                    if (sel == 4) return u_prim;
+                    if (sel == 5) return u_env;
+                    if (sel == 6) return vec4(texel.a);
+                    if (sel == 7) return vec4(v_color.a);
+                    if (sel == 8) return vec4(u_prim.a);
+                    if (sel == 9) return vec4(u_env.a);
+                    return vec4(0.0);
+                }
+
+                void main() {
+                    vec4 texel = texture(u_texture, v_texcoord / u_tex_size);
+                    vec3 rgb = (source(u_rgb[0], texel).rgb - source(u_rgb[1], texel).rgb)
+                        * source(u_rgb[2], texel).rgb + source(u_rgb[3], texel).rgb;
+                    float alpha = (source(u_alpha[0], texel).a - source(u_alpha[1], texel).a)
+                        * source(u_alpha[2], texel).a + source(u_alpha[3], texel).a;
+                    v_fragcolor = clamp(vec4(rgb, alpha), 0.0, 1.0);
+                }
+            \0";
+
+            let program = Program::link(vert_source, frag_source);
+            let loc = |name: &[u8]| gl::GetUniformLocation(program.id, name.as_ptr() as _);
+            let loc_u_texture = loc(b"u_texture\0");
+            let loc_u_size = loc(b"u_size\0");
+            let loc_u_tex_size = loc(b"u_tex_size\0");
+            let loc_u_rgb = loc(b"u_rgb\0");
+            let loc_u_alpha = loc(b"u_alpha\0");
+            let loc_u_prim = loc(b"u_prim\0");
+            let loc_u_env = loc(b"u_env\0");
+            let attr = |name: &[u8]| gl::GetAttribLocation(program.id, name.as_ptr() as _) as u32;
+            let loc_a_position = attr(b"a_position\0");
+            let loc_a_color = attr(b"a_color\0");
+            let loc_a_texcoord = attr(b"a_texcoord\0");
+
+            gl::UseProgram(program.id);
+            gl::Uniform1i(loc_u_texture, 0);
+
+            let vao = VertexArray::new();
+            let vbo = VertexBuffer::new();
+            gl::BindVertexArray(vao.id);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo.id);
+            let stride = ::std::mem::size_of::<TriVertex>() as i32;
+            let float = ::std::mem::size_of::<GLfloat>();
+            for &(loc, len, offset) in &[
+                (loc_a_position, 3, 0),
+                (loc_a_color, 4, 3 * float),
+                (loc_a_texcoord, 2, 7 * float),
+            ] {
+                gl::VertexAttribPointer(loc, len, gl::FLOAT, gl::FALSE, stride, offset as _);
+                gl::EnableVertexAttribArray(loc);
+            }
+
+            Self {
+                program,
+                vao,
+                vbo,
+                fbo: Framebuffer::new(),
+                depth: Renderbuffer::new(),
+                target: Texture::new(),
+                tex: Texture::new(),
+                size: (0, 0),
+                saved: None,
+                loc_u_size,
+                loc_u_tex_size,
+                loc_u_rgb,
+                loc_u_alpha,
+                loc_u_prim,
+                loc_u_env,
+            }
+        }
+    }
+
+    /// Start drawing into a target of the specified size, whose initial
+    /// content is given as RGBA8888 pixels. The depth buffer is cleared.
+    pub fn begin(&mut self, pixels: &[u8], width: usize, height: usize) {
+        unsafe {
+            let mut viewport = [0; 4];
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            self.saved = Some(SavedState {
+                framebuffer: return_param(|x| gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, x)),
+                viewport,
+                blend: gl::IsEnabled(gl::BLEND) == gl::TRUE,
+                depth_test: gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE,
+                scissor_test: gl::IsEnabled(gl::SCISSOR_TEST) == gl::TRUE,
+            });
+
+            gl::ActiveTexture(gl::TEXTURE0);
+            self.target.copy_from::<Rgba8888>(pixels, width, height);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo.id);
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                self.target.id,
+                0,
+            );
+            if self.size != (width, height) {
+                gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth.id);
+                gl::RenderbufferStorage(
+                    gl::RENDERBUFFER,
+                    gl::DEPTH_COMPONENT24,
+                    width as i32,
+                    height as i32,
+                );
+                gl::FramebufferRenderbuffer(
+                    gl::FRAMEBUFFER,
+                    gl::DEPTH_ATTACHMENT,
+                    gl::RENDERBUFFER,
+                    self.depth.id,
+                );
+                self.size = (width, height);
+            }
+
+            gl::Viewport(0, 0, width as i32, height as i32);
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::DepthMask(gl::TRUE);
+            gl::ClearDepth(1.0);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+
+            gl::UseProgram(self.program.id);
+            gl::Uniform2f(self.loc_u_size, width as f32, height as f32);
+        }
+    }
+
+    /// Select the texture sampled by the combiner, as RGBA8888 pixels.
+    pub fn set_texture(
+        &mut self,
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+        wrap: [TextureWrap; 2],
+    ) {
+        unsafe {
+            gl::UseProgram(self.program.id);
+            gl::ActiveTexture(gl::TEXTURE0);
+            self.tex.copy_from::<Rgba8888>(pixels, width, height);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap[0].gl_param());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap[1].gl_param());
+            gl::Uniform2f(self.loc_u_tex_size, width as f32, height as f32);
+        }
+    }
+
+    /// Draw a list of triangles (3 vertices each) with the specified state.
+    pub fn draw(&mut self, state: &DrawState, vertices: &[TriVertex]) {
+        if vertices.is_empty() {
+            return;
+        }
+        unsafe {
+            gl::UseProgram(self.program.id);
+            let rgb: Vec<i32> = state.rgb.iter().map(|&i| i as i32).collect();
+            let alpha: Vec<i32> = state.alpha.iter().map(|&i| i as i32).collect();
+            gl::Uniform1iv(self.loc_u_rgb, 4, rgb.as_ptr());
+            gl::Uniform1iv(self.loc_u_alpha, 4, alpha.as_ptr());
+            gl::Uniform4fv(self.loc_u_prim, 1, state.prim.as_ptr());
+            gl::Uniform4fv(self.loc_u_env, 1, state.env.as_ptr());
+
+            if state.depth_test || state.depth_write {
+                gl::Enable(gl::DEPTH_TEST);
+                gl::DepthFunc(if state.depth_test {
+                    gl::LEQUAL
+                } else {
+                    gl::ALWAYS
+                });
+                gl::DepthMask(state.depth_write as GLboolean);
+            } else {
+                gl::Disable(gl::DEPTH_TEST);
+            }
+            if state.blend {
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            } else {
+                gl::Disable(gl::BLEND);
+            }
+
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.tex.id);
+            gl::BindVertexArray(self.vao.id);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo.id);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                std::mem::size_of_val(vertices) as isize,
+                vertices.as_ptr() as _,
+                gl::STREAM_DRAW,
+            );
+            gl::DrawArrays(gl::TRIANGLES, 0, vertices.len() as i32);
+        }
+    }
+
+    /// Finish drawing: read back the content of the target into pixels
+    /// (RGBA8888), and restore the GL state.
+    pub fn end(&mut self, pixels: &mut [u8]) {
+        let (width, height) = self.size;
+        assert!(pixels.len() >= width * height * 4);
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                0,
+                0,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as *mut ffi::c_void,
+            );
+
+            if let Some(saved) = self.saved.take() {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, saved.framebuffer as u32);
+                let vp = saved.viewport;
+                gl::Viewport(vp[0], vp[1], vp[2], vp[3]);
+                let restore = |cap, enabled| {
+                    if enabled {
+                        gl::Enable(cap)
+                    } else {
+                        gl::Disable(cap)
+                    }
+                };
+                restore(gl::BLEND, saved.blend);
+                restore(gl::DEPTH_TEST, saved.depth_test);
+                restore(gl::SCISSOR_TEST, saved.scissor_test);
+            }
+            gl::DepthMask(gl::TRUE);
+        }
+    }
+}
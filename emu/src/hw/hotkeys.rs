@@ -0,0 +1,124 @@
+use sdl2::keyboard::{Keycode, Scancode};
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+/// An action that can be triggered by a hotkey, either while the emulator
+/// is running or within the debugger.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum HotkeyAction {
+    ToggleDebugger,
+    Pause,
+    FrameAdvance,
+    FastForward,
+    SaveState,
+    LoadState,
+    Rewind,
+    Screenshot,
+    Fullscreen,
+    DebuggerHelp,
+    RecordMacro,
+}
+
+impl HotkeyAction {
+    pub const ALL: [HotkeyAction; 11] = [
+        HotkeyAction::ToggleDebugger,
+        HotkeyAction::Pause,
+        HotkeyAction::FrameAdvance,
+        HotkeyAction::FastForward,
+        HotkeyAction::SaveState,
+        HotkeyAction::LoadState,
+        HotkeyAction::Rewind,
+        HotkeyAction::Screenshot,
+        HotkeyAction::Fullscreen,
+        HotkeyAction::DebuggerHelp,
+        HotkeyAction::RecordMacro,
+    ];
+
+    pub fn desc(self) -> &'static str {
+        use self::HotkeyAction::*;
+        match self {
+            ToggleDebugger => "Toggle debugger",
+            Pause => "Pause / resume",
+            FrameAdvance => "Frame advance",
+            FastForward => "Fast forward (hold)",
+            SaveState => "Save state",
+            LoadState => "Load state",
+            Rewind => "Rewind",
+            Screenshot => "Screenshot",
+            Fullscreen => "Toggle fullscreen",
+            DebuggerHelp => "Debugger help",
+            RecordMacro => "Record input macro",
+        }
+    }
+
+    fn default_scancode(self) -> Scancode {
+        use self::HotkeyAction::*;
+        match self {
+            ToggleDebugger => Scancode::Escape,
+            Pause => Scancode::Space,
+            FrameAdvance => Scancode::Period,
+            FastForward => Scancode::Tab,
+            SaveState => Scancode::F5,
+            LoadState => Scancode::F7,
+            Rewind => Scancode::F8,
+            Screenshot => Scancode::F12,
+            Fullscreen => Scancode::F11,
+            DebuggerHelp => Scancode::H,
+            RecordMacro => Scancode::F9,
+        }
+    }
+}
+
+/// HotkeyConfig maps hotkey actions to keys. Keys are stored by name (as
+/// returned by SDL), so that the configuration file is human-editable.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HotkeyConfig {
+    keys: BTreeMap<HotkeyAction, String>,
+}
+
+impl Default for HotkeyConfig {
+    fn default() -> Self {
+        let mut cfg = HotkeyConfig {
+            keys: BTreeMap::new(),
+        };
+        for action in HotkeyAction::ALL.iter() {
+            cfg.set(*action, action.default_scancode());
+        }
+        cfg
+    }
+}
+
+impl HotkeyConfig {
+    /// Return the key bound to the specified action (if any).
+    pub fn scancode(&self, action: HotkeyAction) -> Option<Scancode> {
+        self.keys
+            .get(&action)
+            .and_then(|name| Keycode::from_name(name))
+            .and_then(Scancode::from_keycode)
+    }
+
+    /// Return the action bound to the specified key (if any).
+    pub fn action(&self, scancode: Scancode) -> Option<HotkeyAction> {
+        HotkeyAction::ALL
+            .iter()
+            .find(|a| self.scancode(**a) == Some(scancode))
+            .cloned()
+    }
+
+    /// Bind a key to an action. If the key was bound to a different action,
+    /// that binding is removed.
+    pub fn set(&mut self, action: HotkeyAction, scancode: Scancode) {
+        if let Some(old) = self.action(scancode) {
+            self.keys.remove(&old);
+        }
+        if let Some(key) = Keycode::from_scancode(scancode) {
+            self.keys.insert(action, key.name());
+        }
+    }
+
+    /// Return the name of the key bound to the specified action, for display.
+    pub fn key_name(&self, action: HotkeyAction) -> &str {
+        self.keys.get(&action).map_or("<none>", |s| s.as_str())
+    }
+}
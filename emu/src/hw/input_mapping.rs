@@ -1,9 +1,14 @@
+use super::stick::StickConfig;
 use crate::input::{InputDeviceKind, InputEvent, InputKind, InputManager};
 
 use sdl2;
+use sdl2::controller::{Axis, GameController};
+use sdl2::event::Event;
 use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::GameControllerSubsystem;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use slog::{info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 /// PhysicalDevice describes how a device was mapped.
 #[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
@@ -12,10 +17,28 @@ enum PhysicalDevice {
     Joystick(String),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 struct InputDeviceConfig {
     phys: PhysicalDevice,
     mapping: HashMap<String, String>, // input name = key/joy
+
+    // Inputs with auto-fire while held: input name = presses per second.
+    #[serde(default)]
+    turbo: HashMap<String, f32>,
+
+    // Mapping of the host analog stick (for devices with analog inputs).
+    #[serde(default)]
+    stick: StickConfig,
+}
+
+// An emulated input, as (device name, input name).
+type InputId = (String, String);
+
+/// A recorded sequence of inputs, played back when its key is pressed.
+#[derive(Serialize, Deserialize, Clone)]
+struct InputMacro {
+    key: String,
+    frames: Vec<Vec<InputId>>, // inputs held in each frame
 }
 
 fn default_scancode_for_kind(kind: InputKind) -> Option<Scancode> {
@@ -35,91 +58,582 @@ fn default_scancode_for_kind(kind: InputKind) -> Option<Scancode> {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct InputConfig {
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct InputConfig {
     devices: HashMap<String, InputDeviceConfig>, // device name => mapped device
+    #[serde(default)]
+    macros: Vec<InputMacro>,
 }
 
 impl InputConfig {
-    pub fn default(im: &InputManager) -> InputConfig {
-        let mut devices = HashMap::new();
-        let mut first_joystick = true;
+    pub(crate) fn default(im: &InputManager) -> InputConfig {
+        let mut cfg = InputConfig {
+            devices: HashMap::new(),
+            macros: Vec::new(),
+        };
+        cfg.add_missing_devices(im);
+        cfg
+    }
+
+    /// Add the default bindings for the devices that are not in the
+    /// configuration (eg: a peripheral that was not connected when it was
+    /// saved). Keyboards are mapped to the host keys with the same names,
+    /// unless they are already bound.
+    pub(crate) fn add_missing_devices(&mut self, im: &InputManager) {
+        let mut first_joystick = self.devices.is_empty();
+        let mut bound: BTreeSet<String> = self
+            .devices
+            .values()
+            .flat_map(|d| d.mapping.values().cloned())
+            .collect();
 
         im.visit(|dev| {
+            if self.devices.contains_key(dev.name()) {
+                return;
+            }
             let mut mapping = HashMap::new();
-            if first_joystick && dev.kind() == InputDeviceKind::Joystick {
-                dev.visit(|inp| {
-                    if let Some(scan) = default_scancode_for_kind(inp.kind()) {
-                        let key_name = Keycode::from_scancode(scan).unwrap().name();
-                        mapping.insert(inp.name().to_owned(), key_name);
+            match dev.kind() {
+                InputDeviceKind::Joystick if first_joystick => {
+                    dev.visit(|inp| {
+                        if let Some(scan) = default_scancode_for_kind(inp.kind()) {
+                            let key_name = Keycode::from_scancode(scan).unwrap().name();
+                            bound.insert(key_name.clone());
+                            mapping.insert(inp.name().to_owned(), key_name);
+                        }
+                    });
+                    first_joystick = false;
+                }
+                InputDeviceKind::Keyboard => dev.visit(|inp| {
+                    if let Some(key) = Keycode::from_name(inp.name()) {
+                        let key_name = key.name();
+                        if bound.insert(key_name.clone()) {
+                            mapping.insert(inp.name().to_owned(), key_name);
+                        }
                     }
-                });
-                first_joystick = false;
+                }),
+                _ => {}
             }
 
-            devices.insert(
+            self.devices.insert(
                 dev.name().to_owned(),
                 InputDeviceConfig {
                     phys: PhysicalDevice::Keyboard,
-                    mapping: mapping,
+                    mapping,
+                    turbo: HashMap::new(),
+                    stick: StickConfig::default(),
                 },
             );
         });
+    }
 
-        InputConfig { devices }
+    /// Return true if the configuration refers to the devices of the
+    /// specified InputManager (eg: it was saved for the same emulator).
+    /// Configured devices that are missing (eg: optional peripherals that
+    /// are not connected) are kept, but ignored.
+    pub(crate) fn matches(&self, im: &InputManager) -> bool {
+        self.devices.keys().any(|name| im.device(name).is_some())
     }
 
-    fn all_keys(&self) -> HashMap<Scancode, (String, String)> {
+    // Auto-fire frequency of an input (if turbo is enabled).
+    fn turbo(&self, id: &InputId) -> Option<f32> {
+        self.devices.get(&id.0)?.turbo.get(&id.1).cloned()
+    }
+
+    fn all_keys(&self, im: &InputManager) -> HashMap<Scancode, (String, String)> {
         self.devices
             .iter()
-            .filter(|(_, d)| d.phys == PhysicalDevice::Keyboard)
-            .map(|(dev_name, d)| {
+            .filter(|(name, d)| d.phys == PhysicalDevice::Keyboard && im.device(name).is_some())
+            .flat_map(|(dev_name, d)| {
                 d.mapping.iter().map(move |(inp_name, key_name)| {
                     let scan =
                         Scancode::from_keycode(Keycode::from_name(key_name).unwrap()).unwrap();
                     (scan, (dev_name.clone(), inp_name.clone()))
                 })
             })
-            .flatten()
             .collect()
     }
 }
 
-pub struct InputMapping {
+/// The input profile in use: the global one, or the one associated with the
+/// running game. It is shown in the input window of the debugger, which can
+/// request an action on it.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InputProfile {
+    pub game: Option<String>, // identifier of the running game, if known
+    pub per_game: bool,       // true if the game has its own profile
+    pub action: Option<ProfileAction>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ProfileAction {
+    CopyToGame, // copy the current bindings into a profile for the game
+    RemoveGame, // remove the game profile, going back to the global one
+}
+
+// Macro recording in progress: the inputs held in each frame. When the
+// recording is stopped, the macro is bound to the next key pressed that is
+// not mapped to an input.
+enum Recording {
+    Inputs(Vec<Vec<InputId>>),
+    WaitKey(Vec<Vec<InputId>>),
+}
+
+// An emulated analog stick, driven by the left stick of a host game
+// controller.
+struct Stick {
+    dev: String,
+    axes: (String, String), // names of the X and Y inputs
+    out: (i16, i16),        // last values sent
+}
+
+/// InputMapping converts host events into events for the emulated inputs.
+/// On top of the plain key bindings, it implements turbo (auto-fire) and
+/// input macros, so they work regardless of the emulated software, and maps
+/// the analog sticks of host game controllers through the configured
+/// response curves.
+pub(crate) struct InputMapping {
+    logger: slog::Logger,
     cfg: InputConfig,
     key_lookup: HashMap<Scancode, (String, String)>,
+    fps: f32,
+
+    held: BTreeSet<InputId>,        // inputs currently pressed
+    turbo: HashMap<InputId, usize>, // held turbo inputs = frames since press
+    playing: Vec<(usize, usize)>,   // macros being played = (index, frame)
+    macro_held: BTreeSet<InputId>,  // inputs pressed by macros
+    recording: Option<Recording>,
+
+    sticks: Vec<Stick>,
+    pads: BTreeMap<u32, (f32, f32)>, // host controller = left stick position
 }
 
 impl InputMapping {
-    pub fn new(cfg: InputConfig) -> Self {
-        let key_lookup = cfg.all_keys();
-        Self { cfg, key_lookup }
+    pub(crate) fn new(
+        cfg: InputConfig,
+        im: &InputManager,
+        fps: isize,
+        logger: slog::Logger,
+    ) -> Self {
+        let key_lookup = cfg.all_keys(im);
+        let mut sticks = Vec::new();
+        im.visit(|dev| {
+            if dev.kind() != InputDeviceKind::Joystick {
+                return;
+            }
+            let (mut x, mut y) = (None, None);
+            dev.visit(|inp| match inp.kind() {
+                InputKind::Horizontal => x = Some(inp.name().to_owned()),
+                InputKind::Vertical => y = Some(inp.name().to_owned()),
+                _ => {}
+            });
+            if let (Some(x), Some(y)) = (x, y) {
+                sticks.push(Stick {
+                    dev: dev.name().to_owned(),
+                    axes: (x, y),
+                    out: (0, 0),
+                });
+            }
+        });
+        Self {
+            logger,
+            cfg,
+            key_lookup,
+            fps: fps as f32,
+            held: BTreeSet::new(),
+            turbo: HashMap::new(),
+            playing: Vec::new(),
+            macro_held: BTreeSet::new(),
+            recording: None,
+            sticks,
+            pads: BTreeMap::new(),
+        }
+    }
+
+    /// Return the configuration (including the recorded macros), to save it.
+    pub(crate) fn config(&self) -> &InputConfig {
+        &self.cfg
+    }
+
+    fn digital(&mut self, id: &InputId, val: bool) -> InputEvent {
+        if val {
+            self.held.insert(id.clone());
+        } else {
+            self.held.remove(id);
+        }
+        InputEvent::Digital(id.0.clone(), id.1.clone(), val)
     }
 
-    pub fn map_event(&self, event: &sdl2::event::Event) -> Option<InputEvent> {
+    pub(crate) fn map_event(&mut self, event: &Event) -> Option<InputEvent> {
         use sdl2::event::Event::*;
         match event {
+            // Analog sticks are sampled once per frame, in tick().
+            ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                let pos = self.pads.entry(*which as u32).or_insert((0.0, 0.0));
+                let v = (*value as f32 / 32767.0).clamp(-1.0, 1.0);
+                match axis {
+                    Axis::LeftX => pos.0 = v,
+                    Axis::LeftY => pos.1 = -v,
+                    _ => {}
+                }
+                None
+            }
+            ControllerDeviceRemoved { which, .. } => {
+                self.pads.remove(&(*which as u32));
+                None
+            }
+
             KeyDown {
                 scancode: Some(scode),
+                repeat,
                 ..
-            } => match self.key_lookup.get(scode) {
-                Some((dev, inp)) => {
-                    Some(InputEvent::Digital(dev.to_string(), inp.to_string(), true))
+            } => {
+                let id = match self.key_lookup.get(scode) {
+                    Some(id) => id.clone(),
+                    None => {
+                        if !repeat {
+                            self.macro_key(*scode);
+                        }
+                        return None;
+                    }
+                };
+                if self.cfg.turbo(&id).is_some() {
+                    if *repeat || self.turbo.contains_key(&id) {
+                        return None;
+                    }
+                    self.turbo.insert(id.clone(), 0);
                 }
-                None => None,
-            },
+                Some(self.digital(&id, true))
+            }
 
             KeyUp {
                 scancode: Some(scode),
                 ..
-            } => match self.key_lookup.get(scode) {
-                Some((dev, inp)) => {
-                    Some(InputEvent::Digital(dev.to_string(), inp.to_string(), false))
-                }
-                None => None,
-            },
+            } => {
+                let id = self.key_lookup.get(scode)?.clone();
+                self.turbo.remove(&id);
+                Some(self.digital(&id, false))
+            }
 
             _ => None,
         }
     }
+
+    // Handle a key not mapped to any input: bind the macro just recorded,
+    // or start the playback of the macro bound to it.
+    fn macro_key(&mut self, scode: Scancode) {
+        let key = match Keycode::from_scancode(scode) {
+            Some(key) => key.name(),
+            None => return,
+        };
+        match self.recording.take() {
+            Some(Recording::WaitKey(frames)) => {
+                self.cfg.macros.retain(|m| m.key != key);
+                info!(
+                    self.logger,
+                    "macro bound to key: {} ({} frames)",
+                    key,
+                    frames.len()
+                );
+                self.cfg.macros.push(InputMacro { key, frames });
+                return;
+            }
+            rec => self.recording = rec,
+        }
+        if let Some(idx) = self.cfg.macros.iter().position(|m| m.key == key) {
+            self.playing.push((idx, 0));
+        }
+    }
+
+    /// Start or stop recording a macro. When the recording is stopped, the
+    /// macro is bound to the next key pressed that is not mapped to an input.
+    pub(crate) fn toggle_recording(&mut self) {
+        self.recording = match self.recording.take() {
+            None => {
+                info!(self.logger, "recording macro...");
+                Some(Recording::Inputs(Vec::new()))
+            }
+            Some(Recording::Inputs(mut frames)) => {
+                // Trim idle frames at both ends.
+                while frames.last().is_some_and(|f| f.is_empty()) {
+                    frames.pop();
+                }
+                let start = frames.iter().position(|f| !f.is_empty());
+                let frames = frames.split_off(start.unwrap_or(frames.len()));
+                if frames.is_empty() {
+                    info!(self.logger, "macro recording cancelled (no inputs)");
+                    None
+                } else {
+                    info!(self.logger, "macro recorded: press a key to bind it");
+                    Some(Recording::WaitKey(frames))
+                }
+            }
+            Some(Recording::WaitKey(_)) => {
+                info!(self.logger, "macro recording cancelled");
+                None
+            }
+        };
+    }
+
+    /// Advance the turbo and macro state by one frame, and return the
+    /// resulting events. It must be called once per emulated frame.
+    pub(crate) fn tick(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+
+        if let Some(Recording::Inputs(frames)) = self.recording.as_mut() {
+            frames.push(self.held.iter().cloned().collect());
+        }
+
+        // Turbo: toggle the held inputs at the configured frequency (with a
+        // period of at least two frames, so that each press is visible).
+        let mut toggles = Vec::new();
+        for (id, frame) in self.turbo.iter_mut() {
+            *frame += 1;
+            let freq = self.cfg.turbo(id).unwrap_or(1.0).max(0.1);
+            let period = ((self.fps / freq).round() as usize).max(2);
+            let pressed = *frame % period < period / 2;
+            if pressed != self.held.contains(id) {
+                toggles.push((id.clone(), pressed));
+            }
+        }
+        for (id, pressed) in toggles {
+            events.push(self.digital(&id, pressed));
+        }
+
+        // Macros: the union of the inputs of all the macros being played.
+        let mut inputs = BTreeSet::new();
+        let macros = &self.cfg.macros;
+        self.playing
+            .retain(|(idx, frame)| macros.get(*idx).is_some_and(|m| *frame < m.frames.len()));
+        for (idx, frame) in self.playing.iter_mut() {
+            inputs.extend(macros[*idx].frames[*frame].iter().cloned());
+            *frame += 1;
+        }
+        for id in self.macro_held.difference(&inputs) {
+            if !self.held.contains(id) {
+                events.push(InputEvent::Digital(id.0.clone(), id.1.clone(), false));
+            }
+        }
+        for id in inputs.difference(&self.macro_held) {
+            events.push(InputEvent::Digital(id.0.clone(), id.1.clone(), true));
+        }
+        self.macro_held = inputs;
+
+        // Analog sticks: the host controllers are assigned to the emulated
+        // sticks in order.
+        let mut pads = self.pads.values();
+        for stick in self.sticks.iter_mut() {
+            let (x, y) = pads.next().cloned().unwrap_or((0.0, 0.0));
+            let out = match self.cfg.devices.get(&stick.dev) {
+                Some(d) => d.stick.map_input(x, y),
+                None => StickConfig::default().map_input(x, y),
+            };
+            if out.0 != stick.out.0 {
+                events.push(InputEvent::Analog(
+                    stick.dev.clone(),
+                    stick.axes.0.clone(),
+                    out.0,
+                ));
+            }
+            if out.1 != stick.out.1 {
+                events.push(InputEvent::Analog(
+                    stick.dev.clone(),
+                    stick.axes.1.clone(),
+                    out.1,
+                ));
+            }
+            stick.out = out;
+        }
+
+        events
+    }
+
+    /// Visit the emulated analog sticks, with their configuration (which can
+    /// be modified), the position of the host stick driving them (if any),
+    /// and the resulting emulated position.
+    pub(crate) fn visit_sticks<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&str, &mut StickConfig, Option<(f32, f32)>, (f32, f32)),
+    {
+        let mut pads = self.pads.values();
+        for stick in self.sticks.iter() {
+            let pos = pads.next().cloned();
+            if let Some(d) = self.cfg.devices.get_mut(&stick.dev) {
+                let (x, y) = pos.unwrap_or((0.0, 0.0));
+                let out = d.stick.map(x, y);
+                f(&stick.dev, &mut d.stick, pos, out);
+            }
+        }
+    }
+}
+
+/// HostPads keeps the host game controllers open, as SDL reports the events
+/// of a controller only while it is open. Controllers connected at startup
+/// are reported as added too.
+pub(crate) struct HostPads {
+    logger: slog::Logger,
+    subsystem: Option<GameControllerSubsystem>,
+    open: Vec<GameController>,
+}
+
+impl HostPads {
+    pub(crate) fn new(context: &sdl2::Sdl, logger: slog::Logger) -> Self {
+        let subsystem = context
+            .game_controller()
+            .map_err(|err| warn!(logger, "cannot initialize game controllers: {}", err))
+            .ok();
+        HostPads {
+            logger,
+            subsystem,
+            open: Vec::new(),
+        }
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                let sub = match self.subsystem.as_ref() {
+                    Some(sub) => sub,
+                    None => return,
+                };
+                match sub.open(*which) {
+                    Ok(pad) => {
+                        info!(self.logger, "game controller: {}", pad.name());
+                        self.open.push(pad);
+                    }
+                    Err(err) => warn!(self.logger, "cannot open game controller: {}", err),
+                }
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.open
+                    .retain(|pad| pad.instance_id() as u32 != *which as u32);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Input, InputDevice};
+    use sdl2::keyboard::Mod;
+    use std::sync::Mutex;
+
+    // Key names are converted through the SDL keymap, which is initialized
+    // by the video subsystem; SDL can be initialized by one test at a time.
+    static SDL: Mutex<()> = Mutex::new(());
+
+    fn with_video<F: FnOnce()>(f: F) {
+        let _lock = SDL.lock().unwrap_or_else(|err| err.into_inner());
+        std::env::set_var("SDL_VIDEODRIVER", "dummy");
+        let sdl = sdl2::init().unwrap();
+        let _video = sdl.video().unwrap();
+        f();
+    }
+
+    // A joystick with a single button, bound to Z by default.
+    fn input_manager() -> InputManager {
+        InputManager::new(vec![InputDevice::new(
+            "pad",
+            InputDeviceKind::Joystick,
+            vec![Input::new_digital("A", InputKind::Button1, 0)],
+        )])
+    }
+
+    fn mapping(cfg: InputConfig, im: &InputManager) -> InputMapping {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        InputMapping::new(cfg, im, 60, logger)
+    }
+
+    fn key(scancode: Scancode, down: bool) -> Event {
+        let (timestamp, window_id, keycode, keymod) = (0, 0, None, Mod::empty());
+        let scancode = Some(scancode);
+        if down {
+            Event::KeyDown {
+                timestamp,
+                window_id,
+                keycode,
+                scancode,
+                keymod,
+                repeat: false,
+            }
+        } else {
+            Event::KeyUp {
+                timestamp,
+                window_id,
+                keycode,
+                scancode,
+                keymod,
+                repeat: false,
+            }
+        }
+    }
+
+    // The digital events, as (input name, pressed).
+    fn digital<I: IntoIterator<Item = InputEvent>>(events: I) -> Vec<(String, bool)> {
+        events
+            .into_iter()
+            .filter_map(|evt| match evt {
+                InputEvent::Digital(_, name, val) => Some((name, val)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn turbo() {
+        with_video(|| {
+            let im = input_manager();
+            let mut cfg = InputConfig::default(&im);
+            let dev = cfg.devices.get_mut("pad").unwrap();
+            dev.turbo.insert("A".into(), 15.0); // 4 frames per press at 60 FPS
+            let mut map = mapping(cfg, &im);
+            let a = |val| vec![("A".to_owned(), val)];
+
+            assert_eq!(digital(map.map_event(&key(Scancode::Z, true))), a(true));
+            assert_eq!(digital(map.tick()), vec![]);
+            assert_eq!(digital(map.tick()), a(false));
+            assert_eq!(digital(map.tick()), vec![]);
+            assert_eq!(digital(map.tick()), a(true));
+
+            assert_eq!(digital(map.map_event(&key(Scancode::Z, false))), a(false));
+            for _ in 0..8 {
+                assert_eq!(digital(map.tick()), vec![]);
+            }
+        });
+    }
+
+    #[test]
+    fn macros() {
+        with_video(|| {
+            let im = input_manager();
+            let mut map = mapping(InputConfig::default(&im), &im);
+            let a = |val| vec![("A".to_owned(), val)];
+
+            // Record A held for two frames; the idle frames at the end are
+            // trimmed. The macro is bound to the next unmapped key (M).
+            map.toggle_recording();
+            map.map_event(&key(Scancode::Z, true));
+            map.tick();
+            map.tick();
+            map.map_event(&key(Scancode::Z, false));
+            map.tick();
+            map.tick();
+            map.toggle_recording();
+            assert!(map.map_event(&key(Scancode::M, true)).is_none());
+            assert_eq!(map.config().macros.len(), 1);
+            assert_eq!(map.config().macros[0].key, "M");
+            assert_eq!(map.config().macros[0].frames.len(), 2);
+
+            // Play it back.
+            map.map_event(&key(Scancode::M, false));
+            map.map_event(&key(Scancode::M, true));
+            assert_eq!(digital(map.tick()), a(true));
+            assert_eq!(digital(map.tick()), vec![]);
+            assert_eq!(digital(map.tick()), a(false));
+            assert_eq!(digital(map.tick()), vec![]);
+        });
+    }
 }
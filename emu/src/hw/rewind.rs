@@ -0,0 +1,178 @@
+//! Rewind: step the emulation backwards.
+//!
+//! A snapshot of the state is taken every few frames, and kept in a ring
+//! buffer of bounded size. To keep memory usage low, only one snapshot every
+//! KEYFRAME_INTERVAL is stored in full (a keyframe); the others are stored
+//! as the XOR with the previous keyframe, which is mostly zeros (as most of
+//! RDRAM doesn't change across a few frames), and compressed.
+
+use crate::state::{CompressedState, CurrentState, State};
+
+use std::collections::VecDeque;
+
+// Number of snapshots per keyframe (including the keyframe itself).
+const KEYFRAME_INTERVAL: usize = 16;
+
+// A keyframe, and the deltas of the following snapshots against it.
+struct Group {
+    key: CompressedState,
+    deltas: Vec<CompressedState>,
+}
+
+/// A ring buffer of snapshots to rewind the emulation (see the module
+/// documentation).
+pub struct Rewind {
+    interval: usize, // frames between snapshots
+    capacity: usize, // maximum number of snapshots
+    frame: usize,    // frames since the last snapshot
+    groups: VecDeque<Group>,
+    key: Option<State>, // keyframe of the last group (decompressed)
+}
+
+impl Rewind {
+    /// Create a rewind buffer that takes a snapshot every interval frames,
+    /// keeping the last capacity snapshots.
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        assert!(interval >= 1 && capacity >= 1, "invalid rewind config");
+        Rewind {
+            interval,
+            capacity,
+            frame: 0,
+            groups: VecDeque::new(),
+            key: None,
+        }
+    }
+
+    /// Return the number of snapshots available.
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|g| 1 + g.deltas.len()).sum()
+    }
+
+    /// Discard all the snapshots (eg: after loading a savestate).
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.key = None;
+        self.frame = 0;
+    }
+
+    /// Notify that a frame has been emulated; a snapshot of the current
+    /// state is taken every interval frames.
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+        if self.frame < self.interval {
+            return;
+        }
+        self.frame = 0;
+
+        let mut state = CurrentState().clone();
+        let delta = match (self.groups.back(), self.key.as_ref()) {
+            (Some(g), Some(key)) => {
+                g.deltas.len() + 1 < KEYFRAME_INTERVAL && key.len() == state.len()
+            }
+            _ => false,
+        };
+        if delta {
+            state.xor(self.key.as_ref().unwrap());
+            let g = self.groups.back_mut().unwrap();
+            g.deltas.push(state.into_compressed());
+        } else {
+            self.groups.push_back(Group {
+                key: state.clone().into_compressed(),
+                deltas: Vec::new(),
+            });
+            self.key = Some(state);
+        }
+
+        // Drop the oldest group (keyframe and deltas) when full.
+        while self.len() > self.capacity {
+            self.groups.pop_front();
+        }
+        if self.groups.is_empty() {
+            self.key = None;
+        }
+    }
+
+    /// Restore the last snapshot, and remove it from the buffer, so that the
+    /// next call goes further back. Returns false if there are no snapshots.
+    pub fn step_back(&mut self) -> bool {
+        let group = match self.groups.back_mut() {
+            Some(g) => g,
+            None => return false,
+        };
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => group.key.decompress(),
+        };
+        let state = match group.deltas.pop() {
+            Some(delta) => {
+                let mut state = delta.decompress();
+                state.xor(&key);
+                self.key = Some(key);
+                state
+            }
+            None => {
+                self.groups.pop_back();
+                key
+            }
+        };
+        state.make_current();
+        self.frame = 0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{ArrayField, Field};
+
+    #[test]
+    fn step_back() {
+        let mut counter = Field::new("Rewind::counter", 0u32);
+        let mut ram = ArrayField::new("Rewind::ram", 0u8, 1024);
+        let mut rw = Rewind::new(2, 20);
+
+        // 60 frames, with snapshots at frames 2, 4, ... 60. The 21st
+        // snapshot (frame 42) drops the first group (16 snapshots).
+        for _ in 0..60 {
+            *counter += 1;
+            ram[*counter as usize] = *counter as u8;
+            rw.end_frame();
+        }
+        assert_eq!(rw.len(), 14);
+
+        // Keyframes and deltas are restored, going backwards.
+        for frame in (34..=60).rev().step_by(2) {
+            assert!(rw.step_back());
+            assert_eq!(*counter, frame);
+            assert_eq!(ram[frame as usize], frame as u8);
+            assert_eq!(ram[frame as usize + 1], 0);
+        }
+        assert_eq!(rw.len(), 0);
+        assert!(!rw.step_back());
+        assert_eq!(*counter, 34);
+    }
+
+    #[test]
+    fn resume_after_rewind() {
+        let mut counter = Field::new("Rewind::counter", 0u32);
+        let mut rw = Rewind::new(1, 100);
+
+        for _ in 0..10 {
+            *counter += 1;
+            rw.end_frame();
+        }
+        for _ in 0..3 {
+            rw.step_back();
+        }
+        assert_eq!(*counter, 8);
+
+        // New snapshots continue from the rewound state.
+        *counter = 100;
+        rw.end_frame();
+        assert!(rw.step_back());
+        assert_eq!(*counter, 100);
+        assert!(rw.step_back());
+        assert_eq!(*counter, 7);
+    }
+}
@@ -0,0 +1,214 @@
+//! Run-ahead: hide the input lag of the emulated software.
+//!
+//! Most games react to an input a few frames after polling it. With
+//! run-ahead, the emulator keeps running a few frames ahead of the "real"
+//! one, and displays the last of them, so that the reaction appears on the
+//! screen earlier. Frames emulated ahead are speculative, as they assume that
+//! the input doesn't change; when it does, the emulation rolls back to the
+//! savestate of the last real frame, and runs again with the new input.
+//! Like their audio, the effects of speculative frames outside of the
+//! emulation are held back by the producer until the frame becomes real (see
+//! [`OutputProducer::set_speculative`](../trait.OutputProducer.html#method.set_speculative)).
+//!
+//! This only works if the emulation is deterministic (see
+//! [`OutputProducer::deterministic`](../trait.OutputProducer.html#method.deterministic)),
+//! and if savestates are fast enough to be taken at every frame (see
+//! [`RunAhead::check_performance`](struct.RunAhead.html#method.check_performance)).
+
+use super::OutputProducer;
+use crate::gfx::{GfxBufferMutLE, Rgb888};
+use crate::snd::{OwnedSndBuffer, SampleFormat};
+use crate::state::{CurrentState, State};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Maximum number of frames that can be emulated ahead.
+pub const MAX_RUN_AHEAD: usize = 4;
+
+// Fraction of the duration of a frame that can be spent taking and restoring
+// savestates, in the worst case (a rollback at every frame).
+const STATE_BUDGET: f64 = 0.25;
+
+/// Run an OutputProducer a few frames ahead (see the module documentation).
+pub struct RunAhead<SF: SampleFormat> {
+    frames: usize,
+    real: Option<State>, // state after the last real frame
+    ahead: VecDeque<(State, OwnedSndBuffer<SF>)>, // frames emulated ahead (state and audio)
+}
+
+impl<SF: SampleFormat> RunAhead<SF> {
+    /// Create a run-ahead of the specified number of frames (up to
+    /// MAX_RUN_AHEAD).
+    pub fn new(frames: usize) -> Self {
+        assert!(
+            (1..=MAX_RUN_AHEAD).contains(&frames),
+            "invalid number of run-ahead frames"
+        );
+        RunAhead {
+            frames,
+            real: None,
+            ahead: VecDeque::with_capacity(frames),
+        }
+    }
+
+    /// Return the number of frames emulated ahead.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Check that savestates of the current emulator can be taken and
+    /// restored at the specified framerate, leaving enough time for the
+    /// emulation itself. Returns an error describing the problem otherwise.
+    pub fn check_performance(&self, fps: isize) -> Result<(), String> {
+        const SAMPLES: u32 = 4;
+        let start = Instant::now();
+        for _ in 0..SAMPLES {
+            let state = CurrentState().clone();
+            state.make_current();
+        }
+        let elapsed = start.elapsed() / SAMPLES;
+
+        // On a rollback, the real frame and all the frames ahead are
+        // saved, and one state is restored.
+        let cost = elapsed * (self.frames as u32 + 2);
+        let budget = Duration::from_nanos((1e9 * STATE_BUDGET / fps.max(1) as f64) as u64);
+        if cost > budget {
+            return Err(format!(
+                "savestates are too slow for run-ahead ({:?} per frame, budget {:?})",
+                cost, budget
+            ));
+        }
+        Ok(())
+    }
+
+    /// Emulate a frame. screen receives the frame emulated ahead, while
+    /// sound receives the audio of the real frame (as the audio of
+    /// speculative frames cannot be taken back). input_changed must be true
+    /// if the input changed since the previous call, so that the frames
+    /// emulated ahead are discarded.
+    pub fn render_frame<P: OutputProducer<AudioSampleFormat = SF>>(
+        &mut self,
+        producer: &mut P,
+        screen: &mut GfxBufferMutLE<Rgb888>,
+        sound: &mut OwnedSndBuffer<SF>,
+        input_changed: bool,
+    ) {
+        if input_changed || self.ahead.len() < self.frames {
+            // Roll back to the last real frame, and emulate the next one
+            // with the new input.
+            if let Some(real) = self.real.take() {
+                real.make_current();
+            }
+            self.ahead.clear();
+            producer.discard_speculative_frames();
+            producer.render_frame(screen, &mut sound.buf_mut());
+            self.real = Some(CurrentState().clone());
+        } else {
+            // The input didn't change, so the first frame emulated ahead
+            // becomes the next real frame.
+            let (state, snd) = self.ahead.pop_front().unwrap();
+            self.real = Some(state);
+            *sound = snd;
+            producer.commit_speculative_frame();
+        }
+
+        producer.set_speculative(true);
+        while self.ahead.len() < self.frames {
+            let mut snd = OwnedSndBuffer::with_capacity(sound.count());
+            producer.render_frame(screen, &mut snd.buf_mut());
+            self.ahead.push_back((CurrentState().clone(), snd));
+        }
+        producer.set_speculative(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::OwnedGfxBufferLE;
+    use crate::input::InputManager;
+    use crate::snd::{SndBufferMut, S16_MONO};
+    use crate::state::Field;
+
+    // A fake emulator: every frame advances a counter (part of the state) by
+    // 1 plus the current input (not part of the state), and outputs it as
+    // audio. It also reports the value of each frame to the outside, holding
+    // it back in speculative frames.
+    struct Counter {
+        value: Field<i16>,
+        input: i16,
+        shown: i16, // value of the last emulated frame (as displayed)
+        speculative: bool,
+        held: VecDeque<i16>,
+        reported: Vec<i16>,
+    }
+
+    impl OutputProducer for Counter {
+        type AudioSampleFormat = S16_MONO;
+
+        fn input_manager(&mut self) -> Option<&mut InputManager> {
+            None
+        }
+
+        fn render_frame(
+            &mut self,
+            _video: &mut GfxBufferMutLE<Rgb888>,
+            audio: &mut SndBufferMut<S16_MONO>,
+        ) {
+            *self.value += 1 + self.input;
+            self.shown = *self.value;
+            audio.set_sample(0, 0, *self.value);
+            if self.speculative {
+                self.held.push_back(*self.value);
+            } else {
+                self.reported.push(*self.value);
+            }
+        }
+
+        fn set_speculative(&mut self, speculative: bool) {
+            self.speculative = speculative;
+        }
+
+        fn commit_speculative_frame(&mut self) {
+            self.reported.push(self.held.pop_front().unwrap());
+        }
+
+        fn discard_speculative_frames(&mut self) {
+            self.held.clear();
+        }
+    }
+
+    #[test]
+    fn rollback() {
+        let mut producer = Counter {
+            value: Field::new("Counter::value", 0),
+            input: 0,
+            shown: 0,
+            speculative: false,
+            held: VecDeque::new(),
+            reported: vec![],
+        };
+        let mut screen = OwnedGfxBufferLE::<Rgb888>::new(1, 1);
+        let mut ra = RunAhead::new(2);
+
+        let mut frame = |producer: &mut Counter, input: Option<i16>| {
+            if let Some(input) = input {
+                producer.input = input;
+            }
+            let mut sound = OwnedSndBuffer::<S16_MONO>::with_capacity(1);
+            ra.render_frame(producer, &mut screen.buf_mut(), &mut sound, input.is_some());
+            (sound.buf().get_sample(0, 0), producer.shown)
+        };
+
+        // Audio follows the real frames, and the screen is 2 frames ahead.
+        assert_eq!(frame(&mut producer, None), (1, 3));
+        assert_eq!(frame(&mut producer, None), (2, 4));
+        // A change of input rolls back to the last real frame.
+        assert_eq!(frame(&mut producer, Some(10)), (13, 35));
+        assert_eq!(frame(&mut producer, None), (24, 46));
+
+        // Only the real frames were reported, each once.
+        assert_eq!(producer.reported, vec![1, 2, 13, 24]);
+    }
+}
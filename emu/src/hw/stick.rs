@@ -0,0 +1,136 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// Shape of the gate limiting the travel of the emulated analog stick.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum StickGate {
+    Circle,  // raw circular mapping of the host stick
+    Octagon, // octagonal gate of the original controller
+}
+
+impl StickGate {
+    pub(crate) const ALL: [StickGate; 2] = [StickGate::Circle, StickGate::Octagon];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            StickGate::Circle => "Circle",
+            StickGate::Octagon => "Octagon",
+        }
+    }
+}
+
+/// Response curve: how the deflection of the host stick maps to the
+/// deflection of the emulated stick.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum StickResponse {
+    Linear,
+    Quadratic, // finer control near the center
+    Cubic,
+}
+
+impl StickResponse {
+    pub(crate) const ALL: [StickResponse; 3] = [
+        StickResponse::Linear,
+        StickResponse::Quadratic,
+        StickResponse::Cubic,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            StickResponse::Linear => "Linear",
+            StickResponse::Quadratic => "Quadratic",
+            StickResponse::Cubic => "Cubic",
+        }
+    }
+
+    fn apply(self, m: f32) -> f32 {
+        match self {
+            StickResponse::Linear => m,
+            StickResponse::Quadratic => m * m,
+            StickResponse::Cubic => m * m * m,
+        }
+    }
+}
+
+// Ratio between the maximum value of each axis on the diagonals and on the
+// axes, for the octagonal gate: the original controller reaches about 80
+// on the axes, and 70 on both axes on the diagonals. A circular gate would
+// stop at 56 on the diagonals, which many games don't expect.
+const DIAGONAL: f32 = 0.875;
+
+/// StickConfig describes how a host analog stick is mapped to an emulated
+/// one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct StickConfig {
+    pub gate: StickGate,
+    pub response: StickResponse,
+    pub range: f32,    // maximum value on the axes (emulated units)
+    pub deadzone: f32, // fraction of the host stick travel ignored
+}
+
+impl Default for StickConfig {
+    fn default() -> Self {
+        StickConfig {
+            gate: StickGate::Octagon,
+            response: StickResponse::Linear,
+            range: 80.0,
+            deadzone: 0.1,
+        }
+    }
+}
+
+impl StickConfig {
+    /// Map the position of the host stick (each axis in -1..1, positive Y is
+    /// up) to the position of the emulated stick (in emulated units).
+    pub(crate) fn map(&self, x: f32, y: f32) -> (f32, f32) {
+        let mag = (x * x + y * y).sqrt();
+        let dz = self.deadzone.clamp(0.0, 0.95);
+        if mag <= dz {
+            return (0.0, 0.0);
+        }
+        let m = self.response.apply(((mag - dz) / (1.0 - dz)).min(1.0));
+        let (ux, uy) = (x / mag, y / mag);
+        let r = self.gate_radius(ux, uy);
+        (ux * r * m, uy * r * m)
+    }
+
+    /// Like map, but return the values of the emulated analog inputs.
+    pub(crate) fn map_input(&self, x: f32, y: f32) -> (i16, i16) {
+        let (x, y) = self.map(x, y);
+        let conv = |v: f32| (v.round().clamp(-128.0, 127.0) as i16) << 8;
+        (conv(x), conv(y))
+    }
+
+    /// Return the outline of the gate (in emulated units), as a closed
+    /// polygon.
+    pub(crate) fn gate(&self) -> Vec<(f32, f32)> {
+        let steps = match self.gate {
+            StickGate::Circle => 32,
+            StickGate::Octagon => 8,
+        };
+        (0..steps)
+            .map(|i| {
+                let a = i as f32 * 2.0 * std::f32::consts::PI / steps as f32;
+                let (ux, uy) = (a.cos(), a.sin());
+                let r = self.gate_radius(ux, uy);
+                (ux * r, uy * r)
+            })
+            .collect()
+    }
+
+    // Distance of the gate from the center, in the specified direction
+    // (unit vector).
+    fn gate_radius(&self, ux: f32, uy: f32) -> f32 {
+        let c = self.range;
+        match self.gate {
+            StickGate::Circle => c,
+            StickGate::Octagon => {
+                // By symmetry, consider only the first octant, where the
+                // gate is the edge between (c,0) and (d,d).
+                let d = c * DIAGONAL;
+                let (ax, ay) = (ux.abs(), uy.abs());
+                let (ax, ay) = if ax >= ay { (ax, ay) } else { (ay, ax) };
+                c * d / (d * ax + (c - d) * ay)
+            }
+        }
+    }
+}
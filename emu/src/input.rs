@@ -1,5 +1,15 @@
+//! Abstract input devices (joysticks, mice, etc.), as seen by the emulated
+//! hardware. The frontend maps host inputs into
+//! [`InputEvent`](enum.InputEvent.html)s, that are processed by an
+//! [`InputManager`](struct.InputManager.html).
+
 use indexmap::map::IndexMap;
 
+use std::collections::VecDeque;
+
+mod movie;
+pub use self::movie::InputMovie;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InputKind {
     Other,
@@ -22,6 +32,7 @@ pub enum InputKind {
 pub enum InputDeviceKind {
     Joystick,
     Mouse,
+    Keyboard, // inputs are named after the host keys (eg: "A", "Return")
     Other,
 }
 
@@ -51,7 +62,7 @@ impl Input {
     pub fn new_digital(name: &str, kind: InputKind, custom_id: usize) -> Input {
         Input {
             name: name.into(),
-            kind: kind,
+            kind,
             value: InputValue::Digital(false),
             prev: InputValue::Digital(false),
             custom_id,
@@ -61,7 +72,7 @@ impl Input {
     pub fn new_analog(name: &str, kind: InputKind, custom_id: usize) -> Input {
         Input {
             name: name.into(),
-            kind: kind,
+            kind,
             value: InputValue::Analog(0),
             prev: InputValue::Analog(0),
             custom_id,
@@ -71,7 +82,7 @@ impl Input {
     pub fn new_coordinate(name: &str, kind: InputKind, custom_id: usize) -> Input {
         Input {
             name: name.into(),
-            kind: kind,
+            kind,
             value: InputValue::Coordinate(0x7FFF),
             prev: InputValue::Coordinate(0x7FFF),
             custom_id,
@@ -121,6 +132,7 @@ pub struct InputDevice {
     name: String,
     kind: InputDeviceKind,
     inputs: IndexMap<String, Input>,
+    #[allow(dead_code)]
     active: bool,
 }
 
@@ -174,8 +186,16 @@ pub struct InputManager {
     devices: IndexMap<String, InputDevice>,
     events: Vec<(usize, InputEvent)>,
     curframe: usize,
+
+    // Input latency measurement: frame of the oldest input change not yet
+    // polled by the emulated software, and the last measured latencies.
+    pending_since: Option<usize>,
+    latencies: VecDeque<usize>,
 }
 
+/// Number of latency measurements kept by InputManager.
+const LATENCY_HISTORY: usize = 64;
+
 impl InputManager {
     pub fn new(devices: Vec<InputDevice>) -> InputManager {
         InputManager {
@@ -185,6 +205,8 @@ impl InputManager {
                 .collect(),
             events: Vec::with_capacity(256),
             curframe: 0,
+            pending_since: None,
+            latencies: VecDeque::with_capacity(LATENCY_HISTORY),
         }
     }
 
@@ -227,12 +249,39 @@ impl InputManager {
             }
         };
         self.events.push((self.curframe, event));
+        if self.pending_since.is_none() {
+            self.pending_since = Some(self.curframe);
+        }
+    }
+
+    /// Notify that the emulated software has just polled the inputs (eg: by
+    /// reading the controller state). This is used to measure the input latency,
+    /// that is the number of frames between an input event being processed,
+    /// and the software reading it.
+    pub fn mark_polled(&mut self) {
+        if let Some(since) = self.pending_since.take() {
+            if self.latencies.len() == LATENCY_HISTORY {
+                self.latencies.pop_front();
+            }
+            self.latencies.push_back(self.curframe - since);
+        }
+    }
+
+    /// Return the last measured input latencies (in frames), oldest first.
+    pub fn latencies(&self) -> &VecDeque<usize> {
+        &self.latencies
     }
 
     pub fn end_frame(&mut self) {
         self.curframe += 1;
     }
 
+    /// Add a device (eg: a peripheral connected after creation). A device
+    /// with the same name is replaced.
+    pub fn add_device(&mut self, device: InputDevice) {
+        self.devices.insert(device.name.clone(), device);
+    }
+
     /// Get a reference to an [InputDevice](struct.InputDevice.html)
     /// by name (if it exists).
     pub fn device(&self, name: &str) -> Option<&InputDevice> {
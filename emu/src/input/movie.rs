@@ -0,0 +1,160 @@
+use super::{InputManager, InputValue};
+
+/// An InputMovie is a frame-by-frame recording of all the inputs of an
+/// [InputManager](struct.InputManager.html). Each frame holds the value of
+/// every input at the beginning of that frame; columns are ordered like
+/// devices and inputs in the InputManager (insertion order).
+///
+/// A movie can be played back by applying each frame to the InputManager
+/// before the frame is emulated; since the movie can be edited, this allows
+/// to precisely control the inputs (eg: for tool-assisted runs).
+#[derive(Clone, Default)]
+pub struct InputMovie {
+    columns: Vec<(String, String)>, // (device name, input name)
+    frames: Vec<Vec<InputValue>>,
+}
+
+impl InputMovie {
+    /// Create an empty movie recording all the inputs defined in the
+    /// specified InputManager.
+    pub fn new(im: &InputManager) -> InputMovie {
+        let mut columns = Vec::new();
+        for dev in im.devices.values() {
+            for inp in dev.inputs.values() {
+                columns.push((dev.name.clone(), inp.name.clone()));
+            }
+        }
+        InputMovie {
+            columns,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Return the number of recorded frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Return the recorded inputs, as pairs of (device name, input name).
+    pub fn columns(&self) -> &[(String, String)] {
+        &self.columns
+    }
+
+    /// Return the values of all inputs in the specified frame.
+    pub fn frame(&self, frame: usize) -> Option<&[InputValue]> {
+        self.frames.get(frame).map(|f| &f[..])
+    }
+
+    /// Record the current value of all inputs as the specified frame.
+    /// If there is a gap between the last recorded frame and this one,
+    /// the gap is filled by repeating the last recorded frame.
+    pub fn record(&mut self, frame: usize, im: &InputManager) {
+        let values: Vec<InputValue> = self
+            .columns
+            .iter()
+            .map(|(dev, inp)| im.devices[dev].inputs[inp].value)
+            .collect();
+        if frame >= self.frames.len() {
+            let fill = self
+                .frames
+                .last()
+                .cloned()
+                .unwrap_or_else(|| values.clone());
+            self.frames.resize(frame, fill);
+            self.frames.push(values);
+        } else {
+            self.frames[frame] = values;
+        }
+    }
+
+    /// Apply the inputs of the specified frame to the InputManager,
+    /// overriding the current values. Returns false if the frame was not
+    /// recorded.
+    pub fn apply(&self, frame: usize, im: &mut InputManager) -> bool {
+        let values = match self.frames.get(frame) {
+            Some(values) => values,
+            None => return false,
+        };
+        for ((dev, inp), val) in self.columns.iter().zip(values) {
+            let inp = im.devices.get_mut(dev).and_then(|d| d.inputs.get_mut(inp));
+            if let Some(inp) = inp {
+                inp.prev = inp.value;
+                inp.value = *val;
+            }
+        }
+        true
+    }
+
+    /// Change the value of an input in a recorded frame.
+    pub fn set(&mut self, frame: usize, column: usize, value: InputValue) {
+        if let Some(values) = self.frames.get_mut(frame) {
+            values[column] = value;
+        }
+    }
+
+    /// Remove all frames starting from the specified one.
+    pub fn truncate(&mut self, frame: usize) {
+        self.frames.truncate(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Input, InputDevice, InputDeviceKind, InputEvent, InputKind};
+    use super::*;
+
+    fn digital(v: InputValue) -> bool {
+        match v {
+            InputValue::Digital(v) => v,
+            _ => panic!("not a digital input"),
+        }
+    }
+
+    fn manager() -> InputManager {
+        InputManager::new(vec![InputDevice::new(
+            "pad",
+            InputDeviceKind::Joystick,
+            vec![
+                Input::new_digital("A", InputKind::Button1, 0),
+                Input::new_analog("X", InputKind::Horizontal, 1),
+            ],
+        )])
+    }
+
+    #[test]
+    fn record_and_apply() {
+        let mut im = manager();
+        let mut movie = InputMovie::new(&im);
+        assert_eq!(movie.columns().len(), 2);
+
+        movie.record(0, &im);
+        im.process_event(InputEvent::Digital("pad".into(), "A".into(), true));
+        movie.record(3, &im);
+        assert_eq!(movie.len(), 4);
+
+        // The gap is filled with the last recorded frame
+        assert!(!digital(movie.frame(2).unwrap()[0]));
+        assert!(digital(movie.frame(3).unwrap()[0]));
+
+        assert!(movie.apply(1, &mut im));
+        assert_eq!(
+            im.device("pad").unwrap().input("A").unwrap().digital(),
+            Some(false)
+        );
+
+        movie.set(1, 1, InputValue::Analog(-40));
+        assert!(movie.apply(1, &mut im));
+        assert_eq!(
+            im.device("pad").unwrap().input("X").unwrap().analog(),
+            Some(-40)
+        );
+
+        movie.truncate(2);
+        assert_eq!(movie.len(), 2);
+        assert!(!movie.apply(2, &mut im));
+    }
+}
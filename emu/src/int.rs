@@ -1,3 +1,6 @@
+//! Helpers to manipulate integers of different sizes (sign extension,
+//! splitting into halves, hex formatting).
+
 pub trait Numerics: Sized {
     type Unsigned: Numerics;
 
@@ -0,0 +1,219 @@
+//! Interrupt lines and interrupt controllers.
+//!
+//! An [`InterruptController`](struct.InterruptController.html) collects a
+//! set of [`IrqLine`](struct.IrqLine.html)s (up to 32), each of which can be
+//! level or edge triggered, and combines them through a mask into a single
+//! output (eg: the interrupt input of a CPU).
+//!
+//! Raised interrupts and the pending mask are reported to the debugger, so
+//! that interrupt storms or lost interrupts can be spotted in the timeline.
+
+use crate::dbg::Tracer;
+use crate::state::Field;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// How an interrupt line sets its pending bit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IrqTrigger {
+    /// The pending bit follows the level of the line.
+    Level,
+    /// The pending bit is set on a rising edge of the line, and stays set
+    /// until it is acknowledged.
+    Edge,
+}
+
+/// Description of an interrupt line.
+#[derive(Clone, Debug)]
+pub struct IrqLine {
+    pub name: &'static str,
+    pub trigger: IrqTrigger,
+}
+
+impl IrqLine {
+    pub fn level(name: &'static str) -> IrqLine {
+        IrqLine {
+            name,
+            trigger: IrqTrigger::Level,
+        }
+    }
+
+    pub fn edge(name: &'static str) -> IrqLine {
+        IrqLine {
+            name,
+            trigger: IrqTrigger::Edge,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, Serialize, Deserialize)]
+struct IrqState {
+    input: u32,   // Current level of each line
+    pending: u32, // Pending interrupts
+    mask: u32,    // Enabled interrupts
+    raised: u32,  // Lines that became pending since the last trace
+}
+
+/// A set of interrupt lines, with a pending bit and a mask bit per line.
+pub struct InterruptController {
+    name: &'static str,
+    lines: Vec<IrqLine>,
+    tracks: Vec<String>, // Debugger track names (one per line)
+    signal: String,      // Debugger signal name for the pending mask
+    state: Field<IrqState>,
+}
+
+impl InterruptController {
+    /// Create an interrupt controller with the specified lines. The index
+    /// of each line in the vector is its number. All lines are initially
+    /// masked.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if more than 32 lines are specified.
+    pub fn new(name: &'static str, lines: Vec<IrqLine>) -> InterruptController {
+        assert!(lines.len() <= 32, "too many interrupt lines");
+        InterruptController {
+            name,
+            tracks: lines
+                .iter()
+                .map(|l| format!("{}: {} IRQ", name, l.name))
+                .collect(),
+            signal: format!("{}: pending IRQs", name),
+            lines,
+            state: Field::new(
+                &format!("InterruptController::{}", name),
+                IrqState::default(),
+            ),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    pub fn lines(&self) -> &[IrqLine] {
+        &self.lines
+    }
+
+    /// Change the level of a line.
+    pub fn set_line(&mut self, line: usize, level: bool) {
+        let bit = 1u32 << line;
+        let state = &mut *self.state;
+        let rising = level && state.input & bit == 0;
+        if level {
+            state.input |= bit;
+        } else {
+            state.input &= !bit;
+        }
+
+        let pending = match self.lines[line].trigger {
+            IrqTrigger::Level => level,
+            IrqTrigger::Edge => rising || state.pending & bit != 0,
+        };
+        if pending && state.pending & bit == 0 {
+            state.raised |= bit;
+        }
+        if pending {
+            state.pending |= bit;
+        } else {
+            state.pending &= !bit;
+        }
+    }
+
+    /// Raise and immediately lower a line. For edge-triggered lines, this
+    /// makes the interrupt pending until it is acknowledged; for
+    /// level-triggered lines, it has no lasting effect.
+    pub fn pulse(&mut self, line: usize) {
+        self.set_line(line, true);
+        self.set_line(line, false);
+    }
+
+    /// Acknowledge an interrupt. The pending bit of an edge-triggered line
+    /// is cleared; the pending bit of a level-triggered line is cleared only
+    /// if the line is low.
+    pub fn ack(&mut self, line: usize) {
+        let bit = 1u32 << line;
+        let state = &mut *self.state;
+        if self.lines[line].trigger == IrqTrigger::Edge || state.input & bit == 0 {
+            state.pending &= !bit;
+        }
+    }
+
+    /// Return the mask of pending interrupts (irrespective of the mask).
+    pub fn pending(&self) -> u32 {
+        self.state.pending
+    }
+
+    /// Return the mask of enabled interrupts.
+    pub fn mask(&self) -> u32 {
+        self.state.mask
+    }
+
+    pub fn set_mask(&mut self, mask: u32) {
+        self.state.mask = mask;
+    }
+
+    /// Return true if at least one enabled interrupt is pending, that is
+    /// if the output of the controller is asserted.
+    pub fn asserted(&self) -> bool {
+        self.state.pending & self.state.mask != 0
+    }
+
+    /// Report raised interrupts and the pending mask to the debugger. It
+    /// should be called once per scanline.
+    pub fn trace(&mut self, tracer: &Tracer) {
+        let state = &mut *self.state;
+        for (idx, track) in self.tracks.iter().enumerate() {
+            if state.raised & (1 << idx) != 0 {
+                tracer.trace_activity(track);
+            }
+        }
+        state.raised = 0;
+        tracer.trace_signal(&self.signal, state.pending as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller(name: &'static str) -> InterruptController {
+        InterruptController::new(name, vec![IrqLine::level("A"), IrqLine::edge("B")])
+    }
+
+    #[test]
+    fn level() {
+        let mut irq = controller("level");
+        irq.set_line(0, true);
+        assert_eq!(irq.pending(), 0b01);
+        assert!(!irq.asserted());
+        irq.set_mask(0b01);
+        assert!(irq.asserted());
+
+        // Ack has no effect while the line is high
+        irq.ack(0);
+        assert_eq!(irq.pending(), 0b01);
+        irq.set_line(0, false);
+        assert_eq!(irq.pending(), 0);
+        assert!(!irq.asserted());
+    }
+
+    #[test]
+    fn edge() {
+        let mut irq = controller("edge");
+        irq.set_mask(0b11);
+        irq.pulse(1);
+        assert_eq!(irq.pending(), 0b10);
+        assert!(irq.asserted());
+
+        // Keeping the line high does not retrigger after ack
+        irq.set_line(1, true);
+        irq.ack(1);
+        irq.set_line(1, true);
+        assert_eq!(irq.pending(), 0);
+        irq.set_line(1, false);
+        irq.set_line(1, true);
+        assert_eq!(irq.pending(), 0b10);
+    }
+}
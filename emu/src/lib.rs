@@ -1,20 +1,107 @@
-#![feature(box_syntax)]
-#![feature(step_trait)]
-#![feature(specialization)]
-#![feature(optin_builtin_traits)]
-#![feature(pin)]
-#![feature(thread_local)]
-#![feature(integer_atomics)]
+//! `emu` is a collection of libraries to write videogame emulators.
+//!
+//! It provides the building blocks that are shared by most emulators, so that
+//! an emulator project can focus on emulating the actual hardware:
+//!
+//!  * [`bus`](bus/index.html): memory buses, memory areas and hardware
+//!    registers, with a derive macro (`DeviceLE` / `DeviceBE`) to describe
+//!    the memory map of a device.
+//!  * [`state`](state/index.html): save states, implemented as a global
+//!    arena of serializable fields.
+//!  * [`sync`](sync/index.html): the scheduler that runs the emulated
+//!    subsystems in lockstep, synchronized on the video beam.
+//!  * [`gfx`](gfx/index.html) and [`snd`](snd/index.html): generic graphic and
+//!    sound buffers, parametrized on pixel and sample formats.
+//!  * [`clock`](clock/index.html): clock domains, with exact conversion
+//!    of cycles between devices running at different frequencies.
+//!  * [`dma`](dma/index.html): a generic DMA engine, with optional timing
+//!    and debugger tracing, for the DMA controllers of emulated devices.
+//!  * [`irq`](irq/index.html): level and edge triggered interrupt lines,
+//!    combined by interrupt controllers.
+//!  * [`time`](time/index.html): host time and random seeds as seen by the
+//!    emulated system, with a deterministic implementation.
+//!  * [`corruptor`](corruptor/index.html): random bit flips in emulated
+//!    memory, to fuzz the emulated devices.
+//!  * [`trigger`](trigger/index.html): conditions on emulated memory,
+//!    evaluated each frame (eg: for achievements or speedrun splits).
+//!  * [`livesplit`](livesplit/index.html): commands to the LiveSplit timer,
+//!    for auto-splitting speedruns.
+//!  * [`input`](input/index.html): abstract input devices, decoupled from
+//!    the host input.
+//!  * [`paranoid`](paranoid/index.html): optional invariant checks in the
+//!    emulated devices, to catch emulation bugs early.
+//!  * [`dbg`](dbg/index.html): an interactive debugger (with tracing,
+//!    breakpoints and views for common hardware) that emulators can extend.
+//!  * [`hw`](hw/index.html): the frontend, which opens a window, plays
+//!    audio, reads host inputs and drives the emulation loop.
+//!
+//! ## Features
+//!
+//!  * `debugger`: the imgui-based views of the debugger. Without it, the
+//!    tracing core (breakpoints, watchpoints, pause points) is still
+//!    available.
+//!  * `frontend` (default): the SDL2/OpenGL frontend in the
+//!    [`hw`](hw/index.html) module; it implies `debugger`.
+//!
+//! Building with `default-features = false` produces a core without any
+//! native windowing dependency, which can be embedded in servers, fuzzers or
+//! WASM builds.
+//!
+//! ## Example
+//!
+//! A minimal memory map, with a RAM mirrored over a larger address range:
+//!
+//! ```
+//! use emu::bus::le::{Bus, BusFill, Mem, MemFlags};
+//!
+//! let logger = slog::Logger::root(slog::Discard, slog::o!());
+//! let mut bus = Bus::new(logger);
+//! let ram = Mem::new("ram", 0x800, MemFlags::default());
+//! bus.map_mem(0x0000, 0x1FFF, &ram, BusFill::Mirror).unwrap();
+//!
+//! bus.write::<u16>(0x0010, 0x1234);
+//! assert_eq!(bus.read::<u16>(0x0810), 0x1234);
+//! ```
+//!
+//! ## Versioning
+//!
+//! This crate follows semantic versioning. Until 1.0, a bump of the minor
+//! version signals a breaking change of the public API; anything marked as
+//! `#[doc(hidden)]` is an implementation detail (used by the derive macros)
+//! and is not covered by these guarantees.
+#![doc(html_root_url = "https://docs.rs/emu/0.0.1")]
+// Stylistic lints that don't fit the low-level APIs of the crate.
+#![allow(
+    clippy::module_inception,
+    clippy::type_complexity,
+    clippy::len_without_is_empty,
+    clippy::missing_safety_doc,
+    clippy::too_many_arguments,
+    clippy::new_ret_no_self,
+    clippy::should_implement_trait,
+    clippy::wrong_self_convention,
+    clippy::enum_variant_names,
+    clippy::extra_unused_lifetimes
+)]
 
 pub mod bus;
+pub mod clock;
+pub mod corruptor;
 pub mod dbg;
+pub mod dma;
+pub mod errors;
 pub mod fp;
 pub mod gfx;
 pub mod hw;
 pub mod input;
 pub mod int;
+pub mod irq;
+pub mod livesplit;
 pub mod log;
 pub mod memint;
+pub mod paranoid;
 pub mod snd;
 pub mod state;
 pub mod sync;
+pub mod time;
+pub mod trigger;
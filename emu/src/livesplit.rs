@@ -0,0 +1,138 @@
+//! LiveSplit auto-splitting.
+//!
+//! [`LiveSplit`](struct.LiveSplit.html) sends commands to the timer of
+//! [LiveSplit](https://livesplit.org) with the text protocol of its server,
+//! so that the splits of a speedrun follow the emulated game (typically, when
+//! a memory [`Trigger`](../trigger/struct.Trigger.html) fires). Commands are
+//! sent either through TCP, to the LiveSplit Server component (port 16834 by
+//! default), or through a named pipe (`\\.\pipe\LiveSplit` on Windows).
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+
+/// A command of the LiveSplit server protocol.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    StartTimer,
+    StartOrSplit,
+    Split,
+    Unsplit,
+    SkipSplit,
+    Pause,
+    Resume,
+    Reset,
+    PauseGameTime,
+    UnpauseGameTime,
+}
+
+impl Command {
+    const ALL: [(&'static str, Command); 10] = [
+        ("starttimer", Command::StartTimer),
+        ("startorsplit", Command::StartOrSplit),
+        ("split", Command::Split),
+        ("unsplit", Command::Unsplit),
+        ("skipsplit", Command::SkipSplit),
+        ("pause", Command::Pause),
+        ("resume", Command::Resume),
+        ("reset", Command::Reset),
+        ("pausegametime", Command::PauseGameTime),
+        ("unpausegametime", Command::UnpauseGameTime),
+    ];
+
+    /// Return the command as sent to LiveSplit.
+    pub fn as_str(self) -> &'static str {
+        Command::ALL.iter().find(|(_, c)| *c == self).unwrap().0
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Command, String> {
+        Command::ALL
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|&(_, cmd)| cmd)
+            .ok_or_else(|| {
+                let names: Vec<_> = Command::ALL.iter().map(|(name, _)| *name).collect();
+                format!(
+                    "invalid LiveSplit command: {:?} (expected one of: {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A connection to LiveSplit.
+pub struct LiveSplit {
+    conn: Box<dyn Write>,
+}
+
+impl LiveSplit {
+    /// Send commands through the specified connection.
+    pub fn new<W: Write + 'static>(conn: W) -> LiveSplit {
+        LiveSplit {
+            conn: Box::new(conn),
+        }
+    }
+
+    /// Connect to LiveSplit at the specified address: `HOST:PORT` for the
+    /// LiveSplit Server component, or the path of a named pipe.
+    pub fn connect(addr: &str) -> io::Result<LiveSplit> {
+        if addr.contains('/') || addr.contains('\\') {
+            Ok(LiveSplit::new(OpenOptions::new().write(true).open(addr)?))
+        } else {
+            let stream = TcpStream::connect(addr)?;
+            stream.set_nodelay(true)?;
+            Ok(LiveSplit::new(stream))
+        }
+    }
+
+    pub fn send(&mut self, cmd: Command) -> io::Result<()> {
+        write!(self.conn, "{}\r\n", cmd)?;
+        self.conn.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    #[test]
+    fn parse() {
+        assert_eq!("split".parse(), Ok(Command::Split));
+        assert_eq!("starttimer".parse(), Ok(Command::StartTimer));
+        assert_eq!(Command::UnpauseGameTime.to_string(), "unpausegametime");
+        assert!("start".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn send() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let mut ls = LiveSplit::connect(&addr).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        ls.send(Command::StartTimer).unwrap();
+        ls.send(Command::Split).unwrap();
+        drop(ls);
+
+        let lines: Vec<_> = BufReader::new(server)
+            .split(b'\n')
+            .map(|l| l.unwrap())
+            .collect();
+        assert_eq!(lines, vec![b"starttimer\r".to_vec(), b"split\r".to_vec()]);
+    }
+}
@@ -17,13 +17,13 @@ use std::time::Instant;
 /// bounds expressed by this trait need to satisfied for a function
 /// to be used in timestamp formatting.
 pub trait ThreadSafeTimestampFn:
-    Fn(&mut io::Write) -> io::Result<()> + Send + Sync + UnwindSafe + RefUnwindSafe + 'static
+    Fn(&mut dyn io::Write) -> io::Result<()> + Send + Sync + UnwindSafe + RefUnwindSafe + 'static
 {
 }
 
 impl<F> ThreadSafeTimestampFn for F
 where
-    F: Fn(&mut io::Write) -> io::Result<()> + Send + Sync,
+    F: Fn(&mut dyn io::Write) -> io::Result<()> + Send + Sync,
     F: UnwindSafe + RefUnwindSafe + 'static,
     F: ?Sized,
 {
@@ -41,7 +41,7 @@ pub trait LogRecordPrinter {
     fn print_header(
         &mut self,
         record: &Record,
-        fn_timestamp: &ThreadSafeTimestampFn<Output = io::Result<()>>,
+        fn_timestamp: &dyn ThreadSafeTimestampFn<Output = io::Result<()>>,
     ) -> io::Result<()>;
     fn print_kv<K: fmt::Display, V: fmt::Display>(&mut self, k: K, v: V) -> io::Result<()>;
     fn finish(self) -> io::Result<()>;
@@ -86,7 +86,7 @@ impl<W: io::Write> LogRecordPrinter for ColorRecordPrinter<W> {
     fn print_header(
         &mut self,
         record: &Record,
-        fn_timestamp: &ThreadSafeTimestampFn<Output = io::Result<()>>,
+        fn_timestamp: &dyn ThreadSafeTimestampFn<Output = io::Result<()>>,
     ) -> io::Result<()> {
         let mut rd = CountingWriter::new(&mut self.buf);
 
@@ -151,12 +151,12 @@ impl<W: io::Write> LogRecordPrinter for ColorRecordPrinter<W> {
         if self.buf.is_empty() {
             return Ok(());
         }
-        write!(&mut self.buf, "\n")?;
+        writeln!(&mut self.buf)?;
 
         let mut io = self
             .io
             .lock()
-            .map_err(|_| io::Error::new(io::ErrorKind::Other, "mutex locking error"))?;
+            .map_err(|_| io::Error::other("mutex locking error"))?;
 
         io.write_all(&self.buf)?;
         self.buf.clear();
@@ -173,8 +173,8 @@ struct Serializer<'a, RP: LogRecordPrinter> {
 impl<'a, RP: LogRecordPrinter> Serializer<'a, RP> {
     fn new(printer: &'a mut RP, reverse: bool) -> Self {
         Serializer {
-            printer: printer,
-            reverse: reverse,
+            printer,
+            reverse,
             stack: vec![],
         }
     }
@@ -295,7 +295,7 @@ struct CountingWriter<W: io::Write> {
 
 impl<W: io::Write> CountingWriter<W> {
     fn new(w: W) -> Self {
-        CountingWriter { w: w, count: 0 }
+        CountingWriter { w, count: 0 }
     }
 
     fn count(&self) -> usize {
@@ -305,9 +305,8 @@ impl<W: io::Write> CountingWriter<W> {
 
 impl<W: io::Write> io::Write for CountingWriter<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.w.write(buf).map(|n| {
+        self.w.write(buf).inspect(|&n| {
             self.count += n;
-            n
         })
     }
 
@@ -318,7 +317,6 @@ impl<W: io::Write> io::Write for CountingWriter<W> {
     fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
         self.w.write_all(buf).map(|_| {
             self.count += buf.len();
-            ()
         })
     }
 }
@@ -328,7 +326,7 @@ where
     RP: LogPrinter,
 {
     printer: RP,
-    fn_timestamp: Box<ThreadSafeTimestampFn<Output = io::Result<()>>>,
+    fn_timestamp: Box<dyn ThreadSafeTimestampFn<Output = io::Result<()>>>,
     use_original_order: bool,
 }
 
@@ -337,7 +335,7 @@ where
     RP: LogPrinter,
 {
     printer: RP,
-    fn_timestamp: Box<ThreadSafeTimestampFn<Output = io::Result<()>>>,
+    fn_timestamp: Box<dyn ThreadSafeTimestampFn<Output = io::Result<()>>>,
     original_order: bool,
 }
 
@@ -386,7 +384,7 @@ impl<RP: LogPrinter> LogDrain<RP> {
     pub fn new(p: RP) -> LogDrainBuilder<RP> {
         let now = Instant::now();
         LogDrainBuilder {
-            fn_timestamp: Box::new(move |w: &mut io::Write| -> io::Result<()> {
+            fn_timestamp: Box::new(move |w: &mut dyn io::Write| -> io::Result<()> {
                 write!(w, "[{}]", now.elapsed().as_secs())
             }),
             printer: p,
@@ -396,7 +394,7 @@ impl<RP: LogPrinter> LogDrain<RP> {
 
     fn format_full(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
         self.printer.with_record(record, |mut printer| {
-            printer.print_header(&record, &*self.fn_timestamp)?;
+            printer.print_header(record, &*self.fn_timestamp)?;
             {
                 let mut serializer = Serializer::new(&mut printer, self.use_original_order);
                 record.kv().serialize(record, &mut serializer)?;
@@ -18,7 +18,7 @@ use serde::{Deserialize, Serialize};
 /// so that it can be used in a very efficient [`EnumMap`](struct.EnumMap.html)
 /// (which boils down to a 4-element array) in case there is a need for a
 /// runtime data structure indexed by access size.
-#[derive(Debug, Enum, Copy, Clone)]
+#[derive(Debug, Enum, Copy, Clone, PartialEq, Eq)]
 pub enum AccessSize {
     Size8,
     Size16,
@@ -78,7 +78,7 @@ impl MemInt for u8 {
     const SIZE_LOG: usize = 0;
     #[inline(always)]
     fn truncate_from(v: u64) -> u8 {
-        return v as u8;
+        v as u8
     }
     #[inline(always)]
     fn endian_read_from<O: ByteOrder>(buf: &[u8]) -> Self {
@@ -100,7 +100,7 @@ impl MemInt for u16 {
     const SIZE_LOG: usize = 1;
     #[inline(always)]
     fn truncate_from(v: u64) -> u16 {
-        return v as u16;
+        v as u16
     }
     #[inline(always)]
     fn endian_read_from<O: ByteOrder>(buf: &[u8]) -> Self {
@@ -122,7 +122,7 @@ impl MemInt for u32 {
     const SIZE_LOG: usize = 2;
     #[inline(always)]
     fn truncate_from(v: u64) -> u32 {
-        return v as u32;
+        v as u32
     }
     #[inline(always)]
     fn endian_read_from<O: ByteOrder>(buf: &[u8]) -> Self {
@@ -144,7 +144,7 @@ impl MemInt for u64 {
     const SIZE_LOG: usize = 3;
     #[inline(always)]
     fn truncate_from(v: u64) -> u64 {
-        return v;
+        v
     }
     #[inline(always)]
     fn endian_read_from<O: ByteOrder>(buf: &[u8]) -> Self {
@@ -225,7 +225,7 @@ impl ByteOrderCombiner for BigEndian {
         let shift = off * 8;
         let full: u64 = (!S::zero()).into();
         let mask = U::truncate_from(full) << shift;
-        return (mask, shift);
+        (mask, shift)
     }
 
     #[inline(always)]
@@ -0,0 +1,98 @@
+//! Invariant checking for emulated devices.
+//!
+//! In paranoid mode, devices verify assumptions that correct software never
+//! violates on the real hardware, but which are easily broken by bugs in the
+//! emulator itself: writes to read-only bits of a register, DMA transfers
+//! running past the end of a memory area, and so on. The checks are meant to
+//! catch emulation bugs early while developing new devices, and are disabled
+//! by default as some of them are expensive.
+//!
+//! Devices report violations with [`paranoid_check!`](../macro.paranoid_check.html);
+//! violations are queued (per thread) and collected by the debugger once per
+//! scanline, which stops emulation with a `TraceEvent::GenericBreak`. Without
+//! a debugger, the emulator should periodically call
+//! [`take_violations`](fn.take_violations.html) and log them.
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+
+/// A violation of an invariant, reported by a device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub device: &'static str,
+    pub msg: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.device, self.msg)
+    }
+}
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static VIOLATIONS: RefCell<Vec<Violation>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Enable or disable paranoid mode for the current thread.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.with(|e| e.set(enabled));
+}
+
+/// Return true if paranoid mode is enabled for the current thread.
+#[inline(always)]
+pub fn enabled() -> bool {
+    ENABLED.with(|e| e.get())
+}
+
+/// Report a violation. Prefer [`paranoid_check!`](../macro.paranoid_check.html),
+/// which only builds the message when paranoid mode is enabled.
+pub fn report(device: &'static str, msg: String) {
+    VIOLATIONS.with(|v| v.borrow_mut().push(Violation { device, msg }));
+}
+
+/// Return the violations reported since the last call, in order.
+pub fn take_violations() -> Vec<Violation> {
+    VIOLATIONS.with(|v| v.replace(Vec::new()))
+}
+
+/// Check an invariant in paranoid mode, reporting a violation (with a
+/// `format!`-like message) if it does not hold. The condition is not
+/// evaluated at all when paranoid mode is disabled.
+///
+/// ```
+/// use emu::paranoid_check;
+///
+/// emu::paranoid::set_enabled(true);
+/// let pc = 0x1004u32;
+/// paranoid_check!("RSP", pc & !0xFFC == 0, "PC outside IMEM: {:x}", pc);
+/// assert_eq!(emu::paranoid::take_violations().len(), 1);
+/// ```
+#[macro_export]
+macro_rules! paranoid_check {
+    ($device:expr, $cond:expr, $($arg:tt)+) => {
+        if $crate::paranoid::enabled() && !$cond {
+            $crate::paranoid::report($device, format!($($arg)+));
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() {
+        paranoid_check!("dev", false, "disabled");
+        assert_eq!(take_violations(), vec![]);
+
+        set_enabled(true);
+        paranoid_check!("dev", 1 + 1 == 2, "holds");
+        paranoid_check!("dev", 1 + 1 == 3, "broken: {}", 3);
+        let v = take_violations();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].to_string(), "dev: broken: 3");
+        assert_eq!(take_violations(), vec![]);
+        set_enabled(false);
+    }
+}
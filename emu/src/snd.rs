@@ -1,3 +1,6 @@
+//! Sound buffers, parametrized on the sample format (see
+//! [`SampleFormat`](trait.SampleFormat.html)).
+
 use byteorder::{BigEndian, ByteOrder, LittleEndian, NativeEndian};
 use num::PrimInt;
 use num_traits::{WrappingAdd, WrappingSub};
@@ -49,9 +52,9 @@ pub trait SampleInt: PrimInt + WrappingAdd + WrappingSub + Send {
     /// use emu::snd::SampleInt;
     ///
     /// fn main() {
-    /// 	let x: u8 = 128;
-    /// 	let y: i16 = x.sconv();
-    /// 	assert_eq!(y, 0i16);
+    ///     let x: u8 = 128;
+    ///     let y: i16 = x.sconv();
+    ///     assert_eq!(y, 0i16);
     /// }
     /// ```
     fn sconv<S2: SampleInt>(self) -> S2 {
@@ -180,18 +183,17 @@ pub struct SndBufferMut<'a, SF: SampleFormat> {
 
 impl<SF: SampleFormat> OwnedSndBuffer<SF> {
     pub fn new(buf: Vec<u8>) -> Result<Self, &'static str> {
-        if buf.len() % SF::frame_size() != 0 {
+        if !buf.len().is_multiple_of(SF::frame_size()) {
             return Err("invalid sound buffer size (not multiple of frame size");
         }
-        return Ok(Self {
+        Ok(Self {
             buf,
             phantom: PhantomData,
-        });
+        })
     }
 
     pub fn with_capacity(nframes: usize) -> Self {
-        let mut v = Vec::new();
-        v.resize(nframes * SF::frame_size(), 0u8);
+        let v = vec![0; nframes * SF::frame_size()];
         Self {
             buf: v,
             phantom: PhantomData,
@@ -345,7 +347,7 @@ where
     fn as_ref(&self) -> &[SF::SAMPLE] {
         unsafe {
             ::std::slice::from_raw_parts(
-                ::std::mem::transmute(&self.buf[0]),
+                &self.buf[0] as *const u8 as *const SF::SAMPLE,
                 self.count() * SF::CHANNELS,
             )
         }
@@ -363,7 +365,7 @@ where
     fn as_mut(&mut self) -> &mut [SF::SAMPLE] {
         unsafe {
             ::std::slice::from_raw_parts_mut(
-                ::std::mem::transmute(&mut self.buf[0]),
+                &mut self.buf[0] as *mut u8 as *mut SF::SAMPLE,
                 self.count() * SF::CHANNELS,
             )
         }
@@ -375,7 +377,7 @@ impl<'a, SF: SampleFormat<ORDER = NativeEndian>> SndBuffer<'a, SF> {
         Self {
             buf: unsafe {
                 ::std::slice::from_raw_parts(
-                    ::std::mem::transmute(&buf[0]),
+                    &buf[0] as *const SF::SAMPLE as *const u8,
                     buf.len() * SF::SAMPLE::SIZE,
                 )
             },
@@ -389,7 +391,7 @@ impl<'a, SF: SampleFormat<ORDER = NativeEndian>> SndBufferMut<'a, SF> {
         Self {
             buf: unsafe {
                 ::std::slice::from_raw_parts_mut(
-                    ::std::mem::transmute(&mut buf[0]),
+                    &mut buf[0] as *mut SF::SAMPLE as *mut u8,
                     buf.len() * SF::SAMPLE::SIZE,
                 )
             },
@@ -398,6 +400,59 @@ impl<'a, SF: SampleFormat<ORDER = NativeEndian>> SndBufferMut<'a, SF> {
     }
 }
 
+/// A resampler for interleaved stereo 16-bit samples, with linear
+/// interpolation.
+///
+/// Each call stretches a block of input frames to the length of the output
+/// block (eg: the samples emitted by an emulated DAC during a video frame,
+/// into the fixed number of frames played by the host for each frame). The
+/// last input frame is kept, so that the interpolation continues across
+/// blocks without discontinuities.
+#[derive(Default)]
+pub struct StereoResampler {
+    last: [i16; 2],
+}
+
+impl StereoResampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resample src into dst (both made of interleaved stereo frames). If src
+    /// is empty, the last frame is repeated.
+    pub fn process(&mut self, src: &[i16], dst: &mut [i16]) {
+        let nsrc = src.len() / 2;
+        let ndst = dst.len() / 2;
+        if ndst == 0 {
+            return;
+        }
+
+        // Frame 0 is the last frame of the previous block, so that the last
+        // output frame falls exactly on the last input frame.
+        let last = self.last;
+        let frame = |idx: usize| {
+            if idx == 0 {
+                last
+            } else {
+                [src[idx * 2 - 2], src[idx * 2 - 1]]
+            }
+        };
+        for (i, d) in dst.chunks_mut(2).enumerate() {
+            let pos = (((i + 1) * nsrc) << 16) / ndst;
+            let (idx, frac) = (pos >> 16, (pos & 0xFFFF) as i64);
+            let a = frame(idx);
+            let b = if frac != 0 { frame(idx + 1) } else { a };
+            for ch in 0..2 {
+                let (a, b) = (a[ch] as i64, b[ch] as i64);
+                d[ch] = (a + (((b - a) * frac) >> 16)) as i16;
+            }
+        }
+        if nsrc != 0 {
+            self.last = [src[nsrc * 2 - 2], src[nsrc * 2 - 1]];
+        }
+    }
+}
+
 #[allow(non_camel_case_types)]
 pub struct sf<S: SampleInt, O: ByteOrder, C: typenum::Unsigned> {
     phantom: PhantomData<(S, O, C)>,
@@ -524,6 +579,26 @@ mod tests {
         assert_eq!(dst.get_sample(7, 1) as u16, 0x7F00);
     }
 
+    #[test]
+    fn stereo_resampler() {
+        let mut rs = StereoResampler::new();
+        let mut dst = [0i16; 8];
+        rs.process(&[100, 200, 300, 400], &mut dst);
+        assert_eq!(dst, [50, 100, 100, 200, 200, 300, 300, 400]);
+
+        // Continue from the last frame of the previous block.
+        let mut dst = [0i16; 2];
+        rs.process(&[-300, -400, 500, 600], &mut dst);
+        assert_eq!(dst, [500, 600]);
+        let mut dst = [0i16; 4];
+        rs.process(&[700, 800], &mut dst);
+        assert_eq!(dst, [600, 700, 700, 800]);
+
+        // An empty block repeats the last frame.
+        rs.process(&[], &mut dst);
+        assert_eq!(dst, [700, 800, 700, 800]);
+    }
+
     #[test]
     fn refcasting() {
         let mut sbuf = OwnedSndBuffer::<U16LE_STEREO>::with_capacity(4);
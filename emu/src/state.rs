@@ -24,7 +24,22 @@
 //! binds it to the global state. Fields acts as smart pointers to the actual
 //! content.
 //!
-//! Example: TODO.
+//! Example:
+//!
+//! ```
+//! use emu::state::{CurrentState, Field};
+//!
+//! let mut counter = Field::new("example::counter", 0u32);
+//! *counter = 1;
+//!
+//! // Take a snapshot of the current state, and then modify the field
+//! let snapshot = CurrentState().clone();
+//! *counter = 2;
+//!
+//! // Restoring the snapshot transparently restores the field
+//! snapshot.make_current();
+//! assert_eq!(*counter, 1);
+//! ```
 //!
 //! Since a state is defined implicitly as the aggregation of all fields, fields
 //! are not meant to be instantiated and freed at runtime while the emulator
@@ -130,6 +145,9 @@
 //! previously serialized states.
 //!
 
+// failure_derive defines its impls inside named consts.
+#![allow(unknown_lints, non_local_definitions)]
+
 use crate::memint::{ByteOrderCombiner, MemInt};
 
 use futures::*;
@@ -139,6 +157,7 @@ use serde::{Deserialize, Serialize};
 use serde_bytes;
 
 use failure::{Error, Fail};
+use std::any::TypeId;
 use std::cell::Cell;
 use std::cell::{RefCell, RefMut};
 use std::collections::BTreeMap;
@@ -148,6 +167,7 @@ use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::ptr;
 use std::rc::Rc;
+use std::slice;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::thread;
 
@@ -162,17 +182,31 @@ enum SerializationFailure {
     InvalidVersion { version: u32 },
 }
 
-// Global per-thread state. Notice that we use #[thread_local] rather than
-// thread_local!() as it's much faster at accessing the state, and also allows
-// non-scoped access (that is, without `with`).
-#[thread_local]
-static mut STATE: Option<RefCell<State>> = None;
+// Global per-thread state.
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::new());
+}
+
+// Return a pointer to the current thread's state. The state lives as long as
+// the thread itself, so the pointer can be used outside of `with` (that is,
+// with a 'static lifetime) by the thread that obtained it.
+#[inline(always)]
+fn state_ptr() -> *const RefCell<State> {
+    STATE.with(|s| s as *const RefCell<State>)
+}
 
 // ID used to cache pointers within the global state. Any time the global State
 // changes in a way that makes all previous pointer invalid, this counter is
 // incremented.
 static STATE_ID: AtomicU32 = AtomicU32::new(0);
 
+/// Return an ID that changes every time the current state is replaced
+/// (eg: when loading a savestate, or rewinding). Caches of data derived from
+/// the state (eg: decoded code) can compare it to know when to flush.
+pub fn state_id() -> u32 {
+    STATE_ID.load(Ordering::Relaxed)
+}
+
 /// Return a mutable reference to the current [`State`](struct.State.html) (for
 /// the current thread).
 ///
@@ -184,12 +218,7 @@ static STATE_ID: AtomicU32 = AtomicU32::new(0);
 /// cannot be moved across threads as well.
 #[allow(non_snake_case)]
 pub fn CurrentState() -> RefMut<'static, State> {
-    unsafe {
-        if STATE.is_none() {
-            STATE = Some(RefCell::new(State::new()));
-        }
-        STATE.as_ref().unwrap().borrow_mut()
-    }
+    unsafe { (*state_ptr()).borrow_mut() }
 }
 
 // Like CurrentState, but does not enforce exclusive mutable access to State.
@@ -198,11 +227,7 @@ pub fn CurrentState() -> RefMut<'static, State> {
 #[allow(non_snake_case)]
 #[inline(always)]
 unsafe fn UnsafeCurrentState() -> &'static mut State {
-    // NOTE: we don't use unwrap() here because it doesn't always get inlined.
-    if let Some(S) = STATE.as_ref() {
-        return &mut *S.as_ptr();
-    }
-    panic!("UnsafeCurrentState called before State initialization");
+    &mut *(*state_ptr()).as_ptr()
 }
 
 /// A `Field` is an object that is part of the emulator state. It is a lightweight
@@ -220,9 +245,8 @@ pub struct Field<F: Copy + Serialize + Deserialize<'static>> {
 }
 
 // A field refers implicitly to the current thread's State, thus we cannot
-// Send it across threads.
-impl<F> !Send for Field<F> {}
-impl<F> !Sync for Field<F> {}
+// Send it across threads. This is enforced by the raw pointer in the cache,
+// which makes Field !Send and !Sync.
 
 impl<F: 'static + Copy + Serialize + Deserialize<'static>> Field<F> {
     /// Create a new Field with the specified name and initial value.
@@ -293,8 +317,8 @@ impl<F: Copy + Serialize + Deserialize<'static>> Default for Field<F> {
     // is performed.
     fn default() -> Self {
         Self {
-            offset: usize::max_value(),
-            cache: Cell::new((u32::max_value(), ptr::null_mut())),
+            offset: usize::MAX,
+            cache: Cell::new((u32::MAX, ptr::null_mut())),
         }
     }
 }
@@ -342,12 +366,9 @@ impl<F: 'static + Copy + Serialize + Deserialize<'static>> DerefMut for Field<F>
 /// content (aliasing). For this reason, cloning is marked as unsafe.
 pub struct EndianField<F: Copy + Serialize + Deserialize<'static> + MemInt, O: ByteOrderCombiner> {
     offset: usize,
-    phantom: PhantomData<(F, O)>,
+    phantom: PhantomData<(F, O, *const ())>, // *const () makes it !Send and !Sync
 }
 
-impl<F, O> !Send for EndianField<F, O> {}
-impl<F, O> !Sync for EndianField<F, O> {}
-
 impl<F, O> EndianField<F, O>
 where
     F: 'static + Copy + Serialize + Deserialize<'static> + MemInt,
@@ -418,7 +439,7 @@ impl<F: Copy + Serialize + MemInt, O: ByteOrderCombiner> Default for EndianField
     // is performed.
     fn default() -> Self {
         Self {
-            offset: usize::max_value(),
+            offset: usize::MAX,
             phantom: PhantomData,
         }
     }
@@ -440,7 +461,7 @@ impl<F: Copy + Serialize + MemInt, O: ByteOrderCombiner> Default for EndianField
 pub struct ArrayField<F: Copy + Serialize + Deserialize<'static>> {
     offset: usize,
     len: usize,
-    phantom: PhantomData<F>,
+    phantom: PhantomData<(F, *const ())>, // *const () makes it !Send and !Sync
 }
 
 impl<F: 'static + Copy + Serialize + Deserialize<'static>> ArrayField<F> {
@@ -512,11 +533,6 @@ impl<F: 'static + Copy + Serialize + Deserialize<'static>> ArrayField<F> {
     }
 }
 
-// A field refers implicitly to the current thread's State, thus we cannot
-// Send it across threads.
-impl<F> !Send for ArrayField<F> {}
-impl<F> !Sync for ArrayField<F> {}
-
 impl<F: Copy + Serialize + Deserialize<'static>> Default for ArrayField<F> {
     /// Default returns an invalid ArrayField, that will cause a panic when used.
     /// It can be used as placeholder in structs until proper initialization
@@ -524,7 +540,7 @@ impl<F: Copy + Serialize + Deserialize<'static>> Default for ArrayField<F> {
     fn default() -> Self {
         Self {
             len: 0,
-            offset: usize::max_value(),
+            offset: usize::MAX,
             phantom: PhantomData,
         }
     }
@@ -555,9 +571,10 @@ type Deser<'de> = rmp_serde::Deserializer<rmp_serde::decode::ReadReader<&'de [u8
 // for each field.
 struct FieldInfo {
     name: String,
-    serialize: Box<for<'de> Fn(&mut Ser<'de>, &State) -> Result<(), rmp_serde::encode::Error>>,
-    deserialize:
-        Box<for<'de> FnMut(&mut Deser<'de>, &mut State) -> Result<(), rmp_serde::decode::Error>>,
+    serialize: Box<dyn for<'de> Fn(&mut Ser<'de>, &State) -> Result<(), rmp_serde::encode::Error>>,
+    deserialize: Box<
+        dyn for<'de> FnMut(&mut Deser<'de>, &mut State) -> Result<(), rmp_serde::decode::Error>,
+    >,
 }
 
 impl FieldInfo {
@@ -598,69 +615,22 @@ impl FieldInfo {
     where
         F: 'static + Copy + Serialize + Deserialize<'static>,
     {
-        // Use trait specialization to special-case ArrayField<u8> using
-        // serde_bytes. This speeds us serialization of large memory buffers
-        // a lot, because they're handled through a fast-path that just copies
-        // the whole buffer, rather than iterating element by element
-        // like for a slice of any other type.
-        trait BufferSerializer: Serialize {
-            fn buffer_serialize<'de>(
-                &self,
-                ser: &mut Ser<'de>,
-            ) -> Result<(), rmp_serde::encode::Error>;
-        }
-
-        // Generic version
-        impl<F: Serialize> BufferSerializer for F {
-            default fn buffer_serialize<'de>(
-                &self,
-                ser: &mut Ser<'de>,
-            ) -> Result<(), rmp_serde::encode::Error> {
-                self.serialize(ser)
+        // Special-case ArrayField<u8> using serde_bytes. This speeds up
+        // serialization of large memory buffers a lot, because they're handled
+        // through a fast-path that just copies the whole buffer, rather than
+        // iterating element by element like for a slice of any other type.
+        fn as_bytes<F: 'static>(buf: &[F]) -> Option<&[u8]> {
+            if TypeId::of::<F>() == TypeId::of::<u8>() {
+                Some(unsafe { slice::from_raw_parts(buf.as_ptr() as *const u8, buf.len()) })
+            } else {
+                None
             }
         }
-
-        // Specialization for [u8]
-        impl BufferSerializer for &[u8] {
-            fn buffer_serialize<'de>(
-                &self,
-                ser: &mut Ser<'de>,
-            ) -> Result<(), rmp_serde::encode::Error> {
-                serde_bytes::Bytes::new(self).serialize(ser)
-            }
-        }
-
-        trait BufferDeserializer {
-            fn buffer_deserialize<'de>(
-                self,
-                deser: &mut Deser<'de>,
-            ) -> Result<(), rmp_serde::decode::Error>;
-        }
-
-        // Generic version
-        impl<'r, F: 'static + Copy> BufferDeserializer for &'r mut [F]
-        where
-            F: serde::de::Deserialize<'static>,
-        {
-            default fn buffer_deserialize<'de>(
-                self,
-                deser: &mut Deser<'de>,
-            ) -> Result<(), rmp_serde::decode::Error> {
-                let buf: Vec<F> = serde::Deserialize::deserialize(deser)?;
-                (*self).copy_from_slice(&buf[..]);
-                Ok(())
-            }
-        }
-
-        // Specialization for [u8]
-        impl<'r> BufferDeserializer for &mut [u8] {
-            fn buffer_deserialize<'de>(
-                self,
-                deser: &mut Deser<'de>,
-            ) -> Result<(), rmp_serde::decode::Error> {
-                let buf: Vec<u8> = serde_bytes::deserialize(deser)?;
-                (*self).copy_from_slice(&buf[..]);
-                Ok(())
+        fn as_bytes_mut<F: 'static>(buf: &mut [F]) -> Option<&mut [u8]> {
+            if TypeId::of::<F>() == TypeId::of::<u8>() {
+                Some(unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len()) })
+            } else {
+                None
             }
         }
 
@@ -669,10 +639,25 @@ impl FieldInfo {
         Self {
             name: name.to_owned(),
             serialize: Box::new(move |ser, state| {
-                field1.as_ref_with_state(state).buffer_serialize(ser)
+                let buf = field1.as_ref_with_state(state);
+                match as_bytes(buf) {
+                    Some(bytes) => serde_bytes::Bytes::new(bytes).serialize(ser),
+                    None => buf.serialize(ser),
+                }
             }),
             deserialize: Box::new(move |deser, state| {
-                field2.as_mut_with_state(state).buffer_deserialize(deser)
+                let buf = field2.as_mut_with_state(state);
+                match as_bytes_mut(buf) {
+                    Some(bytes) => {
+                        let data: Vec<u8> = serde_bytes::deserialize(deser)?;
+                        bytes.copy_from_slice(&data[..]);
+                    }
+                    None => {
+                        let data: Vec<F> = serde::Deserialize::deserialize(deser)?;
+                        buf.copy_from_slice(&data[..]);
+                    }
+                }
+                Ok(())
             }),
         }
     }
@@ -721,7 +706,7 @@ impl State {
     where
         F: 'static + Copy + Serialize + Deserialize<'static>,
     {
-        if name == "" {
+        if name.is_empty() {
             panic!("empty name for state field");
         }
         if self.info.borrow().contains_key(name) {
@@ -730,7 +715,7 @@ impl State {
 
         let f = Field {
             offset: self.alloc_raw(mem::size_of::<F>(), mem::align_of::<F>()),
-            cache: Cell::new((u32::max_value(), ptr::null_mut())),
+            cache: Cell::new((u32::MAX, ptr::null_mut())),
         };
         self.info
             .borrow_mut()
@@ -743,7 +728,7 @@ impl State {
         F: 'static + Copy + Serialize + Deserialize<'static> + MemInt,
         O: 'static + ByteOrderCombiner,
     {
-        if name == "" {
+        if name.is_empty() {
             panic!("empty name for state field");
         }
         if self.info.borrow().contains_key(name) {
@@ -754,7 +739,7 @@ impl State {
         let offset = self.alloc_raw(size, mem::align_of::<F>());
 
         let f = EndianField {
-            offset: offset,
+            offset,
             phantom: PhantomData,
         };
         self.info
@@ -767,7 +752,7 @@ impl State {
     where
         F: 'static + Copy + Serialize + Deserialize<'static>,
     {
-        if name == "" {
+        if name.is_empty() {
             panic!("empty name for state field");
         }
         if self.info.borrow().contains_key(name) {
@@ -778,8 +763,8 @@ impl State {
         let offset = self.alloc_raw(size, mem::align_of::<F>());
 
         let f = ArrayField {
-            offset: offset,
-            len: len,
+            offset,
+            len,
             phantom: PhantomData,
         };
         if serialize {
@@ -809,6 +794,17 @@ impl State {
         CompressedState::new(self)
     }
 
+    /// XOR the contents of the state with another state with the same
+    /// fields. XOR-ing a state with its base produces a delta that is mostly
+    /// zeros (so it compresses well), and XOR-ing the delta again with the
+    /// base gives back the original state.
+    pub(crate) fn xor(&mut self, base: &State) {
+        assert_eq!(self.data.len(), base.data.len(), "xor of different states");
+        for (d, b) in self.data.iter_mut().zip(base.data.iter()) {
+            *d ^= *b;
+        }
+    }
+
     /// Serialize the state into a persistence format that can be written
     /// to disk and reloaded in different process. It relies on Serde-based
     /// serialization.
@@ -822,7 +818,7 @@ impl State {
 
         // Write the header
         let header = "EMUSTATE\x00";
-        writer.write(header.as_bytes())?;
+        writer.write_all(header.as_bytes())?;
 
         // Serialize the whole state like a struct. Each `Field` is a field
         // of this struct, using its name as name of the struct field.
@@ -833,59 +829,76 @@ impl State {
         ser.serialize_u32(self.info.borrow().len() as u32)?;
         for fi in self.info.borrow().values() {
             ser.serialize_str(&fi.name)?;
-            (*fi.serialize)(&mut ser, &self)?;
+            (*fi.serialize)(&mut ser, self)?;
         }
 
         // Compress the output
         use lz4::block::CompressionMode::*;
         let data = lz4::block::compress(&output, Some(HIGHCOMPRESSION(9)), true)?;
 
-        writer.write(&data)?;
+        writer.write_all(&data)?;
 
         Ok(())
     }
 
+    /// Return the names of the serialized fields starting with the specified
+    /// prefix, sorted.
+    pub fn field_names(&self, prefix: &str) -> Vec<String> {
+        self.info
+            .borrow()
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Format the fields whose name starts with the specified prefix (eg:
+    /// "Mi::" for all the registers of the Mi device) as text, one field per
+    /// line, sorted by name. Integers are shown in hex, and byte arrays (eg:
+    /// memories) are summarized with their size. This is meant for tests and
+    /// debugging.
+    pub fn dump(&self, prefix: &str) -> String {
+        let mut out = String::new();
+        for fi in self.info.borrow().values() {
+            if !fi.name.starts_with(prefix) {
+                continue;
+            }
+            let mut buf = Vec::new();
+            let value = (*fi.serialize)(&mut rmp_serde::Serializer::new_named(&mut buf), self)
+                .ok()
+                .and_then(|_| {
+                    rmp_serde::from_slice::<serde_json::Value>(&buf)
+                        .map(|v| dump_value(&v))
+                        .or_else(|_| {
+                            rmp_serde::from_slice::<serde_bytes::ByteBuf>(&buf)
+                                .map(|b| format!("[{} bytes]", b.len()))
+                        })
+                        .ok()
+                })
+                .unwrap_or_else(|| "[unknown]".to_owned());
+            out += &format!("{} = {}\n", fi.name, value);
+        }
+        out
+    }
+
     /// Deserialize into the current state.
     /// Notice that any field not present in the serialized state
     /// maintain their current value, and no error is returned. It is thus
     /// suggested to deserialize over a default initial state.
     pub fn deserialize<R: io::Read>(
         &mut self,
-        mut reader: R,
+        reader: R,
         wanted_magic: &str,
         wanted_version: u32,
     ) -> Result<(), Error> {
-        let mut header = vec![0u8; 9];
-        reader.read_exact(&mut header)?;
-        if header != "EMUSTATE\x00".as_bytes() {
-            return Err(SerializationFailure::InvalidFormat.into());
-        }
-
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf)?;
-        let dec = lz4::block::decompress(&buf, None)?;
-
+        let dec = decompress_serialized(reader)?;
         let mut de = rmp_serde::Deserializer::new(&dec[..]);
-
-        let magic: String = Deserialize::deserialize(&mut de)?;
-        if magic != wanted_magic {
-            return Err(SerializationFailure::InvalidMagic { magic }.into());
-        }
-
-        let version: u32 = Deserialize::deserialize(&mut de)?;
-        if version != wanted_version {
-            return Err(SerializationFailure::InvalidVersion { version }.into());
-        }
-
-        let num_fields: u32 = Deserialize::deserialize(&mut de)?;
+        let num_fields = deserialize_preamble(&mut de, wanted_magic, wanted_version)?;
         let info = self.info.clone(); // avoid borrowing self
         for _ in 0..num_fields {
             let fname: String = Deserialize::deserialize(&mut de)?;
-            match info.borrow_mut().get_mut(&fname) {
-                Some(fi) => {
-                    (*fi.deserialize)(&mut de, self)?;
-                }
-                None => {}
+            if let Some(fi) = info.borrow_mut().get_mut(&fname) {
+                (*fi.deserialize)(&mut de, self)?;
             };
         }
 
@@ -893,6 +906,87 @@ impl State {
     }
 }
 
+// Check the header of a serialized state, and return its decompressed data.
+fn decompress_serialized<R: io::Read>(mut reader: R) -> Result<Vec<u8>, Error> {
+    let mut header = vec![0u8; 9];
+    reader.read_exact(&mut header)?;
+    if header != "EMUSTATE\x00".as_bytes() {
+        return Err(SerializationFailure::InvalidFormat.into());
+    }
+
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    Ok(lz4::block::decompress(&buf, None)?)
+}
+
+// Check the magic string and the version of a serialized state, and return
+// the number of fields that follow.
+fn deserialize_preamble(
+    de: &mut Deser,
+    wanted_magic: &str,
+    wanted_version: u32,
+) -> Result<u32, Error> {
+    let magic: String = Deserialize::deserialize(&mut *de)?;
+    if magic != wanted_magic {
+        return Err(SerializationFailure::InvalidMagic { magic }.into());
+    }
+
+    let version: u32 = Deserialize::deserialize(&mut *de)?;
+    if version != wanted_version {
+        return Err(SerializationFailure::InvalidVersion { version }.into());
+    }
+
+    Ok(Deserialize::deserialize(&mut *de)?)
+}
+
+/// Read a byte array field (eg: a memory) from a state serialized with
+/// [`State::serialize`](struct.State.html#method.serialize), without
+/// deserializing the whole state. The fields of the state do not need to be
+/// defined, so this can be used by tools that inspect savestates without
+/// creating the emulated machine. Returns None if the field is missing.
+pub fn read_bytes_field<R: io::Read>(
+    reader: R,
+    wanted_magic: &str,
+    wanted_version: u32,
+    name: &str,
+) -> Result<Option<Vec<u8>>, Error> {
+    let dec = decompress_serialized(reader)?;
+    let mut de = rmp_serde::Deserializer::new(&dec[..]);
+    let num_fields = deserialize_preamble(&mut de, wanted_magic, wanted_version)?;
+    for _ in 0..num_fields {
+        let fname: String = Deserialize::deserialize(&mut de)?;
+        if fname == name {
+            return Ok(Some(serde_bytes::deserialize(&mut de)?));
+        }
+        serde::de::IgnoredAny::deserialize(&mut de)?;
+    }
+    Ok(None)
+}
+
+// Format a field value for State::dump.
+fn dump_value(v: &serde_json::Value) -> String {
+    use serde_json::Value;
+    match v {
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => format!("{:#x}", u),
+            (None, Some(i)) => format!("-{:#x}", i.wrapping_neg()),
+            _ => n.to_string(),
+        },
+        Value::Array(a) => format!(
+            "[{}]",
+            a.iter().map(dump_value).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Object(o) => format!(
+            "{{{}}}",
+            o.iter()
+                .map(|(k, v)| format!("{}: {}", k, dump_value(v)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        v => v.to_string(),
+    }
+}
+
 /// A compressed snapshot of a `State`, useful for in-process snapshotting.
 /// To be made current, it must be decompressed back into a [`State`](struct.State.html) using
 /// [`decompress()`](#method.decompress).
@@ -919,7 +1013,7 @@ impl CompressedState {
                 .build(&mut compressed)
                 .unwrap();
             io::copy(&mut &data[..], &mut enc).unwrap();
-            enc.finish();
+            enc.finish().1.unwrap();
 
             c.send(compressed).unwrap();
         });
@@ -991,22 +1085,22 @@ mod tests {
         }
 
         let mut f = Field::new("a", Foo { bar: 1, baz: 2 });
-        assert_eq!((*f).bar, 1);
-        assert_eq!((*f).baz, 2);
+        assert_eq!(f.bar, 1);
+        assert_eq!(f.baz, 2);
 
         let s1 = CurrentState().clone();
-        (*f).bar = 3;
-        (*f).baz = 4;
-        assert_eq!((*f).bar, 3);
-        assert_eq!((*f).baz, 4);
+        f.bar = 3;
+        f.baz = 4;
+        assert_eq!(f.bar, 3);
+        assert_eq!(f.baz, 4);
 
         let s2 = s1.make_current();
-        assert_eq!((*f).bar, 1);
-        assert_eq!((*f).baz, 2);
+        assert_eq!(f.bar, 1);
+        assert_eq!(f.baz, 2);
 
         s2.make_current();
-        assert_eq!((*f).bar, 3);
-        assert_eq!((*f).baz, 4);
+        assert_eq!(f.bar, 3);
+        assert_eq!(f.baz, 4);
     }
 
     #[test]
@@ -1152,6 +1246,24 @@ mod tests {
         assert_eq!(e[3], 3);
     }
 
+    #[test]
+    fn read_serialized_field() {
+        let _a = Field::new("a", 4u64);
+        let mut d = ArrayField::internal_new("x", 7u8, 4, true);
+        d[1] = 1;
+        let _e = ArrayField::internal_new("y", 7u8, 4, false);
+
+        let mut bin = Vec::new();
+        CurrentState().serialize(&mut bin, "test", 1).unwrap();
+
+        assert_eq!(
+            read_bytes_field(&bin[..], "test", 1, "x").unwrap(),
+            Some(vec![7, 1, 7, 7])
+        );
+        assert_eq!(read_bytes_field(&bin[..], "test", 1, "y").unwrap(), None);
+        assert!(read_bytes_field(&bin[..], "test", 2, "x").is_err());
+    }
+
     #[test]
     #[should_panic]
     fn double_state_borrow() {
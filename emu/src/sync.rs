@@ -1,7 +1,30 @@
+//! The scheduler of the emulator.
+//!
+//! A [`Sync`](struct.Sync.html) runs multiple [`Subsystem`](trait.Subsystem.html)s
+//! (CPUs, coprocessors, etc.) in lockstep, one video line at a time, and
+//! reports [`Event`](enum.Event.html)s for the beginning and the end of each
+//! frame and for each horizontal/vertical sync.
+//!
+//! Each subsystem runs in its own [`ClockDomain`](../clock/struct.ClockDomain.html);
+//! the scheduler converts cycles between domains exactly.
+//!
+//! [`Timer`](enum.Timer.html)s can be added to be notified at specific points
+//! of emulated time (eg: for automation), independently of the speed of the
+//! host. Their deadlines are part of the emulator state, so they stay in sync
+//! with the emulation across savestates and rewind.
+//!
+//! Subsystems signal each other through [`IrqLine`](struct.IrqLine.html)s:
+//! the level of the line is driven by one of them, and sampled by the other
+//! when it runs.
+
+use serde_derive::{Deserialize, Serialize};
 use slog::*;
+use std::panic::AssertUnwindSafe;
 
+use crate::clock::ClockDomain;
 use crate::dbg;
 use crate::int::Numerics;
+use crate::state::Field;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Event {
@@ -9,11 +32,69 @@ pub enum Event {
     EndFrame,
     HSync(usize, usize),
     VSync(usize, usize),
+    Timer(TimerId), // A timer expired
+}
+
+/// Identifier of a timer, returned by Sync::add_timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(u32);
+
+/// A timer in emulated time. Timers are checked at each synchronization
+/// point of the scheduler, so subsystems are stopped exactly at the
+/// requested cycle (in the main clock domain) before the timer is reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timer {
+    AtCycle(i64),     // Once, at the specified absolute cycle
+    EveryCycles(i64), // Periodically, every N cycles
+    EveryFrames(i64), // Periodically, at the end of every N frames
+}
+
+/// Maximum number of timers that can be pending at the same time.
+pub const MAX_TIMERS: usize = 16;
+
+// A timer scheduled by Sync: next expiration and period (in main cycles).
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct PendingTimer {
+    id: u32,
+    deadline: i64,
+    period: Option<i64>,
+}
+
+/// An interrupt line between two subsystems (eg: from an interrupt
+/// controller to a CPU). The driver sets the level, and the receiver gets a
+/// connected handle with [`connect`](#method.connect) and samples it when
+/// it polls its interrupts. The level is part of the emulator state.
+pub struct IrqLine {
+    level: Field<bool>,
+}
+
+impl IrqLine {
+    pub fn new(name: &str) -> IrqLine {
+        IrqLine {
+            level: Field::new(name, false),
+        }
+    }
+
+    /// Return another handle to the same line.
+    pub fn connect(&self) -> IrqLine {
+        // Only the driver changes the level, and the state is single-threaded.
+        IrqLine {
+            level: unsafe { self.level.clone() },
+        }
+    }
+
+    pub fn set(&mut self, level: bool) {
+        *self.level = level;
+    }
+
+    pub fn get(&self) -> bool {
+        *self.level
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Config {
-    pub main_clock: i64,
+    pub main_clock: ClockDomain,
     pub dot_clock_divider: i64,
     pub hdots: usize,
     pub vdots: usize,
@@ -43,7 +124,12 @@ pub trait Subsystem {
 
 pub trait SyncEmu {
     fn config(&self) -> Config;
-    fn subsystem(&self, idx: usize) -> Option<(&mut dyn Subsystem, i64)>;
+
+    /// Return the subsystem with the specified index, and the clock domain
+    /// it runs in. Subsystems are run in order of index, until None is
+    /// returned.
+    #[allow(clippy::mut_from_ref)] // subsystems are per-thread singletons
+    fn subsystem(&self, idx: usize) -> Option<(&mut dyn Subsystem, ClockDomain)>;
 }
 
 pub struct Sync<E: SyncEmu + 'static> {
@@ -52,12 +138,17 @@ pub struct Sync<E: SyncEmu + 'static> {
     logger: slog::Logger,
 
     current_sub: Option<usize>,
-    frames: i64,
-    cycles: i64,
     line_cycles: i64,
     frame_cycles: i64,
     frame_syncs: Vec<(i64, Event)>,
-    curr_frame: Option<(i64, usize)>,
+    next_timer_id: u32,
+
+    // Emulation position; these are part of the state so that loading a
+    // savestate resumes the frame from the correct point.
+    frames: Field<i64>,
+    cycles: Field<i64>,
+    curr_frame: Field<Option<(i64, usize)>>,
+    timers: Field<[Option<PendingTimer>; MAX_TIMERS]>,
 }
 
 impl<E: SyncEmu + 'static> Sync<E> {
@@ -66,23 +157,27 @@ impl<E: SyncEmu + 'static> Sync<E> {
             cfg: emu.config(),
             emu,
             logger,
-            frames: 0,
-            cycles: 0,
             current_sub: None,
             line_cycles: 0,
             frame_cycles: 0,
             frame_syncs: vec![],
-            curr_frame: None,
+            next_timer_id: 0,
+            frames: Field::new("Sync::frames", 0),
+            cycles: Field::new("Sync::cycles", 0),
+            curr_frame: Field::new("Sync::curr_frame", None),
+            timers: Field::new("Sync::timers", [None; MAX_TIMERS]),
         });
         s.calc();
         s
     }
 
     pub fn new_logger(&self) -> slog::Logger {
-        let sync2: *const Self = &*self;
-        let sync3: *const Self = &*self;
+        // The logger only reads through the pointers, so it cannot observe a
+        // broken state after a panic.
+        let sync2 = AssertUnwindSafe(self as *const Self);
+        let sync3 = AssertUnwindSafe(self as *const Self);
         self.logger.new(o!("pc" => slog::FnValue(move |_| {
-            let sync2 = unsafe { &*sync2 };
+            let sync2 = unsafe { &*sync2.0 };
             sync2.current_pc().map_or("[none]".to_owned(), |pc| {
                 if (pc as u32).sx64() == pc {
                     (pc as u32).hex()
@@ -92,7 +187,7 @@ impl<E: SyncEmu + 'static> Sync<E> {
             })
         }),
         "sub" => slog::FnValue(move |_| {
-            let sync3 = unsafe { &*sync3 };
+            let sync3 = unsafe { &*sync3.0 };
             sync3.current_sub().map_or("[none]", |(s,_)| s.name())
         }),
         ))
@@ -111,50 +206,134 @@ impl<E: SyncEmu + 'static> Sync<E> {
         for y in 0..self.cfg.vdots {
             let cycles = self.line_cycles * y as i64;
             let dot_clock_divider = self.cfg.dot_clock_divider;
-            self.frame_syncs.extend(self.cfg.hsyncs.iter().map(|x| {
-                (
-                    cycles + *x as i64 * dot_clock_divider as i64,
-                    Event::HSync(*x, y),
-                )
-            }))
+            self.frame_syncs.extend(
+                self.cfg
+                    .hsyncs
+                    .iter()
+                    .map(|x| (cycles + *x as i64 * dot_clock_divider, Event::HSync(*x, y))),
+            )
         }
         // Use stable sort to make sure that VSyncs events are
         // generated before HSyncs events (on first pixel)
         self.frame_syncs.sort_by_key(|k| k.0);
     }
 
-    fn current_sub(&self) -> Option<(&mut dyn Subsystem, i64)> {
+    fn current_sub(&self) -> Option<(&mut dyn Subsystem, ClockDomain)> {
         self.current_sub.map(|idx| self.emu.subsystem(idx).unwrap())
     }
 
     pub fn current_pc(&self) -> Option<u64> {
-        self.current_sub().map_or(None, |(s, _)| s.pc())
+        self.current_sub().and_then(|(s, _)| s.pc())
     }
 
     pub fn reset(&mut self) {
-        self.frames = 0;
-        self.cycles = 0;
-        self.curr_frame = None;
+        *self.frames = 0;
+        *self.cycles = 0;
+        *self.curr_frame = None;
     }
 
     pub fn frames(&self) -> i64 {
-        self.frames
+        *self.frames
     }
 
     pub fn cycles(&self) -> i64 {
         match self.current_sub() {
-            Some((sub, freq)) => {
-                ((sub.cycles() as f64 * self.cfg.main_clock as f64) / freq as f64) as i64
+            Some((sub, clock)) => clock.to(&self.cfg.main_clock, sub.cycles()),
+            None => *self.cycles,
+        }
+    }
+
+    /// Add a timer. Its expiration is reported through Event::Timer with the
+    /// returned identifier; periodic timers keep firing until removed.
+    /// Returns None if MAX_TIMERS timers are already pending.
+    pub fn add_timer(&mut self, timer: Timer) -> Option<TimerId> {
+        let slot = self.timers.iter().position(Option::is_none)?;
+        let now = self.cycles();
+        let (deadline, period) = match timer {
+            Timer::AtCycle(cycle) => (cycle, None),
+            Timer::EveryCycles(n) => (now + n.max(1), Some(n.max(1))),
+            Timer::EveryFrames(n) => {
+                let period = n.max(1) * self.frame_cycles;
+                (now - now % self.frame_cycles + period, Some(period))
+            }
+        };
+        // Identifiers are never reused, not even those of the timers in a
+        // savestate loaded later.
+        let id = self.timers.iter().flatten().map(|t| t.id + 1).max();
+        let id = id.unwrap_or(0).max(self.next_timer_id);
+        self.next_timer_id = id + 1;
+        self.timers[slot] = Some(PendingTimer {
+            id,
+            deadline,
+            period,
+        });
+        Some(TimerId(id))
+    }
+
+    /// Remove a timer. Returns false if the timer did not exist (eg: it was
+    /// a one-shot timer that already expired).
+    pub fn remove_timer(&mut self, id: TimerId) -> bool {
+        let slot = self
+            .timers
+            .iter()
+            .position(|t| t.map(|t| t.id) == Some(id.0));
+        match slot {
+            Some(slot) => {
+                self.timers[slot] = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Return the slot of the first timer expiring before the specified
+    // cycle (or at it, if inclusive).
+    fn next_timer(&self, target: i64, inclusive: bool) -> Option<usize> {
+        self.timers
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, t)| t.map(|t| (slot, t)))
+            .filter(|(_, t)| t.deadline < target || (inclusive && t.deadline == target))
+            .min_by_key(|(_, t)| (t.deadline, t.id))
+            .map(|(slot, _)| slot)
+    }
+
+    // Report the expiration of a timer, and reschedule it if periodic.
+    fn fire_timer<F: FnMut(Event)>(&mut self, slot: usize, cb: &mut F) {
+        let mut timer = self.timers[slot].unwrap();
+        self.timers[slot] = match timer.period {
+            Some(period) => {
+                timer.deadline += period;
+                Some(timer)
             }
-            None => self.cycles,
+            None => None,
+        };
+        cb(Event::Timer(TimerId(timer.id)));
+    }
+
+    // Run the emulation until the specified cycle, stopping at each timer
+    // expiring before it.
+    fn run_timers_until<F: FnMut(Event)>(
+        &mut self,
+        target: i64,
+        cb: &mut F,
+        tracer: &dbg::Tracer,
+    ) -> dbg::Result<()> {
+        while let Some(slot) = self.next_timer(target, false) {
+            // Timers expired in the past (eg: one-shot timers added late)
+            // are reported immediately.
+            let deadline = self.timers[slot].unwrap().deadline.max(*self.cycles);
+            self.run_until(deadline, tracer)?;
+            self.fire_timer(slot, cb);
         }
+        self.run_until(target, tracer)
     }
 
     // Return the (x,y) dot position of the emulation in the current frame.
     pub fn dot_pos(&self) -> (usize, usize) {
         let clk = self.cycles();
-        let y = clk / self.line_cycles as i64;
-        let x = clk % self.line_cycles as i64;
+        let y = clk / self.line_cycles;
+        let x = clk % self.line_cycles;
         (x as usize, y as usize)
     }
 
@@ -162,30 +341,35 @@ impl<E: SyncEmu + 'static> Sync<E> {
         if self.curr_frame.is_none() {
             cb(Event::BeginFrame);
         }
-        let (frame_start, idx) = self.curr_frame.unwrap_or((self.cycles, 0));
+        let (frame_start, idx) = self.curr_frame.unwrap_or((*self.cycles, 0));
         let frame_end = frame_start + self.frame_cycles;
         assert_eq!(frame_start % self.frame_cycles, 0);
 
         for idx in idx..self.frame_syncs.len() {
-            self.curr_frame = Some((frame_start, idx));
+            *self.curr_frame = Some((frame_start, idx));
             let (cyc, evt) = self.frame_syncs[idx];
-            self.run_until(frame_start + cyc, tracer)?;
+            self.run_timers_until(frame_start + cyc, &mut cb, tracer)?;
             cb(evt);
 
             // Trace GPU lines.
             // FIXME: this relies on the fact that this specific HSync event
             // was requested. Find out how to handle more generally.
-            match evt {
-                Event::HSync(x, y) if x == 0 => tracer.trace_gpu(y)?,
-                _ => {}
-            };
+            if let Event::HSync(0, y) = evt {
+                tracer.trace_gpu(*self.frames, y, *self.cycles)?;
+            }
         }
 
-        self.curr_frame = Some((frame_start, self.frame_syncs.len()));
-        self.run_until(frame_end, tracer)?;
-        self.frames = self.frames + 1;
-        self.curr_frame = None;
+        *self.curr_frame = Some((frame_start, self.frame_syncs.len()));
+        self.run_timers_until(frame_end, &mut cb, tracer)?;
+        *self.frames += 1;
+        *self.curr_frame = None;
         cb(Event::EndFrame);
+
+        // Timers expiring exactly at the end of the frame (eg: frame timers)
+        // are reported after EndFrame.
+        while let Some(slot) = self.next_timer(frame_end, true) {
+            self.fire_timer(slot, &mut cb);
+        }
         Ok(())
     }
 
@@ -201,17 +385,17 @@ impl<E: SyncEmu + 'static> Sync<E> {
 
     fn run_until(&mut self, target: i64, tracer: &dbg::Tracer) -> dbg::Result<()> {
         let mut idx: usize = 0;
-        while let Some((sub, freq)) = self.emu.subsystem(idx) {
+        while let Some((sub, clock)) = self.emu.subsystem(idx) {
             self.current_sub = Some(idx);
-            let res = sub.run(
-                (target as f64 * freq as f64 / self.cfg.main_clock as f64) as i64,
-                tracer,
-            );
+            let start = clock.to(&self.cfg.main_clock, sub.cycles());
+            let res = sub.run(self.cfg.main_clock.to(&clock, target), tracer);
+            let end = clock.to(&self.cfg.main_clock, sub.cycles());
+            tracer.trace_subsystem(sub.name(), &self.cfg.main_clock, start, end);
             self.current_sub = None;
             res?;
             idx += 1;
         }
-        self.cycles = target;
+        *self.cycles = target;
         Ok(())
     }
 }
@@ -220,6 +404,7 @@ impl<E: SyncEmu + 'static> Sync<E> {
 mod tests {
     use super::*;
     use crate::log::new_console_logger;
+    use crate::state::CurrentState;
 
     struct FakeEmu {
         cfg: Config,
@@ -229,7 +414,7 @@ mod tests {
         fn config(&self) -> Config {
             self.cfg.clone()
         }
-        fn subsystem(&self, _idx: usize) -> Option<(&mut dyn Subsystem, i64)> {
+        fn subsystem(&self, _idx: usize) -> Option<(&mut dyn Subsystem, ClockDomain)> {
             None
         }
     }
@@ -240,7 +425,7 @@ mod tests {
             new_console_logger(),
             FakeEmu {
                 cfg: Config {
-                    main_clock: 128,
+                    main_clock: ClockDomain::new("main", 128),
                     dot_clock_divider: 2,
                     hdots: 4,
                     vdots: 4,
@@ -275,4 +460,94 @@ mod tests {
             events.iter().map(|(_, evt)| *evt).collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn timers() {
+        let mut sync = Sync::new(
+            new_console_logger(),
+            FakeEmu {
+                cfg: Config {
+                    main_clock: ClockDomain::new("main", 128),
+                    dot_clock_divider: 2,
+                    hdots: 4,
+                    vdots: 4,
+                    hsyncs: vec![0, 2],
+                    vsyncs: vec![2],
+                },
+            },
+        );
+
+        let once = sync.add_timer(Timer::AtCycle(10)).unwrap();
+        let cycles = sync.add_timer(Timer::EveryCycles(12)).unwrap();
+        let frames = sync.add_timer(Timer::EveryFrames(1)).unwrap();
+
+        let mut record = Vec::new();
+        sync.run_frame(|evt| {
+            record.push(evt);
+        });
+
+        // Timers expiring on a synchronization point are reported after it.
+        assert_eq!(
+            record,
+            vec![
+                Event::BeginFrame,
+                Event::HSync(0, 0),
+                Event::HSync(2, 0),
+                Event::HSync(0, 1),
+                Event::Timer(once),
+                Event::HSync(2, 1),
+                Event::Timer(cycles),
+                Event::VSync(0, 2),
+                Event::HSync(0, 2),
+                Event::HSync(2, 2),
+                Event::HSync(0, 3),
+                Event::Timer(cycles),
+                Event::HSync(2, 3),
+                Event::EndFrame,
+                Event::Timer(frames),
+            ]
+        );
+
+        assert!(!sync.remove_timer(once));
+        assert!(sync.remove_timer(cycles));
+
+        let mut record = Vec::new();
+        sync.run_frame(|evt| {
+            if let Event::Timer(id) = evt {
+                record.push(id);
+            }
+        });
+        assert_eq!(record, vec![frames]);
+
+        // Deadlines are part of the state: after loading a savestate, the
+        // timers fire again at the same points.
+        let cycles = sync.add_timer(Timer::EveryCycles(12)).unwrap();
+        let snapshot = CurrentState().clone();
+        let run = |sync: &mut Sync<FakeEmu>| {
+            let mut record = Vec::new();
+            sync.run_frame(|evt| record.push(evt));
+            record
+        };
+        let first = run(&mut sync);
+        assert!(first.contains(&Event::Timer(cycles)));
+        snapshot.clone().make_current();
+        assert_eq!(run(&mut sync), first);
+
+        // Identifiers are not reused after loading a savestate.
+        snapshot.make_current();
+        assert!(sync.remove_timer(cycles));
+        let again = sync.add_timer(Timer::EveryCycles(12)).unwrap();
+        assert!(again != cycles && again != frames);
+    }
+
+    #[test]
+    fn irq_line() {
+        let mut line = IrqLine::new("test::irq_line");
+        let cpu = line.connect();
+        assert!(!cpu.get());
+        line.set(true);
+        assert!(cpu.get());
+        line.set(false);
+        assert!(!cpu.get());
+    }
 }
@@ -0,0 +1,178 @@
+//! Host time, as seen by the emulated system.
+//!
+//! Emulated devices must never query the host clock directly: every use of
+//! host time that is visible to the guest (real-time clocks, random seeds)
+//! goes through a [`TimeSource`](trait.TimeSource.html). The default
+//! [`RealTime`](struct.RealTime.html) follows the host clock, while
+//! [`FixedTime`](struct.FixedTime.html) starts at a fixed date and only
+//! advances with emulated time, which makes the emulation fully
+//! deterministic (required for movies, TAS and netplay).
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A source of wall-clock time and random seeds for the emulated system.
+pub trait TimeSource: Send {
+    /// Return the current wall-clock time. `emulated` is the emulated time
+    /// elapsed since power-on.
+    fn now(&self, emulated: Duration) -> SystemTime;
+
+    /// Return a seed for the random number generators of the emulator (eg:
+    /// initial contents of uninitialized memory).
+    fn seed(&self) -> u64;
+
+    /// Return true if the time only depends on the emulated time, so that it
+    /// is the same when emulating again from a savestate.
+    fn deterministic(&self) -> bool {
+        false
+    }
+}
+
+/// A time source that follows the host clock.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RealTime;
+
+impl TimeSource for RealTime {
+    fn now(&self, _emulated: Duration) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn seed(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.as_secs() ^ (now.subsec_nanos() as u64) << 32
+    }
+}
+
+/// A deterministic time source: the clock starts at a fixed date at
+/// power-on, and then advances with emulated time.
+#[derive(Copy, Clone, Debug)]
+pub struct FixedTime {
+    start: SystemTime,
+    seed: u64,
+}
+
+impl FixedTime {
+    pub fn new(start: SystemTime) -> FixedTime {
+        FixedTime { start, seed: 0 }
+    }
+
+    /// Create a time source starting at the specified number of seconds
+    /// since the UNIX epoch.
+    pub fn from_unix(secs: u64) -> FixedTime {
+        FixedTime::new(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    pub fn with_seed(self, seed: u64) -> FixedTime {
+        FixedTime { seed, ..self }
+    }
+}
+
+impl TimeSource for FixedTime {
+    fn now(&self, emulated: Duration) -> SystemTime {
+        self.start + emulated
+    }
+
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn deterministic(&self) -> bool {
+        true
+    }
+}
+
+/// A calendar date and time (UTC), as kept by real-time clock chips.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u32,   // 1-12
+    pub day: u32,     // 1-31
+    pub weekday: u32, // 0 = Sunday
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Convert a time into a calendar date. Times before the UNIX epoch are
+    /// clamped to it.
+    pub fn from_system_time(t: SystemTime) -> DateTime {
+        let secs = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let days = (secs / 86400) as i64;
+        let secs = (secs % 86400) as u32;
+
+        // Convert days since epoch into a civil date, in the proleptic
+        // Gregorian calendar. Years start in March, so that the leap day is
+        // the last day of the year.
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+            weekday: ((days + 4) % 7) as u32, // 1970-01-01 was a Thursday
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed() {
+        let ts = FixedTime::from_unix(1_000_000).with_seed(1234);
+        let t1 = ts.now(Duration::from_secs(10));
+        let t2 = ts.now(Duration::from_secs(10));
+        assert_eq!(t1, t2);
+        assert_eq!(
+            t1.duration_since(UNIX_EPOCH).unwrap(),
+            Duration::from_secs(1_000_010)
+        );
+        assert_eq!(ts.seed(), 1234);
+    }
+
+    #[test]
+    fn datetime() {
+        let dt = |secs| DateTime::from_system_time(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(
+            dt(0),
+            DateTime {
+                year: 1970,
+                month: 1,
+                day: 1,
+                weekday: 4,
+                hour: 0,
+                minute: 0,
+                second: 0,
+            }
+        );
+        // Leap day, on a year multiple of 400.
+        assert_eq!(
+            dt(951_825_045),
+            DateTime {
+                year: 2000,
+                month: 2,
+                day: 29,
+                weekday: 2,
+                hour: 11,
+                minute: 50,
+                second: 45,
+            }
+        );
+        assert_eq!(dt(1_609_459_199).year, 2020);
+        assert_eq!(dt(1_609_459_200).year, 2021);
+    }
+}
@@ -0,0 +1,300 @@
+//! Memory triggers.
+//!
+//! A [`Trigger`](struct.Trigger.html) is a named set of conditions on the
+//! emulated memory, evaluated once per frame. It fires when all its
+//! conditions become true, which is enough to describe achievements
+//! ("the star counter increased"), speedrun splits ("the level number
+//! changed to 5"), or the points of interest of a scripted experiment.
+//!
+//! Conditions use the comparisons of the debugger watchpoints, and are
+//! parsed from strings with the syntax `ADDR[.SIZE] OP VALUE`:
+//!
+//!  * `ADDR` is the hexadecimal address, as seen by the emulated CPU.
+//!  * `SIZE` is `b`, `h` or `w` (8, 16 or 32 bits; the default is `w`).
+//!  * `OP` is one of `==`, `!=`, `>`, `>=`, `<`, `<=`.
+//!  * `VALUE` is a number (hexadecimal with the `0x` prefix), or `prev` for
+//!    the value read at the same address in the previous frame.
+//!
+//! For instance, `8033b21a.h > prev` is true in the frames in which the
+//! halfword at 0x8033B21A increased.
+
+use crate::livesplit;
+use crate::memint::AccessSize;
+use std::fmt;
+use std::str::FromStr;
+
+/// A comparison between the value in memory and the operand of a condition.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compare {
+    Eq, // equal to
+    Ne, // not equal to
+    Gt, // greater than
+    Ge, // greater or equal than
+    Lt, // less than
+    Le, // less or equal than
+}
+
+impl Compare {
+    // Longer operators first, so that ">=" is not parsed as ">".
+    const ALL: [(&'static str, Compare); 6] = [
+        ("==", Compare::Eq),
+        ("!=", Compare::Ne),
+        (">=", Compare::Ge),
+        ("<=", Compare::Le),
+        (">", Compare::Gt),
+        ("<", Compare::Lt),
+    ];
+
+    pub fn check(self, val: u64, cmp: u64) -> bool {
+        match self {
+            Compare::Eq => val == cmp,
+            Compare::Ne => val != cmp,
+            Compare::Gt => val > cmp,
+            Compare::Ge => val >= cmp,
+            Compare::Lt => val < cmp,
+            Compare::Le => val <= cmp,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        Compare::ALL.iter().find(|(_, c)| *c == self).unwrap().0
+    }
+}
+
+/// The right-hand side of a condition.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Value(u64),
+    Prev, // the value read in the previous frame
+}
+
+/// A condition on a value in memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemCondition {
+    pub addr: u64,
+    pub size: AccessSize,
+    pub cmp: Compare,
+    pub rhs: Operand,
+}
+
+impl FromStr for MemCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MemCondition, String> {
+        let err = || {
+            format!(
+                "invalid condition: {:?} (expected ADDR[.b|.h|.w] OP VALUE)",
+                s
+            )
+        };
+
+        let (pos, op, cmp) = Compare::ALL
+            .iter()
+            .filter_map(|&(op, cmp)| s.find(op).map(|pos| (pos, op, cmp)))
+            .min_by_key(|&(pos, _, _)| pos)
+            .ok_or_else(err)?;
+        let (lhs, rhs) = (s[..pos].trim(), s[pos + op.len()..].trim());
+
+        let mut parts = lhs.splitn(2, '.');
+        let addr = parts.next().ok_or_else(err)?;
+        let addr = u64::from_str_radix(addr.trim_start_matches("0x"), 16).map_err(|_| err())?;
+        let size = match parts.next() {
+            Some("b") => AccessSize::Size8,
+            Some("h") => AccessSize::Size16,
+            Some("w") | None => AccessSize::Size32,
+            Some(_) => return Err(err()),
+        };
+
+        let rhs = if rhs == "prev" {
+            Operand::Prev
+        } else if let Some(hex) = rhs.strip_prefix("0x") {
+            Operand::Value(u64::from_str_radix(hex, 16).map_err(|_| err())?)
+        } else {
+            Operand::Value(rhs.parse().map_err(|_| err())?)
+        };
+
+        Ok(MemCondition {
+            addr,
+            size,
+            cmp,
+            rhs,
+        })
+    }
+}
+
+impl fmt::Display for MemCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = match self.size {
+            AccessSize::Size8 => "b",
+            AccessSize::Size16 => "h",
+            AccessSize::Size32 | AccessSize::Size64 => "w",
+        };
+        write!(f, "{:x}.{} {} ", self.addr, size, self.cmp.symbol())?;
+        match self.rhs {
+            Operand::Value(v) => write!(f, "0x{:x}", v),
+            Operand::Prev => write!(f, "prev"),
+        }
+    }
+}
+
+/// A named set of conditions, all of which must be true for the trigger to
+/// fire.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trigger {
+    pub name: String,
+    pub conditions: Vec<MemCondition>,
+
+    /// Fire only the first time (eg: achievements). Otherwise, the trigger
+    /// fires again each time its conditions become true after being false.
+    pub once: bool,
+
+    /// Command sent to LiveSplit when the trigger fires (if connected).
+    pub livesplit: Option<livesplit::Command>,
+}
+
+// Evaluation state of a trigger.
+#[derive(Clone, Default)]
+struct TriggerState {
+    active: bool,           // conditions were true at the last evaluation
+    fired: usize,           // number of times the trigger fired
+    prev: Vec<Option<u64>>, // values read at the last evaluation
+}
+
+/// Evaluates a list of triggers.
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+    states: Vec<TriggerState>,
+}
+
+impl Triggers {
+    pub fn new(triggers: Vec<Trigger>) -> Triggers {
+        Triggers {
+            states: triggers
+                .iter()
+                .map(|t| TriggerState {
+                    prev: vec![None; t.conditions.len()],
+                    ..TriggerState::default()
+                })
+                .collect(),
+            triggers,
+        }
+    }
+
+    pub fn triggers(&self) -> &[Trigger] {
+        &self.triggers
+    }
+
+    /// Return the number of times the trigger at the specified index fired.
+    pub fn fired(&self, idx: usize) -> usize {
+        self.states[idx].fired
+    }
+
+    /// Forget the values read in the previous frame (eg: after loading a
+    /// savestate), so that they are not compared with the values of a
+    /// different run. Triggers that fired only once are not re-armed.
+    pub fn reset(&mut self) {
+        for st in &mut self.states {
+            st.active = false;
+            for prev in &mut st.prev {
+                *prev = None;
+            }
+        }
+    }
+
+    /// Evaluate the triggers, reading memory through read (which returns
+    /// None for addresses that cannot be read: the condition is false).
+    /// It should be called once per frame. Returns the indices of the
+    /// triggers that fired.
+    pub fn eval<F: FnMut(u64, AccessSize) -> Option<u64>>(&mut self, mut read: F) -> Vec<usize> {
+        let mut fired = Vec::new();
+        for (idx, (t, st)) in self.triggers.iter().zip(&mut self.states).enumerate() {
+            let mut active = !t.conditions.is_empty();
+            for (cond, prev) in t.conditions.iter().zip(&mut st.prev) {
+                let val = read(cond.addr, cond.size);
+                let rhs = match cond.rhs {
+                    Operand::Value(v) => Some(v),
+                    Operand::Prev => *prev,
+                };
+                active &= match (val, rhs) {
+                    (Some(val), Some(rhs)) => cond.cmp.check(val, rhs),
+                    _ => false,
+                };
+                *prev = val;
+            }
+            if active && !st.active && !(t.once && st.fired > 0) {
+                st.fired += 1;
+                fired.push(idx);
+            }
+            st.active = active;
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "8033b21a.h >= 0x10".parse(),
+            Ok(MemCondition {
+                addr: 0x8033_b21a,
+                size: AccessSize::Size16,
+                cmp: Compare::Ge,
+                rhs: Operand::Value(0x10),
+            })
+        );
+        let cond: MemCondition = "0x80001000!=prev".parse().unwrap();
+        assert_eq!(cond.size, AccessSize::Size32);
+        assert_eq!(cond.cmp, Compare::Ne);
+        assert_eq!(cond.rhs, Operand::Prev);
+        assert_eq!(cond.to_string(), "80001000.w != prev");
+        assert_eq!(
+            "80001000.b < 12".parse::<MemCondition>().map(|c| c.rhs),
+            Ok(Operand::Value(12))
+        );
+        assert!("80001000.b".parse::<MemCondition>().is_err());
+        assert!("80001000.q == 1".parse::<MemCondition>().is_err());
+        assert!("zz == 1".parse::<MemCondition>().is_err());
+        assert!("80001000 == next".parse::<MemCondition>().is_err());
+    }
+
+    #[test]
+    fn eval() {
+        let cond = |s: &str| s.parse().unwrap();
+        let mut triggers = Triggers::new(vec![
+            Trigger {
+                name: "increased".into(),
+                conditions: vec![cond("0 > prev")],
+                once: false,
+                livesplit: None,
+            },
+            Trigger {
+                name: "level 3".into(),
+                conditions: vec![cond("0 >= 2"), cond("4.b == 3")],
+                once: true,
+                livesplit: None,
+            },
+        ]);
+
+        let mut mem = [0u64; 2];
+        let mut run = |triggers: &mut Triggers, a: u64, b: u64| {
+            mem = [a, b];
+            triggers.eval(|addr, _| mem.get(addr as usize / 4).cloned())
+        };
+        assert_eq!(run(&mut triggers, 1, 3), Vec::<usize>::new()); // no previous value yet
+        assert_eq!(run(&mut triggers, 2, 3), vec![0, 1]);
+        assert_eq!(run(&mut triggers, 3, 3), Vec::<usize>::new()); // still true: no edge
+        assert_eq!(run(&mut triggers, 3, 0), Vec::<usize>::new());
+        assert_eq!(run(&mut triggers, 4, 3), vec![0]); // once
+        assert_eq!(triggers.fired(0), 2);
+        assert_eq!(triggers.fired(1), 1);
+
+        triggers.reset();
+        assert_eq!(run(&mut triggers, 5, 3), Vec::<usize>::new()); // no previous value
+        assert_eq!(run(&mut triggers, 6, 3), vec![0]);
+        assert_eq!(triggers.fired(1), 1);
+    }
+}
@@ -1,9 +1,8 @@
-#![feature(pin)]
-
 #[cfg(test)]
 mod tests {
     use byteorder::LittleEndian;
-    use emu::bus::{Bus, Device, Mem, Reg};
+    use emu::bus::{Bus, CurrentDeviceMap, Device, Mem, MemInfo, Reg, RegInfo};
+    use emu::errors::DeviceError;
     use emu::log::new_console_logger;
     use emu_derive::DeviceLE;
 
@@ -57,4 +56,104 @@ mod tests {
         bus.write::<u32>(0x0400000C, 0xaaaaaaaa);
         assert_eq!(bus.read::<u32>(0x0400000C), 0xaaaa0081);
     }
+
+    #[test]
+    fn overlapping_device() {
+        Box::new(Gpu::default()).register();
+
+        let mut bus = Bus::<LittleEndian>::new(new_console_logger());
+        let gpu = Gpu::get();
+        bus.map_device(0x04000000, gpu, 1).expect("map error");
+        match bus.map_device(0x05000000, gpu, 1) {
+            Err(DeviceError::BusMapping { bank, base, .. }) => {
+                assert_eq!(bank, 1);
+                assert_eq!(base, 0x05000000);
+            }
+            _ => panic!("overlapping mapping should fail"),
+        }
+    }
+
+    #[derive(Default, DeviceLE)]
+    #[device(reset)]
+    struct Timer {
+        #[reg(bank = 0, offset = 0x0)]
+        counter: Reg<LittleEndian, u32>,
+
+        resets: Vec<bool>,
+    }
+
+    impl Timer {
+        fn cb_reset(&mut self, hard: bool) {
+            self.counter.set(0);
+            self.resets.push(hard);
+        }
+    }
+
+    #[test]
+    fn reset_device() {
+        Box::new(Gpu::default()).register();
+        Box::new(Timer::default()).register();
+
+        Timer::get_mut().counter.set(1234);
+        CurrentDeviceMap().reset(false);
+        CurrentDeviceMap().reset(true);
+        assert_eq!(Timer::get().counter.get(), 0);
+        assert_eq!(Timer::get().resets, vec![false, true]);
+    }
+
+    #[test]
+    fn device_info() {
+        Box::new(Gpu::default()).register();
+        Box::new(Timer::default()).register();
+        Timer::get_mut().counter.set(1234);
+
+        let info = CurrentDeviceMap().info();
+        assert_eq!(info.len(), 2);
+        assert_eq!(info[0].name, "Gpu");
+        assert_eq!(info[0].cpu, None);
+        assert_eq!(
+            info[0].mems,
+            vec![MemInfo {
+                name: "ram",
+                bank: 1,
+                offset: 0x0,
+                size: 4194304,
+                vsize: 0x0200_0000,
+            }]
+        );
+        assert_eq!(
+            info[0].regs,
+            vec![RegInfo {
+                name: "reg1",
+                bank: 0,
+                offset: 0xC,
+                size: 4,
+                value: 0,
+            }]
+        );
+        assert_eq!(info[1].name, "Timer");
+        assert_eq!(info[1].regs[0].value, 1234);
+        assert!(info[1].mems.is_empty());
+    }
+
+    #[derive(Default, DeviceLE)]
+    #[device(subword = "shift")]
+    struct Dma {
+        #[reg(bank = 0, offset = 0x0)]
+        addr: Reg<LittleEndian, u32>,
+    }
+
+    #[test]
+    fn subword_device() {
+        Box::new(Dma::default()).register();
+
+        let mut bus = Bus::<LittleEndian>::new(new_console_logger());
+        bus.map_device(0x04000000, Dma::get(), 0)
+            .expect("map error");
+
+        bus.write::<u32>(0x04000000, 0xaabbccdd);
+        bus.write::<u8>(0x04000001, 0x12);
+        assert_eq!(Dma::get().addr.get(), 0x00001200);
+        assert_eq!(bus.read::<u8>(0x04000001), 0x12);
+    }
 }
@@ -1,9 +1,14 @@
-use super::mi::{IrqMask, Mi};
+use super::mi::{IrqMask, Mi, MiIrq};
+use super::n64::VCLK;
 use super::r4300::R4300;
+use super::ri::RDRAM_DMA_WRAP;
 use emu::bus::be::{Device, Reg32};
+use emu::clock::ClockDomain;
 use emu::dbg;
+use emu::dbg::{AudioDma, AudioView};
+use emu::dma::{Dma, DmaTiming, DmaXfer};
 use emu::int::Numerics;
-use emu::snd::{SampleFormat, SampleInt, SndBuffer, SndBufferMut, S16_STEREO};
+use emu::snd::{SampleFormat, SampleInt, SndBuffer, SndBufferMut, StereoResampler, S16_STEREO};
 use emu::state::{ArrayField, Field};
 use emu::sync;
 use emu_derive::DeviceBE;
@@ -11,12 +16,13 @@ use serde_derive::{Deserialize, Serialize};
 
 #[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
 struct AudioFifo {
-    src: u32,   // Source RDRAM address of sample data
-    len: u32,   // Source length of sample data in bytes
-    full: bool, // True if this AudioFifo is full (not empty)
+    xfer: DmaXfer, // Remaining sample data in RDRAM
+    total: u32,    // Length of the DMA as originally programmed (for debugging)
+    full: bool,    // True if this AudioFifo is full (not empty)
 }
 
 #[derive(DeviceBE)]
+#[device(subword = "shift")]
 pub struct Ai {
     // (W): [23:0] starting RDRAM address (8B-aligned)
     #[reg(bank = 0, offset = 0x00, rwmask = 0xFFFFFF, writeonly)]
@@ -33,7 +39,8 @@ pub struct Ai {
 
     // (R): [31]/[0] ai_full (addr & len buffer full)
     //      [30] ai_busy
-    //      Note that a 1to0 transition in ai_full will set interrupt
+    //      [25] DMA enabled
+    //      Note that an interrupt is set whenever a DMA starts playing
     // (W): clear audio interrupt
     #[reg(bank = 0, offset = 0x0C, wcb)]
     reg_status: Reg32,
@@ -53,11 +60,20 @@ pub struct Ai {
     fifo: ArrayField<AudioFifo>,
     fifo_cur: Field<usize>,
     cycles: Field<i64>,
+    dma: Dma,
 
     // Internal sound buffer for the current frame. We're not storing this in
     // the state right now, so after reload there might be some missing samples.
     sndbuffer: Vec<i16>,
 
+    // Samples emitted in the last complete frame (for the debugger).
+    last_samples: Vec<i16>,
+
+    // Conversion of the samples from the DAC rate to the output frequency.
+    resampler: StereoResampler,
+    resampled: Vec<i16>,
+
+    irq: MiIrq, // Interrupt line to MI
     logger: slog::Logger,
 }
 
@@ -75,22 +91,28 @@ impl Ai {
             fifo: ArrayField::new("Ai::fifo", AudioFifo::default(), 2),
             fifo_cur: Field::new("Ai::fifo_cur", 0),
             cycles: Field::new("Ai::cycles", 0),
+            dma: Dma::new("AI DMA", DmaTiming::default()),
             sndbuffer: Vec::new(),
+            last_samples: Vec::new(),
+            resampler: StereoResampler::new(),
+            resampled: Vec::new(),
             logger,
+            irq: Mi::irq_line(IrqMask::AI),
         })
     }
 
     fn update_status(&mut self) {
+        let enabled = self.dma_enabled();
         let mut status = self.reg_status.as_ref::<u32>();
         if self.fifo[0].full && self.fifo[1].full {
-            *status |= 1 << 31;
+            *status |= 1 << 31 | 1;
         } else {
-            if *status & (1 << 31) != 0 {
-                // 1-to-0 transition of full bit triggers an interrupt
-                Mi::get_mut().set_irq_line(IrqMask::AI, true);
-                info!(self.logger, "audio fifo slot available, trigger IRQ");
-            }
-            *status &= !(1 << 31);
+            *status &= !(1 << 31 | 1);
+        }
+        if enabled {
+            *status |= 1 << 25;
+        } else {
+            *status &= !(1 << 25);
         }
         if self.fifo[0].full || self.fifo[1].full {
             *status |= 1 << 30;
@@ -111,6 +133,9 @@ impl Ai {
             return;
         }
 
+        // A DMA queued while the AI is idle starts playing immediately, and
+        // frees the slot for the next one.
+        let idle = !self.fifo[0].full && !self.fifo[1].full;
         let mut widx = *self.fifo_cur;
         if self.fifo[widx].full {
             widx ^= 1;
@@ -120,38 +145,78 @@ impl Ai {
             }
         }
 
+        // The address is 8-byte aligned, and wraps around at the end of the
+        // 24-bit address space while samples are fetched.
+        let src = src & !7;
         info!(self.logger, "start DMA"; "src" => src.hex(), "len" => len);
         self.fifo[widx] = AudioFifo {
-            src,
-            len,
+            xfer: DmaXfer::linear(src, 0, len as usize).wrap(RDRAM_DMA_WRAP, 0),
+            total: len,
             full: true,
         };
+        if idle {
+            self.start_playing();
+        }
         self.update_status();
     }
 
-    fn cb_write_reg_control(&self, _old: u32, new: u32) {
+    fn cb_write_reg_control(&mut self, _old: u32, new: u32) {
         info!(self.logger, "written reg_control"; "val" => new.hex());
+        self.update_status();
+    }
+
+    fn dma_enabled(&self) -> bool {
+        self.reg_control.get() & 1 != 0
+    }
+
+    // A DMA starts playing: the slot of the next DMA is free, so the CPU is
+    // notified with an interrupt.
+    fn start_playing(&mut self) {
+        self.irq.raise();
+        info!(self.logger, "audio fifo slot available, trigger IRQ");
     }
 
     fn cb_write_reg_status(&mut self, old: u32, _new: u32) {
         self.reg_status.set(old);
-        Mi::get_mut().set_irq_line(IrqMask::AI, false);
+        self.irq.ack();
         info!(self.logger, "IRQ acknowledge");
     }
 
+    /// Return the sample rate of the DAC, in Hz.
+    pub fn sample_rate(&self) -> f64 {
+        self.dac_clock().hz()
+    }
+
+    /// Return the clock domain of the DAC, which outputs one sample every
+    /// (dperiod + 1) video clock cycles.
+    pub fn dac_clock(&self) -> ClockDomain {
+        let period = self.reg_dac_sample_period.get() as i64;
+        VCLK.scale("AI DAC", 1, period + 1)
+    }
+
+    /// Report DMA activity to the debugger (once per scanline).
+    pub(crate) fn trace_dma(&mut self, tracer: &dbg::Tracer) {
+        self.dma.trace(tracer);
+    }
+
     pub fn begin_frame<SF: SampleFormat>(&mut self, _output: &mut SndBufferMut<SF>) {
         // Unfortunately, we can't store the mutable reference to output (also,
         // it's generic). So we'll have to live with an internal buffer and a
         // copy at the end of the frame.
-        self.sndbuffer.resize(0, 0);
+        self.sndbuffer.clear();
     }
 
     pub fn end_frame<SF: SampleFormat>(&mut self, output: &mut SndBufferMut<SF>) {
-        // Copy the sound buffer into the output (doing any sample format
-        // conversion).
-        let buf = SndBuffer::<S16_STEREO>::new_typed(&self.sndbuffer[..]);
+        // Resample the sound buffer (emitted at the DAC rate) to the length of
+        // the output, and copy it (doing any sample format conversion).
+        self.resampled.resize(output.count() * 2, 0);
+        self.resampler.process(&self.sndbuffer, &mut self.resampled);
+        let buf = SndBuffer::<S16_STEREO>::new_typed(&self.resampled[..]);
         buf.sconv_into(output);
-        info!(self.logger, "end frame"; "src" => buf.count(), "dst" => output.count());
+        info!(self.logger, "end frame"; "src" => self.sndbuffer.len() / 2, "dst" => output.count());
+
+        self.last_samples.clear();
+        self.last_samples.extend_from_slice(&self.sndbuffer);
     }
 }
 
@@ -160,37 +225,33 @@ impl sync::Subsystem for Ai {
         "Ai"
     }
 
-    fn run(&mut self, target_cycles: i64, _tracer: &dbg::Tracer) -> dbg::Result<()> {
+    fn run(&mut self, target_cycles: i64, tracer: &dbg::Tracer) -> dbg::Result<()> {
         debug!(self.logger, "AI run";
             "fifo" => ?self.fifo[*self.fifo_cur],
             "other" => ?self.fifo[*self.fifo_cur^1],
             "cur" => *self.fifo_cur,
             "period" => self.reg_dac_sample_period.get());
-        let audioframe_bitsize = self.reg_bit_rate.get() + 1;
+        let enabled = self.dma_enabled();
 
         while *self.cycles < target_cycles {
             let fifo = &mut self.fifo[*self.fifo_cur];
-            if fifo.full {
-                // One DMA step: consume one frame of audio
-                match audioframe_bitsize {
-                    16 => {
-                        let sample = R4300::get().bus.read::<u32>(fifo.src);
-                        let left = (sample >> 16) as i16;
-                        let right = (sample & 0xFFFF) as i16;
-                        self.sndbuffer.push(left.sconv());
-                        self.sndbuffer.push(right.sconv());
-                    }
-                    _ => unimplemented!(),
-                }
-
-                // Update DMA source and length
-                fifo.src += (audioframe_bitsize / 8) * 2;
-                fifo.len = fifo.len.checked_sub((audioframe_bitsize / 8) * 2).unwrap();
+            if fifo.full && enabled {
+                // One DMA step: consume one frame of audio (the DAC always
+                // outputs 16-bit stereo samples, whatever the bit rate).
+                let bus = &R4300::get().bus;
+                let sample = self.dma.stream::<_, u32>(bus, &mut fifo.xfer).unwrap_or(0);
+                let left = (sample >> 16) as i16;
+                let right = (sample & 0xFFFF) as i16;
+                self.sndbuffer.push(left.sconv());
+                self.sndbuffer.push(right.sconv());
 
                 // End of buffer? If so, switch to other buffer.
-                if fifo.len == 0 {
+                if fifo.xfer.is_empty() {
                     fifo.full = false;
                     *self.fifo_cur ^= 1;
+                    if self.fifo[*self.fifo_cur].full {
+                        self.start_playing();
+                    }
                 }
             } else {
                 self.sndbuffer.push(i16::MUTE);
@@ -201,11 +262,14 @@ impl sync::Subsystem for Ai {
 
         // Update also user-visibile registers (that reflect current FIFO)
         let fifo = &self.fifo[*self.fifo_cur];
-        self.reg_dram_address.set(fifo.src);
-        self.reg_length.set(fifo.len);
+        self.reg_dram_address.set(fifo.xfer.src);
+        self.reg_length.set(fifo.xfer.len() as u32);
 
         self.update_status();
-        Ok(())
+        if self.dma.out_of_bounds() {
+            warn!(self.logger, "DMA xfer out of bounds"; "src" => self.reg_dram_address.get().hex());
+        }
+        self.dma.trace_oob(tracer)
     }
     fn step(&mut self, _tracer: &dbg::Tracer) -> dbg::Result<()> {
         panic!("Ai::step() should never be called");
@@ -217,3 +281,39 @@ impl sync::Subsystem for Ai {
         None // No program counter
     }
 }
+
+impl AudioView for Ai {
+    fn name(&self) -> &str {
+        "AI"
+    }
+
+    fn dma_queue(&self) -> Vec<Option<AudioDma>> {
+        (0..2)
+            .map(|idx| {
+                let fifo = &self.fifo[idx];
+                if fifo.full {
+                    Some(AudioDma {
+                        addr: fifo.xfer.src as u64,
+                        len: fifo.total as u64,
+                        remaining: fifo.xfer.len() as u64,
+                        playing: idx == *self.fifo_cur,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn visit_info<F: FnMut(&str, String)>(&self, mut visit: F) {
+        let period = self.reg_dac_sample_period.get();
+        visit("DAC period", format!("{}", period));
+        visit("DAC frequency", format!("{:.1} Hz", self.sample_rate()));
+        visit("Bit rate", format!("{}", self.reg_bit_rate.get()));
+        visit("Output frequency", format!("{} Hz", Self::OUTPUT_FREQUENCY));
+    }
+
+    fn last_samples(&self) -> &[i16] {
+        &self.last_samples
+    }
+}
@@ -1,15 +1,31 @@
-use crate::errors::*;
+use crate::errors::LoadError;
+use crate::patch;
 use emu::bus::be::{Mem, MemFlags, Reg32};
+use emu::state::Field;
 
+use byteorder::{BigEndian, ByteOrder};
 use crc::crc32;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
 use std::path::Path;
+use std::result;
+use std::str::FromStr;
+
+/// Size of the address space of the cartridge ROM (PI domain 1, address 2).
+const ROM_SPACE: u32 = 0x07C0_0000;
+
+/// Physical address of the cartridge ROM area.
+pub const ROM_BASE: u32 = 0x1000_0000;
 
 #[derive(DeviceBE)]
 pub struct Cartridge {
+    // CPU loads and stores in the ROM area go through the PI, which affects
+    // their behavior (see rom_read / rom_write); DMAs and instruction
+    // fetches access the memory directly.
     #[mem(offset = 0, vsize = 0x07C0_0000, fill = "Fixed(0x00)")]
     rom: Mem,
+    rom_latch: Field<u32>,    // Last word written to the ROM area
+    rom_latched: Field<bool>, // True if the next read returns rom_latch
 
     #[reg(bank = 1, offset = 0x200)]
     drive64_status: Reg32,
@@ -18,6 +34,7 @@ pub struct Cartridge {
     drive64_cmd: Reg32,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum CicModel {
     Cic6101 = 6101,
     Cic6102 = 6102,
@@ -26,27 +43,234 @@ pub enum CicModel {
     Cic6106 = 6106,
 }
 
-pub fn romswap(rom: Vec<u8>) -> Vec<u8> {
+impl CicModel {
+    pub const ALL: [CicModel; 5] = [
+        CicModel::Cic6101,
+        CicModel::Cic6102,
+        CicModel::Cic6103,
+        CicModel::Cic6105,
+        CicModel::Cic6106,
+    ];
+
+    // Seed of the checksum computed by the boot code (IPL3) of each CIC.
+    fn crc_seed(self) -> u32 {
+        match self {
+            CicModel::Cic6101 | CicModel::Cic6102 => 0xF8CA_4DDC,
+            CicModel::Cic6103 => 0xA388_6759,
+            CicModel::Cic6105 => 0xDF26_F436,
+            CicModel::Cic6106 => 0x1FEA_617A,
+        }
+    }
+
+    /// Return the seeds sent by the CIC to the PIF at boot, as stored by the
+    /// PIF in PIF RAM (bits 0-18 of the word at offset 0x24): the IPL2
+    /// seed (always 0x3F), the IPL3 seed (used by the boot code to verify
+    /// the checksum of the ROM), and the osVersion bit, which is set only by
+    /// the oldest CIC.
+    pub fn pif_seed(self) -> u32 {
+        let (ipl3, version) = match self {
+            CicModel::Cic6101 => (0x3F, 1), // starfox
+            CicModel::Cic6102 => (0x3F, 0), // mario
+            CicModel::Cic6103 => (0x78, 0), // banjo
+            CicModel::Cic6105 => (0x91, 0), // zelda
+            CicModel::Cic6106 => (0x85, 0), // f-zero x
+        };
+        version << 18 | ipl3 << 8 | 0x3F
+    }
+
+    /// Return the offset between the entrypoint in the ROM header and the
+    /// address where the boot code actually loads and jumps to (some boot
+    /// codes use a different address than the one in the header).
+    pub fn entrypoint_offset(self) -> u32 {
+        match self {
+            CicModel::Cic6103 => 0x10_0000,
+            CicModel::Cic6106 => 0x20_0000,
+            _ => 0,
+        }
+    }
+}
+
+/// Detect the CIC model from the boot code (the 4032 bytes at offset 0x40
+/// of a big-endian ROM), by checksumming it.
+pub fn detect_cic(bootcode: &[u8]) -> result::Result<CicModel, LoadError> {
+    match crc32::checksum_ieee(bootcode) {
+        0x6170A4A1 => Ok(CicModel::Cic6101),
+        0x90BB6CB5 => Ok(CicModel::Cic6102),
+        0x0B050EE0 => Ok(CicModel::Cic6103),
+        0x98BC2C86 => Ok(CicModel::Cic6105),
+        0xACC8580A => Ok(CicModel::Cic6106),
+        chk => Err(LoadError::UnknownCic { chk }),
+    }
+}
+
+/// Offset and length of the area of the ROM covered by the header CRCs.
+pub const CRC_START: usize = 0x1000;
+pub const CRC_LENGTH: usize = 0x10_0000;
+
+/// Compute the two checksums of the header (CRC1, CRC2) of a big-endian
+/// ROM, as verified by the boot code of the specified CIC. Bytes beyond
+/// the end of the ROM are considered zero.
+pub fn compute_crcs(rom: &[u8], cic: CicModel) -> (u32, u32) {
+    let word = |off: usize| match rom.get(off..off + 4) {
+        Some(w) => BigEndian::read_u32(w),
+        None => 0,
+    };
+
+    let seed = cic.crc_seed();
+    let (mut t1, mut t2, mut t3, mut t4, mut t5, mut t6) = (seed, seed, seed, seed, seed, seed);
+    for off in (CRC_START..CRC_START + CRC_LENGTH).step_by(4) {
+        let d = word(off);
+        let (sum, carry) = t6.overflowing_add(d);
+        if carry {
+            t4 = t4.wrapping_add(1);
+        }
+        t6 = sum;
+        t3 ^= d;
+        let r = d.rotate_left(d & 0x1F);
+        t5 = t5.wrapping_add(r);
+        if t2 > d {
+            t2 ^= r;
+        } else {
+            t2 ^= t6 ^ d;
+        }
+        t1 = t1.wrapping_add(match cic {
+            // 6105 mixes in the contents of its own boot code
+            CicModel::Cic6105 => word(0x750 + (off & 0xFF)) ^ d,
+            _ => t5 ^ d,
+        });
+    }
+
+    match cic {
+        CicModel::Cic6103 => ((t6 ^ t4).wrapping_add(t3), (t5 ^ t2).wrapping_add(t1)),
+        CicModel::Cic6106 => (
+            t6.wrapping_mul(t4).wrapping_add(t3),
+            t5.wrapping_mul(t2).wrapping_add(t1),
+        ),
+        _ => (t6 ^ t4 ^ t3, t5 ^ t2 ^ t1),
+    }
+}
+
+impl FromStr for CicModel {
+    type Err = String;
+    fn from_str(s: &str) -> result::Result<CicModel, String> {
+        CicModel::ALL
+            .iter()
+            .cloned()
+            .find(|&c| (c as u32).to_string() == s)
+            .ok_or_else(|| format!("unknown CIC model: {}", s))
+    }
+}
+
+/// The header CRCs of a ROM, before and after recomputing them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CrcFix {
+    pub cic: CicModel,
+    pub old: (u32, u32),
+    pub new: (u32, u32),
+}
+
+impl CrcFix {
+    /// Return true if the CRCs in the header were wrong (and were patched).
+    pub fn changed(&self) -> bool {
+        self.old != self.new
+    }
+}
+
+/// Recompute the header CRCs of a big-endian ROM and patch them into the
+/// header, so that the ROM boots after being modified. The CIC is detected
+/// from the boot code, unless specified (for ROMs with a custom boot code).
+pub fn fix_crcs(rom: &mut [u8], cic: Option<CicModel>) -> result::Result<CrcFix, LoadError> {
+    if rom.len() < CRC_START {
+        return Err(LoadError::RomTooSmall { size: rom.len() });
+    }
+    let cic = match cic {
+        Some(cic) => cic,
+        None => detect_cic(&rom[0x40..0x1000])?,
+    };
+    let old = (
+        BigEndian::read_u32(&rom[0x10..]),
+        BigEndian::read_u32(&rom[0x14..]),
+    );
+    let new = compute_crcs(rom, cic);
+    BigEndian::write_u32(&mut rom[0x10..], new.0);
+    BigEndian::write_u32(&mut rom[0x14..], new.1);
+    Ok(CrcFix { cic, old, new })
+}
+
+/// Fix the header CRCs of a ROM file (see fix_crcs), preserving its byte
+/// order. The result is written into output (which can be the input file
+/// itself); if output is None, the CRCs are only checked.
+pub fn fix_crcs_file(
+    input: &Path,
+    output: Option<&Path>,
+    cic: Option<CicModel>,
+) -> result::Result<CrcFix, LoadError> {
+    let data = fs::read(input).map_err(|err| LoadError::io(input, err))?;
+    if data.len() < CRC_START {
+        return Err(LoadError::RomTooSmall { size: data.len() });
+    }
+    let swapped = data[0] != 0x80;
+    let mut rom = romswap(data)?;
+    let fix = fix_crcs(&mut rom, cic)?;
+    if let Some(output) = output {
+        if swapped {
+            rom.chunks_exact_mut(2).for_each(|hw| hw.swap(0, 1));
+        }
+        fs::write(output, rom).map_err(|err| LoadError::io(output, err))?;
+    }
+    Ok(fix)
+}
+
+pub fn romswap(rom: Vec<u8>) -> result::Result<Vec<u8>, LoadError> {
     if rom[0] == 0x80 {
         // ROM is big-endian: nothing to do
-        return rom;
+        Ok(rom)
     } else if rom[1] == 0x80 {
         // ROM is byteswapped
-        return rom
+        Ok(rom
             .iter()
             .enumerate()
             .map(|(idx, _)| rom[idx ^ 1])
-            .collect();
+            .collect())
     } else {
-        panic!("unsupported ROM format")
+        Err(LoadError::UnsupportedRomFormat {
+            header: BigEndian::read_u32(&rom),
+        })
     }
 }
 
 impl Cartridge {
-    pub fn new(romfn: &Path) -> Result<Box<Cartridge>> {
-        let mut file = File::open(romfn)?;
+    pub fn new(romfn: &Path) -> result::Result<Box<Cartridge>, LoadError> {
+        Cartridge::with_patch(romfn, None)
+    }
+
+    /// Load a ROM, applying the specified patch (IPS, BPS, UPS) to it.
+    pub fn with_patch(
+        romfn: &Path,
+        patch: Option<&Path>,
+    ) -> result::Result<Box<Cartridge>, LoadError> {
         let mut contents = vec![];
-        file.read_to_end(&mut contents)?;
+        File::open(romfn)
+            .and_then(|mut file| file.read_to_end(&mut contents))
+            .map_err(|err| LoadError::io(romfn, err))?;
+
+        // The header and the boot code (checksummed to detect the CIC)
+        // take the first 4KB.
+        if contents.len() < 0x1000 {
+            return Err(LoadError::RomTooSmall {
+                size: contents.len(),
+            });
+        }
+
+        let mut contents = romswap(contents)?;
+        if let Some(patch) = patch {
+            contents = patch::apply_file(&contents, patch)?;
+            if contents.len() < 0x1000 {
+                return Err(LoadError::RomTooSmall {
+                    size: contents.len(),
+                });
+            }
+        }
 
         if !contents.len().is_power_of_two() {
             let newsize = contents.len().next_power_of_two();
@@ -56,19 +280,95 @@ impl Cartridge {
         Ok(Box::new(Cartridge {
             drive64_status: Reg32::default(),
             drive64_cmd: Reg32::default(),
-            rom: Mem::from_buffer("rom", romswap(contents), MemFlags::READACCESS),
+            rom: Mem::from_buffer("rom", contents, MemFlags::READACCESS),
+            rom_latch: Field::new("Cartridge::rom_latch", 0),
+            rom_latched: Field::new("Cartridge::rom_latched", false),
         }))
     }
 
-    // Detect the CIC model by checksumming the header of the ROM.
-    pub fn detect_cic_model(&self) -> Result<CicModel> {
-        match crc32::checksum_ieee(&self.rom[0x40..0x1000]) {
-            0x6170A4A1 => Ok(CicModel::Cic6101),
-            0x90BB6CB5 => Ok(CicModel::Cic6102),
-            0x0B050EE0 => Ok(CicModel::Cic6103),
-            0x98BC2C86 => Ok(CicModel::Cic6105),
-            0xACC8580A => Ok(CicModel::Cic6106),
-            chk => bail!("cannot detect CIC model in ROM (chk = {:08x})", chk),
+    /// Return true if the physical address is within the ROM area.
+    pub fn in_rom_area(paddr: u32) -> bool {
+        paddr.wrapping_sub(ROM_BASE) < ROM_SPACE
+    }
+
+    // Read a 32-bit word from the ROM area, as returned by the PI.
+    fn rom_word(&mut self, off: u32) -> u32 {
+        // A write to the ROM area is latched by the PI, and returned by
+        // the next read, irrespective of its address.
+        if *self.rom_latched {
+            *self.rom_latched = false;
+            return *self.rom_latch;
+        }
+        match self.rom.get(off as usize..off as usize + 4) {
+            Some(word) => BigEndian::read_u32(word),
+            // Beyond the end of the ROM, nothing drives the bus, so it
+            // still holds the lower 16 bits of the address (for each
+            // halfword of the transfer). The area is 64K-aligned, so the
+            // offset has the same lower bits.
+            None => (off & 0xFFFF) << 16 | ((off + 2) & 0xFFFF),
         }
     }
+
+    /// Read from the ROM area. `off` is the offset from the start of the
+    /// area, and `size` the size of the access (in bytes). The PI always
+    /// transfers full words: smaller accesses extract their bytes from the
+    /// containing word.
+    pub fn rom_read(&mut self, off: u32, size: usize) -> u64 {
+        if size == 8 {
+            let hi = self.rom_word(off & !7) as u64;
+            let lo = self.rom_word((off & !7) + 4) as u64;
+            return hi << 32 | lo;
+        }
+        let word = self.rom_word(off & !3) as u64;
+        let shift = (4 - size - (off as usize & 3 & !(size - 1))) * 8;
+        (word >> shift) & ((1u64 << (size * 8)) - 1)
+    }
+
+    /// Write to the ROM area. The ROM is obviously not modified, but the
+    /// PI latches the written value (positioned within its word).
+    pub fn rom_write(&mut self, off: u32, val: u64, size: usize) {
+        let word = match size {
+            8 => val as u32,
+            _ => {
+                let shift = (4 - size - (off as usize & 3 & !(size - 1))) * 8;
+                (val << shift) as u32
+            }
+        };
+        *self.rom_latch = word;
+        *self.rom_latched = true;
+    }
+
+    // Return the ROM contents for in-place modification (eg: corruption).
+    pub(crate) fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+
+    // Return the 4-character game code stored in the ROM header
+    // (eg: "NSME" for Super Mario 64 USA).
+    pub fn game_code(&self) -> String {
+        self.rom[0x3B..0x3F]
+            .iter()
+            .map(|&c| if c.is_ascii_graphic() { c as char } else { '?' })
+            .collect()
+    }
+
+    // Return the game title stored in the ROM header.
+    pub fn title(&self) -> String {
+        String::from_utf8_lossy(&self.rom[0x20..0x34])
+            .trim_end_matches(|c: char| c == '\0' || c.is_whitespace())
+            .to_owned()
+    }
+
+    // Return the two checksums stored in the ROM header (CRC1, CRC2).
+    pub fn header_crcs(&self) -> (u32, u32) {
+        (
+            BigEndian::read_u32(&self.rom[0x10..]),
+            BigEndian::read_u32(&self.rom[0x14..]),
+        )
+    }
+
+    // Detect the CIC model by checksumming the header of the ROM.
+    pub fn detect_cic_model(&self) -> result::Result<CicModel, LoadError> {
+        detect_cic(&self.rom[0x40..0x1000])
+    }
 }
@@ -0,0 +1,171 @@
+//! Compatibility database.
+//!
+//! The database (`compat.toml` in the repository) records, for each game
+//! (identified by the 4-character game code in the ROM header), how well it
+//! runs, its known issues, and the settings that it requires. It is edited
+//! by hand, and updated by the regression farm (`tools/regress`) with the
+//! outcome of its last run.
+//!
+//! ```toml
+//! [games.NSME]
+//! title = "Super Mario 64 (USA)"
+//! status = "ingame"
+//! issues = ["no sound"]
+//! settings = ["--fixed-time=0"]
+//! ```
+
+use crate::errors::{LoadError, SaveError};
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+// Comment written at the beginning of the database file, as the regression
+// farm rewrites it.
+const HEADER: &str = "\
+# Compatibility database of r64emu.
+#
+# One entry per game, indexed by the 4-character game code in the ROM header
+# (eg: NSME for Super Mario 64 USA). Fields:
+#
+#   title    = name of the game
+#   status   = unknown, crash, no-boot, boots, ingame, playable, perfect
+#   issues   = list of known issues
+#   settings = command line options required by the game
+#   last_run = outcome of the last run of the regression farm (do not edit)
+#
+# The regression farm updates it with: regress --compat compat.toml ROMDIR.
+# It only changes the status up to \"boots\": higher statuses are set by hand.
+";
+
+/// How well a game runs, from worst to best.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[derive(Default)]
+pub enum CompatStatus {
+    #[default]
+    Unknown, // Never tested
+    Crash,    // The emulator crashes
+    NoBoot,   // Nothing is displayed
+    Boots,    // Something is displayed (intro, menus)
+    Ingame,   // Gameplay works, with major issues
+    Playable, // Playable from start to end, with minor issues
+    Perfect,  // No known issues
+}
+
+impl CompatStatus {
+    /// Return true if the status can be set by the regression farm. Higher
+    /// statuses require playing the game, so they are only set by hand.
+    pub fn is_automatic(self) -> bool {
+        self <= CompatStatus::Boots
+    }
+
+    /// Return the color of the status badge (RGB).
+    pub fn color(self) -> (u8, u8, u8) {
+        match self {
+            CompatStatus::Unknown => (0x90, 0x90, 0x90),
+            CompatStatus::Crash => (0xE0, 0x40, 0x40),
+            CompatStatus::NoBoot => (0xE0, 0x80, 0x30),
+            CompatStatus::Boots => (0xE0, 0xC0, 0x30),
+            CompatStatus::Ingame => (0xA0, 0xD0, 0x40),
+            CompatStatus::Playable => (0x40, 0xC0, 0x40),
+            CompatStatus::Perfect => (0x40, 0xA0, 0xE0),
+        }
+    }
+}
+
+impl fmt::Display for CompatStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CompatStatus::Unknown => "unknown",
+            CompatStatus::Crash => "crash",
+            CompatStatus::NoBoot => "no-boot",
+            CompatStatus::Boots => "boots",
+            CompatStatus::Ingame => "ingame",
+            CompatStatus::Playable => "playable",
+            CompatStatus::Perfect => "perfect",
+        })
+    }
+}
+
+/// The compatibility information of a game.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CompatEntry {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub status: CompatStatus,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub settings: Vec<String>, // Command line options required by the game
+
+    /// Outcome of the last run of the regression farm.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<CompatStatus>,
+}
+
+impl CompatEntry {
+    /// Return true if the last run of the regression farm did worse than
+    /// the recorded status.
+    pub fn regressed(&self) -> bool {
+        self.last_run.is_some_and(|run| run < self.status)
+    }
+}
+
+/// The compatibility database, indexed by game code.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CompatDb {
+    #[serde(default)]
+    pub games: BTreeMap<String, CompatEntry>,
+}
+
+impl FromStr for CompatDb {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CompatDb, String> {
+        toml::from_str(s).map_err(|err| err.to_string())
+    }
+}
+
+impl CompatDb {
+    pub fn to_toml(&self) -> String {
+        format!("{}\n{}", HEADER, toml::to_string_pretty(self).unwrap())
+    }
+
+    /// Load the database from a file.
+    pub fn load(path: &Path) -> Result<CompatDb, LoadError> {
+        let data = fs::read_to_string(path).map_err(|err| LoadError::io(path, err))?;
+        data.parse().map_err(|msg| LoadError::InvalidCompatDb {
+            path: path.display().to_string(),
+            msg,
+        })
+    }
+
+    /// Save the database to a file.
+    pub fn save(&self, path: &Path) -> Result<(), SaveError> {
+        fs::write(path, self.to_toml()).map_err(|err| SaveError::io(path, err))
+    }
+
+    pub fn get(&self, game_code: &str) -> Option<&CompatEntry> {
+        self.games.get(game_code)
+    }
+
+    /// Record the outcome of a run of the regression farm (which can be
+    /// `Crash`, `NoBoot` or `Boots`). The status of the game is updated only
+    /// if it was not set by hand; otherwise, the entry will be marked as
+    /// regressed if the run did worse.
+    pub fn record_run(&mut self, game_code: &str, title: &str, run: CompatStatus) {
+        let entry = self.games.entry(game_code.to_owned()).or_default();
+        if entry.title.is_empty() {
+            entry.title = title.to_owned();
+        }
+        if entry.status.is_automatic() {
+            entry.status = run;
+        }
+        entry.last_run = Some(run);
+    }
+}
@@ -2,9 +2,10 @@ extern crate bit_field;
 extern crate byteorder;
 extern crate emu;
 extern crate slog;
-use super::mi::{IrqMask, Mi};
+use super::mi::{IrqMask, Mi, MiIrq};
 use super::r4300::R4300;
-use super::rdp::Rdp;
+use super::rdp::{Primitive, Rdp};
+use super::VideoBackend;
 use emu::bus::be::{Device, MemIoR, Reg32, RegDeref, RegRef};
 use emu::dbg;
 use emu::int::Numerics;
@@ -37,6 +38,7 @@ impl RegDeref for StatusFlags {
 }
 
 #[derive(DeviceBE)]
+#[device(subword = "shift")]
 pub struct Dp {
     #[reg(bank = 0, offset = 0x0, rwmask = 0x00FFFFFF, wcb)]
     cmd_start: Reg32,
@@ -47,9 +49,12 @@ pub struct Dp {
     #[reg(bank = 0, offset = 0x8, readonly)]
     cmd_current: Reg32,
 
-    #[reg(bank = 0, offset = 0xC, rwmask = 0, wcb)]
+    // Written bits are commands (see cb_write_cmd_status), so they must reach
+    // the callback, which then restores the status.
+    #[reg(bank = 0, offset = 0xC, rwmask = 0x3FF, wcb)]
     cmd_status: Reg32,
 
+    irq: MiIrq, // Interrupt line to MI
     logger: slog::Logger,
 
     fetched_mem: MemIoR<u64>,
@@ -70,6 +75,7 @@ impl Dp {
             cmd_current: Reg32::default(),
             cmd_status: Reg32::default(),
             logger,
+            irq: Mi::irq_line(IrqMask::DP),
             cycles: 0,
             running: false,
             fetched_mem: MemIoR::default(),
@@ -79,6 +85,28 @@ impl Dp {
         })
     }
 
+    /// Return the address of the Z-buffer set by the last Set Z Image
+    /// command, if any.
+    pub(crate) fn z_image(&self) -> Option<u32> {
+        self.gfx.z_image()
+    }
+
+    /// Select how the RDP draws (see N64Builder::video_backend).
+    pub(crate) fn set_video_backend(&mut self, backend: VideoBackend) {
+        self.gfx.set_video_backend(backend);
+    }
+
+    /// Signal the end of a frame to the RDP (see query_pixel).
+    pub(crate) fn end_frame(&mut self) {
+        self.gfx.end_frame();
+    }
+
+    /// Return the primitives of the last frame that touched the pixel at the
+    /// specified RDRAM address. Primitives are only captured while tracing.
+    pub(crate) fn query_pixel(&self, addr: u32) -> Vec<(usize, &Primitive)> {
+        self.gfx.query_pixel(addr)
+    }
+
     fn cmd_status_ref(&self) -> RegRef<StatusFlags> {
         self.cmd_status.as_ref::<StatusFlags>()
     }
@@ -101,7 +129,23 @@ impl Dp {
 
     fn cb_write_cmd_status(&mut self, old: u32, new: u32) {
         self.cmd_status.set(old);
-        warn!(self.logger, "writing to DP status"; o!("val" => new.hex()));
+
+        // Writes are made of pairs of clear/set bits for each flag.
+        let mut status = self.cmd_status_ref();
+        let pairs = [
+            (0, StatusFlags::XBUS_DMA),
+            (2, StatusFlags::FREEZE),
+            (4, StatusFlags::FLUSH),
+        ];
+        for (bit, flag) in pairs.iter() {
+            if new & (1 << bit) != 0 {
+                status.remove(*flag);
+            }
+            if new & (2 << bit) != 0 {
+                status.insert(*flag);
+            }
+        }
+        info!(self.logger, "writing to DP status"; o!("val" => new.hex(), "status" => status.bits().hex()));
     }
 
     fn check_start(&mut self) {
@@ -117,7 +161,14 @@ impl Dp {
             let start = self.cmd_start.get();
             *self.cmd_current_ref() = start;
             self.fetched_start_addr = start;
-            self.fetched_mem = R4300::get().bus.fetch_read::<u64>(start);
+            // With XBUS DMA, commands are fetched from SP DMEM rather than
+            // from RDRAM.
+            let addr = if status.contains(StatusFlags::XBUS_DMA) {
+                0x0400_0000 | (start & 0xFFF)
+            } else {
+                start
+            };
+            self.fetched_mem = R4300::get().bus.fetch_read::<u64>(addr);
             if self.fetched_mem.iter().is_none() {
                 error!(self.logger, "cmd buffer pointing to non-linear memory"; o!("ptr" => start.hex()));
             }
@@ -140,8 +191,9 @@ impl sync::Subsystem for Dp {
         "RDP"
     }
 
-    fn run(&mut self, until: i64, _: &dbg::Tracer) -> dbg::Result<()> {
-        if !self.running {
+    fn run(&mut self, until: i64, t: &dbg::Tracer) -> dbg::Result<()> {
+        self.gfx.set_capture(t.is_active());
+        if !self.running || self.cmd_status_ref().contains(StatusFlags::FREEZE) {
             self.cycles = until;
             return Ok(());
         }
@@ -168,7 +220,7 @@ impl sync::Subsystem for Dp {
             self.check_start();
             if !self.running {
                 self.cycles = until;
-                Mi::get_mut().set_irq_line(IrqMask::DP, true);
+                self.irq.raise();
                 return Ok(());
             }
         }
@@ -0,0 +1,151 @@
+// failure_derive defines its impls inside named consts.
+#![allow(unknown_lints, non_local_definitions)]
+
+use crate::saves::{SaveFormat, SaveMedia};
+use emu::errors::DeviceError;
+use failure::Fail;
+
+use std::io;
+use std::path::Path;
+use std::result;
+
+/// An error happened while loading one of the files required to boot the
+/// emulation (ROM, BIOS).
+#[derive(Debug, Fail)]
+pub enum LoadError {
+    #[fail(display = "cannot access {}: {}", path, err)]
+    Io {
+        path: String,
+        #[cause]
+        err: io::Error,
+    },
+
+    #[fail(display = "unsupported ROM format (header: {:08x})", header)]
+    UnsupportedRomFormat { header: u32 },
+
+    #[fail(display = "ROM file is too small ({} bytes)", size)]
+    RomTooSmall { size: usize },
+
+    #[fail(display = "cannot detect CIC model in ROM (chk = {:08x})", chk)]
+    UnknownCic { chk: u32 },
+
+    #[fail(display = "invalid BIOS size: {} bytes (expected {})", size, expected)]
+    InvalidBiosSize { size: usize, expected: usize },
+
+    #[fail(display = "invalid compatibility database {}: {}", path, msg)]
+    InvalidCompatDb { path: String, msg: String },
+
+    #[fail(display = "cannot apply patch {}: {}", path, msg)]
+    InvalidPatch { path: String, msg: String },
+
+    #[fail(display = "invalid trigger file {}: {}", path, msg)]
+    InvalidTriggers { path: String, msg: String },
+}
+
+impl LoadError {
+    pub(crate) fn io(path: &Path, err: io::Error) -> LoadError {
+        LoadError::Io {
+            path: path.display().to_string(),
+            err,
+        }
+    }
+}
+
+/// A save file could not be converted.
+#[derive(Debug, Fail)]
+pub enum SaveError {
+    #[fail(display = "cannot access {}: {}", path, err)]
+    Io {
+        path: String,
+        #[cause]
+        err: io::Error,
+    },
+
+    #[fail(display = "cannot detect the save type of {}", path)]
+    UnknownType { path: String },
+
+    #[fail(display = "invalid size for {} save: {} bytes", media, size)]
+    InvalidSize { media: SaveMedia, size: usize },
+
+    #[fail(display = "invalid {} save file", format)]
+    InvalidHeader { format: SaveFormat },
+}
+
+impl SaveError {
+    pub(crate) fn io(path: &Path, err: io::Error) -> SaveError {
+        SaveError::Io {
+            path: path.display().to_string(),
+            err,
+        }
+    }
+}
+
+/// A joybus command in PIF RAM could not be executed.
+#[derive(Debug, Fail, PartialEq)]
+pub enum JoybusError {
+    #[fail(display = "joybus command at {:#x} exceeds PIF RAM", offset)]
+    Truncated { offset: usize },
+
+    #[fail(display = "empty joybus command on channel {}", channel)]
+    Empty { channel: usize },
+
+    #[fail(display = "unknown joybus command {:02x}", cmd)]
+    UnknownCommand { cmd: u8 },
+
+    #[fail(
+        display = "invalid lengths for joybus command {:02x} (tx={}, rx={})",
+        cmd, tx, rx
+    )]
+    InvalidLength { cmd: u8, tx: usize, rx: usize },
+}
+
+/// The error type returned by all the fallible operations of the emulator.
+#[derive(Debug, Fail)]
+pub enum EmuError {
+    #[fail(display = "{}", _0)]
+    Load(#[cause] LoadError),
+
+    #[fail(display = "{}", _0)]
+    Device(#[cause] DeviceError),
+
+    #[fail(display = "{}", _0)]
+    Save(#[cause] SaveError),
+
+    /// The machine was configured inconsistently (see N64Builder).
+    #[fail(display = "invalid machine configuration: {}", _0)]
+    Config(String),
+
+    /// A savestate could not be saved or loaded.
+    #[fail(display = "savestate error: {}", _0)]
+    State(String),
+
+    /// The frontend (video/audio output) could not be initialized.
+    #[fail(display = "frontend error: {}", _0)]
+    Frontend(String),
+}
+
+impl From<LoadError> for EmuError {
+    fn from(err: LoadError) -> EmuError {
+        EmuError::Load(err)
+    }
+}
+
+impl From<DeviceError> for EmuError {
+    fn from(err: DeviceError) -> EmuError {
+        EmuError::Device(err)
+    }
+}
+
+impl From<SaveError> for EmuError {
+    fn from(err: SaveError) -> EmuError {
+        EmuError::Save(err)
+    }
+}
+
+impl From<String> for EmuError {
+    fn from(err: String) -> EmuError {
+        EmuError::Frontend(err)
+    }
+}
+
+pub type Result<T> = result::Result<T, EmuError>;
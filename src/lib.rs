@@ -1,6 +1,40 @@
-#![feature(nll)]
-#![feature(stdsimd)]
-#![feature(pin)]
+//! A Nintendo 64 emulator.
+//!
+//! Applications embedding the emulator create the machine with
+//! [`N64::new`](struct.N64.html#method.new) (or with
+//! [`N64Builder`](struct.N64Builder.html), for more options), and then run it
+//! one frame at a time:
+//!
+//! ```no_run
+//! # #[macro_use] extern crate slog;
+//! # extern crate r64emu;
+//! use r64emu::{Buttons, Inputs, OwnedGfxBufferLE, OwnedSndBuffer, Rgb888, N64, S16_STEREO};
+//! use std::path::Path;
+//!
+//! # fn main() -> r64emu::errors::Result<()> {
+//! let logger = slog::Logger::root(slog::Discard, o!());
+//! let mut n64 = N64::new(logger, Path::new("game.z64"), Path::new("pifdata.bin"))?;
+//! let mut video = OwnedGfxBufferLE::<Rgb888>::new(640, 480);
+//! let mut audio = OwnedSndBuffer::<S16_STEREO>::with_capacity(1024);
+//!
+//! let mut inputs = Inputs::default();
+//! inputs.joy[0].buttons = Buttons::START;
+//! n64.run_frame(&mut video.buf_mut(), &mut audio.buf_mut(), &inputs);
+//!
+//! let mut state = Vec::new();
+//! n64.save_state(&mut state)?;
+//! let tv_type: u32 = n64.peek(0x8000_0300); // osTvType, set by the boot code
+//! # Ok(())
+//! # }
+//! ```
+
+// Stylistic lints that don't fit the hardware-oriented code of the crate
+// (eg: bitflags grouped by hardware fields).
+#![allow(
+    clippy::module_inception,
+    clippy::type_complexity,
+    clippy::unusual_byte_groupings
+)]
 
 #[macro_use]
 extern crate slog;
@@ -11,34 +45,39 @@ extern crate byteorder;
 extern crate emu;
 extern crate mips64;
 
-extern crate packed_simd;
-
 #[macro_use]
 extern crate bitflags;
 
-#[macro_use]
-extern crate error_chain;
-
-pub mod errors {
-    error_chain! {
-        foreign_links {
-            Io(::std::io::Error);
-        }
-    }
-}
-
+mod libultra;
 mod rdp;
 
 pub mod ai;
-pub mod r4300;
 pub mod cartridge;
+pub mod compat;
 pub mod dp;
+pub mod errors;
+pub mod mempak;
 pub mod mi;
+pub mod patch;
 pub mod pi;
+pub mod pif;
+pub mod r4300;
+pub mod randnet;
 pub mod ri;
+pub mod saves;
+pub mod savestate;
+pub mod sc64;
+pub mod sdcard;
 pub mod si;
 pub mod sp;
+pub mod triggers;
 pub mod vi;
+pub mod vifilter;
 
 mod n64;
-pub use self::n64::N64;
+pub use self::n64::{BootMode, Buttons, Devices, Inputs, Joypad, N64Builder, VideoBackend, N64};
+
+// Types used by N64::run_frame, so that applications embedding the emulator
+// don't need to depend on the emu crate for them.
+pub use emu::gfx::{GfxBufferMutLE, OwnedGfxBufferLE, Rgb888};
+pub use emu::snd::{OwnedSndBuffer, SndBufferMut, S16_STEREO};
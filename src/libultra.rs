@@ -0,0 +1,298 @@
+//! Recognition of libultra (N64 SDK) data structures in RDRAM, for the
+//! debugger.
+
+use byteorder::{BigEndian, ByteOrder};
+use emu::dbg::{Heap, HeapBlock};
+
+// libaudio heap (arena allocator):
+//
+//   typedef struct {
+//       u8  *base;
+//       u8  *cur;
+//       s32 len;
+//       s32 count;
+//   } ALHeap;
+//
+// Allocations are aligned to 16 bytes. In debug builds of the library, each
+// allocation is preceded by a HeapInfo header:
+//
+//   typedef struct {
+//       s32 magic;  /* AL_HEAP_MAGIC */
+//       s32 size;
+//       u8  *file;
+//       s32 line;
+//       s32 count;
+//       s32 pad0, pad1, pad2;
+//   } HeapInfo;
+const ALHEAP_SIZE: usize = 16;
+const AL_HEAP_MAGIC: u32 = 0x2073_6A73;
+const AL_HEAP_INFO_SIZE: u32 = 32;
+
+// libultra buffer pools (fixed-size allocator):
+//
+//   typedef struct {
+//       u8   *r_startBufferAddress;
+//       void *r_endAddress;
+//       s32  r_bufferSize;
+//       s32  r_bufferCount;
+//       u16  r_freeList;
+//       u16  r_alignSize;
+//   } OSRegion;
+//
+// The control structure is at the beginning of the region. Free buffers are
+// linked through their first halfword; BUF_FREE_WO_NEXT ends the list.
+const OSREGION_SIZE: u32 = 20;
+const BUF_FREE_WO_NEXT: u16 = 0x8000;
+
+// Limit to the length of the source file names in HeapInfo.
+const MAX_FILE_NAME: usize = 64;
+
+// RSP tasks: osSpTaskLoad copies the task descriptor at the end of DMEM
+// (OS_TASK_OFF), where the boot microcode and the task microcode read it:
+//
+//   typedef struct {
+//       u32 type;
+//       u32 flags;
+//       u64 *ucode_boot;
+//       u32 ucode_boot_size;
+//       u64 *ucode;
+//       u32 ucode_size;
+//       u64 *ucode_data;
+//       u32 ucode_data_size;
+//       u64 *dram_stack;
+//       u32 dram_stack_size;
+//       u64 *output_buff;
+//       u64 *output_buff_size;
+//       u64 *data_ptr;
+//       u32 data_size;
+//       u64 *yield_data_ptr;
+//       u32 yield_data_size;
+//   } OSTask_t;
+//
+// Yielding is implemented by the microcode: the CPU requests it with SIG0,
+// the microcode saves its state into the yield buffer and halts with SIG1
+// (yielded) and SIG2 (task done). To resume the task, osSpTaskLoad replaces
+// the microcode data with the yield buffer.
+pub(crate) const OS_TASK_OFF: usize = 0xFC0;
+const OS_TASK_SIZE: usize = 64;
+
+fn read_u32(mem: &[u8], off: usize) -> Option<u32> {
+    mem.get(off..off + 4).map(BigEndian::read_u32)
+}
+
+fn read_u16(mem: &[u8], off: usize) -> Option<u16> {
+    mem.get(off..off + 2).map(BigEndian::read_u16)
+}
+
+// Convert a KSEG0/KSEG1 pointer into an offset in RDRAM.
+fn ptr_offset(mem: &[u8], ptr: u32) -> Option<usize> {
+    if ptr & 0xC000_0000 != 0x8000_0000 {
+        return None;
+    }
+    let off = (ptr & 0x1FFF_FFFF) as usize;
+    if off < mem.len() {
+        Some(off)
+    } else {
+        None
+    }
+}
+
+fn kseg0(off: usize) -> u32 {
+    off as u32 | 0x8000_0000
+}
+
+// Read a NUL-terminated ASCII string.
+fn read_cstr(mem: &[u8], ptr: u32) -> Option<String> {
+    let off = ptr_offset(mem, ptr)?;
+    let bytes: Vec<u8> = mem[off..]
+        .iter()
+        .take(MAX_FILE_NAME)
+        .take_while(|&&c| c != 0)
+        .cloned()
+        .collect();
+    if bytes.is_empty() || !bytes.iter().all(|c| c.is_ascii_graphic()) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn decode_alheap(mem: &[u8], off: usize) -> Option<Heap> {
+    let base = read_u32(mem, off)?;
+    let cur = read_u32(mem, off + 4)?;
+    let len = read_u32(mem, off + 8)?;
+    let count = read_u32(mem, off + 12)?;
+
+    let base_off = ptr_offset(mem, base)?;
+    let end = base.checked_add(len)?;
+    if base & 15 != 0
+        || cur & 15 != 0
+        || len < 0x100
+        || cur <= base
+        || cur > end
+        || (end & 0x1FFF_FFFF) as usize > mem.len()
+        || count > 0x10000
+        || (base_off..base_off + len as usize).contains(&off)
+    {
+        return None;
+    }
+
+    // Walk the debug headers, if any; otherwise, the allocations cannot be
+    // told apart, so report them as a single block.
+    let mut blocks = Vec::new();
+    let mut ptr = base;
+    while ptr < cur {
+        let hoff = ptr_offset(mem, ptr)?;
+        if read_u32(mem, hoff) != Some(AL_HEAP_MAGIC) {
+            blocks.push(HeapBlock {
+                addr: ptr as u64,
+                size: (cur - ptr) as u64,
+                free: false,
+                origin: None,
+            });
+            break;
+        }
+        let size = read_u32(mem, hoff + 4)?;
+        let file = read_u32(mem, hoff + 8)?;
+        let line = read_u32(mem, hoff + 12)?;
+        blocks.push(HeapBlock {
+            addr: (ptr + AL_HEAP_INFO_SIZE) as u64,
+            size: size as u64,
+            free: false,
+            origin: read_cstr(mem, file).map(|f| format!("{}:{}", f, line)),
+        });
+        ptr = ptr.checked_add(AL_HEAP_INFO_SIZE + size)?;
+    }
+    blocks.push(HeapBlock {
+        addr: cur as u64,
+        size: (end - cur) as u64,
+        free: true,
+        origin: None,
+    });
+
+    Some(Heap {
+        kind: "ALHeap".into(),
+        addr: kseg0(off) as u64,
+        base: base as u64,
+        size: len as u64,
+        blocks,
+    })
+}
+
+fn decode_osregion(mem: &[u8], off: usize) -> Option<Heap> {
+    let start = read_u32(mem, off)?;
+    let end = read_u32(mem, off + 4)?;
+    let bufsize = read_u32(mem, off + 8)?;
+    let count = read_u32(mem, off + 12)?;
+    let free_list = read_u16(mem, off + 16)?;
+    let align = read_u16(mem, off + 18)? as u32;
+
+    // The buffers start right after the control structure, aligned.
+    let addr = kseg0(off);
+    let first = (addr + OSREGION_SIZE + align - 1) & !(align.wrapping_sub(1));
+    if ![2, 4, 8, 16].contains(&align)
+        || start != first
+        || bufsize == 0
+        || bufsize & (align - 1) != 0
+        || count == 0
+        || count >= BUF_FREE_WO_NEXT as u32
+        || (end & 0x1FFF_FFFF) as usize > mem.len()
+        || (free_list != BUF_FREE_WO_NEXT && free_list as u32 >= count)
+    {
+        return None;
+    }
+    let size = (bufsize as u64).checked_mul(count as u64)?;
+    if start as u64 + size > end as u64 {
+        return None;
+    }
+
+    // Follow the free list (which cannot be longer than the number of
+    // buffers, unless it is corrupted).
+    let mut free = vec![false; count as usize];
+    let mut idx = free_list;
+    for _ in 0..count {
+        if idx == BUF_FREE_WO_NEXT || idx as u32 >= count || free[idx as usize] {
+            break;
+        }
+        free[idx as usize] = true;
+        idx = read_u16(mem, ptr_offset(mem, start + idx as u32 * bufsize)?)?;
+    }
+
+    Some(Heap {
+        kind: "OSRegion".into(),
+        addr: addr as u64,
+        base: start as u64,
+        size,
+        blocks: free
+            .iter()
+            .enumerate()
+            .map(|(i, &free)| HeapBlock {
+                addr: (start + i as u32 * bufsize) as u64,
+                size: bufsize as u64,
+                free,
+                origin: None,
+            })
+            .collect(),
+    })
+}
+
+/// An RSP task descriptor (OSTask), as found in DMEM.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct OsTask {
+    pub ty: u32,
+    pub ucode: u32,
+    pub ucode_data: u32,
+    pub yield_data_ptr: u32,
+    pub yield_data_size: u32,
+}
+
+impl OsTask {
+    /// Decode the task descriptor at the end of DMEM. Return None if DMEM
+    /// does not contain a libultra task (eg: a homebrew microcode).
+    pub fn decode(dmem: &[u8]) -> Option<OsTask> {
+        let task = dmem.get(OS_TASK_OFF..OS_TASK_OFF + OS_TASK_SIZE)?;
+        let word = |idx: usize| BigEndian::read_u32(&task[idx * 4..]);
+        let ty = word(0);
+        if !(1..=4).contains(&ty) || word(5) == 0 {
+            return None;
+        }
+        Some(OsTask {
+            ty,
+            ucode: word(4),
+            ucode_data: word(6),
+            yield_data_ptr: word(14),
+            yield_data_size: word(15),
+        })
+    }
+
+    /// Name of the task type, as used by the debugger timeline.
+    pub fn track(&self) -> &'static str {
+        match self.ty {
+            1 => "RSP gfx task",
+            2 => "RSP audio task",
+            3 => "RSP video task",
+            4 => "RSP JPEG task",
+            _ => "RSP task",
+        }
+    }
+
+    /// Return true if the task is being resumed after a yield: in this case,
+    /// osSpTaskLoad loads the yield buffer as microcode data.
+    pub fn resumed(&self) -> bool {
+        self.yield_data_ptr != 0 && self.ucode_data == self.yield_data_ptr
+    }
+}
+
+/// Decode the heap whose control structure is at the specified address.
+pub(crate) fn decode_heap(mem: &[u8], addr: u32) -> Option<Heap> {
+    let off = ptr_offset(mem, addr)?;
+    decode_osregion(mem, off).or_else(|| decode_alheap(mem, off))
+}
+
+/// Search RDRAM for heap control structures.
+pub(crate) fn find_heaps(mem: &[u8]) -> Vec<u64> {
+    (0..mem.len().saturating_sub(ALHEAP_SIZE))
+        .step_by(4)
+        .filter(|&off| decode_heap(mem, kseg0(off)).is_some())
+        .map(|off| kseg0(off) as u64)
+        .collect()
+}
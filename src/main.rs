@@ -1,13 +1,27 @@
-#[macro_use]
-extern crate error_chain;
-
+use emu::bus::be::Device;
+use emu::corruptor::CorruptTarget;
+use emu::dbg::{Debugger, DebuggerModel, PausePoint, Symbols, TraceEvent};
+use emu::gfx::{OwnedGfxBufferLE, Rgb888};
 use emu::hw;
+use emu::livesplit::LiveSplit;
 use emu::log;
+use emu::snd::{OwnedSndBuffer, S16_STEREO};
+use emu::time::FixedTime;
+use failure::Fail;
+use r64emu::cartridge::{self, CicModel};
+use r64emu::compat::CompatDb;
 use r64emu::errors::*;
-use r64emu::N64;
+use r64emu::patch;
+use r64emu::r4300::R4300;
+use r64emu::saves::{self, SaveFormat, SaveMedia};
+use r64emu::sp::{OpTrace, RSPCPU};
+use r64emu::triggers;
+use r64emu::{BootMode, N64Builder, VideoBackend, N64};
 
+use std::fs;
 use std::path::Path;
 
+use structopt::clap;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -26,24 +40,460 @@ struct Cli {
     )]
     bios: std::path::PathBuf,
 
+    /// Start in fullscreen mode
+    #[structopt(long = "fullscreen")]
+    fullscreen: bool,
+
+    /// Use exclusive fullscreen (changes video mode) instead of a borderless
+    /// window; the choice is remembered in the configuration file
+    #[structopt(long = "exclusive-fullscreen")]
+    exclusive_fullscreen: bool,
+
+    /// Show the game in a separate window while the debugger is active
+    #[structopt(long = "game-window")]
+    game_window: bool,
+
+    /// Initial scale factor of the separate game window
+    #[structopt(long = "game-window-scale", default_value = "2")]
+    game_window_scale: u32,
+
+    /// Disable vsync in the separate game window
+    #[structopt(long = "game-window-novsync")]
+    game_window_novsync: bool,
+
+    /// Run without any window, until reaching a pause point (see
+    /// --pause-at-*), and print where the emulation stopped
+    #[structopt(
+        long = "headless",
+        raw(conflicts_with_all = r#"&["debugger", "break_at", "gdb", "rewind"]"#)
+    )]
+    headless: bool,
+
+    /// With --headless, save the state into this file when the emulation
+    /// stops
+    #[structopt(
+        long = "dump-state",
+        parse(from_os_str),
+        raw(requires = "\"headless\"")
+    )]
+    dump_state: Option<std::path::PathBuf>,
+
+    /// Pause emulation in the debugger (or stop a --headless run) when
+    /// reaching this frame number
+    #[structopt(long = "pause-at-frame", raw(alias = "\"break-at-frame\""))]
+    pause_at_frame: Option<i64>,
+
+    /// Pause emulation in the debugger (or stop a --headless run) when
+    /// reaching this cycle count
+    #[structopt(long = "pause-at-cycle")]
+    pause_at_cycle: Option<i64>,
+
+    /// Pause emulation in the debugger (or stop a --headless run) when
+    /// reaching this VI scanline
+    #[structopt(long = "pause-at-line")]
+    pause_at_line: Option<usize>,
+
+    /// Stop in the debugger the first time the CPU executes the code at
+    /// this address (hex) or symbol (see --symbols)
+    #[structopt(long = "break-at")]
+    break_at: Option<String>,
+
+    /// Symbol file, in the format of nm (eg: mips64-elf-nm game.elf >
+    /// game.sym), used to resolve --break-at and loaded into the debugger
+    #[structopt(long = "symbols", parse(from_os_str))]
+    symbols: Option<std::path::PathBuf>,
+
+    /// Listen for a remote debugger (GDB remote protocol, eg: gdb-multiarch)
+    /// on this TCP port of localhost
+    #[structopt(long = "gdb")]
+    gdb: Option<u16>,
+
+    /// Reduce the input lag by emulating this number of frames ahead (1-4),
+    /// rolling back when the input changes; requires --fixed-time
+    #[structopt(long = "run-ahead")]
+    run_ahead: Option<usize>,
+
+    /// Take a rewind snapshot every this number of frames, so that the
+    /// emulation can be stepped backwards with the rewind hotkey (F8);
+    /// implies --debugger
+    #[structopt(long = "rewind")]
+    rewind: Option<usize>,
+
+    /// Run the RSP through the threaded-code interpreter instead of the plain one
+    #[structopt(long = "rsp-threaded")]
+    rsp_threaded: bool,
+
+    /// Draw triangles with this backend: software, or opengl (faster, less
+    /// accurate)
+    #[structopt(long = "video-backend", default_value = "software")]
+    video_backend: VideoBackend,
+
+    /// Record the RSP state around the executions of this vector opcode
+    /// (eg: vmrg) into the file specified with --rsp-trace, to create a
+    /// golden test with tools/tracegolden
+    #[structopt(long = "rsp-trace-op", raw(requires = "\"rsp_trace\""))]
+    rsp_trace_op: Option<String>,
+
+    /// File written by --rsp-trace-op
+    #[structopt(long = "rsp-trace", parse(from_os_str))]
+    rsp_trace: Option<std::path::PathBuf>,
+
+    /// Capture a Chrome trace (frames, scheduler, DMA and other activity)
+    /// into this file, viewable with chrome://tracing or Perfetto
+    #[structopt(long = "chrome-trace", parse(from_os_str))]
+    chrome_trace: Option<std::path::PathBuf>,
+
+    /// Start the cartridge clock at this time (seconds since the UNIX epoch)
+    /// and advance it with emulated time only, for deterministic runs
+    #[structopt(long = "fixed-time")]
+    fixed_time: Option<u64>,
+
+    /// Randomly flip bits in memory while running, to test robustness
+    /// (syntax: AREA[@START-END]=RATE, with AREA one of: rdram, rom, and RATE the
+    /// probability of a flip in each frame, between 0 and 1)
+    #[structopt(long = "corrupt", number_of_values = 1)]
+    corrupt: Vec<CorruptTarget>,
+
+    /// Seed of the corruptor (default: random)
+    #[structopt(long = "corrupt-seed")]
+    corrupt_seed: Option<u64>,
+
+    /// Check invariants of the emulated devices, stopping in the debugger
+    /// when one is violated (to catch emulation bugs)
+    #[structopt(long = "paranoid")]
+    paranoid: bool,
+
+    /// Compatibility database, used to show the status of the game
+    #[structopt(long = "compat", parse(from_os_str), default_value = "compat.toml")]
+    compat: std::path::PathBuf,
+
+    /// Memory triggers (TOML), evaluated each frame and logged when they
+    /// fire (eg: achievements, speedrun splits)
+    #[structopt(long = "triggers", parse(from_os_str))]
+    triggers: Option<std::path::PathBuf>,
+
+    /// Send the LiveSplit commands of the triggers to LiveSplit, at this
+    /// address (HOST:PORT of LiveSplit Server, or a named pipe)
+    #[structopt(long = "livesplit", raw(requires = "\"triggers\""))]
+    livesplit: Option<String>,
+
+    /// Controller Pak file for the first controller (created if missing)
+    #[structopt(long = "mempak", parse(from_os_str))]
+    mempak: Option<std::path::PathBuf>,
+
+    /// Connect a Randnet keyboard (mapped to the host keyboard) to the
+    /// specified controller port (1-4)
+    #[structopt(long = "randnet-keyboard")]
+    randnet_keyboard: Option<usize>,
+
+    /// SD card for the emulated SummerCart64: a disk image, or a directory
+    /// (exposed as a FAT32 volume, without writing back any change)
+    #[structopt(long = "sdcard", parse(from_os_str))]
+    sdcard: Option<std::path::PathBuf>,
+
+    /// Patch (IPS, BPS, UPS) applied to the ROM while loading it, without
+    /// modifying the ROM file (default: a patch with the same name as the
+    /// ROM, if any)
+    #[structopt(long = "patch", parse(from_os_str))]
+    patch: Option<std::path::PathBuf>,
+
+    /// Do not apply the patch with the same name as the ROM
+    #[structopt(long = "no-patch")]
+    no_patch: bool,
+
+    /// Skip the boot sequence, starting from the state saved after the
+    /// first boot of the game (saved in the fast boot directory)
+    #[structopt(long = "fast-boot")]
+    fast_boot: bool,
+
+    /// Directory of the states saved by --fast-boot
+    #[structopt(long = "fast-boot-dir", parse(from_os_str), default_value = "fastboot")]
+    fast_boot_dir: std::path::PathBuf,
+
     /// Path to the ROM file
     #[structopt(parse(from_os_str))]
-    rom: std::path::PathBuf,
+    rom: Option<std::path::PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
 }
 
-quick_main!(run);
+#[derive(StructOpt)]
+enum Command {
+    /// Convert a save file (EEPROM, SRAM, FlashRAM, Controller Pak) between
+    /// the formats used by other emulators and flashcarts
+    #[structopt(name = "convert-save")]
+    ConvertSave {
+        /// Save file to convert
+        #[structopt(parse(from_os_str))]
+        input: std::path::PathBuf,
+
+        /// Converted save file
+        #[structopt(parse(from_os_str))]
+        output: std::path::PathBuf,
+
+        /// Save media: eeprom4k, eeprom16k, sram, flashram, mempak
+        /// (default: detected from the input file)
+        #[structopt(long = "media")]
+        media: Option<SaveMedia>,
+
+        /// Format of the input file: raw, swap16, swap32, dexdrive
+        /// (default: detected from the input file)
+        #[structopt(long = "from")]
+        from: Option<SaveFormat>,
+
+        /// Format of the output file: raw, swap16, swap32, dexdrive
+        #[structopt(long = "to", default_value = "raw")]
+        to: SaveFormat,
+    },
+
+    /// Recompute the CRCs in the header of a ROM, which are verified by the
+    /// boot code, and patch them (required after modifying the ROM)
+    #[structopt(name = "fix-crc")]
+    FixCrc {
+        /// ROM file to fix
+        #[structopt(parse(from_os_str))]
+        input: std::path::PathBuf,
+
+        /// Write the fixed ROM into this file, instead of the input file
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+
+        /// Only check the CRCs, failing if they are wrong
+        #[structopt(long = "check")]
+        check: bool,
+
+        /// CIC model: 6101, 6102, 6103, 6105, 6106, for ROMs with a custom
+        /// boot code (default: detected from the boot code)
+        #[structopt(long = "cic")]
+        cic: Option<CicModel>,
+    },
+}
+
+fn main() {
+    if let Err(err) = run() {
+        // Show the error to the user also when the program was not started
+        // from a terminal.
+        let mut msg = err.to_string();
+        for cause in (&err as &dyn Fail).iter_causes() {
+            msg += &format!("\ncaused by: {}", cause);
+        }
+        eprintln!("error: {}", msg);
+        hw::show_error_dialog("R64EMU - Error", &msg);
+        std::process::exit(1);
+    }
+}
 
-fn create_n64(romfn: &Path, biosfn: &Path) -> Result<N64> {
+fn create_n64(args: &Cli, rom: &Path) -> Result<N64> {
     let logger = log::new_console_logger();
-    let mut n64 = N64::new(logger, romfn, biosfn).unwrap();
-    n64.setup_cic(true)?;
+    let patch = match &args.patch {
+        Some(patch) => Some(patch.clone()),
+        None if !args.no_patch => patch::find_patch(rom),
+        None => None,
+    };
+    let mut builder = N64Builder::new(logger)
+        .rom(rom)
+        .bios(&args.bios)
+        .rsp_threaded(args.rsp_threaded)
+        .video_backend(args.video_backend);
+    if let Some(patch) = &patch {
+        builder = builder.patch(patch);
+    }
+    if let Some(secs) = args.fixed_time {
+        builder = builder.time_source(Box::new(FixedTime::from_unix(secs)));
+    }
+    if let Some(mempak) = &args.mempak {
+        builder = builder.mempak(mempak);
+    }
+    if let Some(sdcard) = &args.sdcard {
+        builder = builder.sdcard(sdcard);
+    }
+    if let Some(port) = args.randnet_keyboard {
+        builder = builder.keyboard(port);
+    }
+    if args.fast_boot {
+        builder = builder.boot(BootMode::FastBoot(args.fast_boot_dir.clone()));
+    }
+    let mut n64 = builder.build()?;
+    if let (Some(op), Some(path)) = (&args.rsp_trace_op, &args.rsp_trace) {
+        RSPCPU::get_mut().set_op_trace(Some(OpTrace::create(op, path)?));
+    }
+    if !args.corrupt.is_empty() {
+        n64.set_corruption(args.corrupt.clone(), args.corrupt_seed);
+    }
+    if args.compat.exists() {
+        n64.set_compat_db(&CompatDb::load(&args.compat)?);
+    }
+    if args.paranoid {
+        n64.set_paranoid(true);
+    }
+    if let Some(path) = &args.triggers {
+        n64.set_triggers(triggers::load(path)?);
+    }
+    if let Some(addr) = &args.livesplit {
+        let mut livesplit = LiveSplit::connect(addr)
+            .map_err(|err| format!("cannot connect to LiveSplit at {}: {}", addr, err))?;
+        n64.set_trigger_handler(move |t| {
+            if let Some(cmd) = t.livesplit {
+                if let Err(err) = livesplit.send(cmd) {
+                    eprintln!("cannot send {} to LiveSplit: {}", cmd, err);
+                }
+            }
+        });
+    }
     Ok(n64)
 }
 
+// Resolve an address specified on the command line: a symbol of the symbol
+// file (if any), or a hex number (with or without 0x).
+fn resolve_address(spec: &str, symbols: Option<&Path>) -> Result<u64> {
+    if let Some(path) = symbols {
+        let mut syms = Symbols::default();
+        syms.load(path)
+            .map_err(|err| format!("cannot read symbols {}: {}", path.display(), err))?;
+        if let Some(addr) = syms.lookup(spec) {
+            return Ok(addr);
+        }
+    }
+    u64::from_str_radix(spec.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid address or unknown symbol: {}", spec).into())
+}
+
+// Pause points requested on the command line.
+fn pause_points(args: &Cli) -> Vec<PausePoint> {
+    let mut pps = Vec::new();
+    if let Some(frame) = args.pause_at_frame {
+        pps.push(PausePoint::Frame(frame));
+    }
+    if let Some(cycle) = args.pause_at_cycle {
+        pps.push(PausePoint::Cycle(cycle));
+    }
+    if let Some(line) = args.pause_at_line {
+        pps.push(PausePoint::Scanline(line));
+    }
+    pps
+}
+
+// Run the emulation without any window up to the first pause point (or to
+// a violated invariant, with --paranoid), tracing it like the debugger
+// does. Then print where the emulation stopped and the registers of the
+// main CPU, and save the state if requested.
+fn run_headless(args: &Cli, rom: &Path) -> Result<()> {
+    let pps = pause_points(args);
+    if pps.is_empty() {
+        return Err("--headless requires a pause point (--pause-at-frame, \
+                    --pause-at-cycle or --pause-at-line)"
+            .to_owned()
+            .into());
+    }
+
+    let mut n64 = create_n64(args, rom)?;
+    let mut dbg = Debugger::new(&n64.all_cpus());
+    for pp in pps {
+        dbg.add_pause_point(pp);
+    }
+
+    let mut screen = OwnedGfxBufferLE::<Rgb888>::new(640, 480);
+    let mut sound = OwnedSndBuffer::<S16_STEREO>::with_capacity(4096);
+    let reason = loop {
+        let res = n64.trace_frame(
+            &mut screen.buf_mut(),
+            &mut sound.buf_mut(),
+            &dbg.new_tracer(),
+        );
+        match res.map_err(|evt| *evt) {
+            Ok(()) | Err(TraceEvent::Poll()) => {}
+            Err(TraceEvent::PausePoint(idx)) => {
+                break format!("pause point reached: {}", dbg.remove_pause_point(idx));
+            }
+            Err(TraceEvent::GenericBreak(msg)) => break format!("emulation stopped: {}", msg),
+            Err(evt) => return Err(format!("unexpected debugger event: {:?}", evt).into()),
+        }
+    };
+
+    let ctx = R4300::get().ctx();
+    println!(
+        "{} (frame {}, cycle {})",
+        reason,
+        n64.frames(),
+        n64.cycles()
+    );
+    println!("pc: {:016x}", ctx.pc);
+    for row in 0..8 {
+        let regs: Vec<String> = (row * 4..row * 4 + 4)
+            .map(|idx| format!("{:>4}: {:016x}", mips64::REG_NAMES[idx], ctx.regs[idx]))
+            .collect();
+        println!("{}", regs.join("  "));
+    }
+
+    if let Some(path) = &args.dump_state {
+        let file = fs::File::create(path)
+            .map_err(|err| format!("cannot create {}: {}", path.display(), err))?;
+        n64.save_state(file)?;
+        println!("state saved to {}", path.display());
+    }
+    Ok(())
+}
+
 fn run() -> Result<()> {
     let args = Cli::from_args();
+    match &args.cmd {
+        Some(Command::ConvertSave {
+            input,
+            output,
+            media,
+            from,
+            to,
+        }) => {
+            let media = saves::convert_file(input, output, *media, *from, *to)?;
+            println!("{} save converted to {}", media, to);
+            return Ok(());
+        }
+        Some(Command::FixCrc {
+            input,
+            output,
+            check,
+            cic,
+        }) => {
+            let output = if *check {
+                None
+            } else {
+                Some(output.as_ref().unwrap_or(input).as_path())
+            };
+            let fix = cartridge::fix_crcs_file(input, output, *cic)?;
+            println!(
+                "CIC-NUS-{}: CRCs {:08x} {:08x}",
+                fix.cic as u32, fix.new.0, fix.new.1
+            );
+            if !fix.changed() {
+                println!("CRCs are correct");
+            } else if *check {
+                println!(
+                    "CRCs are wrong (header: {:08x} {:08x})",
+                    fix.old.0, fix.old.1
+                );
+                std::process::exit(1);
+            } else {
+                println!("CRCs fixed (were: {:08x} {:08x})", fix.old.0, fix.old.1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    let rom = match &args.rom {
+        Some(rom) => rom.clone(),
+        None => clap::Error::with_description(
+            "The ROM file is required",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    if args.headless {
+        return run_headless(&args, &rom);
+    }
 
     let mut out = hw::Output::new(
+        log::new_console_logger(),
         hw::VideoConfig {
             window_title: "R64EMU - Nintendo 64 Emulator".into(),
             width: 640,
@@ -57,11 +507,85 @@ fn run() -> Result<()> {
     out.enable_video()?;
     out.enable_audio()?;
 
-    if args.debugger {
-        let mut n64 = create_n64(&args.rom, &args.bios).unwrap();
+    if args.game_window {
+        out.enable_game_window(hw::GameWindowConfig {
+            scale: args.game_window_scale,
+            vsync: !args.game_window_novsync,
+        })?;
+    }
+
+    if args.exclusive_fullscreen {
+        out.config_mut().window.fullscreen_mode = hw::FullscreenMode::Exclusive;
+    }
+    if args.fullscreen {
+        out.toggle_fullscreen();
+    }
+
+    // Pause points are handled by the debugger, so they imply it.
+    let mut debugger = args.debugger;
+    for pp in pause_points(&args) {
+        out.add_pause_point(pp);
+        debugger = true;
+    }
+
+    if let Some(path) = &args.symbols {
+        out.load_symbols(path.clone());
+    }
+    if let Some(spec) = &args.break_at {
+        let pc = resolve_address(spec, args.symbols.as_deref())?;
+        out.set_break_at(N64::MAINCPU_NAME, pc);
+        debugger = true;
+    }
+
+    // The GDB stub is part of the debugger as well.
+    if let Some(port) = args.gdb {
+        out.enable_gdb_stub(&format!("127.0.0.1:{}", port));
+        debugger = true;
+    }
+
+    // Traces are captured by the debugger, so they imply it.
+    if let Some(path) = &args.chrome_trace {
+        out.capture_chrome_trace(path.clone());
+        debugger = true;
+    }
+
+    // Like savestates, rewind requires the emulation to run on the main
+    // thread, that is within the debugger loop.
+    if let Some(frames) = args.rewind {
+        if frames < 1 {
+            return Err("--rewind must be at least 1".to_owned().into());
+        }
+        out.set_rewind(frames);
+        debugger = true;
+    }
+
+    // The OpenGL backend draws with the GL context of the main window, so
+    // the emulation must run on the main thread as well.
+    if args.video_backend == VideoBackend::OpenGl {
+        debugger = true;
+    }
+
+    // Run-ahead rolls back to savestates, so the emulation must be
+    // deterministic.
+    if let Some(frames) = args.run_ahead {
+        if !(1..=hw::MAX_RUN_AHEAD).contains(&frames) {
+            return Err(format!("--run-ahead must be between 1 and {}", hw::MAX_RUN_AHEAD).into());
+        }
+        if args.fixed_time.is_none() {
+            return Err("--run-ahead requires --fixed-time".to_owned().into());
+        }
+        out.set_run_ahead(frames);
+    }
+
+    if debugger {
+        let mut n64 = create_n64(&args, &rom)?;
         out.run_and_debug(&mut n64);
     } else {
-        out.run_threaded(move || Ok(Box::new(create_n64(&args.rom, &args.bios).unwrap())));
+        out.run_threaded(move || {
+            create_n64(&args, &rom)
+                .map(Box::new)
+                .map_err(|err| err.to_string())
+        })?;
     }
 
     Ok(())
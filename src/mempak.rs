@@ -0,0 +1,370 @@
+//! Controller Pak (mempak) emulation and file system.
+//!
+//! A Controller Pak is a 32KB SRAM divided into 128 pages of 256 bytes, with
+//! a simple file system managed by libultra:
+//!
+//!  * page 0: ID area (serial number, device type, checksums).
+//!  * page 1: index table, a linked list of pages for each note (one 16-bit
+//!    entry per page); page 2 is a backup copy.
+//!  * pages 3-4: note table (16 entries of 32 bytes).
+//!  * pages 5-127: note data.
+
+use crate::errors::SaveError;
+use crate::saves::{self, SaveFormat, SaveMedia};
+
+use byteorder::{BigEndian, ByteOrder};
+use emu::dbg::StorageView;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PAGE_SIZE: usize = 0x100;
+const NUM_PAGES: usize = 128;
+const INDEX_PAGE: usize = 1;
+const INDEX_BACKUP_PAGE: usize = 2;
+const NOTE_TABLE: usize = 3 * PAGE_SIZE;
+const NOTE_SIZE: usize = 32;
+const NUM_NOTES: usize = 16;
+const FIRST_DATA_PAGE: usize = 5;
+
+// Special values in the index table.
+const INDEX_END: u16 = 0x0001;
+const INDEX_FREE: u16 = 0x0003;
+
+// Offsets of the copies of the ID block in page 0.
+const ID_BLOCKS: [usize; 4] = [0x20, 0x60, 0x80, 0xC0];
+
+// Status flag of valid notes.
+const NOTE_VALID: u8 = 0x02;
+
+/// A note (file) stored in a Controller Pak.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Note {
+    pub index: usize,      // Index in the note table
+    pub game_code: u32,    // Game code (eg: "NSME" for Super Mario 64 USA)
+    pub publisher: u16,    // Publisher code (eg: "01" for Nintendo)
+    pub name: String,      // Note name, decoded from the N64 font encoding
+    pub extension: String, // Note extension (usually empty)
+    pub pages: usize,      // Number of pages used
+}
+
+// Decode a string in the N64 font encoding (used by note names).
+fn decode_name(raw: &[u8]) -> String {
+    const PUNCT: &[u8] = b"!\"#'*+,-./:=?@";
+    raw.iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| match c {
+            0x0F => ' ',
+            0x10..=0x19 => (b'0' + c - 0x10) as char,
+            0x1A..=0x33 => (b'A' + c - 0x1A) as char,
+            0x34..=0x41 => PUNCT[(c - 0x34) as usize] as char,
+            _ => '_', // Katakana and other symbols
+        })
+        .collect()
+}
+
+/// A Controller Pak, optionally backed by a file.
+pub struct Mempak {
+    data: Vec<u8>,
+    file: Option<(PathBuf, SaveFormat)>,
+    dirty: bool,
+}
+
+impl Mempak {
+    /// Create a new formatted Controller Pak.
+    pub fn new() -> Mempak {
+        let mut pak = Mempak {
+            data: vec![0u8; SaveMedia::Mempak.size()],
+            file: None,
+            dirty: false,
+        };
+        pak.format();
+        pak
+    }
+
+    /// Load a Controller Pak image from a file, in any of the supported save
+    /// formats. If the file does not exist, a new formatted Controller Pak
+    /// is created, and it will be saved there.
+    pub fn load(path: &Path) -> Result<Mempak, SaveError> {
+        let format = match fs::read(path) {
+            Ok(data) => {
+                let format = match saves::detect(path, &data) {
+                    Some((SaveMedia::Mempak, format)) => format,
+                    _ => SaveFormat::Raw,
+                };
+                let mut pak = Mempak::new();
+                pak.data = saves::import(SaveMedia::Mempak, format, &data)?;
+                pak.file = Some((path.to_owned(), format));
+                return Ok(pak);
+            }
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => SaveFormat::Raw,
+            Err(err) => return Err(SaveError::io(path, err)),
+        };
+        let mut pak = Mempak::new();
+        pak.file = Some((path.to_owned(), format));
+        pak.dirty = true;
+        Ok(pak)
+    }
+
+    /// Write the Controller Pak back to its file, if it was modified.
+    pub fn flush(&mut self) -> Result<(), SaveError> {
+        if let (true, Some((path, format))) = (self.dirty, &self.file) {
+            let data = saves::export(SaveMedia::Mempak, *format, &self.data)?;
+            fs::write(path, data).map_err(|err| SaveError::io(path, err))?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Return the raw contents of the Controller Pak.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Read a 32-byte block (joybus command 0x02).
+    pub fn read_block(&self, addr: u16, out: &mut [u8]) {
+        let addr = addr as usize;
+        for (i, b) in out.iter_mut().enumerate() {
+            // Addresses beyond the SRAM are not mapped.
+            *b = self.data.get(addr + i).cloned().unwrap_or(0);
+        }
+    }
+
+    /// Write a 32-byte block (joybus command 0x03).
+    pub fn write_block(&mut self, addr: u16, data: &[u8]) {
+        let addr = addr as usize;
+        if addr + data.len() <= self.data.len() {
+            self.data[addr..addr + data.len()].copy_from_slice(data);
+            self.dirty = true;
+        }
+    }
+
+    /// Initialize an empty file system.
+    pub fn format(&mut self) {
+        for b in self.data.iter_mut() {
+            *b = 0;
+        }
+
+        // ID block (__OSPackId): serial number (left zero), device ID,
+        // number of banks, version, and two checksums of the first 28 bytes.
+        let mut id = [0u8; 32];
+        BigEndian::write_u16(&mut id[0x18..], 0x0001);
+        id[0x1A] = 1;
+        let sum = id[..0x1C]
+            .chunks(2)
+            .fold(0u16, |s, w| s.wrapping_add(BigEndian::read_u16(w)));
+        BigEndian::write_u16(&mut id[0x1C..], sum);
+        BigEndian::write_u16(&mut id[0x1E..], 0xFFF2u16.wrapping_sub(sum));
+        for off in ID_BLOCKS.iter() {
+            self.data[*off..*off + 32].copy_from_slice(&id);
+        }
+
+        for page in 0..NUM_PAGES {
+            self.set_index(page, INDEX_FREE);
+        }
+        self.write_index();
+        self.dirty = true;
+    }
+
+    fn index(&self, page: usize) -> u16 {
+        BigEndian::read_u16(&self.data[INDEX_PAGE * PAGE_SIZE + page * 2..])
+    }
+
+    fn set_index(&mut self, page: usize, val: u16) {
+        BigEndian::write_u16(&mut self.data[INDEX_PAGE * PAGE_SIZE + page * 2..], val);
+    }
+
+    // Update the checksum of the index table, and copy it to its backup.
+    fn write_index(&mut self) {
+        let sum = (FIRST_DATA_PAGE..NUM_PAGES).fold(0u16, |s, p| s.wrapping_add(self.index(p)));
+        self.data[INDEX_PAGE * PAGE_SIZE + 1] = sum as u8;
+        let (index, backup) = self.data.split_at_mut(INDEX_BACKUP_PAGE * PAGE_SIZE);
+        backup[..PAGE_SIZE].copy_from_slice(&index[INDEX_PAGE * PAGE_SIZE..]);
+    }
+
+    fn note_entry(&self, idx: usize) -> &[u8] {
+        let off = NOTE_TABLE + idx * NOTE_SIZE;
+        &self.data[off..off + NOTE_SIZE]
+    }
+
+    // Return the pages of a note, following the index table (and stopping
+    // on corrupted chains).
+    fn note_pages(&self, idx: usize) -> Vec<usize> {
+        let mut pages = Vec::new();
+        let mut page = BigEndian::read_u16(&self.note_entry(idx)[6..]) as usize;
+        while (FIRST_DATA_PAGE..NUM_PAGES).contains(&page) && !pages.contains(&page) {
+            pages.push(page);
+            page = self.index(page) as usize;
+        }
+        pages
+    }
+
+    /// Return the list of valid notes.
+    pub fn notes(&self) -> Vec<Note> {
+        (0..NUM_NOTES)
+            .filter(|&idx| self.note_entry(idx)[8] & NOTE_VALID != 0)
+            .map(|idx| {
+                let e = self.note_entry(idx);
+                Note {
+                    index: idx,
+                    game_code: BigEndian::read_u32(&e[0..]),
+                    publisher: BigEndian::read_u16(&e[4..]),
+                    extension: decode_name(&e[12..16]),
+                    name: decode_name(&e[16..32]),
+                    pages: self.note_pages(idx).len(),
+                }
+            })
+            .collect()
+    }
+
+    /// Return the number of free pages.
+    pub fn free_pages(&self) -> usize {
+        (FIRST_DATA_PAGE..NUM_PAGES)
+            .filter(|&p| self.index(p) == INDEX_FREE)
+            .count()
+    }
+
+    /// Delete a note, freeing its pages.
+    pub fn delete_note(&mut self, idx: usize) {
+        for page in self.note_pages(idx) {
+            self.set_index(page, INDEX_FREE);
+        }
+        self.write_index();
+        let off = NOTE_TABLE + idx * NOTE_SIZE;
+        for b in &mut self.data[off..off + NOTE_SIZE] {
+            *b = 0;
+        }
+        self.dirty = true;
+    }
+
+    /// Export a note as a `.note` file: the 32-byte note entry, followed by
+    /// the contents of its pages.
+    pub fn export_note(&self, idx: usize) -> Vec<u8> {
+        let mut out = self.note_entry(idx).to_vec();
+        for page in self.note_pages(idx) {
+            out.extend_from_slice(&self.data[page * PAGE_SIZE..(page + 1) * PAGE_SIZE]);
+        }
+        out
+    }
+
+    /// Import a note from a `.note` file, into the first free note entry.
+    pub fn import_note(&mut self, note: &[u8]) -> Result<(), String> {
+        if note.len() <= NOTE_SIZE || !(note.len() - NOTE_SIZE).is_multiple_of(PAGE_SIZE) {
+            return Err("invalid note file".into());
+        }
+        let npages = (note.len() - NOTE_SIZE) / PAGE_SIZE;
+        let idx = (0..NUM_NOTES)
+            .find(|&idx| self.note_entry(idx)[8] & NOTE_VALID == 0)
+            .ok_or("no free note entries")?;
+        let free: Vec<usize> = (FIRST_DATA_PAGE..NUM_PAGES)
+            .filter(|&p| self.index(p) == INDEX_FREE)
+            .take(npages)
+            .collect();
+        if free.len() < npages {
+            return Err(format!(
+                "not enough free pages ({} needed, {} free)",
+                npages,
+                self.free_pages()
+            ));
+        }
+
+        for (i, &page) in free.iter().enumerate() {
+            let src = NOTE_SIZE + i * PAGE_SIZE;
+            self.data[page * PAGE_SIZE..(page + 1) * PAGE_SIZE]
+                .copy_from_slice(&note[src..src + PAGE_SIZE]);
+            let next = free.get(i + 1).map_or(INDEX_END, |&p| p as u16);
+            self.set_index(page, next);
+        }
+        self.write_index();
+
+        let off = NOTE_TABLE + idx * NOTE_SIZE;
+        self.data[off..off + NOTE_SIZE].copy_from_slice(&note[..NOTE_SIZE]);
+        BigEndian::write_u16(&mut self.data[off + 6..], free[0] as u16);
+        self.data[off + 8] |= NOTE_VALID;
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+// Format a code stored as ASCII characters (game code, publisher code), or
+// as hex if it is not printable.
+fn format_code(code: &[u8]) -> String {
+    if code.iter().all(|c| c.is_ascii_alphanumeric()) {
+        String::from_utf8_lossy(code).into_owned()
+    } else {
+        code.iter().map(|c| format!("{:02x}", c)).collect()
+    }
+}
+
+impl StorageView for Mempak {
+    fn name(&self) -> &str {
+        "Controller Pak"
+    }
+
+    fn columns(&self) -> &[&str] {
+        &["Name", "Game", "Publisher", "Pages"]
+    }
+
+    fn entries(&self) -> Vec<Vec<String>> {
+        self.notes()
+            .iter()
+            .map(|n| {
+                let mut name = n.name.clone();
+                if !n.extension.is_empty() {
+                    name = format!("{}.{}", name, n.extension);
+                }
+                vec![
+                    name,
+                    format_code(&n.game_code.to_be_bytes()),
+                    format_code(&n.publisher.to_be_bytes()),
+                    n.pages.to_string(),
+                ]
+            })
+            .collect()
+    }
+
+    fn free_space(&self) -> String {
+        format!("{} pages free", self.free_pages())
+    }
+
+    fn delete(&mut self, idx: usize) -> Result<(), String> {
+        let note = self.notes().get(idx).ok_or("invalid note")?.index;
+        self.delete_note(note);
+        Ok(())
+    }
+
+    fn export(&self, idx: usize) -> (String, Vec<u8>) {
+        let note = &self.notes()[idx];
+        let fname = format!(
+            "{}-{}.note",
+            format_code(&note.game_code.to_be_bytes()),
+            note.name.trim()
+        );
+        (fname, self.export_note(note.index))
+    }
+
+    fn import(&mut self, data: &[u8]) -> Result<(), String> {
+        self.import_note(data)
+    }
+}
+
+impl Default for Mempak {
+    fn default() -> Mempak {
+        Mempak::new()
+    }
+}
+
+/// Compute the CRC of a block of data exchanged with a Controller Pak.
+pub fn data_crc(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for i in 0..=data.len() {
+        for bit in (0..8).rev() {
+            let xor = if crc & 0x80 != 0 { 0x85 } else { 0 };
+            crc <<= 1;
+            if i < data.len() && data[i] & (1 << bit) != 0 {
+                crc |= 1;
+            }
+            crc ^= xor;
+        }
+    }
+    crc
+}
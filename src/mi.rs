@@ -1,7 +1,8 @@
-use super::r4300::R4300;
 use emu::bus::be::{Device, Reg32};
+use emu::dbg;
 use emu::int::Numerics;
-use mips64::Cop0;
+use emu::irq::{InterruptController, IrqLine};
+use emu::sync;
 
 use bit_field::BitField;
 use bitflags::bitflags;
@@ -18,7 +19,31 @@ bitflags! {
     }
 }
 
+/// An interrupt line of MI, as seen by the device that drives it. Devices
+/// keep a handle to their line, and raise or acknowledge their interrupt
+/// through it; MI combines the lines with its mask, and drives the
+/// interrupt line of the CPU.
+#[derive(Copy, Clone, Debug)]
+pub struct MiIrq {
+    line: IrqMask,
+}
+
+impl MiIrq {
+    pub fn set(&self, level: bool) {
+        Mi::get_mut().set_irq_line(self.line, level);
+    }
+
+    pub fn raise(&self) {
+        self.set(true);
+    }
+
+    pub fn ack(&self) {
+        self.set(false);
+    }
+}
+
 #[derive(DeviceBE)]
+#[device(subword = "shift")]
 pub struct Mi {
     // 0x04300000 to 0x04300003  MI_INIT_MODE_REG or MI_MODE_REG //MI init mode
     // (W): [0-6] init length        (R): [0-6] init length
@@ -31,12 +56,17 @@ pub struct Mi {
     #[reg(offset = 0x00, wcb)]
     reg_mode: Reg32,
 
+    #[reg(offset = 0x04, init = 0x0202_0102, readonly)]
+    version: Reg32,
+
     #[reg(offset = 0x08, readonly)]
     irq_ack: Reg32,
 
     #[reg(offset = 0x0C, wcb)]
     irq_mask: Reg32,
 
+    irq: InterruptController,
+    cpu_irq: sync::IrqLine, // Output to the CPU (IP2), asserted by irq
     logger: slog::Logger,
 }
 
@@ -44,8 +74,21 @@ impl Mi {
     pub fn new(logger: slog::Logger) -> Box<Mi> {
         Box::new(Mi {
             reg_mode: Reg32::default(),
+            version: Reg32::default(),
             irq_ack: Reg32::default(),
             irq_mask: Reg32::default(),
+            irq: InterruptController::new(
+                "MI",
+                vec![
+                    IrqLine::level("SP"),
+                    IrqLine::level("SI"),
+                    IrqLine::level("AI"),
+                    IrqLine::level("VI"),
+                    IrqLine::level("PI"),
+                    IrqLine::level("DP"),
+                ],
+            ),
+            cpu_irq: sync::IrqLine::new("Mi::cpu_irq"),
             logger,
         })
     }
@@ -87,13 +130,21 @@ impl Mi {
         info!(self.logger, "written reg_mode"; "mode" => mode.hex());
     }
 
+    /// Return a handle to the specified interrupt line, for the device that
+    /// drives it. MI does not need to exist yet, but it must be registered
+    /// before the handle is used.
+    pub fn irq_line(line: IrqMask) -> MiIrq {
+        MiIrq { line }
+    }
+
     pub fn set_irq_line(&mut self, lines: IrqMask, status: bool) {
-        let old = self.irq_ack.get();
-        let new = if status {
-            old | lines.bits()
-        } else {
-            old & !lines.bits()
-        };
+        let old = self.irq.pending();
+        for line in 0..self.irq.lines().len() {
+            if lines.bits() & (1 << line) != 0 {
+                self.irq.set_line(line, status);
+            }
+        }
+        let new = self.irq.pending();
         self.irq_ack.set(new);
 
         if old != new {
@@ -110,15 +161,25 @@ impl Mi {
             }
         }
         self.irq_mask.set(mask);
+        self.irq.set_mask(mask);
         if old != mask {
             info!(self.logger, "changed IRQ mask"; "irq" => ?IrqMask::from_bits(mask));
         }
         self.update_cpu_irq();
     }
 
-    fn update_cpu_irq(&self) {
-        R4300::get_mut()
-            .cop0
-            .set_hwint_line(0, (self.irq_ack.get() & self.irq_mask.get()) != 0);
+    fn update_cpu_irq(&mut self) {
+        self.cpu_irq.set(self.irq.asserted());
+    }
+
+    /// Return the interrupt line of MI to the CPU, to be connected to its
+    /// first hardware interrupt line (IP2).
+    pub fn cpu_irq(&self) -> sync::IrqLine {
+        self.cpu_irq.connect()
+    }
+
+    /// Report raised interrupts to the debugger (once per scanline).
+    pub(crate) fn trace_irq(&mut self, tracer: &dbg::Tracer) {
+        self.irq.trace(tracer);
     }
 }
@@ -1,54 +1,135 @@
-use emu::bus::be::{Bus, Device};
+use emu::bus::be::Device;
+use emu::bus::CurrentDeviceMap;
+use emu::clock::ClockDomain;
+use emu::corruptor::{CorruptTarget, Corruptor};
 use emu::dbg;
-use emu::dbg::{DebuggerModel, DebuggerRenderer};
+use emu::dbg::DebuggerModel;
+#[cfg(feature = "debugger")]
+use emu::dbg::DebuggerRenderer;
+use emu::dma;
 use emu::gfx::{GfxBufferMutLE, Rgb888};
 use emu::hw;
 use emu::input::*;
+use emu::memint::AccessSize;
 use emu::snd::{SampleFormat, SndBufferMut, S16_STEREO};
 use emu::state::{CurrentState, State};
 use emu::sync;
 use emu::sync::Subsystem;
-use emu_derive::DeviceBE;
+use emu::time::TimeSource;
+use emu::trigger::{Trigger, Triggers};
 
-use slog;
-use std::ops::{Deref, DerefMut};
-use std::path::Path;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::DerefMut;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use super::ai::Ai;
-use super::cartridge::{Cartridge, CicModel};
-use super::r4300::R4300;
+use super::cartridge::Cartridge;
+use super::compat::{CompatDb, CompatEntry};
 use super::dp::Dp;
 use super::errors::*;
 use super::mi::Mi;
 use super::mips64;
 use super::pi::Pi;
+use super::r4300::{R4300Config, R4300};
 use super::ri::Ri;
+use super::savestate;
+use super::sc64::Sc64;
 use super::si::Si;
-use super::sp::{Sp, RSPCPU};
+use super::sp::{self, RSPCPUConfig, Sp, StatusFlags, RSPCPU};
 use super::vi::Vi;
 
+mod builder;
+mod embed;
+pub use self::builder::*;
+pub use self::embed::*;
+
 // Used in debugger windows
-pub(crate) const MAINCPU_NAME: &'static str = "R4300";
-pub(crate) const RSPCPU_NAME: &'static str = "RSP";
+pub(crate) const MAINCPU_NAME: &str = "R4300";
+pub(crate) const RSPCPU_NAME: &str = "RSP";
 
 pub struct N64 {
     logger: slog::Logger,
     sync: Box<sync::Sync<SyncEmu>>,
     initial_state: State,
+    last_cpu_pc: u64, // CPU PC at the previous scanline (for idle detection)
+    corruptor: Option<Corruptor>,
+    compat: Option<CompatEntry>, // Entry of the compatibility database
+    timer_handler: Option<Box<dyn FnMut(sync::TimerId)>>,
+    speculative: bool, // Emulating frames ahead (see hw::RunAhead)
+    held_timers: VecDeque<Vec<sync::TimerId>>, // Timers expired in each speculative frame
+    boot_state: Option<PathBuf>, // Where to save the state after boot (fast boot)
+    triggers: Option<Triggers>,
+    trigger_handler: Option<Box<dyn FnMut(&Trigger)>>,
+}
+
+// Magic string and version of the states saved by fast boot. Bump the
+// version when a change makes the saved states unusable.
+const FASTBOOT_MAGIC: &str = "r64emu-fastboot";
+const FASTBOOT_VERSION: u32 = 1;
+
+// Memory areas that can be corrupted by the corruptor.
+const CORRUPT_TARGETS: [&str; 2] = ["rdram", "rom"];
+
+// Corrupt memory at the end of each frame (if requested).
+fn corrupt_memory(corruptor: &mut Option<Corruptor>) {
+    if let Some(c) = corruptor {
+        c.corrupt("rdram", &mut Ri::get_mut().rdram);
+        c.corrupt("rom", Cartridge::get_mut().rom_mut());
+    }
+}
+
+// Evaluate the memory triggers at the end of each frame (if any), reading
+// memory as seen by the main CPU.
+fn eval_triggers(
+    triggers: &mut Option<Triggers>,
+    handler: &mut Option<Box<dyn FnMut(&Trigger)>>,
+    logger: &slog::Logger,
+) {
+    let triggers = match triggers {
+        Some(t) => t,
+        None => return,
+    };
+    let fired = triggers.eval(|addr, size| {
+        let word = R4300::get().peek(addr)? as u64;
+        Some(match size {
+            AccessSize::Size8 => (word >> ((3 - (addr & 3)) * 8)) & 0xFF,
+            AccessSize::Size16 => (word >> ((2 - (addr & 2)) * 8)) & 0xFFFF,
+            AccessSize::Size32 | AccessSize::Size64 => word,
+        })
+    });
+    for idx in fired {
+        let t = &triggers.triggers()[idx];
+        info!(logger, "trigger fired"; "name" => &t.name, "count" => triggers.fired(idx));
+        if let Some(handler) = handler {
+            handler(t);
+        }
+    }
+}
+
+// Log the invariant violations reported by the devices in paranoid mode.
+// With the debugger, they are collected by the tracer instead.
+fn log_violations(logger: &slog::Logger) {
+    for v in emu::paranoid::take_violations() {
+        error!(logger, "invariant violated"; "device" => v.device, "msg" => v.msg);
+    }
 }
 
 // N64 timings
 // https://assemblergames.com/threads/mapping-n64-overclockability-achieved-3-0x-multiplier-but-not-3-0x-speed.51656/
 
 // Oscillators
-const X1: i64 = 14_705_000;
-const X2: i64 = 14_318_000;
+const X1: ClockDomain = ClockDomain::new("X1", 14_705_000);
+const X2: ClockDomain = ClockDomain::new("X2", 14_318_000);
 
-const RDRAM_CLOCK: i64 = X1 * 17;
-const MAIN_CLOCK: i64 = RDRAM_CLOCK / 4;
-const _PIF_CLOCK: i64 = MAIN_CLOCK / 4;
-const _CARTRIDGE_CLOCK: i64 = _PIF_CLOCK / 8; // 1.953 MHZ
-pub(crate) const VCLK: i64 = X2 * 17 / 5; // 48.6812 MHZ
+const RDRAM_CLOCK: ClockDomain = X1.scale("RDRAM", 17, 1);
+pub(crate) const MAIN_CLOCK: ClockDomain = RDRAM_CLOCK.scale("RCP", 1, 4); // 62.5 MHZ
+const CPU_CLOCK: ClockDomain = MAIN_CLOCK.scale("VR4300", 3, 2); // 93.75 MHZ, FIXME: uses DIVMOD
+const _PIF_CLOCK: ClockDomain = MAIN_CLOCK.scale("PIF", 1, 4);
+const _CARTRIDGE_CLOCK: ClockDomain = _PIF_CLOCK.scale("Cartridge", 1, 8); // 1.953 MHZ
+pub(crate) const VCLK: ClockDomain = X2.scale("VCLK", 17, 5); // 48.6812 MHZ
 
 struct SyncEmu;
 impl sync::SyncEmu for SyncEmu {
@@ -62,10 +143,10 @@ impl sync::SyncEmu for SyncEmu {
             vsyncs: vec![],
         }
     }
-    fn subsystem(&self, idx: usize) -> Option<(&mut dyn sync::Subsystem, i64)> {
+    fn subsystem(&self, idx: usize) -> Option<(&mut dyn sync::Subsystem, ClockDomain)> {
         match idx {
-            0 => Some((R4300::get_mut().deref_mut(), MAIN_CLOCK + MAIN_CLOCK / 2)), // FIXME: uses DIVMOD),
-            1 => Some((RSPCPU::get_mut().deref_mut(), MAIN_CLOCK)),
+            0 => Some((R4300::get_mut().deref_mut(), CPU_CLOCK)),
+            1 => Some((Sp::get_mut(), MAIN_CLOCK)),
             2 => Some((Dp::get_mut(), MAIN_CLOCK)),
             3 => Some((Ai::get_mut(), VCLK)),
             4 => Some((Pi::get_mut(), MAIN_CLOCK)),
@@ -74,7 +155,7 @@ impl sync::SyncEmu for SyncEmu {
     }
 }
 
-pub(crate) const JOY_NAMES: [&'static str; 4] = ["joy1", "joy2", "joy3", "joy4"];
+pub(crate) const JOY_NAMES: [&str; 4] = ["joy1", "joy2", "joy3", "joy4"];
 
 fn create_input_manager() -> InputManager {
     let joy = InputDevice::new(
@@ -115,72 +196,205 @@ fn create_input_manager() -> InputManager {
 
 impl N64 {
     pub const AUDIO_OUTPUT_FREQUENCY: i64 = Ai::OUTPUT_FREQUENCY;
+    pub const MAINCPU_NAME: &'static str = MAINCPU_NAME;
 
     pub fn new(logger: slog::Logger, romfn: &Path, biosfn: &Path) -> Result<N64> {
-        let sync = sync::Sync::new(logger.new(o!()), SyncEmu);
-
-        R4300::new(sync::Sync::new_logger(&sync)).register();
-        Mi::new(sync::Sync::new_logger(&sync)).register();
-        Cartridge::new(romfn)
-            .chain_err(|| "cannot open rom file")?
-            .register();
-
-        Pi::new(
-            sync::Sync::new_logger(&sync),
-            biosfn,
-            create_input_manager(),
-        )
-        .chain_err(|| "cannot open BIOS file")?
-        .register();
-        Dp::new(sync::Sync::new_logger(&sync)).register();
-        Sp::new(sync::Sync::new_logger(&sync))?.register();
-        Si::new(sync::Sync::new_logger(&sync)).register();
-        Vi::new(sync::Sync::new_logger(&sync)).register();
-        Ai::new(sync::Sync::new_logger(&sync)).register();
-        Ri::new(sync::Sync::new_logger(&sync)).register();
-
-        // Now that all devices have been created, map the CPU buses.
-        R4300::get_mut().map_bus()?;
-        RSPCPU::get_mut().map_bus()?;
-
-        return Ok(N64 {
-            logger,
-            sync,
-            initial_state: CurrentState().clone(),
-        });
+        N64::with_patch(logger, romfn, None, biosfn)
     }
 
-    // Setup the CIC (copy protection) emulation.
-    pub fn setup_cic(&mut self, hard_reset: bool) -> Result<()> {
-        // The 32-bit word at offset 0x24 in PIF RAM (bus addr: 0x1FC0_07E4)
-        // is filled by PIF during boot. It contains the encryption seed
-        // (that PIF got after negotiation with CIC), and some other information
-        // that the CPU can use.
-
-        // bits     | reg | description
-        // 00080000 | S3  | osRomType (0=GamePack, 1=DD)
-        // 00040000 | S7  | osVersion
-        // 00020000 | S5  | osResetType (1 = NMI, 0 = cold reset)
-        // 0000FF00 | S6  | CIC IPL3 seed value
-        // 000000FF | --  | CIC IPL2 seed value
-        // -------- | S4  | TV Type (0=PAL, 1=NTSC, 2=MPAL)
-
-        // Setup the encryption seed, given the CIC model that we detect
-        // by checksumming the ROM header.
-        let mut seed: u32 = match Cartridge::get().detect_cic_model()? {
-            CicModel::Cic6101 => 0x3F, // starfox
-            CicModel::Cic6102 => 0x3F, // mario
-            CicModel::Cic6103 => 0x78, // banjo
-            CicModel::Cic6105 => 0x91, // zelda
-            CicModel::Cic6106 => 0x85, // f-zero x
-        } << 8;
-
-        // Set the NMI/reset bit
-        if !hard_reset {
-            seed |= 0x0002_0000;
+    /// Create the emulator, applying a patch (IPS, BPS, UPS) to the ROM as
+    /// it is loaded; the ROM file is not modified.
+    pub fn with_patch(
+        logger: slog::Logger,
+        romfn: &Path,
+        patch: Option<&Path>,
+        biosfn: &Path,
+    ) -> Result<N64> {
+        let mut builder = N64Builder::new(logger)
+            .rom(romfn)
+            .bios(biosfn)
+            .boot(BootMode::Deferred);
+        if let Some(patch) = patch {
+            builder = builder.patch(patch);
         }
+        builder.build()
+    }
+
+    /// Change the source of wall-clock time visible to the game (cartridge
+    /// RTC). The default is the host clock; use
+    /// [`FixedTime`](../emu/time/struct.FixedTime.html) for deterministic runs.
+    pub fn set_time_source(&mut self, time: Box<dyn TimeSource>) {
+        Pi::get_mut().set_time_source(time);
+    }
+
+    /// Change the delay between pressing the reset button and the actual
+    /// reset (default: 0.5 seconds, as on the real hardware).
+    pub fn set_nmi_delay(&mut self, delay: Duration) {
+        Pi::get_mut().set_nmi_delay(delay);
+    }
+
+    /// Insert a Controller Pak in the first controller, backed by the
+    /// specified file. If the file does not exist, a new formatted
+    /// Controller Pak is created.
+    pub fn mount_mempak(&mut self, path: &Path) -> Result<()> {
+        Pi::get_mut().mount_mempak(path)?;
+        Ok(())
+    }
+
+    /// Connect a Randnet keyboard to the specified controller port (1-4),
+    /// in place of the controller. It is mapped to the host keyboard.
+    pub fn connect_keyboard(&mut self, port: usize) {
+        assert!(
+            port >= 1 && port <= JOY_NAMES.len(),
+            "invalid controller port"
+        );
+        Pi::get_mut().connect_keyboard(port - 1);
+    }
 
-        R4300::get_mut().bus.write::<u32>(0x1FC0_07E4, seed);
+    /// Insert an SD card in the emulated SummerCart64, backed by a disk
+    /// image or by a host directory (presented as a FAT32 volume).
+    pub fn mount_sdcard(&mut self, path: &Path) -> Result<()> {
+        Sc64::get_mut().insert_sdcard(path)?;
+        Ok(())
+    }
+
+    /// Look up the emulated game in the compatibility database, to show
+    /// its status in the debugger. Returns the entry of the game.
+    pub fn set_compat_db(&mut self, db: &CompatDb) -> &CompatEntry {
+        let code = Cartridge::get().game_code();
+        let entry = db.get(&code).cloned().unwrap_or_default();
+        info!(self.logger, "compatibility"; "game" => code, "status" => entry.status.to_string());
+        for s in &entry.settings {
+            warn!(self.logger, "this game requires a setting"; "setting" => s);
+        }
+        self.compat = Some(entry);
+        self.compat.as_ref().unwrap()
+    }
+
+    /// Enable random corruption of emulated memory ("rdram" or "rom"), to
+    /// test the robustness of the emulation. If no seed is specified, it is
+    /// taken from the time source.
+    pub fn set_corruption(&mut self, targets: Vec<CorruptTarget>, seed: Option<u64>) {
+        for t in &targets {
+            if !CORRUPT_TARGETS.contains(&t.name.as_str()) {
+                warn!(self.logger, "unknown memory area to corrupt"; "target" => t.to_string());
+            }
+        }
+        let seed = seed.unwrap_or_else(|| Pi::get().seed());
+        info!(self.logger, "memory corruption enabled"; "seed" => seed);
+        self.corruptor = Some(Corruptor::new(seed, targets));
+    }
+
+    /// Enable paranoid mode: the devices check invariants that are only
+    /// broken by emulation bugs, and report the violations to the debugger
+    /// (or log them, when running without it).
+    pub fn set_paranoid(&mut self, enabled: bool) {
+        info!(self.logger, "paranoid mode"; "enabled" => enabled);
+        emu::paranoid::set_enabled(enabled);
+    }
+
+    /// Add a timer in emulated time (eg: every 10 frames), for automation
+    /// that must be deterministic. Expirations are reported to the handler
+    /// set with set_timer_handler, while emulation is stopped exactly at the
+    /// requested point. With run-ahead, the expirations in frames emulated
+    /// ahead are instead reported once the frame becomes real (and never if
+    /// it is rolled back). Returns None if too many timers are pending.
+    pub fn add_timer(&mut self, timer: sync::Timer) -> Option<sync::TimerId> {
+        self.sync.add_timer(timer)
+    }
+
+    pub fn remove_timer(&mut self, id: sync::TimerId) -> bool {
+        self.sync.remove_timer(id)
+    }
+
+    /// Set the function called when a timer expires.
+    pub fn set_timer_handler<F: FnMut(sync::TimerId) + 'static>(&mut self, handler: F) {
+        self.timer_handler = Some(Box::new(handler));
+    }
+
+    /// Install memory triggers, evaluated at the end of each frame. Triggers
+    /// that fire are logged, and reported to the handler set with
+    /// set_trigger_handler.
+    pub fn set_triggers(&mut self, triggers: Vec<Trigger>) {
+        info!(self.logger, "memory triggers"; "count" => triggers.len());
+        self.triggers = Some(Triggers::new(triggers));
+    }
+
+    /// Set the function called when a memory trigger fires.
+    pub fn set_trigger_handler<F: FnMut(&Trigger) + 'static>(&mut self, handler: F) {
+        self.trigger_handler = Some(Box::new(handler));
+    }
+
+    /// Enable fast boot: the state of the console right after the boot code
+    /// (PIF ROM and IPL3) has jumped to the game is saved into a per-game file
+    /// in the specified directory, and the next runs start from it, skipping
+    /// the boot sequence. Returns true if the state was loaded. This must be
+    /// called after setup_cic.
+    pub fn set_fast_boot(&mut self, dir: &Path) -> Result<bool> {
+        let path = dir.join(format!("{}.state", self.session_id().unwrap()));
+        if let Ok(file) = File::open(&path) {
+            let mut state = CurrentState().clone();
+            match state.deserialize(BufReader::new(file), FASTBOOT_MAGIC, FASTBOOT_VERSION) {
+                Ok(()) => {
+                    state.make_current();
+                    info!(self.logger, "fast boot: state loaded"; "path" => path.display().to_string());
+                    return Ok(true);
+                }
+                Err(err) => {
+                    warn!(self.logger, "fast boot: cannot load state, booting normally"; "path" => path.display().to_string(), "err" => err.to_string());
+                }
+            }
+        }
+        self.boot_state = Some(path);
+        Ok(false)
+    }
+
+    // Save the state for fast boot (if requested), as soon as the boot code
+    // has jumped to the game in RDRAM.
+    fn save_boot_state(&mut self) {
+        let pc = R4300::get().ctx().get_pc() as u32;
+        if self.boot_state.is_none() || pc < 0x8000_0000 || pc & 0x1FFF_FFFF >= 0x0080_0000 {
+            return;
+        }
+        let path = self.boot_state.take().unwrap();
+        let res = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| File::create(&path))
+            .map_err(failure::Error::from)
+            .and_then(|file| {
+                CurrentState().serialize(BufWriter::new(file), FASTBOOT_MAGIC, FASTBOOT_VERSION)
+            });
+        match res {
+            Ok(()) => {
+                info!(self.logger, "fast boot: state saved"; "path" => path.display().to_string())
+            }
+            Err(err) => {
+                warn!(self.logger, "fast boot: cannot save state"; "path" => path.display().to_string(), "err" => err.to_string())
+            }
+        }
+    }
+
+    /// Setup the CIC (copy protection) emulation, as done by PIF at boot.
+    /// This must be called once after the emulator has been configured.
+    pub fn setup_cic(&mut self, hard_reset: bool) -> Result<()> {
+        Pi::get_mut().setup_cic(hard_reset)?;
+        Ok(())
+    }
+
+    /// Save the state of the whole machine into writer, in a versioned
+    /// binary format (see the [`savestate`](savestate/index.html) module).
+    pub fn save_state<W: Write>(&self, writer: W) -> Result<()> {
+        savestate::save_state(writer)
+    }
+
+    /// Load a state saved by [`save_state`](#method.save_state). The machine
+    /// is left untouched if the state cannot be loaded.
+    pub fn load_state<R: Read>(&mut self, reader: R) -> Result<()> {
+        savestate::load_state(reader)?;
+        if let Some(t) = &mut self.triggers {
+            t.reset();
+        }
         Ok(())
     }
 }
@@ -193,27 +407,76 @@ impl hw::OutputProducer for N64 {
         screen: &mut GfxBufferMutLE<Rgb888>,
         sound: &mut SndBufferMut<Self::AudioSampleFormat>,
     ) {
+        let corruptor = &mut self.corruptor;
+        let logger = &self.logger;
+        let timer_handler = &mut self.timer_handler;
+        let triggers = &mut self.triggers;
+        let trigger_handler = &mut self.trigger_handler;
+        let mut held_timers = if self.speculative {
+            self.held_timers.push_back(vec![]);
+            self.held_timers.back_mut()
+        } else {
+            None
+        };
         self.sync.run_frame(|evt| match evt {
             sync::Event::BeginFrame => {
                 Vi::get_mut().begin_frame(screen);
                 Ai::get_mut().begin_frame(sound);
                 Pi::get_mut().begin_frame();
             }
-            sync::Event::HSync(x, y) if x == 0 => {
+            sync::Event::HSync(0, y) => {
                 Vi::get_mut().set_line(y);
             }
             sync::Event::EndFrame => {
                 Vi::get_mut().end_frame(screen);
                 Ai::get_mut().end_frame(sound);
                 Pi::get_mut().end_frame();
+                corrupt_memory(corruptor);
+                eval_triggers(triggers, trigger_handler, logger);
+                log_violations(logger);
             }
+            sync::Event::Timer(id) => match held_timers.as_mut() {
+                Some(held) => held.push(id),
+                None => {
+                    if let Some(handler) = timer_handler {
+                        handler(id);
+                    }
+                }
+            },
             _ => {}
         });
+        self.save_boot_state();
     }
 
     fn input_manager(&mut self) -> Option<&mut InputManager> {
         Some(&mut Pi::get_mut().input)
     }
+
+    fn game_id(&self) -> Option<String> {
+        Some(Cartridge::get().game_code())
+    }
+
+    // The corruptor is not part of the state, so it flips different bits
+    // when emulating again from a savestate.
+    fn deterministic(&self) -> bool {
+        Pi::get().deterministic_time() && self.corruptor.is_none()
+    }
+
+    fn set_speculative(&mut self, speculative: bool) {
+        self.speculative = speculative;
+    }
+
+    fn commit_speculative_frame(&mut self) {
+        if let Some(ids) = self.held_timers.pop_front() {
+            if let Some(handler) = self.timer_handler.as_mut() {
+                ids.into_iter().for_each(handler);
+            }
+        }
+    }
+
+    fn discard_speculative_frames(&mut self) {
+        self.held_timers.clear();
+    }
 }
 
 impl DebuggerModel for N64 {
@@ -223,6 +486,12 @@ impl DebuggerModel for N64 {
         sound: &mut SndBufferMut<SF>,
         tracer: &dbg::Tracer,
     ) -> dbg::Result<()> {
+        let last_cpu_pc = &mut self.last_cpu_pc;
+        let corruptor = &mut self.corruptor;
+        let timer_handler = &mut self.timer_handler;
+        let logger = &self.logger;
+        let triggers = &mut self.triggers;
+        let trigger_handler = &mut self.trigger_handler;
         self.sync.trace_frame(
             |evt| match evt {
                 sync::Event::BeginFrame => {
@@ -234,14 +503,51 @@ impl DebuggerModel for N64 {
                     Vi::get_mut().end_frame(screen);
                     Ai::get_mut().end_frame(sound);
                     Pi::get_mut().end_frame();
+                    Dp::get_mut().end_frame();
+                    corrupt_memory(corruptor);
+                    eval_triggers(triggers, trigger_handler, logger);
+                }
+                sync::Event::HSync(0, y) => {
+                    let vi_irq = Vi::get_mut().set_line(y);
+                    let halted = Sp::get().get_status().contains(StatusFlags::HALT);
+                    tracer.trace_signal("RSP: halted", halted as u64);
+
+                    let yield_req = Sp::get().get_status().contains(StatusFlags::YIELD);
+                    tracer.trace_signal("RSP: yield request", yield_req as u64);
+
+                    // Frame graph
+                    if !halted {
+                        let track = Sp::get().task().map_or("RSP task", |t| t.track());
+                        tracer.trace_activity(track);
+                    }
+                    Pi::get_mut().trace_dma(tracer);
+                    Si::get_mut().trace_dma(tracer);
+                    Sp::get_mut().trace_dma(tracer);
+                    Ai::get_mut().trace_dma(tracer);
+                    Mi::get_mut().trace_irq(tracer);
+                    R4300::get_mut().cop0.trace_irq(tracer);
+                    if vi_irq {
+                        tracer.trace_activity("VI interrupt");
+                    }
+                    // There is no idle-loop detection, so approximate it: a CPU
+                    // found within a few instructions of where it was at the
+                    // previous scanline is most likely spinning in a wait loop.
+                    let pc = R4300::get().ctx().get_pc();
+                    if pc.wrapping_sub(*last_cpu_pc).wrapping_add(16) <= 32 {
+                        tracer.trace_activity("CPU idle");
+                    }
+                    *last_cpu_pc = pc;
                 }
-                sync::Event::HSync(x, y) if x == 0 => {
-                    Vi::get_mut().set_line(y);
+                sync::Event::Timer(id) => {
+                    if let Some(handler) = timer_handler {
+                        handler(id);
+                    }
                 }
                 _ => {}
             },
             tracer,
         )?;
+        self.save_boot_state();
         Ok(())
     }
 
@@ -253,13 +559,130 @@ impl DebuggerModel for N64 {
         }
     }
 
-    fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
+    #[cfg(feature = "debugger")]
+    fn render_debug<'a, 'ui>(&mut self, dr: &mut DebuggerRenderer<'a, 'ui>) {
         R4300::get_mut().render_debug(dr);
         RSPCPU::get_mut().render_debug(dr);
+        dr.render_dualmemview(Sp::get_mut());
+        dr.render_packetview(Pi::get_mut());
+        dr.render_audioview(Ai::get_mut());
+        dr.render_videoview(Vi::get_mut());
+        dr.render_heapview(Ri::get_mut());
+        dr.render_memview(Ri::get_mut());
+        dr.render_memview(Sp::get_mut());
+        if let Some(pak) = Pi::get_mut().mempak_mut() {
+            dr.render_storageview(pak);
+        }
     }
 
-    fn all_cpus(&self) -> Vec<String> {
-        vec![MAINCPU_NAME.into(), RSPCPU_NAME.into()]
+    fn mem_probes(&self) -> Vec<dbg::MemProbe> {
+        // Track CPU polling of the RSP handshake registers, to debug
+        // microcode synchronization issues.
+        vec![
+            dbg::MemProbe {
+                cpu: MAINCPU_NAME.into(),
+                addr: 0xA404_0010,
+                mask: StatusFlags::HALT.bits() as u64,
+                signal: "CPU: SP_STATUS.HALT".into(),
+            },
+            dbg::MemProbe {
+                cpu: MAINCPU_NAME.into(),
+                addr: 0xA404_001C,
+                mask: 1,
+                signal: "CPU: SP_SEMAPHORE".into(),
+            },
+        ]
+    }
+
+    fn session_id(&self) -> Option<String> {
+        let cart = Cartridge::get();
+        let (crc1, crc2) = cart.header_crcs();
+        Some(format!("{}-{:08X}{:08X}", cart.game_code(), crc1, crc2))
+    }
+
+    fn status_badge(&self) -> Option<dbg::StatusBadge> {
+        let entry = self.compat.as_ref()?;
+        let mut details: Vec<String> = entry
+            .issues
+            .iter()
+            .map(|i| format!("Issue: {}", i))
+            .collect();
+        details.extend(entry.settings.iter().map(|s| format!("Requires: {}", s)));
+        let mut label = entry.status.to_string().to_uppercase();
+        if entry.regressed() {
+            label.push('!');
+            details.push(format!(
+                "Regressed: last run was {}",
+                entry.last_run.unwrap()
+            ));
+        }
+        Some(dbg::StatusBadge {
+            label,
+            color: entry.status.color(),
+            details,
+        })
+    }
+
+    fn query_pixel(&self, x: usize, y: usize) -> Vec<dbg::PixelHit> {
+        let addr = match Vi::get().pixel_addr(x, y) {
+            Some(addr) => addr,
+            None => return vec![],
+        };
+        Dp::get()
+            .query_pixel(addr)
+            .into_iter()
+            .map(|(idx, prim)| dbg::PixelHit {
+                name: format!("#{} {}", idx, prim.name),
+                details: prim
+                    .state
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.clone()))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn framebuffer_pixel(&self, x: usize, y: usize) -> Option<dbg::RawPixel> {
+        Vi::get().framebuffer_pixel(x, y)
+    }
+
+    fn output_size(&self) -> Option<(usize, usize)> {
+        match Vi::get().geometry().output_size() {
+            (0, _) | (_, 0) => None,
+            size => Some(size),
+        }
+    }
+
+    fn hw_events(&self) -> Vec<String> {
+        let mut events = vec![sp::EVENT_TASK_START.into(), sp::EVENT_DMA_DONE.into()];
+        for channel in ["PI DMA", "SI DMA", "SP DMA", "AI DMA"].iter() {
+            events.push(dma::oob_event(channel));
+        }
+        events
+    }
+
+    fn cpu_register(&mut self, cpu_name: &str, reg: &str) -> Option<u64> {
+        match cpu_name {
+            MAINCPU_NAME => dbg::read_register(&mut **R4300::get_mut(), reg),
+            RSPCPU_NAME => dbg::read_register(&mut **RSPCPU::get_mut(), reg),
+            _ => None,
+        }
+    }
+
+    fn cpu_breakpoint_addr(&self, cpu_name: &str, addr: u64) -> u64 {
+        match cpu_name {
+            MAINCPU_NAME => <R4300Config as mips64::Config>::pc_mask(addr as u32) as u64,
+            RSPCPU_NAME => <RSPCPUConfig as mips64::Config>::pc_mask(addr as u32) as u64,
+            _ => addr,
+        }
+    }
+
+    fn cpu_peek(&mut self, cpu_name: &str, addr: u64) -> Option<u32> {
+        match cpu_name {
+            MAINCPU_NAME => R4300::get().peek(addr),
+            RSPCPU_NAME => RSPCPU::get().peek(addr),
+            _ => None,
+        }
     }
 
     fn cycles(&self) -> i64 {
@@ -271,16 +694,17 @@ impl DebuggerModel for N64 {
     }
 
     fn reset(&mut self, hard: bool) {
+        // A hard reset is a power cycle: restore the initial emulator status
+        // (clearing all memories), before resetting the devices. A soft reset
+        // is like pressing the reset button: the devices are reset only after
+        // the pre-NMI interrupt (and devices that are not reset keep their
+        // state: for instance, RDRAM contents are preserved).
         if hard {
-            // Hard reset: restore initial emulator status
             self.initial_state.clone().make_current();
-            self.setup_cic(true).unwrap();
             self.sync.reset();
+            CurrentDeviceMap().reset(true);
         } else {
-            // Soft reset: just trigger a reset on CPUs and hope for the best
-            R4300::get_mut().reset();
-            RSPCPU::get_mut().reset();
-            self.setup_cic(false).unwrap();
+            Pi::get_mut().press_reset();
         }
     }
 }
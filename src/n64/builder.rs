@@ -0,0 +1,461 @@
+//! Construction of the emulated machine.
+//!
+//! [`N64Builder`](struct.N64Builder.html) registers the devices, maps the
+//! buses of the CPUs and plugs the peripherals, either for the full machine
+//! (returning a [`N64`](../struct.N64.html) ready to run), or for a subset of
+//! the devices, to be driven directly by tests (eg: an RSP-only rig).
+
+use emu::bus::be::Device;
+use emu::state::CurrentState;
+use emu::sync;
+use emu::time::TimeSource;
+use mips64::Cop0;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::{create_input_manager, SyncEmu, JOY_NAMES, N64};
+use crate::ai::Ai;
+use crate::cartridge::Cartridge;
+use crate::dp::Dp;
+use crate::errors::*;
+use crate::mi::Mi;
+use crate::pi::Pi;
+use crate::r4300::R4300;
+use crate::ri::{Ri, RDRAM_SIZE, RDRAM_SIZE_EXPANSION};
+use crate::sc64::Sc64;
+use crate::si::Si;
+use crate::sp::{Sp, RSPCPU};
+use crate::vi::Vi;
+
+bitflags! {
+    /// The devices created by N64Builder. The main CPU is always present.
+    pub struct Devices: u32 {
+        const RI =        0b0_0000_0001; // RDRAM interface, and RDRAM
+        const MI =        0b0_0000_0010; // MIPS interface (interrupts)
+        const SP =        0b0_0000_0100; // RSP (with its CPU) and DP command interface
+        const VI =        0b0_0000_1000; // video interface
+        const AI =        0b0_0001_0000; // audio interface
+        const PI =        0b0_0010_0000; // peripheral interface and PIF (requires the BIOS)
+        const SI =        0b0_0100_0000; // serial interface
+        const CARTRIDGE = 0b0_1000_0000; // cartridge (requires the ROM)
+        const SC64 =      0b1_0000_0000; // SummerCart64 flashcart extensions
+
+        /// The RSP alone, to run microcode.
+        const RSP_RIG = Self::SP.bits | Self::MI.bits;
+
+        /// The RCP with RDRAM, without the interfaces to the outer world.
+        const RCP_RIG = Self::RI.bits | Self::MI.bits | Self::SP.bits;
+
+        /// The whole machine except the video interface, for headless runs.
+        const HEADLESS = Self::RI.bits
+            | Self::MI.bits
+            | Self::SP.bits
+            | Self::AI.bits
+            | Self::PI.bits
+            | Self::SI.bits
+            | Self::CARTRIDGE.bits
+            | Self::SC64.bits;
+    }
+}
+
+impl Devices {
+    /// The single devices, with their names.
+    pub const ALL: [(Devices, &'static str); 9] = [
+        (Devices::RI, "RI"),
+        (Devices::MI, "MI"),
+        (Devices::SP, "SP"),
+        (Devices::VI, "VI"),
+        (Devices::AI, "AI"),
+        (Devices::PI, "PI"),
+        (Devices::SI, "SI"),
+        (Devices::CARTRIDGE, "Cartridge"),
+        (Devices::SC64, "SC64"),
+    ];
+
+    // Return the names of the devices, eg: "MI, SP".
+    fn names(self) -> String {
+        Devices::ALL
+            .iter()
+            .filter(|(dev, _)| self.contains(*dev))
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// How the machine is booted by N64Builder::build.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BootMode {
+    /// Run the boot code as after powering on the console.
+    ColdReset,
+    /// Run the boot code as after pressing the reset button.
+    WarmReset,
+    /// Like ColdReset, but save the state after the boot code in the
+    /// specified directory, and start from it in the next runs (see
+    /// N64::set_fast_boot).
+    FastBoot(PathBuf),
+    /// Don't prepare the boot: N64::setup_cic must be called before running
+    /// the emulation (eg: after further configuration).
+    Deferred,
+}
+
+/// Create the emulated machine, choosing its devices, the installed RDRAM,
+/// the peripherals plugged into it, and how it boots.
+///
+/// ```no_run
+/// # #[macro_use]
+/// # extern crate slog;
+/// # extern crate r64emu;
+/// # use r64emu::ri::RDRAM_SIZE_EXPANSION;
+/// # use r64emu::{Devices, N64Builder};
+/// # use std::path::Path;
+/// # fn main() {
+/// # let logger = slog::Logger::root(slog::Discard, o!());
+/// // The full machine, with the Expansion Pak.
+/// let n64 = N64Builder::new(logger.clone())
+///     .rom(Path::new("game.z64"))
+///     .bios(Path::new("bios/pifdata.bin"))
+///     .rdram_size(RDRAM_SIZE_EXPANSION)
+///     .build()
+///     .unwrap();
+///
+/// // Just the RSP, driven directly through RSPCPU.
+/// N64Builder::new(logger)
+///     .devices(Devices::RSP_RIG)
+///     .build_rig()
+///     .unwrap();
+/// # }
+/// ```
+/// How the RDP draws into RDRAM (see N64Builder::video_backend).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VideoBackend {
+    /// The software rasterizer: accurate, and always available.
+    Software,
+    /// Translate triangles and the combiner state into OpenGL draw calls,
+    /// read back into RDRAM at the end of each display list. It requires
+    /// the `frontend` feature, and the GL context of the frontend must be
+    /// current on the thread running the emulation.
+    OpenGl,
+}
+
+impl VideoBackend {
+    pub const ALL: [VideoBackend; 2] = [VideoBackend::Software, VideoBackend::OpenGl];
+
+    /// Return the name of the backend, as accepted by from_str.
+    pub fn name(self) -> &'static str {
+        match self {
+            VideoBackend::Software => "software",
+            VideoBackend::OpenGl => "opengl",
+        }
+    }
+}
+
+impl fmt::Display for VideoBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for VideoBackend {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<VideoBackend, String> {
+        VideoBackend::ALL
+            .iter()
+            .cloned()
+            .find(|b| b.name() == s)
+            .ok_or_else(|| format!("unknown video backend: {}", s))
+    }
+}
+
+pub struct N64Builder {
+    logger: slog::Logger,
+    devices: Devices,
+    rom: Option<PathBuf>,
+    patch: Option<PathBuf>,
+    bios: Option<PathBuf>,
+    rdram_size: usize,
+    mempak: Option<PathBuf>,
+    keyboard: Option<usize>, // controller port (1-4)
+    sdcard: Option<PathBuf>,
+    time: Option<Box<dyn TimeSource>>,
+    rsp_threaded: bool,
+    video_backend: VideoBackend,
+    boot: BootMode,
+}
+
+impl N64Builder {
+    /// Start configuring a full machine, with 4 MiB of RDRAM, booted from a
+    /// cold reset.
+    pub fn new(logger: slog::Logger) -> Self {
+        N64Builder {
+            logger,
+            devices: Devices::all(),
+            rom: None,
+            patch: None,
+            bios: None,
+            rdram_size: RDRAM_SIZE,
+            mempak: None,
+            keyboard: None,
+            sdcard: None,
+            time: None,
+            rsp_threaded: false,
+            video_backend: VideoBackend::Software,
+            boot: BootMode::ColdReset,
+        }
+    }
+
+    /// Select the devices to create (default: all). A subset of the devices
+    /// can only be created with build_rig.
+    pub fn devices(mut self, devices: Devices) -> Self {
+        self.devices = devices;
+        self
+    }
+
+    /// Load the cartridge ROM from the specified file.
+    pub fn rom(mut self, path: &Path) -> Self {
+        self.rom = Some(path.to_owned());
+        self
+    }
+
+    /// Apply a patch (IPS, BPS, UPS) to the ROM as it is loaded; the ROM
+    /// file is not modified.
+    pub fn patch(mut self, path: &Path) -> Self {
+        self.patch = Some(path.to_owned());
+        self
+    }
+
+    /// Load the PIF ROM (BIOS) from the specified file.
+    pub fn bios(mut self, path: &Path) -> Self {
+        self.bios = Some(path.to_owned());
+        self
+    }
+
+    /// Set the amount of installed RDRAM: RDRAM_SIZE, or
+    /// RDRAM_SIZE_EXPANSION to emulate the Expansion Pak.
+    pub fn rdram_size(mut self, size: usize) -> Self {
+        self.rdram_size = size;
+        self
+    }
+
+    /// Insert a Controller Pak in the first controller, backed by the
+    /// specified file (see N64::mount_mempak).
+    pub fn mempak(mut self, path: &Path) -> Self {
+        self.mempak = Some(path.to_owned());
+        self
+    }
+
+    /// Connect a Randnet keyboard to the specified controller port (1-4).
+    pub fn keyboard(mut self, port: usize) -> Self {
+        self.keyboard = Some(port);
+        self
+    }
+
+    /// Insert an SD card in the emulated SummerCart64 (see
+    /// N64::mount_sdcard).
+    pub fn sdcard(mut self, path: &Path) -> Self {
+        self.sdcard = Some(path.to_owned());
+        self
+    }
+
+    /// Set the source of wall-clock time visible to the game (see
+    /// N64::set_time_source).
+    pub fn time_source(mut self, time: Box<dyn TimeSource>) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    /// Run the RSP through the threaded-code interpreter instead of the plain
+    /// interpreter (see RSPCPU::set_threaded).
+    pub fn rsp_threaded(mut self, enabled: bool) -> Self {
+        self.rsp_threaded = enabled;
+        self
+    }
+
+    /// Select how the RDP draws (default: VideoBackend::Software).
+    pub fn video_backend(mut self, backend: VideoBackend) -> Self {
+        self.video_backend = backend;
+        self
+    }
+
+    /// Set how the machine is booted (default: BootMode::ColdReset).
+    pub fn boot(mut self, mode: BootMode) -> Self {
+        self.boot = mode;
+        self
+    }
+
+    /// Create the full machine and prepare it to boot.
+    pub fn build(self) -> Result<N64> {
+        if self.devices != Devices::all() {
+            return Err(EmuError::Config(format!(
+                "the emulation requires all the devices (missing: {})",
+                (Devices::all() - self.devices).names()
+            )));
+        }
+        let logger = self.logger.clone();
+        let boot = self.boot.clone();
+        let sync = self.create()?;
+
+        let mut n64 = N64 {
+            logger,
+            sync,
+            initial_state: CurrentState().clone(),
+            last_cpu_pc: 0,
+            corruptor: None,
+            compat: None,
+            timer_handler: None,
+            speculative: false,
+            held_timers: VecDeque::new(),
+            boot_state: None,
+            triggers: None,
+            trigger_handler: None,
+        };
+        match boot {
+            BootMode::ColdReset => n64.setup_cic(true)?,
+            BootMode::WarmReset => n64.setup_cic(false)?,
+            BootMode::FastBoot(dir) => {
+                n64.setup_cic(true)?;
+                n64.set_fast_boot(&dir)?;
+            }
+            BootMode::Deferred => {}
+        }
+        Ok(n64)
+    }
+
+    /// Create a subset of the machine: the selected devices are registered
+    /// and mapped, and are then driven directly (eg: through
+    /// `RSPCPU::get_mut()`), as there is no emulation loop. The boot mode is
+    /// ignored.
+    pub fn build_rig(self) -> Result<()> {
+        self.create().map(|_| ())
+    }
+
+    // Check that the configuration is consistent.
+    fn check(&self) -> Result<()> {
+        let err = |msg: String| Err(EmuError::Config(msg));
+
+        // Devices that access other devices directly.
+        let deps = [
+            (Devices::SP, Devices::MI),
+            (Devices::VI, Devices::MI | Devices::SP),
+            (Devices::AI, Devices::MI),
+            (Devices::PI, Devices::MI | Devices::SI | Devices::CARTRIDGE),
+            (Devices::SI, Devices::MI),
+            (Devices::SC64, Devices::CARTRIDGE),
+        ];
+        for &(dev, req) in deps.iter() {
+            if self.devices.contains(dev) && !self.devices.contains(req) {
+                return err(format!(
+                    "{} requires {}",
+                    dev.names(),
+                    (req - self.devices).names()
+                ));
+            }
+        }
+
+        if self.devices.contains(Devices::CARTRIDGE) && self.rom.is_none() {
+            return err("no ROM specified".into());
+        }
+        if self.devices.contains(Devices::PI) && self.bios.is_none() {
+            return err("no BIOS specified".into());
+        }
+        if self.rdram_size != RDRAM_SIZE && self.rdram_size != RDRAM_SIZE_EXPANSION {
+            return err(format!("invalid RDRAM size: {} bytes", self.rdram_size));
+        }
+        if let Some(port) = self.keyboard {
+            if port < 1 || port > JOY_NAMES.len() {
+                return err(format!(
+                    "invalid controller port for the keyboard: {}",
+                    port
+                ));
+            }
+        }
+        let pi = self.mempak.is_some() || self.keyboard.is_some() || self.time.is_some();
+        if pi && !self.devices.contains(Devices::PI) {
+            return err("the controllers and the RTC require PI".into());
+        }
+        if self.sdcard.is_some() && !self.devices.contains(Devices::SC64) {
+            return err("the SD card requires SC64".into());
+        }
+        if self.video_backend == VideoBackend::OpenGl {
+            if !cfg!(feature = "frontend") {
+                return err("the OpenGL video backend requires the frontend feature".into());
+            }
+            if !self.devices.contains(Devices::SP) {
+                return err("the OpenGL video backend requires SP".into());
+            }
+        }
+        Ok(())
+    }
+
+    // Register the selected devices, map the buses and plug the peripherals.
+    fn create(self) -> Result<Box<sync::Sync<SyncEmu>>> {
+        self.check()?;
+        let sync = sync::Sync::new(self.logger.new(o!()), SyncEmu);
+        let devices = self.devices;
+        let sub_logger = || sync::Sync::new_logger(&sync);
+
+        R4300::new(sub_logger()).register();
+        if devices.contains(Devices::MI) {
+            Mi::new(sub_logger()).register();
+            R4300::get_mut()
+                .cop0
+                .connect_hwint_line(0, Mi::get().cpu_irq());
+        }
+        // ROM and BIOS are present if required (see check).
+        if devices.contains(Devices::CARTRIDGE) {
+            let rom = self.rom.as_ref().unwrap();
+            Cartridge::with_patch(rom, self.patch.as_deref())?.register();
+            if let Some(patch) = &self.patch {
+                info!(self.logger, "ROM patched"; "patch" => patch.display().to_string());
+            }
+        }
+        if devices.contains(Devices::PI) {
+            let bios = self.bios.as_ref().unwrap();
+            Pi::new(sub_logger(), bios, create_input_manager())?.register();
+        }
+        if devices.contains(Devices::SP) {
+            Dp::new(sub_logger()).register();
+            Sp::new(sub_logger())?.register();
+        }
+        if devices.contains(Devices::SI) {
+            Si::new(sub_logger()).register();
+        }
+        if devices.contains(Devices::VI) {
+            Vi::new(sub_logger()).register();
+        }
+        if devices.contains(Devices::AI) {
+            Ai::new(sub_logger()).register();
+        }
+        if devices.contains(Devices::RI) {
+            Ri::with_rdram_size(sub_logger(), self.rdram_size).register();
+        }
+        if devices.contains(Devices::SC64) {
+            Sc64::new(sub_logger()).register();
+        }
+
+        // Now that all devices have been created, map the CPU buses.
+        R4300::get_mut().map_bus()?;
+        if devices.contains(Devices::SP) {
+            RSPCPU::get_mut().map_bus()?;
+            RSPCPU::get_mut().set_threaded(self.rsp_threaded);
+            Dp::get_mut().set_video_backend(self.video_backend);
+        }
+
+        if let Some(time) = self.time {
+            Pi::get_mut().set_time_source(time);
+        }
+        if let Some(path) = &self.mempak {
+            Pi::get_mut().mount_mempak(path)?;
+        }
+        if let Some(port) = self.keyboard {
+            Pi::get_mut().connect_keyboard(port - 1);
+        }
+        if let Some(path) = &self.sdcard {
+            Sc64::get_mut().insert_sdcard(path)?;
+        }
+        Ok(sync)
+    }
+}
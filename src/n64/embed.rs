@@ -0,0 +1,141 @@
+//! High-level API to embed the emulator in other applications.
+//!
+//! Frontends and tools that drive the emulation themselves (eg: a libretro
+//! core, or a research tool) create the machine with
+//! [`N64::new`](struct.N64.html#method.new) or
+//! [`N64Builder`](struct.N64Builder.html), and then only need
+//! [`run_frame`](struct.N64.html#method.run_frame) with the state of the
+//! controllers, [`save_state`](struct.N64.html#method.save_state) /
+//! [`load_state`](struct.N64.html#method.load_state), and
+//! [`peek`](struct.N64.html#method.peek) / [`poke`](struct.N64.html#method.poke)
+//! to access the memory, without going through the devices.
+
+use emu::bus::be::Device;
+use emu::gfx::{GfxBufferMutLE, Rgb888};
+use emu::hw::OutputProducer;
+use emu::input::{Input, InputEvent, InputManager};
+use emu::memint::MemInt;
+use emu::snd::{SndBufferMut, S16_STEREO};
+
+use super::{JOY_NAMES, N64};
+use crate::pi::Pi;
+use crate::r4300::R4300;
+
+bitflags! {
+    /// The buttons of a controller, at the bit positions reported by the
+    /// controller to the console.
+    #[derive(Default)]
+    pub struct Buttons: u32 {
+        const A =       1 << 31;
+        const B =       1 << 30;
+        const Z =       1 << 29;
+        const START =   1 << 28;
+        const UP =      1 << 27;
+        const DOWN =    1 << 26;
+        const LEFT =    1 << 25;
+        const RIGHT =   1 << 24;
+        const L =       1 << 21;
+        const R =       1 << 20;
+        const C_UP =    1 << 19;
+        const C_DOWN =  1 << 18;
+        const C_LEFT =  1 << 17;
+        const C_RIGHT = 1 << 16;
+    }
+}
+
+impl Buttons {
+    /// All the buttons, with the names of the corresponding inputs in the
+    /// input manager.
+    pub const ALL: [(Buttons, &'static str); 14] = [
+        (Buttons::A, "A"),
+        (Buttons::B, "B"),
+        (Buttons::Z, "Z"),
+        (Buttons::START, "S"),
+        (Buttons::UP, "up"),
+        (Buttons::DOWN, "down"),
+        (Buttons::LEFT, "left"),
+        (Buttons::RIGHT, "right"),
+        (Buttons::L, "L"),
+        (Buttons::R, "R"),
+        (Buttons::C_UP, "c-up"),
+        (Buttons::C_DOWN, "c-down"),
+        (Buttons::C_LEFT, "c-left"),
+        (Buttons::C_RIGHT, "c-right"),
+    ];
+}
+
+/// The state of a controller.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Joypad {
+    pub buttons: Buttons,
+    pub x: i8, // analog stick, as reported to the console
+    pub y: i8,
+}
+
+/// The state of all the inputs of the console for a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Inputs {
+    pub joy: [Joypad; 4],
+    pub reset: bool, // reset button
+}
+
+// Send to the input manager the events for the inputs that changed.
+fn apply_inputs(input: &mut InputManager, inputs: &Inputs) {
+    let mut events = Vec::new();
+    for (joy, &dev_name) in inputs.joy.iter().zip(JOY_NAMES.iter()) {
+        let dev = input.device(dev_name).unwrap();
+        for &(button, name) in Buttons::ALL.iter() {
+            let val = joy.buttons.contains(button);
+            if dev.input(name).and_then(Input::digital) != Some(val) {
+                events.push(InputEvent::Digital(dev_name.into(), name.into(), val));
+            }
+        }
+        for &(name, val) in [("X", joy.x), ("Y", joy.y)].iter() {
+            let val = (val as i16) << 8;
+            if dev.input(name).and_then(Input::analog) != Some(val) {
+                events.push(InputEvent::Analog(dev_name.into(), name.into(), val));
+            }
+        }
+    }
+
+    let reset = input.device("console").and_then(|d| d.input("reset"));
+    if reset.and_then(Input::digital) != Some(inputs.reset) {
+        events.push(InputEvent::Digital(
+            "console".into(),
+            "reset".into(),
+            inputs.reset,
+        ));
+    }
+
+    for evt in events {
+        input.process_event(evt);
+    }
+}
+
+impl N64 {
+    /// Emulate a frame with the specified inputs, drawing it into video and
+    /// the corresponding audio samples into audio. video must be at least
+    /// 640x480.
+    pub fn run_frame(
+        &mut self,
+        video: &mut GfxBufferMutLE<Rgb888>,
+        audio: &mut SndBufferMut<S16_STEREO>,
+        inputs: &Inputs,
+    ) {
+        apply_inputs(&mut Pi::get_mut().input, inputs);
+        self.render_frame(video, audio);
+    }
+
+    /// Read from the memory map of the main CPU, at the specified physical
+    /// address (KSEG0/KSEG1 addresses are accepted as well). Notice that
+    /// reading some hardware registers has side effects.
+    pub fn peek<U: MemInt + 'static>(&self, addr: u32) -> U {
+        R4300::get().bus.read::<U>(addr & 0x1FFF_FFFF)
+    }
+
+    /// Write into the memory map of the main CPU, at the specified physical
+    /// address (KSEG0/KSEG1 addresses are accepted as well).
+    pub fn poke<U: MemInt + 'static>(&mut self, addr: u32, val: U) {
+        R4300::get_mut().bus.write::<U>(addr & 0x1FFF_FFFF, val);
+    }
+}
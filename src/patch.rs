@@ -0,0 +1,314 @@
+//! ROM patches (IPS, BPS, UPS).
+//!
+//! Patches are applied to the ROM when it is loaded (soft-patching), so the
+//! original file is never modified. They are applied to the big-endian
+//! contents of the ROM, as that is the format that romhacks are distributed
+//! for. BPS and UPS patches contain checksums of the original and patched
+//! ROM, which are verified.
+
+use crate::errors::LoadError;
+use crc::crc32;
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Maximum size of a patched ROM. The largest N64 ROMs are 64 MiB, so a
+/// larger size can only come from a corrupted patch.
+pub const MAX_PATCHED_SIZE: usize = 64 << 20;
+
+/// A format of ROM patch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Bps,
+    Ups,
+}
+
+impl PatchFormat {
+    pub const ALL: [PatchFormat; 3] = [PatchFormat::Ips, PatchFormat::Bps, PatchFormat::Ups];
+
+    /// Return the file extension (and name) of the format.
+    pub fn name(self) -> &'static str {
+        match self {
+            PatchFormat::Ips => "ips",
+            PatchFormat::Bps => "bps",
+            PatchFormat::Ups => "ups",
+        }
+    }
+
+    fn magic(self) -> &'static [u8] {
+        match self {
+            PatchFormat::Ips => b"PATCH",
+            PatchFormat::Bps => b"BPS1",
+            PatchFormat::Ups => b"UPS1",
+        }
+    }
+
+    /// Detect the format of a patch from its header.
+    pub fn detect(patch: &[u8]) -> Option<PatchFormat> {
+        PatchFormat::ALL
+            .iter()
+            .cloned()
+            .find(|f| patch.starts_with(f.magic()))
+    }
+}
+
+impl fmt::Display for PatchFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name().to_uppercase())
+    }
+}
+
+/// Find a patch with the same name of the ROM (eg: "game.bps" for
+/// "game.z64"), which is applied automatically.
+pub fn find_patch(rom: &Path) -> Option<PathBuf> {
+    PatchFormat::ALL
+        .iter()
+        .map(|f| rom.with_extension(f.name()))
+        .find(|path| path.is_file())
+}
+
+/// Apply a patch to a ROM, returning the patched ROM.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(rom, patch),
+        Some(PatchFormat::Bps) => apply_bps(rom, patch),
+        Some(PatchFormat::Ups) => apply_ups(rom, patch),
+        None => Err("unknown patch format".into()),
+    }
+}
+
+/// Apply a patch file to a ROM; see apply.
+pub fn apply_file(rom: &[u8], path: &Path) -> Result<Vec<u8>, LoadError> {
+    let patch = fs::read(path).map_err(|err| LoadError::io(path, err))?;
+    apply(rom, &patch).map_err(|msg| LoadError::InvalidPatch {
+        path: path.display().to_string(),
+        msg,
+    })
+}
+
+// A cursor over the contents of a patch.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Reader { data, pos }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let b = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| "truncated patch".to_owned())?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    // Big-endian integer of n bytes (IPS).
+    fn be(&mut self, n: usize) -> Result<usize, String> {
+        Ok(self
+            .bytes(n)?
+            .iter()
+            .fold(0, |acc, &b| acc << 8 | b as usize))
+    }
+
+    // Variable-length integer (BPS, UPS).
+    fn varint(&mut self) -> Result<usize, String> {
+        let overflow = || "invalid number".to_owned();
+        let (mut val, mut shift) = (0usize, 1usize);
+        loop {
+            let b = self.u8()?;
+            val = ((b & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|v| val.checked_add(v))
+                .ok_or_else(overflow)?;
+            if b & 0x80 != 0 {
+                return Ok(val);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(overflow)?;
+            val = val.checked_add(shift).ok_or_else(overflow)?;
+        }
+    }
+
+    // Size of the patched ROM (BPS, UPS).
+    fn target_size(&mut self) -> Result<usize, String> {
+        let size = self.varint()?;
+        if size > MAX_PATCHED_SIZE {
+            return Err(format!("patched ROM is too big ({} bytes)", size));
+        }
+        Ok(size)
+    }
+
+    // Signed offset (BPS): the lowest bit is the sign.
+    fn offset(&mut self) -> Result<isize, String> {
+        let v = self.varint()?;
+        let mag = (v >> 1) as isize;
+        Ok(if v & 1 != 0 { -mag } else { mag })
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = rom.to_vec();
+    let mut r = Reader::new(patch, 5);
+    loop {
+        let off = r.be(3)?;
+        if off == 0x454F46 {
+            break; // "EOF"
+        }
+        let size = r.be(2)?;
+        let (data, size) = if size == 0 {
+            // RLE record
+            let size = r.be(2)?;
+            (None, size)
+        } else {
+            (Some(r.bytes(size)?), size)
+        };
+        if out.len() < off + size {
+            out.resize(off + size, 0);
+        }
+        match data {
+            Some(data) => out[off..off + size].copy_from_slice(data),
+            None => {
+                let val = r.u8()?;
+                out[off..off + size].iter_mut().for_each(|b| *b = val);
+            }
+        }
+    }
+    // Optional extension: size of the patched ROM.
+    if let Ok(size) = r.be(3) {
+        out.truncate(size);
+    }
+    Ok(out)
+}
+
+// Split the 12-byte footer of BPS/UPS patches (CRCs of the original ROM,
+// patched ROM and patch), checking the CRC of the patch itself.
+fn footer(patch: &[u8]) -> Result<(u32, u32, usize), String> {
+    if patch.len() < 4 + 12 {
+        return Err("truncated patch".into());
+    }
+    let end = patch.len() - 12;
+    let le = |off: usize| {
+        patch[off..off + 4]
+            .iter()
+            .rev()
+            .fold(0u32, |acc, &b| acc << 8 | b as u32)
+    };
+    if crc32::checksum_ieee(&patch[..end + 8]) != le(end + 8) {
+        return Err("corrupted patch (invalid checksum)".into());
+    }
+    Ok((le(end), le(end + 4), end))
+}
+
+fn check_source(rom: &[u8], crc: u32) -> Result<(), String> {
+    if crc32::checksum_ieee(rom) != crc {
+        return Err("the patch is for a different ROM (or ROM version)".into());
+    }
+    Ok(())
+}
+
+fn check_target(out: &[u8], crc: u32) -> Result<(), String> {
+    if crc32::checksum_ieee(out) != crc {
+        return Err("patched ROM has an invalid checksum".into());
+    }
+    Ok(())
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let (src_crc, dst_crc, end) = footer(patch)?;
+    check_source(rom, src_crc)?;
+
+    let mut r = Reader::new(&patch[..end], 4);
+    let _src_size = r.varint()?;
+    let dst_size = r.target_size()?;
+    let meta_size = r.varint()?;
+    r.bytes(meta_size)?;
+
+    let mut out = Vec::with_capacity(dst_size);
+    let (mut src_rel, mut dst_rel) = (0isize, 0isize);
+    let invalid = || "invalid copy in patch".to_owned();
+    while r.pos < end {
+        let cmd = r.varint()?;
+        let len = (cmd >> 2) + 1;
+        if len > dst_size - out.len() {
+            return Err("patched ROM has an invalid size".into());
+        }
+        match cmd & 3 {
+            // Copy from the same position of the original ROM
+            0 => {
+                let pos = out.len();
+                let data = rom.get(pos..pos + len).ok_or_else(invalid)?;
+                out.extend_from_slice(data);
+            }
+            // Copy from the patch
+            1 => out.extend_from_slice(r.bytes(len)?),
+            // Copy from anywhere in the original ROM
+            2 => {
+                src_rel += r.offset()?;
+                let pos = src_rel as usize;
+                if src_rel < 0 || pos + len > rom.len() {
+                    return Err(invalid());
+                }
+                out.extend_from_slice(&rom[pos..pos + len]);
+                src_rel += len as isize;
+            }
+            // Copy from the patched ROM (possibly overlapping, to repeat data)
+            _ => {
+                dst_rel += r.offset()?;
+                if dst_rel < 0 || dst_rel as usize >= out.len() {
+                    return Err(invalid());
+                }
+                for _ in 0..len {
+                    let b = *out.get(dst_rel as usize).ok_or_else(invalid)?;
+                    out.push(b);
+                    dst_rel += 1;
+                }
+            }
+        }
+    }
+
+    if out.len() != dst_size {
+        return Err("patched ROM has an invalid size".into());
+    }
+    check_target(&out, dst_crc)?;
+    Ok(out)
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let (src_crc, dst_crc, end) = footer(patch)?;
+    check_source(rom, src_crc)?;
+
+    let mut r = Reader::new(&patch[..end], 4);
+    let _src_size = r.varint()?;
+    let dst_size = r.target_size()?;
+
+    // Each hunk is XOR-ed with the original ROM (considering bytes beyond
+    // its end as zero), and terminated by a zero byte.
+    let mut out = rom.to_vec();
+    out.resize(dst_size, 0);
+    let mut pos = 0;
+    while r.pos < end {
+        pos += r.varint()?;
+        loop {
+            let x = r.u8()?;
+            if pos < out.len() {
+                out[pos] ^= x;
+            }
+            pos += 1;
+            if x == 0 {
+                break;
+            }
+        }
+    }
+
+    check_target(&out, dst_crc)?;
+    Ok(out)
+}
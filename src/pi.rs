@@ -1,24 +1,38 @@
-use super::mi::{IrqMask, Mi};
+use super::cartridge::Cartridge;
+use super::mi::{IrqMask, Mi, MiIrq};
+use super::n64::{JOY_NAMES, MAIN_CLOCK};
 use super::r4300::R4300;
-use super::n64::JOY_NAMES;
+use super::ri::RDRAM_DMA_WRAP;
 use super::si::Si;
-use crate::errors::*;
+use crate::errors::{JoybusError, LoadError, SaveError};
+use crate::mempak::Mempak;
+use crate::pif::{
+    self, CARTRIDGE_CHANNEL, CONTROL, CONTROL_CHALLENGE, CONTROL_JOYBUS, PIF_RAM_SIZE,
+};
+use crate::randnet;
 use bitfield::Bit;
 use byteorder::{BigEndian, ByteOrder};
 use emu::bus::be::{Device, Mem, MemFlags, Reg32};
+use emu::bus::CurrentDeviceMap;
 use emu::dbg;
+use emu::dbg::{Packet, PacketView};
+use emu::dma::{Dma, DmaTiming, DmaXfer};
 use emu::input::{InputManager, InputValue};
 use emu::int::Numerics;
 use emu::state::Field;
 use emu::sync;
+use emu::time::{DateTime, RealTime, TimeSource};
 use emu_derive::DeviceBE;
+use mips64::Cop0;
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io::Read;
-use std::ops::Range;
 use std::path::Path;
 use std::result;
+use std::time::Duration;
 
 #[derive(DeviceBE)]
+#[device(reset, subword = "shift")]
 pub struct Pi {
     #[mem(bank = 1, offset = 0x0, vsize = 0x7C0)]
     rom: Mem,
@@ -45,23 +59,29 @@ pub struct Pi {
     // (R) [0] DMA busy             (W): [0] reset controller
     //     [1] IO busy                       (and abort current op)
     //     [2] error                     [1] clear intr
-    #[reg(bank = 0, offset = 0x10, rwmask = 0, wcb)]
+    //     [3] interrupt
+    #[reg(bank = 0, offset = 0x10, wcb)]
     dma_status: Reg32,
 
+    // Domain 1 (cartridge ROM) timings are reset to the slowest ones, and
+    // then configured by the boot code from the first word of the ROM
+    // header (eg: 0x80371240 -> latency 0x40, pulse width 0x12, page size
+    // 7, release 3).
+
     // [7:0] domain 1 device latency
-    #[reg(bank = 0, offset = 0x0014, rwmask = 0)]
+    #[reg(bank = 0, offset = 0x0014, rwmask = 0xFF, init = 0xFF)]
     dom1_latency: Reg32,
 
     // [7:0] domain 1 device R/W strobe pulse width
-    #[reg(bank = 0, offset = 0x0018, rwmask = 0)]
+    #[reg(bank = 0, offset = 0x0018, rwmask = 0xFF, init = 0xFF)]
     dom1_pulse_width: Reg32,
 
     // [3:0] domain 1 device page size
-    #[reg(bank = 0, offset = 0x001C, rwmask = 0xF)]
+    #[reg(bank = 0, offset = 0x001C, rwmask = 0xF, init = 0xF)]
     dom1_page_size: Reg32,
 
     // [1:0] domain 1 device R/W release duration
-    #[reg(bank = 0, offset = 0x0020, rwmask = 0x3)]
+    #[reg(bank = 0, offset = 0x0020, rwmask = 0x3, init = 0x3)]
     dom1_release: Reg32,
 
     // [7:0] domain 2 device latency
@@ -80,22 +100,89 @@ pub struct Pi {
     #[reg(bank = 0, offset = 0x0030, rwmask = 0x3)]
     dom2_release: Reg32,
 
+    irq: MiIrq, // Interrupt line to MI
     logger: slog::Logger,
     cycles: Field<i64>,
     pub(crate) input: InputManager,
+    joybus_log: VecDeque<JoybusTxn>,
+    joybus_count: usize,
+    dma: Dma,
+    time: Box<dyn TimeSource>, // Wall clock for the cartridge RTC
+    mempak: Option<Mempak>,    // Controller Pak inserted in the first controller
+    keyboard: Option<usize>,   // Joybus channel of the Randnet keyboard, if connected
+    nmi_countdown: Field<i64>, // Cycles until the NMI after a reset, if pending
+    nmi_delay: i64,            // Delay between the pre-NMI interrupt and the NMI
+}
+
+// Delay between pressing the reset button and the NMI on the real hardware.
+// Games use this time to handle the pre-NMI interrupt (eg: to save data or
+// to fade out the audio).
+const NMI_DELAY: Duration = Duration::from_millis(500);
+
+// CPU interrupt line connected to the reset button (IP4).
+const PRENMI_HWINT_LINE: usize = 2;
+
+// Number of joybus transactions kept for the debugger.
+const JOYBUS_LOG_SIZE: usize = 32;
+
+// Size of the PIF boot ROM (BIOS), in bytes.
+const PIF_ROM_SIZE: usize = 0x7C0;
+
+// Bits of PI_STATUS.
+const STATUS_DMA_BUSY: u32 = 1 << 0;
+const STATUS_IO_BUSY: u32 = 1 << 1;
+const STATUS_INTERRUPT: u32 = 1 << 3;
+
+// Bits written to PI_STATUS (unrelated to the bits read from it).
+const STATUS_W_RESET: u32 = 1 << 0;
+const STATUS_W_CLEAR_INTERRUPT: u32 = 1 << 1;
+
+// Return true if the cartridge address belongs to PI domain 2 (64DD
+// registers, SRAM and FlashRAM), rather than domain 1 (ROM).
+fn is_domain2(addr: u32) -> bool {
+    (0x0500_0000..0x0600_0000).contains(&addr) || (0x0800_0000..0x1000_0000).contains(&addr)
+}
+
+// A joybus transaction, recorded for the debugger: contents of PIF RAM
+// before and after the joybus commands were executed.
+struct JoybusTxn {
+    id: usize,
+    before: [u8; PIF_RAM_SIZE],
+    after: [u8; PIF_RAM_SIZE],
 }
 
 impl Pi {
-    pub fn new(logger: slog::Logger, pifrom: &Path, input: InputManager) -> Result<Box<Pi>> {
+    pub fn new(
+        logger: slog::Logger,
+        pifrom: &Path,
+        input: InputManager,
+    ) -> result::Result<Box<Pi>, LoadError> {
         let mut contents = vec![];
-        File::open(pifrom)?.read_to_end(&mut contents)?;
+        File::open(pifrom)
+            .and_then(|mut file| file.read_to_end(&mut contents))
+            .map_err(|err| LoadError::io(pifrom, err))?;
+        if contents.len() != PIF_ROM_SIZE {
+            return Err(LoadError::InvalidBiosSize {
+                size: contents.len(),
+                expected: PIF_ROM_SIZE,
+            });
+        }
 
         Ok(Box::new(Pi {
             logger,
+            irq: Mi::irq_line(IrqMask::PI),
             rom: Mem::from_buffer("pif_rom", contents, MemFlags::READACCESS),
             ram: Mem::default(),
             cycles: Field::new("Pi::cycles", 0),
-            input: input,
+            input,
+            joybus_log: VecDeque::with_capacity(JOYBUS_LOG_SIZE),
+            joybus_count: 0,
+            dma: Dma::new("PI DMA", DmaTiming::default()),
+            time: Box::new(RealTime),
+            mempak: None,
+            keyboard: None,
+            nmi_countdown: Field::new("Pi::nmi_countdown", 0),
+            nmi_delay: duration_cycles(NMI_DELAY),
             dma_ram_addr: Reg32::default(),
             dma_rom_addr: Reg32::default(),
             dma_rd_len: Reg32::default(),
@@ -113,188 +200,427 @@ impl Pi {
     }
 
     fn cb_write_dma_status(&mut self, old: u32, new: u32) {
-        self.dma_status.set(old); // write bits are not related to read bits
         info!(self.logger, "write dma status"; o!("val" => format!("{:x}", new)));
-        Mi::get_mut().set_irq_line(IrqMask::PI, false);
+        let mut status = old; // write bits are not related to read bits
+        if new & STATUS_W_RESET != 0 {
+            // Abort the DMA in progress: its interrupt will never be raised.
+            self.dma.abort();
+            status &= !(STATUS_DMA_BUSY | STATUS_IO_BUSY);
+        }
+        if new & STATUS_W_CLEAR_INTERRUPT != 0 {
+            status &= !STATUS_INTERRUPT;
+            self.irq.ack();
+        }
+        self.dma_status.set(status);
+    }
+
+    // Return the number of cycles taken by a DMA of len bytes from/to the
+    // specified cartridge address, with the timings of its PI domain. The
+    // bus transfers 16-bit words: each word takes the pulse width and the
+    // release duration, and each page (of 4 << page_size bytes) also takes
+    // the latency.
+    fn dma_cycles(&self, cart_addr: u32, len: usize) -> i64 {
+        let (lat, pwd, pgs, rls) = if is_domain2(cart_addr) {
+            (
+                &self.dom2_latency,
+                &self.dom2_pulse_width,
+                &self.dom2_page_size,
+                &self.dom2_release,
+            )
+        } else {
+            (
+                &self.dom1_latency,
+                &self.dom1_pulse_width,
+                &self.dom1_page_size,
+                &self.dom1_release,
+            )
+        };
+        let page = 4usize << pgs.get();
+        let pages = (cart_addr as usize % page + len).div_ceil(page);
+        let words = len.div_ceil(2);
+        pages as i64 * (lat.get() as i64 + 1)
+            + words as i64 * (pwd.get() as i64 + 1 + rls.get() as i64 + 1)
+    }
+
+    // Start the timing of a DMA: the data was already transferred, but the
+    // controller stays busy (and the interrupt is raised) until the time
+    // taken by the transfer on the cartridge bus has elapsed.
+    fn start_dma(&mut self, cart_addr: u32, len: usize) {
+        let cycles = self.dma_cycles(cart_addr, len);
+        self.dma.add_busy(cycles);
+        self.dma_status
+            .set(self.dma_status.get() | STATUS_DMA_BUSY | STATUS_IO_BUSY);
+    }
+
+    // The DMA is complete: raise the interrupt.
+    fn end_dma(&mut self) {
+        let status = self.dma_status.get() & !(STATUS_DMA_BUSY | STATUS_IO_BUSY);
+        self.dma_status.set(status | STATUS_INTERRUPT);
+        self.irq.raise();
     }
 
     fn cb_write_dma_wr_len(&mut self, _old: u32, len: u32) {
-        let mut raddr = self.dma_rom_addr.get();
-        let mut waddr = self.dma_ram_addr.get();
+        let raddr = self.dma_rom_addr.get();
+        let waddr = self.dma_ram_addr.get();
         info!(self.logger, "DMA xfer"; o!(
             "src(rom)" => raddr.hex(),
             "dst(ram)" => waddr.hex(),
             "len" => len+1));
 
-        let bus = &mut R4300::get_mut().bus;
-        let mut i = 0;
-        while i < len + 1 {
-            let data = bus.read::<u32>(raddr);
-            bus.write::<u32>(waddr, data);
-            raddr = raddr + 4;
-            waddr = waddr + 4;
-            i += 4;
+        // Transfers are performed in 32-bit words. The RDRAM address wraps
+        // around at the end of the 24-bit address space.
+        let len = (len as usize + 1 + 3) & !3;
+        let end = self.dma.xfer(
+            &mut R4300::get_mut().bus,
+            DmaXfer::linear(raddr, waddr, len).wrap(0, RDRAM_DMA_WRAP),
+        );
+        if self.dma.out_of_bounds() {
+            warn!(self.logger, "DMA xfer out of bounds"; o!(
+                "src(rom)" => raddr.hex(),
+                "dst(ram)" => waddr.hex(),
+                "len" => len));
         }
-        self.dma_rom_addr.set(raddr);
-        self.dma_ram_addr.set(waddr);
-        Mi::get_mut().set_irq_line(IrqMask::PI, true);
+        self.dma_rom_addr.set(end.src);
+        self.dma_ram_addr.set(end.dst);
+        self.start_dma(raddr, len);
     }
 
     fn cb_write_dma_rd_len(&mut self, _old: u32, val: u32) {
         let mut raddr = self.dma_ram_addr.get();
         let mut waddr = self.dma_rom_addr.get();
+        let cart_addr = waddr;
         info!(self.logger, "DMA xfer"; o!(
             "src(ram)" => raddr.hex(),
             "dst(rom)" => waddr.hex(),
             "len" => val+1));
 
+        // The cartridge is read-only, so each word just goes through the
+        // PI write latch: only the last one is visible afterwards. As for
+        // the other direction, the RDRAM address wraps around at the end of
+        // the 24-bit address space.
         let bus = &mut R4300::get_mut().bus;
         let mut i = 0;
         while i < val + 1 {
             let v = bus.read::<u32>(raddr);
-            info!(self.logger, "DMA DATA"; "data" => v.hex());
+            bus.write::<u32>(waddr, v);
 
-            raddr = raddr + 4;
-            waddr = waddr + 4;
+            raddr = (raddr + 4) & (RDRAM_DMA_WRAP - 1);
+            waddr = waddr.wrapping_add(4);
             i += 4;
+            if raddr == 0 && i < val + 1 {
+                warn!(self.logger, "DMA xfer out of bounds"; o!(
+                    "src(ram)" => self.dma_ram_addr.get().hex(),
+                    "len" => val+1));
+                self.dma.set_out_of_bounds();
+            }
         }
-        Mi::get_mut().set_irq_line(IrqMask::PI, true);
-
-        unimplemented!();
+        self.dma_ram_addr.set(raddr);
+        self.dma_rom_addr.set(waddr);
+        self.start_dma(cart_addr, i as usize);
     }
 
-    pub fn begin_frame(&mut self) {
-        self.input.begin_frame();
+    /// Change the source of wall-clock time used by the cartridge RTC.
+    pub fn set_time_source(&mut self, time: Box<dyn TimeSource>) {
+        self.time = time;
     }
-    pub fn end_frame(&mut self) {
-        self.input.end_frame();
+
+    /// Change the delay between pressing the reset button and the NMI.
+    pub fn set_nmi_delay(&mut self, delay: Duration) {
+        self.nmi_delay = duration_cycles(delay);
     }
 
-    fn joybus_cmd(
-        &mut self,
-        ch: usize,
-        cmd: Range<usize>,
-        out: Range<usize>,
-    ) -> result::Result<(), &'static str> {
-        if cmd.len() == 0 {
-            return Err("joybus: 0-len command");
+    /// Press the reset button. The CPU immediately receives the pre-NMI
+    /// interrupt, and the actual reset (NMI) happens later, after the
+    /// configured delay. Pressing the button again while a reset is already
+    /// pending has no effect.
+    pub fn press_reset(&mut self) {
+        if *self.nmi_countdown > 0 {
+            return;
         }
+        info!(self.logger, "reset button pressed"; o!("nmi_delay" => self.nmi_delay));
+        R4300::get_mut()
+            .cop0
+            .set_hwint_line(PRENMI_HWINT_LINE, true);
+        *self.nmi_countdown = self.nmi_delay.max(1);
+    }
 
-        match self.ram[cmd.start] {
-            0 => {
-                // Read controller status
-                if ch == 0 {
-                    self.ram[out.start + 0] = 0x05;
-                    self.ram[out.start + 1] = 0x00;
-                    self.ram[out.start + 2] = 0x02;
-                }
-            }
-            1 => {
-                // Read input data
-                if ch < 4 {
-                    let mut value: u32 = 0;
-                    self.input
-                        .device(JOY_NAMES[ch])
-                        .unwrap()
-                        .visit(|i| match i.value() {
-                            InputValue::Digital(val) => {
-                                if val {
-                                    value.set_bit(i.custom_id(), true);
-                                }
-                            }
-                            InputValue::Analog(val) => {
-                                value |= ((val >> 8) as u8 as u32) << i.custom_id()
-                            }
-                            _ => unreachable!(),
-                        });
-
-                    // S+Left+Right => Reset.
-                    if value.bit(21) && value.bit(20) && value.bit(18) {
-                        value.set_bit(23, true);
-                    }
+    /// Insert a Controller Pak in the first controller, backed by the
+    /// specified file (which is created if it does not exist).
+    pub fn mount_mempak(&mut self, path: &Path) -> result::Result<(), SaveError> {
+        self.mempak = Some(Mempak::load(path)?);
+        Ok(())
+    }
 
-                    BigEndian::write_u32(&mut self.ram[out.start..], value);
-                }
+    /// Return the Controller Pak inserted in the first controller, if any.
+    pub fn mempak_mut(&mut self) -> Option<&mut Mempak> {
+        self.mempak.as_mut()
+    }
+
+    /// Connect a Randnet keyboard to the specified controller port (0-3),
+    /// in place of the controller.
+    pub fn connect_keyboard(&mut self, port: usize) {
+        self.input.add_device(randnet::input_device());
+        self.keyboard = Some(port);
+    }
+
+    // Execute a joybus command sent to the Randnet keyboard.
+    fn keyboard_cmd(&mut self, cmd: &[u8], out: &mut [u8]) -> pif::Result<()> {
+        match cmd[0] {
+            pif::CMD_INFO | pif::CMD_RESET => {
+                pif::check_len(cmd, out, 1, 3)?;
+                out[..3].copy_from_slice(&randnet::DEVICE_TYPE);
             }
-            _ => {
-                return Err("invalid command");
+            randnet::CMD_READ_KEYS => {
+                pif::check_len(cmd, out, 2, 7)?;
+                let dev = self.input.device(randnet::DEVICE_NAME).unwrap();
+                randnet::read_keys(dev, &mut out[..7]);
+                self.input.mark_polled();
             }
-        };
+            cmd => return Err(JoybusError::UnknownCommand { cmd }),
+        }
+        Ok(())
+    }
+
+    /// Return true if the time source is deterministic (see
+    /// TimeSource::deterministic).
+    pub(crate) fn deterministic_time(&self) -> bool {
+        self.time.deterministic()
+    }
+
+    /// Return a seed for random number generators, from the time source.
+    pub(crate) fn seed(&self) -> u64 {
+        self.time.seed()
+    }
 
-        return Ok(());
+    // Read a block of the cartridge RTC. Block 2 contains the current
+    // time, in BCD; the other blocks (control registers) read as zero.
+    fn rtc_read(&self, block: u8, out: &mut [u8]) {
+        for b in out.iter_mut() {
+            *b = 0;
+        }
+        if block == 2 {
+            let now = self.time.now(MAIN_CLOCK.duration(*self.cycles));
+            let dt = DateTime::from_system_time(now);
+            let bcd = |v: u32| (((v / 10 % 10) << 4) | (v % 10)) as u8;
+            out[0] = bcd(dt.second);
+            out[1] = bcd(dt.minute);
+            out[2] = bcd(dt.hour) | 0x80; // 24-hour mode
+            out[3] = bcd(dt.day);
+            out[4] = bcd(dt.weekday);
+            out[5] = bcd(dt.month);
+            out[6] = bcd(dt.year % 100);
+            out[7] = bcd(dt.year / 100 - 19);
+        }
     }
 
-    fn joybus_exec(&mut self) -> result::Result<(), &'static str> {
-        let mut ch = 0;
-        let mut idx = 0;
-        while idx < 0x3F {
-            let t = self.ram[idx];
-            idx += 1;
-            if t == 0xFE {
-                // Special marker: end of joybus
-                return Ok(());
+    // Setup the CIC (copy protection) emulation.
+    pub(crate) fn setup_cic(&mut self, hard_reset: bool) -> result::Result<(), LoadError> {
+        // The 32-bit word at offset 0x24 in PIF RAM (bus addr: 0x1FC0_07E4)
+        // is filled by PIF during boot. It contains the encryption seed
+        // (that PIF got after negotiation with CIC), and some other information
+        // that the CPU can use.
+
+        // bits     | reg | description
+        // 00080000 | S3  | osRomType (0=GamePack, 1=DD)
+        // 00040000 | S7  | osVersion
+        // 00020000 | S5  | osResetType (1 = NMI, 0 = cold reset)
+        // 0000FF00 | S6  | CIC IPL3 seed value
+        // 000000FF | --  | CIC IPL2 seed value
+        // -------- | S4  | TV Type (0=PAL, 1=NTSC, 2=MPAL)
+
+        // Setup the encryption seed, given the CIC model that we detect
+        // by checksumming the boot code.
+        let cic = Cartridge::get().detect_cic_model()?;
+        info!(self.logger, "CIC detected"; o!("model" => cic as u32));
+        let mut seed = cic.pif_seed();
+
+        // Set the NMI/reset bit
+        if !hard_reset {
+            seed |= 0x0002_0000;
+        }
+
+        BigEndian::write_u32(&mut self.ram[0x24..], seed);
+        Ok(())
+    }
+
+    // On both kinds of reset, the PIF negotiates again with the CIC, and
+    // reports the kind of reset to the boot code.
+    fn cb_reset(&mut self, hard: bool) {
+        if hard {
+            *self.nmi_countdown = 0;
+            R4300::get_mut()
+                .cop0
+                .set_hwint_line(PRENMI_HWINT_LINE, false);
+        }
+        if let Err(err) = self.setup_cic(hard) {
+            error!(self.logger, "cannot setup CIC after reset"; o!("err" => err.to_string()));
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.input.begin_frame();
+
+        let reset = self.input.device("console").and_then(|d| d.input("reset"));
+        if reset.and_then(|i| i.digital()) == Some(true) {
+            self.press_reset();
+        }
+    }
+
+    /// Report DMA activity to the debugger (once per scanline).
+    pub(crate) fn trace_dma(&mut self, tracer: &dbg::Tracer) {
+        self.dma.trace(tracer);
+    }
+
+    pub fn end_frame(&mut self) {
+        self.input.end_frame();
+        if let Some(pak) = self.mempak.as_mut() {
+            if let Err(err) = pak.flush() {
+                error!(self.logger, "cannot save controller pak"; o!("err" => err.to_string()));
             }
-            if t < 0x80 {
-                let r = *self.ram.get(idx).ok_or("joybus: premature end of RAM")?;
-                idx += 1;
+        }
+    }
 
-                if ch >= 1 {
-                    self.ram[idx - 1] |= 0x80;
+    // Return the state of the buttons and stick of a controller, as
+    // reported by the joybus state command.
+    fn controller_state(&mut self, ch: usize) -> u32 {
+        let mut value: u32 = 0;
+        self.input
+            .device(JOY_NAMES[ch])
+            .unwrap()
+            .visit(|i| match i.value() {
+                InputValue::Digital(val) => {
+                    if val {
+                        value.set_bit(i.custom_id(), true);
+                    }
                 }
+                InputValue::Analog(val) => value |= ((val >> 8) as u8 as u32) << i.custom_id(),
+                _ => unreachable!(),
+            });
+
+        self.input.mark_polled();
+
+        // S+Left+Right => Reset.
+        if value.bit(21) && value.bit(20) && value.bit(18) {
+            value.set_bit(23, true);
+        }
+        value
+    }
+
+    // Execute a joybus command sent to the specified channel. Return false
+    // if no device is connected to it: the four controllers (the Controller
+    // Pak is only inserted in the first one), the Randnet keyboard and the
+    // cartridge RTC are emulated.
+    fn joybus_cmd(&mut self, ch: usize, cmd: &[u8], out: &mut [u8]) -> pif::Result<bool> {
+        if self.keyboard == Some(ch) {
+            self.keyboard_cmd(cmd, out)?;
+            return Ok(true);
+        }
 
-                let mid = idx + t as usize;
-                let end = mid + r as usize;
-                self.joybus_cmd(ch, idx..mid, mid..end)?;
-                idx = end;
-                ch += 1;
+        match ch {
+            0..=3 => {
+                let state = self.controller_state(ch);
+                let pak = if ch == 0 { self.mempak.as_mut() } else { None };
+                pif::controller_cmd(cmd, out, state, pak)?;
             }
+            CARTRIDGE_CHANNEL => match cmd[0] {
+                6 => {
+                    // RTC status
+                    pif::check_len(cmd, out, 1, 3)?;
+                    out[..3].copy_from_slice(&[0x00, 0x10, 0x00]);
+                }
+                7 => {
+                    // RTC read block (8 bytes of data, plus status)
+                    pif::check_len(cmd, out, 2, 9)?;
+                    self.rtc_read(cmd[1], &mut out[..8]);
+                    out[8] = 0x00;
+                }
+                8 => {
+                    // RTC write block: setting the clock is not supported, as
+                    // the time always comes from the time source.
+                    pif::check_len(cmd, out, 1, 1)?;
+                    out[0] = 0x00;
+                }
+                _ => return Ok(false),
+            },
+            _ => return Ok(false),
         }
-        return Err("joybus: no PIFRAM marker found");
+        Ok(true)
+    }
+
+    /// Execute the commands requested by the control byte of PIF RAM: the
+    /// challenge of the CIC, and the joybus commands. This is done by the
+    /// PIF when a SI DMA read starts.
+    pub(crate) fn run_commands(&mut self) {
+        if self.ram[CONTROL] & CONTROL_CHALLENGE != 0 {
+            info!(self.logger, "CIC challenge");
+            pif::cic_challenge(&mut self.ram[..]);
+            self.ram[CONTROL] &= !CONTROL_CHALLENGE;
+        }
+        if self.ram[CONTROL] & CONTROL_JOYBUS == 0 {
+            return;
+        }
+        let mut before = [0u8; PIF_RAM_SIZE];
+        before.copy_from_slice(&self.ram[..]);
+
+        let mut ram = before;
+        if let Err(err) = pif::execute(&mut ram, |ch, cmd, out| self.joybus_cmd(ch, cmd, out)) {
+            warn!(self.logger, "joybus error"; o!("err" => err.to_string()));
+        }
+        ram[CONTROL] &= !CONTROL_JOYBUS;
+        self.ram[..].copy_from_slice(&ram);
+
+        if self.joybus_log.len() == JOYBUS_LOG_SIZE {
+            self.joybus_log.pop_front();
+        }
+        self.joybus_log.push_back(JoybusTxn {
+            id: self.joybus_count,
+            before,
+            after: ram,
+        });
+        self.joybus_count += 1;
     }
 }
 
+// Convert a duration into a number of cycles of the PI clock.
+fn duration_cycles(d: Duration) -> i64 {
+    (MAIN_CLOCK.hz() * d.as_micros() as f64 / 1_000_000.0) as i64
+}
+
 impl sync::Subsystem for Pi {
     fn name(&self) -> &str {
         "Pi"
     }
 
-    fn run(&mut self, target_cycles: i64, _tracer: &dbg::Tracer) -> dbg::Result<()> {
-        // FIXME: we have no timing info at the moment. Let's just do everything
-        // we can when we are called.
+    fn run(&mut self, target_cycles: i64, tracer: &dbg::Tracer) -> dbg::Result<()> {
+        let elapsed = target_cycles - *self.cycles;
         *self.cycles = target_cycles;
-
-        let status = self.ram[0x3F];
-        if status & 0x20 != 0 {
-            info!(self.logger, "unlock boot");
-            self.ram[0x3F] |= 0x80;
-            self.ram[0x3F] &= !0x20;
+        if self.dma.run(elapsed) {
+            self.end_dma();
         }
 
-        if status & 0x01 != 0 {
-            info!(self.logger, "joybus triggered");
-            self.joybus_exec();
-            self.ram[0x3F] &= !1;
-
-            let mut mem = self.ram.iter();
-            for i in 0..8 {
-                println!(
-                    "JOY: {:03x}: {:02x} {:02x} {:02x} {:02x} -- {:02x} {:02x} {:02x} {:02x}",
-                    i * 8,
-                    mem.next().unwrap(),
-                    mem.next().unwrap(),
-                    mem.next().unwrap(),
-                    mem.next().unwrap(),
-                    mem.next().unwrap(),
-                    mem.next().unwrap(),
-                    mem.next().unwrap(),
-                    mem.next().unwrap()
-                );
+        if *self.nmi_countdown > 0 {
+            *self.nmi_countdown -= elapsed;
+            if *self.nmi_countdown <= 0 {
+                *self.nmi_countdown = 0;
+                info!(self.logger, "NMI");
+                R4300::get_mut()
+                    .cop0
+                    .set_hwint_line(PRENMI_HWINT_LINE, false);
+                CurrentDeviceMap().reset(false);
             }
+        }
 
-            Si::get_mut().set_busy(false);
+        let status = self.ram[CONTROL];
+        if status & 0x20 != 0 {
+            info!(self.logger, "unlock boot");
+            self.ram[CONTROL] |= 0x80;
+            self.ram[CONTROL] &= !0x20;
         }
 
-        Ok(())
+        // SI is not scheduled: its transfers (to and from PIF RAM) are
+        // reported here.
+        self.dma.trace_oob(tracer)?;
+        Si::get_mut().trace_oob(tracer)
     }
 
     fn step(&mut self, _tracer: &dbg::Tracer) -> dbg::Result<()> {
@@ -307,3 +633,61 @@ impl sync::Subsystem for Pi {
         None // No program counter
     }
 }
+
+impl PacketView for Pi {
+    fn name(&self) -> &str {
+        "PIF"
+    }
+
+    fn num_transactions(&self) -> usize {
+        self.joybus_log.len()
+    }
+
+    fn transaction(&self, idx: usize) -> (String, &[u8], &[u8]) {
+        let txn = &self.joybus_log[idx];
+        (format!("joybus #{}", txn.id), &txn.before, &txn.after)
+    }
+
+    fn decode<'a>(&self, before: &'a [u8], after: &'a [u8]) -> Vec<Packet<'a>> {
+        let mut packets = Vec::new();
+        // Commands that do not fit in PIF RAM are not shown.
+        for cmd in pif::commands(before).filter_map(|cmd| cmd.ok()) {
+            let op = before.get(cmd.tx.start).cloned();
+            let desc = match op {
+                Some(0x00) | Some(0xFF) => "info/reset",
+                Some(0x01) => "read buttons",
+                Some(0x02) => "read mempak",
+                Some(0x03) => "write mempak",
+                Some(0x04) => "read eeprom",
+                Some(0x05) => "write eeprom",
+                Some(0x06) => "rtc status",
+                Some(0x07) => "read rtc",
+                Some(0x08) => "write rtc",
+                Some(randnet::CMD_READ_KEYS) => "read keyboard",
+                Some(_) => "unknown",
+                None => "empty",
+            };
+
+            // The status bits are written by PIF in the RX length byte.
+            let status = after[cmd.offset + 1];
+            let status = match (status & 0x80 != 0, status & 0x40 != 0) {
+                (false, false) => "ok".to_owned(),
+                (true, _) => "no device".to_owned(),
+                (false, true) => "rx overflow".to_owned(),
+            };
+
+            packets.push(Packet {
+                channel: cmd.channel,
+                offset: cmd.offset,
+                tx: &before[cmd.tx],
+                rx: &after[cmd.rx],
+                desc: match op {
+                    Some(c) => format!("{:02x} ({})", c, desc),
+                    None => desc.to_owned(),
+                },
+                status,
+            });
+        }
+        packets
+    }
+}
@@ -0,0 +1,277 @@
+//! PIF RAM and the joybus protocol.
+//!
+//! The CPU talks to the controllers (and to the EEPROM and RTC of the
+//! cartridge) through the 64 bytes of PIF RAM. A SI DMA write fills it with
+//! a list of joybus commands and sets bit 0 of the last byte (the control
+//! byte); the PIF executes the commands when the following SI DMA read
+//! starts, writing the responses in place, so that the read returns them.
+//!
+//! Commands are laid out one channel after the other (the four controller
+//! ports, then the cartridge), starting from channel 0:
+//!
+//!  * 0x00: the channel is skipped.
+//!  * 0xFD: the channel is reset (and skipped).
+//!  * 0xFE: end of the commands.
+//!  * 0xFF: padding, ignored.
+//!  * Otherwise, a command: TX length, RX length (both in bits 0-5), the TX
+//!    bytes (the command byte and its arguments) and room for the RX bytes.
+//!    If no device answers on the channel, the PIF sets bit 7 of the RX
+//!    length.
+//!
+//! Controllers answer to [`controller_cmd`](fn.controller_cmd.html): device
+//! info, state of the buttons and stick, and reads and writes of the
+//! Controller Pak.
+//!
+//! Games using the CIC-NUS-6105 also check the CIC at boot, by writing a
+//! challenge in PIF RAM and setting bit 1 of the control byte; the PIF
+//! forwards it to the CIC, and writes its response in place (see
+//! [`cic_challenge`](fn.cic_challenge.html)).
+
+use crate::errors::JoybusError;
+use crate::mempak::{self, Mempak};
+use byteorder::{BigEndian, ByteOrder};
+use std::ops::Range;
+use std::result;
+
+/// Size of PIF RAM, in bytes.
+pub const PIF_RAM_SIZE: usize = 0x40;
+
+/// Offset of the control byte in PIF RAM.
+pub const CONTROL: usize = 0x3F;
+
+/// Bit of the control byte that requests the execution of the commands.
+pub const CONTROL_JOYBUS: u8 = 0x01;
+
+/// Bit of the control byte that requests the CIC to answer the challenge
+/// in PIF RAM (CIC-NUS-6105 only).
+pub const CONTROL_CHALLENGE: u8 = 0x02;
+
+// Offset and length (in bytes) of the challenge in PIF RAM.
+const CHALLENGE_START: usize = 0x30;
+const CHALLENGE_LEN: usize = 15;
+
+/// Number of joybus channels: four controller ports, and the cartridge.
+pub const CHANNELS: usize = 5;
+
+/// Joybus channel of the cartridge port (EEPROM, RTC).
+pub const CARTRIDGE_CHANNEL: usize = 4;
+
+// Joybus commands understood by the controllers.
+pub const CMD_INFO: u8 = 0x00;
+pub const CMD_STATE: u8 = 0x01;
+pub const CMD_READ_PAK: u8 = 0x02;
+pub const CMD_WRITE_PAK: u8 = 0x03;
+pub const CMD_RESET: u8 = 0xFF;
+
+// Bit of the RX length set when no device answered.
+const RX_NO_DEVICE: u8 = 0x80;
+
+// Device type of a standard controller, reported by CMD_INFO.
+const CONTROLLER_TYPE: [u8; 2] = [0x05, 0x00];
+
+// Third byte of the CMD_INFO response: whether a pak is inserted.
+const PAK_PRESENT: u8 = 0x01;
+const PAK_ABSENT: u8 = 0x02;
+
+pub type Result<T> = result::Result<T, JoybusError>;
+
+/// A joybus command in PIF RAM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Command {
+    pub channel: usize,   // Joybus channel (port)
+    pub offset: usize,    // Offset of the TX length byte
+    pub tx: Range<usize>, // TX bytes (the command byte and its arguments)
+    pub rx: Range<usize>, // RX bytes (the response)
+}
+
+/// Parse the joybus commands in PIF RAM, up to the end marker or the last
+/// channel. The iteration stops after the first command that does not fit
+/// in PIF RAM, which is returned as an error.
+pub fn commands(ram: &[u8]) -> Commands<'_> {
+    Commands { ram, ch: 0, idx: 0 }
+}
+
+/// Iterator over the joybus commands in PIF RAM (see [`commands`](fn.commands.html)).
+pub struct Commands<'a> {
+    ram: &'a [u8],
+    ch: usize,
+    idx: usize,
+}
+
+impl<'a> Iterator for Commands<'a> {
+    type Item = Result<Command>;
+
+    fn next(&mut self) -> Option<Result<Command>> {
+        while self.idx < CONTROL && self.ch < CHANNELS {
+            match self.ram[self.idx] {
+                0xFE => break,
+                0xFF => self.idx += 1,
+                0x00 | 0xFD => {
+                    self.idx += 1;
+                    self.ch += 1;
+                }
+                t => {
+                    let offset = self.idx;
+                    // Stop iterating after an error.
+                    self.idx = CONTROL;
+                    if offset + 2 > CONTROL {
+                        return Some(Err(JoybusError::Truncated { offset }));
+                    }
+                    let start = offset + 2;
+                    let mid = start + (t & 0x3F) as usize;
+                    let end = mid + (self.ram[offset + 1] & 0x3F) as usize;
+                    if end > CONTROL {
+                        return Some(Err(JoybusError::Truncated { offset }));
+                    }
+
+                    let cmd = Command {
+                        channel: self.ch,
+                        offset,
+                        tx: start..mid,
+                        rx: mid..end,
+                    };
+                    self.idx = end;
+                    self.ch += 1;
+                    return Some(Ok(cmd));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Execute the joybus commands in PIF RAM. device is called for each
+/// command with the channel, the TX bytes (never empty) and the buffer of
+/// the RX bytes; it returns false if no device is connected to the channel.
+pub fn execute<F>(ram: &mut [u8], mut device: F) -> Result<()>
+where
+    F: FnMut(usize, &[u8], &mut [u8]) -> Result<bool>,
+{
+    let cmds: Vec<_> = commands(ram).collect();
+    for cmd in cmds {
+        let cmd = cmd?;
+        if cmd.tx.is_empty() {
+            return Err(JoybusError::Empty {
+                channel: cmd.channel,
+            });
+        }
+        let (head, tail) = ram.split_at_mut(cmd.rx.start);
+        if !device(cmd.channel, &head[cmd.tx], &mut tail[..cmd.rx.len()])? {
+            ram[cmd.offset + 1] |= RX_NO_DEVICE;
+        }
+    }
+    Ok(())
+}
+
+/// Execute a joybus command sent to a controller, whose buttons and stick
+/// are in state (as returned by CMD_STATE), with an optional Controller Pak.
+/// cmd must not be empty (see [`execute`](fn.execute.html)).
+pub fn controller_cmd(
+    cmd: &[u8],
+    out: &mut [u8],
+    state: u32,
+    pak: Option<&mut Mempak>,
+) -> Result<()> {
+    match cmd[0] {
+        CMD_INFO | CMD_RESET => {
+            check_len(cmd, out, 1, 3)?;
+            out[..2].copy_from_slice(&CONTROLLER_TYPE);
+            out[2] = if pak.is_some() {
+                PAK_PRESENT
+            } else {
+                PAK_ABSENT
+            };
+        }
+        CMD_STATE => {
+            check_len(cmd, out, 1, 4)?;
+            BigEndian::write_u32(out, state);
+        }
+        CMD_READ_PAK => {
+            // 32 bytes of data, plus their CRC. The low 5 bits of the
+            // address are its checksum.
+            check_len(cmd, out, 3, 33)?;
+            let addr = BigEndian::read_u16(&cmd[1..]) & 0xFFE0;
+            let mut data = [0u8; 32];
+            if let Some(pak) = &pak {
+                pak.read_block(addr, &mut data);
+            }
+            out[..32].copy_from_slice(&data);
+            out[32] = pak_crc(&data, pak.is_some());
+        }
+        CMD_WRITE_PAK => {
+            // 32 bytes of data; returns their CRC.
+            check_len(cmd, out, 35, 1)?;
+            let addr = BigEndian::read_u16(&cmd[1..]) & 0xFFE0;
+            let data = &cmd[3..35];
+            out[0] = pak_crc(data, pak.is_some());
+            if let Some(pak) = pak {
+                pak.write_block(addr, data);
+            }
+        }
+        cmd => return Err(JoybusError::UnknownCommand { cmd }),
+    }
+    Ok(())
+}
+
+/// Check that a command (cmd) and its response buffer (out) are at least
+/// tx and rx bytes long.
+pub fn check_len(cmd: &[u8], out: &[u8], tx: usize, rx: usize) -> Result<()> {
+    if cmd.len() < tx || out.len() < rx {
+        return Err(JoybusError::InvalidLength {
+            cmd: cmd[0],
+            tx: cmd.len(),
+            rx: out.len(),
+        });
+    }
+    Ok(())
+}
+
+// CRC returned by a pak command. Without a pak, the controller returns a
+// wrong CRC, which is how libultra detects that the pak was removed.
+fn pak_crc(data: &[u8], present: bool) -> u8 {
+    let crc = mempak::data_crc(data);
+    if present {
+        crc
+    } else {
+        !crc
+    }
+}
+
+/// Answer the challenge of the CIC-NUS-6105 in PIF RAM. The challenge and
+/// the response are 30 nibbles, stored at offset 0x30; the two bytes before
+/// them are cleared.
+pub fn cic_challenge(ram: &mut [u8]) {
+    const LUT0: [u8; 16] = [
+        0x4, 0x7, 0xA, 0x7, 0xE, 0x5, 0xE, 0x1, 0xC, 0xF, 0x8, 0xF, 0x6, 0x3, 0x6, 0x9,
+    ];
+    const LUT1: [u8; 16] = [
+        0x4, 0x1, 0xA, 0x7, 0xE, 0x5, 0xE, 0x1, 0xC, 0x9, 0x8, 0x5, 0x6, 0x3, 0xC, 0x9,
+    ];
+
+    // The CIC switches between the two tables, depending on each nibble of
+    // the response.
+    let mut key = 0xB;
+    let mut alt = false;
+    let mut nibbles = [0u8; CHALLENGE_LEN * 2];
+    for (i, n) in nibbles.iter_mut().enumerate() {
+        let byte = ram[CHALLENGE_START + i / 2];
+        let chl = if i % 2 == 0 { byte >> 4 } else { byte & 0xF };
+
+        let rsp = (key + 5 * chl) & 0xF;
+        key = if alt { LUT1 } else { LUT0 }[rsp as usize];
+        let sgn = rsp & 8 != 0;
+        let mag = if sgn { !rsp } else { rsp } & 7;
+        alt = match rsp {
+            0x1 | 0x9 if alt => true,
+            0xB | 0xE if alt => false,
+            _ => (mag % 3 == 1) == sgn,
+        };
+        *n = rsp;
+    }
+
+    ram[CHALLENGE_START - 2] = 0;
+    ram[CHALLENGE_START - 1] = 0;
+    for (i, pair) in nibbles.chunks(2).enumerate() {
+        ram[CHALLENGE_START + i] = pair[0] << 4 | pair[1];
+    }
+}
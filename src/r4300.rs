@@ -1,19 +1,37 @@
-use std::ops::{Deref, DerefMut};
-use mips64;
 use emu::bus::be::{Bus, Device};
+use emu::memint::MemInt;
+use mips64;
+use std::ops::{Deref, DerefMut};
 
-use super::n64::MAINCPU_NAME;
 use super::ai::Ai;
-use super::cartridge::{Cartridge, CicModel};
+use super::cartridge::{self, Cartridge};
 use super::dp::Dp;
 use super::errors::*;
 use super::mi::Mi;
+use super::n64::MAINCPU_NAME;
 use super::pi::Pi;
 use super::ri::Ri;
+use super::sc64::{self, Sc64};
 use super::si::Si;
-use super::sp::{Sp, RSPCPU};
+use super::sp::Sp;
 use super::vi::Vi;
 
+// Mirror windows of the physical address space (begin, end, size). The RCP
+// decodes only the lower address bits within the window of each interface,
+// so SP memories and the register blocks are echoed through all of it.
+const MIRRORS: &[(u32, u32, u32)] = &[
+    (0x0400_0000, 0x0403_FFFF, 0x2000), // SP DMEM/IMEM
+    (0x0404_0000, 0x0407_FFFF, 0x20),   // SP registers
+    (0x0408_0000, 0x040F_FFFF, 0x8),    // SP PC/IBIST
+    (0x0410_0000, 0x041F_FFFF, 0x20),   // DP command registers
+    (0x0430_0000, 0x043F_FFFF, 0x10),   // MI
+    (0x0440_0000, 0x044F_FFFF, 0x40),   // VI
+    (0x0450_0000, 0x045F_FFFF, 0x20),   // AI
+    (0x0460_0000, 0x046F_FFFF, 0x40),   // PI
+    (0x0470_0000, 0x047F_FFFF, 0x20),   // RI
+    (0x0480_0000, 0x048F_FFFF, 0x20),   // SI
+];
+
 pub struct R4300Config;
 
 impl mips64::Config for R4300Config {
@@ -22,9 +40,32 @@ impl mips64::Config for R4300Config {
     type Cop1 = mips64::Fpu;
     type Cop2 = mips64::CopNull;
     type Cop3 = mips64::CopNull;
+
+    // All DMAs writing RDRAM go through the main bus.
+    const BLOCK_CACHE: bool = true;
+    const TLB: bool = true;
+
+    // Loads and stores in the cartridge ROM area go through the PI, which
+    // latches writes and drives an open bus beyond the end of the ROM.
+    fn data_read<U: MemInt>(bus: &Bus, paddr: u32) -> U {
+        if Cartridge::in_rom_area(paddr) && Cartridge::try_get().is_ok() {
+            let off = paddr - cartridge::ROM_BASE;
+            return U::truncate_from(Cartridge::get_mut().rom_read(off, U::SIZE));
+        }
+        bus.read::<U>(paddr)
+    }
+
+    fn data_write<U: MemInt>(bus: &mut Bus, paddr: u32, val: U) {
+        if Cartridge::in_rom_area(paddr) && Cartridge::try_get().is_ok() {
+            let off = paddr - cartridge::ROM_BASE;
+            return Cartridge::get_mut().rom_write(off, val.into(), U::SIZE);
+        }
+        bus.write::<U>(paddr, val)
+    }
 }
 
 #[derive(DeviceBE)]
+#[device(reset, cpu)]
 pub struct R4300 {
     cpu: mips64::Cpu<R4300Config>,
 }
@@ -59,22 +100,66 @@ impl R4300 {
         })
     }
 
+    // The reset button is connected to the NMI line of the CPU. On hard
+    // reset, the CPU state is restored together with the rest of the state.
+    fn cb_reset(&mut self, hard: bool) {
+        if !hard {
+            self.cpu.nmi();
+        }
+    }
+
+    /// Map the devices on the CPU bus. Devices that have not been registered
+    /// (eg: in the test rigs built by N64Builder) are left unmapped.
     pub fn map_bus(&mut self) -> Result<()> {
-        self.bus.map_device(0x0000_0000, Ri::get(), 0)?;
-        self.bus.map_device(0x03F0_0000, Ri::get(), 1)?;
-        self.bus.map_device(0x0400_0000, Sp::get(), 0)?;
-        self.bus.map_device(0x0404_0000, Sp::get(), 1)?;
-        self.bus.map_device(0x0408_0000, Sp::get(), 2)?;
-        self.bus.map_device(0x0410_0000, Dp::get(), 0)?;
-        self.bus.map_device(0x0430_0000, Mi::get(), 0)?;
-        self.bus.map_device(0x0440_0000, Vi::get(), 0)?;
-        self.bus.map_device(0x0450_0000, Ai::get(), 0)?;
-        self.bus.map_device(0x0460_0000, Pi::get(), 0)?;
-        self.bus.map_device(0x0470_0000, Ri::get(), 2)?;
-        self.bus.map_device(0x0480_0000, Si::get(), 0)?;
-        self.bus.map_device(0x1000_0000, Cartridge::get(), 0)?;
-        self.bus.map_device(0x1800_0000, Cartridge::get(), 1)?;
-        self.bus.map_device(0x1FC0_0000, Pi::get(), 1)?;
+        if let Ok(ri) = Ri::try_get() {
+            self.bus.map_device(0x0000_0000, ri, 0)?;
+            self.bus.map_device(0x03F0_0000, ri, 1)?;
+            self.bus.map_device(0x0470_0000, ri, 2)?;
+        }
+        if let Ok(sp) = Sp::try_get() {
+            self.bus.map_device(0x0400_0000, sp, 0)?;
+            self.bus.map_device(0x0404_0000, sp, 1)?;
+            self.bus.map_device(0x0408_0000, sp, 2)?;
+        }
+        if let Ok(dp) = Dp::try_get() {
+            self.bus.map_device(0x0410_0000, dp, 0)?;
+        }
+        if let Ok(mi) = Mi::try_get() {
+            self.bus.map_device(0x0430_0000, mi, 0)?;
+        }
+        if let Ok(vi) = Vi::try_get() {
+            self.bus.map_device(0x0440_0000, vi, 0)?;
+        }
+        if let Ok(ai) = Ai::try_get() {
+            self.bus.map_device(0x0450_0000, ai, 0)?;
+        }
+        if let Ok(pi) = Pi::try_get() {
+            self.bus.map_device(0x0460_0000, pi, 0)?;
+            self.bus.map_device(0x1FC0_0000, pi, 1)?;
+        }
+        if let Ok(si) = Si::try_get() {
+            self.bus.map_device(0x0480_0000, si, 0)?;
+        }
+        if let Ok(cart) = Cartridge::try_get() {
+            self.bus.map_device(cartridge::ROM_BASE, cart, 0)?;
+            self.bus.map_device(0x1800_0000, cart, 1)?;
+        }
+        if let Ok(sc) = Sc64::try_get() {
+            self.bus.map_device(sc64::BUFFER_BASE, sc, 0)?;
+            self.bus.map_device(sc64::REGS_BASE, sc, 1)?;
+        }
+        self.map_mirrors()
+    }
+
+    /// Declare the mirror windows of the physical address space. This is
+    /// called by map_bus, and is exposed for tests that map only a subset
+    /// of the devices.
+    pub fn map_mirrors(&mut self) -> Result<()> {
+        for &(begin, end, size) in MIRRORS {
+            self.bus
+                .map_mirror(begin, end, size)
+                .map_err(String::from)?;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,127 @@
+//! Randnet keyboard emulation.
+//!
+//! The Randnet keyboard is a joybus device, connected to a controller port
+//! in place of a controller. It identifies itself with device type 0x0002,
+//! and reports the pressed keys with command 0x13:
+//!
+//!  * TX: 0x13, LED state (bit 0: Num Lock, 1: Caps Lock, 2: power).
+//!  * RX: three 16-bit key codes (0 if not used), and a status byte.
+//!
+//! Key codes are the positions of the keys in the keyboard matrix (row in
+//! the high byte, column in the low byte). The Home key is not part of the
+//! matrix, and is reported in the status byte.
+//!
+//! The keyboard is an [`InputDevice`](../../emu/input/struct.InputDevice.html)
+//! whose inputs are named after the host keys, so that the frontend maps it
+//! to the host keyboard by default.
+
+use byteorder::{BigEndian, ByteOrder};
+use emu::input::{Input, InputDevice, InputDeviceKind, InputKind};
+
+/// Name of the keyboard input device.
+pub const DEVICE_NAME: &str = "keyboard";
+
+/// Joybus device type reported by the status command.
+pub const DEVICE_TYPE: [u8; 3] = [0x00, 0x02, 0x00];
+
+/// Joybus command that reads the pressed keys.
+pub const CMD_READ_KEYS: u8 = 0x13;
+
+// Bits of the status byte.
+const STATUS_HOME: u8 = 0x01; // Home key pressed
+const STATUS_ERROR: u8 = 0x10; // more than three keys pressed
+
+// Pseudo key code used for the Home key, which is reported in the status.
+const HOME: usize = 0;
+
+// Host key name => position in the keyboard matrix.
+const KEYS: &[(&str, u16)] = &[
+    ("Escape", 0x0A08),
+    ("1", 0x0501),
+    ("2", 0x0601),
+    ("3", 0x0701),
+    ("4", 0x0801),
+    ("5", 0x0901),
+    ("6", 0x0A01),
+    ("7", 0x0B01),
+    ("8", 0x0C01),
+    ("9", 0x0D01),
+    ("0", 0x0E01),
+    ("-", 0x0F01),
+    ("Backspace", 0x0F07),
+    ("Tab", 0x0506),
+    ("Q", 0x0502),
+    ("W", 0x0602),
+    ("E", 0x0702),
+    ("R", 0x0802),
+    ("T", 0x0902),
+    ("Y", 0x0A02),
+    ("U", 0x0B02),
+    ("I", 0x0C02),
+    ("O", 0x0D02),
+    ("P", 0x0E02),
+    ("Return", 0x0D07),
+    ("A", 0x0503),
+    ("S", 0x0603),
+    ("D", 0x0703),
+    ("F", 0x0803),
+    ("G", 0x0903),
+    ("H", 0x0A03),
+    ("J", 0x0B03),
+    ("K", 0x0C03),
+    ("L", 0x0D03),
+    ("Left Shift", 0x0E05),
+    ("Z", 0x0504),
+    ("X", 0x0604),
+    ("C", 0x0704),
+    ("V", 0x0804),
+    ("B", 0x0904),
+    ("N", 0x0A04),
+    ("M", 0x0B04),
+    (",", 0x0C04),
+    (".", 0x0D04),
+    ("/", 0x0E04),
+    ("Right Shift", 0x0E06),
+    ("Left Ctrl", 0x1107),
+    ("Left Alt", 0x1108),
+    ("Space", 0x0806),
+    ("Up", 0x0205),
+    ("Down", 0x0206),
+    ("Left", 0x0207),
+    ("Right", 0x0208),
+];
+
+/// Create the input device of the keyboard.
+pub fn input_device() -> InputDevice {
+    let mut inputs = vec![Input::new_digital("Home", InputKind::Other, HOME)];
+    inputs.extend(
+        KEYS.iter()
+            .map(|(name, code)| Input::new_digital(name, InputKind::Other, *code as usize)),
+    );
+    InputDevice::new(DEVICE_NAME, InputDeviceKind::Keyboard, inputs)
+}
+
+/// Build the response to the read keys command (7 bytes), given the state of
+/// the keyboard input device. Like the real keyboard, if more than three
+/// keys are pressed, no key is reported and the error bit is set.
+pub fn read_keys(dev: &InputDevice, out: &mut [u8]) {
+    let mut codes = Vec::new();
+    let mut status = 0;
+    dev.visit(|i| {
+        if i.digital() == Some(true) {
+            match i.custom_id() {
+                HOME => status |= STATUS_HOME,
+                code => codes.push(code as u16),
+            }
+        }
+    });
+    if codes.len() > 3 {
+        codes.clear();
+        status |= STATUS_ERROR;
+    }
+    codes.resize(3, 0);
+    for (idx, code) in codes.iter().enumerate() {
+        BigEndian::write_u16(&mut out[idx * 2..], *code);
+    }
+    out[6] = status;
+}
@@ -0,0 +1,78 @@
+// Capture of the primitives drawn by the RDP, for debugging: it allows to
+// find which primitives touched a pixel of the last frame, and the state of
+// the pipeline used to draw them.
+
+// Maximum number of primitives captured per frame (to bound memory usage
+// if frames are never completed).
+const MAX_PRIMITIVES: usize = 65536;
+
+/// A primitive drawn by the RDP, with the state used to draw it.
+#[derive(Clone, Debug)]
+pub struct Primitive {
+    pub name: &'static str,
+    pub fb_addr: u32,               // color image: address
+    pub fb_width: usize,            // color image: width in pixels
+    pub fb_bpp: usize,              // color image: bits per pixel
+    pub rect: (u32, u32, u32, u32), // x0, y0, x1, y1 (in pixels, inclusive)
+    pub state: Vec<(&'static str, String)>,
+}
+
+impl Primitive {
+    /// Return true if the primitive touched the pixel at the specified
+    /// address of RDRAM.
+    pub fn touches(&self, addr: u32) -> bool {
+        let bytes = (self.fb_bpp / 8).max(1) as u32;
+        let pitch = self.fb_width as u32 * bytes;
+        if addr < self.fb_addr || pitch == 0 {
+            return false;
+        }
+        let off = addr - self.fb_addr;
+        let (x, y) = ((off % pitch) / bytes, off / pitch);
+        let (x0, y0, x1, y1) = self.rect;
+        x >= x0 && x <= x1 && y >= y0 && y <= y1
+    }
+}
+
+/// The primitives drawn in the current frame, and in the last completed
+/// one. Capturing is disabled by default, as it slows down emulation.
+#[derive(Default)]
+pub struct Capture {
+    enabled: bool,
+    current: Vec<Primitive>,
+    last: Vec<Primitive>,
+}
+
+impl Capture {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.current.clear();
+        }
+        self.enabled = enabled;
+    }
+
+    pub fn record(&mut self, prim: Primitive) {
+        if self.enabled && self.current.len() < MAX_PRIMITIVES {
+            self.current.push(prim);
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        if self.enabled {
+            self.last = std::mem::take(&mut self.current);
+        }
+    }
+
+    /// Return the primitives of the last frame that touched the pixel at
+    /// the specified address, with their index in the frame.
+    pub fn query(&self, addr: u32) -> Vec<(usize, &Primitive)> {
+        self.last
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.touches(addr))
+            .collect()
+    }
+}
@@ -12,7 +12,6 @@ extern crate emu;
 use self::bit_field::BitField;
 use super::{MColor, MultiColor};
 use emu::gfx::{Color, Rgba8888};
-use std::fmt;
 use std::ptr;
 
 struct CombinerCycle {
@@ -55,10 +54,10 @@ pub(crate) struct Combiner {
     cycle_alpha: [CombinerCycle; 2],
 }
 
-struct CombinerMode(u64);
+pub(crate) struct CombinerMode(pub u64);
 impl CombinerMode {
     #[inline]
-    fn cyc0_rgb(&self) -> (u32, u32, u32, u32) {
+    pub(crate) fn cyc0_rgb(&self) -> (u32, u32, u32, u32) {
         (
             self.0.get_bits(52..56) as u32,
             self.0.get_bits(28..32) as u32,
@@ -68,7 +67,7 @@ impl CombinerMode {
     }
 
     #[inline]
-    fn cyc0_alpha(&self) -> (u32, u32, u32, u32) {
+    pub(crate) fn cyc0_alpha(&self) -> (u32, u32, u32, u32) {
         (
             self.0.get_bits(44..47) as u32,
             self.0.get_bits(12..15) as u32,
@@ -78,7 +77,7 @@ impl CombinerMode {
     }
 
     #[inline]
-    fn cyc1_rgb(&self) -> (u32, u32, u32, u32) {
+    pub(crate) fn cyc1_rgb(&self) -> (u32, u32, u32, u32) {
         (
             self.0.get_bits(37..41) as u32,
             self.0.get_bits(24..28) as u32,
@@ -88,7 +87,7 @@ impl CombinerMode {
     }
 
     #[inline]
-    fn cyc1_alpha(&self) -> (u32, u32, u32, u32) {
+    pub(crate) fn cyc1_alpha(&self) -> (u32, u32, u32, u32) {
         (
             self.0.get_bits(21..24) as u32,
             self.0.get_bits(3..6) as u32,
@@ -139,7 +138,7 @@ impl Combiner {
         // Save as combined color (FIXME: this is not correct with parallel pixels)
         self.combined = c;
 
-        return c;
+        c
     }
 
     unsafe fn setup_cycle_basic(&self, v: u32) -> *const MultiColor {
@@ -160,21 +159,21 @@ impl Combiner {
     ) -> CombinerCycle {
         CombinerCycle {
             suba: match suba {
-                0...5 => self.setup_cycle_basic(suba),
+                0..=5 => self.setup_cycle_basic(suba),
                 6 => &self.one,
                 7 => &self.noise,
-                8...15 => &self.zero,
+                8..=15 => &self.zero,
                 _ => unreachable!(),
             },
             subb: match subb {
-                0...5 => self.setup_cycle_basic(subb),
+                0..=5 => self.setup_cycle_basic(subb),
                 6 => &self.key_center,
                 7 => &self.conv_k4,
-                8...15 => &self.zero,
+                8..=15 => &self.zero,
                 _ => unreachable!(),
             },
             mul: match mul {
-                0...5 => self.setup_cycle_basic(mul),
+                0..=5 => self.setup_cycle_basic(mul),
                 6 => &self.key_scale,
                 7 => &self.combined,
                 8 => &self.texel0,
@@ -185,11 +184,11 @@ impl Combiner {
                 13 => &self.lod_fraction,
                 14 => &self.prim_lod_fraction,
                 15 => &self.conv_k5,
-                16...31 => &self.zero,
+                16..=31 => &self.zero,
                 _ => unreachable!(),
             },
             add: match add {
-                0...5 => self.setup_cycle_basic(add),
+                0..=5 => self.setup_cycle_basic(add),
                 6 => &self.one,
                 _ => &self.zero,
             },
@@ -202,28 +201,28 @@ impl Combiner {
     ) -> CombinerCycle {
         CombinerCycle {
             suba: match suba {
-                0...5 => self.setup_cycle_basic(suba),
+                0..=5 => self.setup_cycle_basic(suba),
                 6 => &self.one,
-                7...15 => &self.zero,
+                7..=15 => &self.zero,
                 _ => unreachable!(),
             },
             subb: match subb {
-                0...5 => self.setup_cycle_basic(subb),
+                0..=5 => self.setup_cycle_basic(subb),
                 6 => &self.one,
-                7...15 => &self.zero,
+                7..=15 => &self.zero,
                 _ => unreachable!(),
             },
             mul: match mul {
                 0 => &self.lod_fraction,
-                1...5 => self.setup_cycle_basic(mul),
+                1..=5 => self.setup_cycle_basic(mul),
                 6 => &self.prim_lod_fraction,
-                7...31 => &self.zero,
+                7..=31 => &self.zero,
                 _ => unreachable!(),
             },
             add: match add {
-                0...5 => self.setup_cycle_basic(add),
+                0..=5 => self.setup_cycle_basic(add),
                 6 => &self.one,
-                7...15 => &self.zero,
+                7..=15 => &self.zero,
                 _ => unreachable!(),
             },
         }
@@ -241,12 +240,14 @@ impl Combiner {
     pub(crate) fn set_tex0(&mut self, c: MultiColor) {
         self.texel0 = c;
     }
+    #[allow(dead_code)]
     pub(crate) fn set_tex1(&mut self, c: MultiColor) {
         self.texel1 = c;
     }
     pub(crate) fn set_prim(&mut self, c: Color<Rgba8888>) {
         self.prim = MultiColor::from_color(c);
     }
+    #[allow(dead_code)]
     pub(crate) fn set_shade(&mut self, c: Color<Rgba8888>) {
         self.shade = MultiColor::from_color(c);
     }
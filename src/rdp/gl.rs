@@ -0,0 +1,160 @@
+// OpenGL backend.
+//
+// Triangles are translated into draw calls of emu's TriangleRenderer: the
+// edges become three vertices, the shade, texture and depth coefficients
+// become vertex attributes, and the 1-cycle combiner mode selects the inputs
+// of the combiner shader. Triangles are batched by state, and drawn into the
+// color image all at once when the batch is flushed (at Sync Full, and
+// before the color image is changed or accessed by other commands).
+
+// TODO:
+//   * 2-cycle mode, and blender modes other than alpha blending
+//   * Z-buffer in RDRAM (depth is kept by GL, and cleared at each flush)
+//   * noise, chroma key, LOD and coverage inputs of the combiner
+
+extern crate bit_field;
+extern crate emu;
+use self::bit_field::BitField;
+use super::cc::CombinerMode;
+use emu::hw::{CombinerInput, DrawState, TextureWrap, TriVertex, TriangleRenderer};
+
+/// A texture decoded from TMEM, as RGBA8888 pixels.
+pub(crate) struct GlTexture {
+    pub pixels: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub wrap: [TextureWrap; 2],
+}
+
+// Triangles drawn with the same state. If texture is set, it is selected
+// before drawing them.
+struct Batch {
+    state: DrawState,
+    texture: Option<GlTexture>,
+    vertices: Vec<TriVertex>,
+}
+
+pub(crate) struct GlBackend {
+    renderer: TriangleRenderer,
+    batches: Vec<Batch>,
+
+    /// The tile whose texture is selected, if still valid (that is, TMEM and
+    /// the tile descriptors did not change since it was decoded).
+    pub tile: Option<usize>,
+}
+
+impl GlBackend {
+    pub fn new() -> GlBackend {
+        GlBackend {
+            renderer: TriangleRenderer::new(),
+            batches: Vec::new(),
+            tile: None,
+        }
+    }
+
+    /// Return true if there are no triangles to draw.
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Queue a triangle, selecting a new texture first if specified.
+    pub fn push(&mut self, state: DrawState, texture: Option<GlTexture>, vertices: &[TriVertex]) {
+        match self.batches.last_mut() {
+            Some(b) if b.state == state && texture.is_none() => {
+                b.vertices.extend_from_slice(vertices);
+            }
+            _ => self.batches.push(Batch {
+                state,
+                texture,
+                vertices: vertices.to_vec(),
+            }),
+        }
+    }
+
+    /// Drop the queued triangles without drawing them.
+    pub fn clear(&mut self) {
+        self.batches.clear();
+        self.tile = None;
+    }
+
+    /// Draw the queued triangles into the color image, whose content is
+    /// given and returned as RGBA8888 pixels.
+    pub fn flush(&mut self, pixels: &mut [u8], width: usize, height: usize) {
+        self.renderer.begin(pixels, width, height);
+        for b in self.batches.drain(..) {
+            if let Some(t) = &b.texture {
+                self.renderer
+                    .set_texture(&t.pixels, t.width, t.height, t.wrap);
+            }
+            self.renderer.draw(&b.state, &b.vertices);
+        }
+        self.renderer.end(pixels);
+    }
+}
+
+// Inputs common to all the slots of the combiner. There is a single texture,
+// so TEXEL1 is the same as TEXEL0; COMBINED is undefined in 1-cycle mode.
+fn basic_input(v: u32) -> CombinerInput {
+    match v {
+        1 | 2 => CombinerInput::Texel,
+        3 => CombinerInput::Prim,
+        4 => CombinerInput::Shade,
+        5 => CombinerInput::Env,
+        _ => CombinerInput::Zero,
+    }
+}
+
+/// Translate the combiner mode (Set Combine Mode) into the inputs of the
+/// combiner shader, for RGB and alpha, as used in 1-cycle mode.
+pub(crate) fn combiner_inputs(mode: u64) -> ([CombinerInput; 4], [CombinerInput; 4]) {
+    let mode = CombinerMode(mode);
+
+    let (suba, subb, mul, add) = mode.cyc1_rgb();
+    let rgb = [
+        match suba {
+            6 => CombinerInput::One,
+            _ => basic_input(suba),
+        },
+        basic_input(subb),
+        match mul {
+            0..=5 => basic_input(mul),
+            8 | 9 => CombinerInput::TexelAlpha,
+            10 => CombinerInput::PrimAlpha,
+            11 => CombinerInput::ShadeAlpha,
+            12 => CombinerInput::EnvAlpha,
+            13 => CombinerInput::One, // LOD fraction, with a single LOD
+            _ => CombinerInput::Zero,
+        },
+        match add {
+            6 => CombinerInput::One,
+            _ => basic_input(add),
+        },
+    ];
+
+    let (suba, subb, mul, add) = mode.cyc1_alpha();
+    let one_or_basic = |v: u32| match v {
+        6 => CombinerInput::One,
+        _ => basic_input(v),
+    };
+    let alpha = [
+        one_or_basic(suba),
+        one_or_basic(subb),
+        match mul {
+            0 => CombinerInput::One, // LOD fraction, with a single LOD
+            _ => basic_input(mul),
+        },
+        one_or_basic(add),
+    ];
+    (rgb, alpha)
+}
+
+/// Return true if the blender (Set Other Modes) blends the combined color
+/// with the color image using the combined alpha, in 1-cycle mode. Other
+/// blender modes are drawn as opaque.
+pub(crate) fn alpha_blending(modes: u64) -> bool {
+    let p = modes.get_bits(30..32);
+    let m = modes.get_bits(26..28);
+    let a = modes.get_bits(22..24);
+    let b = modes.get_bits(18..20);
+    (p, m, a, b) == (0, 1, 0, 0)
+}
@@ -2,10 +2,104 @@ extern crate byteorder;
 extern crate emu;
 use byteorder::{ByteOrder, LittleEndian};
 use emu::gfx::{Color, ColorConverter, ColorFormat, Rgba8888};
-use packed_simd::*;
-use std::arch::x86_64::*;
+use std::ops::{Add, BitAnd, Div, Mul, Shl, Shr, Sub};
 
-type MultiColor = u16x8;
+/// MultiColor holds two RGBA colors (the pixel pipeline processes two pixels
+/// in parallel), using 16-bit components to hold intermediate results. All
+/// operations are performed component-wise, with wrapping arithmetic; the
+/// compiler is able to vectorize them.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct MultiColor([u16; 8]);
+
+impl MultiColor {
+    #[inline(always)]
+    pub fn splat(v: u16) -> Self {
+        MultiColor([v; 8])
+    }
+
+    #[inline(always)]
+    fn extract(self, idx: usize) -> u16 {
+        self.0[idx]
+    }
+
+    #[inline(always)]
+    fn replace(mut self, idx: usize, v: u16) -> Self {
+        self.0[idx] = v;
+        self
+    }
+
+    #[inline(always)]
+    fn zip(mut self, other: Self, f: impl Fn(u16, u16) -> u16) -> Self {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a = f(*a, *b);
+        }
+        self
+    }
+
+    #[inline(always)]
+    fn map(mut self, f: impl Fn(u16) -> u16) -> Self {
+        for a in self.0.iter_mut() {
+            *a = f(*a);
+        }
+        self
+    }
+}
+
+impl Add for MultiColor {
+    type Output = Self;
+    #[inline(always)]
+    fn add(self, other: Self) -> Self {
+        self.zip(other, u16::wrapping_add)
+    }
+}
+
+impl Sub for MultiColor {
+    type Output = Self;
+    #[inline(always)]
+    fn sub(self, other: Self) -> Self {
+        self.zip(other, u16::wrapping_sub)
+    }
+}
+
+impl Mul for MultiColor {
+    type Output = Self;
+    #[inline(always)]
+    fn mul(self, other: Self) -> Self {
+        self.zip(other, u16::wrapping_mul)
+    }
+}
+
+impl Div for MultiColor {
+    type Output = Self;
+    #[inline(always)]
+    fn div(self, other: Self) -> Self {
+        self.zip(other, |a, b| a / b)
+    }
+}
+
+impl BitAnd for MultiColor {
+    type Output = Self;
+    #[inline(always)]
+    fn bitand(self, other: Self) -> Self {
+        self.zip(other, |a, b| a & b)
+    }
+}
+
+impl Shl<u32> for MultiColor {
+    type Output = Self;
+    #[inline(always)]
+    fn shl(self, n: u32) -> Self {
+        self.map(|a| a.wrapping_shl(n))
+    }
+}
+
+impl Shr<u32> for MultiColor {
+    type Output = Self;
+    #[inline(always)]
+    fn shr(self, n: u32) -> Self {
+        self.map(|a| a.wrapping_shr(n))
+    }
+}
 
 pub(crate) trait MColor: Sized + Copy {
     fn from_color<CF: ColorFormat>(c: Color<CF>) -> Self;
@@ -19,9 +113,9 @@ pub(crate) trait MColor: Sized + Copy {
 impl MColor for MultiColor {
     fn from_color<CF: ColorFormat>(c: Color<CF>) -> Self {
         let (r, g, b, a) = c.components();
-        u16x8::new(
+        MultiColor([
             r as u16, g as u16, b as u16, a as u16, r as u16, g as u16, b as u16, a as u16,
-        )
+        ])
     }
 
     fn overflown(self) -> bool {
@@ -29,23 +123,16 @@ impl MColor for MultiColor {
     }
 
     fn get_color<CF: ColorFormat>(&self, idx: usize) -> Color<CF> {
-        // Rust does not expose a _mm_pack* functions through the uAAxBB SIMD
-        // structs, so there is no way to convert from u16x8 to u8x16 without
-        // using scalar code. The following code is able to keep it fully
-        // vectorized, and generate a final "MOVD XMM" instruction to
-        // extract the required color index.
-        let c = unsafe {
-            let c = __m128i::from_bits(*self);
-            let c = _mm_packus_epi16(c, _mm_setzero_si128());
-            u8x16::from_bits(c)
-        };
-        let mut cbuf: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
-        c.write_to_slice_unaligned(&mut cbuf);
-        match idx {
-            0 => Color::<Rgba8888>::from_bits(LittleEndian::read_u32(&cbuf[0..4])).cconv(),
-            1 => Color::<Rgba8888>::from_bits(LittleEndian::read_u32(&cbuf[4..8])).cconv(),
-            _ => panic!("invalid MultiColor index"),
+        if idx > 1 {
+            panic!("invalid MultiColor index");
+        }
+        // Components are saturated to 8-bit (treating them as signed, like
+        // the PACKUSWB instruction).
+        let mut cbuf = [0u8; 4];
+        for (i, c) in cbuf.iter_mut().enumerate() {
+            *c = (self.0[idx * 4 + i] as i16).clamp(0, 0xFF) as u8;
         }
+        Color::<Rgba8888>::from_bits(LittleEndian::read_u32(&cbuf)).cconv()
     }
 
     fn map_alpha(self, f: fn(u16) -> u16) -> Self {
@@ -77,8 +164,9 @@ pub(crate) enum CycleMode {
     Fill,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default)]
 pub(crate) enum DpColorFormat {
+    #[default]
     Rgba,
     Yuv,
     ColorIndex,
@@ -86,12 +174,6 @@ pub(crate) enum DpColorFormat {
     Intensity,
 }
 
-impl Default for DpColorFormat {
-    fn default() -> DpColorFormat {
-        DpColorFormat::Rgba
-    }
-}
-
 impl DpColorFormat {
     pub fn from_bits(bits: usize) -> Option<DpColorFormat> {
         match bits {
@@ -106,10 +188,14 @@ impl DpColorFormat {
 }
 
 mod bl;
+mod capture;
 mod cc;
+#[cfg(feature = "frontend")]
+mod gl;
 mod pipeline;
 mod raster;
 mod rdp;
+mod tri;
 
-pub use self::pipeline::PixelPipeline;
+pub use self::capture::Primitive;
 pub use self::rdp::Rdp;
@@ -21,8 +21,20 @@ impl PixelPipeline {
     pub fn calc_pixels(&mut self, shade: MultiColor, fb: MultiColor) -> MultiColor {
         self.cc.set_tex0(shade);
         let combined = self.cc.combine_1cycle(shade);
-        let blended = self.bl.blend_1cycle(combined, shade, fb);
-        return blended;
+
+        self.bl.blend_1cycle(combined, shade, fb)
+    }
+
+    #[inline(always)]
+    pub fn calc_pixels_tex(
+        &mut self,
+        shade: MultiColor,
+        texel: MultiColor,
+        fb: MultiColor,
+    ) -> MultiColor {
+        self.cc.set_tex0(texel);
+        let combined = self.cc.combine_1cycle(shade);
+        self.bl.blend_1cycle(combined, shade, fb)
     }
 
     pub fn set_combine_mode(&mut self, mode: u64) {
@@ -37,6 +49,9 @@ impl PixelPipeline {
     pub fn set_blend_color(&mut self, c: Color<Rgba8888>) {
         self.bl.set_blend_color(c);
     }
+    pub fn set_fog_color(&mut self, c: Color<Rgba8888>) {
+        self.bl.set_fog_color(c);
+    }
     pub fn set_other_modes(&mut self, modes: u64) {
         self.bl.set_other_modes(modes);
     }
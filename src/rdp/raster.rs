@@ -5,13 +5,13 @@ use self::byteorder::{BigEndian, ByteOrder, LittleEndian};
 use self::emu::fp::formats::*;
 use self::emu::fp::FixedPoint;
 use self::emu::gfx::*;
-use self::num::ToPrimitive;
+use self::num::{range, range_inclusive, ToPrimitive};
 use super::pipeline::PixelPipeline;
 use super::{DpColorFormat, MColor, MultiColor};
 use std::marker::PhantomData;
 
 #[inline(always)]
-pub fn fill_rect<'a, 'b, CF1, CF2, FP1, O1>(
+pub fn fill_rect<'a, CF1, CF2, FP1, O1>(
     dst: &mut GfxBufferMut<'a, CF1, O1>,
     dr: Rect<FP1>,
     color: Color<CF2>,
@@ -23,17 +23,17 @@ pub fn fill_rect<'a, 'b, CF1, CF2, FP1, O1>(
 {
     let dr = dr.truncate();
 
-    for dy in dr.c0.y.floor()..=dr.c1.y.floor() {
+    for dy in range_inclusive(dr.c0.y.floor(), dr.c1.y.floor()) {
         let mut dst = dst.line(dy.to_usize().unwrap());
 
-        for dx in dr.c0.x.floor()..=dr.c1.x.floor() {
+        for dx in range_inclusive(dr.c0.x.floor(), dr.c1.x.floor()) {
             let didx = dx.to_usize().unwrap();
             dst.set(didx, color.cconv());
         }
     }
 }
 
-pub fn fill_rect_pp<'a, 'b, CF1, CF2, FP1, O1>(
+pub fn fill_rect_pp<'a, CF1, CF2, FP1, O1>(
     dst: &mut GfxBufferMut<'a, CF1, O1>,
     dr: Rect<FP1>,
     color: Color<CF2>,
@@ -48,17 +48,14 @@ pub fn fill_rect_pp<'a, 'b, CF1, CF2, FP1, O1>(
     let color = MultiColor::from_color(color);
     let black = MultiColor::from_color(Color::<Rgba8888>::new_clamped(0, 0, 0, 0xff));
 
-    for dy in dr.c0.y.floor()..dr.c1.y.floor() {
+    for dy in range(dr.c0.y.floor(), dr.c1.y.floor()) {
         let mut dst = dst.line(dy.to_usize().unwrap());
 
-        for dx in dr.c0.x.floor()..dr.c1.x.floor() {
+        for dx in range(dr.c0.x.floor(), dr.c1.x.floor()) {
             let didx = dx.to_usize().unwrap();
             let cres = pp.calc_pixels(color, black);
             if cres.overflown() {
-                panic!(format!(
-                    "pixel pipeline returned overflown pixel: {:?}",
-                    cres
-                ));
+                panic!("pixel pipeline returned overflown pixel: {:?}", cres);
             }
             dst.set(didx, cres.get_color(0));
         }
@@ -85,18 +82,18 @@ fn int_draw_rect<'a, 'b, CF1, CF2, FP1, FP2, O1, O2>(
     let mut sy = st.y;
 
     let w = (dr.c1.x.floor() - dr.c0.x.floor()).to_usize().unwrap();
-    if (w + 1) % 4 != 0 {
+    if !(w + 1).is_multiple_of(4) {
         println!("{:?}", w + 1);
         panic!("cannot unroll loop");
     }
 
-    for dy in dr.c0.y.floor()..=dr.c1.y.floor() {
+    for dy in range_inclusive(dr.c0.y.floor(), dr.c1.y.floor()) {
         let mut dst = dst.line(dy.to_usize().unwrap());
         let src = src.line(sy.floor().to_usize().unwrap());
 
         // FIXME: Do 4 pixels at a time (manual unroll). Not sure if it's OK.
         let mut sx = sx;
-        for dx in (dr.c0.x.floor()..=dr.c1.x.floor()).step_by(4) {
+        for dx in range_inclusive(dr.c0.x.floor(), dr.c1.x.floor()).step_by(4) {
             let c1 = src.get(sx.floor().to_usize().unwrap());
             sx = sx + dsdt.x;
 
@@ -137,6 +134,7 @@ pub fn draw_rect<'a, 'b, CF1, CF2, FP1, FP2, O1, O2>(
     int_draw_rect(dst, dr, src, sr.c0, dsdt);
 }
 
+#[allow(dead_code)]
 pub fn draw_rect_scaled<'a, 'b, CF1, CF2, FP1, FP2, O1, O2>(
     dst: &mut GfxBufferMut<'a, CF1, O1>,
     dr: Rect<FP1>,
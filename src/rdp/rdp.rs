@@ -3,15 +3,22 @@ extern crate byteorder;
 extern crate emu;
 extern crate slog;
 use self::bit_field::BitField;
-use self::byteorder::{BigEndian, LittleEndian};
+use self::byteorder::{BigEndian, ByteOrder, LittleEndian};
 use self::emu::bus::Device;
 use super::super::r4300::R4300;
+use super::super::VideoBackend;
+use super::capture::{Capture, Primitive};
+#[cfg(feature = "frontend")]
+use super::gl::{self, GlBackend, GlTexture};
 use super::pipeline::PixelPipeline;
 use super::raster::{draw_rect, fill_rect, fill_rect_pp, DpRenderState};
-use super::{CycleMode, DpColorFormat};
+use super::tri::{z_compress, z_decompress, Attr, Span, Triangle};
+use super::{CycleMode, DpColorFormat, MColor, MultiColor};
 use emu::fp::formats::*;
 use emu::fp::Q;
 use emu::gfx::*;
+#[cfg(feature = "frontend")]
+use emu::hw::{CombinerInput, DrawState, TextureWrap, TriVertex};
 use emu::int::Numerics;
 use std::marker::PhantomData;
 
@@ -30,6 +37,108 @@ struct TileDescriptor {
     rect: Rect<U30F2>,
 }
 
+impl TileDescriptor {
+    // Fetch the texel at the specified coordinates (in texels) from TMEM,
+    // applying the clamping, wrapping and mirroring of the tile.
+    fn texel(&self, tmem: &[u8], s: i32, t: i32) -> Color<Rgba8888> {
+        let s = self.wrap(0, s - self.rect.c0.x.floor() as i32) as usize;
+        let t = self.wrap(1, t - self.rect.c0.y.floor() as i32) as usize;
+        let off = self.tmem_addr as usize + t * self.pitch + s * self.bpp / 8;
+        let byte = |idx: usize| tmem[(off + idx) & (tmem.len() - 1)];
+        let nibble = if s & 1 == 0 {
+            byte(0) >> 4
+        } else {
+            byte(0) & 0xF
+        };
+
+        let (r, g, b, a) = match (self.color_format, self.bpp) {
+            (DpColorFormat::Rgba, 16) => {
+                let v = (byte(0) as u16) << 8 | byte(1) as u16;
+                let c = |v: u16| ((v & 0x1F) << 3 | (v & 0x1F) >> 2) as u8;
+                (c(v >> 11), c(v >> 6), c(v >> 1), (v & 1) as u8 * 0xFF)
+            }
+            (DpColorFormat::Rgba, 32) => (byte(0), byte(1), byte(2), byte(3)),
+            (DpColorFormat::IntensityAlpha, 16) => (byte(0), byte(0), byte(0), byte(1)),
+            (DpColorFormat::IntensityAlpha, 8) => {
+                let i = (byte(0) >> 4) * 0x11;
+                (i, i, i, (byte(0) & 0xF) * 0x11)
+            }
+            (DpColorFormat::IntensityAlpha, 4) => {
+                let i = (nibble >> 1) << 5 | (nibble >> 1) << 2 | (nibble >> 2);
+                (i, i, i, (nibble & 1) * 0xFF)
+            }
+            (DpColorFormat::Intensity, 8) => (byte(0), byte(0), byte(0), byte(0)),
+            (DpColorFormat::Intensity, 4) => {
+                let i = nibble * 0x11;
+                (i, i, i, i)
+            }
+            _ => (0, 0, 0, 0), // FIXME: color-indexed and YUV textures
+        };
+        Color::new_clamped(r, g, b, a)
+    }
+
+    // Apply clamping (also when no mask is set), wrapping and mirroring to a
+    // texel coordinate relative to the tile.
+    fn wrap(&self, axis: usize, v: i32) -> i32 {
+        let mask = self.mask[axis] as i32;
+        let mut v = v;
+        if self.clamp[axis] || mask == 0 {
+            let size = match axis {
+                0 => self.rect.width().floor(),
+                _ => self.rect.height().floor(),
+            };
+            v = v.max(0).min(size as i32);
+        }
+        if mask == 0 {
+            return v;
+        }
+        let mirrored = self.mirror[axis] && v & (mask + 1) != 0;
+        if mirrored {
+            mask - (v & mask)
+        } else {
+            v & mask
+        }
+    }
+}
+
+#[cfg(feature = "frontend")]
+impl TileDescriptor {
+    // Decode the tile into a texture for the OpenGL backend, as large as its
+    // wrapping mask (or as the tile, if there is no mask). Coordinates are
+    // relative to the top-left corner of the tile.
+    fn decode(&self, tmem: &[u8]) -> GlTexture {
+        let size = |axis: usize| match (self.mask[axis], axis) {
+            (0, 0) => self.rect.width().floor() as usize + 1,
+            (0, _) => self.rect.height().floor() as usize + 1,
+            (mask, _) => mask as usize + 1,
+        };
+        let (width, height) = (size(0), size(1));
+        let (s0, t0) = (self.rect.c0.x.floor() as i32, self.rect.c0.y.floor() as i32);
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for t in 0..height as i32 {
+            for s in 0..width as i32 {
+                let (r, g, b, a) = self.texel(tmem, s0 + s, t0 + t).components();
+                pixels.extend_from_slice(&[r as u8, g as u8, b as u8, a as u8]);
+            }
+        }
+        let wrap = |axis: usize| {
+            if self.clamp[axis] || self.mask[axis] == 0 {
+                TextureWrap::Clamp
+            } else if self.mirror[axis] {
+                TextureWrap::Mirror
+            } else {
+                TextureWrap::Repeat
+            }
+        };
+        GlTexture {
+            pixels,
+            width,
+            height,
+            wrap: [wrap(0), wrap(1)],
+        }
+    }
+}
+
 #[derive(Copy, Clone, Default, Debug)]
 struct ImageFormat {
     color_format: DpColorFormat,
@@ -44,38 +153,67 @@ impl ImageFormat {
     }
 }
 
+// State of the combiner that is not kept by the pixel pipeline in a form
+// usable by the OpenGL backend.
+#[derive(Copy, Clone)]
+#[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+struct CombineState {
+    mode: u64,
+    prim: Color<Rgba8888>,
+    env: Color<Rgba8888>,
+}
+
 pub struct Rdp {
     logger: slog::Logger,
     tmem: Box<[u8]>,
     clip: Rect<I30F2>,
     fb: ImageFormat,
     tex: ImageFormat,
+    z_image: Option<u32>,
     tiles: [TileDescriptor; 8],
     fill_color: u32,
+    prim_z: u32,
     cycle_mode: CycleMode,
+    other_modes: u64,
+    combine: CombineState,
 
     pipeline: PixelPipeline,
 
-    cmdbuf: [u64; 16],
+    cmdbuf: [u64; 22], // longest command: shaded, textured, Z-buffered triangle
     cmdlen: usize,
+
+    capture: Capture,
+
+    #[cfg(feature = "frontend")]
+    gl: Option<Box<GlBackend>>,
 }
 
 impl Rdp {
     pub fn new(logger: slog::Logger) -> Rdp {
-        let mut tmem = Vec::new();
-        tmem.resize(4096, 0);
+        let tmem = vec![0; 4096];
         Rdp {
-            logger: logger,
+            logger,
             tmem: tmem.into_boxed_slice(),
             clip: Rect::default(),
             fb: ImageFormat::default(),
             tex: ImageFormat::default(),
+            z_image: None,
             tiles: [TileDescriptor::default(); 8],
             fill_color: 0,
+            prim_z: 0,
             cycle_mode: CycleMode::One,
+            other_modes: 0,
+            combine: CombineState {
+                mode: 0,
+                prim: Color::new_clamped(0, 0, 0, 0),
+                env: Color::new_clamped(0, 0, 0, 0),
+            },
             pipeline: PixelPipeline::new(),
-            cmdbuf: [0u64; 16],
+            cmdbuf: [0u64; 22],
             cmdlen: 0,
+            capture: Capture::default(),
+            #[cfg(feature = "frontend")]
+            gl: None,
         }
     }
 
@@ -97,6 +235,195 @@ impl Rdp {
         (fb_mem, 320, 240, self.fb.pitch())
     }
 
+    /// Return the address of the Z-buffer, if it was set.
+    pub fn z_image(&self) -> Option<u32> {
+        self.z_image
+    }
+
+    /// Enable or disable the capture of the primitives drawn in each frame
+    /// (see query_pixel).
+    pub fn set_capture(&mut self, enabled: bool) {
+        self.capture.set_enabled(enabled);
+    }
+
+    /// Signal the end of a frame, for the capture of primitives. Triangles
+    /// queued by the OpenGL backend are drawn.
+    pub fn end_frame(&mut self) {
+        self.flush_gl();
+        self.capture.end_frame();
+    }
+
+    /// Return the primitives of the last frame that touched the pixel at
+    /// the specified address, with their index in the frame.
+    pub fn query_pixel(&self, addr: u32) -> Vec<(usize, &Primitive)> {
+        self.capture.query(addr)
+    }
+
+    // Record the primitive being drawn by the current command into the
+    // capture, with the state of the pipeline.
+    fn record(
+        &mut self,
+        name: &'static str,
+        rect: (u32, u32, u32, u32),
+        mut details: Vec<(&'static str, String)>,
+    ) {
+        let words: Vec<String> = self.cmdbuf[..self.cmdlen]
+            .iter()
+            .map(|w| format!("{:016x}", w))
+            .collect();
+        let mut state = vec![
+            ("Command", words.join(" ")),
+            ("Cycle mode", format!("{:?}", self.cycle_mode)),
+            (
+                "Color image",
+                format!(
+                    "{:08x} ({} pixels, {} bpp, {:?})",
+                    self.fb.dram_addr, self.fb.width, self.fb.bpp, self.fb.color_format
+                ),
+            ),
+        ];
+        state.append(&mut details);
+        match self.cycle_mode {
+            CycleMode::Fill => state.push(("Fill color", format!("{:08x}", self.fill_color))),
+            _ => {
+                state.push(("Combiner", self.pipeline.fmt_combiner()));
+                state.push(("Blender", self.pipeline.fmt_blender()));
+            }
+        }
+        self.capture.record(Primitive {
+            name,
+            fb_addr: self.fb.dram_addr,
+            fb_width: self.fb.width,
+            fb_bpp: self.fb.bpp,
+            rect,
+            state,
+        });
+    }
+
+    // Draw a triangle (commands 0x08-0x0F) into the color image, with depth
+    // testing against the Z image if enabled.
+    fn draw_triangle(&mut self, op: u64) {
+        let tri = Triangle::parse(op, &self.cmdbuf[..self.cmdlen]);
+        info!(self.logger, "DP: Triangle"; "op" => op.hex(), "tile" => tri.tile, "left_major" => tri.left_major);
+        if self.capture.enabled() {
+            let coeffs = format!(
+                "shade:{} texture:{} z:{}",
+                tri.shade.is_some(),
+                tri.tex.is_some(),
+                tri.z.is_some()
+            );
+            self.record("Triangle", tri.bounds(), vec![("Coefficients", coeffs)]);
+        }
+
+        let bpp = self.fb.bpp / 8;
+        if bpp != 2 && bpp != 4 {
+            error!(self.logger, "DP: unsupported color image for triangles"; "bpp" => self.fb.bpp);
+            return;
+        }
+        if self.draw_triangle_gl(&tri) {
+            return;
+        }
+        let fb_mem = match R4300::get_mut()
+            .bus
+            .fetch_write::<u8>(self.fb.dram_addr)
+            .mem()
+        {
+            Some(mem) => mem,
+            None => {
+                error!(self.logger, "DP: color image in non-linear memory"; "addr" => self.fb.dram_addr.hex());
+                return;
+            }
+        };
+
+        // Depth compare and update are enabled by other modes. The depth comes
+        // either from the triangle or from Set Prim Depth.
+        let z_compare = self.other_modes.get_bit(4);
+        let z_update = self.other_modes.get_bit(5);
+        let z_prim = self.other_modes.get_bit(2);
+        let mut zbuf = match self.z_image {
+            Some(addr) if z_compare || z_update => {
+                R4300::get_mut().bus.fetch_write::<u8>(addr).mem()
+            }
+            _ => None,
+        };
+
+        let clip = (
+            self.clip.c0.x.floor(),
+            self.clip.c0.y.floor(),
+            self.clip.c1.x.floor(),
+            self.clip.c1.y.floor(),
+        );
+        let width = self.fb.width;
+        let fill = matches!(self.cycle_mode, CycleMode::Fill);
+        let fill_color = self.fill_color;
+        let prim_z = self.prim_z << 3;
+        let tile = &self.tiles[tri.tile];
+        let tmem = &self.tmem[..];
+        let pipeline = &mut self.pipeline;
+
+        tri.walk(clip, |span| {
+            for x in span.x0..span.x1 {
+                let idx = span.y as usize * width + x as usize;
+                let off = idx * bpp;
+                if off + bpp > fb_mem.len() {
+                    return;
+                }
+
+                if let Some(zbuf) = zbuf.as_mut() {
+                    let z = match tri.z {
+                        Some(ref z) if !z_prim => (z.at(span, x) >> 13).clamp(0, 0x3FFFF) as u32,
+                        _ => prim_z,
+                    };
+                    let zoff = idx * 2;
+                    if zoff + 2 > zbuf.len() {
+                        return;
+                    }
+                    let old = z_decompress(BigEndian::read_u16(&zbuf[zoff..]));
+                    if z_compare && z > old {
+                        continue;
+                    }
+                    if z_update {
+                        BigEndian::write_u16(&mut zbuf[zoff..], z_compress(z));
+                    }
+                }
+
+                if fill {
+                    // In fill mode, the fill color is written as is; for
+                    // 16-bit images, it holds two pixels.
+                    match bpp {
+                        2 => {
+                            let v = if x & 1 == 0 {
+                                fill_color >> 16
+                            } else {
+                                fill_color
+                            };
+                            BigEndian::write_u16(&mut fb_mem[off..], v as u16);
+                        }
+                        _ => BigEndian::write_u32(&mut fb_mem[off..], fill_color),
+                    }
+                    continue;
+                }
+
+                let shade = match tri.shade {
+                    Some(ref shade) => shade_at(shade, span, x),
+                    None => MultiColor::default(),
+                };
+                let texel = match tri.tex {
+                    Some(ref st) => {
+                        // S and T are s10.5 texel coordinates.
+                        let s = (st[0].at(span, x) >> 21) as i32;
+                        let t = (st[1].at(span, x) >> 21) as i32;
+                        MultiColor::from_color(tile.texel(tmem, s, t))
+                    }
+                    None => MultiColor::default(),
+                };
+                let fb = read_pixel(&fb_mem[off..], bpp);
+                let c = pipeline.calc_pixels_tex(shade, texel, fb);
+                write_pixel(&mut fb_mem[off..], bpp, c.get_color(0));
+            }
+        });
+    }
+
     pub fn op(&mut self, cmd: u64) {
         info!(self.logger, "DP command"; "cmd" => cmd.hex());
         self.cmdbuf[self.cmdlen] = cmd;
@@ -104,6 +431,14 @@ impl Rdp {
 
         let op = self.cmdbuf[0].get_bits(56..62);
         match op {
+            0x08..=0x0F => {
+                // Triangle (4 to 22 words, depending on the coefficients)
+                if self.cmdlen != Triangle::num_words(op) {
+                    return;
+                }
+                self.draw_triangle(op);
+                self.cmdlen = 0;
+            }
             0x2D => {
                 // Set Scissor
                 self.clip = Rect::from_bits(
@@ -125,6 +460,7 @@ impl Rdp {
                 };
 
                 if op == 0x3F {
+                    self.flush_gl();
                     self.fb = format;
                     info!(self.logger, "DP: Set Color Image"; "format" => ?self.fb);
                 } else {
@@ -133,9 +469,27 @@ impl Rdp {
                 }
                 self.cmdlen = 0;
             }
-            0x28 => {
-                // Sync Tile
-                info!(self.logger, "DP: Sync Tile");
+            0x3E => {
+                // Set Z Image
+                let addr = cmd.get_bits(0..26) as u32;
+                info!(self.logger, "DP: Set Z Image"; "addr" => addr.hex());
+                self.z_image = Some(addr);
+                self.cmdlen = 0;
+            }
+            0x26..=0x29 => {
+                // Sync Load / Sync Pipe / Sync Tile / Sync Full. Commands are
+                // executed one at a time, so there is nothing to wait for,
+                // except for the triangles queued by the OpenGL backend.
+                info!(self.logger, "DP: Sync"; "op" => op.hex());
+                if op == 0x29 {
+                    self.flush_gl();
+                }
+                self.cmdlen = 0;
+            }
+            0x2E => {
+                // Set Prim Depth
+                self.prim_z = cmd.get_bits(16..31) as u32;
+                info!(self.logger, "DP: Set Prim Depth"; "z" => self.prim_z.hex());
                 self.cmdlen = 0;
             }
             0x2F => {
@@ -147,6 +501,7 @@ impl Rdp {
                     3 => CycleMode::Fill,
                     _ => unreachable!(),
                 };
+                self.other_modes = cmd;
                 self.pipeline.set_other_modes(cmd);
                 warn!(self.logger, "DP: Set Other Modes"; "blender" => self.pipeline.fmt_blender());
                 self.cmdlen = 0;
@@ -172,7 +527,17 @@ impl Rdp {
                 let ptex = Point::new(s, t);
                 let slope = Point::new(dsdx, dtdy);
                 info!(self.logger, "DP: Textured Rectangle"; "idx" => tile, "tile" => ?self.tiles[tile], "screen" => ?rect, "ptex" => ?ptex, "slope" => ?slope);
+                if self.capture.enabled() {
+                    let details = vec![
+                        ("Tile", format!("{} {:?}", tile, self.tiles[tile])),
+                        ("Texture", format!("{:?}", ptex)),
+                        ("Slope", format!("{:?}", slope)),
+                    ];
+                    let prim = (x0 >> 2, y0 >> 2, x1 >> 2, y1 >> 2);
+                    self.record("Texture Rectangle", prim, details);
+                }
 
+                self.flush_gl();
                 let tmem_addr = self.tiles[tile].tmem_addr as usize;
                 let tmem_pitch = self.tiles[tile].pitch;
                 let tex_rect = self.tiles[tile].rect;
@@ -216,6 +581,7 @@ impl Rdp {
 
                 // Load_Tile also updates the internal tile rect
                 self.tiles[tile].rect = rect;
+                self.load_texture_gl();
 
                 let tmem_addr = self.tiles[tile].tmem_addr as usize;
                 let tmem_pitch = self.tiles[tile].pitch;
@@ -237,13 +603,9 @@ impl Rdp {
                     )
                     .unwrap();
 
-                    let tex = GfxBufferLE::<Rgba5551>::new(
-                        &tex_mem,
-                        copy_width,
-                        height,
-                        self.tex.pitch(),
-                    )
-                    .unwrap();
+                    let tex =
+                        GfxBufferLE::<Rgba5551>::new(tex_mem, copy_width, height, self.tex.pitch())
+                            .unwrap();
 
                     draw_rect(
                         &mut tmem,
@@ -260,9 +622,8 @@ impl Rdp {
                     )
                     .unwrap();
 
-                    let tex =
-                        GfxBufferLE::<I8>::new(&tex_mem, copy_width, height, self.tex.pitch())
-                            .unwrap();
+                    let tex = GfxBufferLE::<I8>::new(tex_mem, copy_width, height, self.tex.pitch())
+                        .unwrap();
 
                     draw_rect(
                         &mut tmem,
@@ -279,10 +640,55 @@ impl Rdp {
 
                 self.cmdlen = 0;
             }
+            0x32 => {
+                // Set Tile Size
+                let tile = cmd.get_bits(24..27) as usize;
+                let s0 = cmd.get_bits(44..56) as u32;
+                let t0 = cmd.get_bits(32..44) as u32;
+                let s1 = cmd.get_bits(12..24) as u32;
+                let t1 = cmd.get_bits(0..12) as u32;
+                self.tiles[tile].rect = Rect::<U30F2>::from_bits(s0, t0, s1, t1);
+                self.texture_changed_gl();
+                info!(self.logger, "DP: Set Tile Size"; "idx" => tile, "rect" => ?self.tiles[tile].rect);
+                self.cmdlen = 0;
+            }
+            0x33 => {
+                // Load Block: load a contiguous run of texels into TMEM.
+                // FIXME: DxT (swapping of odd lines) is not emulated.
+                let tile = cmd.get_bits(24..27) as usize;
+                let s0 = cmd.get_bits(44..56) as u32;
+                let t0 = cmd.get_bits(32..44) as u32;
+                let s1 = cmd.get_bits(12..24) as u32;
+                self.tiles[tile].rect = Rect::<U30F2>::from_bits(s0, t0, s1 << 2, t0);
+                self.load_texture_gl();
+
+                let bpp = self.tex.bpp;
+                let texels = (s1 as usize + 1).saturating_sub(s0 as usize >> 2);
+                let first = (t0 as usize >> 2) * self.tex.width + (s0 as usize >> 2);
+                let src = self.tex.dram_addr + (first * bpp / 8) as u32;
+                let len = (texels * bpp).div_ceil(8);
+                info!(self.logger, "DP: Load Block"; "idx" => tile, "src" => src.hex(), "len" => len);
+
+                let tex_reader = R4300::get().bus.fetch_read::<u8>(src);
+                match tex_reader.mem() {
+                    Some(tex_mem) => {
+                        let tmem_addr = self.tiles[tile].tmem_addr as usize;
+                        let tmem_len = self.tmem.len();
+                        for (idx, b) in tex_mem.iter().take(len).enumerate() {
+                            self.tmem[(tmem_addr + idx) & (tmem_len - 1)] = *b;
+                        }
+                    }
+                    None => {
+                        error!(self.logger, "DP: Load Block from non-linear memory"; "src" => src.hex())
+                    }
+                }
+                self.cmdlen = 0;
+            }
             0x35 => {
                 // Set Tile
                 let idx = cmd.get_bits(24..27) as usize;
                 let color_format = self.parse_color_format(cmd.get_bits(53..56));
+                self.texture_changed_gl();
                 let tile = &mut self.tiles[idx];
                 tile.color_format = color_format;
                 tile.bpp = 4 << cmd.get_bits(51..53);
@@ -307,6 +713,11 @@ impl Rdp {
                 let y0 = cmd.get_bits(0..12) as u32;
                 let mut rect = Rect::<U30F2>::from_bits(x0, y0, x1, y1);
                 info!(self.logger, "DP: Fill Rectangle"; "rect" => ?rect);
+                if self.capture.enabled() {
+                    let prim = (x0 >> 2, y0 >> 2, x1 >> 2, y1 >> 2);
+                    self.record("Fill Rectangle", prim, vec![]);
+                }
+                self.flush_gl();
 
                 match self.cycle_mode {
                     CycleMode::Fill => {
@@ -361,9 +772,33 @@ impl Rdp {
             0x3C => {
                 // Set Combine Mode
                 self.pipeline.set_combine_mode(cmd);
+                self.combine.mode = cmd;
                 info!(self.logger, "DP: Set Combine Mode"; "cmd" => cmd.hex(), "cc" => self.pipeline.fmt_combiner());
                 self.cmdlen = 0;
             }
+            0x38 => {
+                // Set Fog Color
+                let c = Color::<Abgr8888>::from_bits(cmd as u32);
+                self.pipeline.set_fog_color(c.cconv());
+                info!(self.logger, "DP: Set Fog Color"; "c" => ?c);
+                self.cmdlen = 0;
+            }
+            0x3A => {
+                // Set Prim Color
+                let c = Color::<Abgr8888>::from_bits(cmd as u32);
+                self.pipeline.set_prim_color(c.cconv());
+                self.combine.prim = c.cconv();
+                info!(self.logger, "DP: Set Prim Color"; "c" => ?c);
+                self.cmdlen = 0;
+            }
+            0x3B => {
+                // Set Env Color
+                let c = Color::<Abgr8888>::from_bits(cmd as u32);
+                self.pipeline.set_env_color(c.cconv());
+                self.combine.env = c.cconv();
+                info!(self.logger, "DP: Set Env Color"; "c" => ?c);
+                self.cmdlen = 0;
+            }
             0x39 => {
                 // Set Blend Color
                 let c = Color::<Abgr8888>::from_bits(cmd as u32);
@@ -379,3 +814,204 @@ impl Rdp {
         };
     }
 }
+
+#[cfg(feature = "frontend")]
+impl Rdp {
+    /// Select how the triangles are drawn (see N64Builder::video_backend).
+    /// With the OpenGL backend, the GL context of the frontend must be
+    /// current.
+    pub fn set_video_backend(&mut self, backend: VideoBackend) {
+        self.flush_gl();
+        self.gl = match backend {
+            VideoBackend::Software => None,
+            VideoBackend::OpenGl => Some(Box::new(GlBackend::new())),
+        };
+    }
+
+    // Queue a triangle for the OpenGL backend. Returns false if the backend
+    // is not enabled, and the triangle must be drawn in software.
+    fn draw_triangle_gl(&mut self, tri: &Triangle) -> bool {
+        let backend = match self.gl.as_mut() {
+            Some(backend) => backend,
+            None => return false,
+        };
+        let color = |c: Color<Rgba8888>| {
+            let (r, g, b, a) = c.components();
+            let f = |v: i32| v as f32 / 255.0;
+            [f(r), f(g), f(b), f(a)]
+        };
+
+        let fill = matches!(self.cycle_mode, CycleMode::Fill);
+        let (rgb, alpha, prim) = if fill {
+            // In fill mode, the fill color is drawn as a constant.
+            let mut buf = [0u8; 4];
+            BigEndian::write_u32(&mut buf, self.fill_color);
+            let c = read_pixel(&buf, self.fb.bpp / 8).get_color(0);
+            let k = [
+                CombinerInput::Zero,
+                CombinerInput::Zero,
+                CombinerInput::Zero,
+                CombinerInput::Prim,
+            ];
+            (k, k, color(c))
+        } else {
+            let (rgb, alpha) = gl::combiner_inputs(self.combine.mode);
+            (rgb, alpha, color(self.combine.prim))
+        };
+
+        let z_buffer = self.z_image.is_some();
+        let state = DrawState {
+            rgb,
+            alpha,
+            prim,
+            env: color(self.combine.env),
+            depth_test: z_buffer && self.other_modes.get_bit(4),
+            depth_write: z_buffer && self.other_modes.get_bit(5),
+            blend: !fill && gl::alpha_blending(self.other_modes),
+        };
+
+        let tile = &self.tiles[tri.tile];
+        let texture = match tri.tex {
+            Some(_) if !fill && backend.tile != Some(tri.tile) => {
+                backend.tile = Some(tri.tile);
+                Some(tile.decode(&self.tmem))
+            }
+            _ => None,
+        };
+
+        // GL samples pixels at their center, while the RDP covers the pixels
+        // whose top-left corner is inside the triangle: shift by half a pixel.
+        let origin = (tile.rect.c0.x.floor() as f32, tile.rect.c0.y.floor() as f32);
+        let z_prim = self.other_modes.get_bit(2);
+        let prim_z = (self.prim_z << 3) as f32;
+        let mut vertices = [TriVertex::default(); 3];
+        for (tv, v) in vertices.iter_mut().zip(tri.vertices().iter()) {
+            let z = match tri.z {
+                Some(_) if !z_prim => v.z,
+                _ => prim_z,
+            };
+            tv.pos = [v.x + 0.5, v.y + 0.5, (z / 0x3FFFF as f32).clamp(0.0, 1.0)];
+            for (c, s) in tv.color.iter_mut().zip(v.shade.iter()) {
+                *c = (s / 255.0).clamp(0.0, 1.0);
+            }
+            tv.tex = [v.st[0] - origin.0, v.st[1] - origin.1];
+        }
+        backend.push(state, texture, &vertices);
+        true
+    }
+
+    // Draw the triangles queued by the OpenGL backend into the color image.
+    fn flush_gl(&mut self) {
+        let backend = match self.gl.as_mut() {
+            Some(backend) if !backend.is_empty() => backend,
+            _ => return,
+        };
+        let fb_mem = match R4300::get_mut()
+            .bus
+            .fetch_write::<u8>(self.fb.dram_addr)
+            .mem()
+        {
+            Some(mem) => mem,
+            None => {
+                error!(self.logger, "DP: color image in non-linear memory"; "addr" => self.fb.dram_addr.hex());
+                backend.clear();
+                return;
+            }
+        };
+
+        // Nothing is drawn below the scissor, so it bounds the height of the
+        // color image.
+        let bpp = self.fb.bpp / 8;
+        let width = self.fb.width;
+        let height = (self.clip.c1.y.floor().max(0) as usize).min(fb_mem.len() / (width * bpp));
+        if height == 0 {
+            backend.clear();
+            return;
+        }
+
+        let mut pixels = vec![0u8; width * height * 4];
+        for (idx, px) in pixels.chunks_mut(4).enumerate() {
+            let c: Color<Rgba8888> = read_pixel(&fb_mem[idx * bpp..], bpp).get_color(0);
+            let (r, g, b, a) = c.components();
+            px.copy_from_slice(&[r as u8, g as u8, b as u8, a as u8]);
+        }
+        backend.flush(&mut pixels, width, height);
+        for (idx, px) in pixels.chunks(4).enumerate() {
+            let c = Color::<Rgba8888>::new_clamped(px[0], px[1], px[2], px[3]);
+            write_pixel(&mut fb_mem[idx * bpp..], bpp, c);
+        }
+    }
+
+    // Signal that TMEM or the tile descriptors changed, so that the texture
+    // is decoded again by the OpenGL backend.
+    fn texture_changed_gl(&mut self) {
+        if let Some(backend) = self.gl.as_mut() {
+            backend.tile = None;
+        }
+    }
+
+    // Signal that a texture is being loaded into TMEM. If it is read from the
+    // color image, the queued triangles are drawn first.
+    fn load_texture_gl(&mut self) {
+        if self.tex.dram_addr == self.fb.dram_addr {
+            self.flush_gl();
+        }
+        self.texture_changed_gl();
+    }
+}
+
+#[cfg(not(feature = "frontend"))]
+impl Rdp {
+    /// Select how the triangles are drawn (see N64Builder::video_backend).
+    /// Only the software backend is available without the frontend.
+    pub fn set_video_backend(&mut self, backend: VideoBackend) {
+        if backend != VideoBackend::Software {
+            error!(self.logger, "DP: video backend not available"; "backend" => backend.name());
+        }
+    }
+
+    fn draw_triangle_gl(&mut self, _tri: &Triangle) -> bool {
+        false
+    }
+
+    fn flush_gl(&mut self) {}
+
+    fn texture_changed_gl(&mut self) {}
+
+    fn load_texture_gl(&mut self) {}
+}
+
+// Compute the shade color at the specified pixel of a triangle span.
+fn shade_at(shade: &[Attr; 4], span: &Span, x: i32) -> MultiColor {
+    let comp = |idx: usize| (shade[idx].at(span, x) >> 16).clamp(0, 0xFF) as i32;
+    let c = Color::<Rgba8888>::new_clamped(comp(0), comp(1), comp(2), comp(3));
+    MultiColor::from_color(c)
+}
+
+// Read a pixel of the color image (RGBA5551 or RGBA8888), for blending.
+fn read_pixel(mem: &[u8], bpp: usize) -> MultiColor {
+    let c = match bpp {
+        2 => {
+            let v = BigEndian::read_u16(mem);
+            let c = |v: u16| ((v & 0x1F) << 3 | (v & 0x1F) >> 2) as i32;
+            Color::<Rgba8888>::new_clamped(c(v >> 11), c(v >> 6), c(v >> 1), (v & 1) as i32 * 0xFF)
+        }
+        _ => Color::<Abgr8888>::from_bits(BigEndian::read_u32(mem)).cconv(),
+    };
+    MultiColor::from_color(c)
+}
+
+// Write a pixel of the color image (RGBA5551 or RGBA8888).
+fn write_pixel(mem: &mut [u8], bpp: usize, c: Color<Rgba8888>) {
+    match bpp {
+        2 => {
+            let (r, g, b, a) = c.components();
+            let v = (r >> 3) << 11 | (g >> 3) << 6 | (b >> 3) << 1 | (a >> 7);
+            BigEndian::write_u16(mem, v as u16);
+        }
+        _ => {
+            let c: Color<Abgr8888> = c.cconv();
+            BigEndian::write_u32(mem, c.to_bits());
+        }
+    }
+}
@@ -0,0 +1,261 @@
+// Triangle setup and edge walking.
+//
+// Triangle commands describe a triangle by its three edges (the major edge
+// H, from top to bottom, and the two minor edges M and L) as starting X
+// coordinates and slopes, and optionally the shade, texture and depth
+// coefficients: the value at the top of the major edge, and the increments
+// per pixel along X and per scanline along the major edge. The RSP computes
+// all of them, so the RDP only needs to walk the edges one scanline at a
+// time, and interpolate the attributes across each span.
+
+// TODO:
+//   * subpixel coverage (antialiasing)
+//   * perspective-correct texture coordinates
+
+extern crate bit_field;
+use self::bit_field::BitField;
+
+/// An attribute interpolated across a triangle. All values are s15.16 fixed
+/// point.
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct Attr {
+    base: i64, // value at the top of the major edge
+    dx: i64,   // increment per pixel along X
+    de: i64,   // increment per scanline along the major edge
+}
+
+impl Attr {
+    /// Return the value of the attribute at the specified pixel of a span.
+    #[inline(always)]
+    pub fn at(&self, span: &Span, x: i32) -> i64 {
+        let dx = ((x as i64) << 16) - span.major_x;
+        self.base + self.de * span.dy + ((self.dx * dx) >> 16)
+    }
+}
+
+/// A horizontal span of pixels covered by a triangle.
+pub(crate) struct Span {
+    pub y: i32,
+    pub x0: i32,  // first pixel
+    pub x1: i32,  // last pixel (exclusive)
+    major_x: i64, // X of the major edge (s15.16)
+    dy: i64,      // scanlines from the top of the triangle
+}
+
+/// A vertex of a triangle, with the value of its attributes (see
+/// Triangle::vertices).
+#[cfg(feature = "frontend")]
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct Vertex {
+    pub x: f32,
+    pub y: f32,
+    pub shade: [f32; 4], // R, G, B, A (0-255)
+    pub st: [f32; 2],    // S, T (texels)
+    pub z: f32,          // 18-bit depth
+}
+
+pub(crate) struct Triangle {
+    pub left_major: bool, // the major edge is the left one
+    pub tile: usize,
+    yh: i32, // top (s11.2)
+    ym: i32, // middle vertex (s11.2)
+    yl: i32, // bottom (s11.2)
+    xh: i64, // major edge, at the top
+    xm: i64, // first minor edge, at the top
+    xl: i64, // second minor edge, at the middle vertex
+    dxhdy: i64,
+    dxmdy: i64,
+    dxldy: i64,
+    pub shade: Option<[Attr; 4]>, // R, G, B, A
+    pub tex: Option<[Attr; 3]>,   // S, T (s10.5 texels), W
+    pub z: Option<Attr>,
+}
+
+impl Triangle {
+    /// Return the number of words of a triangle command.
+    pub fn num_words(op: u64) -> usize {
+        let mut n = 4;
+        if op & 4 != 0 {
+            n += 8;
+        }
+        if op & 2 != 0 {
+            n += 8;
+        }
+        if op & 1 != 0 {
+            n += 2;
+        }
+        n
+    }
+
+    /// Parse a triangle command (0x08-0x0F), whose words must all be
+    /// available (see num_words).
+    pub fn parse(op: u64, words: &[u64]) -> Triangle {
+        let w0 = words[0];
+        let sx14 = |v: u64| ((v as u32) << 18) as i32 >> 18;
+        let hi = |v: u64| (v >> 32) as i32 as i64;
+        let lo = |v: u64| v as i32 as i64;
+
+        let mut tri = Triangle {
+            left_major: w0.get_bit(55),
+            tile: w0.get_bits(48..51) as usize,
+            yl: sx14(w0.get_bits(32..46)),
+            ym: sx14(w0.get_bits(16..30)),
+            yh: sx14(w0.get_bits(0..14)),
+            xl: hi(words[1]),
+            dxldy: lo(words[1]),
+            xh: hi(words[2]),
+            dxhdy: lo(words[2]),
+            xm: hi(words[3]),
+            dxmdy: lo(words[3]),
+            shade: None,
+            tex: None,
+            z: None,
+        };
+
+        let mut words = &words[4..];
+        if op & 4 != 0 {
+            let a = parse_attrs(&words[..8]);
+            tri.shade = Some([a[0], a[1], a[2], a[3]]);
+            words = &words[8..];
+        }
+        if op & 2 != 0 {
+            let a = parse_attrs(&words[..8]);
+            tri.tex = Some([a[0], a[1], a[2]]);
+            words = &words[8..];
+        }
+        if op & 1 != 0 {
+            tri.z = Some(Attr {
+                base: hi(words[0]),
+                dx: lo(words[0]),
+                de: hi(words[1]),
+            });
+        }
+        tri
+    }
+
+    /// Return the bounding box of the triangle, in pixels (x0, y0, x1, y1,
+    /// inclusive).
+    pub fn bounds(&self) -> (u32, u32, u32, u32) {
+        let mut bounds = (u32::MAX, u32::MAX, 0, 0);
+        self.walk((0, 0, 0x1000, 0x1000), |span| {
+            bounds.0 = bounds.0.min(span.x0 as u32);
+            bounds.1 = bounds.1.min(span.y as u32);
+            bounds.2 = bounds.2.max(span.x1 as u32 - 1);
+            bounds.3 = bounds.3.max(span.y as u32);
+        });
+        bounds
+    }
+
+    /// Return the three vertices of the triangle (top, middle, bottom), in
+    /// the same coordinates used by walk, with the attributes interpolated
+    /// at each of them.
+    #[cfg(feature = "frontend")]
+    pub fn vertices(&self) -> [Vertex; 3] {
+        let ytop = (self.yh >> 2) as f64;
+        let fixed = |v: i64| v as f64 / 65536.0;
+        let major_x = |y: f64| fixed(self.xh) + fixed(self.dxhdy) * (y - ytop);
+        let attr = |a: &Attr, x: f64, y: f64| {
+            fixed(a.base) + fixed(a.de) * (y - ytop) + fixed(a.dx) * (x - major_x(y))
+        };
+
+        let ym = self.ym as f64 / 4.0;
+        let yl = self.yl as f64 / 4.0;
+        let points = [
+            (fixed(self.xh), self.yh as f64 / 4.0),
+            (fixed(self.xl), ym),
+            (major_x(yl), yl),
+        ];
+        let mut vtx = [Vertex::default(); 3];
+        for (v, &(x, y)) in vtx.iter_mut().zip(points.iter()) {
+            v.x = x as f32;
+            v.y = y as f32;
+            if let Some(ref shade) = self.shade {
+                for (c, a) in v.shade.iter_mut().zip(shade.iter()) {
+                    *c = attr(a, x, y) as f32;
+                }
+            }
+            if let Some(ref tex) = self.tex {
+                // S and T are s10.5 texel coordinates.
+                v.st[0] = (attr(&tex[0], x, y) / 32.0) as f32;
+                v.st[1] = (attr(&tex[1], x, y) / 32.0) as f32;
+            }
+            if let Some(ref z) = self.z {
+                v.z = (attr(z, x, y) * 8.0) as f32;
+            }
+        }
+        vtx
+    }
+
+    /// Walk the edges of the triangle, calling f for each span of pixels
+    /// within the clipping rectangle (x0, y0, x1, y1 in pixels, exclusive).
+    /// Pixels are covered if their top-left corner is inside the triangle.
+    pub fn walk<F: FnMut(&Span)>(&self, clip: (i32, i32, i32, i32), mut f: F) {
+        let ytop = self.yh >> 2;
+        let ymid = self.ym >> 2;
+        for y in ytop.max(clip.1)..(self.yl >> 2).min(clip.3) {
+            let dy = (y - ytop) as i64;
+            let major = self.xh + self.dxhdy * dy;
+            let minor = if y < ymid {
+                self.xm + self.dxmdy * dy
+            } else {
+                self.xl + self.dxldy * (y - ymid) as i64
+            };
+            let (left, right) = if self.left_major {
+                (major, minor)
+            } else {
+                (minor, major)
+            };
+
+            let x0 = ((left >> 16) as i32).max(clip.0);
+            let x1 = ((right >> 16) as i32).min(clip.2);
+            if x0 < x1 {
+                f(&Span {
+                    y,
+                    x0,
+                    x1,
+                    major_x: major,
+                    dy,
+                });
+            }
+        }
+    }
+}
+
+// Parse a block of shade or texture coefficients: 8 words with the integer
+// and fractional parts of up to 4 attributes (values, X increments, edge
+// increments, Y increments).
+fn parse_attrs(words: &[u64]) -> [Attr; 4] {
+    let fixed = |int: u64, frac: u64, idx: usize| {
+        let shift = 48 - idx * 16;
+        let int = (int >> shift) as i16 as i64;
+        let frac = (frac >> shift) & 0xFFFF;
+        (int << 16) | frac as i64
+    };
+    let mut attrs = [Attr::default(); 4];
+    for (idx, attr) in attrs.iter_mut().enumerate() {
+        attr.base = fixed(words[0], words[2], idx);
+        attr.dx = fixed(words[1], words[3], idx);
+        attr.de = fixed(words[4], words[6], idx);
+    }
+    attrs
+}
+
+/// Convert an 18-bit depth value into the 16-bit format stored in the
+/// Z-buffer (a 3-bit exponent and an 11-bit mantissa, followed by 2 bits
+/// of delta Z, which are not emulated). The conversion preserves ordering.
+pub(crate) fn z_compress(z: u32) -> u16 {
+    let z = z.min(0x3FFFF);
+    let exp = (!z << 14).leading_zeros().min(7);
+    let shift = 6u32.saturating_sub(exp);
+    let mant = (z >> shift) & 0x7FF;
+    ((exp << 13) | (mant << 2)) as u16
+}
+
+/// Convert a value read from the Z-buffer back into an 18-bit depth value.
+pub(crate) fn z_decompress(val: u16) -> u32 {
+    let exp = (val >> 13) as u32;
+    let mant = ((val >> 2) & 0x7FF) as u32;
+    let shift = 6u32.saturating_sub(exp);
+    let base = (0x3F800u32 << (7 - exp)) & 0x3F800; // exp leading ones
+    base | (mant << shift)
+}
@@ -1,11 +1,16 @@
 extern crate emu;
 extern crate slog;
-use emu::bus::be::{Mem, Reg32};
+use emu::bus::be::{Mem, MemFlags, Reg32};
+use emu::dbg::{Heap, HeapView, MemoryView};
+
+use super::libultra;
 
 /// RDRAM
 #[derive(DeviceBE)]
+#[device(subword = "shift")]
 pub struct Ri {
-    #[mem(bank = 0, size = 4194304, offset = 0x0000_0000)]
+    // Address space not backed by the installed RDRAM modules reads as zero.
+    #[mem(bank = 0, offset = 0x0000_0000, vsize = 0x03F0_0000, fill = "Fixed(0)")]
     pub(crate) rdram: Mem,
 
     #[reg(bank = 1, offset = 0x00)]
@@ -82,10 +87,31 @@ pub struct Ri {
     _logger: slog::Logger,
 }
 
+/// Size of the RDRAM installed on the motherboard.
+pub const RDRAM_SIZE: usize = 4 * 1024 * 1024;
+
+/// Size of the RDRAM with the Expansion Pak installed.
+pub const RDRAM_SIZE_EXPANSION: usize = 8 * 1024 * 1024;
+
+/// Size of the RDRAM address space seen by the DMA controllers of the RCP,
+/// whose RDRAM address counters are 24-bit wide: transfers wrap around at
+/// its end.
+pub const RDRAM_DMA_WRAP: u32 = 0x0100_0000;
+
 impl Ri {
     pub fn new(logger: slog::Logger) -> Box<Ri> {
+        Ri::with_rdram_size(logger, RDRAM_SIZE)
+    }
+
+    /// Create the RDRAM interface with the specified amount of RDRAM
+    /// installed (RDRAM_SIZE, or RDRAM_SIZE_EXPANSION).
+    pub fn with_rdram_size(logger: slog::Logger, size: usize) -> Box<Ri> {
+        assert!(
+            size == RDRAM_SIZE || size == RDRAM_SIZE_EXPANSION,
+            "invalid RDRAM size"
+        );
         Box::new(Ri {
-            rdram: Mem::default(),
+            rdram: Mem::new("Ri::rdram", size, MemFlags::default()),
 
             reg_rdram_config: Reg32::default(),
             reg_rdram_device_id: Reg32::default(),
@@ -111,3 +137,32 @@ impl Ri {
         })
     }
 }
+
+impl HeapView for Ri {
+    fn name(&self) -> &str {
+        "RDRAM"
+    }
+
+    fn find_heaps(&self) -> Vec<u64> {
+        libultra::find_heaps(&self.rdram)
+    }
+
+    fn heap(&self, addr: u64) -> Option<Heap> {
+        libultra::decode_heap(&self.rdram, addr as u32)
+    }
+}
+
+impl MemoryView for Ri {
+    fn name(&self) -> &str {
+        "RDRAM"
+    }
+
+    // RDRAM is shown at its cached (KSEG0) address, as used by games.
+    fn mem(&self, _idx: usize) -> (&str, u64, &[u8]) {
+        ("RDRAM", 0x8000_0000, &self.rdram[..])
+    }
+
+    fn mem_mut(&mut self, _idx: usize) -> &mut [u8] {
+        &mut self.rdram[..]
+    }
+}
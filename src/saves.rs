@@ -0,0 +1,254 @@
+//! Save files of cartridge save media and Controller Paks.
+//!
+//! Save data is kept in memory in its native format: the exact contents of
+//! the chip, in big-endian byte order (which is also what flashcarts like
+//! the EverDrive and the 64drive use). Other emulators and tools store it
+//! differently: byteswapped, with a header, or padded to a larger size.
+//! This module converts between those formats.
+
+use crate::errors::SaveError;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Magic string at the beginning of DexDrive (.n64) files.
+const DEXDRIVE_MAGIC: &[u8] = b"123-456-STD";
+
+/// Size of the DexDrive header (including the note comments).
+const DEXDRIVE_HEADER_SIZE: usize = 0x1040;
+
+/// A kind of save media.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SaveMedia {
+    Eeprom4k,
+    Eeprom16k,
+    Sram,
+    FlashRam,
+    Mempak,
+}
+
+impl SaveMedia {
+    pub const ALL: [SaveMedia; 5] = [
+        SaveMedia::Eeprom4k,
+        SaveMedia::Eeprom16k,
+        SaveMedia::Sram,
+        SaveMedia::FlashRam,
+        SaveMedia::Mempak,
+    ];
+
+    /// Return the size of the media, in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            SaveMedia::Eeprom4k => 0x200,
+            SaveMedia::Eeprom16k => 0x800,
+            SaveMedia::Sram => 0x8000,
+            SaveMedia::FlashRam => 0x20000,
+            SaveMedia::Mempak => 0x8000,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SaveMedia::Eeprom4k => "eeprom4k",
+            SaveMedia::Eeprom16k => "eeprom16k",
+            SaveMedia::Sram => "sram",
+            SaveMedia::FlashRam => "flashram",
+            SaveMedia::Mempak => "mempak",
+        }
+    }
+
+    /// Return the file extension conventionally used for this media.
+    pub fn extension(self) -> &'static str {
+        match self {
+            SaveMedia::Eeprom4k | SaveMedia::Eeprom16k => "eep",
+            SaveMedia::Sram => "sra",
+            SaveMedia::FlashRam => "fla",
+            SaveMedia::Mempak => "mpk",
+        }
+    }
+}
+
+impl fmt::Display for SaveMedia {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SaveMedia {
+    type Err = String;
+    fn from_str(s: &str) -> Result<SaveMedia, String> {
+        SaveMedia::ALL
+            .iter()
+            .cloned()
+            .find(|m| m.name() == s)
+            .ok_or_else(|| format!("unknown save media: {}", s))
+    }
+}
+
+/// A file format for save data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SaveFormat {
+    /// Native byte order (flashcarts, most emulators).
+    Raw,
+    /// Bytes swapped within each 16-bit halfword.
+    Swap16,
+    /// Bytes swapped within each 32-bit word (Project64 SRAM/FlashRAM).
+    Swap32,
+    /// DexDrive Controller Pak dump (.n64), with a 0x1040-byte header.
+    DexDrive,
+}
+
+impl SaveFormat {
+    pub const ALL: [SaveFormat; 4] = [
+        SaveFormat::Raw,
+        SaveFormat::Swap16,
+        SaveFormat::Swap32,
+        SaveFormat::DexDrive,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            SaveFormat::Raw => "raw",
+            SaveFormat::Swap16 => "swap16",
+            SaveFormat::Swap32 => "swap32",
+            SaveFormat::DexDrive => "dexdrive",
+        }
+    }
+
+    fn swap(self, data: &mut [u8]) {
+        match self {
+            SaveFormat::Swap16 => data.chunks_mut(2).for_each(|c| c.reverse()),
+            SaveFormat::Swap32 => data.chunks_mut(4).for_each(|c| c.reverse()),
+            SaveFormat::Raw | SaveFormat::DexDrive => {}
+        }
+    }
+}
+
+impl fmt::Display for SaveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SaveFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<SaveFormat, String> {
+        SaveFormat::ALL
+            .iter()
+            .cloned()
+            .find(|m| m.name() == s)
+            .ok_or_else(|| format!("unknown save format: {}", s))
+    }
+}
+
+fn is_padding(data: &[u8]) -> bool {
+    data.iter().all(|&b| b == 0x00) || data.iter().all(|&b| b == 0xFF)
+}
+
+/// Guess the save media and the file format from the file name and the
+/// size of the data. Returns None if the save type cannot be detected.
+pub fn detect(path: &Path, data: &[u8]) -> Option<(SaveMedia, SaveFormat)> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        // 4kbit EEPROMs are often saved padded to 16kbit.
+        "eep" if data.len() <= SaveMedia::Eeprom4k.size() => {
+            Some((SaveMedia::Eeprom4k, SaveFormat::Raw))
+        }
+        "eep" => Some((SaveMedia::Eeprom16k, SaveFormat::Raw)),
+        "sra" => Some((SaveMedia::Sram, SaveFormat::Raw)),
+        "fla" => Some((SaveMedia::FlashRam, SaveFormat::Raw)),
+        "mpk" => Some((SaveMedia::Mempak, SaveFormat::Raw)),
+        "n64" if data.starts_with(DEXDRIVE_MAGIC) => {
+            Some((SaveMedia::Mempak, SaveFormat::DexDrive))
+        }
+        _ => None,
+    }
+}
+
+/// Convert the contents of a save file into the native contents of the
+/// media.
+///
+/// Files larger than the media are accepted only if the excess is padding
+/// (all 0x00 or all 0xFF), which is how some tools store smaller media
+/// (eg: 4kbit EEPROMs in 2KB files). Files smaller than the media are
+/// padded with 0xFF (erased flash), as some tools truncate them.
+pub fn import(media: SaveMedia, format: SaveFormat, data: &[u8]) -> Result<Vec<u8>, SaveError> {
+    let data = match format {
+        SaveFormat::DexDrive => {
+            if media != SaveMedia::Mempak
+                || data.len() < DEXDRIVE_HEADER_SIZE
+                || !data.starts_with(DEXDRIVE_MAGIC)
+            {
+                return Err(SaveError::InvalidHeader { format });
+            }
+            &data[DEXDRIVE_HEADER_SIZE..]
+        }
+        _ => data,
+    };
+
+    let size = media.size();
+    if data.len() > size && !is_padding(&data[size..]) {
+        return Err(SaveError::InvalidSize {
+            media,
+            size: data.len(),
+        });
+    }
+
+    let mut image = data[..data.len().min(size)].to_vec();
+    image.resize(size, 0xFF);
+    format.swap(&mut image);
+    Ok(image)
+}
+
+/// Convert the native contents of the media into a save file.
+pub fn export(media: SaveMedia, format: SaveFormat, image: &[u8]) -> Result<Vec<u8>, SaveError> {
+    if image.len() != media.size() {
+        return Err(SaveError::InvalidSize {
+            media,
+            size: image.len(),
+        });
+    }
+
+    let mut data = image.to_vec();
+    format.swap(&mut data);
+    if format == SaveFormat::DexDrive {
+        if media != SaveMedia::Mempak {
+            return Err(SaveError::InvalidHeader { format });
+        }
+        let mut header = vec![0u8; DEXDRIVE_HEADER_SIZE];
+        header[..DEXDRIVE_MAGIC.len()].copy_from_slice(DEXDRIVE_MAGIC);
+        header.extend(data);
+        data = header;
+    }
+    Ok(data)
+}
+
+/// Convert a save file into another format. The media and the format of the
+/// input file are detected from its name and contents, unless specified.
+/// Returns the media of the save.
+pub fn convert_file(
+    input: &Path,
+    output: &Path,
+    media: Option<SaveMedia>,
+    from: Option<SaveFormat>,
+    to: SaveFormat,
+) -> Result<SaveMedia, SaveError> {
+    let data = fs::read(input).map_err(|err| SaveError::io(input, err))?;
+    let (media, from) = match (media, from, detect(input, &data)) {
+        (Some(media), Some(from), _) => (media, from),
+        (media, from, Some((dmedia, dfrom))) => (media.unwrap_or(dmedia), from.unwrap_or(dfrom)),
+        (Some(media), None, None) => (media, SaveFormat::Raw),
+        (None, _, None) => {
+            return Err(SaveError::UnknownType {
+                path: input.display().to_string(),
+            })
+        }
+    };
+
+    let image = import(media, from, &data)?;
+    let data = export(media, to, &image)?;
+    fs::write(output, data).map_err(|err| SaveError::io(output, err))?;
+    Ok(media)
+}
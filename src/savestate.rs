@@ -0,0 +1,50 @@
+//! Savestates: snapshots of the whole machine that can be written to disk
+//! and reloaded later, also by a different process.
+//!
+//! All the emulated state (CPU registers, COP0/COP1/COP2, RDRAM, DMEM/IMEM,
+//! device registers) is kept in [`emu::state`](../../emu/state/index.html)
+//! fields, so a savestate is the serialization of the current `State`. Each
+//! device can inspect its own part through the
+//! [`Snapshot`](../../emu/bus/trait.Snapshot.html) trait.
+//!
+//! The format is versioned: bump `SAVESTATE_VERSION` when a change makes the
+//! previous savestates unusable (adding or removing fields does not).
+
+use emu::state::CurrentState;
+
+use std::io::{Read, Write};
+
+use super::errors::*;
+
+// Magic string and version of savestates.
+const SAVESTATE_MAGIC: &str = "r64emu-savestate";
+const SAVESTATE_VERSION: u32 = 1;
+
+/// Save the state of the current machine into writer.
+pub fn save_state<W: Write>(writer: W) -> Result<()> {
+    CurrentState()
+        .serialize(writer, SAVESTATE_MAGIC, SAVESTATE_VERSION)
+        .map_err(|err| EmuError::State(err.to_string()))
+}
+
+/// Load a state saved by [`save_state`](fn.save_state.html) into the current
+/// machine. The machine must have been created with the same devices; fields
+/// missing from the savestate keep their current value. On error, the
+/// current state is left untouched.
+pub fn load_state<R: Read>(reader: R) -> Result<()> {
+    let mut state = CurrentState().clone();
+    state
+        .deserialize(reader, SAVESTATE_MAGIC, SAVESTATE_VERSION)
+        .map_err(|err| EmuError::State(err.to_string()))?;
+    state.make_current();
+    Ok(())
+}
+
+/// Read the contents of RDRAM from a state saved by
+/// [`save_state`](fn.save_state.html), without loading it into a machine
+/// (eg: to compare the memory of two savestates).
+pub fn read_rdram<R: Read>(reader: R) -> Result<Vec<u8>> {
+    emu::state::read_bytes_field(reader, SAVESTATE_MAGIC, SAVESTATE_VERSION, "Ri::rdram")
+        .map_err(|err| EmuError::State(err.to_string()))?
+        .ok_or_else(|| EmuError::State("RDRAM missing from savestate".into()))
+}
@@ -0,0 +1,221 @@
+//! SummerCart64 flashcart emulation.
+//!
+//! Only the parts of the interface needed by homebrew to access the SD card
+//! are emulated: the register interface (with its lock), the data buffer and
+//! the SD card commands. See the SummerCart64 documentation for the
+//! description of the commands.
+
+use super::cartridge::Cartridge;
+use crate::errors::LoadError;
+use crate::sdcard::{SdCard, SECTOR_SIZE};
+use emu::bus::be::{Device, Mem, Reg32};
+use emu::int::Numerics;
+use emu::state::Field;
+use emu_derive::DeviceBE;
+use std::path::Path;
+
+/// Bus address of the data buffer.
+pub const BUFFER_BASE: u32 = 0x1FFE_0000;
+
+/// Bus address of the registers.
+pub const REGS_BASE: u32 = 0x1FFF_0000;
+
+// Value of the identifier register ("SCv2").
+const IDENTIFIER: u32 = 0x5343_7632;
+
+// Firmware version reported by VERSION_GET.
+const VERSION: (u32, u32) = (2, 12);
+
+// Sequence of writes to the KEY register that unlocks the registers.
+const UNLOCK_SEQUENCE: [u32; 3] = [0x0000_0000, 0x5F55_4E4C, 0x4F43_4B5F];
+const LOCK_KEY: u32 = 0xFFFF_FFFF;
+
+// SCR bits
+const SCR_CMD_ERROR: u32 = 1 << 30;
+
+// Error codes returned in DATA0 when a command fails.
+const ERR_UNKNOWN_CMD: u32 = 1;
+const ERR_BAD_ADDRESS: u32 = 2;
+const ERR_NO_CARD: u32 = 3;
+const ERR_CARD_IO: u32 = 4;
+
+// SD card status bits (SD_CARD_OP, get status).
+const SD_INSERTED: u32 = 1 << 0;
+const SD_INITIALIZED: u32 = 1 << 1;
+const SD_TYPE_BLOCK: u32 = 1 << 2;
+const SD_BYTE_SWAP: u32 = 1 << 4;
+
+#[derive(DeviceBE)]
+pub struct Sc64 {
+    #[mem(bank = 0, offset = 0x0, size = 0x2000)]
+    buffer: Mem,
+
+    // (W): [7:0] command to execute, using DATA0/DATA1 as arguments
+    // (R): [31] command busy, [30] command error
+    #[reg(bank = 1, offset = 0x00, wcb)]
+    scr: Reg32,
+
+    #[reg(bank = 1, offset = 0x04)]
+    data0: Reg32,
+
+    #[reg(bank = 1, offset = 0x08)]
+    data1: Reg32,
+
+    // (R): "SCv2" once unlocked, 0 otherwise
+    #[reg(bank = 1, offset = 0x0C, readonly)]
+    identifier: Reg32,
+
+    // (W): lock/unlock sequence
+    #[reg(bank = 1, offset = 0x10, writeonly, wcb)]
+    key: Reg32,
+
+    unlock: Field<usize>, // Progress in the unlock sequence
+    sd_sector: Field<u32>,
+    sd_status: Field<u32>,
+    sdcard: Option<SdCard>,
+    logger: slog::Logger,
+}
+
+impl Sc64 {
+    pub fn new(logger: slog::Logger) -> Box<Sc64> {
+        Box::new(Sc64 {
+            buffer: Mem::default(),
+            scr: Reg32::default(),
+            data0: Reg32::default(),
+            data1: Reg32::default(),
+            identifier: Reg32::default(),
+            key: Reg32::default(),
+            unlock: Field::new("Sc64::unlock", 0),
+            sd_sector: Field::new("Sc64::sd_sector", 0),
+            sd_status: Field::new("Sc64::sd_status", 0),
+            sdcard: None,
+            logger,
+        })
+    }
+
+    /// Insert an SD card backed by the specified disk image or directory.
+    pub fn insert_sdcard(&mut self, path: &Path) -> Result<(), LoadError> {
+        let card = SdCard::open(path)?;
+        info!(self.logger, "SD card inserted"; o!("path" => path.display().to_string(), "sectors" => card.sectors()));
+        self.sdcard = Some(card);
+        *self.sd_status = SD_INSERTED;
+        Ok(())
+    }
+
+    fn locked(&self) -> bool {
+        *self.unlock != UNLOCK_SEQUENCE.len()
+    }
+
+    fn cb_write_key(&mut self, _old: u32, new: u32) {
+        *self.unlock = match new {
+            LOCK_KEY => 0,
+            _ if !self.locked() => *self.unlock,
+            _ if new == UNLOCK_SEQUENCE[*self.unlock] => *self.unlock + 1,
+            _ if new == UNLOCK_SEQUENCE[0] => 1,
+            _ => 0,
+        };
+        self.identifier
+            .set(if self.locked() { 0 } else { IDENTIFIER });
+    }
+
+    fn cb_write_scr(&mut self, _old: u32, new: u32) {
+        self.scr.set(0);
+        if self.locked() {
+            return;
+        }
+        let cmd = new as u8 as char;
+        if let Err(code) = self.command(cmd) {
+            warn!(self.logger, "command failed"; o!("cmd" => cmd.to_string(), "err" => code));
+            self.data0.set(code);
+            self.scr.set(SCR_CMD_ERROR);
+        }
+    }
+
+    fn command(&mut self, cmd: char) -> Result<(), u32> {
+        let (arg0, arg1) = (self.data0.get(), self.data1.get());
+        match cmd {
+            'v' => self.data0.set(IDENTIFIER),
+            'V' => {
+                self.data0.set(VERSION.0 << 16 | VERSION.1);
+                self.data1.set(0);
+            }
+            // Configuration options are accepted, but have no effect.
+            'c' => self.data1.set(0),
+            'C' => {}
+            'i' => self.sd_card_op(arg0, arg1)?,
+            'I' => *self.sd_sector = arg0,
+            's' | 'S' => {
+                if *self.sd_status & SD_INITIALIZED == 0 {
+                    return Err(ERR_NO_CARD);
+                }
+                let sector = *self.sd_sector as u64;
+                let len = arg1 as usize * SECTOR_SIZE;
+                info!(self.logger, "SD card transfer"; o!(
+                    "cmd" => cmd.to_string(), "sector" => sector, "count" => arg1, "addr" => arg0.hex()));
+
+                let swap = *self.sd_status & SD_BYTE_SWAP != 0;
+                let card = self.sdcard.as_mut().ok_or(ERR_NO_CARD)?;
+                let mem = pi_memory(&mut self.buffer, arg0, len).ok_or(ERR_BAD_ADDRESS)?;
+                let res = if cmd == 's' {
+                    card.read(sector, mem).map(|_| {
+                        if swap {
+                            mem.chunks_mut(2).for_each(|c| c.reverse());
+                        }
+                    })
+                } else if swap {
+                    let mut data = mem.to_vec();
+                    data.chunks_mut(2).for_each(|c| c.reverse());
+                    card.write(sector, &data)
+                } else {
+                    card.write(sector, mem)
+                };
+                if let Err(err) = res {
+                    error!(self.logger, "SD card error"; o!("err" => err.to_string()));
+                    return Err(ERR_CARD_IO);
+                }
+                *self.sd_sector += arg1;
+            }
+            _ => return Err(ERR_UNKNOWN_CMD),
+        }
+        Ok(())
+    }
+
+    fn sd_card_op(&mut self, addr: u32, op: u32) -> Result<(), u32> {
+        let sectors = self.sdcard.as_ref().ok_or(ERR_NO_CARD)?.sectors();
+        match op {
+            0 => *self.sd_status |= SD_INITIALIZED | SD_TYPE_BLOCK,
+            1 => *self.sd_status &= !(SD_INITIALIZED | SD_TYPE_BLOCK),
+            2 => self.data1.set(*self.sd_status),
+            3 => {
+                // Card registers: CSD (version 2.0, which only encodes the
+                // capacity in units of 512KB), followed by CID.
+                let mem = pi_memory(&mut self.buffer, addr, 32).ok_or(ERR_BAD_ADDRESS)?;
+                let c_size = (sectors / 1024).saturating_sub(1) as u32;
+                let mut regs = [0u8; 32];
+                regs[0] = 0x40;
+                regs[3] = 0x32; // 25MHz
+                regs[5] = 0x59; // 512-byte blocks
+                regs[7] = (c_size >> 16) as u8 & 0x3F;
+                regs[8] = (c_size >> 8) as u8;
+                regs[9] = c_size as u8;
+                regs[19..24].copy_from_slice(b"R64EM");
+                mem.copy_from_slice(&regs);
+            }
+            4 => *self.sd_status |= SD_BYTE_SWAP,
+            5 => *self.sd_status &= !SD_BYTE_SWAP,
+            _ => return Err(ERR_UNKNOWN_CMD),
+        }
+        Ok(())
+    }
+}
+
+// Return the memory at the specified PI address, which can be either in the
+// data buffer, or in the SDRAM (the cartridge ROM).
+fn pi_memory(buffer: &mut Mem, addr: u32, len: usize) -> Option<&mut [u8]> {
+    let (mem, off) = match addr {
+        0x1000_0000..=0x1FFD_FFFF => (Cartridge::get_mut().rom_mut(), addr - 0x1000_0000),
+        BUFFER_BASE..=0x1FFE_FFFF => (&mut buffer[..], addr - BUFFER_BASE),
+        _ => return None,
+    };
+    mem.get_mut(off as usize..off as usize + len)
+}
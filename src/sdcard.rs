@@ -0,0 +1,413 @@
+//! SD card emulation, for flashcarts with an SD card slot.
+//!
+//! The card can be backed either by a disk image file, which is modified in
+//! place, or by a host directory. In the latter case, the directory is
+//! presented to the guest as a FAT32 volume, synthesized on the fly: file
+//! contents are read from the host when the corresponding sectors are
+//! accessed. Writes from the guest are kept in memory and never modify the
+//! host directory.
+
+use crate::errors::LoadError;
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Size of a sector, in bytes.
+pub const SECTOR_SIZE: usize = 512;
+
+/// An SD card, seen as an array of sectors.
+pub struct SdCard {
+    backend: Backend,
+}
+
+enum Backend {
+    Image(File, u64),
+    Directory(VirtualFat),
+}
+
+impl SdCard {
+    /// Open an SD card backed by the specified disk image or directory.
+    pub fn open(path: &Path) -> Result<SdCard, LoadError> {
+        let backend = if path.is_dir() {
+            Backend::Directory(VirtualFat::new(path).map_err(|err| LoadError::io(path, err))?)
+        } else {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .map_err(|err| LoadError::io(path, err))?;
+            let len = file
+                .metadata()
+                .map_err(|err| LoadError::io(path, err))?
+                .len();
+            Backend::Image(file, len / SECTOR_SIZE as u64)
+        };
+        Ok(SdCard { backend })
+    }
+
+    /// Return the number of sectors of the card.
+    pub fn sectors(&self) -> u64 {
+        match &self.backend {
+            Backend::Image(_, sectors) => *sectors,
+            Backend::Directory(fat) => fat.total_sectors as u64,
+        }
+    }
+
+    /// Read consecutive sectors into `buf` (whose length must be a multiple
+    /// of the sector size).
+    pub fn read(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.check_range(sector, buf.len())?;
+        match &mut self.backend {
+            Backend::Image(file, _) => {
+                file.seek(SeekFrom::Start(sector * SECTOR_SIZE as u64))?;
+                file.read_exact(buf)
+            }
+            Backend::Directory(fat) => {
+                for (i, chunk) in buf.chunks_mut(SECTOR_SIZE).enumerate() {
+                    fat.read_sector(sector as u32 + i as u32, chunk)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Write consecutive sectors from `buf` (whose length must be a multiple
+    /// of the sector size).
+    pub fn write(&mut self, sector: u64, buf: &[u8]) -> io::Result<()> {
+        self.check_range(sector, buf.len())?;
+        match &mut self.backend {
+            Backend::Image(file, _) => {
+                file.seek(SeekFrom::Start(sector * SECTOR_SIZE as u64))?;
+                file.write_all(buf)
+            }
+            Backend::Directory(fat) => {
+                for (i, chunk) in buf.chunks(SECTOR_SIZE).enumerate() {
+                    fat.overlay.insert(sector as u32 + i as u32, chunk.to_vec());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn check_range(&self, sector: u64, len: usize) -> io::Result<()> {
+        let count = (len / SECTOR_SIZE) as u64;
+        if !len.is_multiple_of(SECTOR_SIZE) || sector + count > self.sectors() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid access to sectors {}..{}", sector, sector + count),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Geometry of the synthesized FAT32 volume.
+const CLUSTER_SECTORS: u32 = 8;
+const CLUSTER_SIZE: usize = CLUSTER_SECTORS as usize * SECTOR_SIZE;
+const RESERVED_SECTORS: u32 = 32;
+const FSINFO_SECTOR: u32 = 1;
+const BACKUP_BOOT_SECTOR: u32 = 6;
+const ROOT_CLUSTER: u32 = 2;
+
+// FAT32 requires at least 65525 clusters (otherwise it would be FAT16).
+const MIN_CLUSTERS: u32 = 65525 + 16;
+
+// Free clusters left for files created by the guest (64MB).
+const SPARE_CLUSTERS: u32 = 16384;
+
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LFN: u8 = 0x0F;
+
+// Contents of a range of clusters.
+enum Content {
+    Dir(Vec<u8>),
+    File(PathBuf),
+}
+
+struct Extent {
+    first: u32, // First cluster
+    count: u32, // Number of (contiguous) clusters
+    content: Content,
+}
+
+// A FAT32 volume synthesized from a host directory. Each file and directory
+// is allocated in contiguous clusters.
+struct VirtualFat {
+    fat: Vec<u32>,        // FAT entries of the allocated clusters
+    extents: Vec<Extent>, // Sorted by first cluster
+    fat_sectors: u32,
+    total_sectors: u32,
+    overlay: HashMap<u32, Vec<u8>>, // Sectors written by the guest
+}
+
+impl VirtualFat {
+    fn new(root: &Path) -> io::Result<VirtualFat> {
+        let mut fat = VirtualFat {
+            fat: vec![0x0FFF_FFF8, END_OF_CHAIN],
+            extents: Vec::new(),
+            fat_sectors: 0,
+            total_sectors: 0,
+            overlay: HashMap::new(),
+        };
+        fat.add_dir(root, None)?;
+        fat.extents.sort_by_key(|e| e.first);
+
+        let clusters = (fat.fat.len() as u32 - 2 + SPARE_CLUSTERS).max(MIN_CLUSTERS);
+        fat.fat_sectors = ((clusters + 2) * 4).div_ceil(SECTOR_SIZE as u32);
+        fat.total_sectors = RESERVED_SECTORS + fat.fat_sectors + clusters * CLUSTER_SECTORS;
+        Ok(fat)
+    }
+
+    // Allocate a chain of contiguous clusters, returning the first one
+    // (or 0 for an empty chain).
+    fn alloc(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let first = self.fat.len() as u32;
+        for c in first..first + count - 1 {
+            self.fat.push(c + 1);
+        }
+        self.fat.push(END_OF_CHAIN);
+        first
+    }
+
+    // Add a directory (recursively), returning its first cluster. `parent`
+    // is the first cluster of the parent directory (None for the root).
+    fn add_dir(&mut self, path: &Path, parent: Option<u32>) -> io::Result<u32> {
+        let mut children: Vec<_> = fs::read_dir(path)?.collect::<io::Result<_>>()?;
+        children.sort_by_key(|e| e.file_name());
+        let names: Vec<String> = children
+            .iter()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+
+        // Allocate the directory before its children, so that its size must
+        // be computed upfront.
+        let mut short_names = Vec::new();
+        let mut nentries = if parent.is_some() { 2 } else { 0 };
+        for (idx, name) in names.iter().enumerate() {
+            let (short, lfn) = short_name(name, idx + 1);
+            nentries += 1 + if lfn {
+                name.encode_utf16().count().div_ceil(13)
+            } else {
+                0
+            };
+            short_names.push((short, lfn));
+        }
+        let count = (nentries * 32).div_ceil(CLUSTER_SIZE);
+        let first = self.alloc(count.max(1) as u32);
+
+        let mut data = Vec::with_capacity(count.max(1) * CLUSTER_SIZE);
+        if let Some(parent) = parent {
+            data.extend_from_slice(&dir_entry(b".          ", ATTR_DIRECTORY, first, 0));
+            // The root directory is referenced as cluster 0.
+            let parent = if parent == ROOT_CLUSTER { 0 } else { parent };
+            data.extend_from_slice(&dir_entry(b"..         ", ATTR_DIRECTORY, parent, 0));
+        }
+        for (child, (name, (short, lfn))) in children.iter().zip(names.iter().zip(short_names)) {
+            let meta = child.metadata()?;
+            let (attr, cluster, size) = if meta.is_dir() {
+                (ATTR_DIRECTORY, self.add_dir(&child.path(), Some(first))?, 0)
+            } else {
+                // FAT32 files are limited to 4GB.
+                let size = meta.len().min(0xFFFF_FFFF) as u32;
+                let count = (size as usize).div_ceil(CLUSTER_SIZE);
+                let cluster = self.alloc(count as u32);
+                if count > 0 {
+                    self.extents.push(Extent {
+                        first: cluster,
+                        count: count as u32,
+                        content: Content::File(child.path()),
+                    });
+                }
+                (0, cluster, size)
+            };
+            if lfn {
+                data.extend(lfn_entries(name, &short));
+            }
+            data.extend_from_slice(&dir_entry(&short, attr, cluster, size));
+        }
+        data.resize(count.max(1) * CLUSTER_SIZE, 0);
+
+        self.extents.push(Extent {
+            first,
+            count: count.max(1) as u32,
+            content: Content::Dir(data),
+        });
+        Ok(first)
+    }
+
+    fn read_sector(&self, sector: u32, buf: &mut [u8]) -> io::Result<()> {
+        if let Some(data) = self.overlay.get(&sector) {
+            buf.copy_from_slice(data);
+            return Ok(());
+        }
+        for b in buf.iter_mut() {
+            *b = 0;
+        }
+
+        let data_start = RESERVED_SECTORS + self.fat_sectors;
+        match sector {
+            0 | BACKUP_BOOT_SECTOR => self.boot_sector(buf),
+            FSINFO_SECTOR => {
+                LittleEndian::write_u32(&mut buf[0..], 0x4161_5252);
+                LittleEndian::write_u32(&mut buf[484..], 0x6141_7272);
+                LittleEndian::write_u32(&mut buf[488..], 0xFFFF_FFFF); // free count: unknown
+                LittleEndian::write_u32(&mut buf[492..], 0xFFFF_FFFF); // next free: unknown
+                LittleEndian::write_u32(&mut buf[508..], 0xAA55_0000);
+            }
+            s if s >= RESERVED_SECTORS && s < data_start => {
+                let first = (s - RESERVED_SECTORS) as usize * SECTOR_SIZE / 4;
+                for (i, entry) in buf.chunks_mut(4).enumerate() {
+                    let val = self.fat.get(first + i).cloned().unwrap_or(0);
+                    LittleEndian::write_u32(entry, val);
+                }
+            }
+            s if s >= data_start => {
+                let cluster = ROOT_CLUSTER + (s - data_start) / CLUSTER_SECTORS;
+                let idx = match self.extents.binary_search_by_key(&cluster, |e| e.first) {
+                    Ok(idx) => idx,
+                    Err(0) => return Ok(()),
+                    Err(idx) => idx - 1,
+                };
+                let ext = &self.extents[idx];
+                if cluster >= ext.first + ext.count {
+                    return Ok(()); // free cluster
+                }
+                let off = (s - data_start - (ext.first - ROOT_CLUSTER) * CLUSTER_SECTORS) as usize
+                    * SECTOR_SIZE;
+                match &ext.content {
+                    Content::Dir(data) => buf.copy_from_slice(&data[off..off + SECTOR_SIZE]),
+                    Content::File(path) => {
+                        let mut f = File::open(path)?;
+                        f.seek(SeekFrom::Start(off as u64))?;
+                        // The last sector of a file is partial.
+                        let mut read = 0;
+                        while read < buf.len() {
+                            match f.read(&mut buf[read..])? {
+                                0 => break,
+                                n => read += n,
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn boot_sector(&self, buf: &mut [u8]) {
+        buf[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        buf[3..11].copy_from_slice(b"R64EMU  ");
+        LittleEndian::write_u16(&mut buf[11..], SECTOR_SIZE as u16);
+        buf[13] = CLUSTER_SECTORS as u8;
+        LittleEndian::write_u16(&mut buf[14..], RESERVED_SECTORS as u16);
+        buf[16] = 1; // number of FATs
+        buf[21] = 0xF8; // media: fixed disk
+        LittleEndian::write_u16(&mut buf[24..], 63); // sectors per track
+        LittleEndian::write_u16(&mut buf[26..], 255); // heads
+        LittleEndian::write_u32(&mut buf[32..], self.total_sectors);
+        LittleEndian::write_u32(&mut buf[36..], self.fat_sectors);
+        LittleEndian::write_u32(&mut buf[44..], ROOT_CLUSTER);
+        LittleEndian::write_u16(&mut buf[48..], FSINFO_SECTOR as u16);
+        LittleEndian::write_u16(&mut buf[50..], BACKUP_BOOT_SECTOR as u16);
+        buf[64] = 0x80; // drive number
+        buf[66] = 0x29; // extended boot signature
+        LittleEndian::write_u32(&mut buf[67..], 0x5236_3445); // volume ID
+        buf[71..82].copy_from_slice(b"R64EMU     ");
+        buf[82..90].copy_from_slice(b"FAT32   ");
+        buf[510] = 0x55;
+        buf[511] = 0xAA;
+    }
+}
+
+// Return the 8.3 name of a file, and whether it also needs a long file name.
+// Names that are not valid 8.3 names get a numeric tail based on `idx`
+// (which is unique within the directory).
+fn short_name(name: &str, idx: usize) -> ([u8; 11], bool) {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "$%'-_@~`!(){}^#&".contains(c);
+    let (base, ext) = match name.rfind('.') {
+        Some(pos) if pos > 0 => (&name[..pos], &name[pos + 1..]),
+        _ => (name, ""),
+    };
+
+    let mut short = [b' '; 11];
+    if base.len() <= 8
+        && ext.len() <= 3
+        && base
+            .chars()
+            .chain(ext.chars())
+            .all(|c| valid(c) && !c.is_ascii_lowercase())
+    {
+        short[..base.len()].copy_from_slice(base.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        return (short, false);
+    }
+
+    let filter = |s: &str| -> Vec<u8> {
+        s.chars()
+            .filter(|&c| valid(c))
+            .map(|c| c.to_ascii_uppercase() as u8)
+            .collect()
+    };
+    let tail = format!("~{}", idx);
+    let base = filter(base);
+    let ext = filter(ext);
+    let blen = base.len().min(8 - tail.len());
+    short[..blen].copy_from_slice(&base[..blen]);
+    short[blen..blen + tail.len()].copy_from_slice(tail.as_bytes());
+    let elen = ext.len().min(3);
+    short[8..8 + elen].copy_from_slice(&ext[..elen]);
+    (short, true)
+}
+
+fn dir_entry(name: &[u8; 11], attr: u8, cluster: u32, size: u32) -> [u8; 32] {
+    let mut e = [0u8; 32];
+    e[..11].copy_from_slice(name);
+    e[11] = attr;
+    LittleEndian::write_u16(&mut e[16..], 0x0021); // creation date: 1980-01-01
+    LittleEndian::write_u16(&mut e[18..], 0x0021); // access date
+    LittleEndian::write_u16(&mut e[20..], (cluster >> 16) as u16);
+    LittleEndian::write_u16(&mut e[24..], 0x0021); // modification date
+    LittleEndian::write_u16(&mut e[26..], cluster as u16);
+    LittleEndian::write_u32(&mut e[28..], size);
+    e
+}
+
+// Build the long file name entries for a file, in the order in which they
+// appear in the directory (last part first).
+fn lfn_entries(name: &str, short: &[u8; 11]) -> Vec<u8> {
+    let checksum = short
+        .iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c));
+
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    if !chars.len().is_multiple_of(13) {
+        chars.push(0);
+        chars.resize(chars.len().div_ceil(13) * 13, 0xFFFF);
+    }
+
+    let parts: Vec<_> = chars.chunks(13).collect();
+    let mut out = Vec::with_capacity(parts.len() * 32);
+    for (seq, part) in parts.iter().enumerate().rev() {
+        let mut e = [0u8; 32];
+        e[0] = (seq as u8 + 1) | if seq == parts.len() - 1 { 0x40 } else { 0 };
+        e[11] = ATTR_LFN;
+        e[13] = checksum;
+        let offsets = (1..11)
+            .step_by(2)
+            .chain((14..26).step_by(2))
+            .chain((28..32).step_by(2));
+        for (off, &c) in offsets.zip(part.iter()) {
+            LittleEndian::write_u16(&mut e[off..], c);
+        }
+        out.extend_from_slice(&e);
+    }
+    out
+}
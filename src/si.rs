@@ -1,15 +1,23 @@
 use slog;
 
-use super::mi::{IrqMask, Mi};
+use super::mi::{IrqMask, Mi, MiIrq};
 use super::r4300::R4300;
 use super::pi::Pi;
+use super::pif::PIF_RAM_SIZE;
+use super::ri::RDRAM_DMA_WRAP;
 
 use emu::bus::be::Reg32;
 use emu::bus::Device;
+use emu::dbg;
+use emu::dma::{Dma, DmaTiming, DmaXfer};
 use emu::int::Numerics;
 use emu_derive::DeviceBE;
 
+// Address of PIF RAM, which is transferred as a whole by each SI DMA.
+const PIF_RAM_ADDR: u32 = 0x1FC0_07C0;
+
 #[derive(DeviceBE)]
+#[device(subword = "shift")]
 pub struct Si {
     #[reg(bank = 0, offset = 0x00)]
     dma_address: Reg32,
@@ -29,7 +37,9 @@ pub struct Si {
     #[reg(bank = 0, offset = 0x18, rwmask = 0, wcb)]
     status: Reg32,
 
+    irq: MiIrq, // Interrupt line to MI
     logger: slog::Logger,
+    dma: Dma,
 }
 
 impl Si {
@@ -40,82 +50,75 @@ impl Si {
             start_dma_read: Reg32::default(),
             start_dma_write: Reg32::default(),
             logger,
+            irq: Mi::irq_line(IrqMask::SI),
+            dma: Dma::new("SI DMA", DmaTiming::default()),
         })
     }
 
-    pub(crate) fn set_busy(&mut self, busy: bool) {
-        let mut status = self.status.get();
-        if busy {
-            status |= 1 << 1;
-        } else {
-            status &= !(1 << 1);
-        }
-        self.status.set(status);
+    /// Report DMA activity to the debugger (once per scanline).
+    pub(crate) fn trace_dma(&mut self, tracer: &dbg::Tracer) {
+        self.dma.trace(tracer);
     }
 
     pub(crate) fn raise_irq(&mut self) {
         let status = self.status.get();
         self.status.set(status | (1 << 12));
-        Mi::get_mut().set_irq_line(IrqMask::SI, true);
+        self.irq.raise();
     }
 
     fn cb_write_status(&mut self, old: u32, new: u32) {
         // Any write to SI status clears the IRQ line
         self.status.set(old & !(1 << 12));
-        Mi::get_mut().set_irq_line(IrqMask::SI, false);
+        self.irq.ack();
 
         info!(self.logger, "write SI status reg"; "val" => new.hex());
     }
 
+    // Return the addresses of a transfer between RDRAM and PIF RAM. The
+    // RDRAM address wraps around at the end of the 24-bit address space,
+    // while the PIF address only selects the starting word within PIF RAM
+    // (which the transfer wraps around); other addresses are out of bounds.
+    fn dma_addrs(&mut self, pif: u32) -> (u32, u32) {
+        let ram = self.dma_address.get();
+        if ram >= RDRAM_DMA_WRAP || pif & 0x1FFF_FFC0 != PIF_RAM_ADDR {
+            warn!(self.logger, "SI DMA out of bounds"; "pifram" => pif.hex(), "rdram" => ram.hex());
+            self.dma.set_out_of_bounds();
+        }
+        (ram & (RDRAM_DMA_WRAP - 1), PIF_RAM_ADDR | (pif & 0x3C))
+    }
+
+    /// Report a latched out-of-bounds transfer to the debugger.
+    pub(crate) fn trace_oob(&mut self, tracer: &dbg::Tracer) -> dbg::Result<()> {
+        self.dma.trace_oob(tracer)
+    }
+
     fn cb_write_start_dma_read(&mut self, _old: u32, new: u32) {
-        let mut src = new;
-        let mut dst = self.dma_address.get();
+        let (dst, src) = self.dma_addrs(new);
         info!(self.logger, "SI DMA read"; "pifram" => src.hex(), "rdram" => dst.hex());
 
+        // The PIF executes the pending commands, so that the DMA returns
+        // their responses.
+        Pi::get_mut().run_commands();
+
         let bus = &mut R4300::get_mut().bus;
-        for _ in 0..16 {
-            let val = bus.read::<u32>(src);
-            bus.write::<u32>(dst, val);
-            src += 4;
-            dst += 4;
+        let xfer =
+            DmaXfer::linear(src, dst, PIF_RAM_SIZE).wrap(PIF_RAM_SIZE as u32, RDRAM_DMA_WRAP);
+        self.dma.xfer(bus, xfer);
+        if !self.dma.busy() {
+            self.raise_irq();
         }
-        self.raise_irq();
     }
 
     fn cb_write_start_dma_write(&mut self, _old: u32, new: u32) {
-        let mut src = self.dma_address.get();
-        let mut dst = new;
+        let (src, dst) = self.dma_addrs(new);
         info!(self.logger, "SI DMA write"; "rdram" => src.hex(), "pifram" => dst.hex());
 
         let bus = &mut R4300::get_mut().bus;
-        for _ in 0..16 {
-            let val = bus.read::<u32>(src);
-            bus.write::<u32>(dst, val);
-            src += 4;
-            dst += 4;
-        }
-        self.raise_irq();
-
-        if bus.read::<u8>(0x1fc0_07c0) & 1 != 0 {
-            self.set_busy(true);
-        }
-
-        // Dump PIF RAM
-        let dst = bus.fetch_read::<u8>(new);
-        let mut mem = dst.iter().unwrap();
-        for i in 0..8 {
-            println!(
-                "SI: {:03x}: {:02x} {:02x} {:02x} {:02x} -- {:02x} {:02x} {:02x} {:02x}",
-                (new & 0xFFF) + i * 8,
-                mem.next().unwrap(),
-                mem.next().unwrap(),
-                mem.next().unwrap(),
-                mem.next().unwrap(),
-                mem.next().unwrap(),
-                mem.next().unwrap(),
-                mem.next().unwrap(),
-                mem.next().unwrap()
-            );
+        let xfer =
+            DmaXfer::linear(src, dst, PIF_RAM_SIZE).wrap(RDRAM_DMA_WRAP, PIF_RAM_SIZE as u32);
+        self.dma.xfer(bus, xfer);
+        if !self.dma.busy() {
+            self.raise_irq();
         }
     }
 }
@@ -39,6 +39,21 @@ pub(crate) unsafe fn acc_add(
     res_hi = _mm_sub_epi16(res_hi, carry_md);
     res_hi = _mm_sub_epi16(res_hi, carry_md2);
 
+    // The accumulator silently wraps around on the hardware, but no
+    // microcode is expected to rely on it: a signed overflow of the 48-bit
+    // sum is more likely a bug in the emulation of the opcode.
+    emu::paranoid_check!(
+        "RSP",
+        {
+            let ovf = _mm_and_si128(
+                _mm_xor_si128(acc1_hi, res_hi),
+                _mm_xor_si128(acc2_hi, res_hi),
+            );
+            _mm_movemask_epi8(ovf) & 0xAAAA == 0
+        },
+        "accumulator overflow"
+    );
+
     (res_lo, res_md, res_hi)
 }
 
@@ -7,7 +7,7 @@ use emu::dbg::{Operand, Tracer};
 use mips64;
 use mips64::{DecodedInsn, REG_NAMES};
 
-const RSP_COP0_REG_NAMES: [&'static str; 32] = [
+const RSP_COP0_REG_NAMES: [&str; 32] = [
     "DMA_CACHE",
     "DMA_DRAM",
     "DMA_READ_LENGTH",
@@ -114,12 +114,19 @@ impl mips64::Cop0 for SpCop0 {
             // Breakpoint exception is used by RSP to halt itself
             Breakpoint => {
                 info!(self._logger, "RSP break");
+                // PC was moved back to BREAK: when the RSP is restarted, it
+                // resumes after it (or at the branch target, in a delay slot).
+                let pc = if ctx.delay_slot {
+                    ctx.next_pc - 4
+                } else {
+                    ctx.pc + 4
+                };
+                ctx.set_pc(pc);
                 let sp = Sp::get_mut();
                 let mut status = sp.get_status();
                 status.insert(StatusFlags::HALT | StatusFlags::BROKE);
-                match sp.set_status(status) {
-                    Some(halt) => ctx.set_halt_line(halt),
-                    None => {}
+                if let Some(halt) = sp.set_status(status) {
+                    ctx.set_halt_line(halt)
                 }
             }
             _ => unimplemented!(),
@@ -135,7 +142,7 @@ impl mips64::Cop for SpCop0 {
         panic!("unsupported COP0 reg access in RSP")
     }
 
-    fn op(&mut self, cpu: &mut mips64::CpuContext, opcode: u32, _t: &Tracer) -> dbg::Result<()> {
+    fn op(&mut self, cpu: &mut mips64::CpuContext, opcode: u32, t: &Tracer) -> dbg::Result<()> {
         let mut op = C0op {
             opcode,
             cpu,
@@ -145,7 +152,11 @@ impl mips64::Cop for SpCop0 {
             0x00 => {
                 // MFC0: read from SP HW register
                 let rd = op.rd() as u32;
-                *op.mrt64() = op.cop0.reg_bus.read::<u32>(rd * 4) as u64;
+                let val = op.cop0.reg_bus.read::<u32>(rd * 4);
+                if rd * 4 == 0x1C {
+                    t.trace_signal("RSP: SP_SEMAPHORE", val as u64);
+                }
+                *op.mrt64() = val as u64;
             }
             0x04 => {
                 // MTC0: write to SP HW register
@@ -157,14 +168,14 @@ impl mips64::Cop for SpCop0 {
                 // by peeling some layer
                 if reg == 0x10 {
                     let sp = Sp::get_mut();
-                    match sp.write_status(val) {
-                        Some(halt) => op.cpu.set_halt_line(halt),
-                        None => {}
+                    if let Some(halt) = sp.write_status(val) {
+                        op.cpu.set_halt_line(halt)
                     }
                     return Ok(());
                 }
 
                 op.cop0.reg_bus.write::<u32>(reg, val);
+                Sp::get_mut().trace_events(t)?;
             }
             _ => panic!("unimplemented RSP COP0 opcode: func={:x?}", op.func()),
         }
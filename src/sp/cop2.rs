@@ -83,7 +83,7 @@ impl SpCop2 {
     pub fn new(logger: slog::Logger) -> Result<SpCop2> {
         Ok(SpCop2 {
             ctx: Field::new("sp::cop2", SpCop2Context::default()),
-            logger: logger,
+            logger,
         })
     }
 
@@ -245,6 +245,7 @@ impl<'a> Vectorop<'a> {
     fn setvd_lane(&mut self, idx: usize, val: u16) {
         self.ctx.vregs[self.rd()].setlane(idx, val);
     }
+    #[allow(dead_code)]
     fn setvs_lane(&mut self, idx: usize, val: u16) {
         self.ctx.vregs[self.rs()].setlane(idx, val);
     }
@@ -594,6 +595,7 @@ impl SpCop2 {
                 }
                 0x33 => {
                     // VMOV
+                    #[allow(clippy::erasing_op)] // keep the table uniform
                     let se = match op.e() {
                         0..=1 => (op.e() & 0b000) | (op.rs() & 0b111),
                         2..=3 => (op.e() & 0b001) | (op.rs() & 0b110),
@@ -721,7 +723,7 @@ fn lxv<T: MemInt>(reg: &mut VectorReg, element: usize, dmem: &[u8], base: u32, o
     let mut mem: u128 = mem64.into();
     mem <<= 128 - T::SIZE * 8;
 
-    write_partial_right::<LittleEndian>(&mut reg.0, mem, element as usize * 8, T::SIZE * 8);
+    write_partial_right::<LittleEndian>(&mut reg.0, mem, element * 8, T::SIZE * 8);
 }
 
 // Plain "store vector subword into memory"
@@ -775,10 +777,10 @@ impl Cop for SpCop2 {
         let (base, vtidx, op, element, offset) = SpCop2::oploadstore(op, ctx);
         let vt = &mut self.ctx.vregs[vtidx];
         match op {
-            0x00 => lxv::<u8>(vt, element as usize, &dmem, base, offset), // LBV
-            0x01 => lxv::<u16>(vt, element as usize, &dmem, base, offset), // LSV
-            0x02 => lxv::<u32>(vt, element as usize, &dmem, base, offset), // LLV
-            0x03 => lxv::<u64>(vt, element as usize, &dmem, base, offset), // LDV
+            0x00 => lxv::<u8>(vt, element as usize, dmem, base, offset), // LBV
+            0x01 => lxv::<u16>(vt, element as usize, dmem, base, offset), // LSV
+            0x02 => lxv::<u32>(vt, element as usize, dmem, base, offset), // LLV
+            0x03 => lxv::<u64>(vt, element as usize, dmem, base, offset), // LDV
             0x04 => {
                 // LQV
                 let ea = ((base + (offset << 4)) & 0xFFF) as usize;
@@ -828,14 +830,14 @@ impl Cop for SpCop2 {
         t: &dbg::Tracer,
     ) -> dbg::Result<()> {
         let sp = Sp::get_mut();
-        let mut dmem = &mut sp.dmem;
+        let dmem = &mut sp.dmem;
         let (base, vtidx, op, element, offset) = SpCop2::oploadstore(op, ctx);
         let vt = &self.ctx.vregs[vtidx];
         match op {
-            0x00 => sxv::<u8>(&mut dmem, base, offset, vt, element as usize), // SBV
-            0x01 => sxv::<u16>(&mut dmem, base, offset, vt, element as usize), // SSV
-            0x02 => sxv::<u32>(&mut dmem, base, offset, vt, element as usize), // SLV
-            0x03 => sxv::<u64>(&mut dmem, base, offset, vt, element as usize), // SDV
+            0x00 => sxv::<u8>(dmem, base, offset, vt, element as usize), // SBV
+            0x01 => sxv::<u16>(dmem, base, offset, vt, element as usize), // SSV
+            0x02 => sxv::<u32>(dmem, base, offset, vt, element as usize), // SLV
+            0x03 => sxv::<u64>(dmem, base, offset, vt, element as usize), // SDV
             0x04 => {
                 // SQV
                 let ea = ((base + (offset << 4)) & 0xFFF) as usize;
@@ -4,15 +4,15 @@ use emu::dbg::Operand;
 use mips64::{DecodedInsn, REG_NAMES};
 
 // Decoder constants
-const VREG_NAMES: [&'static str; 32] = [
+const VREG_NAMES: [&str; 32] = [
     "v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7", "v8", "v9", "v10", "v11", "v12", "v13", "v14",
     "v15", "v16", "v17", "v18", "v19", "v20", "v21", "v22", "v23", "v24", "v25", "v26", "v27",
     "v28", "v29", "v30", "v31",
 ];
 
-const VMEM_FMT: &'static str = "{}[e{}],{}({})";
-const VREG2_FMT: &'static str = "{},{}[e{}]";
-const VREG3_FMT: &'static str = "{},{},{}[e{}]";
+const VMEM_FMT: &str = "{}[e{}],{}({})";
+const VREG2_FMT: &str = "{},{}[e{}]";
+const VREG3_FMT: &str = "{},{},{}[e{}]";
 
 pub(crate) fn decode(opcode: u32, _pc: u64) -> DecodedInsn {
     use self::Operand::*;
@@ -23,11 +23,11 @@ pub(crate) fn decode(opcode: u32, _pc: u64) -> DecodedInsn {
     let rsx = (opcode >> 11) & 0x1f;
     let rdx = (opcode >> 6) & 0x1f;
     // let grs = REG_NAMES[((opcode >> 11) & 0x1f) as usize].into();
-    let grt = REG_NAMES[((opcode >> 16) & 0x1f) as usize].into();
+    let grt = REG_NAMES[((opcode >> 16) & 0x1f) as usize];
     // let grd = REG_NAMES[((opcode >> 6) & 0x1f) as usize].into();
-    let vrs = VREG_NAMES[((opcode >> 11) & 0x1f) as usize].into();
-    let vrt = VREG_NAMES[((opcode >> 16) & 0x1f) as usize].into();
-    let vrd = VREG_NAMES[((opcode >> 6) & 0x1f) as usize].into();
+    let vrs = VREG_NAMES[((opcode >> 11) & 0x1f) as usize];
+    let vrt = VREG_NAMES[((opcode >> 16) & 0x1f) as usize];
+    let vrd = VREG_NAMES[((opcode >> 6) & 0x1f) as usize];
 
     let vreg2insn_new =
         |name| DecodedInsn::new3(name, IOReg(vrd), IReg(vrt), Imm8(e)).with_fmt(VREG2_FMT);
@@ -127,7 +127,7 @@ pub(crate) fn decode(opcode: u32, _pc: u64) -> DecodedInsn {
                     .with_fmt(VMEM_FMT)
             };
             match oploadstore {
-                0x00 => vloadinsn_new("lbv", off * 1),
+                0x00 => vloadinsn_new("lbv", off),
                 0x01 => vloadinsn_new("lsv", off * 2),
                 0x02 => vloadinsn_new("llv", off * 4),
                 0x03 => vloadinsn_new("ldv", off * 8),
@@ -148,7 +148,7 @@ pub(crate) fn decode(opcode: u32, _pc: u64) -> DecodedInsn {
                     .with_fmt(VMEM_FMT)
             };
             match oploadstore {
-                0x00 => vstoreinsn_new("sbv", off * 1),
+                0x00 => vstoreinsn_new("sbv", off),
                 0x01 => vstoreinsn_new("ssv", off * 2),
                 0x02 => vstoreinsn_new("slv", off * 4),
                 0x03 => vstoreinsn_new("sdv", off * 8),
@@ -1,6 +1,9 @@
 mod sp;
 pub use self::sp::*;
 mod decode;
+mod threaded;
+mod optrace;
+pub use self::optrace::{OpRecord, OpTrace, VectorState, MAX_OP_RECORDS};
 
 /// NOTE: please do not add tests here. To test ops, add them at the integration level
 /// (tests/spvector.rs) so that they can more easily cover all the different implementations
@@ -8,6 +11,7 @@ mod decode;
 mod accumulator;
 mod cop0;
 mod cop2;
+pub use self::cop2::SpCop2;
 mod vclip;
 mod vmul;
 mod vrcp;
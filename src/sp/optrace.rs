@@ -0,0 +1,140 @@
+//! Capture of the RSP state around the execution of a vector opcode.
+//!
+//! When a game hits a suspected bug in a vector opcode, OpTrace records the
+//! COP2 state before and after each execution of that opcode (up to
+//! MAX_OP_RECORDS) into a TOML file. tools/tracegolden then turns the trace
+//! into a golden test, so that the bug becomes a permanent regression test.
+
+use super::cop2::SpCop2;
+use super::sp::RSPCPUConfig;
+use crate::errors::*;
+use mips64::Cop;
+
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Maximum number of executions recorded into a trace.
+pub const MAX_OP_RECORDS: usize = 64;
+
+/// The state of COP2. Vector registers and accumulator slices are formatted
+/// as hex strings, with lane 0 first (as shown by the debugger).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VectorState {
+    pub vpr: Vec<String>,
+    pub vco: u16,
+    pub vcc: u16,
+    pub vce: u8,
+    pub accum: Vec<String>, // high, middle, low
+}
+
+impl VectorState {
+    pub(crate) fn capture(cpu: &mips64::Cpu<RSPCPUConfig>) -> Self {
+        let ctx = cpu.ctx();
+        let cop2 = |idx| cpu.cop2.reg(ctx, idx);
+        let hex = |idx| format!("{:032x}", cop2(idx));
+        VectorState {
+            vpr: (0..32).map(&hex).collect(),
+            vco: cop2(SpCop2::REG_VCO) as u16,
+            vcc: cop2(SpCop2::REG_VCC) as u16,
+            vce: cop2(SpCop2::REG_VCE) as u8,
+            accum: vec![
+                hex(SpCop2::REG_ACCUM_HI),
+                hex(SpCop2::REG_ACCUM_MD),
+                hex(SpCop2::REG_ACCUM_LO),
+            ],
+        }
+    }
+
+    /// Return the value of a vector register, with lane 0 in the top bits.
+    pub fn vreg(&self, idx: usize) -> u128 {
+        u128::from_str_radix(&self.vpr[idx], 16).unwrap_or(0)
+    }
+
+    /// Return a slice of the accumulator (0: high, 1: middle, 2: low), with
+    /// lane 0 in the top bits.
+    pub fn accum(&self, idx: usize) -> u128 {
+        u128::from_str_radix(&self.accum[idx], 16).unwrap_or(0)
+    }
+}
+
+/// An execution of the traced opcode.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpRecord {
+    pub pc: u32, // offset within IMEM
+    pub opcode: u32,
+    pub insn: String, // disassembly
+    pub pre: VectorState,
+    pub post: VectorState,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpTraceFile {
+    op: Vec<OpRecord>,
+}
+
+/// A trace of the executions of a vector opcode (see the module
+/// documentation), enabled with RSPCPU::set_op_trace.
+pub struct OpTrace {
+    mnemonic: String,
+    file: File,
+    records: usize,
+}
+
+impl OpTrace {
+    /// Create a trace of the vector opcode with the specified mnemonic
+    /// (eg: "vmrg"), written into the specified file.
+    pub fn create(mnemonic: &str, path: &Path) -> Result<Self> {
+        let file = File::create(path).map_err(|err| {
+            EmuError::Config(format!("cannot create {}: {}", path.display(), err))
+        })?;
+        Ok(OpTrace {
+            mnemonic: mnemonic.to_lowercase(),
+            file,
+            records: 0,
+        })
+    }
+
+    /// Load the records of a trace.
+    pub fn load(path: &Path) -> Result<Vec<OpRecord>> {
+        let invalid = |msg: String| EmuError::Config(format!("{}: {}", path.display(), msg));
+        let src = fs::read_to_string(path).map_err(|err| invalid(err.to_string()))?;
+        let trace: OpTraceFile = toml::from_str(&src).map_err(|err| invalid(err.to_string()))?;
+        Ok(trace.op)
+    }
+
+    /// Return true if all the records have been written.
+    pub fn is_full(&self) -> bool {
+        self.records >= MAX_OP_RECORDS
+    }
+
+    // Return true if the opcode must be recorded.
+    pub(crate) fn matches(&self, cpu: &mips64::Cpu<RSPCPUConfig>, opcode: u32, pc: u32) -> bool {
+        opcode >> 26 == 0x12
+            && opcode & (1 << 25) != 0
+            && cpu.cop2.decode(opcode, pc as u64).op == self.mnemonic
+    }
+
+    // Write a record, with the state before executing the opcode and the
+    // current one.
+    pub(crate) fn record(
+        &mut self,
+        cpu: &mips64::Cpu<RSPCPUConfig>,
+        pc: u32,
+        opcode: u32,
+        pre: VectorState,
+    ) -> io::Result<()> {
+        let rec = OpRecord {
+            pc,
+            opcode,
+            insn: cpu.cop2.decode(opcode, pc as u64).disasm(),
+            pre,
+            post: VectorState::capture(cpu),
+        };
+        let toml = toml::to_string(&OpTraceFile { op: vec![rec] }).map_err(io::Error::other)?;
+        writeln!(self.file, "{}", toml)?;
+        self.records += 1;
+        Ok(())
+    }
+}
@@ -1,10 +1,19 @@
-use super::super::mi::{IrqMask, Mi};
+use super::super::mi::{IrqMask, Mi, MiIrq};
 use super::super::r4300::R4300;
+use super::super::ri::RDRAM_DMA_WRAP;
 use super::cop0::SpCop0;
 use super::cop2::SpCop2;
+use super::optrace::{OpTrace, VectorState};
+use super::threaded::Threaded;
 use crate::errors::*;
+use crate::libultra::OsTask;
+use byteorder::{BigEndian, ByteOrder};
 use emu::bus::be::{Bus, Device, Mem, Reg32};
+use emu::dbg;
+use emu::dbg::{DualMemView, MemHighlight, MemoryView};
+use emu::dma::{Dma, DmaTiming, DmaXfer};
 use emu::int::Numerics;
+use emu::sync;
 use mips64;
 
 use slog;
@@ -30,6 +39,27 @@ bitflags! {
     }
 }
 
+// Aliases of the signals used by the libultra scheduler to implement task
+// yielding.
+impl StatusFlags {
+    pub(crate) const YIELD: StatusFlags = StatusFlags::SIG0;
+    pub(crate) const YIELDED: StatusFlags = StatusFlags::SIG1;
+    pub(crate) const TASKDONE: StatusFlags = StatusFlags::SIG2;
+}
+
+/// Hardware events reported to the debugger, which can break on them.
+pub(crate) const EVENT_TASK_START: &str = "RSP released from halt (task start)";
+pub(crate) const EVENT_DMA_DONE: &str = "SP DMA completed";
+
+// Description of the DMA transfer between RDRAM and SP memory in progress,
+// kept for the debugger memory view.
+#[derive(Copy, Clone, Debug)]
+struct SpDma {
+    rsp_addr: u32, // offset within DMEM/IMEM (bit 12 selects IMEM)
+    len: usize,    // number of bytes transferred on the SP side
+    to_rsp: bool,  // true if RDRAM -> RSP, false if RSP -> RDRAM
+}
+
 pub struct RSPCPUConfig;
 pub struct ArchRSP;
 
@@ -39,10 +69,12 @@ impl mips64::Arch for ArchRSP {
         if !mips64::ArchI::has_op(op) {
             return false;
         }
+        #[allow(clippy::match_like_matches_macro)]
         match op {
             "lwl" | "lwr" | "swl" | "swr" => false,
             "mult" | "multu" | "div" | "divu" => false,
             "mfhi" | "mflo" | "mthi" | "mhlo" => false,
+            "syscall" => false,
             _ => true,
         }
     }
@@ -63,13 +95,18 @@ impl mips64::Config for RSPCPUConfig {
 }
 
 #[derive(DeviceBE)]
+#[device(reset, cpu)]
 pub struct RSPCPU {
+    logger: slog::Logger,
     cpu: mips64::Cpu<RSPCPUConfig>,
+    threaded: Option<Threaded>, // None: run the interpreter
+    op_trace: Option<OpTrace>,
 }
 
 impl RSPCPU {
     fn new(logger: slog::Logger) -> Result<Box<Self>> {
         Ok(Box::new(RSPCPU {
+            logger: logger.new(o!()),
             cpu: mips64::Cpu::new(
                 "RSP",
                 logger.new(o!()),
@@ -81,9 +118,83 @@ impl RSPCPU {
                     mips64::CopNull {},
                 ),
             ),
+            threaded: None,
+            op_trace: None,
         }))
     }
 
+    fn cb_reset(&mut self, _hard: bool) {
+        self.cpu.reset();
+    }
+
+    /// Select whether the RSP runs through the threaded-code interpreter
+    /// (see sp::threaded) or the plain interpreter (default). Single-stepping
+    /// always goes through the plain interpreter.
+    pub fn set_threaded(&mut self, enabled: bool) {
+        self.threaded = if enabled {
+            Some(Threaded::default())
+        } else {
+            None
+        };
+    }
+
+    /// Record the executions of a vector opcode into the specified trace
+    /// (see sp::optrace). While tracing, the RSP runs one instruction at a
+    /// time through the interpreter.
+    pub fn set_op_trace(&mut self, trace: Option<OpTrace>) {
+        self.op_trace = trace;
+    }
+
+    /// Run the RSP until the specified clock, with the selected engine.
+    pub fn run(&mut self, until: i64, t: &dbg::Tracer) -> dbg::Result<()> {
+        if self.op_trace.is_some() {
+            return self.run_traced(until, t);
+        }
+        match &mut self.threaded {
+            Some(threaded) => threaded.run(&mut self.cpu, until, t),
+            None => self.cpu.run(until, t),
+        }
+    }
+
+    // Run the RSP one instruction at a time, recording the executions of
+    // the traced opcode. Tracing stops when the trace is full.
+    fn run_traced(&mut self, until: i64, t: &dbg::Tracer) -> dbg::Result<()> {
+        while self.cpu.ctx().clock < until {
+            if self.op_trace.as_ref().is_none_or(OpTrace::is_full) {
+                self.op_trace = None;
+                return self.run(until, t);
+            }
+            let trace = self.op_trace.as_mut().unwrap();
+
+            let pc = self.cpu.ctx().pc as u32 & 0xFFC;
+            let opcode = BigEndian::read_u32(&Sp::get().imem[pc as usize..]);
+            let pre = if trace.matches(&self.cpu, opcode, pc) {
+                Some(VectorState::capture(&self.cpu))
+            } else {
+                None
+            };
+
+            let clock = self.cpu.ctx().clock;
+            self.cpu.run(clock + 1, t)?;
+
+            if let Some(pre) = pre {
+                if let Err(err) = trace.record(&self.cpu, pc, opcode, pre) {
+                    error!(self.logger, "cannot write RSP trace"; o!("err" => err.to_string()));
+                    self.op_trace = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Notify that IMEM has been written by a DMA transfer.
+    fn imem_written(&mut self, addr: u32, len: usize) {
+        self.cpu.predecode(addr, len);
+        if let Some(threaded) = &mut self.threaded {
+            threaded.invalidate(addr, len);
+        }
+    }
+
     pub fn map_bus(&mut self) -> Result<()> {
         // Main bus of the RSPCPU is bank 0 of SP: IMEM and DMEM.
         self.bus.map_device(0x0000_0000, Sp::get(), 0)?;
@@ -110,6 +221,7 @@ impl DerefMut for RSPCPU {
 }
 
 #[derive(DeviceBE)]
+#[device(reset, subword = "shift")]
 pub struct Sp {
     #[mem(bank = 0, offset = 0x0000, size = 4096)]
     pub dmem: Mem,
@@ -144,6 +256,11 @@ pub struct Sp {
     #[reg(bank = 1, offset = 0x1C, init = 0x0, rwmask = 0x1, rcb)]
     reg_semaphore: Reg32,
 
+    last_dma: Option<SpDma>,
+    task: Option<OsTask>,
+    dma: Dma,
+    events: Vec<&'static str>, // hardware events not yet reported
+    irq: MiIrq,                // Interrupt line to MI
     logger: slog::Logger,
 }
 
@@ -154,6 +271,7 @@ impl Sp {
 
         Ok(Box::new(Sp {
             logger: logger.new(o!()),
+            irq: Mi::irq_line(IrqMask::SP),
             dmem: Mem::default(),
             imem: Mem::default(),
             reg_status: Reg32::default(),
@@ -165,21 +283,69 @@ impl Sp {
             reg_rsp_pc: Reg32::default(),
             reg_dma_full: Reg32::default(),
             reg_semaphore: Reg32::default(),
+            last_dma: None,
+            task: None,
+            dma: Dma::new("SP DMA", DmaTiming::default()),
+            events: Vec::new(),
         }))
     }
 
+    /// Report DMA activity to the debugger (once per scanline).
+    pub(crate) fn trace_dma(&mut self, tracer: &dbg::Tracer) {
+        self.dma.trace(tracer);
+    }
+
+    /// Report the pending hardware events to the debugger. Events are
+    /// latched by the register callbacks, which have no access to the
+    /// tracer; if the debugger breaks on one, the following ones are
+    /// reported at the next call.
+    pub(crate) fn trace_events(&mut self, tracer: &dbg::Tracer) -> dbg::Result<()> {
+        while !self.events.is_empty() {
+            let evt = self.events.remove(0);
+            tracer.trace_hw_event(evt)?;
+        }
+        self.dma.trace_oob(tracer)?;
+
+        // Transfers are immediate, so the last one is in progress (and
+        // highlighted in the memory view) only until its events have been
+        // reported, including while the debugger is stopped on them.
+        self.last_dma = None;
+        Ok(())
+    }
+
+    // The reset signal halts the RSP. IMEM and DMEM are preserved.
+    fn cb_reset(&mut self, _hard: bool) {
+        if let Some(halt) = self.set_status(StatusFlags::HALT) {
+            RSPCPU::get_mut().ctx_mut().set_halt_line(halt);
+        }
+    }
+
     pub(crate) fn get_status(&self) -> StatusFlags {
         StatusFlags::from_bits(self.reg_status.get()).unwrap()
     }
 
+    /// Return true if the RSP is halted.
+    pub fn halted(&self) -> bool {
+        self.get_status().contains(StatusFlags::HALT)
+    }
+
+    /// Return true if the RSP halted itself by executing BREAK.
+    pub fn broke(&self) -> bool {
+        self.get_status().contains(StatusFlags::BROKE)
+    }
+
+    /// Return the libultra task currently (or last) run by the RSP, if any.
+    pub(crate) fn task(&self) -> Option<OsTask> {
+        self.task
+    }
+
     fn cb_write_reg_status(&mut self, old: u32, new: u32) {
         self.reg_status.set(old); // restore previous value, as write bits are completely different
         let change_halt = self.write_status(new);
 
         let cpu = RSPCPU::get_mut();
-        match change_halt {
-            Some(halt) => cpu.ctx_mut().set_halt_line(halt),
-            None => {}
+        if let Some(halt) = change_halt {
+            cpu.ctx_mut().set_halt_line(halt)
         }
     }
 
@@ -200,11 +366,11 @@ impl Sp {
         }
         if new & (1 << 3) != 0 {
             info!(self.logger, "clear RSP Interrupt");
-            Mi::get_mut().set_irq_line(IrqMask::SP, false);
+            self.irq.ack();
         }
         if new & (1 << 4) != 0 {
             info!(self.logger, "force-set RSP Interrupt");
-            Mi::get_mut().set_irq_line(IrqMask::SP, true);
+            self.irq.raise();
         }
         if new & (1 << 5) != 0 {
             status.remove(StatusFlags::SINGLESTEP);
@@ -283,7 +449,14 @@ impl Sp {
         if changed.contains(StatusFlags::HALT) {
             if status.contains(StatusFlags::HALT) {
                 if status.contains(StatusFlags::INTBREAK) {
-                    Mi::get_mut().set_irq_line(IrqMask::SP, true);
+                    self.irq.raise();
+                }
+                if let Some(task) = self.task {
+                    if status.contains(StatusFlags::YIELDED) {
+                        info!(self.logger, "RSP task yielded"; "type" => task.track(), "yield_data" => task.yield_data_ptr.hex(), "size" => task.yield_data_size);
+                    } else if status.contains(StatusFlags::TASKDONE) {
+                        info!(self.logger, "RSP task done"; "type" => task.track());
+                    }
                 }
                 return Some(true);
             } else {
@@ -291,6 +464,11 @@ impl Sp {
                 // execution continues from the point where it was halted
                 // before (verified on real hardware).
                 info!(self.logger, "RSP started");
+                self.events.push(EVENT_TASK_START);
+                self.task = OsTask::decode(&self.dmem[..]);
+                if let Some(task) = self.task {
+                    info!(self.logger, "RSP task started"; "type" => task.track(), "ucode" => task.ucode.hex(), "resumed" => task.resumed());
+                }
                 return Some(false);
             }
         }
@@ -312,81 +490,95 @@ impl Sp {
         old
     }
 
-    fn dma_xfer(
-        &self,
-        mut src: u32,
-        mut dst: u32,
-        width: usize,
-        count: usize,
-        skip_src: usize,
-        skip_dst: usize,
-    ) {
-        let bus = &mut R4300::get_mut().bus;
-        for _ in 0..count {
-            let src_hwio = bus.fetch_read::<u8>(src);
-            let mut dst_hwio = bus.fetch_write::<u8>(dst);
-            if !src_hwio.is_mem() {
-                error!(self.logger, "DMA src address not in linear memory!");
-                break;
-            }
-            if !dst_hwio.is_mem() {
-                error!(self.logger, "DMA dst address not in linear memory!");
-                break;
-            }
-            let src_mem = src_hwio.mem().unwrap();
-            let dst_mem = dst_hwio.mem().unwrap();
-            dst_mem[0..width].copy_from_slice(&src_mem[0..width]);
-
-            src += (width + skip_src) as u32;
-            dst += (width + skip_dst) as u32;
-        }
-    }
-
-    fn cb_write_reg_dma_rd_len(&self, _old: u32, val: u32) {
+    // Decode a DMA transfer between RDRAM and SP memory from the value
+    // written into a length register. Rows are contiguous in SP memory, and
+    // the SP address wraps around within the selected 4 KiB memory (DMEM or
+    // IMEM), while the RDRAM address (which is the only one affected by
+    // skip) wraps around at the end of the 24-bit address space.
+    fn dma_xfer(&mut self, val: u32, to_rsp: bool) -> DmaXfer {
         let width = (val & 0xFFF) as usize + 1;
         let count = ((val >> 12) & 0xFF) as usize + 1;
         let skip = ((val >> 20) & 0xFFF) as usize;
 
         // Addresses are treated as 64-bit aligned.
-        let src = self.reg_dma_rdram_addr.get() & !0x7;
-        let dst = self.reg_dma_rsp_addr.get() & !0x7;
+        let rdram = self.reg_dma_rdram_addr.get() & !0x7;
+        let rsp = self.reg_dma_rsp_addr.get() & !0x7;
 
-        info!(self.logger, "DMA xfer: RDRAM -> RSP"; o!(
-            "rdram" => src.hex(),
-            "rsp" =>  dst.hex(),
+        info!(self.logger, "DMA xfer"; o!(
+            "dir" => if to_rsp { "RDRAM -> RSP" } else { "RSP -> RDRAM" },
+            "rdram" => rdram.hex(),
+            "rsp" =>  rsp.hex(),
             "width" => width,
             "count" => count,
             "skip" => skip,
         ));
 
-        self.dma_xfer(src, dst + 0x0400_0000, width, count, skip, 0);
+        self.last_dma = Some(SpDma {
+            rsp_addr: rsp & 0x1FFF,
+            len: (width * count).min(0x1000),
+            to_rsp,
+        });
+
+        let rsp = rsp + 0x0400_0000;
+        if to_rsp {
+            DmaXfer {
+                src: rdram,
+                dst: rsp,
+                width,
+                count,
+                src_skip: skip,
+                dst_skip: 0,
+                src_wrap: RDRAM_DMA_WRAP,
+                dst_wrap: 0x1000,
+            }
+        } else {
+            DmaXfer {
+                src: rsp,
+                dst: rdram,
+                width,
+                count,
+                src_skip: 0,
+                dst_skip: skip,
+                src_wrap: 0x1000,
+                dst_wrap: RDRAM_DMA_WRAP,
+            }
+        }
     }
 
-    fn cb_write_reg_dma_wr_len(&self, _old: u32, val: u32) {
-        let width = (val & 0xFFF) as usize + 1;
-        let count = ((val >> 12) & 0xFF) as usize + 1;
-        let skip = ((val >> 20) & 0xFFF) as usize;
+    // Execute a DMA transfer, logging it if it goes out of bounds.
+    fn dma_run(&mut self, xfer: DmaXfer) {
+        self.dma.xfer(&mut R4300::get_mut().bus, xfer);
+        self.events.push(EVENT_DMA_DONE); // transfers are immediate
+        if self.dma.out_of_bounds() {
+            warn!(self.logger, "DMA xfer out of bounds"; o!(
+                "src" => xfer.src.hex(),
+                "dst" => xfer.dst.hex(),
+                "len" => xfer.len(),
+            ));
+        }
+    }
 
-        info!(self.logger, "DMA xfer: RSP -> RDRAM"; o!(
-            "rsp" =>  self.reg_dma_rsp_addr.get().hex(),
-            "rdram" => self.reg_dma_rdram_addr.get().hex(),
-            "width" => width,
-            "count" => count,
-            "skip" => skip,
-        ));
+    fn cb_write_reg_dma_rd_len(&mut self, _old: u32, val: u32) {
+        let xfer = self.dma_xfer(val, true);
+        self.dma_run(xfer);
+
+        // Microcode is loaded into IMEM right before starting the RSP:
+        // decode it immediately, so that the RSP inner loop can directly
+        // dispatch the instructions, and drop the blocks translated from the
+        // previous contents.
+        if xfer.dst & 0x1000 != 0 {
+            RSPCPU::get_mut().imem_written(xfer.dst & 0xFFF, xfer.len().min(0x1000));
+        }
+    }
 
-        self.dma_xfer(
-            self.reg_dma_rsp_addr.get() + 0x0400_0000,
-            self.reg_dma_rdram_addr.get(),
-            width,
-            count,
-            0,
-            skip,
-        );
+    fn cb_write_reg_dma_wr_len(&mut self, _old: u32, val: u32) {
+        let xfer = self.dma_xfer(val, false);
+        self.dma_run(xfer);
     }
 
     fn cb_write_reg_rsp_pc(&self, _old: u32, val: u32) {
         info!(self.logger, "RSP set PC"; o!("pc" => val.hex()));
+        emu::paranoid_check!("SP", val & !0xFFC == 0, "RSP PC outside IMEM: {:x}", val);
         RSPCPU::get_mut().ctx_mut().set_pc(val as u64);
     }
 
@@ -394,3 +586,93 @@ impl Sp {
         RSPCPU::get().ctx().get_pc() as u32 & 0xFFF
     }
 }
+
+// The RSP is scheduled through the SP, so that the hardware events latched
+// while it was halted (eg: the task start) are reported before it executes
+// its first instruction.
+impl sync::Subsystem for Sp {
+    fn name(&self) -> &str {
+        "RSP"
+    }
+
+    fn run(&mut self, until: i64, tracer: &dbg::Tracer) -> dbg::Result<()> {
+        self.trace_events(tracer)?;
+        RSPCPU::get_mut().run(until, tracer)
+    }
+
+    fn step(&mut self, tracer: &dbg::Tracer) -> dbg::Result<()> {
+        self.trace_events(tracer)?;
+        sync::Subsystem::step(RSPCPU::get_mut().deref_mut(), tracer)
+    }
+
+    fn cycles(&self) -> i64 {
+        RSPCPU::get().ctx().clock
+    }
+
+    fn pc(&self) -> Option<u64> {
+        Some(RSPCPU::get().ctx().get_pc())
+    }
+}
+
+impl DualMemView for Sp {
+    fn name(&self) -> &str {
+        "SP"
+    }
+
+    fn mem(&self, idx: usize) -> (&str, &[u8]) {
+        match idx {
+            0 => ("DMEM", &self.dmem[..]),
+            1 => ("IMEM", &self.imem[..]),
+            _ => unreachable!(),
+        }
+    }
+
+    fn visit_highlights<F: FnMut(usize, (usize, usize), MemHighlight)>(&self, mut visit: F) {
+        if let Some(dma) = self.last_dma {
+            let kind = if dma.to_rsp {
+                MemHighlight::DmaTarget
+            } else {
+                MemHighlight::DmaSource
+            };
+
+            // The SP side of the transfer is contiguous, and wraps
+            // around at the end of the selected memory.
+            let bank = (dma.rsp_addr as usize >> 12) & 1;
+            let start = dma.rsp_addr as usize & 0xFFF;
+            let end = (start + dma.len).min(0x1000);
+            visit(bank, (start, end), kind);
+            if start + dma.len > 0x1000 {
+                visit(bank, (0, start + dma.len - 0x1000), kind);
+            }
+        }
+
+        let pc = RSPCPU::get().ctx().get_pc() as usize & 0xFFC;
+        visit(1, (pc, pc + 4), MemHighlight::Pc);
+    }
+}
+
+impl MemoryView for Sp {
+    fn name(&self) -> &str {
+        "SP"
+    }
+
+    fn mem_count(&self) -> usize {
+        2
+    }
+
+    fn mem(&self, idx: usize) -> (&str, u64, &[u8]) {
+        match idx {
+            0 => ("DMEM", 0xA400_0000, &self.dmem[..]),
+            1 => ("IMEM", 0xA400_1000, &self.imem[..]),
+            _ => unreachable!(),
+        }
+    }
+
+    fn mem_mut(&mut self, idx: usize) -> &mut [u8] {
+        match idx {
+            0 => &mut self.dmem[..],
+            1 => &mut self.imem[..],
+            _ => unreachable!(),
+        }
+    }
+}
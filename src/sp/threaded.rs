@@ -0,0 +1,220 @@
+//! Threaded-code interpreter for the RSP.
+//!
+//! IMEM is decoded one basic block at a time into a sequence of host
+//! closures, each specialized for a single instruction: register indices
+//! and immediates are decoded once, the most common ALU ops are implemented
+//! inline, COP2 vector ops are dispatched directly to the SSE implementation
+//! in SpCop2, and all other ops fall back to the interpreter functions (see
+//! mips64::Cpu::decode_op). No host code is generated: this only saves the
+//! decoding and dispatching of each instruction. Blocks end after a branch
+//! or jump and its delay slot, so that the execution of a block follows a
+//! linear sequence of instructions.
+//!
+//! Each block keeps a copy of the IMEM contents it was decoded from, which
+//! is compared before executing it: this catches writes through the bus
+//! (eg: the main CPU writing IMEM directly) without monitoring them. DMA
+//! transfers into IMEM call Threaded::invalidate instead, which also stops
+//! the block being executed if the RSP overwrote it with its own DMA.
+
+use super::sp::{RSPCPUConfig, Sp};
+
+use byteorder::{BigEndian, ByteOrder};
+use emu::bus::be::Device;
+use emu::dbg::{Result, Tracer};
+use emu::int::Numerics;
+use mips64::{Config, Cop, CpuContext};
+use std::cell::Cell;
+use std::rc::Rc;
+
+type Cpu = mips64::Cpu<RSPCPUConfig>;
+
+// A decoded instruction.
+type ThreadedOp = Box<dyn Fn(&mut Cpu, &mut CpuContext, &Tracer) -> Result<()>>;
+
+const IMEM_SIZE: usize = 0x1000;
+
+// Maximum number of instructions in a block.
+const MAX_BLOCK_INSNS: usize = 64;
+
+struct Block {
+    code: Vec<u8>, // IMEM contents the block was decoded from
+    ops: Vec<ThreadedOp>,
+    valid: Cell<bool>, // false once invalidated while executing
+}
+
+/// The RSP threaded-code interpreter (see the module documentation).
+pub(crate) struct Threaded {
+    blocks: Vec<Option<Rc<Block>>>, // indexed by IMEM word
+}
+
+impl Default for Threaded {
+    fn default() -> Self {
+        Threaded {
+            blocks: vec![None; IMEM_SIZE / 4],
+        }
+    }
+}
+
+impl Threaded {
+    /// Run the RSP until the specified clock, executing decoded blocks.
+    pub fn run(&mut self, cpu: &mut Cpu, until: i64, t: &Tracer) -> Result<()> {
+        cpu.run_compiled(until, t, |cpu, ctx, until, t| self.exec(cpu, ctx, until, t))
+    }
+
+    /// Discard the blocks that overlap the specified range of IMEM, because
+    /// it has been written.
+    pub fn invalidate(&mut self, addr: u32, len: usize) {
+        let start = addr as usize & (IMEM_SIZE - 1);
+        let end = start + len;
+        if end > IMEM_SIZE {
+            self.invalidate(0, end - IMEM_SIZE);
+        }
+        for (idx, slot) in self.blocks.iter_mut().enumerate() {
+            let overlaps = match slot {
+                Some(b) => idx * 4 < end && idx * 4 + b.code.len() > start,
+                None => false,
+            };
+            if overlaps {
+                slot.take().unwrap().valid.set(false);
+            }
+        }
+    }
+
+    // Execute the block at the current PC, decoding it if required.
+    fn exec(&mut self, cpu: &mut Cpu, ctx: &mut CpuContext, until: i64, t: &Tracer) -> Result<()> {
+        let block = self.lookup((ctx.pc as usize & (IMEM_SIZE - 1)) >> 2);
+        for op in block.ops.iter() {
+            // Same sequence of the interpreter loop (see mips64::Cpu::run).
+            ctx.tight_exit = ctx.delay_slot;
+            ctx.delay_slot = false;
+            ctx.pc = ctx.next_pc;
+            ctx.next_pc += 4;
+            op(cpu, ctx, t)?;
+            t.trace_insn("RSP", RSPCPUConfig::pc_mask(ctx.pc as u32) as u64)?;
+            if ctx.clock >= until || ctx.tight_exit || !block.valid.get() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Return the block starting at the specified IMEM word, decoding it
+    // again if IMEM was modified since it was decoded.
+    fn lookup(&mut self, idx: usize) -> Rc<Block> {
+        let imem = &Sp::get().imem[..IMEM_SIZE];
+        if let Some(block) = &self.blocks[idx] {
+            if imem[idx * 4..idx * 4 + block.code.len()] == block.code[..] {
+                return block.clone();
+            }
+        }
+        let block = Rc::new(decode(imem, idx));
+        self.blocks[idx] = Some(block.clone());
+        block
+    }
+}
+
+// Return true if the opcode transfers control (branches, jumps, break),
+// and thus ends a block.
+fn ends_block(opcode: u32) -> bool {
+    match opcode >> 26 {
+        0x00 => match opcode & 0x3F {
+            0x08 | 0x09 | 0x0D => true, // JR, JALR, BREAK
+            _ => false,
+        },
+        0x01..=0x07 | 0x14..=0x17 => true,
+        _ => false,
+    }
+}
+
+// Decode the block starting at the specified IMEM word.
+fn decode(imem: &[u8], idx: usize) -> Block {
+    let mut ops = Vec::new();
+    let mut pc = idx * 4;
+    let mut delay_slot = false;
+    while pc < imem.len() && ops.len() < MAX_BLOCK_INSNS {
+        let opcode = BigEndian::read_u32(&imem[pc..]);
+        ops.push(decode_op(opcode));
+        pc += 4;
+        if delay_slot {
+            break;
+        }
+        delay_slot = ends_block(opcode);
+    }
+
+    Block {
+        code: imem[idx * 4..pc].to_vec(),
+        ops,
+        valid: Cell::new(true),
+    }
+}
+
+// Decode an instruction. The inline ops must match the interpreter
+// exactly (including the clock accounting), as blocks can be stopped at any
+// instruction and resumed by the interpreter (eg: when single-stepping).
+fn decode_op(opcode: u32) -> ThreadedOp {
+    let rs = ((opcode >> 21) & 0x1F) as usize;
+    let rt = ((opcode >> 16) & 0x1F) as usize;
+    let rd = ((opcode >> 11) & 0x1F) as usize;
+    let sa = (opcode >> 6) & 0x1F;
+    let simm = opcode as i16 as i32;
+    let imm = (opcode & 0xFFFF) as u64;
+
+    // An ALU op, writing the value computed from the registers into dst.
+    // Writes to r0 are discarded, as done by the interpreter.
+    macro_rules! alu {
+        ($dst:expr, |$r:ident| $val:expr) => {
+            if $dst == 0 {
+                Box::new(move |_cpu: &mut Cpu, ctx: &mut CpuContext, _t: &Tracer| {
+                    ctx.clock += 1;
+                    Ok(())
+                })
+            } else {
+                Box::new(move |_cpu: &mut Cpu, ctx: &mut CpuContext, _t: &Tracer| {
+                    ctx.clock += 1;
+                    let val = {
+                        let $r = &ctx.regs;
+                        $val
+                    };
+                    ctx.regs[$dst] = val;
+                    Ok(())
+                })
+            }
+        };
+    }
+
+    match opcode >> 26 {
+        0x00 => match opcode & 0x3F {
+            0x00 => return alu!(rd, |r| ((r[rt] as u32) << sa).sx64()), // SLL
+            0x02 => return alu!(rd, |r| ((r[rt] as u32) >> sa).sx64()), // SRL
+            0x03 => return alu!(rd, |r| ((r[rt] as i32) >> sa).sx64()), // SRA
+            0x21 => return alu!(rd, |r| (r[rs] as u32).wrapping_add(r[rt] as u32).sx64()), // ADDU
+            0x23 => return alu!(rd, |r| (r[rs] as u32).wrapping_sub(r[rt] as u32).sx64()), // SUBU
+            0x24 => return alu!(rd, |r| r[rs] & r[rt]),                 // AND
+            0x25 => return alu!(rd, |r| r[rs] | r[rt]),                 // OR
+            0x26 => return alu!(rd, |r| r[rs] ^ r[rt]),                 // XOR
+            0x27 => return alu!(rd, |r| !(r[rs] | r[rt])),              // NOR
+            0x2A => return alu!(rd, |r| ((r[rs] as i32) < (r[rt] as i32)) as u64), // SLT
+            0x2B => return alu!(rd, |r| ((r[rs] as u32) < (r[rt] as u32)) as u64), // SLTU
+            _ => {}
+        },
+        0x09 => return alu!(rt, |r| (r[rs] as i32).wrapping_add(simm).sx64()), // ADDIU
+        0x0A => return alu!(rt, |r| ((r[rs] as i32) < simm) as u64),           // SLTI
+        0x0B => return alu!(rt, |r| ((r[rs] as u32) < simm as u32) as u64),    // SLTIU
+        0x0C => return alu!(rt, |r| r[rs] & imm),                              // ANDI
+        0x0D => return alu!(rt, |r| r[rs] | imm),                              // ORI
+        0x0E => return alu!(rt, |r| r[rs] ^ imm),                              // XORI
+        0x0F => return alu!(rt, |_r| (simm << 16).sx64()),                     // LUI
+
+        // COP2 vector ops
+        0x12 if opcode & (1 << 25) != 0 => {
+            return Box::new(move |cpu: &mut Cpu, ctx: &mut CpuContext, t: &Tracer| {
+                ctx.clock += 1;
+                cpu.cop2.op(ctx, opcode, t)
+            });
+        }
+        _ => {}
+    }
+
+    let func = Cpu::decode_op(opcode);
+    Box::new(move |cpu: &mut Cpu, ctx: &mut CpuContext, t: &Tracer| func(cpu, ctx, opcode, t))
+}
@@ -0,0 +1,86 @@
+//! Trigger files.
+//!
+//! A trigger file lists memory triggers (see
+//! [`emu::trigger`](../../emu/trigger/index.html)) in TOML, each one with a
+//! name and the conditions that must all be true for it to fire:
+//!
+//! ```toml
+//! [[trigger]]
+//! name = "Star collected"
+//! when = ["8033b21a.h > prev"]
+//!
+//! [[trigger]]
+//! name = "Reached the castle"
+//! when = ["8033b249.b == 6", "8032ddf8.h == 0x1a"]
+//! once = true
+//! livesplit = "split"
+//! ```
+//!
+//! Addresses are virtual addresses of the main CPU (eg: KSEG0), and only
+//! memory can be read (not hardware registers). `livesplit` is the command
+//! sent to LiveSplit when the trigger fires (see
+//! [`emu::livesplit`](../../emu/livesplit/index.html)), eg: `starttimer` or
+//! `split`.
+
+use super::errors::LoadError;
+use emu::trigger::Trigger;
+use serde_derive::Deserialize;
+
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct TriggerDef {
+    name: String,
+    when: Vec<String>,
+    #[serde(default)]
+    once: bool,
+    livesplit: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TriggerFile {
+    #[serde(default)]
+    trigger: Vec<TriggerDef>,
+}
+
+/// Parse the triggers described by a trigger file.
+pub fn parse(s: &str) -> Result<Vec<Trigger>, String> {
+    let file: TriggerFile = toml::from_str(s).map_err(|err| err.to_string())?;
+    file.trigger
+        .into_iter()
+        .map(|def| {
+            let conditions = def
+                .when
+                .iter()
+                .map(|c| c.parse())
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(|err| format!("trigger {:?}: {}", def.name, err))?;
+            if conditions.is_empty() {
+                return Err(format!("trigger {:?}: no conditions", def.name));
+            }
+            let livesplit = match &def.livesplit {
+                Some(cmd) => Some(
+                    cmd.parse()
+                        .map_err(|err| format!("trigger {:?}: {}", def.name, err))?,
+                ),
+                None => None,
+            };
+            Ok(Trigger {
+                name: def.name,
+                conditions,
+                once: def.once,
+                livesplit,
+            })
+        })
+        .collect()
+}
+
+/// Load the triggers from a file.
+pub fn load(path: &Path) -> Result<Vec<Trigger>, LoadError> {
+    let data = fs::read_to_string(path).map_err(|err| LoadError::io(path, err))?;
+    parse(&data).map_err(|msg| LoadError::InvalidTriggers {
+        path: path.display().to_string(),
+        msg,
+    })
+}
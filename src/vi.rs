@@ -1,14 +1,91 @@
 use emu::bus::be::{Device, Reg32};
+use emu::dbg::{PixelFormat, RasterPos, RawPixel, VideoView};
 use emu::gfx::*;
 use emu::int::Numerics;
+use emu::state::Field;
 use emu_derive::DeviceBE;
 
-use super::mi::{IrqMask, Mi};
+use super::dp::Dp;
+use super::mi::{IrqMask, Mi, MiIrq};
 use super::r4300::R4300;
+use super::vifilter::{self, Depth16, Filters, Sampler};
 
+use byteorder::{BigEndian, ByteOrder};
+use image::png::PNGEncoder;
+use image::ColorType;
 use slog;
 
+use std::fs::File;
+
+/// What is shown on the screen, to debug rendering issues. The debug modes
+/// show a buffer as grayscale, instead of the framebuffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DisplayMode {
+    Color,    // Framebuffer (normal output)
+    Raw,      // Framebuffer without VI filters, with 16-bit colors expanded
+    Depth,    // Z-buffer of the RDP (brighter is farther)
+    Coverage, // Coverage values stored in the framebuffer
+}
+
+impl DisplayMode {
+    pub const ALL: [DisplayMode; 4] = [
+        DisplayMode::Color,
+        DisplayMode::Raw,
+        DisplayMode::Depth,
+        DisplayMode::Coverage,
+    ];
+    const NAMES: [&'static str; 4] = ["Color", "Raw", "Depth", "Coverage"];
+}
+
+/// Geometry of the picture output by the VI, as programmed in its
+/// registers: the size of the active video area, and the part of the
+/// framebuffer that is scaled into it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Geometry {
+    pub width: usize,     // active video width, in screen pixels
+    pub lines: usize,     // active video lines in each field
+    pub interlaced: bool, // two fields per frame, with alternate lines
+    pub x_start: usize,   // framebuffer column of the first pixel (22.10)
+    pub x_step: usize,    // framebuffer columns per screen pixel (22.10)
+    pub y_start: usize,   // framebuffer line of the first line (22.10)
+    pub y_step: usize,    // framebuffer lines per screen line (22.10)
+}
+
+impl Geometry {
+    /// Size of the output picture. Interlaced frames are made of the lines
+    /// of two fields, while the lines of progressive frames are shown twice,
+    /// so that both have the same height on the screen.
+    pub fn output_size(&self) -> (usize, usize) {
+        (self.width, self.lines * 2)
+    }
+
+    /// Size of the part of the framebuffer shown in the active video area.
+    pub fn source_size(&self) -> (usize, usize) {
+        let end = |start: usize, step: usize, n: usize| {
+            if n == 0 {
+                0
+            } else {
+                ((start + (n - 1) * step) >> 10) + 1
+            }
+        };
+        (
+            end(self.x_start, self.x_step, self.width),
+            end(self.y_start, self.y_step, self.lines),
+        )
+    }
+
+    /// Return the position in the framebuffer (22.10 fixed point) shown at
+    /// the specified coordinates of the output picture.
+    pub fn source_pos(&self, x: usize, y: usize) -> (usize, usize) {
+        (
+            self.x_start + x * self.x_step,
+            self.y_start + (y / 2) * self.y_step,
+        )
+    }
+}
+
 #[derive(DeviceBE)]
+#[device(subword = "shift")]
 pub struct Vi {
     // [1:0] type[1:0] (pixel size)
     //     0: blank (no data, no sync)
@@ -29,7 +106,8 @@ pub struct Vi {
     //     3: neither (replicate pixels, no interpolate)
     // [11] reserved - diagnostics only
     // [15:12] reserved
-    #[reg(offset = 0x00, rwmask = 0xFFFF)]
+    // [16] dither_filter_enable (normally on if 16-bit)
+    #[reg(offset = 0x00, rwmask = 0x1FFFF)]
     status: Reg32,
 
     // [23:0] frame buffer origin in bytes
@@ -98,8 +176,12 @@ pub struct Vi {
     #[reg(offset = 0x34, rwmask = 0xFFFFFFF)]
     y_scale: Reg32,
 
+    irq: MiIrq, // Interrupt line to MI
     logger: slog::Logger,
     framecount: usize,
+    field: Field<u32>, // field being displayed (always 0 if not interlaced)
+    display_mode: DisplayMode,
+    filters: Filters, // filters enabled by the user (see set_filter)
 }
 
 impl Vi {
@@ -120,24 +202,52 @@ impl Vi {
             x_scale: Reg32::default(),
             y_scale: Reg32::default(),
             logger,
+            irq: Mi::irq_line(IrqMask::VI),
             framecount: 0,
+            field: Field::new("Vi::field", 0),
+            display_mode: DisplayMode::Color,
+            filters: Filters::all(),
         })
     }
 
-    pub fn set_line(&mut self, y: usize) {
-        // FIXME: handle interleaved mode (LSB is fixed within the same field)
+    /// Update the current half-line; return true if the VI interrupt was
+    /// raised. V_CURRENT is sampled once per line, so its lsb is the field
+    /// (constant in progressive modes), and the interrupt is raised when it
+    /// first matches V_INTR.
+    pub fn set_line(&mut self, y: usize) -> bool {
         // FIXME: NTSC has 525 lines, what happens to this 9-bit register when line > 512?
-        self.current_line.set(y as u32);
+        let line = (y as u32 & !1) | *self.field;
+        let changed = line != self.current_line.get();
+        self.current_line.set(line);
+
+        let raise = changed && line == self.vertical_interrupt.get();
+        if raise {
+            self.irq.raise();
+        }
+        raise
+    }
 
-        if y as u32 == self.vertical_interrupt.get() {
-            Mi::get_mut().set_irq_line(IrqMask::VI, true);
+    /// Return the geometry of the picture, as programmed in the registers.
+    pub fn geometry(&self) -> Geometry {
+        let (h_start, h_end) = hi_lo(self.horizontal_video.get(), 0x3FF);
+        let (v_start, v_end) = hi_lo(self.vertical_video.get(), 0x3FF);
+        let (x_offset, x_scale) = hi_lo(self.x_scale.get(), 0xFFF);
+        let (y_offset, y_scale) = hi_lo(self.y_scale.get(), 0xFFF);
+        Geometry {
+            width: h_end.saturating_sub(h_start) as usize,
+            lines: (v_end.saturating_sub(v_start) / 2) as usize,
+            interlaced: self.status.get() & (1 << 6) != 0,
+            x_start: x_offset as usize,
+            x_step: x_scale as usize,
+            y_start: y_offset as usize,
+            y_step: y_scale as usize,
         }
     }
 
     fn cb_write_current_line(&mut self, _old: u32, _new: u32) {
         info!(self.logger, "ack VI interrupt");
         // Writing the current line register acknowledge the interrupt
-        Mi::get_mut().set_irq_line(IrqMask::VI, false);
+        self.irq.ack();
     }
 
     fn cb_write_vertical_interrupt(&self, _old: u32, new: u32) {
@@ -148,76 +258,376 @@ impl Vi {
 
     pub fn end_frame(&mut self, screen: &mut GfxBufferMutLE<Rgb888>) {
         self.framecount += 1;
+        let field = *self.field;
+        let geom = self.geometry();
+        *self.field = if geom.interlaced { field ^ 1 } else { 0 };
 
         let bpp = self.status.get() & 3;
 
         // display disable -> clear screen
         if bpp == 0 || bpp == 1 {
-            let black = Color::<Rgb888>::new_clamped(0, 0, 0, 0);
-            for y in 0..480 {
-                let mut line = screen.line(y);
-                for x in 0..640 {
-                    line.set(x, black);
-                }
-            }
+            clear(screen);
             return;
         }
 
+        let (filters, depth16) = match self.display_mode {
+            DisplayMode::Color => (self.active_filters(), Depth16::Hardware),
+            DisplayMode::Raw => (Filters::empty(), Depth16::Expanded),
+            _ => {
+                self.draw_debug(screen, bpp);
+                return;
+            }
+        };
+
         info!(self.logger, "draw frame"; o!("origin" => self.origin.get().hex()));
+        let width = self.width.get() as usize;
+        let (src_w, src_h) = geom.source_size();
+        let (out_w, out_h) = geom.output_size();
+        if width == 0 || src_w == 0 || src_h == 0 {
+            error!(
+                self.logger, "unsupported screen size";
+                o!("width" => width, "geometry" => ?geom)
+            );
+            clear(screen);
+            return;
+        }
+
+        // Fetch the framebuffer lines shown in the active video area (plus
+        // one, for the interpolation), and scale them to the screen. In
+        // interlaced modes, only the lines of the current field are drawn,
+        // and the other ones keep the previous field.
         let memio = R4300::get().bus.fetch_read::<u8>(self.origin.get());
-        let src = memio.mem().unwrap();
-
-        match self.width.get() {
-            640 => {
-                let src = GfxBufferLE::<Rgb888>::new(src, 640, 480, 640 * 4).unwrap();
-                for y in 0..480 {
-                    let mut dst = screen.line(y);
-                    let src = src.line(y);
-                    for x in 0..640 {
-                        dst.set(x, src.get(x));
-                    }
-                }
+        let src = memio.mem().unwrap_or(&[]);
+        let height = src_h + 1;
+        let mut pixels = vifilter::fetch(src, width, height, bpp, depth16);
+        vifilter::restore(&mut pixels, width, height, filters);
+        let mut sampler = Sampler::new(&pixels, width, height, filters, self.framecount as u32);
+        let (sw, sh) = (screen.width(), screen.height());
+        for y in 0..sh {
+            let oy = y * out_h / sh;
+            if geom.interlaced && oy as u32 & 1 != field {
+                continue;
+            }
+            let mut dst = screen.line(y);
+            for x in 0..sw {
+                let (fx, fy) = geom.source_pos(x * out_w / sw, oy);
+                dst.set(x, sampler.get(fx, fy));
             }
+        }
+    }
+}
 
-            320 => {
-                match bpp {
-                    // 32-bit
-                    3 => {
-                        let src = GfxBufferLE::<Rgb888>::new(src, 320, 240, 320 * 4).unwrap();
-                        for y in 0..240 {
-                            let (mut dst1, mut dst2) = screen.lines(y * 2, y * 2 + 1);
-                            let src = src.line(y);
-                            for x in 0..320 {
-                                let px = src.get(x);
-                                dst1.set(x * 2, px);
-                                dst1.set(x * 2 + 1, px);
-                                dst2.set(x * 2, px);
-                                dst2.set(x * 2 + 1, px);
-                            }
-                        }
-                    }
-                    // 16-bit
-                    2 => {
-                        let src = GfxBufferBE::<Xbgr1555>::new(src, 320, 240, 320 * 2).unwrap();
-                        for y in 0..240 {
-                            let (mut dst1, mut dst2) = screen.lines(y * 2, y * 2 + 1);
-                            let src = src.line(y);
-                            for x in 0..320 {
-                                let px = src.get(x).cconv();
-                                dst1.set(x * 2, px);
-                                dst1.set(x * 2 + 1, px);
-                                dst2.set(x * 2, px);
-                                dst2.set(x * 2 + 1, px);
-                            }
-                        }
+// Clear the screen to black.
+fn clear(screen: &mut GfxBufferMutLE<Rgb888>) {
+    let black = Color::<Rgb888>::new_clamped(0, 0, 0, 0);
+    let (w, h) = (screen.width(), screen.height());
+    for y in 0..h {
+        let mut line = screen.line(y);
+        for x in 0..w {
+            line.set(x, black);
+        }
+    }
+}
+
+impl Vi {
+    /// Change what is shown on the screen (see DisplayMode).
+    pub fn set_display_mode(&mut self, mode: DisplayMode) {
+        info!(self.logger, "display mode"; "mode" => ?mode);
+        self.display_mode = mode;
+    }
+
+    /// Enable or disable a filter of the VI. Filters are applied only when
+    /// enabled both by the user and by VI_STATUS: disabling them shows the
+    /// raw output of the RDP.
+    pub fn set_filter(&mut self, filter: Filters, enabled: bool) {
+        info!(self.logger, "filter"; "filter" => ?filter, "enabled" => enabled);
+        self.filters.set(filter, enabled);
+    }
+
+    // Return the filters to apply to the current frame.
+    fn active_filters(&self) -> Filters {
+        Filters::from_status(self.status.get()) & self.filters
+    }
+
+    // Return the framebuffer pixel shown at the specified coordinates of a
+    // screen of the specified size.
+    fn screen_to_fb(&self, x: usize, y: usize, (sw, sh): (usize, usize)) -> (usize, usize) {
+        let geom = self.geometry();
+        let (out_w, out_h) = geom.output_size();
+        let (fx, fy) = geom.source_pos(x * out_w / sw, y * out_h / sh);
+        (fx >> 10, fy >> 10)
+    }
+
+    /// Return the RDRAM address of the framebuffer pixel shown at the
+    /// specified screen coordinates (on a 640x480 screen), or None if the
+    /// display is disabled.
+    pub fn pixel_addr(&self, x: usize, y: usize) -> Option<u32> {
+        let pxsize = match self.status.get() & 3 {
+            2 => 2,
+            3 => 4,
+            _ => return None,
+        };
+        let width = self.width.get() as usize;
+        let (fbx, fby) = self.screen_to_fb(x, y, (640, 480));
+        let off = (fby * width + fbx) * pxsize;
+        Some(self.origin.get() + off as u32)
+    }
+
+    /// Return the framebuffer pixel shown at the specified screen
+    /// coordinates, with its raw value as stored in RDRAM, or None if the
+    /// display is disabled.
+    pub fn framebuffer_pixel(&self, x: usize, y: usize) -> Option<RawPixel> {
+        let addr = self.pixel_addr(x, y)?;
+        let (pxsize, format) = match self.status.get() & 3 {
+            2 => (2, PixelFormat::Rgba5551),
+            _ => (4, PixelFormat::Rgba8888),
+        };
+        let memio = R4300::get().bus.fetch_read::<u8>(addr);
+        let mem = memio.mem().and_then(|m| m.get(..pxsize))?;
+        let value = match format {
+            PixelFormat::Rgba5551 => BigEndian::read_u16(mem) as u32,
+            PixelFormat::Rgba8888 => BigEndian::read_u32(mem),
+        };
+        let (fbx, fby) = self.screen_to_fb(x, y, (640, 480));
+        Some(RawPixel {
+            x: fbx,
+            y: fby,
+            addr: addr as u64,
+            value,
+            format,
+        })
+    }
+
+    // Draw a debug view of the current frame as grayscale (see DisplayMode),
+    // scaled to the screen. The Z-buffer has the same size as the
+    // framebuffer, with 16-bit pixels.
+    fn draw_debug(&self, screen: &mut GfxBufferMutLE<Rgb888>, bpp: u32) {
+        let width = self.width.get() as usize;
+        let size = (screen.width(), screen.height());
+        let (addr, pxsize) = match self.display_mode {
+            DisplayMode::Depth => (Dp::get().z_image(), 2),
+            _ => (Some(self.origin.get()), if bpp == 3 { 4 } else { 2 }),
+        };
+        let memio = addr.map(|addr| R4300::get().bus.fetch_read::<u8>(addr));
+        let src = memio.as_ref().and_then(|m| m.mem()).unwrap_or(&[]);
+        let byte = |off: usize| src.get(off).cloned().unwrap_or(0);
+
+        for y in 0..size.1 {
+            let mut dst = screen.line(y);
+            for x in 0..size.0 {
+                let (fbx, fby) = self.screen_to_fb(x, y, size);
+                let off = (fby * width + fbx) * pxsize;
+                let val = match (self.display_mode, pxsize) {
+                    // Z-buffer: the upper bits of the (compressed) depth
+                    (DisplayMode::Depth, _) => byte(off),
+                    // 16-bit framebuffer: only the alpha bit is stored in
+                    // RDRAM (the other coverage bits are hidden).
+                    (_, 2) => (byte(off + 1) & 1) * 0xFF,
+                    // 32-bit framebuffer: coverage is in alpha[7:5]
+                    _ => ((byte(off + 3) >> 5) as usize * 0xFF / 7) as u8,
+                };
+                let c = val as i32;
+                dst.set(x, Color::<Rgb888>::new_clamped(c, c, c, 0));
+            }
+        }
+    }
+}
+
+// Split a register containing two 10-bit (or 12-bit) fields at [25:16] and [9:0].
+fn hi_lo(val: u32, mask: u32) -> (u32, u32) {
+    ((val >> 16) & mask, val & mask)
+}
+
+// Format a 2.10 fixed point value.
+fn fix2_10(val: u32) -> String {
+    format!("{:.4}", val as f32 / 1024.0)
+}
+
+impl VideoView for Vi {
+    fn name(&self) -> &str {
+        "VI"
+    }
+
+    fn visit_regs<F: FnMut(&str, u64, &[(&str, String)])>(&self, mut visit: F) {
+        let status = self.status.get();
+        visit(
+            "VI_STATUS",
+            status as u64,
+            &[
+                (
+                    "type",
+                    match status & 3 {
+                        0 => "blank".into(),
+                        1 => "reserved".into(),
+                        2 => "16-bit (5/5/5/3)".into(),
+                        _ => "32-bit (8/8/8/8)".into(),
+                    },
+                ),
+                ("gamma dither", format!("{}", status & (1 << 2) != 0)),
+                ("gamma", format!("{}", status & (1 << 3) != 0)),
+                ("divot", format!("{}", status & (1 << 4) != 0)),
+                ("serrate", format!("{}", status & (1 << 6) != 0)),
+                ("dither filter", format!("{}", status & (1 << 16) != 0)),
+                (
+                    "anti-alias",
+                    match (status >> 8) & 3 {
+                        0 => "aa+resamp (always fetch)".into(),
+                        1 => "aa+resamp (fetch if needed)".into(),
+                        2 => "resamp only".into(),
+                        _ => "none (replicate)".into(),
+                    },
+                ),
+            ],
+        );
+
+        let origin = self.origin.get();
+        visit("VI_ORIGIN", origin as u64, &[("address", format!("{:08x}", origin))]);
+        let width = self.width.get();
+        visit("VI_WIDTH", width as u64, &[("pixels", format!("{}", width))]);
+        let intr = self.vertical_interrupt.get();
+        visit("VI_V_INTR", intr as u64, &[("half-line", format!("{}", intr))]);
+        let cur = self.current_line.get();
+        visit("VI_V_CURRENT", cur as u64, &[("half-line", format!("{}", cur))]);
+
+        let timing = self.timing.get();
+        visit(
+            "VI_BURST",
+            timing as u64,
+            &[
+                ("hsync width", format!("{}", timing & 0xFF)),
+                ("burst width", format!("{}", (timing >> 8) & 0xFF)),
+                ("vsync width", format!("{}", (timing >> 16) & 0xF)),
+                ("burst start", format!("{}", (timing >> 20) & 0x3FF)),
+            ],
+        );
+
+        let vsync = self.vertical_sync.get();
+        visit("VI_V_SYNC", vsync as u64, &[("half-lines", format!("{}", vsync & 0x3FF))]);
+        let hsync = self.horizontal_sync.get();
+        visit(
+            "VI_H_SYNC",
+            hsync as u64,
+            &[
+                ("line duration", format!("{} (1/4 px)", hsync & 0xFFF)),
+                ("leap pattern", format!("{:05b}", (hsync >> 16) & 0x1F)),
+            ],
+        );
+        let (leap_a, leap_b) = hi_lo(self.horizontal_sync_leap.get(), 0xFFF);
+        visit(
+            "VI_LEAP",
+            self.horizontal_sync_leap.get() as u64,
+            &[("leap a", format!("{}", leap_a)), ("leap b", format!("{}", leap_b))],
+        );
+
+        for (name, reg) in [
+            ("VI_H_START", &self.horizontal_video),
+            ("VI_V_START", &self.vertical_video),
+            ("VI_V_BURST", &self.vertical_burst),
+        ]
+        .iter()
+        {
+            let (start, end) = hi_lo(reg.get(), 0x3FF);
+            visit(
+                name,
+                reg.get() as u64,
+                &[("start", format!("{}", start)), ("end", format!("{}", end))],
+            );
+        }
+
+        for (name, reg) in [("VI_X_SCALE", &self.x_scale), ("VI_Y_SCALE", &self.y_scale)].iter() {
+            let (offset, scale) = hi_lo(reg.get(), 0xFFF);
+            visit(
+                name,
+                reg.get() as u64,
+                &[("scale", fix2_10(scale)), ("offset", fix2_10(offset))],
+            );
+        }
+    }
+
+    fn raster(&self) -> RasterPos {
+        let total = self.vertical_sync.get() as usize & 0x3FF;
+        RasterPos {
+            line: self.current_line.get() as usize,
+            total_lines: if total != 0 { total } else { 525 },
+            interrupt_line: Some(self.vertical_interrupt.get() as usize),
+        }
+    }
+
+    fn display_modes(&self) -> &[&'static str] {
+        &DisplayMode::NAMES
+    }
+
+    fn display_mode(&self) -> usize {
+        DisplayMode::ALL
+            .iter()
+            .position(|&m| m == self.display_mode)
+            .unwrap()
+    }
+
+    fn set_display_mode(&mut self, mode: usize) {
+        Vi::set_display_mode(self, DisplayMode::ALL[mode]);
+    }
+
+    fn filters(&self) -> Vec<(&'static str, bool)> {
+        Filters::ALL
+            .iter()
+            .map(|&(f, name)| (name, self.filters.contains(f)))
+            .collect()
+    }
+
+    fn set_filter(&mut self, idx: usize, enabled: bool) {
+        Vi::set_filter(self, Filters::ALL[idx].0, enabled);
+    }
+
+    fn dump_framebuffer(&self) -> Result<String, String> {
+        let bpp = self.status.get() & 3;
+        if bpp != 2 && bpp != 3 {
+            return Err("display is disabled".into());
+        }
+
+        // Compute the visible size from the active video area and the
+        // vertical scale, like the hardware does.
+        let width = self.width.get() as usize;
+        let height = self.geometry().source_size().1;
+        if width == 0 || height == 0 {
+            return Err(format!("invalid framebuffer size {}x{}", width, height));
+        }
+
+        let memio = R4300::get().bus.fetch_read::<u8>(self.origin.get());
+        let src = memio
+            .mem()
+            .ok_or_else(|| format!("origin {:08x} is not in RAM", self.origin.get()))?;
+
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        match bpp {
+            3 => {
+                let src = GfxBufferLE::<Rgb888>::new(src, width, height, width * 4)?;
+                for y in 0..height {
+                    let line = src.line(y);
+                    for x in 0..width {
+                        let (r, g, b, _) = line.get(x).components();
+                        rgba.extend_from_slice(&[r as u8, g as u8, b as u8, 0xFF]);
                     }
-                    _ => unimplemented!(),
                 }
             }
-
             _ => {
-                error!(self.logger, "unsupported screen width"; o!("width" => self.width.get()));
+                let src = GfxBufferBE::<Xbgr1555>::new(src, width, height, width * 2)?;
+                for y in 0..height {
+                    let line = src.line(y);
+                    for x in 0..width {
+                        let px: Color<Rgb888> = line.get(x).cconv();
+                        let (r, g, b, _) = px.components();
+                        rgba.extend_from_slice(&[r as u8, g as u8, b as u8, 0xFF]);
+                    }
+                }
             }
         }
+
+        let fname = format!("vi-{:06}.png", self.framecount);
+        let f = File::create(&fname).map_err(|e| e.to_string())?;
+        PNGEncoder::new(f)
+            .encode(&rgba, width as u32, height as u32, ColorType::RGBA(8))
+            .map_err(|e| e.to_string())?;
+        Ok(fname)
     }
 }
@@ -0,0 +1,385 @@
+//! Post-processing filters applied by the VI to the framebuffer while
+//! scanning it out.
+//!
+//! The VI doesn't simply display the framebuffer: depending on VI_STATUS, it
+//! reconstructs the edges of antialiased primitives from the coverage of
+//! the pixels, removes the dithering noise of 16-bit framebuffers and the
+//! "divots" (single pixel notches) left along the edges, resamples
+//! the framebuffer to the output resolution with bilinear interpolation, and
+//! applies a gamma boost. Each filter can also be disabled by the user (see
+//! Vi::set_filter), to look at the raw output of the RDP.
+
+use emu::gfx::{BufferLineSetter, Color, GfxBufferMutLE, Rgb888};
+
+bitflags! {
+    pub struct Filters: u32 {
+        const AA =           0b00001; // edge reconstruction of partially covered pixels
+        const RESAMPLE =     0b00010; // bilinear interpolation when upscaling
+        const DITHER =       0b00100; // dither filter (restore the dithered bits)
+        const GAMMA =        0b01000; // gamma boost
+        const GAMMA_DITHER = 0b10000; // random noise added before the gamma boost
+        const DIVOT =       0b100000; // removal of notches along antialiased edges
+    }
+}
+
+impl Filters {
+    /// All the filters, in the order they are applied, with their names.
+    pub const ALL: [(Filters, &'static str); 6] = [
+        (Filters::AA, "Anti-alias"),
+        (Filters::DITHER, "Dither filter"),
+        (Filters::DIVOT, "Divot"),
+        (Filters::RESAMPLE, "Resample"),
+        (Filters::GAMMA, "Gamma"),
+        (Filters::GAMMA_DITHER, "Gamma dither"),
+    ];
+
+    /// Return the filters enabled by the specified value of VI_STATUS.
+    pub fn from_status(status: u32) -> Filters {
+        let mut f = Filters::empty();
+        f.set(Filters::GAMMA_DITHER, status & (1 << 2) != 0);
+        f.set(Filters::GAMMA, status & (1 << 3) != 0);
+        f.set(Filters::DIVOT, status & (1 << 4) != 0);
+        f.set(Filters::DITHER, status & (1 << 16) != 0);
+        match (status >> 8) & 3 {
+            0 | 1 => f |= Filters::AA | Filters::RESAMPLE,
+            2 => f |= Filters::RESAMPLE,
+            _ => {}
+        }
+        f
+    }
+}
+
+/// A pixel of the framebuffer as fetched by the VI: 8-bit color components,
+/// and 3-bit coverage (7 is fully covered).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pixel {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub cvg: u8,
+}
+
+impl Pixel {
+    fn components(self) -> [u8; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    fn with_components(self, c: [u8; 3]) -> Pixel {
+        Pixel {
+            r: c[0],
+            g: c[1],
+            b: c[2],
+            cvg: self.cvg,
+        }
+    }
+}
+
+/// How the pixels of 16-bit (RGBA5551) framebuffers are converted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Depth16 {
+    /// Like the VI: the 5-bit components are padded with zeros (the dither
+    /// filter restores the missing bits), and the alpha bit is the most
+    /// significant bit of the coverage. The other two bits of the coverage
+    /// are stored in the hidden bits of RDRAM, which are not emulated, so
+    /// they are assumed set.
+    Hardware,
+    /// The 5-bit components are expanded to the full 8-bit range by
+    /// replicating their top bits, and all the pixels are fully covered:
+    /// the framebuffer as a plain image.
+    Expanded,
+}
+
+/// Decode a framebuffer (as stored in RDRAM) of the specified size; bpp is
+/// the pixel size in VI_STATUS (2: 16-bit, 3: 32-bit). Pixels beyond the
+/// end of src are black.
+pub fn fetch(src: &[u8], width: usize, height: usize, bpp: u32, depth16: Depth16) -> Vec<Pixel> {
+    let byte = |off: usize| src.get(off).cloned().unwrap_or(0);
+    let mut dst = Vec::with_capacity(width * height);
+    for idx in 0..width * height {
+        dst.push(match bpp {
+            3 => Pixel {
+                r: byte(idx * 4),
+                g: byte(idx * 4 + 1),
+                b: byte(idx * 4 + 2),
+                cvg: byte(idx * 4 + 3) >> 5,
+            },
+            _ => {
+                let px = (byte(idx * 2) as u16) << 8 | byte(idx * 2 + 1) as u16;
+                let c5 = |shift: u16| {
+                    let c = ((px >> shift) & 0x1F) as u8;
+                    match depth16 {
+                        Depth16::Hardware => c << 3,
+                        Depth16::Expanded => c << 3 | c >> 2,
+                    }
+                };
+                Pixel {
+                    r: c5(11),
+                    g: c5(6),
+                    b: c5(1),
+                    cvg: match depth16 {
+                        Depth16::Hardware => (px as u8 & 1) << 2 | 3,
+                        Depth16::Expanded => 7,
+                    },
+                }
+            }
+        });
+    }
+    dst
+}
+
+/// Apply the filters that work on the framebuffer at its own resolution
+/// (anti-alias, dither filter and divot) to a fetched framebuffer.
+pub fn restore(pixels: &mut [Pixel], width: usize, height: usize, filters: Filters) {
+    if !filters.intersects(Filters::AA | Filters::DITHER | Filters::DIVOT) || width == 0 {
+        return;
+    }
+    let src = pixels.to_vec();
+    let at = |x: isize, y: isize| -> Option<Pixel> {
+        if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+            return None;
+        }
+        Some(src[y as usize * width + x as usize])
+    };
+
+    // The neighbors used by the VI for anti-aliasing: two pixels on the
+    // lines above and below, and the pixels two steps away on the same line
+    // (the closest ones might belong to the same edge). The dither filter
+    // uses all the adjacent pixels.
+    const AA_NEAR: [(isize, isize); 6] = [(-1, -1), (1, -1), (-2, 0), (2, 0), (-1, 1), (1, 1)];
+    const DITHER_NEAR: [(isize, isize); 8] = [
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (-1, 0),
+        (1, 0),
+        (-1, 1),
+        (0, 1),
+        (1, 1),
+    ];
+
+    let mut near = [Pixel::default(); 8];
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let center = src[y as usize * width + x as usize];
+            let (offsets, filter) = if center.cvg < 7 {
+                (&AA_NEAR[..], Filters::AA)
+            } else {
+                (&DITHER_NEAR[..], Filters::DITHER)
+            };
+            if !filters.contains(filter) {
+                continue;
+            }
+            let mut n = 0;
+            for (dx, dy) in offsets {
+                match at(x + dx, y + dy) {
+                    // Anti-aliasing uses only fully covered pixels
+                    Some(p) if filter == Filters::DITHER || p.cvg == 7 => {
+                        near[n] = p;
+                        n += 1;
+                    }
+                    _ => {}
+                }
+            }
+            pixels[y as usize * width + x as usize] = if filter == Filters::AA {
+                antialias(center, &near[..n])
+            } else {
+                dedither(center, &near[..n])
+            };
+        }
+    }
+
+    if filters.contains(Filters::DIVOT) {
+        divot(pixels, &src, width);
+    }
+}
+
+// Remove the divots: along an antialiased edge, a pixel darker or brighter
+// than both its neighbors on the same line is replaced by the median of the
+// three. Only pixels next to (or with) partial coverage are affected, using
+// the coverage as fetched from the framebuffer.
+fn divot(pixels: &mut [Pixel], fetched: &[Pixel], width: usize) {
+    let line = pixels.to_vec();
+    for (y, row) in line.chunks(width).enumerate() {
+        for x in 1..width.saturating_sub(1) {
+            let idx = y * width + x;
+            if fetched[idx - 1..=idx + 1].iter().all(|p| p.cvg == 7) {
+                continue;
+            }
+            let (l, c, r) = (
+                row[x - 1].components(),
+                row[x].components(),
+                row[x + 1].components(),
+            );
+            let mut out = c;
+            for i in 0..3 {
+                out[i] = l[i].max(c[i]).min(r[i]).max(l[i].min(c[i]));
+            }
+            pixels[idx] = row[x].with_components(out);
+        }
+    }
+}
+
+// Reconstruct the color of a partially covered pixel, blending it with the
+// background, estimated from the fully covered neighbors: the second
+// brightest and the second darkest are the most likely background and
+// foreground colors, which discards outliers.
+fn antialias(center: Pixel, near: &[Pixel]) -> Pixel {
+    if near.len() < 2 {
+        return center;
+    }
+    let c = center.components();
+    let mut out = [0u8; 3];
+    let mut vals = [0i32; 7];
+    for i in 0..3 {
+        let vals = &mut vals[..near.len() + 1];
+        for (v, p) in vals.iter_mut().zip(near) {
+            *v = p.components()[i] as i32;
+        }
+        vals[near.len()] = c[i] as i32;
+        vals.sort_unstable();
+        let (penmin, penmax) = (vals[1], vals[vals.len() - 2]);
+        let back = penmax + penmin - 2 * c[i] as i32;
+        let v = c[i] as i32 + ((back * (7 - center.cvg as i32) + 4) >> 3);
+        out[i] = v.clamp(0, 255) as u8;
+    }
+    center.with_components(out)
+}
+
+// Remove the dithering noise from a fully covered pixel: each neighbor
+// whose color (at 5-bit precision) is brighter or darker than the center
+// moves it by one step at 8-bit precision, restoring the bits lost when the
+// RDP dithered the color to 16-bit.
+fn dedither(center: Pixel, near: &[Pixel]) -> Pixel {
+    let c = center.components();
+    let mut out = c;
+    for i in 0..3 {
+        let mut v = c[i] as i32;
+        for p in near {
+            let n = p.components()[i];
+            if n >> 3 > c[i] >> 3 {
+                v += 1;
+            } else if n >> 3 < c[i] >> 3 {
+                v -= 1;
+            }
+        }
+        out[i] = v.clamp(0, 255) as u8;
+    }
+    center.with_components(out)
+}
+
+/// Scale a (restored) framebuffer to the screen, interpolating the pixels
+/// if the resample filter is enabled (or else replicating them), and apply
+/// the gamma boost. seed initializes the noise of the gamma dither.
+pub fn scale(
+    pixels: &[Pixel],
+    width: usize,
+    height: usize,
+    filters: Filters,
+    seed: u32,
+    screen: &mut GfxBufferMutLE<Rgb888>,
+) {
+    let (sw, sh) = (screen.width(), screen.height());
+    if width == 0 || height == 0 {
+        return;
+    }
+    let mut sampler = Sampler::new(pixels, width, height, filters, seed);
+    for y in 0..sh {
+        let mut dst = screen.line(y);
+        // Source coordinates in 22.10 fixed point
+        let fy = y * height * 1024 / sh;
+        for x in 0..sw {
+            let fx = x * width * 1024 / sw;
+            dst.set(x, sampler.get(fx, fy));
+        }
+    }
+}
+
+/// Sample a (restored) framebuffer at arbitrary positions, like the VI
+/// while scaling it to the active video area: the pixels are interpolated if
+/// the resample filter is enabled (or else replicated), and the gamma boost
+/// is applied.
+pub struct Sampler<'a> {
+    pixels: &'a [Pixel],
+    width: usize,
+    height: usize,
+    filters: Filters,
+    rng: Noise,
+}
+
+impl<'a> Sampler<'a> {
+    /// Create a sampler for a framebuffer of the specified size. seed
+    /// initializes the noise of the gamma dither.
+    pub fn new(
+        pixels: &'a [Pixel],
+        width: usize,
+        height: usize,
+        filters: Filters,
+        seed: u32,
+    ) -> Sampler<'a> {
+        Sampler {
+            pixels,
+            width,
+            height,
+            filters,
+            rng: Noise(seed | 1),
+        }
+    }
+
+    fn at(&self, x: usize, y: usize) -> [u8; 3] {
+        self.pixels[y.min(self.height - 1) * self.width + x.min(self.width - 1)].components()
+    }
+
+    /// Return the color at the specified position of the framebuffer, in
+    /// 22.10 fixed point. Positions beyond the edges repeat the last pixel.
+    pub fn get(&mut self, fx: usize, fy: usize) -> Color<Rgb888> {
+        if self.width == 0 || self.height == 0 {
+            return Color::<Rgb888>::new_clamped(0, 0, 0, 0);
+        }
+        let (x0, y0) = (fx >> 10, fy >> 10);
+        let mut c = self.at(x0, y0);
+        if self.filters.contains(Filters::RESAMPLE) {
+            let (wx, wy) = ((fx & 1023) as u32, (fy & 1023) as u32);
+            let (c01, c10, c11) = (
+                self.at(x0 + 1, y0),
+                self.at(x0, y0 + 1),
+                self.at(x0 + 1, y0 + 1),
+            );
+            for i in 0..3 {
+                let top = c[i] as u32 * (1024 - wx) + c01[i] as u32 * wx;
+                let bot = c10[i] as u32 * (1024 - wx) + c11[i] as u32 * wx;
+                c[i] = ((top * (1024 - wy) + bot * wy + (1 << 19)) >> 20) as u8;
+            }
+        }
+        if self.filters.contains(Filters::GAMMA) {
+            for comp in c.iter_mut() {
+                let noise = if self.filters.contains(Filters::GAMMA_DITHER) {
+                    self.rng.next() & 0x3F
+                } else {
+                    0
+                };
+                *comp = gamma(*comp, noise);
+            }
+        }
+        Color::<Rgb888>::new_clamped(c[0] as i32, c[1] as i32, c[2] as i32, 0)
+    }
+}
+
+// Gamma boost (square root) of a color component, with 6 additional
+// fractional bits of noise.
+fn gamma(c: u8, noise: u32) -> u8 {
+    let v = (c as u32) << 6 | noise;
+    ((v as f32 / (255 << 6 | 0x3F) as f32).sqrt() * 255.0).round() as u8
+}
+
+// A simple xorshift generator for the gamma dither noise, so that the
+// output is deterministic.
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
@@ -0,0 +1,201 @@
+#[macro_use]
+extern crate slog;
+
+extern crate byteorder;
+extern crate crc;
+extern crate emu;
+extern crate mips64;
+extern crate r64emu;
+
+use byteorder::{BigEndian, ByteOrder};
+use crc::crc32;
+use emu::bus::be::Device;
+use emu::dbg::DebuggerModel;
+use emu::gfx::{OwnedGfxBufferLE, Rgb888};
+use emu::hw::OutputProducer;
+use emu::snd::{OwnedSndBuffer, S16_STEREO};
+use mips64::Cop;
+use r64emu::r4300::R4300;
+use r64emu::{BootMode, N64Builder, N64};
+use slog::Discard;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+mod common;
+use common::*;
+
+// Status bit that enables COP1 (FPU) instructions.
+const STATUS_CU1: u128 = 1 << 29;
+
+// Word written by the game after executing an FPU instruction.
+const MARKER_ADDR: u32 = 0x100;
+const MARKER: u32 = 0x600D_0000;
+
+// Boot code run from the PIF ROM: like the real one, it enables the
+// coprocessors in Status, and then it copies the game from the ROM into
+// RDRAM (0x400) and jumps to it.
+fn bios() -> Vec<u32> {
+    vec![
+        lui(T0, 0x3400), // CU1 | CU0 | FR
+        mtc0(T0, STATUS),
+        lui(S0, 0xB000 - 0x10000),
+        lui(S1, 0xA000 - 0x10000),
+        addiu(T2, ZERO, game().len() as i32),
+        lw(T0, 0x1000, S0),
+        addiu(S0, S0, 4),
+        sw(T0, 0x400, S1),
+        addiu(T2, T2, -1),
+        bne(T2, ZERO, -5),
+        addiu(S1, S1, 4),
+        lui(T0, 0x8000 - 0x10000),
+        ori(T0, T0, 0x400),
+        jr(T0),
+        NOP,
+    ]
+}
+
+// The game executes an FPU instruction, which raises a Coprocessor Unusable
+// exception if CU1 is not set, and then writes the marker and spins.
+fn game() -> Vec<u32> {
+    vec![
+        mtc1(ZERO, 0),
+        lui(T1, (MARKER >> 16) as i32),
+        lui(T2, 0xA000 - 0x10000),
+        sw(T1, MARKER_ADDR as i32, T2),
+        beq(ZERO, ZERO, -1),
+        NOP,
+    ]
+}
+
+// Make the boot code (ROM 0x40..0x1000) look like the one of CIC-NUS-6102,
+// by forging its last word so that it has the same CRC32 (which is what
+// the CIC detection checks).
+fn forge_cic_6102(rom: &mut [u8]) {
+    // Walk back from the final CRC register to find the table entries
+    // selected by the last 4 bytes, and then choose the bytes that select
+    // them.
+    let table = crc32::make_table(crc32::IEEE);
+    let mut reg = !0x90BB_6CB5u32;
+    let mut idx = [0usize; 4];
+    for i in (0..4).rev() {
+        idx[i] = table.iter().position(|t| t >> 24 == reg >> 24).unwrap();
+        reg = (reg ^ table[idx[i]]) << 8;
+    }
+    let mut reg = !crc32::checksum_ieee(&rom[0x40..0xFFC]);
+    for (i, &k) in idx.iter().enumerate() {
+        rom[0xFFC + i] = reg as u8 ^ k as u8;
+        reg = (reg >> 8) ^ table[k];
+    }
+}
+
+// Build the whole machine, with the boot code and the game above.
+fn make_n64(name: &str, boot: BootMode) -> N64 {
+    let mut rom = vec![0u8; 0x2000];
+    BigEndian::write_u32(&mut rom, 0x8037_1240);
+    for (idx, op) in game().iter().enumerate() {
+        BigEndian::write_u32(&mut rom[0x1000 + idx * 4..], *op);
+    }
+    forge_cic_6102(&mut rom);
+    let mut bios = vec![0u8; 0x7C0];
+    for (idx, op) in self::bios().iter().enumerate() {
+        BigEndian::write_u32(&mut bios[idx * 4..], *op);
+    }
+
+    let romfn = env::temp_dir().join(format!("r64emu-boot-{}.z64", name));
+    let biosfn = env::temp_dir().join(format!("r64emu-boot-{}.pif", name));
+    fs::write(&romfn, rom).unwrap();
+    fs::write(&biosfn, bios).unwrap();
+    let logger = slog::Logger::root(Discard, o!());
+    let n64 = N64Builder::new(logger)
+        .rom(&romfn)
+        .bios(&biosfn)
+        .boot(boot)
+        .build()
+        .unwrap();
+    fs::remove_file(&romfn).unwrap();
+    fs::remove_file(&biosfn).unwrap();
+    n64
+}
+
+fn run_frames(n64: &mut N64, frames: usize) {
+    let mut screen = OwnedGfxBufferLE::<Rgb888>::new(640, 480);
+    let mut sound = OwnedSndBuffer::<S16_STEREO>::with_capacity(4096);
+    for _ in 0..frames {
+        n64.render_frame(&mut screen.buf_mut(), &mut sound.buf_mut());
+    }
+}
+
+fn cu1() -> bool {
+    let cpu = R4300::get();
+    cpu.cop0.reg(cpu.ctx(), STATUS as usize) & STATUS_CU1 != 0
+}
+
+// Check that the game is running with CU1 set, and that its FPU instruction
+// did not raise an exception.
+fn assert_game_running() {
+    assert!(cu1());
+    assert_eq!(read(MARKER_ADDR), MARKER);
+    let pc = R4300::get().ctx().pc as u32;
+    assert!((0x8000_0400..0x8000_0420).contains(&pc), "pc: {:x}", pc);
+}
+
+#[test]
+fn cold_reset() {
+    let mut n64 = make_n64("cold", BootMode::ColdReset);
+    run_frames(&mut n64, 2);
+    assert_game_running();
+}
+
+#[test]
+fn warm_reset() {
+    let mut n64 = make_n64("warm", BootMode::WarmReset);
+    run_frames(&mut n64, 2);
+    assert_game_running();
+}
+
+#[test]
+fn soft_reset() {
+    let mut n64 = make_n64("soft", BootMode::ColdReset);
+    n64.set_nmi_delay(Duration::from_millis(1));
+    run_frames(&mut n64, 2);
+    write(MARKER_ADDR, 0);
+
+    // The NMI runs the boot code again, which runs the game again.
+    n64.reset(false);
+    run_frames(&mut n64, 2);
+    assert_game_running();
+}
+
+#[test]
+fn fast_boot() {
+    let dir: PathBuf = env::temp_dir().join("r64emu-boot-fastboot");
+    let _ = fs::remove_dir_all(&dir);
+
+    // Each machine needs its own thread (devices are thread-local). The
+    // first run boots normally, and saves the state as soon as the boot
+    // code jumps to the game.
+    let first = dir.clone();
+    thread::spawn(move || {
+        let mut n64 = make_n64("fast1", BootMode::FastBoot(first));
+        run_frames(&mut n64, 2);
+        assert_game_running();
+    })
+    .join()
+    .unwrap();
+
+    // The next runs start from it, already in the game.
+    let next = dir.clone();
+    thread::spawn(move || {
+        let mut n64 = make_n64("fast2", BootMode::FastBoot(next));
+        assert!(cu1());
+        run_frames(&mut n64, 1);
+        assert_game_running();
+    })
+    .join()
+    .unwrap();
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,74 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use r64emu::errors::EmuError;
+use r64emu::r4300::R4300;
+use r64emu::ri::RDRAM_SIZE_EXPANSION;
+use r64emu::{Devices, N64Builder};
+use slog::Discard;
+use std::path::Path;
+
+fn builder() -> N64Builder {
+    let logger = slog::Logger::root(Discard, o!());
+    N64Builder::new(logger)
+}
+
+fn config_error(res: Result<(), EmuError>) -> String {
+    match res {
+        Err(EmuError::Config(msg)) => msg,
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(()) => panic!("invalid configuration accepted"),
+    }
+}
+
+#[test]
+fn invalid_configs() {
+    // The full machine requires the ROM and the BIOS.
+    let msg = config_error(builder().build().map(|_| ()));
+    assert_eq!(msg, "no ROM specified");
+
+    // Devices require the devices they access.
+    let msg = config_error(builder().devices(Devices::VI).build_rig());
+    assert_eq!(msg, "VI requires MI, SP");
+
+    // Peripherals require the devices they are plugged into.
+    let res = builder().devices(Devices::RSP_RIG).keyboard(1).build_rig();
+    assert_eq!(config_error(res), "the controllers and the RTC require PI");
+    let res = builder()
+        .rom(Path::new("game.z64"))
+        .bios(Path::new("pif.bin"))
+        .keyboard(5)
+        .build_rig();
+    assert_eq!(
+        config_error(res),
+        "invalid controller port for the keyboard: 5"
+    );
+
+    // A subset of the devices cannot be run.
+    let res = builder().devices(Devices::HEADLESS).build().map(|_| ());
+    assert_eq!(
+        config_error(res),
+        "the emulation requires all the devices (missing: VI)"
+    );
+}
+
+#[test]
+fn expansion_pak() {
+    builder()
+        .devices(Devices::RCP_RIG)
+        .rdram_size(RDRAM_SIZE_EXPANSION)
+        .build_rig()
+        .unwrap();
+
+    // The RDRAM beyond 4 MiB is installed, and the one beyond 8 MiB reads
+    // as zero.
+    let bus = &mut R4300::get_mut().bus;
+    bus.write::<u32>(0x0040_1000, 0xAABB_CCDD);
+    assert_eq!(bus.read::<u32>(0x0040_1000), 0xAABB_CCDD);
+    bus.write::<u32>(0x0080_1000, 0xAABB_CCDD);
+    assert_eq!(bus.read::<u32>(0x0080_1000), 0);
+}
@@ -0,0 +1,66 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use r64emu::r4300::R4300;
+use r64emu::{Devices, N64Builder};
+use slog::Discard;
+
+fn make_bus() {
+    let logger = slog::Logger::root(Discard, o!());
+    N64Builder::new(logger)
+        .devices(Devices::RCP_RIG)
+        .build_rig()
+        .unwrap();
+}
+
+fn read(addr: u32) -> u32 {
+    R4300::get_mut().bus.read::<u32>(addr)
+}
+
+fn write(addr: u32, val: u32) {
+    R4300::get_mut().bus.write::<u32>(addr, val);
+}
+
+#[test]
+fn mirrors() {
+    make_bus();
+
+    // RDRAM: the address space beyond the installed 4 MiB reads as zero,
+    // and ignores writes.
+    write(0x0000_1000, 0x1122_3344);
+    assert_eq!(read(0x0000_1000), 0x1122_3344);
+    write(0x0040_1000, 0xAABB_CCDD);
+    assert_eq!(read(0x0040_1000), 0);
+    assert_eq!(read(0x03EF_FFFC), 0);
+    assert_eq!(read(0x0000_1000), 0x1122_3344);
+
+    // SP DMEM/IMEM are echoed every 8 KiB through the SP memory window.
+    write(0x0400_0010, 0x1234_5678);
+    write(0x0400_1020, 0x9ABC_DEF0);
+    assert_eq!(read(0x0400_2010), 0x1234_5678);
+    assert_eq!(read(0x0403_E010), 0x1234_5678);
+    assert_eq!(read(0x0401_3020), 0x9ABC_DEF0);
+    write(0x0402_A010, 0x0BAD_F00D);
+    assert_eq!(read(0x0400_0010), 0x0BAD_F00D);
+    assert_eq!(R4300::get_mut().bus.read::<u8>(0x0400_6013), 0x0D);
+
+    // SP registers echo every 0x20 bytes.
+    write(0x0404_0000, 0x0000_0FC0); // SP_MEM_ADDR
+    assert_eq!(read(0x0404_0020), 0x0000_0FC0);
+    assert_eq!(read(0x0407_FFE0), 0x0000_0FC0);
+    write(0x0405_0040, 0x0000_0100);
+    assert_eq!(read(0x0404_0000), 0x0000_0100);
+
+    // MI registers echo every 0x10 bytes.
+    write(0x0430_001C, 0x0000_0AAA); // MI_INTR_MASK: set all
+    assert_eq!(read(0x0430_000C), 0x3F);
+    assert_eq!(read(0x043F_FFFC), 0x3F);
+    assert_eq!(read(0x0430_0014), read(0x0430_0004));
+
+    // Outside of the windows, accesses are still unmapped.
+    assert_eq!(read(0x0420_0000), 0xFFFF_FFFF);
+}
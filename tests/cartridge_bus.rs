@@ -0,0 +1,113 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use r64emu::cartridge::Cartridge;
+use r64emu::r4300::R4300;
+use r64emu::{Devices, N64Builder};
+use slog::Discard;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::*;
+
+fn write_rom(name: &str) -> PathBuf {
+    let mut rom = vec![0u8; 0x1000];
+    rom[0] = 0x80;
+    rom[0x100..0x108].copy_from_slice(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]);
+    let path = env::temp_dir().join(name);
+    fs::write(&path, rom).unwrap();
+    path
+}
+
+fn cartridge(name: &str) -> Box<Cartridge> {
+    let path = write_rom(name);
+    let cart = Cartridge::new(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    cart
+}
+
+#[test]
+fn rom_read() {
+    let mut cart = cartridge("r64emu-cart-read.z64");
+    assert_eq!(cart.rom_read(0x100, 4), 0x1122_3344);
+    assert_eq!(cart.rom_read(0x102, 2), 0x3344);
+    assert_eq!(cart.rom_read(0x105, 1), 0x66);
+    assert_eq!(cart.rom_read(0x100, 8), 0x1122_3344_5566_7788);
+}
+
+#[test]
+fn open_bus() {
+    // Beyond the end of the ROM, each halfword reads as the lower 16 bits
+    // of its address.
+    let mut cart = cartridge("r64emu-cart-openbus.z64");
+    assert_eq!(cart.rom_read(0x1000, 4), 0x1000_1002);
+    assert_eq!(cart.rom_read(0x0123_4564, 4), 0x4564_4566);
+    assert_eq!(cart.rom_read(0x0123_4566, 2), 0x4566);
+    assert_eq!(cart.rom_read(0x0200_0000, 8), 0x0000_0002_0004_0006);
+}
+
+#[test]
+fn write_latch() {
+    // A write is returned by the next read (only), at any address.
+    let mut cart = cartridge("r64emu-cart-latch.z64");
+    cart.rom_write(0x100, 0xdead_beef, 4);
+    assert_eq!(cart.rom_read(0x800, 4), 0xdead_beef);
+    assert_eq!(cart.rom_read(0x100, 4), 0x1122_3344);
+
+    cart.rom_write(0x2002, 0xabcd, 2);
+    assert_eq!(cart.rom_read(0x2000, 4), 0x0000_abcd);
+    assert_eq!(cart.rom_read(0x2000, 4), 0x2000_2002);
+}
+
+#[test]
+fn cpu_access() {
+    let path = write_rom("r64emu-cart-cpu.z64");
+    let logger = slog::Logger::root(Discard, o!());
+    N64Builder::new(logger)
+        .devices(Devices::RI | Devices::MI | Devices::CARTRIDGE)
+        .rom(&path)
+        .build_rig()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    // The ROM is mapped as memory, for DMAs and instruction fetches.
+    assert!(R4300::get()
+        .bus
+        .fetch_read::<u8>(0x1000_0000)
+        .mem()
+        .is_some());
+
+    // CPU loads and stores go through the PI latch and open bus.
+    write_code(
+        0x1000,
+        &[
+            lui(S1, 0xB000 - 0x10000),
+            lui(T0, 0xDEAD - 0x10000),
+            sw(T0, 0x100, S1),
+            lw(T1, 0x800, S1),
+            lw(T2, 0x100, S1),
+            lw(T3, 0x1000, S1),
+            sw(T1, 0x100, S0),
+            sw(T2, 0x104, S0),
+            sw(T3, 0x108, S0),
+            beq(ZERO, ZERO, -1),
+            NOP,
+        ],
+    );
+    let cpu = R4300::get_mut();
+    cpu.ctx_mut().set_pc(0xFFFF_FFFF_8000_1000);
+    cpu.ctx_mut().regs[S0 as usize] = 0xFFFF_FFFF_8000_0000;
+    let clock = cpu.ctx().clock;
+    cpu.run(clock + 1000, &Tracer::null()).unwrap();
+
+    assert_eq!(read(0x100), 0xDEAD_0000);
+    assert_eq!(read(0x104), 0x1122_3344);
+    assert_eq!(read(0x108), 0x1000_1002);
+}
@@ -0,0 +1,111 @@
+extern crate byteorder;
+extern crate r64emu;
+
+use byteorder::{BigEndian, ByteOrder};
+use r64emu::cartridge::{compute_crcs, fix_crcs, fix_crcs_file, CicModel};
+use r64emu::errors::LoadError;
+use std::env;
+use std::fs;
+
+// A big-endian ROM filled with pseudo-random bytes.
+fn rom() -> Vec<u8> {
+    let mut x: u32 = 1;
+    let mut rom: Vec<u8> = (0..0x10_1000)
+        .map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (x >> 16) as u8
+        })
+        .collect();
+    rom[0..4].copy_from_slice(&[0x80, 0x37, 0x12, 0x40]);
+    rom
+}
+
+#[test]
+fn crcs() {
+    // Reference values computed with the n64crc algorithm.
+    let rom = rom();
+    assert_eq!(
+        compute_crcs(&rom, CicModel::Cic6102),
+        (0xecc1_e9d8, 0xad9b_78ae)
+    );
+    assert_eq!(
+        compute_crcs(&rom, CicModel::Cic6103),
+        (0xd7d7_cda6, 0x00d0_05f1)
+    );
+    assert_eq!(
+        compute_crcs(&rom, CicModel::Cic6105),
+        (0xd26d_5772, 0x01fe_dc07)
+    );
+    assert_eq!(
+        compute_crcs(&rom, CicModel::Cic6106),
+        (0x1471_481c, 0x11ab_25c2)
+    );
+    assert_eq!(
+        compute_crcs(&rom, CicModel::Cic6101),
+        compute_crcs(&rom, CicModel::Cic6102)
+    );
+}
+
+#[test]
+fn pif_seeds() {
+    assert_eq!(CicModel::Cic6101.pif_seed(), 0x0004_3F3F);
+    assert_eq!(CicModel::Cic6102.pif_seed(), 0x0000_3F3F);
+    assert_eq!(CicModel::Cic6103.pif_seed(), 0x0000_783F);
+    assert_eq!(CicModel::Cic6105.pif_seed(), 0x0000_913F);
+    assert_eq!(CicModel::Cic6106.pif_seed(), 0x0000_853F);
+}
+
+#[test]
+fn fix() {
+    let mut rom = rom();
+    let old = (
+        BigEndian::read_u32(&rom[0x10..]),
+        BigEndian::read_u32(&rom[0x14..]),
+    );
+
+    // The boot code is random, so the CIC cannot be detected.
+    match fix_crcs(&mut rom, None) {
+        Err(LoadError::UnknownCic { .. }) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    let fix = fix_crcs(&mut rom, Some(CicModel::Cic6103)).unwrap();
+    assert_eq!(fix.cic, CicModel::Cic6103);
+    assert_eq!(fix.old, old);
+    assert_eq!(fix.new, (0xd7d7_cda6, 0x00d0_05f1));
+    assert!(fix.changed());
+    assert_eq!(BigEndian::read_u32(&rom[0x10..]), 0xd7d7_cda6);
+    assert_eq!(BigEndian::read_u32(&rom[0x14..]), 0x00d0_05f1);
+
+    let fix = fix_crcs(&mut rom, Some(CicModel::Cic6103)).unwrap();
+    assert!(!fix.changed());
+
+    assert!(fix_crcs(&mut rom[..0x800], Some(CicModel::Cic6102)).is_err());
+}
+
+#[test]
+fn fix_file() {
+    // A byteswapped ROM is fixed preserving its byte order.
+    let mut rom = rom();
+    let swapped: Vec<u8> = (0..rom.len()).map(|idx| rom[idx ^ 1]).collect();
+    let input = env::temp_dir().join("r64emu-crc-input.v64");
+    let output = env::temp_dir().join("r64emu-crc-output.v64");
+    fs::write(&input, &swapped).unwrap();
+
+    let check = fix_crcs_file(&input, None, Some(CicModel::Cic6102)).unwrap();
+    assert!(check.changed());
+
+    let fix = fix_crcs_file(&input, Some(&output), Some(CicModel::Cic6102)).unwrap();
+    assert_eq!(fix, check);
+    fix_crcs(&mut rom, Some(CicModel::Cic6102)).unwrap();
+    let fixed = fs::read(&output).unwrap();
+    assert_eq!(fixed[0x10..0x18], swapped_range(&rom, 0x10..0x18)[..]);
+    assert_eq!(fixed[0x18..], swapped[0x18..]);
+
+    fs::remove_file(&input).unwrap();
+    fs::remove_file(&output).unwrap();
+}
+
+fn swapped_range(rom: &[u8], range: std::ops::Range<usize>) -> Vec<u8> {
+    range.map(|idx| rom[idx ^ 1]).collect()
+}
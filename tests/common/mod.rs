@@ -0,0 +1,111 @@
+//! Helpers shared by the integration tests that run code on the RCP rig:
+//! access to the bus of the main CPU, and a minimal MIPS assembler for the
+//! test code (the RSP uses the same encoding for the instructions of its
+//! subset).
+
+#![allow(dead_code)]
+
+use emu::bus::be::Device;
+use r64emu::r4300::R4300;
+use r64emu::{Devices, N64Builder};
+use slog::Discard;
+
+pub fn make_rcp() {
+    let logger = slog::Logger::root(Discard, o!());
+    N64Builder::new(logger)
+        .devices(Devices::RCP_RIG)
+        .build_rig()
+        .unwrap();
+}
+
+pub fn read(addr: u32) -> u32 {
+    R4300::get_mut().bus.read::<u32>(addr)
+}
+
+pub fn write(addr: u32, val: u32) {
+    R4300::get_mut().bus.write::<u32>(addr, val);
+}
+
+pub fn write_code(addr: u32, code: &[u32]) {
+    for (idx, op) in code.iter().enumerate() {
+        write(addr + idx as u32 * 4, *op);
+    }
+}
+
+// General purpose registers.
+pub const ZERO: u32 = 0;
+pub const T0: u32 = 8;
+pub const T1: u32 = 9;
+pub const T2: u32 = 10;
+pub const T3: u32 = 11;
+pub const S0: u32 = 16;
+pub const S1: u32 = 17;
+
+// COP0 registers of the main CPU.
+pub const INDEX: u32 = 0;
+pub const ENTRY_LO0: u32 = 2;
+pub const ENTRY_LO1: u32 = 3;
+pub const PAGE_MASK: u32 = 5;
+pub const BAD_VADDR: u32 = 8;
+pub const COUNT: u32 = 9;
+pub const ENTRY_HI: u32 = 10;
+pub const COMPARE: u32 = 11;
+pub const STATUS: u32 = 12;
+pub const CAUSE: u32 = 13;
+pub const EPC: u32 = 14;
+
+pub fn itype(op: u32, rs: u32, rt: u32, imm: i32) -> u32 {
+    op << 26 | rs << 21 | rt << 16 | (imm as u32 & 0xFFFF)
+}
+pub fn special(rs: u32, rt: u32, rd: u32, func: u32) -> u32 {
+    rs << 21 | rt << 16 | rd << 11 | func
+}
+pub fn lw(rt: u32, off: i32, base: u32) -> u32 {
+    itype(0x23, base, rt, off)
+}
+pub fn sw(rt: u32, off: i32, base: u32) -> u32 {
+    itype(0x2B, base, rt, off)
+}
+pub fn addiu(rt: u32, rs: u32, imm: i32) -> u32 {
+    itype(0x09, rs, rt, imm)
+}
+pub fn andi(rt: u32, rs: u32, imm: i32) -> u32 {
+    itype(0x0C, rs, rt, imm)
+}
+pub fn ori(rt: u32, rs: u32, imm: i32) -> u32 {
+    itype(0x0D, rs, rt, imm)
+}
+pub fn lui(rt: u32, imm: i32) -> u32 {
+    itype(0x0F, 0, rt, imm)
+}
+pub fn beq(rs: u32, rt: u32, off: i32) -> u32 {
+    itype(0x04, rs, rt, off)
+}
+pub fn bne(rs: u32, rt: u32, off: i32) -> u32 {
+    itype(0x05, rs, rt, off)
+}
+pub fn add(rd: u32, rs: u32, rt: u32) -> u32 {
+    special(rs, rt, rd, 0x20)
+}
+pub fn jr(rs: u32) -> u32 {
+    special(rs, 0, 0, 0x08)
+}
+pub fn teq(rs: u32, rt: u32) -> u32 {
+    special(rs, rt, 0, 0x34)
+}
+pub fn tnei(rs: u32, imm: i32) -> u32 {
+    itype(0x01, rs, 0x0E, imm)
+}
+pub fn mfc0(rt: u32, rd: u32) -> u32 {
+    0x4000_0000 | rt << 16 | rd << 11
+}
+pub fn mtc0(rt: u32, rd: u32) -> u32 {
+    0x4080_0000 | rt << 16 | rd << 11
+}
+pub fn mtc1(rt: u32, fs: u32) -> u32 {
+    0x4480_0000 | rt << 16 | fs << 11
+}
+pub const TLBWI: u32 = 0x4200_0002;
+pub const SYSCALL: u32 = 0x0000_000C;
+pub const BREAK: u32 = 0x0000_000D;
+pub const NOP: u32 = 0;
@@ -0,0 +1,51 @@
+extern crate r64emu;
+
+use r64emu::compat::{CompatDb, CompatStatus};
+
+const DB: &str = r#"
+[games.NSME]
+title = "SUPER MARIO 64"
+status = "ingame"
+issues = ["no sound"]
+
+[games.NFZE]
+status = "no-boot"
+settings = ["--fixed-time=0"]
+"#;
+
+#[test]
+fn parse() {
+    let db: CompatDb = DB.parse().unwrap();
+    let sm64 = db.get("NSME").unwrap();
+    assert_eq!(sm64.status, CompatStatus::Ingame);
+    assert_eq!(sm64.issues, vec!["no sound".to_owned()]);
+    assert_eq!(db.get("NFZE").unwrap().settings.len(), 1);
+    assert!(db.get("NXXX").is_none());
+    let invalid = "[games.NSME]\nstatus = \"great\"";
+    assert!(invalid.parse::<CompatDb>().is_err());
+
+    let db2: CompatDb = db.to_toml().parse().unwrap();
+    assert_eq!(db, db2);
+}
+
+#[test]
+fn record_run() {
+    let mut db: CompatDb = DB.parse().unwrap();
+
+    // Automatic statuses are updated by the regression farm
+    db.record_run("NFZE", "F-ZERO X", CompatStatus::Boots);
+    let fzero = db.get("NFZE").unwrap();
+    assert_eq!(fzero.status, CompatStatus::Boots);
+    assert_eq!(fzero.title, "F-ZERO X");
+    assert!(!fzero.regressed());
+
+    // Manual statuses are preserved, but regressions are recorded
+    db.record_run("NSME", "SUPER MARIO", CompatStatus::Crash);
+    let sm64 = db.get("NSME").unwrap();
+    assert_eq!(sm64.status, CompatStatus::Ingame);
+    assert_eq!(sm64.title, "SUPER MARIO 64");
+    assert!(sm64.regressed());
+
+    db.record_run("NNEW", "NEW GAME", CompatStatus::NoBoot);
+    assert_eq!(db.get("NNEW").unwrap().status, CompatStatus::NoBoot);
+}
@@ -0,0 +1,149 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::{CacheView, Tracer};
+use r64emu::r4300::R4300;
+
+mod common;
+use common::*;
+
+// Layout of RDRAM used by the test.
+const CODE_ADDR: u32 = 0x1000;
+const RESULT_ADDR: u32 = 0x0100;
+
+// Register with the base of KSEG0, for data accesses.
+const KSEG0: u32 = S0;
+
+const SP_DMEM: u32 = 0x0400_0000;
+const SP_MEM_ADDR: u32 = 0x0404_0000;
+const SP_DRAM_ADDR: u32 = 0x0404_0004;
+const SP_WR_LEN: u32 = 0x0404_000C;
+
+// Run the main CPU from the beginning of the test code, for the specified
+// number of cycles. Data is accessed through KSEG0 (the lower addresses are
+// mapped by the TLB), with the base in register KSEG0.
+fn run_code(cycles: i64) {
+    let cpu = R4300::get_mut();
+    let pc = 0xFFFF_FFFF_8000_0000 | CODE_ADDR as u64;
+    cpu.ctx_mut().set_pc(pc);
+    cpu.ctx_mut().regs[KSEG0 as usize] = 0xFFFF_FFFF_8000_0000;
+    let clock = cpu.ctx().clock;
+    cpu.run(clock + cycles, &Tracer::null()).unwrap();
+}
+
+// Store the value into the result word, and spin (the busy-wait detector
+// then skips to the end of the run).
+fn store_result(val: i32) -> Vec<u32> {
+    vec![
+        addiu(T0, ZERO, val),
+        sw(T0, RESULT_ADDR as i32, KSEG0),
+        beq(ZERO, ZERO, -1),
+        NOP,
+    ]
+}
+
+#[test]
+fn block_hits() {
+    make_rcp();
+
+    // Count up to 100 in a loop, long enough not to be considered a
+    // busy-wait loop.
+    write_code(
+        CODE_ADDR,
+        &[
+            addiu(T0, ZERO, 0),
+            addiu(T1, ZERO, 100),
+            // loop:
+            addiu(T0, T0, 1),
+            NOP,
+            NOP,
+            NOP,
+            NOP,
+            bne(T0, T1, -6), // -> loop
+            NOP,
+            sw(T0, RESULT_ADDR as i32, KSEG0),
+            beq(ZERO, ZERO, -1),
+            NOP,
+        ],
+    );
+    run_code(10_000);
+    assert_eq!(read(RESULT_ADDR), 100);
+
+    // Decoded blocks: the whole code up to the loop branch (which runs the
+    // first iteration), the loop (the other 99 iterations), and the tail.
+    let stats = R4300::get().cache_stats();
+    assert_eq!(stats.misses, 3);
+    assert_eq!(stats.hits, 98);
+    assert_eq!(stats.invalidations, 0);
+    assert_eq!(stats.blocks, 3);
+
+    // Running the same code again only hits the cache.
+    write(RESULT_ADDR, 0);
+    R4300::get_mut().reset_cache_stats();
+    run_code(10_000);
+    assert_eq!(read(RESULT_ADDR), 100);
+    let stats = R4300::get().cache_stats();
+    assert_eq!(stats.misses, 0);
+    assert!(stats.hit_ratio() > 0.99);
+}
+
+#[test]
+fn invalidate_on_bus_write() {
+    make_rcp();
+
+    write_code(CODE_ADDR, &store_result(1));
+    run_code(1000);
+    assert_eq!(read(RESULT_ADDR), 1);
+
+    write_code(CODE_ADDR, &store_result(2));
+    run_code(1000);
+    assert_eq!(read(RESULT_ADDR), 2);
+    assert!(R4300::get().cache_stats().invalidations > 0);
+}
+
+#[test]
+fn invalidate_on_self_modifying_code() {
+    make_rcp();
+
+    // The code patches an instruction further down the same block, which
+    // must be executed in its new version.
+    let mut code = vec![
+        lw(T2, CODE_ADDR as i32 + 0x40, KSEG0),
+        sw(T2, CODE_ADDR as i32 + 0x14, KSEG0), // patch the first op of the tail
+        NOP,
+        NOP,
+        NOP,
+    ];
+    code.extend(store_result(1));
+    write_code(CODE_ADDR, &code);
+    write(CODE_ADDR + 0x40, addiu(T0, ZERO, 2));
+
+    run_code(1000);
+    assert_eq!(read(RESULT_ADDR), 2);
+    assert!(R4300::get().cache_stats().invalidations > 0);
+}
+
+#[test]
+fn invalidate_on_dma() {
+    make_rcp();
+
+    write_code(CODE_ADDR, &store_result(1));
+    run_code(1000);
+    assert_eq!(read(RESULT_ADDR), 1);
+
+    // Overwrite the code with a SP DMA from DMEM.
+    let code = store_result(3);
+    write_code(SP_DMEM, &code);
+    write(SP_MEM_ADDR, 0);
+    write(SP_DRAM_ADDR, CODE_ADDR);
+    write(SP_WR_LEN, code.len() as u32 * 4 - 1);
+    assert_eq!(read(CODE_ADDR), code[0]);
+
+    run_code(1000);
+    assert_eq!(read(RESULT_ADDR), 3);
+    assert!(R4300::get().cache_stats().invalidations > 0);
+}
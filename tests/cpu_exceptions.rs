@@ -0,0 +1,167 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use r64emu::mi::{IrqMask, Mi};
+use r64emu::r4300::R4300;
+
+mod common;
+use common::*;
+
+// Layout of RDRAM used by the test. The exception handler is at the general
+// exception vector in KSEG0 (0x180).
+const CODE_ADDR: u32 = 0x1000;
+const RESULT_ADDR: u32 = 0x0100;
+const VECTOR: u32 = 0x180;
+
+// Register with the base of KSEG0, for data accesses.
+const KSEG0: u32 = S0;
+
+// Exception recorded by the handler: (vector, exception code, BD, EPC, Cause).
+type Exc = (u32, u32, bool, u32, u32);
+
+// Run the code, with Status cleared first (so that exceptions go through the
+// vectors in KSEG0), after calling setup, and return the exception recorded
+// by the handler.
+fn run_exception_with<F: FnOnce()>(code: &[u32], setup: F) -> Exc {
+    make_rcp();
+
+    // The handler stores Cause, EPC and the vector into the result words,
+    // and spins.
+    write_code(
+        VECTOR,
+        &[
+            mfc0(T0, CAUSE),
+            sw(T0, RESULT_ADDR as i32, KSEG0),
+            mfc0(T0, EPC),
+            sw(T0, RESULT_ADDR as i32 + 4, KSEG0),
+            addiu(T0, ZERO, VECTOR as i32),
+            sw(T0, RESULT_ADDR as i32 + 8, KSEG0),
+            beq(ZERO, ZERO, -1),
+            NOP,
+        ],
+    );
+    write(RESULT_ADDR + 8, 0xFFFF_FFFF);
+
+    let mut prologue = vec![mtc0(ZERO, STATUS)];
+    prologue.extend(code);
+    write_code(CODE_ADDR, &prologue);
+    setup();
+
+    // Run the main CPU from the beginning of the test code, with the base
+    // of KSEG0 in register KSEG0.
+    let cpu = R4300::get_mut();
+    cpu.ctx_mut()
+        .set_pc(0xFFFF_FFFF_8000_0000 | CODE_ADDR as u64);
+    cpu.ctx_mut().regs[KSEG0 as usize] = 0xFFFF_FFFF_8000_0000;
+    let clock = cpu.ctx().clock;
+    cpu.run(clock + 1000, &Tracer::null()).unwrap();
+
+    let cause = read(RESULT_ADDR);
+    (
+        read(RESULT_ADDR + 8),
+        (cause >> 2) & 0x1F,
+        cause >> 31 != 0,
+        read(RESULT_ADDR + 4),
+        cause,
+    )
+}
+
+fn run_exception(code: &[u32]) -> Exc {
+    run_exception_with(code, || {})
+}
+
+#[test]
+fn syscall_in_delay_slot() {
+    let exc = run_exception(&[
+        beq(ZERO, ZERO, 2), // 0x8000_1004
+        SYSCALL,
+        NOP,
+        beq(ZERO, ZERO, -1),
+        NOP,
+    ]);
+    assert_eq!((exc.0, exc.1, exc.2, exc.3), (0x180, 8, true, 0x8000_1004));
+}
+
+#[test]
+fn overflow() {
+    let exc = run_exception(&[
+        lui(T0, 0x7FFF),
+        addiu(T1, ZERO, 5),
+        add(T1, T0, T0), // 0x8000_100C
+        beq(ZERO, ZERO, -1),
+        NOP,
+    ]);
+    assert_eq!(
+        (exc.0, exc.1, exc.2, exc.3),
+        (0x180, 12, false, 0x8000_100C)
+    );
+    // The destination register is not modified.
+    assert_eq!(R4300::get().ctx().regs[T1 as usize], 5);
+}
+
+#[test]
+fn trap() {
+    let exc = run_exception(&[
+        tnei(ZERO, 0),
+        teq(ZERO, ZERO), // 0x8000_1008
+        beq(ZERO, ZERO, -1),
+        NOP,
+    ]);
+    assert_eq!(
+        (exc.0, exc.1, exc.2, exc.3),
+        (0x180, 13, false, 0x8000_1008)
+    );
+}
+
+#[test]
+fn coprocessor_unusable() {
+    let exc = run_exception(&[
+        mtc1(ZERO, 0), // 0x8000_1004
+        beq(ZERO, ZERO, -1),
+        NOP,
+    ]);
+    assert_eq!(
+        (exc.0, exc.1, exc.2, exc.3),
+        (0x180, 11, false, 0x8000_1004)
+    );
+    assert_eq!((exc.4 >> 28) & 3, 1); // CE
+}
+
+#[test]
+fn timer_interrupt() {
+    let exc = run_exception(&[
+        mtc0(ZERO, COUNT),
+        addiu(T0, ZERO, 10),
+        mtc0(T0, COMPARE),
+        ori(T0, ZERO, 0x8001), // IE, IM7
+        mtc0(T0, STATUS),
+        addiu(T1, T1, 1), // 0x8000_1018
+        beq(ZERO, ZERO, -2),
+        NOP,
+    ]);
+    assert_eq!((exc.0, exc.1, exc.2, exc.3), (0x180, 0, false, 0x8000_1018));
+    assert_ne!(exc.4 & 0x8000, 0); // IP7
+}
+
+#[test]
+fn mi_interrupt() {
+    let exc = run_exception_with(
+        &[
+            ori(T0, ZERO, 0x0401), // IE, IM2
+            mtc0(T0, STATUS),
+            beq(ZERO, ZERO, -1), // 0x8000_100C
+            NOP,
+        ],
+        || {
+            write(0x0430_000C, 0b10); // MI_INTR_MASK: set SP
+            Mi::irq_line(IrqMask::SP).raise();
+        },
+    );
+    assert_eq!((exc.0, exc.1, exc.2, exc.3), (0x180, 0, false, 0x8000_100C));
+    assert_ne!(exc.4 & 0x0400, 0); // IP2
+}
@@ -0,0 +1,337 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate mips64;
+extern crate mips_oracle;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use mips64::REG_NAMES;
+use mips_oracle::{Oracle, State};
+use r64emu::r4300::R4300;
+use std::collections::HashMap;
+use std::env;
+use std::panic;
+use std::thread;
+
+mod common;
+use common::{make_rcp, mtc0, read, write, NOP, STATUS};
+
+// Randomized test of the CPU core against the reference interpreter in
+// tests/mips-oracle. Each program is generated by trying random instructions
+// on the oracle, which rejects the ones whose behavior is not defined by the
+// architecture (or that raise exceptions); then it is run by the core and the
+// oracle side by side, comparing the registers and the data memory after each
+// instruction.
+//
+// Branches only jump forward, so that each address is executed at most once
+// and the program can be generated while the oracle runs it. Loads and stores
+// go through a small data window in KSEG0, whose base is kept in register
+// BASE, which programs never modify.
+//
+// Set CPU_ORACLE_SEED to run a single program (eg: the one of a failure).
+
+const CODE_ADDR: u32 = 0x1000;
+const DATA_ADDR: u32 = 0x8000;
+const DATA_SIZE: u32 = 0x100;
+const KSEG0: u64 = 0xFFFF_FFFF_8000_0000;
+const BASE: usize = 16; // s0
+
+const PROGRAMS: u64 = 64;
+const STEPS: usize = 256;
+
+// xorshift64*: deterministic, so that a failure can be reproduced from its
+// seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+// Random register value, biased towards the values that 32-bit operations
+// accept (sign-extended words) and towards the corner cases.
+fn random_value(rng: &mut Rng) -> u64 {
+    const CORNERS: [u64; 6] = [
+        0x7FFF_FFFF,
+        0xFFFF_FFFF_8000_0000,
+        0xFFFF_FFFF_FFFF_FFFF,
+        0x0000_0000_FFFF_FFFF,
+        0x8000_0000_0000_0000,
+        0x7FFF_FFFF_FFFF_FFFF,
+    ];
+    match rng.below(6) {
+        0 => rng.below(16),
+        1 => rng.below(0x20000).wrapping_sub(0x10000),
+        2 | 3 => rng.next() as u32 as i32 as u64,
+        4 => CORNERS[rng.below(CORNERS.len() as u64) as usize],
+        _ => rng.next(),
+    }
+}
+
+// Fields of the opcodes filled with random values.
+const RS: u32 = 0x03E0_0000;
+const RT: u32 = 0x001F_0000;
+const RD: u32 = 0x0000_F800;
+const SA: u32 = 0x0000_07C0;
+const IMM: u32 = 0x0000_FFFF;
+
+#[derive(Copy, Clone)]
+enum Kind {
+    Alu,
+    Branch,
+    Jump,
+    // Load or store of the specified size; unaligned ones (LWL, SDR, ...)
+    // can access any address.
+    Mem(u32, bool),
+}
+
+fn special(func: u32) -> u32 {
+    func
+}
+fn regimm(rt: u32) -> u32 {
+    0x0400_0000 | rt << 16
+}
+fn op(op: u32) -> u32 {
+    op << 26
+}
+
+// The instructions used in the programs: encoding, random fields, kind.
+fn opcodes() -> Vec<(u32, u32, Kind)> {
+    use Kind::*;
+    let mut ops = Vec::new();
+    for &f in &[0x00, 0x02, 0x03, 0x38, 0x3A, 0x3B, 0x3C, 0x3E, 0x3F] {
+        ops.push((special(f), RT | RD | SA, Alu)); // shifts
+    }
+    for &f in &[0x04, 0x06, 0x07, 0x14, 0x16, 0x17] {
+        ops.push((special(f), RS | RT | RD, Alu)); // variable shifts
+    }
+    for f in (0x20..0x28).chain(vec![0x2A, 0x2B, 0x2C, 0x2D, 0x2E, 0x2F]) {
+        ops.push((special(f), RS | RT | RD, Alu));
+    }
+    for f in (0x18..0x20).chain(vec![0x30, 0x31, 0x32, 0x33, 0x34, 0x36]) {
+        ops.push((special(f), RS | RT, Alu)); // mult/div, traps
+    }
+    ops.push((special(0x10), RD, Alu)); // MFHI
+    ops.push((special(0x11), RS, Alu)); // MTHI
+    ops.push((special(0x12), RD, Alu)); // MFLO
+    ops.push((special(0x13), RS, Alu)); // MTLO
+
+    for &rt in &[0x00, 0x01, 0x02, 0x03, 0x10, 0x11, 0x12, 0x13] {
+        ops.push((regimm(rt), RS, Branch));
+    }
+    for &rt in &[0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0E] {
+        ops.push((regimm(rt), RS | IMM, Alu)); // trap immediate
+    }
+    ops.push((op(0x02), 0, Jump)); // J
+    ops.push((op(0x03), 0, Jump)); // JAL
+    for &o in &[0x04, 0x05, 0x14, 0x15] {
+        ops.push((op(o), RS | RT, Branch));
+    }
+    for &o in &[0x06, 0x07, 0x16, 0x17] {
+        ops.push((op(o), RS, Branch));
+    }
+    for o in (0x08..0x0F).chain(vec![0x18, 0x19]) {
+        ops.push((op(o), RS | RT | IMM, Alu));
+    }
+    ops.push((op(0x0F), RT | IMM, Alu)); // LUI
+
+    let mem: [(u32, u32, bool); 19] = [
+        (0x1A, 8, false), // LDL
+        (0x1B, 8, false), // LDR
+        (0x20, 1, true),  // LB
+        (0x21, 2, true),  // LH
+        (0x22, 4, false), // LWL
+        (0x23, 4, true),  // LW
+        (0x24, 1, true),  // LBU
+        (0x25, 2, true),  // LHU
+        (0x26, 4, false), // LWR
+        (0x27, 4, true),  // LWU
+        (0x28, 1, true),  // SB
+        (0x29, 2, true),  // SH
+        (0x2A, 4, false), // SWL
+        (0x2B, 4, true),  // SW
+        (0x2C, 8, false), // SDL
+        (0x2D, 8, false), // SDR
+        (0x2E, 4, false), // SWR
+        (0x37, 8, true),  // LD
+        (0x3F, 8, true),  // SD
+    ];
+    for &(o, size, aligned) in mem.iter() {
+        ops.push((op(o), RT, Mem(size, aligned)));
+    }
+    ops
+}
+
+fn random_insn(rng: &mut Rng, ops: &[(u32, u32, Kind)], pc: u64) -> u32 {
+    let (enc, fields, kind) = ops[rng.below(ops.len() as u64) as usize];
+    let insn = enc | (rng.next() as u32 & fields);
+    match kind {
+        Kind::Alu => insn,
+        // Forward branches, past the delay slot.
+        Kind::Branch => insn | (1 + rng.below(4) as u32),
+        Kind::Jump => {
+            let tgt = pc + 8 + rng.below(4) * 4;
+            insn | (tgt >> 2) as u32 & 0x03FF_FFFF
+        }
+        Kind::Mem(size, aligned) => {
+            let mut off = rng.below((DATA_SIZE - size + 1) as u64) as u32;
+            if aligned {
+                off &= !(size - 1);
+            }
+            insn | (BASE as u32) << 21 | off
+        }
+    }
+}
+
+// Generate a program of the specified number of steps, by running the
+// oracle on random instructions. Return the program, indexed by address.
+fn generate(rng: &mut Rng, oracle: &Oracle, steps: usize) -> HashMap<u64, u32> {
+    let ops = opcodes();
+    let mut oracle = oracle.clone();
+    let mut program = HashMap::new();
+    for _ in 0..steps {
+        let pc = oracle.state.pc;
+        let insn = (0..100)
+            .map(|_| random_insn(rng, &ops, pc))
+            .find(|&insn| {
+                let mut o = oracle.clone();
+                o.step(insn).is_ok() && o.state.regs[BASE] == oracle.state.regs[BASE]
+            })
+            .unwrap_or(NOP);
+        oracle.step(insn).unwrap();
+        program.insert(pc, insn);
+    }
+    program
+}
+
+// Run the core for a single instruction (a not-taken likely branch also
+// skips its delay slot).
+fn step_core() {
+    let cpu = R4300::get_mut();
+    let clock = cpu.ctx().clock;
+    cpu.run(clock + 1, &Tracer::null()).unwrap();
+}
+
+fn core_state() -> State {
+    let ctx = R4300::get().ctx();
+    State {
+        regs: ctx.regs,
+        hi: ctx.hi,
+        lo: ctx.lo,
+        pc: ctx.pc,
+        npc: ctx.next_pc,
+    }
+}
+
+fn diff(exp: &State, found: &State) -> Vec<String> {
+    let mut regs: Vec<_> = (0..32)
+        .map(|i| (REG_NAMES[i], exp.regs[i], found.regs[i]))
+        .collect();
+    regs.push(("hi", exp.hi, found.hi));
+    regs.push(("lo", exp.lo, found.lo));
+    regs.push(("pc", exp.pc, found.pc));
+    regs.push(("npc", exp.npc, found.npc));
+    regs.into_iter()
+        .filter(|(_, e, f)| e != f)
+        .map(|(name, e, f)| format!("{}: expected {:#018x}, found {:#018x}", name, e, f))
+        .collect()
+}
+
+fn run_program(seed: u64) {
+    let mut rng = Rng::new(seed);
+    let pc = KSEG0 | CODE_ADDR as u64;
+    let mut regs = [0u64; 32];
+    for reg in regs.iter_mut().skip(1) {
+        *reg = random_value(&mut rng);
+    }
+    regs[BASE] = KSEG0 | DATA_ADDR as u64;
+    let state = State::new(regs, random_value(&mut rng), random_value(&mut rng), pc);
+    let mem: Vec<u8> = (0..DATA_SIZE).map(|_| rng.next() as u8).collect();
+    let mut oracle = Oracle::new(state.clone(), regs[BASE], mem);
+    let program = generate(&mut rng, &oracle, STEPS);
+
+    // Load the program and the data, and clear Status (kernel mode, no
+    // interrupts) before the program starts.
+    make_rcp();
+    write(CODE_ADDR - 4, mtc0(0, STATUS));
+    for (&addr, &insn) in program.iter() {
+        write((addr - pc) as u32 + CODE_ADDR, insn);
+    }
+    for (idx, word) in oracle.mem.chunks(4).enumerate() {
+        let val = word.iter().fold(0, |v, &b| v << 8 | b as u32);
+        write(DATA_ADDR + idx as u32 * 4, val);
+    }
+
+    R4300::get_mut().ctx_mut().set_pc(pc - 4);
+    step_core();
+    let ctx = R4300::get_mut().ctx_mut();
+    ctx.set_pc(pc);
+    ctx.regs = state.regs;
+    ctx.hi = state.hi;
+    ctx.lo = state.lo;
+
+    for step in 0..STEPS {
+        let pc = oracle.state.pc;
+        let insn = program[&pc];
+        oracle.step(insn).unwrap();
+        step_core();
+
+        let mut errors = diff(&oracle.state, &core_state());
+        for (idx, word) in oracle.mem.chunks(4).enumerate() {
+            let exp = word.iter().fold(0, |v, &b| v << 8 | b as u32);
+            let found = read(DATA_ADDR + idx as u32 * 4);
+            if exp != found {
+                errors.push(format!(
+                    "mem[{:#x}]: expected {:#010x}, found {:#010x}",
+                    idx * 4,
+                    exp,
+                    found
+                ));
+            }
+        }
+        assert!(
+            errors.is_empty(),
+            "seed {}, step {}: opcode {:#010x} at {:#018x}\n{}",
+            seed,
+            step,
+            insn,
+            pc,
+            errors.join("\n")
+        );
+    }
+}
+
+// The devices and their state are per-thread, so each program runs in its
+// own thread, on a freshly built machine.
+fn run_program_in_thread(seed: u64) {
+    if let Err(err) = thread::spawn(move || run_program(seed)).join() {
+        panic::resume_unwind(err);
+    }
+}
+
+#[test]
+fn random_programs() {
+    match env::var("CPU_ORACLE_SEED") {
+        Ok(seed) => run_program_in_thread(seed.parse().expect("invalid CPU_ORACLE_SEED")),
+        Err(_) => {
+            for seed in 0..PROGRAMS {
+                run_program_in_thread(seed);
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use r64emu::r4300::R4300;
+
+mod common;
+use common::*;
+
+// Layout of RDRAM used by the test. The exception handlers are at the
+// vectors in KSEG0 (0x000 for TLB refills, 0x180 for the others).
+const CODE_ADDR: u32 = 0x1000;
+const RESULT_ADDR: u32 = 0x0100;
+
+// Register with the base of KSEG0, for data accesses.
+const KSEG0: u32 = S0;
+
+// Run the main CPU from the beginning of the test code, for the specified
+// number of cycles, with the base of KSEG0 in register KSEG0.
+fn run_code(cycles: i64) {
+    let cpu = R4300::get_mut();
+    let pc = 0xFFFF_FFFF_8000_0000 | CODE_ADDR as u64;
+    cpu.ctx_mut().set_pc(pc);
+    cpu.ctx_mut().regs[KSEG0 as usize] = 0xFFFF_FFFF_8000_0000;
+    let clock = cpu.ctx().clock;
+    cpu.run(clock + cycles, &Tracer::null()).unwrap();
+}
+
+// Install an exception handler at the specified vector, which stores Cause,
+// EPC, BadVAddr and the vector into the result words, and spins.
+fn install_handler(vector: u32) {
+    write_code(
+        vector,
+        &[
+            mfc0(T0, CAUSE),
+            sw(T0, RESULT_ADDR as i32, KSEG0),
+            mfc0(T0, EPC),
+            sw(T0, RESULT_ADDR as i32 + 4, KSEG0),
+            mfc0(T0, BAD_VADDR),
+            sw(T0, RESULT_ADDR as i32 + 8, KSEG0),
+            addiu(T0, ZERO, vector as i32),
+            sw(T0, RESULT_ADDR as i32 + 12, KSEG0),
+            beq(ZERO, ZERO, -1),
+            NOP,
+        ],
+    );
+}
+
+// Run the code, with Status cleared first (so that exceptions go through the
+// vectors in KSEG0), and return the exception recorded by the handlers as
+// (vector, exception code, BD, EPC, BadVAddr).
+fn run_exception(code: &[u32]) -> (u32, u32, bool, u32, u32) {
+    make_rcp();
+    install_handler(0x000);
+    install_handler(0x180);
+    write(RESULT_ADDR + 12, 0xFFFF_FFFF);
+
+    let mut prologue = vec![mtc0(ZERO, STATUS)];
+    prologue.extend(code);
+    write_code(CODE_ADDR, &prologue);
+    run_code(1000);
+
+    let cause = read(RESULT_ADDR);
+    (
+        read(RESULT_ADDR + 12),
+        (cause >> 2) & 0x1F,
+        cause >> 31 != 0,
+        read(RESULT_ADDR + 4),
+        read(RESULT_ADDR + 8),
+    )
+}
+
+#[test]
+fn refill_on_load() {
+    let exc = run_exception(&[
+        lui(T1, 0x0040),
+        lw(T0, 0, T1), // 0x8000_1008
+        beq(ZERO, ZERO, -1),
+        NOP,
+    ]);
+    assert_eq!(exc, (0x000, 2, false, 0x8000_1008, 0x0040_0000));
+}
+
+#[test]
+fn refill_in_delay_slot() {
+    let exc = run_exception(&[
+        lui(T1, 0x0040),
+        beq(ZERO, ZERO, 2), // 0x8000_1008
+        sw(T0, 4, T1),
+        NOP,
+        beq(ZERO, ZERO, -1),
+        NOP,
+    ]);
+    assert_eq!(exc, (0x000, 3, true, 0x8000_1008, 0x0040_0004));
+}
+
+#[test]
+fn mapped_access() {
+    // Map 0x00400000 to 0x2000 (dirty) and 0x00401000 to 0x3000 (clean).
+    let exc = run_exception(&[
+        mtc0(ZERO, INDEX),
+        mtc0(ZERO, PAGE_MASK),
+        lui(T1, 0x0040),
+        mtc0(T1, ENTRY_HI),
+        addiu(T2, ZERO, (0x2000 >> 12) << 6 | 0b110),
+        mtc0(T2, ENTRY_LO0),
+        addiu(T2, ZERO, (0x3000 >> 12) << 6 | 0b010),
+        mtc0(T2, ENTRY_LO1),
+        TLBWI,
+        addiu(T0, ZERO, 42),
+        sw(T0, 0x10, T1),
+        addiu(T2, ZERO, 77),
+        sw(T2, 0x3004, KSEG0),
+        lw(T3, 0x1004, T1),
+        sw(T3, RESULT_ADDR as i32 + 16, KSEG0),
+        sw(T0, 0x1000, T1), // 0x8000_1040
+        beq(ZERO, ZERO, -1),
+        NOP,
+    ]);
+    assert_eq!(read(0x2010), 42);
+    assert_eq!(read(RESULT_ADDR + 16), 77);
+    assert_eq!(read(0x3000), 0);
+    assert_eq!(exc, (0x180, 1, false, 0x8000_1040, 0x0040_1000));
+}
@@ -0,0 +1,156 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::memint::MemInt;
+use emu::state::CurrentState;
+use r64emu::mi::{IrqMask, Mi};
+use r64emu::r4300::R4300;
+use r64emu::{Devices, N64Builder};
+use slog::Discard;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// A scripted sequence of bus accesses against a device. The transcript of
+// the accesses (with the values read back) and the final state of the
+// registers of the device are compared against a golden file in
+// tests/device_regs. Run with UPDATE_GOLDEN=1 to regenerate the golden files
+// after an intended change of semantics.
+struct RegScript {
+    transcript: String,
+}
+
+impl RegScript {
+    // Create a minimal machine: the CPU (to access the bus and receive
+    // interrupts) and MI. Other devices can be registered and mapped
+    // by the tests that need them.
+    fn new() -> RegScript {
+        let logger = slog::Logger::root(Discard, o!());
+        N64Builder::new(logger)
+            .devices(Devices::MI)
+            .build_rig()
+            .unwrap();
+        RegScript {
+            transcript: String::new(),
+        }
+    }
+
+    fn write(&mut self, addr: u32, val: u32) {
+        self.transcript += &format!("W {:08x} <- {:08x}\n", addr, val);
+        R4300::get_mut().bus.write::<u32>(addr, val);
+    }
+
+    fn read(&mut self, addr: u32) -> u32 {
+        let val = R4300::get_mut().bus.read::<u32>(addr);
+        self.transcript += &format!("R {:08x} -> {:08x}\n", addr, val);
+        val
+    }
+
+    // Like write and read, for accesses of other sizes (eg: to check the
+    // behavior of the device on narrow or wide accesses to its registers).
+    fn write_as<U: MemInt>(&mut self, addr: u32, val: U) {
+        self.transcript += &format!(
+            "W{} {:08x} <- {:0w$x}\n",
+            U::SIZE * 8,
+            addr,
+            Into::<u64>::into(val),
+            w = U::SIZE * 2
+        );
+        R4300::get_mut().bus.write::<U>(addr, val);
+    }
+
+    fn read_as<U: MemInt>(&mut self, addr: u32) -> U {
+        let val = R4300::get_mut().bus.read::<U>(addr);
+        self.transcript += &format!(
+            "R{} {:08x} -> {:0w$x}\n",
+            U::SIZE * 8,
+            addr,
+            Into::<u64>::into(val),
+            w = U::SIZE * 2
+        );
+        val
+    }
+
+    // Record an action done outside of the bus (eg: a device raising an
+    // interrupt).
+    fn note(&mut self, msg: &str) {
+        self.transcript += &format!("# {}\n", msg);
+    }
+
+    // Compare the transcript and the registers of the device (state fields
+    // starting with prefix) against the golden file.
+    fn check(self, name: &str, prefix: &str) {
+        let actual = format!("{}--\n{}", self.transcript, CurrentState().dump(prefix));
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/device_regs")
+            .join(format!("{}.golden", name));
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&path, &actual).unwrap();
+            return;
+        }
+        let expected = fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("cannot read {}: {}", path.display(), err));
+        assert!(
+            actual == expected,
+            "register state differs from {} (run with UPDATE_GOLDEN=1 to update)\n\
+             expected:\n{}\nactual:\n{}",
+            path.display(),
+            expected,
+            actual
+        );
+    }
+}
+
+const MI_MODE: u32 = 0x0430_0000;
+const MI_VERSION: u32 = 0x0430_0004;
+const MI_INTR: u32 = 0x0430_0008;
+const MI_INTR_MASK: u32 = 0x0430_000C;
+
+#[test]
+fn mi_mode() {
+    let mut s = RegScript::new();
+    s.write(MI_MODE, 0x0000_017F); // init length + clear/set init mode
+    s.write(MI_MODE, 0x0000_2000); // set RDRAM reg mode
+    s.read(MI_MODE);
+    s.write(MI_VERSION, 0xFFFF_FFFF); // read-only
+    s.read(MI_VERSION);
+    s.write(MI_INTR_MASK, 0x0000_0AAA); // set all masks
+    s.write(MI_INTR_MASK, 0x0000_0001); // clear SP mask
+    s.read(MI_INTR_MASK);
+    s.write(MI_INTR, 0xFFFF_FFFF); // read-only
+    s.read(MI_INTR);
+    s.check("mi_mode", "Mi::");
+}
+
+#[test]
+fn mi_interrupts() {
+    let mut s = RegScript::new();
+    s.note("raise SP, VI, DP");
+    Mi::get_mut().set_irq_line(IrqMask::SP | IrqMask::VI | IrqMask::DP, true);
+    s.read(MI_INTR);
+    s.write(MI_MODE, 0x0000_0800); // clear DP interrupt
+    s.read(MI_INTR);
+    s.write(MI_INTR_MASK, 0x0000_0082); // set SP and VI masks
+    s.read(MI_INTR_MASK);
+    s.check("mi_interrupts", "Mi::");
+}
+
+#[test]
+fn mi_subword() {
+    // Narrow writes to RCP registers write the whole register, with the
+    // value shifted into its lane; wide accesses are split in address order.
+    let mut s = RegScript::new();
+    s.write_as::<u8>(MI_INTR_MASK + 3, 0x0A); // set SP and SI masks
+    s.write_as::<u8>(MI_INTR_MASK + 2, 0x08); // set DP mask
+    s.read_as::<u16>(MI_INTR_MASK + 2);
+    s.read_as::<u8>(MI_INTR_MASK);
+    s.write_as::<u16>(MI_MODE + 2, 0x0100); // set init mode, clear init length
+    s.read(MI_MODE);
+    s.write_as::<u64>(MI_INTR, 0x0000_0002); // MI_INTR is read-only
+    s.read_as::<u64>(MI_INTR);
+    s.check("mi_subword", "Mi::");
+}
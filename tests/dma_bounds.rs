@@ -0,0 +1,384 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::{self, AudioView, Debugger, DualMemView, MemHighlight, TraceEvent};
+use emu::dma::oob_event;
+use emu::sync::Subsystem;
+use r64emu::ai::Ai;
+use r64emu::pi::Pi;
+use r64emu::r4300::R4300;
+use r64emu::sp::{Sp, RSPCPU};
+use r64emu::{Devices, N64Builder};
+use slog::Discard;
+use std::env;
+use std::fs;
+
+const SP_DMEM: u32 = 0x0400_0000;
+const SP_IMEM: u32 = 0x0400_1000;
+const SP_MEM_ADDR: u32 = 0x0404_0000;
+const SP_DRAM_ADDR: u32 = 0x0404_0004;
+const SP_RD_LEN: u32 = 0x0404_0008;
+const SP_WR_LEN: u32 = 0x0404_000C;
+const AI_DRAM_ADDR: u32 = 0x0450_0000;
+const AI_LEN: u32 = 0x0450_0004;
+const AI_CONTROL: u32 = 0x0450_0008;
+const AI_DACRATE: u32 = 0x0450_0010;
+const AI_BITRATE: u32 = 0x0450_0014;
+const PI_DRAM_ADDR: u32 = 0x0460_0000;
+const PI_CART_ADDR: u32 = 0x0460_0004;
+const PI_RD_LEN: u32 = 0x0460_0008;
+const PI_WR_LEN: u32 = 0x0460_000C;
+const PI_STATUS: u32 = 0x0460_0010;
+const PI_BSD_DOM1_LAT: u32 = 0x0460_0014;
+const PI_BSD_DOM1_PWD: u32 = 0x0460_0018;
+const PI_BSD_DOM1_PGS: u32 = 0x0460_001C;
+const PI_BSD_DOM1_RLS: u32 = 0x0460_0020;
+const MI_INTR: u32 = 0x0430_0008;
+const SI_DRAM_ADDR: u32 = 0x0480_0000;
+const SI_PIF_ADDR_RD64B: u32 = 0x0480_0004;
+const SI_PIF_ADDR_WR64B: u32 = 0x0480_0010;
+const PIF_RAM: u32 = 0x1FC0_07C0;
+const ROM: u32 = 0x1000_0000;
+
+// Create the whole machine (except VI), with a small ROM whose bytes
+// 0x100..0x108 are 11 22 33 44 55 66 77 88.
+fn make_n64(name: &str) {
+    let mut rom = vec![0u8; 0x1000];
+    rom[0] = 0x80;
+    rom[0x100..0x108].copy_from_slice(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]);
+    let romfn = env::temp_dir().join(format!("r64emu-dma-{}.z64", name));
+    let biosfn = env::temp_dir().join(format!("r64emu-dma-{}.pif", name));
+    fs::write(&romfn, rom).unwrap();
+    fs::write(&biosfn, vec![0u8; 0x7C0]).unwrap();
+
+    let logger = slog::Logger::root(Discard, o!());
+    N64Builder::new(logger)
+        .devices(Devices::HEADLESS - Devices::SC64)
+        .rom(&romfn)
+        .bios(&biosfn)
+        .build_rig()
+        .unwrap();
+    fs::remove_file(&romfn).unwrap();
+    fs::remove_file(&biosfn).unwrap();
+}
+
+fn read(addr: u32) -> u32 {
+    R4300::get_mut().bus.read::<u32>(addr)
+}
+
+fn write(addr: u32, val: u32) {
+    R4300::get_mut().bus.write::<u32>(addr, val);
+}
+
+// Create a debugger that breaks on the out-of-bounds events of all the DMA
+// channels.
+fn debugger() -> Debugger {
+    let channels = ["PI DMA", "SI DMA", "SP DMA", "AI DMA"];
+    let mut dbg = Debugger::new(&vec![]);
+    dbg.set_hw_events(channels.iter().map(|c| oob_event(c)).collect());
+    for c in channels.iter() {
+        dbg.set_hw_event_break(&oob_event(c), true);
+    }
+    dbg
+}
+
+// Check that an out-of-bounds event was reported for the specified channel.
+fn assert_oob(res: dbg::Result<()>, channel: &str) {
+    match res.map_err(|evt| *evt) {
+        Err(TraceEvent::HwEvent(name)) => assert_eq!(name, oob_event(channel)),
+        res => panic!("no out-of-bounds event for {}: {:?}", channel, res),
+    }
+}
+
+// Set the shortest timings for PI domain 1, so that a small DMA completes
+// within a single call to run_pi.
+fn fast_pi_timings() {
+    write(PI_BSD_DOM1_LAT, 0);
+    write(PI_BSD_DOM1_PWD, 0);
+    write(PI_BSD_DOM1_PGS, 0);
+    write(PI_BSD_DOM1_RLS, 0);
+}
+
+fn run_pi(dbg: &Debugger) -> dbg::Result<()> {
+    let cycles = Pi::get().cycles();
+    Pi::get_mut().run(cycles + 100, &dbg.new_tracer())
+}
+
+fn run_sp(dbg: &Debugger) -> dbg::Result<()> {
+    let clock = RSPCPU::get().ctx().clock;
+    Sp::get_mut().run(clock + 100, &dbg.new_tracer())
+}
+
+#[test]
+fn pi_in_bounds() {
+    make_n64("pi-in-bounds");
+    let dbg = debugger();
+
+    write(PI_DRAM_ADDR, 0x1000);
+    write(PI_CART_ADDR, ROM + 0x100);
+    write(PI_WR_LEN, 7);
+    assert_eq!(read(0x1000), 0x1122_3344);
+    assert_eq!(read(0x1004), 0x5566_7788);
+    assert_eq!(read(PI_DRAM_ADDR), 0x1008);
+    assert!(run_pi(&dbg).is_ok());
+}
+
+#[test]
+fn pi_timing() {
+    make_n64("pi-timing");
+    let dbg = debugger();
+
+    // Timings of the usual ROM header (0x80371240): 65 cycles for the page,
+    // and 23 for each of the 4 halfwords.
+    write(PI_BSD_DOM1_LAT, 0x40);
+    write(PI_BSD_DOM1_PWD, 0x12);
+    write(PI_BSD_DOM1_PGS, 0x07);
+    write(PI_BSD_DOM1_RLS, 0x03);
+    write(PI_DRAM_ADDR, 0x1000);
+    write(PI_CART_ADDR, ROM + 0x100);
+    write(PI_WR_LEN, 7);
+    assert_eq!(read(PI_STATUS) & 3, 3);
+
+    assert!(run_pi(&dbg).is_ok());
+    assert_eq!(read(PI_STATUS) & 3, 3);
+    assert_eq!(read(MI_INTR) & 0x10, 0);
+
+    assert!(run_pi(&dbg).is_ok());
+    assert_eq!(read(PI_STATUS) & 3, 0);
+    assert_ne!(read(MI_INTR) & 0x10, 0);
+}
+
+#[test]
+fn pi_status_reset() {
+    make_n64("pi-status-reset");
+    let dbg = debugger();
+    fast_pi_timings();
+
+    // Resetting the controller aborts the DMA: it never completes.
+    write(PI_DRAM_ADDR, 0x1000);
+    write(PI_CART_ADDR, ROM + 0x100);
+    write(PI_WR_LEN, 7);
+    assert_eq!(read(PI_STATUS) & 3, 3);
+    write(PI_STATUS, 1);
+    assert_eq!(read(PI_STATUS) & 3, 0);
+    assert!(run_pi(&dbg).is_ok());
+    assert!(run_pi(&dbg).is_ok());
+    assert_eq!(read(PI_STATUS) & 8, 0);
+    assert_eq!(read(MI_INTR) & 0x10, 0);
+
+    // The interrupt of a previous DMA is not cleared by the reset.
+    write(PI_WR_LEN, 7);
+    assert!(run_pi(&dbg).is_ok());
+    write(PI_WR_LEN, 7);
+    write(PI_STATUS, 1);
+    assert_eq!(read(PI_STATUS) & 0xB, 8);
+    assert_ne!(read(MI_INTR) & 0x10, 0);
+}
+
+#[test]
+fn pi_status_clear_interrupt() {
+    make_n64("pi-status-clear-interrupt");
+    let dbg = debugger();
+    fast_pi_timings();
+
+    write(PI_DRAM_ADDR, 0x1000);
+    write(PI_CART_ADDR, ROM + 0x100);
+    write(PI_WR_LEN, 7);
+    assert!(run_pi(&dbg).is_ok());
+    assert_eq!(read(PI_STATUS) & 0xB, 8);
+    assert_ne!(read(MI_INTR) & 0x10, 0);
+
+    // Clearing the interrupt does not abort a DMA in progress.
+    write(PI_WR_LEN, 7);
+    write(PI_STATUS, 2);
+    assert_eq!(read(PI_STATUS) & 0xB, 3);
+    assert_eq!(read(MI_INTR) & 0x10, 0);
+    assert!(run_pi(&dbg).is_ok());
+    assert_eq!(read(PI_STATUS) & 0xB, 8);
+    assert_ne!(read(MI_INTR) & 0x10, 0);
+}
+
+#[test]
+fn pi_rdram_wrap() {
+    make_n64("pi-rdram-wrap");
+    let dbg = debugger();
+
+    // The RDRAM address wraps around at 16 MiB, rather than spilling into
+    // the RCP registers.
+    write(PI_DRAM_ADDR, 0x00FF_FFFC);
+    write(PI_CART_ADDR, ROM + 0x100);
+    write(PI_WR_LEN, 7);
+    assert_eq!(read(0x0000), 0x5566_7788);
+    assert_eq!(read(PI_DRAM_ADDR), 0x0004);
+    assert_eq!(read(PI_CART_ADDR), ROM + 0x108);
+
+    assert_oob(run_pi(&dbg), "PI DMA");
+    assert!(run_pi(&dbg).is_ok());
+}
+
+#[test]
+fn pi_read_wrap() {
+    make_n64("pi-read-wrap");
+    let dbg = debugger();
+
+    // RDRAM to cartridge, with both addresses wrapping around.
+    write(PI_DRAM_ADDR, 0x00FF_FFF8);
+    write(PI_CART_ADDR, 0xFFFF_FFF8);
+    write(PI_RD_LEN, 15);
+    assert_eq!(read(PI_DRAM_ADDR), 0x0008);
+    assert_eq!(read(PI_CART_ADDR), 0x0008);
+    assert_oob(run_pi(&dbg), "PI DMA");
+}
+
+#[test]
+fn si_rdram_wrap() {
+    make_n64("si-rdram-wrap");
+    let dbg = debugger();
+
+    // The second half of PIF RAM comes from the beginning of RDRAM.
+    write(0x0000, 0xDEAD_BEEF);
+    write(SI_DRAM_ADDR, 0x00FF_FFE0);
+    write(SI_PIF_ADDR_WR64B, PIF_RAM);
+    assert_eq!(read(PIF_RAM + 0x20), 0xDEAD_BEEF);
+    assert_oob(run_pi(&dbg), "SI DMA");
+}
+
+#[test]
+fn si_pif_wrap() {
+    make_n64("si-pif-wrap");
+    let dbg = debugger();
+
+    // Starting from the middle of PIF RAM, the transfer wraps around at its
+    // end.
+    write(PIF_RAM, 0x1234_5678);
+    write(PIF_RAM + 0x20, 0x9ABC_DEF0);
+    write(SI_DRAM_ADDR, 0x2000);
+    write(SI_PIF_ADDR_RD64B, PIF_RAM + 0x20);
+    assert_eq!(read(0x2000), 0x9ABC_DEF0);
+    assert_eq!(read(0x2020), 0x1234_5678);
+    assert_oob(run_pi(&dbg), "SI DMA");
+
+    // An address outside of PIF RAM is clamped into it.
+    write(SI_PIF_ADDR_RD64B, 0x1FC0_0000);
+    assert_eq!(read(0x2000), 0x1234_5678);
+    assert_oob(run_pi(&dbg), "SI DMA");
+
+    write(SI_PIF_ADDR_RD64B, PIF_RAM);
+    assert!(run_pi(&dbg).is_ok());
+}
+
+#[test]
+fn sp_in_bounds() {
+    make_n64("sp-in-bounds");
+    let dbg = debugger();
+
+    // A transfer ending exactly at the end of DMEM.
+    write(0x1000, 0x1111_1111);
+    write(0x100C, 0x2222_2222);
+    write(SP_MEM_ADDR, 0x0FF0);
+    write(SP_DRAM_ADDR, 0x1000);
+    write(SP_RD_LEN, 15);
+    assert_eq!(read(SP_DMEM + 0xFF0), 0x1111_1111);
+    assert_eq!(read(SP_DMEM + 0xFFC), 0x2222_2222);
+    assert!(run_sp(&dbg).is_ok());
+}
+
+#[test]
+fn sp_dma_highlight() {
+    make_n64("sp-dma-highlight");
+    let dbg = debugger();
+    let dma_highlights = || {
+        let mut highlights = vec![];
+        Sp::get().visit_highlights(|bank, range, kind| {
+            if kind != MemHighlight::Pc {
+                highlights.push((bank, range, kind));
+            }
+        });
+        highlights
+    };
+
+    // The memory view highlights the DMA only until it is complete.
+    write(SP_MEM_ADDR, 0x0FF0);
+    write(SP_DRAM_ADDR, 0x1000);
+    write(SP_RD_LEN, 15);
+    assert_eq!(
+        dma_highlights(),
+        vec![(0, (0xFF0, 0x1000), MemHighlight::DmaTarget)]
+    );
+    assert!(run_sp(&dbg).is_ok());
+    assert_eq!(dma_highlights(), vec![]);
+}
+
+#[test]
+fn sp_dmem_wrap() {
+    make_n64("sp-dmem-wrap");
+    let dbg = debugger();
+
+    // The transfer wraps around at the end of DMEM, without touching IMEM.
+    write(0x1000, 0x1111_1111);
+    write(0x1008, 0x2222_2222);
+    write(SP_MEM_ADDR, 0x0FF8);
+    write(SP_DRAM_ADDR, 0x1000);
+    write(SP_RD_LEN, 15);
+    assert_eq!(read(SP_DMEM + 0xFF8), 0x1111_1111);
+    assert_eq!(read(SP_DMEM), 0x2222_2222);
+    assert_eq!(read(SP_IMEM), 0);
+    assert_oob(run_sp(&dbg), "SP DMA");
+}
+
+#[test]
+fn sp_imem_wrap() {
+    make_n64("sp-imem-wrap");
+    let dbg = debugger();
+
+    // The same happens at the end of IMEM, which does not continue into
+    // DMEM.
+    write(SP_DMEM, 0x3333_3333);
+    write(SP_IMEM + 0xFF8, 0x1111_1111);
+    write(SP_IMEM, 0x2222_2222);
+    write(SP_MEM_ADDR, 0x1FF8);
+    write(SP_DRAM_ADDR, 0x1000);
+    write(SP_WR_LEN, 15);
+    assert_eq!(read(0x1000), 0x1111_1111);
+    assert_eq!(read(0x1008), 0x2222_2222);
+    assert_oob(run_sp(&dbg), "SP DMA");
+}
+
+#[test]
+fn sp_rdram_wrap() {
+    make_n64("sp-rdram-wrap");
+    let dbg = debugger();
+
+    // Two rows of 8 bytes, skipping 8 bytes: the second row starts past
+    // the end of the 24-bit RDRAM address space.
+    write(0x0000, 0x2222_2222);
+    write(SP_MEM_ADDR, 0x0100);
+    write(SP_DRAM_ADDR, 0x00FF_FFF0);
+    write(SP_RD_LEN, (8 << 20) | (1 << 12) | 7);
+    assert_eq!(read(SP_DMEM + 0x108), 0x2222_2222);
+    assert_oob(run_sp(&dbg), "SP DMA");
+}
+
+#[test]
+fn ai_rdram_wrap() {
+    make_n64("ai-rdram-wrap");
+    let dbg = debugger();
+
+    // 16-bit stereo samples, one per cycle.
+    write(AI_CONTROL, 1);
+    write(AI_DACRATE, 0);
+    write(AI_BITRATE, 15);
+    write(AI_DRAM_ADDR, 0x00FF_FFF8);
+    write(AI_LEN, 16);
+
+    // After three samples, playback continues from the beginning of RDRAM.
+    let ai = Ai::get_mut();
+    assert_oob(ai.run(3, &dbg.new_tracer()), "AI DMA");
+    let fifo = ai.dma_queue().into_iter().flatten().find(|f| f.playing);
+    let fifo = fifo.unwrap();
+    assert_eq!((fifo.addr, fifo.remaining), (0x4, 4));
+}
@@ -32,6 +32,21 @@ Start:
   lw s2,$08(a1)   // Test result size
   addi a1,a1,$10      // Skip header
 
+  // The RCP cycles taken by each test vector are stored after the results,
+  // preceded by the latency of starting the RSP and seeing it halted,
+  // measured with a single break at the end of IMEM.
+  multu s0,s2
+  mflo a3
+  add a3,a2 // A3 = Timings
+  lui t0,SP_MEM_BASE
+  ori t1,r0,$000D // break
+  sw t1,SP_IMEM+$0FFC(t0)
+  SetSPPC($0FFC)
+  jal RunSP
+  nop
+  sw v0,0(a3)
+  addi a3,4
+
 Loop:
   lui t0,SP_MEM_BASE // T0 = SP Memory Base Register ($A4000000)
   sw t0,SP_MEM_ADDR(a0) // Store Memory Offset To SP Memory Address Register ($A4040000)
@@ -42,12 +57,10 @@ Loop:
 
   // Start RSP and wait until finished
   SetSPPC($0000)
-  StartSP()
-WaitSPHalted:
-  lw t0,SP_STATUS(a0)
-  andi t0,t0,1
-  beqz t0,WaitSPHalted
+  jal RunSP
   nop
+  sw v0,0(a3)
+  addi a3,4
 
   // Copy results
   lui t0,SP_MEM_BASE
@@ -64,6 +77,7 @@ WaitSPHalted:
   add a2,s2
 
 End:
+  or a2,a3,r0 // Skip the timings
   li t5,0xA0000000  // uncached segment
   or a2,t5
   li t4,0xABABABAB
@@ -95,6 +109,23 @@ Halt:
 	j Halt
 	nop
 
+// Start the RSP and wait until it halts. Returns in V0 the RCP cycles
+// elapsed, as counted by the DP clock counter (24 bits).
+RunSP:
+  lui t1,DPC_BASE
+  lw t2,DPC_CLOCK(t1)
+  StartSP()
+WaitSPHalted:
+  lw t0,SP_STATUS(a0)
+  andi t0,t0,1
+  beqz t0,WaitSPHalted
+  nop
+  lw v0,DPC_CLOCK(t1)
+  subu v0,v0,t2
+  li t0,$00FFFFFF
+  jr ra
+  and v0,v0,t0
+
 align(1024) // Align 64-Bit
 RSPCode:
 insert RSPCode2, "rsp.bin"
@@ -4,8 +4,8 @@
 
 set -euo pipefail
 
-if [ $# -ne 3 ]; then
-	echo "Usage: run.sh <OUTPUT_FILENAME> <OUTPUT_SIZE> <MEM_SIZE>"
+if [ $# -ne 5 ]; then
+	echo "Usage: run.sh <OUTPUT_FILENAME> <OUTPUT_SIZE> <MEM_SIZE> <TIMINGS_FILENAME> <TIMINGS_SIZE>"
 	exit 1
 fi
 
@@ -21,3 +21,4 @@ read -r
 sleep 2
 64drive -q -o 0x1000000 -s "$3" -d golden.raw
 head -c "$2" <golden.raw >"$1"
+tail -c +"$(($2 + 1))" <golden.raw | head -c "$5" >"$4"
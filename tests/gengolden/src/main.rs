@@ -3,7 +3,7 @@ extern crate serde_derive;
 extern crate byteorder;
 extern crate toml;
 
-use byteorder::{BigEndian, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use std::env;
 use std::fs;
 use std::path::Path;
@@ -24,44 +24,100 @@ struct Testsuite {
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("usage: gengolden <TESTNAME.TOML>");
-        exit(1);
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.split_first() {
+        Some((flag, suites)) if flag == "--regen" => regen(suites),
+        Some((tomlname, [])) => generate(Path::new(tomlname)),
+        _ => {
+            println!("usage: gengolden <TESTNAME.TOML>");
+            println!("       gengolden --regen [TESTNAME.TOML...]");
+            exit(1);
+        }
     }
-    let tomlname = Path::new(&args[1]);
+}
 
+fn load(tomlname: &Path) -> Testsuite {
     let tomlsrc = fs::read_to_string(tomlname).expect("TOML file not found");
-    let t: Testsuite = toml::from_str(&tomlsrc).unwrap();
+    toml::from_str(&tomlsrc).unwrap()
+}
 
-    // Calculate input and output size
-    let mut input_size: u32 = 0;
-    let mut output_size: u32 = 0;
-    for d in &t.input_desc {
+// Calculate the size of the inputs or outputs of a test vector
+fn desc_size(desc: &[String]) -> u32 {
+    let mut size: u32 = 0;
+    for d in desc {
         if d.starts_with("v128:") {
-            input_size += 16;
+            size += 16;
         } else if d.starts_with("u32:") {
-            input_size += 4;
+            size += 4;
         } else {
-            panic!(format!("invalid desc string: {}", *d));
+            panic!("invalid desc string: {}", *d);
         }
     }
-    for d in &t.output_desc {
-        if d.starts_with("v128:") {
-            output_size += 16;
-        } else if d.starts_with("u32:") {
-            output_size += 4;
-        } else {
-            panic!(format!("invalid desc string: {}", *d));
+    size
+}
+
+// Check whether the RSP binary and the golden results of the suite are
+// missing or stale, and return the reason. Modification times are not
+// compared, as they are not preserved by checkouts: the golden results are
+// stale if their size does not match the test vectors.
+fn check_stale(tomlname: &Path, t: &Testsuite) -> Option<String> {
+    let rspname = tomlname.with_extension("rsp");
+    if !rspname.exists() {
+        return Some(format!("{} missing", rspname.display()));
+    }
+    let goldenname = tomlname.with_extension("golden");
+    let expected = desc_size(&t.output_desc) as u64 * t.test.len() as u64;
+    match fs::metadata(&goldenname) {
+        Err(_) => Some(format!("{} missing", goldenname.display())),
+        Ok(md) if md.len() != expected => Some(format!(
+            "{} is stale ({} bytes, expected {})",
+            goldenname.display(),
+            md.len(),
+            expected
+        )),
+        Ok(_) => None,
+    }
+}
+
+// Regenerate the suites (or all the suites in the current directory) whose
+// RSP binary or golden results are missing or stale.
+fn regen(suites: &[String]) {
+    let mut suites: Vec<_> = suites.iter().map(|s| Path::new(s).to_path_buf()).collect();
+    if suites.is_empty() {
+        for entry in fs::read_dir(".").expect("cannot read current directory") {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "toml")
+                && path != Path::new("./Cargo.toml")
+            {
+                suites.push(path);
+            }
+        }
+        suites.sort();
+    }
+    for tomlname in &suites {
+        match check_stale(tomlname, &load(tomlname)) {
+            Some(reason) => {
+                println!("Regenerating {}: {}", tomlname.display(), reason);
+                generate(tomlname);
+            }
+            None => println!("Up to date: {}", tomlname.display()),
         }
     }
-    if input_size % 8 != 0 {
+}
+
+fn generate(tomlname: &Path) {
+    let t = load(tomlname);
+
+    // Calculate input and output size
+    let input_size = desc_size(&t.input_desc);
+    let output_size = desc_size(&t.output_desc);
+    if !input_size.is_multiple_of(8) {
         panic!(
             "input size must be multiple of 8 bytes (found: {})",
             input_size
         );
     }
-    if output_size % 8 != 0 {
+    if !output_size.is_multiple_of(8) {
         panic!(
             "output size must be multiple of 8 bytes (found: {})",
             output_size
@@ -84,7 +140,7 @@ fn main() {
 
         fs::write("rsp.asm", prefix + &t.rsp_code).expect("cannot write RSP.ASM file");
         let status = Command::new("bass")
-            .args(&["-o", "rsp.bin", "rsp.asm"])
+            .args(["-o", "rsp.bin", "rsp.asm"])
             .status()
             .expect("failed to execute bass");
         if !status.success() {
@@ -104,12 +160,12 @@ fn main() {
 
         for tv in &t.test {
             if tv.input.len() * 4 != input_size as usize {
-                panic!(format!(
+                panic!(
                     "test {} has invalid number of inputs ({} vs {})",
                     &tv.name,
                     tv.input.len() * 4,
                     input_size
-                ));
+                );
             }
 
             for v in &tv.input {
@@ -122,12 +178,15 @@ fn main() {
     {
         let goldenname = tomlname.with_extension("golden");
         let total_output_size = output_size as usize * t.test.len();
-        let memsize = ((total_output_size + 4095) / 4096) * 4096;
+        let timings_size = (t.test.len() + 1) * 4;
+        let memsize = (total_output_size + timings_size).div_ceil(4096) * 4096;
         let status = Command::new("./run.sh")
-            .args(&[
+            .args([
                 goldenname.to_str().unwrap(),
                 &total_output_size.to_string(),
                 &memsize.to_string(),
+                "timings.bin",
+                &timings_size.to_string(),
             ])
             .status()
             .expect("failed to execute run.sh");
@@ -136,13 +195,33 @@ fn main() {
         }
     }
 
+    // Write the RSP cycles of each test vector. The first timing is the
+    // latency of starting the RSP and seeing it halted (with just a break),
+    // which is subtracted from the others.
+    {
+        let timings = fs::read("timings.bin").expect("cannot read timings.bin");
+        let mut timings = timings.chunks_exact(4).map(BigEndian::read_u32);
+        let latency = timings.next().expect("timings missing");
+        let mut cycles = String::from(
+            "# RSP cycles of each test vector (up to the final break, excluded),\n\
+             # measured on hardware by gengolden. A value can also be changed into\n\
+             # a range, eg: [10, 12].\n",
+        );
+        for (tv, timing) in t.test.iter().zip(timings) {
+            cycles += &format!("{:?} = {}\n", tv.name, timing.saturating_sub(latency));
+        }
+        fs::write(tomlname.with_extension("cycles"), cycles).expect("cannot write cycles file");
+    }
+
     // Cleanup
     fs::rename("rsp.bin", tomlname.with_extension("rsp")).unwrap();
     fs::remove_file("input.bin").unwrap();
+    fs::remove_file("timings.bin").unwrap();
 
     println!(
-        "Generated: {}, {}",
+        "Generated: {}, {}, {}",
         tomlname.with_extension("rsp").display(),
-        tomlname.with_extension("golden").display()
+        tomlname.with_extension("golden").display(),
+        tomlname.with_extension("cycles").display()
     );
 }
@@ -13,14 +13,16 @@ use emu::snd::{OwnedSndBuffer, S16_STEREO};
 use failure::Error;
 use image::png::PNGEncoder;
 use image::{ColorType, Pixel, RgbaImage};
-use r64emu::N64;
+use r64emu::N64Builder;
 use slog::Discard;
 use std::env;
 use std::fs;
 use std::io;
 use std::path::Path;
 
-static KROM_PATH: &'static str = "roms/tests";
+mod report;
+
+static KROM_PATH: &str = "roms/tests";
 
 const FPS10: u32 = 0x10;
 const RES_320: u32 = 0x20;
@@ -38,8 +40,11 @@ fn test_krom(romfn: &str, flags: u32) -> Result<(), Error> {
     };
 
     // Create N64 object and emulate 5 frames
-    let mut n64 = N64::new(logger, Path::new(romfn), Path::new("bios/pifdata.bin")).unwrap();
-    n64.setup_cic(true).unwrap();
+    let mut n64 = N64Builder::new(logger)
+        .rom(Path::new(romfn))
+        .bios(Path::new("bios/pifdata.bin"))
+        .build()
+        .unwrap();
     let mut screen1 = OwnedGfxBufferLE::<Rgb888>::new(640, 480);
     let mut sound1 = OwnedSndBuffer::<S16_STEREO>::with_capacity(512);
 
@@ -61,17 +66,14 @@ fn test_krom(romfn: &str, flags: u32) -> Result<(), Error> {
     // Insert artifacts as present in krom's reference files
     // Line 40 and 120 sometimes are duplicated
     let mut screen = OwnedGfxBufferLE::<Rgb888>::new(resw, resh);
-    let mut y1 = 0;
     for y in 0..resh {
         let mut screen1_buf = screen1.buf_mut();
-        let src = screen1_buf.line(y1 * scale);
+        let src = screen1_buf.line(y * scale);
         let mut screen_buf = screen.buf_mut();
         let mut dst = screen_buf.line(y);
         for x in 0..resw {
             dst.set(x, src.get(x * scale));
         }
-
-        y1 += 1;
     }
 
     // Read expected output
@@ -101,12 +103,21 @@ fn test_krom(romfn: &str, flags: u32) -> Result<(), Error> {
                 rmsd += (diff as f32).sqrt();
             }
         }
-        let rmsd = ((rmsd as f32) / ((resw * resh) as f32)).sqrt();
+        let rmsd = (rmsd / ((resw * resh) as f32)).sqrt();
         let threshold = if flags & APPROX != 0 { 5.0 } else { 0.0 };
+        let stem = Path::new(romfn).file_stem().unwrap().to_string_lossy();
+        let mut results = report::Suite::new(&format!("krom.{}", stem));
         if rmsd > threshold {
             success = false;
             println!("Difference (RMSD): {}", rmsd);
+            results.fail(
+                romfn,
+                format!("Difference (RMSD): {} (threshold: {})", rmsd, threshold),
+            );
+        } else {
+            results.pass(romfn);
         }
+        results.write();
     }
 
     // Dump produced image
@@ -115,7 +126,7 @@ fn test_krom(romfn: &str, flags: u32) -> Result<(), Error> {
         let mut screen2_buf = screen2.buf_mut();
         let (raw, _pitch) = screen2_buf.raw();
         let mut pngout = io::Cursor::new(Vec::new());
-        PNGEncoder::new(&mut pngout).encode(&raw, resw as u32, resh as u32, ColorType::RGBA(8))?;
+        PNGEncoder::new(&mut pngout).encode(raw, resw as u32, resh as u32, ColorType::RGBA(8))?;
         let pngout = pngout.into_inner();
         fs::write("failed-test.png", &pngout)?;
 
@@ -123,8 +134,8 @@ fn test_krom(romfn: &str, flags: u32) -> Result<(), Error> {
             .filter(|s| s == "iTerm.app")
             .is_some()
         {
-            print!(
-                "\x1b]1337;File=width=80%;inline=1:{}\x07\n",
+            println!(
+                "\x1b]1337;File=width=80%;inline=1:{}\x07",
                 base64::encode(&pngout)
             );
         }
@@ -146,8 +157,8 @@ fn test_krom(romfn: &str, flags: u32) -> Result<(), Error> {
             .is_some()
         {
             //print!("\x08\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A\x1b[1A"); // put images side by side
-            print!(
-                "\x1b]1337;File=width=80%;inline=1:{}\x07\n",
+            println!(
+                "\x1b]1337;File=width=80%;inline=1:{}\x07",
                 base64::encode(&pngout)
             );
             println!();
@@ -196,6 +207,7 @@ macro_rules! krom_video {
         krom!($test_name, concat!("Video/", $romfn), $flags);
     };
 }
+#[allow(unused_macros)]
 macro_rules! krom_rdp {
     ($test_name:ident, $romfn:expr, $flags:expr) => {
         krom!($test_name, concat!("RDP/", $romfn), $flags);
@@ -0,0 +1,82 @@
+extern crate byteorder;
+extern crate r64emu;
+
+use byteorder::{BigEndian, ByteOrder};
+use r64emu::mempak::{data_crc, Mempak};
+
+// Build a .note file with the specified name (in N64 font encoding) and
+// number of pages.
+fn note(name: &[u8], pages: usize) -> Vec<u8> {
+    let mut data = vec![0u8; 32 + pages * 256];
+    data[..4].copy_from_slice(b"NSME");
+    data[4..6].copy_from_slice(b"01");
+    data[16..16 + name.len()].copy_from_slice(name);
+    for (i, b) in data[32..].iter_mut().enumerate() {
+        *b = (i / 256) as u8 + 1;
+    }
+    data
+}
+
+#[test]
+fn format() {
+    let pak = Mempak::new();
+    assert_eq!(pak.notes(), vec![]);
+    assert_eq!(pak.free_pages(), 123);
+    // Index table checksum, and its backup copy
+    assert_eq!(pak.data()[0x101], 0x71);
+    assert_eq!(&pak.data()[0x100..0x200], &pak.data()[0x200..0x300]);
+}
+
+#[test]
+fn format_id() {
+    // ID block, as decoded by libultra (__OSPackId), and its backups
+    let pak = Mempak::new();
+    let id = &pak.data()[0x20..0x40];
+    assert_eq!(BigEndian::read_u16(&id[0x18..]), 0x0001); // deviceid
+    assert_eq!(id[0x1A], 1); // banks
+    assert_eq!(id[0x1B], 0); // version
+    let sum = id[..0x1C]
+        .chunks(2)
+        .fold(0u16, |s, w| s.wrapping_add(BigEndian::read_u16(w)));
+    assert_eq!(sum, 0x0101);
+    assert_eq!(BigEndian::read_u16(&id[0x1C..]), sum);
+    assert_eq!(BigEndian::read_u16(&id[0x1E..]), 0xFFF2 - sum);
+    for off in [0x60, 0x80, 0xC0].iter() {
+        assert_eq!(&pak.data()[*off..*off + 32], id);
+    }
+}
+
+#[test]
+fn notes() {
+    let mut pak = Mempak::new();
+    // "MARIO 64"
+    let mario = note(&[0x26, 0x1A, 0x2B, 0x22, 0x28, 0x0F, 0x16, 0x14], 3);
+    pak.import_note(&mario).unwrap();
+    pak.import_note(&note(&[0x1B], 120)).unwrap();
+    assert!(pak.import_note(&note(&[0x1C], 1)).is_err());
+
+    let notes = pak.notes();
+    assert_eq!(notes.len(), 2);
+    assert_eq!(notes[0].name, "MARIO 64");
+    assert_eq!(notes[0].game_code, 0x4E53_4D45);
+    assert_eq!(notes[0].pages, 3);
+    assert_eq!(notes[1].pages, 120);
+    assert_eq!(pak.free_pages(), 0);
+
+    // Deleting a note frees its pages, which can be reused
+    pak.delete_note(notes[1].index);
+    assert_eq!(pak.free_pages(), 120);
+    pak.import_note(&note(&[0x1C], 4)).unwrap();
+    assert_eq!(pak.notes()[1].name, "C");
+
+    // Export gives back the imported file (with the start page filled in)
+    let exported = pak.export_note(notes[0].index);
+    assert_eq!(&exported[32..], &mario[32..]);
+    assert_eq!(&exported[16..32], &mario[16..32]);
+}
+
+#[test]
+fn crc() {
+    assert_eq!(data_crc(&[0u8; 32]), 0x00);
+    assert_eq!(data_crc(&[0xFFu8; 32]), 0x0A);
+}
@@ -0,0 +1,547 @@
+//! Reference interpreter of the MIPS III integer instruction set.
+//!
+//! It is used as an oracle by the randomized tests of the CPU core (see
+//! `tests/cpu_oracle.rs`), so it is written to be obviously correct rather
+//! than fast: each instruction is decoded from scratch and implemented as
+//! literally as possible from the architecture manual, and loads and stores
+//! go byte by byte through a small memory window.
+//!
+//! Instructions whose result is not defined by the architecture (eg: 32-bit
+//! operations on registers that do not hold sign-extended words, or a branch
+//! in a delay slot), and instructions that would raise an exception, are
+//! rejected with an error and leave the state unchanged. This way, the tests
+//! only compare what the architecture specifies, and can generate random
+//! programs by trying random instructions.
+
+use std::result;
+
+pub type Result<T> = result::Result<T, String>;
+
+/// The architectural state visible to the program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct State {
+    pub regs: [u64; 32],
+    pub hi: u64,
+    pub lo: u64,
+    /// Address of the next instruction to execute.
+    pub pc: u64,
+    /// Address of the instruction following it (the branch target, when it
+    /// is in a delay slot).
+    pub npc: u64,
+}
+
+impl State {
+    /// Create a state with the specified registers, starting at pc.
+    pub fn new(regs: [u64; 32], hi: u64, lo: u64, pc: u64) -> State {
+        State {
+            regs,
+            hi,
+            lo,
+            pc,
+            npc: pc.wrapping_add(4),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Oracle {
+    pub state: State,
+    /// Virtual address of the first byte of mem.
+    pub mem_base: u64,
+    /// The only memory accessible by loads and stores.
+    pub mem: Vec<u8>,
+    delay_slot: bool,
+}
+
+fn sx32(v: u32) -> u64 {
+    v as i32 as i64 as u64
+}
+
+// Check that a register holds a sign-extended word, which is required by
+// all the 32-bit operations.
+fn word(v: u64) -> Result<i32> {
+    if sx32(v as u32) == v {
+        Ok(v as i32)
+    } else {
+        Err(format!("operand {:#018x} is not a sign-extended word", v))
+    }
+}
+
+fn byte(v: u64, n: u64) -> u8 {
+    (v >> (n * 8)) as u8
+}
+
+fn set_byte(v: u64, n: u64, b: u8) -> u64 {
+    (v & !(0xFF << (n * 8))) | (b as u64) << (n * 8)
+}
+
+impl Oracle {
+    pub fn new(state: State, mem_base: u64, mem: Vec<u8>) -> Oracle {
+        Oracle {
+            state,
+            mem_base,
+            mem,
+            delay_slot: false,
+        }
+    }
+
+    /// Whether the next instruction is in the delay slot of a branch.
+    pub fn in_delay_slot(&self) -> bool {
+        self.delay_slot
+    }
+
+    /// Execute the instruction at the current PC (the oracle does not fetch
+    /// code, so the caller provides it). On error, the state is unchanged.
+    pub fn step(&mut self, insn: u32) -> Result<()> {
+        let mut next = self.clone();
+        next.exec(insn)?;
+        *self = next;
+        Ok(())
+    }
+
+    fn set(&mut self, reg: usize, val: u64) {
+        if reg != 0 {
+            self.state.regs[reg] = val;
+        }
+    }
+
+    // Return the offset into mem of an access of size bytes at addr.
+    fn offset(&self, addr: u64, size: u64) -> Result<usize> {
+        let off = addr.wrapping_sub(self.mem_base);
+        if off >= self.mem.len() as u64 || off + size > self.mem.len() as u64 {
+            return Err(format!("address {:#018x} outside of memory", addr));
+        }
+        Ok(off as usize)
+    }
+
+    fn load(&self, addr: u64, size: u64) -> Result<u64> {
+        if !addr.is_multiple_of(size) {
+            return Err(format!("unaligned load at {:#018x}", addr));
+        }
+        let off = self.offset(addr, size)?;
+        let mut val = 0u64;
+        for i in 0..size as usize {
+            val = val << 8 | self.mem[off + i] as u64;
+        }
+        Ok(val)
+    }
+
+    fn store(&mut self, addr: u64, size: u64, val: u64) -> Result<()> {
+        if !addr.is_multiple_of(size) {
+            return Err(format!("unaligned store at {:#018x}", addr));
+        }
+        let off = self.offset(addr, size)?;
+        for i in 0..size {
+            self.mem[off + i as usize] = byte(val, size - 1 - i);
+        }
+        Ok(())
+    }
+
+    // Load the bytes from addr up to the end of the aligned word (or
+    // doubleword) of the specified size into the most significant bytes of
+    // reg (LWL, LDL).
+    fn load_left(&self, addr: u64, size: u64, mut reg: u64) -> Result<u64> {
+        let n = size - addr % size;
+        let off = self.offset(addr, n)?;
+        for i in 0..n {
+            reg = set_byte(reg, size - 1 - i, self.mem[off + i as usize]);
+        }
+        Ok(reg)
+    }
+
+    // Load the bytes from the beginning of the aligned word (or doubleword)
+    // up to addr into the least significant bytes of reg (LWR, LDR).
+    fn load_right(&self, addr: u64, size: u64, mut reg: u64) -> Result<u64> {
+        let n = addr % size + 1;
+        let off = self.offset(addr - (n - 1), n)?;
+        for i in 0..n {
+            reg = set_byte(reg, i, self.mem[off + (n - 1 - i) as usize]);
+        }
+        Ok(reg)
+    }
+
+    // Store the most significant bytes of reg from addr up to the end of
+    // the aligned word (or doubleword) (SWL, SDL).
+    fn store_left(&mut self, addr: u64, size: u64, reg: u64) -> Result<()> {
+        let n = size - addr % size;
+        let off = self.offset(addr, n)?;
+        for i in 0..n {
+            self.mem[off + i as usize] = byte(reg, size - 1 - i);
+        }
+        Ok(())
+    }
+
+    // Store the least significant bytes of reg from the beginning of the
+    // aligned word (or doubleword) up to addr (SWR, SDR).
+    fn store_right(&mut self, addr: u64, size: u64, reg: u64) -> Result<()> {
+        let n = addr % size + 1;
+        let off = self.offset(addr - (n - 1), n)?;
+        for i in 0..n {
+            self.mem[off + (n - 1 - i) as usize] = byte(reg, i);
+        }
+        Ok(())
+    }
+
+    fn trap(cond: bool) -> Result<()> {
+        if cond {
+            Err("trap".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn exec(&mut self, insn: u32) -> Result<()> {
+        let op = insn >> 26;
+        let rs = (insn >> 21 & 31) as usize;
+        let rt = (insn >> 16 & 31) as usize;
+        let rd = (insn >> 11 & 31) as usize;
+        let sa = insn >> 6 & 31;
+        let imm = insn as u16 as u64;
+        let simm = insn as u16 as i16 as i64;
+        let s = self.state.regs[rs];
+        let t = self.state.regs[rt];
+
+        // Advance to the next instruction; branches change npc below.
+        let pc = self.state.pc;
+        let in_delay_slot = self.delay_slot;
+        self.delay_slot = false;
+        self.state.pc = self.state.npc;
+        self.state.npc = self.state.npc.wrapping_add(4);
+
+        // A branch: link (if requested) and jump to tgt if cond holds. Likely
+        // branches skip the delay slot when not taken.
+        let branch = |o: &mut Oracle, cond: bool, tgt: u64, link: bool, likely: bool| {
+            if in_delay_slot {
+                return Err("branch in a delay slot".to_owned());
+            }
+            if link {
+                if rs == 31 {
+                    return Err("branch and link reading the link register".to_owned());
+                }
+                o.state.regs[31] = pc.wrapping_add(8);
+            }
+            if cond {
+                o.state.npc = tgt;
+                o.delay_slot = true;
+            } else if likely {
+                o.state.pc = o.state.npc;
+                o.state.npc = o.state.npc.wrapping_add(4);
+            } else {
+                o.delay_slot = true;
+            }
+            Ok(())
+        };
+        let btgt = pc.wrapping_add(4).wrapping_add((simm << 2) as u64);
+        let ea = s.wrapping_add(simm as u64);
+
+        match op {
+            0x00 => match insn & 0x3F {
+                0x00 => self.set(rd, sx32((word(t)? as u32) << sa)), // SLL
+                0x02 => self.set(rd, sx32((word(t)? as u32) >> sa)), // SRL
+                0x03 => self.set(rd, sx32((word(t)? >> sa) as u32)), // SRA
+                0x04 => self.set(rd, sx32((word(t)? as u32) << (s & 31))), // SLLV
+                0x06 => self.set(rd, sx32((word(t)? as u32) >> (s & 31))), // SRLV
+                0x07 => self.set(rd, sx32((word(t)? >> (s & 31)) as u32)), // SRAV
+                0x0F => {}                                           // SYNC
+                0x10 => self.set(rd, self.state.hi),                 // MFHI
+                0x11 => self.state.hi = s,                           // MTHI
+                0x12 => self.set(rd, self.state.lo),                 // MFLO
+                0x13 => self.state.lo = s,                           // MTLO
+                0x14 => self.set(rd, t << (s & 63)),                 // DSLLV
+                0x16 => self.set(rd, t >> (s & 63)),                 // DSRLV
+                0x17 => self.set(rd, ((t as i64) >> (s & 63)) as u64), // DSRAV
+                0x18 => {
+                    // MULT
+                    let p = word(s)? as i64 * word(t)? as i64;
+                    self.state.lo = sx32(p as u32);
+                    self.state.hi = sx32((p >> 32) as u32);
+                }
+                0x19 => {
+                    // MULTU
+                    let p = word(s)? as u32 as u64 * word(t)? as u32 as u64;
+                    self.state.lo = sx32(p as u32);
+                    self.state.hi = sx32((p >> 32) as u32);
+                }
+                0x1A => {
+                    // DIV
+                    let (a, b) = (word(s)?, word(t)?);
+                    if b == 0 || (a == i32::MIN && b == -1) {
+                        return Err("undefined division".into());
+                    }
+                    self.state.lo = sx32((a / b) as u32);
+                    self.state.hi = sx32((a % b) as u32);
+                }
+                0x1B => {
+                    // DIVU
+                    let (a, b) = (word(s)? as u32, word(t)? as u32);
+                    if b == 0 {
+                        return Err("undefined division".into());
+                    }
+                    self.state.lo = sx32(a / b);
+                    self.state.hi = sx32(a % b);
+                }
+                0x1C => {
+                    // DMULT
+                    let p = s as i64 as i128 * t as i64 as i128;
+                    self.state.lo = p as u64;
+                    self.state.hi = (p >> 64) as u64;
+                }
+                0x1D => {
+                    // DMULTU
+                    let p = s as u128 * t as u128;
+                    self.state.lo = p as u64;
+                    self.state.hi = (p >> 64) as u64;
+                }
+                0x1E => {
+                    // DDIV
+                    let (a, b) = (s as i64, t as i64);
+                    if b == 0 || (a == i64::MIN && b == -1) {
+                        return Err("undefined division".into());
+                    }
+                    self.state.lo = (a / b) as u64;
+                    self.state.hi = (a % b) as u64;
+                }
+                0x1F => {
+                    // DDIVU
+                    if t == 0 {
+                        return Err("undefined division".into());
+                    }
+                    self.state.lo = s / t;
+                    self.state.hi = s % t;
+                }
+                0x20 => {
+                    // ADD
+                    let r = word(s)?.checked_add(word(t)?).ok_or("overflow")?;
+                    self.set(rd, sx32(r as u32));
+                }
+                0x21 => self.set(rd, sx32(word(s)?.wrapping_add(word(t)?) as u32)), // ADDU
+                0x22 => {
+                    // SUB
+                    let r = word(s)?.checked_sub(word(t)?).ok_or("overflow")?;
+                    self.set(rd, sx32(r as u32));
+                }
+                0x23 => self.set(rd, sx32(word(s)?.wrapping_sub(word(t)?) as u32)), // SUBU
+                0x24 => self.set(rd, s & t),                                        // AND
+                0x25 => self.set(rd, s | t),                                        // OR
+                0x26 => self.set(rd, s ^ t),                                        // XOR
+                0x27 => self.set(rd, !(s | t)),                                     // NOR
+                0x2A => self.set(rd, ((s as i64) < (t as i64)) as u64),             // SLT
+                0x2B => self.set(rd, (s < t) as u64),                               // SLTU
+                0x2C => {
+                    // DADD
+                    let r = (s as i64).checked_add(t as i64).ok_or("overflow")?;
+                    self.set(rd, r as u64);
+                }
+                0x2D => self.set(rd, s.wrapping_add(t)), // DADDU
+                0x2E => {
+                    // DSUB
+                    let r = (s as i64).checked_sub(t as i64).ok_or("overflow")?;
+                    self.set(rd, r as u64);
+                }
+                0x2F => self.set(rd, s.wrapping_sub(t)), // DSUBU
+                0x30 => Oracle::trap(s as i64 >= t as i64)?, // TGE
+                0x31 => Oracle::trap(s >= t)?,           // TGEU
+                0x32 => Oracle::trap((s as i64) < t as i64)?, // TLT
+                0x33 => Oracle::trap(s < t)?,            // TLTU
+                0x34 => Oracle::trap(s == t)?,           // TEQ
+                0x36 => Oracle::trap(s != t)?,           // TNE
+                0x38 => self.set(rd, t << sa),           // DSLL
+                0x3A => self.set(rd, t >> sa),           // DSRL
+                0x3B => self.set(rd, ((t as i64) >> sa) as u64), // DSRA
+                0x3C => self.set(rd, t << (sa + 32)),    // DSLL32
+                0x3E => self.set(rd, t >> (sa + 32)),    // DSRL32
+                0x3F => self.set(rd, ((t as i64) >> (sa + 32)) as u64), // DSRA32
+                f => return Err(format!("unsupported special opcode {:#x}", f)),
+            },
+            0x01 => {
+                let neg = (s as i64) < 0;
+                let (us, usimm) = (s, simm as u64);
+                match rt {
+                    0x00 => branch(self, neg, btgt, false, false)?, // BLTZ
+                    0x01 => branch(self, !neg, btgt, false, false)?, // BGEZ
+                    0x02 => branch(self, neg, btgt, false, true)?,  // BLTZL
+                    0x03 => branch(self, !neg, btgt, false, true)?, // BGEZL
+                    0x08 => Oracle::trap(s as i64 >= simm)?,        // TGEI
+                    0x09 => Oracle::trap(us >= usimm)?,             // TGEIU
+                    0x0A => Oracle::trap((s as i64) < simm)?,       // TLTI
+                    0x0B => Oracle::trap(us < usimm)?,              // TLTIU
+                    0x0C => Oracle::trap(s as i64 == simm)?,        // TEQI
+                    0x0E => Oracle::trap(s as i64 != simm)?,        // TNEI
+                    0x10 => branch(self, neg, btgt, true, false)?,  // BLTZAL
+                    0x11 => branch(self, !neg, btgt, true, false)?, // BGEZAL
+                    0x12 => branch(self, neg, btgt, true, true)?,   // BLTZALL
+                    0x13 => branch(self, !neg, btgt, true, true)?,  // BGEZALL
+                    f => return Err(format!("unsupported regimm opcode {:#x}", f)),
+                }
+            }
+            0x02 | 0x03 => {
+                // J, JAL
+                let tgt = (pc.wrapping_add(4) & !0x0FFF_FFFF) | ((insn & 0x03FF_FFFF) as u64) << 2;
+                if in_delay_slot {
+                    return Err("branch in a delay slot".into());
+                }
+                if op == 0x03 {
+                    self.state.regs[31] = pc.wrapping_add(8);
+                }
+                self.state.npc = tgt;
+                self.delay_slot = true;
+            }
+            0x04 => branch(self, s == t, btgt, false, false)?, // BEQ
+            0x05 => branch(self, s != t, btgt, false, false)?, // BNE
+            0x06 => branch(self, s as i64 <= 0, btgt, false, false)?, // BLEZ
+            0x07 => branch(self, s as i64 > 0, btgt, false, false)?, // BGTZ
+            0x08 => {
+                // ADDI
+                let r = word(s)?.checked_add(simm as i32).ok_or("overflow")?;
+                self.set(rt, sx32(r as u32));
+            }
+            0x09 => self.set(rt, sx32(word(s)?.wrapping_add(simm as i32) as u32)), // ADDIU
+            0x0A => self.set(rt, ((s as i64) < simm) as u64),                      // SLTI
+            0x0B => self.set(rt, (s < simm as u64) as u64),                        // SLTIU
+            0x0C => self.set(rt, s & imm),                                         // ANDI
+            0x0D => self.set(rt, s | imm),                                         // ORI
+            0x0E => self.set(rt, s ^ imm),                                         // XORI
+            0x0F => self.set(rt, sx32((imm as u32) << 16)),                        // LUI
+            0x14 => branch(self, s == t, btgt, false, true)?,                      // BEQL
+            0x15 => branch(self, s != t, btgt, false, true)?,                      // BNEL
+            0x16 => branch(self, s as i64 <= 0, btgt, false, true)?,               // BLEZL
+            0x17 => branch(self, s as i64 > 0, btgt, false, true)?,                // BGTZL
+            0x18 => {
+                // DADDI
+                let r = (s as i64).checked_add(simm).ok_or("overflow")?;
+                self.set(rt, r as u64);
+            }
+            0x19 => self.set(rt, s.wrapping_add(simm as u64)), // DADDIU
+            0x1A => {
+                let v = self.load_left(ea, 8, t)?; // LDL
+                self.set(rt, v);
+            }
+            0x1B => {
+                let v = self.load_right(ea, 8, t)?; // LDR
+                self.set(rt, v);
+            }
+            0x20 => {
+                let v = self.load(ea, 1)?; // LB
+                self.set(rt, v as u8 as i8 as i64 as u64);
+            }
+            0x21 => {
+                let v = self.load(ea, 2)?; // LH
+                self.set(rt, v as u16 as i16 as i64 as u64);
+            }
+            0x22 => {
+                let v = self.load_left(ea, 4, t)?; // LWL
+                self.set(rt, sx32(v as u32));
+            }
+            0x23 => {
+                let v = self.load(ea, 4)?; // LW
+                self.set(rt, sx32(v as u32));
+            }
+            0x24 => {
+                let v = self.load(ea, 1)?; // LBU
+                self.set(rt, v);
+            }
+            0x25 => {
+                let v = self.load(ea, 2)?; // LHU
+                self.set(rt, v);
+            }
+            0x26 => {
+                // LWR: the upper half of the register is implementation
+                // dependent, unless the whole word is loaded.
+                if ea % 4 != 3 {
+                    return Err("partial LWR".into());
+                }
+                let v = self.load_right(ea, 4, t)?;
+                self.set(rt, sx32(v as u32));
+            }
+            0x27 => {
+                let v = self.load(ea, 4)?; // LWU
+                self.set(rt, v);
+            }
+            0x28 => self.store(ea, 1, t)?,       // SB
+            0x29 => self.store(ea, 2, t)?,       // SH
+            0x2A => self.store_left(ea, 4, t)?,  // SWL
+            0x2B => self.store(ea, 4, t)?,       // SW
+            0x2C => self.store_left(ea, 8, t)?,  // SDL
+            0x2D => self.store_right(ea, 8, t)?, // SDR
+            0x2E => self.store_right(ea, 4, t)?, // SWR
+            0x37 => {
+                let v = self.load(ea, 8)?; // LD
+                self.set(rt, v);
+            }
+            0x3F => self.store(ea, 8, t)?, // SD
+            op => return Err(format!("unsupported opcode {:#x}", op)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u64 = 0xFFFF_FFFF_8000_0100;
+
+    fn oracle() -> Oracle {
+        let mut regs = [0u64; 32];
+        regs[1] = BASE;
+        regs[2] = 0x1122_3344_5566_7788;
+        let mem = (0..16).map(|i| 0xA0 + i as u8).collect();
+        Oracle::new(State::new(regs, 0, 0, 0xFFFF_FFFF_8000_1000), BASE, mem)
+    }
+
+    fn itype(op: u32, rs: u32, rt: u32, imm: u16) -> u32 {
+        op << 26 | rs << 21 | rt << 16 | imm as u32
+    }
+
+    #[test]
+    fn unaligned() {
+        let mut o = oracle();
+        o.step(itype(0x1A, 1, 3, 3)).unwrap(); // LDL r3, 3(r1)
+        assert_eq!(o.state.regs[3], 0xA3A4_A5A6_A700_0000);
+        o.state.regs[3] = 0x1122_3344_5566_7788;
+        o.step(itype(0x1B, 1, 3, 2)).unwrap(); // LDR r3, 2(r1)
+        assert_eq!(o.state.regs[3], 0x1122_3344_55A0_A1A2);
+        o.step(itype(0x22, 1, 4, 1)).unwrap(); // LWL r4, 1(r1)
+        assert_eq!(o.state.regs[4], 0xFFFF_FFFF_A1A2_A300);
+        assert!(o.step(itype(0x26, 1, 4, 1)).is_err()); // partial LWR
+        o.step(itype(0x2A, 1, 2, 6)).unwrap(); // SWL r2, 6(r1)
+        assert_eq!(o.mem[4..8], [0xA4, 0xA5, 0x55, 0x66]);
+        o.step(itype(0x2D, 1, 2, 9)).unwrap(); // SDR r2, 9(r1)
+        assert_eq!(o.mem[8..11], [0x77, 0x88, 0xAA]);
+        assert!(o.step(itype(0x23, 1, 4, 2)).is_err()); // unaligned LW
+        assert!(o.step(itype(0x23, 1, 4, 16)).is_err()); // out of memory
+    }
+
+    #[test]
+    fn branches() {
+        let pc = 0xFFFF_FFFF_8000_1000;
+
+        // Not taken likely branch: skip the delay slot.
+        let mut o = oracle();
+        o.step(itype(0x15, 0, 0, 4)).unwrap(); // BNEL r0, r0
+        assert_eq!((o.state.pc, o.state.npc), (pc + 8, pc + 12));
+        assert!(!o.in_delay_slot());
+
+        // Taken branch and link: execute the delay slot, then the target.
+        let mut o = oracle();
+        o.step(itype(0x01, 0, 0x11, 4)).unwrap(); // BGEZAL r0
+        assert_eq!((o.state.pc, o.state.npc), (pc + 4, pc + 20));
+        assert_eq!(o.state.regs[31], pc + 8);
+        assert!(o.step(itype(0x04, 0, 0, 1)).is_err()); // branch in delay slot
+        o.step(0).unwrap();
+        assert_eq!(o.state.pc, pc + 20);
+    }
+
+    #[test]
+    fn undefined() {
+        let mut o = oracle();
+        let before = o.state.clone();
+        assert!(o.step(2 << 21 | 2 << 16 | 3 << 11 | 0x21).is_err()); // ADDU on a doubleword
+        assert!(o.step(0x1A).is_err()); // DIV by zero
+        assert!(o.step(0x34).is_err()); // TEQ r0, r0
+        assert_eq!(o.state, before);
+        o.step(1 << 11 | 0x25).unwrap(); // OR r1, r0, r0
+        o.step(2 << 16 | 0x25).unwrap(); // OR r0, r0, r2
+        assert_eq!((o.state.regs[0], o.state.regs[1]), (0, 0));
+    }
+}
@@ -0,0 +1,158 @@
+extern crate r64emu;
+
+use r64emu::errors::JoybusError;
+use r64emu::mempak::{data_crc, Mempak};
+use r64emu::pif::{self, CMD_INFO, CMD_READ_PAK, CMD_STATE, CMD_WRITE_PAK, PIF_RAM_SIZE};
+
+// Build PIF RAM with the specified commands, terminated by 0xFE.
+fn ram(cmds: &[u8]) -> [u8; PIF_RAM_SIZE] {
+    let mut ram = [0u8; PIF_RAM_SIZE];
+    ram[..cmds.len()].copy_from_slice(cmds);
+    ram[cmds.len()] = 0xFE;
+    ram
+}
+
+#[test]
+fn execute_channels() {
+    // Channel 0 skipped, padding, command on channel 1, command on
+    // channel 2 (no device), then end.
+    let mut ram = ram(&[
+        0x00, 0xFF, 0xFF, 0x01, 0x04, CMD_STATE, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0x03, CMD_INFO,
+        0xFF, 0xFF, 0xFF,
+    ]);
+    let mut seen = Vec::new();
+    pif::execute(&mut ram, |ch, cmd, out| {
+        seen.push((ch, cmd.to_vec(), out.len()));
+        if ch == 1 {
+            out.copy_from_slice(&[1, 2, 3, 4]);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    })
+    .unwrap();
+
+    assert_eq!(seen, vec![(1, vec![CMD_STATE], 4), (2, vec![CMD_INFO], 3)]);
+    assert_eq!(&ram[3..10], &[0x01, 0x04, CMD_STATE, 1, 2, 3, 4]);
+    // No device on channel 2
+    assert_eq!(ram[11], 0x83);
+    // Nothing after the end marker
+    assert_eq!(ram[16], 0xFE);
+}
+
+#[test]
+fn commands_truncated() {
+    // A command whose length byte is the last byte before the control byte
+    let mut buf = [0xFFu8; PIF_RAM_SIZE];
+    buf[0x3E] = 0x01;
+    let cmds: Vec<_> = pif::commands(&buf).collect();
+    assert_eq!(cmds.len(), 1);
+    assert!(cmds[0].is_err());
+
+    let ram = ram(&[0xFF, 0x01, 0x03, CMD_INFO, 0, 0, 0]);
+    let cmds: Vec<_> = pif::commands(&ram).map(Result::unwrap).collect();
+    assert_eq!(
+        cmds,
+        vec![pif::Command {
+            channel: 0,
+            offset: 1,
+            tx: 3..4,
+            rx: 4..7,
+        }]
+    );
+}
+
+#[test]
+fn execute_overflow() {
+    let mut ram = [0u8; PIF_RAM_SIZE];
+    ram[0] = 0x01;
+    ram[1] = 0x3F;
+    assert_eq!(
+        pif::execute(&mut ram, |_, _, _| Ok(true)),
+        Err(JoybusError::Truncated { offset: 0 })
+    );
+}
+
+#[test]
+fn execute_errors() {
+    // TX length 0 on channel 1
+    let mut ram = ram(&[0x00, 0x40, 0x03, 0, 0, 0]);
+    assert_eq!(
+        pif::execute(&mut ram, |_, _, _| Ok(true)),
+        Err(JoybusError::Empty { channel: 1 })
+    );
+
+    let mut out = [0u8; 3];
+    assert_eq!(
+        pif::controller_cmd(&[0x42], &mut out, 0, None),
+        Err(JoybusError::UnknownCommand { cmd: 0x42 })
+    );
+    assert_eq!(
+        pif::controller_cmd(&[CMD_STATE], &mut out, 0, None),
+        Err(JoybusError::InvalidLength {
+            cmd: CMD_STATE,
+            tx: 1,
+            rx: 3
+        })
+    );
+}
+
+#[test]
+fn controller_info_state() {
+    let mut out = [0u8; 3];
+    pif::controller_cmd(&[CMD_INFO], &mut out, 0, None).unwrap();
+    assert_eq!(out, [0x05, 0x00, 0x02]);
+
+    let mut pak = Mempak::new();
+    pif::controller_cmd(&[CMD_INFO], &mut out, 0, Some(&mut pak)).unwrap();
+    assert_eq!(out, [0x05, 0x00, 0x01]);
+
+    let mut out = [0u8; 4];
+    pif::controller_cmd(&[CMD_STATE], &mut out, 0x8000_1F9C, None).unwrap();
+    assert_eq!(out, [0x80, 0x00, 0x1F, 0x9C]);
+}
+
+#[test]
+fn controller_pak() {
+    let mut pak = Mempak::new();
+    let data: Vec<u8> = (0..32).collect();
+
+    // Write at 0x0400 (the low 5 bits are the address checksum)
+    let mut cmd = vec![CMD_WRITE_PAK, 0x04, 0x15];
+    cmd.extend_from_slice(&data);
+    let mut out = [0u8; 1];
+    pif::controller_cmd(&cmd, &mut out, 0, Some(&mut pak)).unwrap();
+    assert_eq!(out[0], data_crc(&data));
+
+    let mut out = [0u8; 33];
+    pif::controller_cmd(&[CMD_READ_PAK, 0x04, 0x15], &mut out, 0, Some(&mut pak)).unwrap();
+    assert_eq!(&out[..32], &data[..]);
+    assert_eq!(out[32], data_crc(&data));
+
+    // Without a pak, the CRC is wrong
+    pif::controller_cmd(&[CMD_READ_PAK, 0x04, 0x15], &mut out, 0, None).unwrap();
+    assert_eq!(&out[..32], &[0u8; 32]);
+    assert_ne!(out[32], data_crc(&[0u8; 32]));
+}
+
+#[test]
+fn cic_challenge() {
+    let mut ram = [0xFFu8; PIF_RAM_SIZE];
+    for (i, b) in ram[0x30..0x3F].iter_mut().enumerate() {
+        *b = 0x10 + i as u8;
+    }
+    ram[0x3F] = pif::CONTROL_CHALLENGE;
+    pif::cic_challenge(&mut ram);
+
+    assert_eq!(&ram[0x2E..0x30], &[0, 0]);
+    assert_eq!(
+        &ram[0x30..0x3F],
+        &[
+            0x04, 0x3C, 0xB9, 0xEB, 0x42, 0xF2, 0xF7, 0x61, 0x66, 0x34, 0x39, 0xE3, 0xC2, 0xFA,
+            0xD9
+        ]
+    );
+    // Only the challenge is modified
+    assert_eq!(ram[0x2D], 0xFF);
+    assert_eq!(ram[0x3F], pif::CONTROL_CHALLENGE);
+}
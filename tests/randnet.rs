@@ -0,0 +1,37 @@
+extern crate emu;
+extern crate r64emu;
+
+use emu::input::{InputEvent, InputManager};
+use r64emu::randnet;
+
+fn press(im: &mut InputManager, key: &str) {
+    im.process_event(InputEvent::Digital(
+        randnet::DEVICE_NAME.into(),
+        key.into(),
+        true,
+    ));
+}
+
+fn read_keys(im: &InputManager) -> [u8; 7] {
+    let mut out = [0xFFu8; 7];
+    randnet::read_keys(im.device(randnet::DEVICE_NAME).unwrap(), &mut out);
+    out
+}
+
+#[test]
+fn read_keys_response() {
+    let mut im = InputManager::new(vec![randnet::input_device()]);
+    assert_eq!(read_keys(&im), [0, 0, 0, 0, 0, 0, 0]);
+
+    press(&mut im, "Q");
+    press(&mut im, "Home");
+    assert_eq!(read_keys(&im), [0x05, 0x02, 0, 0, 0, 0, 0x01]);
+
+    press(&mut im, "W");
+    press(&mut im, "E");
+    assert_eq!(read_keys(&im), [0x05, 0x02, 0x06, 0x02, 0x07, 0x02, 0x01]);
+
+    // More than three keys: none is reported
+    press(&mut im, "R");
+    assert_eq!(read_keys(&im), [0, 0, 0, 0, 0, 0, 0x11]);
+}
@@ -0,0 +1,251 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use emu::sync::Subsystem;
+use r64emu::dp::Dp;
+use r64emu::r4300::R4300;
+
+mod common;
+use common::{make_rcp, read, write};
+
+const DPC_START: u32 = 0x0410_0000;
+const DPC_END: u32 = 0x0410_0004;
+const DPC_STATUS: u32 = 0x0410_000C;
+const SP_DMEM: u32 = 0x0400_0000;
+
+// Layout of RDRAM used by the tests.
+const CMD_ADDR: u32 = 0x1000;
+const FB_ADDR: u32 = 0x10000;
+const TEX_ADDR: u32 = 0x20000;
+const Z_ADDR: u32 = 0x30000;
+const WIDTH: u32 = 16;
+
+fn pixel16(x: u32, y: u32) -> u32 {
+    let addr = FB_ADDR + (y * WIDTH + x) * 2;
+    R4300::get_mut().bus.read::<u16>(addr) as u32
+}
+
+fn pixel32(x: u32, y: u32) -> u32 {
+    read(FB_ADDR + (y * WIDTH + x) * 4)
+}
+
+// Copy the commands at the specified address, and let the RDP run them.
+fn run_commands(addr: u32, cmds: &[u64]) {
+    for (idx, cmd) in cmds.iter().enumerate() {
+        write(addr + idx as u32 * 8, (cmd >> 32) as u32);
+        write(addr + idx as u32 * 8 + 4, *cmd as u32);
+    }
+    write(DPC_START, addr);
+    write(DPC_END, addr + cmds.len() as u32 * 8);
+    run_dp();
+}
+
+fn run_dp() {
+    let dp = Dp::get_mut();
+    let until = dp.cycles() + 10_000;
+    dp.run(until, &Tracer::null()).unwrap();
+}
+
+// Commands setting up a WIDTHxWIDTH color image, with the specified pixel
+// size (2 for 16-bit, 3 for 32-bit).
+fn setup(size: u64, other_modes: u64) -> Vec<u64> {
+    vec![
+        0x3F << 56 | size << 51 | (WIDTH as u64 - 1) << 32 | FB_ADDR as u64,
+        0x2D << 56 | (WIDTH as u64 * 4) << 12 | (WIDTH as u64 * 4),
+        0x2F << 56 | other_modes,
+    ]
+}
+
+// Combine mode (1-cycle) selecting the specified input as the output color
+// and alpha (1 = texel 0, 4 = shade).
+fn combine(input: u64) -> u64 {
+    0x3C << 56 | 15 << 37 | 31 << 32 | 15 << 24 | input << 6 | 7 << 21 | 7 << 18 | 7 << 3 | input
+}
+
+// Edge words of a left-major triangle (op 0x08-0x0F) covering the
+// rectangle x0..x1, y0..y1 (in pixels).
+fn rect_edges(op: u64, x0: u32, y0: u32, x1: u32, y1: u32) -> Vec<u64> {
+    vec![
+        op << 56 | 1 << 55 | ((y1 as u64) << 2) << 32 | ((y1 as u64) << 2) << 16 | (y0 as u64) << 2,
+        (x1 as u64) << 48,
+        (x0 as u64) << 48,
+        (x1 as u64) << 48,
+    ]
+}
+
+// Shade coefficients: a constant color, plus optional increments of red
+// along X and green along Y.
+fn shade_coeffs(color: u32, drdx: u64, dgde: u64) -> Vec<u64> {
+    let c = |shift: u32| (color >> shift) as u64 & 0xFF;
+    vec![
+        c(24) << 48 | c(16) << 32 | c(8) << 16 | c(0),
+        drdx << 48,
+        0,
+        0,
+        dgde << 32,
+        0,
+        0,
+        0,
+    ]
+}
+
+// Z coefficients: a constant depth.
+fn z_coeffs(depth: u32) -> Vec<u64> {
+    vec![((depth as u64) << 13) << 32, 0]
+}
+
+#[test]
+fn fill_triangle() {
+    make_rcp();
+
+    // Fill mode (16-bit); the right edge goes down one pixel per scanline.
+    let mut cmds = setup(2, 3 << 52);
+    cmds.push(0x37 << 56 | 0xF801_F801);
+    cmds.extend(vec![
+        0x08 << 56 | 1 << 55 | (16 << 2) << 32 | (16 << 2) << 16,
+        0,
+        0,
+        0x1_0000,
+    ]);
+    run_commands(CMD_ADDR, &cmds);
+
+    for y in 0..WIDTH {
+        for x in 0..WIDTH {
+            let exp = if x < y { 0xF801 } else { 0 };
+            assert_eq!(pixel16(x, y), exp, "pixel ({}, {})", x, y);
+        }
+    }
+}
+
+#[test]
+fn shaded_triangles() {
+    make_rcp();
+
+    let mut cmds = setup(3, 0);
+    cmds.push(combine(4));
+    cmds.extend(rect_edges(0x0C, 0, 0, 8, 8));
+    cmds.extend(shade_coeffs(0x4080_C0FF, 0, 0));
+
+    // A gradient: red increases along X, and green along Y.
+    cmds.extend(rect_edges(0x0C, 8, 0, 16, 8));
+    cmds.extend(shade_coeffs(0x0000_00FF, 16, 16));
+    run_commands(CMD_ADDR, &cmds);
+
+    assert_eq!(pixel32(0, 0), 0x4080_C0FF);
+    assert_eq!(pixel32(7, 7), 0x4080_C0FF);
+    assert_eq!(pixel32(0, 8), 0);
+    assert_eq!(pixel32(8, 0), 0x0000_00FF);
+    assert_eq!(pixel32(11, 5), 0x3050_00FF);
+    assert_eq!(pixel32(15, 7), 0x7070_00FF);
+}
+
+#[test]
+fn z_buffer() {
+    make_rcp();
+    for off in (0..WIDTH * WIDTH * 2).step_by(4) {
+        write(Z_ADDR + off, 0xFFFF_FFFF);
+    }
+
+    // Compare and update the Z-buffer.
+    let mut cmds = setup(3, 1 << 4 | 1 << 5);
+    cmds.push(0x3E << 56 | Z_ADDR as u64);
+    cmds.push(combine(4));
+
+    // Green square, then a red one behind it (only visible where it does
+    // not overlap).
+    cmds.extend(rect_edges(0x0D, 0, 0, 8, 8));
+    cmds.extend(shade_coeffs(0x00FF_00FF, 0, 0));
+    cmds.extend(z_coeffs(0x100));
+    cmds.extend(rect_edges(0x0D, 4, 0, 12, 8));
+    cmds.extend(shade_coeffs(0xFF00_00FF, 0, 0));
+    cmds.extend(z_coeffs(0x200));
+    run_commands(CMD_ADDR, &cmds);
+
+    assert_eq!(pixel32(3, 3), 0x00FF_00FF);
+    assert_eq!(pixel32(5, 3), 0x00FF_00FF);
+    assert_eq!(pixel32(9, 3), 0xFF00_00FF);
+
+    // A blue square in front of both.
+    let mut cmds = rect_edges(0x0D, 0, 0, 12, 8);
+    cmds.extend(shade_coeffs(0x0000_FFFF, 0, 0));
+    cmds.extend(z_coeffs(0x80));
+    run_commands(CMD_ADDR + 0x800, &cmds);
+
+    assert_eq!(pixel32(3, 3), 0x0000_FFFF);
+    assert_eq!(pixel32(9, 3), 0x0000_FFFF);
+}
+
+#[test]
+fn textured_triangle() {
+    make_rcp();
+
+    // 4x4 RGBA16 texture, with red increasing along S and green along T.
+    let texel = |s: u32, t: u32| (s * 8) << 11 | (t * 8) << 6 | 1;
+    for t in 0..4 {
+        for s in (0..4).step_by(2) {
+            let addr = TEX_ADDR + (t * 4 + s) * 2;
+            write(addr, texel(s, t) << 16 | texel(s + 1, t));
+        }
+    }
+
+    let mut cmds = setup(3, 0);
+    cmds.extend(vec![
+        0x3D << 56 | 2 << 51 | 3 << 32 | TEX_ADDR as u64, // Set Texture Image
+        0x35 << 56 | 2 << 51 | 7 << 24,                   // Set Tile 7 (load)
+        0x33 << 56 | 7 << 24 | 15 << 12,                  // Load Block (16 texels)
+        0x35 << 56 | 2 << 51 | 1 << 41 | 2 << 14 | 2 << 4, // Set Tile 0 (4x4, wrapping)
+        0x32 << 56 | (3 << 2) << 12 | (3 << 2),           // Set Tile Size 0
+        combine(1),
+    ]);
+
+    // One texel per pixel, along both axes.
+    cmds.extend(rect_edges(0x0A, 0, 0, 8, 8));
+    cmds.extend(vec![0, 32 << 48, 0, 0, 32 << 32, 0, 0, 0]);
+    run_commands(CMD_ADDR, &cmds);
+
+    let c5 = |v: u32| v << 3 | v >> 2;
+    for y in 0..8 {
+        for x in 0..8 {
+            let exp = c5((x % 4) * 8) << 24 | c5((y % 4) * 8) << 16 | 0xFF;
+            assert_eq!(pixel32(x, y), exp, "pixel ({}, {})", x, y);
+        }
+    }
+}
+
+#[test]
+fn xbus_commands() {
+    make_rcp();
+
+    // Fetch the commands from DMEM rather than RDRAM.
+    write(DPC_STATUS, 1 << 1);
+    let mut cmds = setup(2, 3 << 52);
+    cmds.push(0x37 << 56 | 0x07C1_07C1);
+    cmds.extend(rect_edges(0x08, 0, 0, 4, 4));
+    run_commands(SP_DMEM + 0x100, &cmds);
+
+    assert_eq!(pixel16(0, 0), 0x07C1);
+    assert_eq!(pixel16(3, 3), 0x07C1);
+    assert_eq!(pixel16(4, 4), 0);
+}
+
+#[test]
+fn freeze() {
+    make_rcp();
+
+    write(DPC_STATUS, 1 << 3);
+    let mut cmds = setup(2, 3 << 52);
+    cmds.push(0x37 << 56 | 0xFFFF_FFFF);
+    cmds.extend(rect_edges(0x08, 0, 0, 4, 4));
+    run_commands(CMD_ADDR, &cmds);
+    assert_eq!(pixel16(0, 0), 0);
+
+    // Commands are processed as soon as the RDP is unfrozen.
+    write(DPC_STATUS, 1 << 2);
+    run_dp();
+    assert_eq!(pixel16(0, 0), 0xFFFF);
+}
@@ -0,0 +1,208 @@
+//! Machine-readable results of the golden tests.
+//!
+//! When `GOLDEN_RESULTS` is set to a directory, each suite writes there the
+//! result of each of its test vectors, with the differences of the failed
+//! ones: JUnit XML by default, or JSON with `GOLDEN_RESULTS_FORMAT=json`.
+//! Suites run in parallel, so each one is written into its own file, named
+//! after the suite.
+
+#![allow(dead_code)]
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+enum Status {
+    Pass,
+    Fail(String),
+    Skip(String),
+}
+
+struct Case {
+    name: String,
+    status: Status,
+}
+
+pub struct Suite {
+    name: String,
+    cases: Vec<Case>,
+}
+
+impl Suite {
+    pub fn new(name: &str) -> Suite {
+        Suite {
+            name: name.to_owned(),
+            cases: Vec::new(),
+        }
+    }
+
+    pub fn pass(&mut self, case: &str) {
+        self.add(case, Status::Pass);
+    }
+
+    /// Record a failed test vector, with the description of the differences
+    /// from the expected results.
+    pub fn fail(&mut self, case: &str, diff: String) {
+        self.add(case, Status::Fail(diff));
+    }
+
+    pub fn skip(&mut self, case: &str, reason: String) {
+        self.add(case, Status::Skip(reason));
+    }
+
+    fn add(&mut self, case: &str, status: Status) {
+        self.cases.push(Case {
+            name: case.to_owned(),
+            status,
+        });
+    }
+
+    /// Return the names of the failed test vectors.
+    pub fn failures(&self) -> Vec<&str> {
+        self.cases
+            .iter()
+            .filter(|c| matches!(c.status, Status::Fail(_)))
+            .map(|c| c.name.as_str())
+            .collect()
+    }
+
+    fn count(&self, skipped: bool) -> usize {
+        self.cases
+            .iter()
+            .filter(|c| match c.status {
+                Status::Skip(_) => skipped,
+                Status::Fail(_) => !skipped,
+                Status::Pass => false,
+            })
+            .count()
+    }
+
+    /// Write the results into the directory specified by GOLDEN_RESULTS, if
+    /// any.
+    pub fn write(&self) {
+        let dir = match env::var_os("GOLDEN_RESULTS") {
+            Some(dir) => dir,
+            None => return,
+        };
+        let json = env::var("GOLDEN_RESULTS_FORMAT").is_ok_and(|f| f == "json");
+        let (ext, out) = if json {
+            ("json", self.json())
+        } else {
+            ("xml", self.junit())
+        };
+
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = Path::new(&dir).join(format!("{}.{}", name, ext));
+        fs::create_dir_all(&dir).expect("cannot create results directory");
+        fs::write(&path, out).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    }
+
+    fn junit(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        writeln!(
+            out,
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">",
+            xml_escape(&self.name),
+            self.cases.len(),
+            self.count(false),
+            self.count(true)
+        )
+        .unwrap();
+        for case in &self.cases {
+            let attrs = format!(
+                "classname=\"{}\" name=\"{}\"",
+                xml_escape(&self.name),
+                xml_escape(&case.name)
+            );
+            match case.status {
+                Status::Pass => writeln!(out, "  <testcase {}/>", attrs),
+                Status::Fail(ref diff) => writeln!(
+                    out,
+                    "  <testcase {}>\n    <failure message=\"results differ\">{}</failure>\n  </testcase>",
+                    attrs,
+                    xml_escape(diff)
+                ),
+                Status::Skip(ref reason) => writeln!(
+                    out,
+                    "  <testcase {}>\n    <skipped message=\"{}\"/>\n  </testcase>",
+                    attrs,
+                    xml_escape(reason)
+                ),
+            }
+            .unwrap();
+        }
+        out += "</testsuite>\n";
+        out
+    }
+
+    fn json(&self) -> String {
+        let cases: Vec<_> = self
+            .cases
+            .iter()
+            .map(|case| {
+                let (status, detail) = match case.status {
+                    Status::Pass => ("pass", String::new()),
+                    Status::Fail(ref diff) => ("fail", format!(", \"diff\": {}", json_str(diff))),
+                    Status::Skip(ref reason) => {
+                        ("skip", format!(", \"reason\": {}", json_str(reason)))
+                    }
+                };
+                format!(
+                    "    {{\"name\": {}, \"status\": \"{}\"{}}}",
+                    json_str(&case.name),
+                    status,
+                    detail
+                )
+            })
+            .collect();
+        format!(
+            "{{\n  \"suite\": {},\n  \"tests\": {},\n  \"failures\": {},\n  \"skipped\": {},\n  \"cases\": [\n{}\n  ]\n}}\n",
+            json_str(&self.name),
+            self.cases.len(),
+            self.count(false),
+            self.count(true),
+            cases.join(",\n")
+        )
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out += "&amp;",
+            '<' => out += "&lt;",
+            '>' => out += "&gt;",
+            '"' => out += "&quot;",
+            '\'' => out += "&apos;",
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,176 @@
+extern crate crc;
+extern crate r64emu;
+
+use crc::crc32;
+use r64emu::cartridge::Cartridge;
+use r64emu::patch::{self, PatchFormat};
+use std::env;
+use std::fs;
+
+fn rom() -> Vec<u8> {
+    let mut rom: Vec<u8> = (0..0x2000).map(|idx| (idx * 7) as u8).collect();
+    rom[0] = 0x80;
+    rom
+}
+
+fn varint(out: &mut Vec<u8>, mut val: usize) {
+    loop {
+        let b = (val & 0x7F) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(b | 0x80);
+            return;
+        }
+        out.push(b);
+        val -= 1;
+    }
+}
+
+// Append the footer of BPS/UPS patches.
+fn footer(mut patch: Vec<u8>, src: &[u8], dst: &[u8]) -> Vec<u8> {
+    patch.extend_from_slice(&crc32::checksum_ieee(src).to_le_bytes());
+    patch.extend_from_slice(&crc32::checksum_ieee(dst).to_le_bytes());
+    let crc = crc32::checksum_ieee(&patch);
+    patch.extend_from_slice(&crc.to_le_bytes());
+    patch
+}
+
+#[test]
+fn ips() {
+    let src = rom();
+    let mut p = b"PATCH".to_vec();
+    p.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x03, 1, 2, 3]); // 0x100: 1,2,3
+    p.extend_from_slice(&[0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x10, 0xAA]); // 0x200: RLE
+    p.extend_from_slice(&[0x00, 0x20, 0x00, 0x00, 0x02, 4, 5]); // extend ROM
+    p.extend_from_slice(b"EOF");
+
+    let mut dst = src.clone();
+    dst[0x100..0x103].copy_from_slice(&[1, 2, 3]);
+    dst[0x200..0x210].copy_from_slice(&[0xAA; 0x10]);
+    dst.extend_from_slice(&[4, 5]);
+    assert_eq!(PatchFormat::detect(&p), Some(PatchFormat::Ips));
+    assert_eq!(patch::apply(&src, &p).unwrap(), dst);
+
+    // Truncation extension
+    p.extend_from_slice(&[0x00, 0x10, 0x00]);
+    assert_eq!(patch::apply(&src, &p).unwrap(), &dst[..0x1000]);
+
+    assert!(patch::apply(&src, &p[..12]).is_err());
+}
+
+#[test]
+fn bps() {
+    let src = rom();
+    let mut dst = src[..0x1000].to_vec(); // source read
+    dst.extend_from_slice(b"hello"); // target read
+    dst.extend_from_slice(&src[0x10..0x20]); // source copy
+    dst.extend_from_slice(&[src[0x1F]; 7]); // target copy (overlapping)
+    dst.extend_from_slice(&src[0x100..0x108]); // source copy, after the previous one
+
+    let mut p = b"BPS1".to_vec();
+    varint(&mut p, src.len());
+    varint(&mut p, dst.len());
+    varint(&mut p, 3);
+    p.extend_from_slice(b"abc"); // metadata
+    varint(&mut p, (0x1000 - 1) << 2);
+    varint(&mut p, (5 - 1) << 2 | 1);
+    p.extend_from_slice(b"hello");
+    varint(&mut p, (0x10 - 1) << 2 | 2);
+    varint(&mut p, 0x10 << 1);
+    varint(&mut p, (7 - 1) << 2 | 3);
+    varint(&mut p, 0x1014 << 1);
+    varint(&mut p, (8 - 1) << 2 | 2);
+    varint(&mut p, (0x100 - 0x20) << 1);
+    let p = footer(p, &src, &dst);
+
+    assert_eq!(PatchFormat::detect(&p), Some(PatchFormat::Bps));
+    assert_eq!(patch::apply(&src, &p).unwrap(), dst);
+
+    // The patch is for a different ROM
+    let mut other = src.clone();
+    other[0x1234] ^= 1;
+    assert!(patch::apply(&other, &p).is_err());
+
+    // Corrupted patch
+    let mut bad = p.clone();
+    bad[10] ^= 1;
+    assert!(patch::apply(&src, &bad).is_err());
+}
+
+#[test]
+fn ups() {
+    let src = rom();
+    let mut dst = src.clone();
+    dst[0x10] ^= 0x55;
+    dst[0x11] ^= 0x66;
+    dst[0x800] ^= 0x01;
+    dst.extend_from_slice(&[9, 9]);
+
+    let mut p = b"UPS1".to_vec();
+    varint(&mut p, src.len());
+    varint(&mut p, dst.len());
+    varint(&mut p, 0x10);
+    p.extend_from_slice(&[0x55, 0x66, 0x00]);
+    varint(&mut p, 0x800 - 0x13);
+    p.extend_from_slice(&[0x01, 0x00]);
+    varint(&mut p, src.len() - 0x802);
+    p.extend_from_slice(&[9, 9, 0x00]);
+    let p = footer(p, &src, &dst);
+
+    assert_eq!(PatchFormat::detect(&p), Some(PatchFormat::Ups));
+    assert_eq!(patch::apply(&src, &p).unwrap(), dst);
+    assert!(patch::apply(&dst, &p).is_err());
+}
+
+#[test]
+fn too_big() {
+    // The size of the patched ROM is checked before allocating it.
+    let src = rom();
+    for magic in [&b"BPS1"[..], &b"UPS1"[..]].iter() {
+        let mut p = magic.to_vec();
+        varint(&mut p, src.len());
+        varint(&mut p, 1 << 40);
+        varint(&mut p, 0);
+        let p = footer(p, &src, &src);
+        let err = patch::apply(&src, &p).unwrap_err();
+        assert!(err.contains("too big"), "{}", err);
+    }
+}
+
+#[test]
+fn varint_overflow() {
+    // Numbers that don't fit 64 bits are rejected.
+    let src = rom();
+    for magic in [&b"BPS1"[..], &b"UPS1"[..]].iter() {
+        let mut p = magic.to_vec();
+        p.extend_from_slice(&[0x7F; 10]);
+        p.push(0xFF);
+        let p = footer(p, &src, &src);
+        let err = patch::apply(&src, &p).unwrap_err();
+        assert!(err.contains("invalid number"), "{}", err);
+    }
+}
+
+#[test]
+fn soft_patch() {
+    // The patch is found next to the ROM, and applied while loading it,
+    // without modifying the ROM file.
+    let dir = env::temp_dir().join("r64emu-soft-patch");
+    fs::create_dir_all(&dir).unwrap();
+    let (romfn, patchfn) = (dir.join("game.z64"), dir.join("game.ips"));
+    fs::write(&romfn, rom()).unwrap();
+    let _ = fs::remove_file(&patchfn);
+    assert_eq!(patch::find_patch(&romfn), None);
+
+    let mut p = b"PATCH".to_vec();
+    p.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x04, 0xCA, 0xFE, 0xBA, 0xBE]);
+    p.extend_from_slice(b"EOF");
+    fs::write(&patchfn, p).unwrap();
+    assert_eq!(patch::find_patch(&romfn), Some(patchfn.clone()));
+
+    let mut cart = Cartridge::with_patch(&romfn, Some(&patchfn)).unwrap();
+    assert_eq!(cart.rom_read(0x100, 4), 0xCAFE_BABE);
+    assert_eq!(fs::read(&romfn).unwrap(), rom());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
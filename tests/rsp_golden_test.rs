@@ -1,5 +1,3 @@
-#![feature(pin)]
-
 #[macro_use]
 extern crate slog;
 #[macro_use]
@@ -13,31 +11,30 @@ extern crate toml;
 use byteorder::{BigEndian, ByteOrder};
 use emu::bus::be::Device;
 use emu::dbg::Tracer;
-use r64emu::dp::Dp;
-use r64emu::sp::{Sp, RSPCPU};
 use r64emu::r4300::R4300;
+use r64emu::sp::{Sp, RSPCPU};
+use r64emu::{Devices, N64Builder};
 use slog::Discard;
 use std::borrow;
+use std::collections::HashMap;
 use std::env;
+use std::fmt::{self, Write as FmtWrite};
 use std::fs;
+use std::io::{self, Write};
 use std::iter::Iterator;
 use std::path::Path;
+use std::process::Command;
+use std::thread;
 
-fn make_sp() {
-    let logger = slog::Logger::root(Discard, o!());
-    R4300::new(logger.new(o!())).register();
-    Dp::new(logger.new(o!())).register();
-    Sp::new(logger.new(o!())).unwrap().register();
+mod report;
 
-    // Simplified bus mapping for R4300: just SP registers.
-    {
-        let bus = &mut R4300::get_mut().bus;
-        bus.map_device(0x0400_0000, Sp::get(), 0).unwrap();
-        bus.map_device(0x0404_0000, Sp::get(), 1).unwrap();
-        bus.map_device(0x0408_0000, Sp::get(), 2).unwrap();
-    }
-    // Standard bus mapping for RSP.
-    RSPCPU::get_mut().map_bus().unwrap();
+fn make_sp(threaded: bool) {
+    let logger = slog::Logger::root(Discard, o!());
+    N64Builder::new(logger)
+        .devices(Devices::RSP_RIG)
+        .rsp_threaded(threaded)
+        .build_rig()
+        .unwrap();
 }
 
 #[allow(dead_code)]
@@ -47,6 +44,33 @@ struct TestVector {
     input: Vec<u32>,
 }
 
+// Expected RSP cycles of a test vector, from the optional .cycles file of
+// the suite written by gengolden: an exact count, or an inclusive range.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Cycles {
+    Exact(i64),
+    Range(i64, i64),
+}
+
+impl Cycles {
+    fn contains(&self, cycles: i64) -> bool {
+        match *self {
+            Cycles::Exact(c) => cycles == c,
+            Cycles::Range(min, max) => cycles >= min && cycles <= max,
+        }
+    }
+}
+
+impl fmt::Display for Cycles {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Cycles::Exact(c) => write!(f, "{}", c),
+            Cycles::Range(min, max) => write!(f, "{}..={}", min, max),
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Deserialize)]
 struct Testsuite {
@@ -57,7 +81,7 @@ struct Testsuite {
 }
 
 impl Testsuite {
-    fn inout_size(&self, desc: &Vec<String>) -> usize {
+    fn inout_size(&self, desc: &[String]) -> usize {
         let mut size: usize = 0;
         for d in desc.iter() {
             match d.split(":").next().unwrap() {
@@ -77,40 +101,146 @@ impl Testsuite {
         self.inout_size(&self.output_desc)
     }
 
-    fn display<'a, K: borrow::Borrow<u32>, I: Iterator<Item = K>>(
+    // Format the values, one line for each entry of the description.
+    fn format<K: borrow::Borrow<u32>, I: Iterator<Item = K>>(
         &self,
-        desc: &Vec<String>,
+        desc: &[String],
         mut vals: I,
-    ) {
+    ) -> Vec<String> {
+        let mut lines = Vec::new();
         for d in desc {
             let comp = d.split(":").collect::<Vec<&str>>();
+            let mut line = format!("    {:>12}: ", comp[1]);
             match comp[0] {
                 "v128" => {
-                    print!("    {:>12}: ", comp[1]);
                     for _ in 0..4 {
                         let c = vals.next().unwrap();
-                        print!("{:08x} ", *c.borrow());
+                        write!(line, "{:08x} ", *c.borrow()).unwrap();
                     }
-                    println!();
                 }
                 "u32" => {
                     let c = vals.next().unwrap();
-                    println!("    {:>12}: {:08x}", comp[1], *c.borrow());
+                    write!(line, "{:08x}", *c.borrow()).unwrap();
                 }
-                _ => assert!(false, "unsupported input desc type: {}", comp[0]),
+                _ => panic!("unsupported input desc type: {}", comp[0]),
             };
+            lines.push(line);
         }
+        lines
     }
 
-    pub fn display_input<'a, K: borrow::Borrow<u32>, I: Iterator<Item = K>>(&self, vals: I) {
-        self.display(&self.input_desc, vals)
+    pub fn display_input<K: borrow::Borrow<u32>, I: Iterator<Item = K>>(&self, vals: I) {
+        for line in self.format(&self.input_desc, vals) {
+            println!("{}", line);
+        }
     }
-    pub fn display_output<'a, K: borrow::Borrow<u32>, I: Iterator<Item = K>>(&self, vals: I) {
-        self.display(&self.output_desc, vals)
+    pub fn display_output<K: borrow::Borrow<u32>, I: Iterator<Item = K>>(&self, vals: I) {
+        for line in self.format(&self.output_desc, vals) {
+            println!("{}", line);
+        }
+    }
+
+    // Describe the outputs that differ from the expected ones, as the
+    // expected (-) and found (+) values.
+    pub fn diff_output(&self, exp: &[u8], found: &[u8]) -> String {
+        let exp = self.format(
+            &self.output_desc,
+            exp.chunks_exact(4).map(BigEndian::read_u32),
+        );
+        let found = self.format(
+            &self.output_desc,
+            found.chunks_exact(4).map(BigEndian::read_u32),
+        );
+        let mut diff = String::new();
+        for (e, f) in exp.iter().zip(found.iter()).filter(|(e, f)| e != f) {
+            writeln!(diff, "-{}\n+{}", e, f).unwrap();
+        }
+        diff
     }
 }
 
+// Check that the RSP binary and the golden results of the suite exist and
+// match its TOML file, and return the reason otherwise. Modification times
+// are not compared, as they are not preserved by checkouts: the golden
+// results are stale if their size does not match the test vectors.
+fn check_artifacts(tomlname: &Path, test: &Testsuite) -> Result<(), String> {
+    let rspname = tomlname.with_extension("rsp");
+    match fs::metadata(&rspname) {
+        Err(err) => return Err(format!("{}: {}", rspname.display(), err)),
+        Ok(md) if md.len() == 0 || md.len() > 0x1000 => {
+            return Err(format!(
+                "{}: invalid size ({} bytes)",
+                rspname.display(),
+                md.len()
+            ));
+        }
+        Ok(_) => {}
+    }
+
+    let goldenname = tomlname.with_extension("golden");
+    let expected = (test.output_size() * test.test.len()) as u64;
+    match fs::metadata(&goldenname) {
+        Err(err) => Err(format!("{}: {}", goldenname.display(), err)),
+        Ok(md) if md.len() != expected => Err(format!(
+            "{}: stale ({} bytes, expected {} for {} test vectors)",
+            goldenname.display(),
+            md.len(),
+            expected,
+            test.test.len()
+        )),
+        Ok(_) => Ok(()),
+    }
+}
+
+fn in_path(tool: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(tool).is_file()))
+}
+
+// Regenerate the artifacts of the suite through gengolden, if requested with
+// RSP_GOLDEN_REGEN and the toolchain it needs is installed. Return true if
+// gengolden succeeded.
+fn regen_artifacts(tomlname: &Path) -> bool {
+    if env::var_os("RSP_GOLDEN_REGEN").is_none() {
+        return false;
+    }
+    let missing: Vec<_> = ["bass", "chksum64", "64drive"]
+        .iter()
+        .filter(|tool| !in_path(tool))
+        .collect();
+    if !missing.is_empty() {
+        log_suite(
+            tomlname,
+            &format!("cannot regenerate, missing tools: {:?}", missing),
+        );
+        return false;
+    }
+
+    // gengolden runs from its directory (where run.sh and the assembler
+    // includes are), and is built into its own target directory, as the
+    // target directory of the tests is locked while they run.
+    let tomlname = fs::canonicalize(tomlname).unwrap();
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    Command::new(cargo)
+        .args(["run", "--release", "-p", "gengolden"])
+        .args(["--target-dir", "../../target/gengolden", "--", "--regen"])
+        .arg(&tomlname)
+        .current_dir("tests/gengolden")
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// Report a skipped suite. It is written to stderr directly, so that it is
+// not hidden by the output capture of the test harness.
+fn log_suite(tomlname: &Path, msg: &str) {
+    let _ = writeln!(io::stderr(), "{}: {}", tomlname.display(), msg);
+}
+
 fn test_golden(testname: &str) {
+    run_golden(testname, false);
+}
+
+fn run_golden(testname: &str, threaded: bool) {
     let path = env::current_dir().unwrap();
     println!("The current directory is {}", path.display());
 
@@ -118,7 +248,24 @@ fn test_golden(testname: &str) {
     let tomlsrc = fs::read_to_string(tomlname).expect("TOML file not found");
     let test: Testsuite = toml::from_str(&tomlsrc).unwrap();
 
-    make_sp();
+    let stem = tomlname.file_stem().unwrap().to_string_lossy();
+    let mode = if threaded { ".threaded" } else { "" };
+    let mut results = report::Suite::new(&format!("rsp_golden{}.{}", mode, stem));
+
+    // Suites whose artifacts are missing or stale are skipped (and reported),
+    // unless they can be regenerated.
+    let mut artifacts = check_artifacts(tomlname, &test);
+    if artifacts.is_err() && regen_artifacts(tomlname) {
+        artifacts = check_artifacts(tomlname, &test);
+    }
+    if let Err(reason) = artifacts {
+        log_suite(tomlname, &format!("SKIPPED, {}", reason));
+        results.skip(&stem, reason);
+        results.write();
+        return;
+    }
+
+    make_sp(threaded);
 
     {
         // Load RSP microcode into IMEM
@@ -133,6 +280,16 @@ fn test_golden(testname: &str) {
     let goldenbin = fs::read(goldenname).expect("golden file not found");
     let mut golden = goldenbin.chunks_exact(output_size);
 
+    // Timings are checked only through the interpreter, that can be run one
+    // cycle at a time. Until the timing model of the RSP is complete,
+    // differences are only reported, unless RSP_GOLDEN_TIMING is set.
+    let cycles: HashMap<String, Cycles> =
+        match fs::read_to_string(tomlname.with_extension("cycles")) {
+            Ok(src) if !threaded => toml::from_str(&src).expect("invalid cycles file"),
+            _ => HashMap::new(),
+        };
+    let strict_timing = env::var_os("RSP_GOLDEN_TIMING").is_some();
+
     for t in &test.test {
         println!("running test: {}", &t.name);
 
@@ -161,7 +318,26 @@ fn test_golden(testname: &str) {
 
             let cpu = RSPCPU::get_mut();
             let clock = cpu.ctx().clock;
-            cpu.run(clock + 1000, &Tracer::null()).unwrap();
+            match cycles.get(&t.name) {
+                None => cpu.run(clock + 1000, &Tracer::null()).unwrap(),
+                Some(expected) => {
+                    while main_bus.read::<u32>(0x0404_0010) & 1 == 0
+                        && cpu.ctx().clock < clock + 1000
+                    {
+                        let now = cpu.ctx().clock;
+                        cpu.run(now + 1, &Tracer::null()).unwrap();
+                    }
+                    // Exclude the final break, as the timings measured
+                    // on hardware.
+                    let elapsed = cpu.ctx().clock - clock - 1;
+                    if !expected.contains(elapsed) {
+                        let msg =
+                            format!("{}: took {} cycles, expected {}", t.name, elapsed, expected);
+                        assert!(!strict_timing, "{}", msg);
+                        println!("  timing differs: {}", msg);
+                    }
+                }
+            }
         }
 
         // Read the results
@@ -172,8 +348,38 @@ fn test_golden(testname: &str) {
             println!("   outputs:");
             test.display_output(outbuf.chunks_exact(4).map(BigEndian::read_u32));
 
-            // Load test input into DMEM
-            assert!(exp == outbuf, "output is different from expected result");
+            if exp == outbuf {
+                results.pass(&t.name);
+            } else {
+                println!("  output is different from expected result");
+                results.fail(&t.name, test.diff_output(exp, outbuf));
+            }
+        }
+    }
+
+    results.write();
+    let failures = results.failures();
+    assert!(
+        failures.is_empty(),
+        "output is different from expected result: {:?}",
+        failures
+    );
+}
+
+// Run the golden tests extracted from traces by tools/tracegolden. Each test
+// runs in its own thread, as the devices can only be created once per thread.
+fn run_golden_traces(threaded: bool) {
+    let entries = match fs::read_dir("tests/gengolden/traces") {
+        Ok(entries) => entries,
+        Err(_) => return, // no traces yet
+    };
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            let testname = path.to_str().unwrap().to_owned();
+            thread::spawn(move || run_golden(&testname, threaded))
+                .join()
+                .unwrap_or_else(|_| panic!("{}: failed", path.display()));
         }
     }
 }
@@ -203,6 +409,11 @@ define_golden_test!(golden_mfc2, "mfc2.toml");
 define_golden_test!(golden_mtc2, "mtc2.toml");
 define_golden_test!(golden_vmrg, "vmrg.toml");
 
+#[test]
+fn golden_traces() {
+    run_golden_traces(false);
+}
+
 #[test]
 fn golden_lqv_sqv() {
     test_golden("tests/gengolden/lqv_sqv.toml");
@@ -337,3 +548,36 @@ fn golden_vmadm() {
 fn golden_compelt() {
     test_golden("tests/gengolden/compelt.toml");
 }
+
+#[test]
+fn golden_gpr_zero() {
+    test_golden("tests/gengolden/gpr_zero.toml");
+}
+
+// The same tests, running the microcode through the RSP threaded-code
+// interpreter.
+mod threaded {
+    macro_rules! define_threaded_tests {
+        ($($test:ident),*) => {
+            $(
+                #[test]
+                fn $test() {
+                    let testname = concat!("tests/gengolden/", stringify!($test), ".toml");
+                    super::run_golden(testname, true);
+                }
+            )*
+        };
+    }
+
+    define_threaded_tests!(
+        vsubb, vsucb, vrcp, vrcpl, vrsq, veq, vne, vge, vlt, vcl, vch, vcr, mfc2, mtc2, vmrg,
+        lqv_sqv, lrv_srv, ldv_sdv, llv_slv, lsv_ssv, lbv_sbv, ltv, stv, swv, vadd, vsub, vsubc,
+        vaddc, vlogical, vmulf, vmulu, vmacf, vmacu, vmudn, vmadn, vmudh, vmadh, vmudl, vmadl,
+        vmudm, vmadm, compelt, gpr_zero
+    );
+
+    #[test]
+    fn golden_traces() {
+        super::run_golden_traces(true);
+    }
+}
@@ -0,0 +1,49 @@
+extern crate r64emu;
+
+use r64emu::saves::{export, import, SaveFormat, SaveMedia};
+
+fn pattern(media: SaveMedia) -> Vec<u8> {
+    (0..media.size()).map(|i| (i * 7) as u8).collect()
+}
+
+#[test]
+fn roundtrip() {
+    for &media in SaveMedia::ALL.iter() {
+        let image = pattern(media);
+        for &format in SaveFormat::ALL.iter() {
+            if format == SaveFormat::DexDrive && media != SaveMedia::Mempak {
+                assert!(export(media, format, &image).is_err());
+                continue;
+            }
+            let data = export(media, format, &image).unwrap();
+            assert_eq!(import(media, format, &data).unwrap(), image);
+        }
+    }
+}
+
+#[test]
+fn swap32() {
+    let mut image = vec![0u8; SaveMedia::Sram.size()];
+    image[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+    let data = export(SaveMedia::Sram, SaveFormat::Swap32, &image).unwrap();
+    assert_eq!(&data[..8], &[4, 3, 2, 1, 8, 7, 6, 5]);
+}
+
+#[test]
+fn padding() {
+    // 4kbit EEPROM padded to 16kbit
+    let mut data = pattern(SaveMedia::Eeprom4k);
+    data.resize(SaveMedia::Eeprom16k.size(), 0);
+    let image = import(SaveMedia::Eeprom4k, SaveFormat::Raw, &data).unwrap();
+    assert_eq!(image, pattern(SaveMedia::Eeprom4k));
+
+    // Not padding: the file does not contain a 4kbit EEPROM
+    data[0x400] = 1;
+    assert!(import(SaveMedia::Eeprom4k, SaveFormat::Raw, &data).is_err());
+
+    // Truncated files are filled as erased
+    let image = import(SaveMedia::FlashRam, SaveFormat::Raw, &[0x12; 16]).unwrap();
+    assert_eq!(image.len(), SaveMedia::FlashRam.size());
+    assert_eq!(image[15], 0x12);
+    assert_eq!(image[16], 0xFF);
+}
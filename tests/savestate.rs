@@ -0,0 +1,75 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::{Device, Snapshot};
+use r64emu::errors::EmuError;
+use r64emu::r4300::R4300;
+use r64emu::ri::Ri;
+use r64emu::savestate::{load_state, save_state};
+use r64emu::sp::Sp;
+use r64emu::{Devices, N64Builder};
+use slog::Discard;
+
+fn make_bus() {
+    let logger = slog::Logger::root(Discard, o!());
+    N64Builder::new(logger)
+        .devices(Devices::RCP_RIG)
+        .build_rig()
+        .unwrap();
+}
+
+fn read(addr: u32) -> u32 {
+    R4300::get_mut().bus.read::<u32>(addr)
+}
+
+fn write(addr: u32, val: u32) {
+    R4300::get_mut().bus.write::<u32>(addr, val);
+}
+
+#[test]
+fn round_trip() {
+    make_bus();
+
+    // RDRAM, DMEM and a SP register.
+    write(0x0000_1000, 0x1122_3344);
+    write(0x0400_0010, 0x5566_7788);
+    write(0x0404_0000, 0x0000_0FF8);
+    let mut saved = Vec::new();
+    save_state(&mut saved).unwrap();
+
+    write(0x0000_1000, 0);
+    write(0x0400_0010, 0);
+    write(0x0404_0000, 0);
+    load_state(&saved[..]).unwrap();
+    assert_eq!(read(0x0000_1000), 0x1122_3344);
+    assert_eq!(read(0x0400_0010), 0x5566_7788);
+    assert_eq!(read(0x0404_0000), 0x0000_0FF8);
+}
+
+#[test]
+fn invalid_state() {
+    make_bus();
+
+    write(0x0000_1000, 0x1122_3344);
+    match load_state(&b"not a savestate"[..]) {
+        Err(EmuError::State(_)) => {}
+        res => panic!("unexpected result: {:?}", res.map(|_| ())),
+    }
+    // A failed load leaves the machine untouched.
+    assert_eq!(read(0x0000_1000), 0x1122_3344);
+}
+
+#[test]
+fn device_fields() {
+    make_bus();
+
+    // Devices own the fields named after their tag.
+    assert!(Ri::get().state_fields().contains(&"Ri::rdram".to_owned()));
+    let fields = Sp::get().state_fields();
+    assert!(fields.contains(&"Sp::dmem".to_owned()));
+    assert!(fields.contains(&"Sp::imem".to_owned()));
+    assert!(!fields.contains(&"Ri::rdram".to_owned()));
+}
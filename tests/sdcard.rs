@@ -0,0 +1,66 @@
+extern crate r64emu;
+
+use r64emu::sdcard::{SdCard, SECTOR_SIZE};
+use std::env;
+use std::fs;
+
+fn le16(b: &[u8]) -> u32 {
+    b[0] as u32 | (b[1] as u32) << 8
+}
+
+fn le32(b: &[u8]) -> u32 {
+    le16(b) | le16(&b[2..]) << 16
+}
+
+#[test]
+fn directory() {
+    let dir = env::temp_dir().join("r64emu-sdcard-test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("DATA")).unwrap();
+    fs::write(dir.join("HELLO.TXT"), b"hello, world").unwrap();
+    fs::write(
+        dir.join("DATA").join("long file name.bin"),
+        vec![0xAB; 5000],
+    )
+    .unwrap();
+
+    let mut sd = SdCard::open(&dir).unwrap();
+    let mut sector = vec![0u8; SECTOR_SIZE];
+    let mut read = |sd: &mut SdCard, n: u32| -> Vec<u8> {
+        sd.read(n as u64, &mut sector).unwrap();
+        sector.clone()
+    };
+
+    let boot = read(&mut sd, 0);
+    assert_eq!(&boot[82..90], b"FAT32   ");
+    assert_eq!(&boot[510..], &[0x55, 0xAA]);
+    let spc = boot[13] as u32;
+    let data_start = le16(&boot[14..]) + le32(&boot[36..]);
+    let cluster = |c: u32| data_start + (c - 2) * spc;
+
+    // Root directory: "DATA" (a directory), then "HELLO.TXT"
+    let root = read(&mut sd, cluster(le32(&boot[44..])));
+    assert_eq!(&root[0..11], b"DATA       ");
+    assert_eq!(root[11], 0x10);
+    assert_eq!(&root[32..43], b"HELLO   TXT");
+    assert_eq!(le32(&root[32 + 28..]), 12);
+    let hello = read(&mut sd, cluster(le16(&root[32 + 26..])));
+    assert_eq!(&hello[..12], b"hello, world");
+    assert!(hello[12..].iter().all(|&b| b == 0));
+
+    // Subdirectory: ".", "..", then a long file name entry
+    let sub = read(&mut sd, cluster(le16(&root[26..])));
+    assert_eq!(&sub[0..11], b".          ");
+    assert_eq!(&sub[32..43], b"..         ");
+    assert_eq!(sub[64], 0x42); // last LFN entry, sequence 2
+    assert_eq!(sub[64 + 11], 0x0F);
+    assert_eq!(&sub[128..139], b"LONGFI~1BIN");
+    assert_eq!(le32(&sub[128 + 28..]), 5000);
+
+    // Writes are kept in memory
+    sd.write(100, &[0x55; SECTOR_SIZE]).unwrap();
+    assert_eq!(read(&mut sd, 100), vec![0x55; SECTOR_SIZE]);
+    assert!(sd.read(sd.sectors(), &mut [0u8; SECTOR_SIZE]).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,82 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate mips64;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use emu::sync::Subsystem;
+use mips64::Cop;
+use r64emu::pi::Pi;
+use r64emu::r4300::R4300;
+use r64emu::{Devices, N64Builder};
+use slog::Discard;
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+// Cause, and its bit for the pre-NMI interrupt (IP4).
+const CAUSE: usize = 13;
+const CAUSE_IP4: u128 = 1 << 12;
+
+// Reset vector of the main CPU, where the NMI jumps to.
+const RESET_VECTOR: u64 = 0xFFFF_FFFF_BFC0_0000;
+
+// Create the whole machine (except VI), with an empty ROM.
+fn make_n64(name: &str) {
+    let mut rom = vec![0u8; 0x1000];
+    rom[0] = 0x80;
+    let romfn = env::temp_dir().join(format!("r64emu-reset-{}.z64", name));
+    let biosfn = env::temp_dir().join(format!("r64emu-reset-{}.pif", name));
+    fs::write(&romfn, rom).unwrap();
+    fs::write(&biosfn, vec![0u8; 0x7C0]).unwrap();
+
+    let logger = slog::Logger::root(Discard, o!());
+    N64Builder::new(logger)
+        .devices(Devices::HEADLESS - Devices::SC64)
+        .rom(&romfn)
+        .bios(&biosfn)
+        .build_rig()
+        .unwrap();
+    fs::remove_file(&romfn).unwrap();
+    fs::remove_file(&biosfn).unwrap();
+}
+
+fn prenmi_pending() -> bool {
+    let cpu = R4300::get();
+    cpu.cop0.reg(cpu.ctx(), CAUSE) & CAUSE_IP4 != 0
+}
+
+fn run_pi(cycles: i64) {
+    let pi = Pi::get_mut();
+    let target = pi.cycles() + cycles;
+    pi.run(target, &Tracer::null()).unwrap();
+}
+
+#[test]
+fn nmi_delay() {
+    make_n64("nmi-delay");
+
+    // 1ms at the RCP clock (62.496 MHz).
+    const DELAY: i64 = 62_496;
+    Pi::get_mut().set_nmi_delay(Duration::from_millis(1));
+
+    // The CPU keeps running the game until the NMI.
+    R4300::get_mut().ctx_mut().set_pc(0xFFFF_FFFF_8000_1000);
+
+    Pi::get_mut().press_reset();
+    assert!(prenmi_pending());
+
+    // Pressing the button again does not postpone the NMI.
+    run_pi(DELAY / 2);
+    Pi::get_mut().press_reset();
+    run_pi(DELAY / 2 - 1);
+    assert!(prenmi_pending());
+    assert_eq!(R4300::get().ctx().pc, 0xFFFF_FFFF_8000_1000);
+
+    run_pi(1);
+    assert!(!prenmi_pending());
+    assert_eq!(R4300::get().ctx().pc, RESET_VECTOR);
+}
@@ -0,0 +1,219 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use r64emu::sp::{Sp, RSPCPU};
+
+mod common;
+use common::*;
+
+// Layout of RDRAM used by the test.
+const TASK_ADDR: u32 = 0x1000;
+const UCODE_ADDR: u32 = 0x2000;
+
+// libultra constants (sptask.h, rcp.h).
+const M_GFXTASK: u32 = 1;
+const M_AUDTASK: u32 = 2;
+const OS_TASK_YIELDED: u32 = 0x1;
+
+const SP_MEM_ADDR: u32 = 0x0404_0000;
+const SP_DRAM_ADDR: u32 = 0x0404_0004;
+const SP_RD_LEN: u32 = 0x0404_0008;
+const SP_STATUS: u32 = 0x0404_0010;
+const SP_PC: u32 = 0x0408_0000;
+const MI_INTR: u32 = 0x0430_0008;
+
+const SP_CLR_HALT: u32 = 1 << 0;
+const SP_CLR_BROKE: u32 = 1 << 2;
+const SP_CLR_INTR: u32 = 1 << 3;
+const SP_SET_INTR_BREAK: u32 = 1 << 8;
+const SP_CLR_SIG0: u32 = 1 << 9;
+const SP_SET_SIG0: u32 = 1 << 10;
+const SP_CLR_SIG1: u32 = 1 << 11;
+const SP_CLR_SIG2: u32 = 1 << 13;
+const SP_STATUS_SIG1: u32 = 1 << 8;
+const SP_STATUS_SIG2: u32 = 1 << 9;
+
+// A yieldable microcode. Its state is a counter and a target value (8 bytes
+// at the beginning of DMEM), loaded from the task microcode data. It
+// increments the counter until it reaches the target, and then writes the
+// state into the output buffer. When a yield is requested (SIG0), it saves
+// the state into the yield buffer, and halts with SIG1 and SIG2, like
+// libultra microcodes.
+fn ucode() -> Vec<u32> {
+    vec![
+        lw(T0, 0xFD8, ZERO), // OSTask.ucode_data
+        lw(T1, 0xFDC, ZERO), // OSTask.ucode_data_size
+        mtc0(ZERO, 0),       // DMA: DMEM 0x000
+        mtc0(T0, 1),
+        addiu(T1, T1, -1),
+        mtc0(T1, 2), // DMA: RDRAM -> DMEM
+        mfc0(T2, 6), // wait for DMA
+        bne(T2, ZERO, -2),
+        NOP,
+        lw(S0, 0, ZERO),
+        lw(S1, 4, ZERO),
+        // loop:
+        addiu(S0, S0, 1),
+        sw(S0, 0, ZERO),
+        beq(S0, S1, 9), // -> done
+        NOP,
+        mfc0(T2, 4), // SP_STATUS
+        andi(T2, T2, 0x80),
+        beq(T2, ZERO, -7), // no yield requested -> loop
+        NOP,
+        // yield:
+        lw(T0, 0xFF8, ZERO),   // OSTask.yield_data_ptr
+        ori(T1, ZERO, 0x5000), // SET_SIG1 | SET_SIG2
+        beq(ZERO, ZERO, 3),    // -> save
+        NOP,
+        // done:
+        lw(T0, 0xFE8, ZERO),   // OSTask.output_buff
+        ori(T1, ZERO, 0x4000), // SET_SIG2
+        // save:
+        mtc0(ZERO, 0),
+        mtc0(T0, 1),
+        ori(T2, ZERO, 7),
+        mtc0(T2, 3), // DMA: DMEM -> RDRAM
+        mfc0(T2, 6), // wait for DMA
+        bne(T2, ZERO, -2),
+        NOP,
+        mtc0(T1, 4), // signal the CPU
+        BREAK,
+        NOP,
+    ]
+}
+
+struct Task {
+    ty: u32,
+    flags: u32,
+    data: u32,
+    output: u32,
+    yield_data: u32,
+}
+
+impl Task {
+    fn new(ty: u32, data: u32, target: u32) -> Task {
+        write(data, 0);
+        write(data + 4, target);
+        Task {
+            ty,
+            flags: 0,
+            data,
+            output: data + 0x100,
+            yield_data: data + 0x200,
+        }
+    }
+
+    // Emulate osSpTaskLoad + osSpTaskStartGo.
+    fn start(&mut self) {
+        let mut ucode_data = self.data;
+        if self.flags & OS_TASK_YIELDED != 0 {
+            ucode_data = self.yield_data;
+            self.flags &= !OS_TASK_YIELDED;
+        }
+        let ucode = ucode();
+        let task = [
+            self.ty,
+            self.flags,
+            0,
+            0,
+            UCODE_ADDR,
+            ucode.len() as u32 * 4,
+            ucode_data,
+            8,
+            0,
+            0,
+            self.output,
+            0,
+            0,
+            0,
+            self.yield_data,
+            8,
+        ];
+        for (idx, w) in task.iter().enumerate() {
+            write(TASK_ADDR + idx as u32 * 4, *w);
+        }
+        for (idx, w) in ucode.iter().enumerate() {
+            write(UCODE_ADDR + idx as u32 * 4, *w);
+        }
+
+        write(SP_MEM_ADDR, 0xFC0);
+        write(SP_DRAM_ADDR, TASK_ADDR);
+        write(SP_RD_LEN, task.len() as u32 * 4 - 1);
+        write(SP_MEM_ADDR, 0x1000);
+        write(SP_DRAM_ADDR, UCODE_ADDR);
+        write(SP_RD_LEN, ucode.len() as u32 * 4 - 1);
+
+        write(
+            SP_STATUS,
+            SP_CLR_BROKE | SP_CLR_SIG0 | SP_CLR_SIG1 | SP_CLR_SIG2 | SP_SET_INTR_BREAK,
+        );
+        write(SP_PC, 0);
+        write(SP_STATUS, SP_CLR_HALT);
+    }
+}
+
+// Run the RSP for the specified number of cycles; return true if it halted.
+fn run(cycles: i64) -> bool {
+    let cpu = RSPCPU::get_mut();
+    let clock = cpu.ctx().clock;
+    cpu.run(clock + cycles, &Tracer::null()).unwrap();
+    Sp::get().halted()
+}
+
+// Wait for the RSP to halt, and acknowledge its interrupt.
+fn wait_halt() -> u32 {
+    assert!(run(100_000), "RSP did not halt");
+    assert!(Sp::get().broke());
+    assert_eq!(read(MI_INTR) & 1, 1, "SP interrupt not raised");
+    write(SP_STATUS, SP_CLR_INTR);
+    read(SP_STATUS)
+}
+
+#[test]
+fn alternating_tasks() {
+    make_rcp();
+
+    // A long graphics task is preempted by short audio tasks, which
+    // overwrite DMEM: it can complete only if its state is correctly saved
+    // and restored across yields.
+    let mut gfx = Task::new(M_GFXTASK, 0x10000, 2000);
+    let mut aud = Task::new(M_AUDTASK, 0x20000, 50);
+
+    let mut yields = 0;
+    gfx.start();
+    loop {
+        let finished = run(1000);
+        if !finished {
+            // Audio task ready: yield the graphics task.
+            write(SP_STATUS, SP_SET_SIG0);
+        }
+        let status = wait_halt();
+        assert_ne!(status & SP_STATUS_SIG2, 0);
+        if status & SP_STATUS_SIG1 == 0 {
+            break; // completed (possibly right before yielding)
+        }
+        gfx.flags |= OS_TASK_YIELDED;
+        yields += 1;
+        let count = read(gfx.yield_data);
+        assert!(count > 0 && count < 2000, "yield count: {}", count);
+        assert_eq!(read(gfx.yield_data + 4), 2000);
+
+        write(aud.output, 0);
+        aud.start();
+        let status = wait_halt();
+        assert_eq!(status & (SP_STATUS_SIG1 | SP_STATUS_SIG2), SP_STATUS_SIG2);
+        assert_eq!(read(aud.output), 50);
+
+        gfx.start();
+    }
+
+    assert!(yields >= 10, "graphics task yielded {} times", yields);
+    assert_eq!(read(gfx.output), 2000);
+    assert_eq!(read(gfx.output + 4), 2000);
+}
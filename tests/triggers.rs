@@ -0,0 +1,40 @@
+extern crate emu;
+extern crate r64emu;
+
+use emu::livesplit::Command;
+use emu::trigger::{Compare, Operand};
+use r64emu::triggers;
+
+const TRIGGERS: &str = r#"
+[[trigger]]
+name = "Star collected"
+when = ["8033b21a.h > prev"]
+
+[[trigger]]
+name = "Reached the castle"
+when = ["8033b249.b == 6", "8032ddf8.h == 0x1a"]
+once = true
+livesplit = "split"
+"#;
+
+#[test]
+fn parse() {
+    let t = triggers::parse(TRIGGERS).unwrap();
+    assert_eq!(t.len(), 2);
+    assert_eq!(t[0].name, "Star collected");
+    assert!(!t[0].once);
+    assert_eq!(t[0].conditions[0].rhs, Operand::Prev);
+    assert!(t[1].once);
+    assert_eq!(t[1].conditions[1].cmp, Compare::Eq);
+    assert_eq!(t[1].conditions[1].rhs, Operand::Value(0x1a));
+    assert_eq!(t[0].livesplit, None);
+    assert_eq!(t[1].livesplit, Some(Command::Split));
+
+    assert!(triggers::parse("[[trigger]]\nname = \"x\"\nwhen = []").is_err());
+    assert!(triggers::parse("[[trigger]]\nname = \"x\"\nwhen = [\"80000000 ~ 1\"]").is_err());
+    assert!(triggers::parse("").unwrap().is_empty());
+    assert!(
+        triggers::parse("[[trigger]]\nname = \"x\"\nwhen = [\"0 == 1\"]\nlivesplit = \"go\"")
+            .is_err()
+    );
+}
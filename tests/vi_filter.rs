@@ -0,0 +1,203 @@
+extern crate emu;
+extern crate image;
+extern crate r64emu;
+
+use emu::gfx::{BufferLineGetter, OwnedGfxBufferLE, Rgb888};
+use image::png::PNGEncoder;
+use image::{ColorType, ImageFormat};
+use r64emu::vifilter::{self, Depth16, Filters, Pixel};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn px(r: u8, g: u8, b: u8, cvg: u8) -> Pixel {
+    Pixel { r, g, b, cvg }
+}
+
+#[test]
+fn status_bits() {
+    assert_eq!(Filters::from_status(0x0000_0302), Filters::empty());
+    assert_eq!(
+        Filters::from_status(0x0000_320E),
+        Filters::RESAMPLE | Filters::GAMMA | Filters::GAMMA_DITHER
+    );
+    assert_eq!(
+        Filters::from_status(0x0001_0012),
+        Filters::AA | Filters::RESAMPLE | Filters::DITHER | Filters::DIVOT
+    );
+}
+
+#[test]
+fn fetch_16bit() {
+    // White with the alpha bit, and pure red without it: like the VI, the
+    // low bits of the components are zero, and the alpha bit is the top bit
+    // of the coverage.
+    let src = [0xFF, 0xFF, 0xF8, 0x00];
+    let pixels = vifilter::fetch(&src, 2, 1, 2, Depth16::Hardware);
+    assert_eq!(pixels, vec![px(248, 248, 248, 7), px(248, 0, 0, 3)]);
+
+    let pixels = vifilter::fetch(&src, 2, 1, 2, Depth16::Expanded);
+    assert_eq!(pixels, vec![px(255, 255, 255, 7), px(255, 0, 0, 7)]);
+}
+
+#[test]
+fn antialias_edge() {
+    // A partially covered pixel on the edge between a white area (above)
+    // and a black one (below) is blended with the background, according to
+    // its coverage.
+    let (w, h) = (5, 3);
+    let (white, black) = (px(255, 255, 255, 7), px(0, 0, 0, 7));
+    let mut pixels = vec![white; w];
+    pixels.extend_from_slice(&[white, white, px(255, 255, 255, 3), black, black]);
+    pixels.extend_from_slice(&[black; 5]);
+
+    let mut raw = pixels.clone();
+    vifilter::restore(&mut raw, w, h, Filters::empty());
+    assert_eq!(raw, pixels);
+
+    vifilter::restore(&mut pixels, w, h, Filters::AA);
+    assert_eq!(pixels[w + 2], px(128, 128, 128, 3));
+}
+
+#[test]
+fn dither_filter() {
+    // A flat area dithered between two adjacent 5-bit values is restored
+    // to an intermediate 8-bit value.
+    let (w, h) = (3, 3);
+    let lo = px(0x80, 0x80, 0x80, 7);
+    let hi = px(0x88, 0x88, 0x88, 7);
+    let mut pixels: Vec<Pixel> = (0..w * h)
+        .map(|idx| if idx % 2 == 0 { lo } else { hi })
+        .collect();
+    vifilter::restore(&mut pixels, w, h, Filters::DITHER);
+    assert_eq!(pixels[w + 1].r, 0x84);
+}
+
+#[test]
+fn divot() {
+    // A notch along an antialiased edge is replaced by the median of the
+    // pixel and its neighbors; fully covered areas are not affected.
+    let (w, h) = (5, 2);
+    let mut pixels = vec![
+        px(100, 100, 100, 7),
+        px(200, 200, 200, 7),
+        px(10, 10, 10, 7),
+        px(200, 200, 200, 5),
+        px(200, 200, 200, 7),
+    ];
+    let row = pixels.clone();
+    pixels.extend_from_slice(&row);
+    pixels[w + 3].cvg = 7;
+    vifilter::restore(&mut pixels, w, h, Filters::DIVOT);
+    assert_eq!(pixels[1].r, 200);
+    assert_eq!(pixels[2].r, 200);
+    assert_eq!(pixels[w + 2].r, 10);
+}
+
+#[test]
+fn scale_and_gamma() {
+    let pixels = vec![px(0, 0, 0, 7), px(64, 64, 64, 7)];
+    let mut buf = OwnedGfxBufferLE::<Rgb888>::new(4, 1);
+    let row = |buf: &OwnedGfxBufferLE<Rgb888>| -> Vec<i32> {
+        (0..4)
+            .map(|x| buf.buf().line(0).get(x).components().0)
+            .collect()
+    };
+
+    // Replicated pixels
+    vifilter::scale(&pixels, 2, 1, Filters::empty(), 0, &mut buf.buf_mut());
+    assert_eq!(row(&buf), vec![0, 0, 64, 64]);
+
+    // Interpolated pixels
+    vifilter::scale(&pixels, 2, 1, Filters::RESAMPLE, 0, &mut buf.buf_mut());
+    assert_eq!(row(&buf), vec![0, 32, 64, 64]);
+
+    // Gamma boost: sqrt(64/255)
+    vifilter::scale(&pixels, 2, 1, Filters::GAMMA, 0, &mut buf.buf_mut());
+    assert_eq!(row(&buf)[3], 128);
+}
+
+// Framebuffer dumps (raw RDRAM contents) in tests/vi_filter are converted to
+// the screen (at twice their size) like the VI does, with the filters enabled
+// by the specified VI_STATUS, and compared against the golden PNG files next
+// to them. The dumps are synthetic scenes: a dithered gradient, and an
+// antialiased edge. Run with UPDATE_GOLDEN=1 to regenerate the golden files
+// after an intended change of the filters.
+fn check_golden(dump: &str, golden: &str, bpp: u32, filters: Filters, depth16: Depth16) {
+    const W: usize = 32;
+    const H: usize = 24;
+
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/vi_filter");
+    let src = fs::read(dir.join(format!("{}.bin", dump))).unwrap();
+    let mut pixels = vifilter::fetch(&src, W, H, bpp, depth16);
+    vifilter::restore(&mut pixels, W, H, filters);
+    let mut screen = OwnedGfxBufferLE::<Rgb888>::new(W * 2, H * 2);
+    vifilter::scale(&pixels, W, H, filters, 0, &mut screen.buf_mut());
+
+    let mut actual = Vec::with_capacity(W * H * 12);
+    for y in 0..H * 2 {
+        let buf = screen.buf();
+        let line = buf.line(y);
+        for x in 0..W * 2 {
+            let (r, g, b, _) = line.get(x).components();
+            actual.extend_from_slice(&[r as u8, g as u8, b as u8]);
+        }
+    }
+
+    let path = dir.join(format!("{}.png", golden));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        let f = fs::File::create(&path).unwrap();
+        PNGEncoder::new(f)
+            .encode(&actual, W as u32 * 2, H as u32 * 2, ColorType::RGB(8))
+            .unwrap();
+        return;
+    }
+    let data =
+        fs::read(&path).unwrap_or_else(|err| panic!("cannot read {}: {}", path.display(), err));
+    let expected = image::load_from_memory_with_format(&data, ImageFormat::PNG)
+        .unwrap()
+        .to_rgb();
+    assert_eq!(expected.dimensions(), (W as u32 * 2, H as u32 * 2));
+    let expected = expected.into_raw();
+    if let Some(idx) = (0..actual.len()).find(|&i| actual[i] != expected[i]) {
+        let (x, y) = (idx / 3 % (W * 2), idx / 3 / (W * 2));
+        panic!(
+            "screen differs from {} at {}, {} (run with UPDATE_GOLDEN=1 to update)\n\
+             expected: {:?}\nactual: {:?}",
+            path.display(),
+            x,
+            y,
+            &expected[idx / 3 * 3..][..3],
+            &actual[idx / 3 * 3..][..3],
+        );
+    }
+}
+
+#[test]
+fn golden_dither16() {
+    let filters = Filters::from_status(0x0001_0012);
+    check_golden("dither16", "dither16", 2, filters, Depth16::Hardware);
+}
+
+#[test]
+fn golden_edge16() {
+    let filters = Filters::from_status(0x0001_0012);
+    check_golden("edge16", "edge16", 2, filters, Depth16::Hardware);
+}
+
+#[test]
+fn golden_edge16_raw() {
+    check_golden(
+        "edge16",
+        "edge16_raw",
+        2,
+        Filters::empty(),
+        Depth16::Expanded,
+    );
+}
+
+#[test]
+fn golden_edge32() {
+    let filters = Filters::from_status(0x0000_0013);
+    check_golden("edge32", "edge32", 3, filters, Depth16::Hardware);
+}
@@ -0,0 +1,223 @@
+//! Frame-hash based bisection of regressions.
+//!
+//! `bisect-helper` runs a ROM headless up to a frame, with a deterministic
+//! clock, and prints the hash of the framebuffer and of the whole state of
+//! the machine at that frame. The hash of the framebuffer is the same
+//! reported by `regress`.
+//!
+//! The exit status follows the convention of `git bisect run`: 0 if the
+//! commit is good, 1 if it is bad, 125 if it cannot be tested. A commit is
+//! good when the hash matches `--good` (or does not match `--bad`); with
+//! `--determinism`, the ROM is run twice and the commit is good when both
+//! runs end in the same state. Other errors (eg: the ROM cannot be loaded)
+//! abort the bisection.
+//!
+//! Each run happens in a worker process (the same executable, invoked with
+//! `--worker`), as the emulator keeps its state in thread-local storage.
+
+use emu::gfx::{OwnedGfxBufferLE, Rgb888};
+use emu::hw::OutputProducer;
+use emu::snd::{OwnedSndBuffer, S16_STEREO};
+use emu::time::FixedTime;
+use failure::{bail, format_err, Error};
+use r64emu::N64Builder;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use structopt::StructOpt;
+
+// Exit codes understood by git bisect run.
+const EXIT_GOOD: i32 = 0;
+const EXIT_BAD: i32 = 1;
+const EXIT_SKIP: i32 = 125;
+const EXIT_ABORT: i32 = 128;
+
+#[derive(StructOpt)]
+#[structopt(name = "bisect-helper")]
+struct Cli {
+    /// Path to the PIF ROM
+    #[structopt(long = "bios", parse(from_os_str), default_value = "bios/pifdata.bin")]
+    bios: PathBuf,
+
+    /// Frame at which the hashes are computed
+    #[structopt(long = "frames", default_value = "300")]
+    frames: usize,
+
+    /// Start the cartridge clock at this time (seconds since the UNIX epoch)
+    #[structopt(long = "fixed-time", default_value = "0")]
+    fixed_time: u64,
+
+    /// Compare the hash of the state instead of the one of the framebuffer
+    #[structopt(long = "state")]
+    state: bool,
+
+    /// Hash of a good commit: the commit is bad if the hash is different
+    #[structopt(long = "good", conflicts_with = "bad")]
+    good: Option<String>,
+
+    /// Hash of a bad commit: the commit is good if the hash is different
+    #[structopt(long = "bad")]
+    bad: Option<String>,
+
+    /// Run the ROM twice: the commit is bad if the two runs differ
+    #[structopt(long = "determinism")]
+    determinism: bool,
+
+    /// Skip the commit (instead of marking it bad) if the emulator crashes
+    #[structopt(long = "skip-crash")]
+    skip_crash: bool,
+
+    /// Run the ROM in this process (internal: used by the worker processes)
+    #[structopt(long = "worker", raw(hidden = "true"))]
+    worker: bool,
+
+    /// ROM to run
+    #[structopt(parse(from_os_str))]
+    rom: PathBuf,
+}
+
+// Result of a run: the hashes of the framebuffer and of the state.
+#[derive(PartialEq)]
+struct Hashes {
+    frame: u32,
+    state: u32,
+}
+
+impl Hashes {
+    fn parse(out: &str) -> Option<Hashes> {
+        let mut frame = None;
+        let mut state = None;
+        for line in out.lines() {
+            let mut fields = line.split_whitespace();
+            let (key, val) = (fields.next(), fields.next());
+            let val = val.and_then(|v| u32::from_str_radix(v, 16).ok());
+            match key {
+                Some("frame") => frame = val,
+                Some("state") => state = val,
+                _ => {}
+            }
+        }
+        Some(Hashes {
+            frame: frame?,
+            state: state?,
+        })
+    }
+}
+
+// Worker: run the ROM, and print the hashes.
+fn run_worker(args: &Cli) -> Result<(), Error> {
+    let logger = slog::Logger::root(slog::Discard, slog::o!());
+    let mut n64 = N64Builder::new(logger)
+        .rom(&args.rom)
+        .bios(&args.bios)
+        .time_source(Box::new(FixedTime::from_unix(args.fixed_time)))
+        .build()?;
+
+    let mut screen = OwnedGfxBufferLE::<Rgb888>::new(640, 480);
+    let mut sound = OwnedSndBuffer::<S16_STEREO>::with_capacity(4096);
+    for _ in 0..args.frames {
+        n64.render_frame(&mut screen.buf_mut(), &mut sound.buf_mut());
+    }
+
+    let frame = {
+        let buf = screen.buf();
+        let (raw, _pitch) = buf.raw();
+        crc::crc32::checksum_ieee(raw)
+    };
+    let mut state = Vec::new();
+    n64.save_state(&mut state)?;
+    println!("frame {:08x}", frame);
+    println!("state {:08x}", crc::crc32::checksum_ieee(&state));
+    Ok(())
+}
+
+// Run the ROM in a worker process. Returns None if the worker crashed.
+fn spawn_worker(args: &Cli) -> Result<Option<Hashes>, Error> {
+    let out = Command::new(std::env::current_exe()?)
+        .arg("--worker")
+        .arg("--bios")
+        .arg(&args.bios)
+        .arg("--frames")
+        .arg(args.frames.to_string())
+        .arg("--fixed-time")
+        .arg(args.fixed_time.to_string())
+        .arg(&args.rom)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()?;
+    match out.status.code() {
+        Some(0) => {}
+        // The worker reports errors that happen before running the ROM.
+        Some(EXIT_ABORT) => bail!("cannot run {}", args.rom.display()),
+        _ => return Ok(None),
+    }
+    let out = String::from_utf8_lossy(&out.stdout);
+    Hashes::parse(&out)
+        .map(Some)
+        .ok_or_else(|| format_err!("invalid output from worker: {:?}", out))
+}
+
+fn parse_hash(hash: &str) -> Result<u32, Error> {
+    u32::from_str_radix(hash.trim_start_matches("0x"), 16)
+        .map_err(|_| format_err!("invalid hash: {}", hash))
+}
+
+// Run the bisection step, returning the exit code for git bisect.
+fn run(args: &Cli) -> Result<i32, Error> {
+    let good = args.good.as_ref().map(|h| parse_hash(h)).transpose()?;
+    let bad = args.bad.as_ref().map(|h| parse_hash(h)).transpose()?;
+    let crash = if args.skip_crash { EXIT_SKIP } else { EXIT_BAD };
+
+    let first = match spawn_worker(args)? {
+        Some(h) => h,
+        None => {
+            println!("crash");
+            return Ok(crash);
+        }
+    };
+    println!("frame {:08x}", first.frame);
+    println!("state {:08x}", first.state);
+
+    if args.determinism {
+        let second = match spawn_worker(args)? {
+            Some(h) => h,
+            None => {
+                println!("crash (second run)");
+                return Ok(crash);
+            }
+        };
+        if second != first {
+            println!(
+                "non-deterministic: second run ended with frame {:08x}, state {:08x}",
+                second.frame, second.state
+            );
+            return Ok(EXIT_BAD);
+        }
+    }
+
+    let hash = if args.state { first.state } else { first.frame };
+    Ok(match (good, bad) {
+        (Some(good), _) if hash != good => EXIT_BAD,
+        (_, Some(bad)) if hash == bad => EXIT_BAD,
+        _ => EXIT_GOOD,
+    })
+}
+
+fn main() {
+    let args = Cli::from_args();
+    if args.worker {
+        // A panic makes the worker exit with a different code, and it is
+        // reported as a crash.
+        if let Err(err) = run_worker(&args) {
+            eprintln!("bisect-helper: {}", err);
+            std::process::exit(EXIT_ABORT);
+        }
+        return;
+    }
+    match run(&args) {
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            eprintln!("bisect-helper: {}", err);
+            std::process::exit(EXIT_ABORT);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use crate::PackError;
+use byteorder::{BigEndian, ByteOrder};
+
+const PT_LOAD: u32 = 1;
+const EM_MIPS: u16 = 8;
+
+/// A loadable segment of the program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub vaddr: u32,
+    pub data: Vec<u8>,
+    pub memsz: u32, // size in memory; bytes beyond data are zero (BSS)
+}
+
+/// A program to be packed: its loadable segments and its entrypoint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+}
+
+fn invalid(msg: &str) -> PackError {
+    PackError::InvalidElf {
+        msg: msg.to_owned(),
+    }
+}
+
+impl Program {
+    /// Parse a 32-bit big-endian MIPS ELF executable.
+    pub fn from_elf(elf: &[u8]) -> Result<Program, PackError> {
+        if elf.len() < 0x34 || &elf[0..4] != b"\x7FELF" {
+            return Err(invalid("not an ELF file"));
+        }
+        if elf[4] != 1 || elf[5] != 2 {
+            return Err(invalid("not a 32-bit big-endian ELF file"));
+        }
+        if BigEndian::read_u16(&elf[0x12..]) != EM_MIPS {
+            return Err(invalid("not a MIPS executable"));
+        }
+
+        let entry = BigEndian::read_u32(&elf[0x18..]);
+        let phoff = BigEndian::read_u32(&elf[0x1C..]) as usize;
+        let phentsize = BigEndian::read_u16(&elf[0x2A..]) as usize;
+        let phnum = BigEndian::read_u16(&elf[0x2C..]) as usize;
+
+        let mut segments = Vec::new();
+        for idx in 0..phnum {
+            let ph = elf
+                .get(phoff + idx * phentsize..phoff + idx * phentsize + 0x20)
+                .ok_or_else(|| invalid("truncated program header"))?;
+            let (ptype, offset, vaddr) = (
+                BigEndian::read_u32(&ph[0x00..]),
+                BigEndian::read_u32(&ph[0x04..]) as usize,
+                BigEndian::read_u32(&ph[0x08..]),
+            );
+            let (filesz, memsz) = (
+                BigEndian::read_u32(&ph[0x10..]) as usize,
+                BigEndian::read_u32(&ph[0x14..]),
+            );
+            if ptype != PT_LOAD || memsz == 0 {
+                continue;
+            }
+            let data = elf
+                .get(offset..offset + filesz)
+                .ok_or_else(|| invalid("truncated segment"))?;
+            segments.push(Segment {
+                vaddr,
+                data: data.to_vec(),
+                memsz,
+            });
+        }
+        if segments.is_empty() {
+            return Err(invalid("no loadable segments"));
+        }
+        segments.sort_by_key(|s| s.vaddr);
+        Ok(Program { entry, segments })
+    }
+
+    /// Create a program from a flat binary, loaded at the specified address
+    /// and starting from its first byte.
+    pub fn from_binary(data: &[u8], vaddr: u32) -> Program {
+        Program {
+            entry: vaddr,
+            segments: vec![Segment {
+                vaddr,
+                data: data.to_vec(),
+                memsz: data.len() as u32,
+            }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a minimal ELF file with a single PT_LOAD segment.
+    fn elf(vaddr: u32, data: &[u8], memsz: u32) -> Vec<u8> {
+        let mut elf = vec![0u8; 0x54];
+        elf[0..6].copy_from_slice(b"\x7FELF\x01\x02");
+        BigEndian::write_u16(&mut elf[0x12..], EM_MIPS);
+        BigEndian::write_u32(&mut elf[0x18..], vaddr + 8);
+        BigEndian::write_u32(&mut elf[0x1C..], 0x34);
+        BigEndian::write_u16(&mut elf[0x2A..], 0x20);
+        BigEndian::write_u16(&mut elf[0x2C..], 1);
+        BigEndian::write_u32(&mut elf[0x34..], PT_LOAD);
+        BigEndian::write_u32(&mut elf[0x38..], 0x54);
+        BigEndian::write_u32(&mut elf[0x3C..], vaddr);
+        BigEndian::write_u32(&mut elf[0x44..], data.len() as u32);
+        BigEndian::write_u32(&mut elf[0x48..], memsz);
+        elf.extend_from_slice(data);
+        elf
+    }
+
+    #[test]
+    fn parse() {
+        let prog = Program::from_elf(&elf(0x8000_0400, &[1, 2, 3, 4], 16)).unwrap();
+        assert_eq!(prog.entry, 0x8000_0408);
+        assert_eq!(
+            prog.segments,
+            vec![Segment {
+                vaddr: 0x8000_0400,
+                data: vec![1, 2, 3, 4],
+                memsz: 16,
+            }]
+        );
+
+        let mut bad = elf(0x8000_0400, &[1, 2, 3, 4], 16);
+        bad[5] = 1; // little-endian
+        assert!(Program::from_elf(&bad).is_err());
+        assert!(Program::from_elf(&bad[..0x40]).is_err());
+    }
+}
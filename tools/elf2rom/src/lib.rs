@@ -0,0 +1,255 @@
+//! Pack a MIPS program into a bootable N64 ROM.
+//!
+//! The ROM is made of a standard header, the boot code (IPL3) taken from
+//! an existing ROM, a small boot stub that loads the program, and the
+//! program image itself; the header CRCs are computed for the CIC that
+//! matches the boot code. This allows test programs and homebrew to be
+//! built entirely from Rust build scripts:
+//!
+//! ```no_run
+//! use elf2rom::{pack, Program, RomConfig};
+//!
+//! let elf = std::fs::read("test.elf").unwrap();
+//! let ipl3 = elf2rom::bootcode(&std::fs::read("ipl3.bin").unwrap()).unwrap();
+//! let rom = pack(&Program::from_elf(&elf).unwrap(), &ipl3, &RomConfig::default()).unwrap();
+//! std::fs::write("test.z64", rom).unwrap();
+//! ```
+
+// failure_derive defines its impls inside named consts.
+#![allow(unknown_lints, non_local_definitions)]
+
+mod elf;
+mod stub;
+
+pub use self::elf::{Program, Segment};
+
+use byteorder::{BigEndian, ByteOrder};
+use failure::Fail;
+use r64emu::cartridge::{compute_crcs, detect_cic, romswap, CicModel, CRC_LENGTH, CRC_START};
+use r64emu::errors::LoadError;
+use stub::StubConfig;
+
+/// Size of the boot code (IPL3), stored at offset 0x40 of the ROM.
+pub const BOOTCODE_SIZE: usize = 0x1000 - 0x40;
+
+// Address where IPL3 loads the boot stub. It is far from the usual address
+// of programs (0x80000400), so that they can be loaded there.
+const STUB_ADDR: u32 = 0x8030_0000;
+const STUB_MAX_SIZE: usize = 0x100;
+
+// Offset of the program image in the ROM, after the boot stub.
+const IMAGE_ROM: u32 = CRC_START as u32 + STUB_MAX_SIZE as u32;
+
+#[derive(Debug, Fail)]
+pub enum PackError {
+    #[fail(display = "invalid ELF file: {}", msg)]
+    InvalidElf { msg: String },
+
+    #[fail(display = "invalid boot code: {}", err)]
+    InvalidBootcode {
+        #[cause]
+        err: LoadError,
+    },
+
+    #[fail(display = "program address outside of RDRAM: {:08x}", addr)]
+    InvalidAddress { addr: u32 },
+
+    #[fail(display = "program overlaps the boot stub at {:08x}", addr)]
+    StubOverlap { addr: u32 },
+
+    #[fail(
+        display = "invalid game code: {:?} (4 ASCII characters expected)",
+        code
+    )]
+    InvalidGameCode { code: String },
+}
+
+/// Contents of the ROM header, and settings of the boot stub.
+#[derive(Clone, Debug)]
+pub struct RomConfig {
+    pub title: String,     // at most 20 ASCII characters
+    pub game_code: String, // media, 2-character ID, region (eg: "NR6E")
+    pub stack_top: u32,    // initial stack pointer
+}
+
+impl Default for RomConfig {
+    fn default() -> Self {
+        RomConfig {
+            title: "R64EMU TEST".into(),
+            game_code: "NR6E".into(),
+            stack_top: 0x8040_0000,
+        }
+    }
+}
+
+/// Extract the boot code from a file, which can be either a ROM (in any
+/// byte order) or the boot code alone.
+pub fn bootcode(data: &[u8]) -> Result<Vec<u8>, PackError> {
+    if data.len() == BOOTCODE_SIZE {
+        return Ok(data.to_vec());
+    }
+    if data.len() < 0x1000 {
+        return Err(PackError::InvalidBootcode {
+            err: LoadError::RomTooSmall { size: data.len() },
+        });
+    }
+    let rom = romswap(data[..0x1000].to_vec()).map_err(|err| PackError::InvalidBootcode { err })?;
+    Ok(rom[0x40..0x1000].to_vec())
+}
+
+/// Pack a program into a ROM. The CIC is detected from the boot code.
+pub fn pack(prog: &Program, bootcode: &[u8], cfg: &RomConfig) -> Result<Vec<u8>, PackError> {
+    let cic = detect_cic(bootcode).map_err(|err| PackError::InvalidBootcode { err })?;
+    pack_with_cic(prog, bootcode, cic, cfg)
+}
+
+fn check_addr(addr: u32) -> Result<u32, PackError> {
+    match addr {
+        0x8000_0000..=0xBFFF_FFFF => Ok(addr),
+        _ => Err(PackError::InvalidAddress { addr }),
+    }
+}
+
+fn align(val: u32, align: u32) -> u32 {
+    (val + align - 1) & !(align - 1)
+}
+
+fn pack_with_cic(
+    prog: &Program,
+    bootcode: &[u8],
+    cic: CicModel,
+    cfg: &RomConfig,
+) -> Result<Vec<u8>, PackError> {
+    let code = cfg.game_code.as_bytes();
+    if code.len() != 4 || !code.iter().all(|c| c.is_ascii_graphic()) {
+        return Err(PackError::InvalidGameCode {
+            code: cfg.game_code.clone(),
+        });
+    }
+
+    // Flatten the segments into a single image; the zero-filled part of
+    // the last segments (BSS) is cleared by the stub instead.
+    let base = check_addr(prog.segments[0].vaddr & !7)?;
+    let file_end = prog
+        .segments
+        .iter()
+        .map(|s| s.vaddr + s.data.len() as u32)
+        .max()
+        .unwrap();
+    let mem_end = prog
+        .segments
+        .iter()
+        .map(|s| s.vaddr + s.memsz)
+        .max()
+        .unwrap();
+    let image_end = align(file_end, 8);
+    let bss_end = align(mem_end, 4).max(image_end);
+    check_addr(bss_end - 1)?;
+    check_addr(prog.entry)?;
+
+    let mut image = vec![0u8; (image_end - base) as usize];
+    for s in &prog.segments {
+        let off = (s.vaddr - base) as usize;
+        image[off..off + s.data.len()].copy_from_slice(&s.data);
+    }
+
+    let stub = stub::assemble(&StubConfig {
+        image_vaddr: base,
+        image_rom: IMAGE_ROM,
+        image_len: image.len() as u32,
+        bss: (image_end, bss_end),
+        stack_top: cfg.stack_top,
+        entry: prog.entry,
+    });
+    assert!(stub.len() <= STUB_MAX_SIZE);
+    let phys = |addr: u32| addr & 0x1FFF_FFFF;
+    let stub_range = phys(STUB_ADDR)..phys(STUB_ADDR) + stub.len() as u32;
+    if phys(base) < stub_range.end && stub_range.start < phys(bss_end) {
+        return Err(PackError::StubOverlap { addr: STUB_ADDR });
+    }
+
+    // Pad to a power of two, as done when loading the ROM, so that the
+    // CRCs are computed on the same contents.
+    let size = (IMAGE_ROM as usize + image.len())
+        .max(CRC_START + CRC_LENGTH)
+        .next_power_of_two();
+    let mut rom = vec![0xFFu8; size];
+
+    BigEndian::write_u32(&mut rom[0x00..], 0x8037_1240); // PI timings
+    BigEndian::write_u32(&mut rom[0x04..], 0x0000_000F); // clock rate
+    BigEndian::write_u32(&mut rom[0x08..], STUB_ADDR + cic.entrypoint_offset());
+    BigEndian::write_u32(&mut rom[0x0C..], 0x0000_144C); // libultra release
+    for b in &mut rom[0x18..0x40] {
+        *b = 0;
+    }
+    let title = cfg.title.bytes().filter(|c| c.is_ascii()).take(20);
+    for (idx, c) in title.chain(std::iter::repeat(b' ')).take(20).enumerate() {
+        rom[0x20 + idx] = c;
+    }
+    rom[0x3B..0x3F].copy_from_slice(code);
+
+    rom[0x40..0x1000].copy_from_slice(bootcode);
+    rom[CRC_START..IMAGE_ROM as usize]
+        .iter_mut()
+        .for_each(|b| *b = 0);
+    rom[CRC_START..CRC_START + stub.len()].copy_from_slice(&stub);
+    rom[IMAGE_ROM as usize..IMAGE_ROM as usize + image.len()].copy_from_slice(&image);
+
+    let (crc1, crc2) = compute_crcs(&rom, cic);
+    BigEndian::write_u32(&mut rom[0x10..], crc1);
+    BigEndian::write_u32(&mut rom[0x14..], crc2);
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let prog = Program {
+            entry: 0x8000_0410,
+            segments: vec![Segment {
+                vaddr: 0x8000_0400,
+                data: vec![0xAA; 0x21],
+                memsz: 0x100,
+            }],
+        };
+        let boot = vec![0x55; BOOTCODE_SIZE];
+        let rom = pack_with_cic(&prog, &boot, CicModel::Cic6103, &RomConfig::default()).unwrap();
+
+        assert_eq!(rom.len(), 0x20_0000);
+        assert_eq!(BigEndian::read_u32(&rom[0x00..]), 0x8037_1240);
+        assert_eq!(BigEndian::read_u32(&rom[0x08..]), 0x8040_0000);
+        assert_eq!(&rom[0x20..0x34], b"R64EMU TEST         ");
+        assert_eq!(&rom[0x3B..0x3F], b"NR6E");
+        assert_eq!(&rom[0x40..0x1000], &boot[..]);
+        assert_eq!(&rom[0x1100..0x1121], &[0xAA; 0x21][..]);
+        assert_eq!(&rom[0x1121..0x1128], &[0; 7][..]);
+        assert_eq!(
+            (
+                BigEndian::read_u32(&rom[0x10..]),
+                BigEndian::read_u32(&rom[0x14..])
+            ),
+            compute_crcs(&rom, CicModel::Cic6103)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let boot = vec![0x55; BOOTCODE_SIZE];
+        let cfg = RomConfig::default();
+        let prog = |vaddr| Program::from_binary(&[0; 16], vaddr);
+        let pack = |p: &Program, cfg: &RomConfig| pack_with_cic(p, &boot, CicModel::Cic6102, cfg);
+
+        assert!(pack(&prog(0x0000_0400), &cfg).is_err());
+        assert!(pack(&prog(STUB_ADDR), &cfg).is_err());
+        let bad = RomConfig {
+            game_code: "N".into(),
+            ..cfg.clone()
+        };
+        assert!(pack(&prog(0x8000_0400), &bad).is_err());
+        assert!(pack(&prog(0x8000_0400), &cfg).is_ok());
+        assert!(pack(&prog(0xA000_0400), &cfg).is_ok());
+    }
+}
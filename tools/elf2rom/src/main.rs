@@ -0,0 +1,84 @@
+//! Pack a MIPS program (ELF executable or raw binary) into a bootable N64 ROM.
+
+use elf2rom::{bootcode, pack, Program, RomConfig};
+use failure::Error;
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "elf2rom")]
+struct Cli {
+    /// ROM (or 4032-byte dump) to take the boot code (IPL3) from
+    #[structopt(long = "ipl3", parse(from_os_str))]
+    ipl3: PathBuf,
+
+    /// Input is a raw binary loaded at --base, instead of an ELF executable
+    #[structopt(long = "raw")]
+    raw: bool,
+
+    /// Load address (and entrypoint) of a raw binary
+    #[structopt(
+        long = "base",
+        default_value = "0x80000400",
+        parse(try_from_str = "parse_hex")
+    )]
+    base: u32,
+
+    /// Title stored in the ROM header
+    #[structopt(long = "title", default_value = "R64EMU TEST")]
+    title: String,
+
+    /// Game code stored in the ROM header (media, ID, region)
+    #[structopt(long = "game-code", default_value = "NR6E")]
+    game_code: String,
+
+    /// Initial stack pointer
+    #[structopt(
+        long = "stack",
+        default_value = "0x80400000",
+        parse(try_from_str = "parse_hex")
+    )]
+    stack: u32,
+
+    /// Output ROM file
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: PathBuf,
+
+    /// Program to pack
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+}
+
+fn parse_hex(s: &str) -> Result<u32, std::num::ParseIntError> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    }
+}
+
+fn run(args: &Cli) -> Result<(), Error> {
+    let input = fs::read(&args.input)?;
+    let prog = if args.raw {
+        Program::from_binary(&input, args.base)
+    } else {
+        Program::from_elf(&input)?
+    };
+    let ipl3 = bootcode(&fs::read(&args.ipl3)?)?;
+    let cfg = RomConfig {
+        title: args.title.clone(),
+        game_code: args.game_code.clone(),
+        stack_top: args.stack,
+    };
+    fs::write(&args.output, pack(&prog, &ipl3, &cfg)?)?;
+    Ok(())
+}
+
+fn main() {
+    let args = Cli::from_args();
+    if let Err(err) = run(&args) {
+        eprintln!("elf2rom: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,111 @@
+//! The boot stub, run by IPL3 in place of the program.
+//!
+//! IPL3 copies the first megabyte of the ROM after the boot code (that is,
+//! the stub) to the entrypoint and jumps to it. The stub then loads the
+//! program image to its address with a PI DMA (so that it has no size
+//! limit), clears the BSS, sets up the stack pointer and jumps to the
+//! program entrypoint.
+
+const ZERO: u32 = 0;
+const T0: u32 = 8;
+const T1: u32 = 9;
+const SP: u32 = 29;
+
+const PI_BASE: u32 = 0xA460_0000;
+
+/// Parameters of the boot stub; addresses are virtual (KSEG0/KSEG1).
+pub struct StubConfig {
+    pub image_vaddr: u32, // where to load the program image
+    pub image_rom: u32,   // offset of the image in the ROM
+    pub image_len: u32,   // length of the image (multiple of 8)
+    pub bss: (u32, u32),  // area to clear (start, end), word-aligned
+    pub stack_top: u32,
+    pub entry: u32,
+}
+
+// A minimal MIPS assembler: just the instructions needed by the stub.
+#[derive(Default)]
+struct Asm(Vec<u32>);
+
+impl Asm {
+    fn itype(&mut self, op: u32, rs: u32, rt: u32, imm: u32) {
+        self.0.push(op << 26 | rs << 21 | rt << 16 | (imm & 0xFFFF));
+    }
+    fn pos(&self) -> usize {
+        self.0.len()
+    }
+    fn li(&mut self, rt: u32, val: u32) {
+        self.itype(0x0F, ZERO, rt, val >> 16); // lui
+        self.itype(0x0D, rt, rt, val); // ori
+    }
+    fn lui(&mut self, rt: u32, imm: u32) {
+        self.itype(0x0F, ZERO, rt, imm);
+    }
+    fn addiu(&mut self, rt: u32, rs: u32, imm: u32) {
+        self.itype(0x09, rs, rt, imm);
+    }
+    fn andi(&mut self, rt: u32, rs: u32, imm: u32) {
+        self.itype(0x0C, rs, rt, imm);
+    }
+    fn lw(&mut self, rt: u32, off: u32, base: u32) {
+        self.itype(0x23, base, rt, off);
+    }
+    fn sw(&mut self, rt: u32, off: u32, base: u32) {
+        self.itype(0x2B, base, rt, off);
+    }
+    // Branch backward to the specified position (followed by a nop
+    // in the delay slot).
+    fn bne(&mut self, rs: u32, rt: u32, target: usize) {
+        let off = target as i32 - (self.pos() as i32 + 1);
+        self.itype(0x05, rs, rt, off as u32);
+        self.nop();
+    }
+    fn jr(&mut self, rs: u32) {
+        self.0.push(rs << 21 | 0x08);
+        self.nop();
+    }
+    fn nop(&mut self) {
+        self.0.push(0);
+    }
+
+    // Wait until the PI is idle (PI base address in T0).
+    fn pi_wait(&mut self) {
+        let wait = self.pos();
+        self.lw(T1, 0x10, T0); // PI_STATUS
+        self.andi(T1, T1, 3); // DMA busy | IO busy
+        self.bne(T1, ZERO, wait);
+    }
+}
+
+/// Assemble the boot stub, returning its code as big-endian bytes.
+pub fn assemble(cfg: &StubConfig) -> Vec<u8> {
+    let mut a = Asm::default();
+
+    // PI DMA: ROM -> RDRAM
+    a.lui(T0, PI_BASE >> 16);
+    a.pi_wait();
+    a.li(T1, cfg.image_vaddr & 0x1FFF_FFFF);
+    a.sw(T1, 0x00, T0); // PI_DRAM_ADDR
+    a.li(T1, 0x1000_0000 + cfg.image_rom);
+    a.sw(T1, 0x04, T0); // PI_CART_ADDR
+    a.li(T1, cfg.image_len - 1);
+    a.sw(T1, 0x0C, T0); // PI_WR_LEN: starts the transfer
+    a.pi_wait();
+
+    // Clear the BSS
+    let (start, end) = cfg.bss;
+    if end > start {
+        a.li(T0, start);
+        a.li(T1, end);
+        let clear = a.pos();
+        a.sw(ZERO, 0, T0);
+        a.addiu(T0, T0, 4);
+        a.bne(T0, T1, clear);
+    }
+
+    a.li(SP, cfg.stack_top);
+    a.li(T0, cfg.entry);
+    a.jr(T0);
+
+    a.0.iter().flat_map(|w| w.to_be_bytes().to_vec()).collect()
+}
@@ -0,0 +1,576 @@
+//! A minimal assembler for RSP code.
+//!
+//! It accepts the subset of the bass syntax (`arch n64.rsp`) used by the
+//! golden test suites: labels, the scalar opcodes and pseudo-opcodes, the
+//! COP2 moves, and the vector loads, stores and opcodes, with the same
+//! encodings as bass, so that the generated binaries are identical.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+/// An error found while assembling a source.
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize, // 1-based
+    pub msg: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl error::Error for AsmError {}
+
+type Result<T> = std::result::Result<T, String>;
+
+/// Assemble RSP code (at address 0 of IMEM) into a big-endian binary.
+pub fn assemble(src: &str) -> std::result::Result<Vec<u8>, AsmError> {
+    assemble_at(src, 0)
+}
+
+/// Assemble code to be run at the specified address. Being a subset of the
+/// CPU instruction set, this also works for simple CPU programs.
+pub fn assemble_at(src: &str, base: u32) -> std::result::Result<Vec<u8>, AsmError> {
+    // First pass: find the statements and the addresses of the labels.
+    let mut labels = HashMap::new();
+    let mut stmts = Vec::new();
+    let mut pc = base;
+    for (idx, line) in src.lines().enumerate() {
+        let err = |msg| AsmError { line: idx + 1, msg };
+        let mut line = line.split("//").next().unwrap().trim();
+        if let Some(pos) = line.find(':') {
+            let label = line[..pos].trim();
+            if !is_ident(label) {
+                return Err(err(format!("invalid label: {:?}", label)));
+            }
+            if labels.insert(label.to_owned(), pc).is_some() {
+                return Err(err(format!("duplicated label: {}", label)));
+            }
+            line = line[pos + 1..].trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, args) = match line.find(char::is_whitespace) {
+            Some(pos) => (&line[..pos], line[pos..].trim()),
+            None => (line, ""),
+        };
+        let args: Vec<&str> = match args {
+            "" => Vec::new(),
+            _ => args.split(',').map(str::trim).collect(),
+        };
+        let mnemonic = mnemonic.to_lowercase();
+        let size = match mnemonic.as_str() {
+            "li" | "la" => 8,
+            _ => 4,
+        };
+        stmts.push((idx + 1, pc, mnemonic, args));
+        pc += size;
+    }
+
+    // Second pass: encode the statements.
+    let mut out = Vec::with_capacity((pc - base) as usize);
+    for (line, pc, mnemonic, args) in stmts {
+        let asm = Stmt {
+            pc,
+            args: &args,
+            labels: &labels,
+        };
+        let words = asm
+            .encode(&mnemonic)
+            .map_err(|msg| AsmError { line, msg })?;
+        for w in words {
+            out.extend_from_slice(&[(w >> 24) as u8, (w >> 16) as u8, (w >> 8) as u8, w as u8]);
+        }
+    }
+    Ok(out)
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+const GPR_NAMES: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "s8",
+    "ra",
+];
+
+const COP2_CONTROL: [&str; 3] = ["vco", "vcc", "vce"];
+
+// Vector loads and stores: opcode (in the RD field), and size of the
+// access, by which the offset is scaled.
+fn vmem_info(name: &str) -> Option<(u32, u32)> {
+    Some(match name {
+        "bv" => (0x00, 1),
+        "sv" => (0x01, 2),
+        "lv" => (0x02, 4),
+        "dv" => (0x03, 8),
+        "qv" => (0x04, 16),
+        "rv" => (0x05, 16),
+        "pv" => (0x06, 8),
+        "uv" => (0x07, 8),
+        "hv" => (0x08, 16),
+        "fv" => (0x09, 16),
+        "wv" => (0x0A, 16),
+        "tv" => (0x0B, 16),
+        _ => return None,
+    })
+}
+
+// Vector opcodes: function code.
+fn vector_func(name: &str) -> Option<u32> {
+    Some(match name {
+        "vmulf" => 0x00,
+        "vmulu" => 0x01,
+        "vmudl" => 0x04,
+        "vmudm" => 0x05,
+        "vmudn" => 0x06,
+        "vmudh" => 0x07,
+        "vmacf" => 0x08,
+        "vmacu" => 0x09,
+        "vmadl" => 0x0C,
+        "vmadm" => 0x0D,
+        "vmadn" => 0x0E,
+        "vmadh" => 0x0F,
+        "vadd" => 0x10,
+        "vsub" => 0x11,
+        "vabs" => 0x13,
+        "vaddc" => 0x14,
+        "vsubc" => 0x15,
+        "vsubb" => 0x17,
+        "vsucb" => 0x19,
+        "vsar" => 0x1D,
+        "vlt" => 0x20,
+        "veq" => 0x21,
+        "vne" => 0x22,
+        "vge" => 0x23,
+        "vcl" => 0x24,
+        "vch" => 0x25,
+        "vcr" => 0x26,
+        "vmrg" => 0x27,
+        "vand" => 0x28,
+        "vnand" => 0x29,
+        "vor" => 0x2A,
+        "vnor" => 0x2B,
+        "vxor" => 0x2C,
+        "vnxor" => 0x2D,
+        "vrcp" => 0x30,
+        "vrcpl" => 0x31,
+        "vrcph" => 0x32,
+        "vmov" => 0x33,
+        "vrsq" => 0x34,
+        "vrsql" => 0x35,
+        "vrsqh" => 0x36,
+        "vnop" => 0x37,
+        _ => return None,
+    })
+}
+
+fn rtype(rs: u32, rt: u32, rd: u32, sa: u32, func: u32) -> u32 {
+    rs << 21 | rt << 16 | rd << 11 | sa << 6 | func
+}
+
+fn itype(op: u32, rs: u32, rt: u32, imm: i64) -> u32 {
+    op << 26 | rs << 21 | rt << 16 | (imm as u32 & 0xFFFF)
+}
+
+// A statement being encoded.
+struct Stmt<'a> {
+    pc: u32,
+    args: &'a [&'a str],
+    labels: &'a HashMap<String, u32>,
+}
+
+impl<'a> Stmt<'a> {
+    fn nargs(&self, n: &[usize]) -> Result<usize> {
+        if n.contains(&self.args.len()) {
+            Ok(self.args.len())
+        } else {
+            Err(format!("invalid number of operands: {}", self.args.len()))
+        }
+    }
+
+    fn gpr(&self, idx: usize) -> Result<u32> {
+        let arg = self.args[idx];
+        if let Some(r) = GPR_NAMES.iter().position(|&n| n == arg) {
+            return Ok(r as u32);
+        }
+        // Registers can also be specified by number (eg: "r5", or "5").
+        let num = match arg {
+            "fp" => return Ok(30),
+            _ if arg.starts_with('r') => &arg[1..],
+            _ => arg,
+        };
+        match num.parse::<u32>() {
+            Ok(r) if r < 32 => Ok(r),
+            _ => Err(format!("invalid register: {}", arg)),
+        }
+    }
+
+    // Parse a vector register, with an optional element (eg: "v3[e5]").
+    fn vreg(&self, idx: usize) -> Result<(u32, u32)> {
+        let arg = self.args[idx];
+        let err = || format!("invalid vector register: {}", arg);
+        let (reg, elem) = match arg.find('[') {
+            Some(pos) if arg.ends_with(']') => (&arg[..pos], Some(&arg[pos + 1..arg.len() - 1])),
+            Some(_) => return Err(err()),
+            None => (arg, None),
+        };
+        let reg = match reg.trim().trim_start_matches('v').parse::<u32>() {
+            Ok(r) if r < 32 && reg.starts_with('v') => r,
+            _ => return Err(err()),
+        };
+        let elem = match elem {
+            Some(e) => match e.trim().trim_start_matches('e').parse::<u32>() {
+                Ok(e) if e < 16 => e,
+                _ => return Err(err()),
+            },
+            None => 0,
+        };
+        Ok((reg, elem))
+    }
+
+    fn imm(&self, idx: usize) -> Result<i64> {
+        let arg = self.args[idx];
+        if let Some(&addr) = self.labels.get(arg) {
+            return Ok(addr as i64);
+        }
+        let neg = arg.starts_with('-');
+        let num = arg.trim_start_matches('-').trim();
+        let val = if let Some(hex) = num.strip_prefix('$') {
+            i64::from_str_radix(hex, 16)
+        } else if let Some(hex) = num.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16)
+        } else if let Some(bin) = num.strip_prefix('%') {
+            i64::from_str_radix(bin, 2)
+        } else {
+            num.parse::<i64>()
+        };
+        match val {
+            Ok(v) if neg => Ok(-v),
+            Ok(v) => Ok(v),
+            Err(_) => Err(format!("invalid value or unknown label: {}", arg)),
+        }
+    }
+
+    fn imm16(&self, idx: usize) -> Result<i64> {
+        let v = self.imm(idx)?;
+        if !(-0x8000..=0xFFFF).contains(&v) {
+            return Err(format!("immediate out of range: {}", self.args[idx]));
+        }
+        Ok(v)
+    }
+
+    // Parse a memory operand (eg: "$10(a0)"), returning offset and base.
+    fn mem(&self, idx: usize) -> Result<(i64, u32)> {
+        let arg = self.args[idx];
+        let err = || format!("invalid memory operand: {}", arg);
+        let pos = arg.find('(').ok_or_else(err)?;
+        if !arg.ends_with(')') {
+            return Err(err());
+        }
+        let sub = Stmt {
+            pc: self.pc,
+            args: &[arg[..pos].trim(), &arg[pos + 1..arg.len() - 1]],
+            labels: self.labels,
+        };
+        let off = match sub.args[0] {
+            "" => 0,
+            _ => sub.imm(0)?,
+        };
+        Ok((off, sub.gpr(1)?))
+    }
+
+    fn branch_offset(&self, idx: usize) -> Result<i64> {
+        let off = (self.imm(idx)? - (self.pc as i64 + 4)) >> 2;
+        if !(-0x8000..=0x7FFF).contains(&off) {
+            return Err(format!("branch target out of range: {}", self.args[idx]));
+        }
+        Ok(off)
+    }
+
+    fn encode(&self, mnemonic: &str) -> Result<Vec<u32>> {
+        let w = match mnemonic {
+            "nop" => {
+                self.nargs(&[0])?;
+                0
+            }
+            "break" => {
+                self.nargs(&[0])?;
+                0x0D
+            }
+
+            "add" | "addu" | "sub" | "subu" | "and" | "or" | "xor" | "nor" | "slt" | "sltu" => {
+                let func = match mnemonic {
+                    "add" => 0x20,
+                    "addu" => 0x21,
+                    "sub" => 0x22,
+                    "subu" => 0x23,
+                    "and" => 0x24,
+                    "or" => 0x25,
+                    "xor" => 0x26,
+                    "nor" => 0x27,
+                    "slt" => 0x2A,
+                    _ => 0x2B,
+                };
+                let n = self.nargs(&[2, 3])?;
+                let rd = self.gpr(0)?;
+                let rs = if n == 3 { self.gpr(1)? } else { rd };
+                rtype(rs, self.gpr(n - 1)?, rd, 0, func)
+            }
+
+            "addi" | "addiu" | "subi" | "subiu" | "slti" | "sltiu" | "andi" | "ori" | "xori" => {
+                let op = match mnemonic {
+                    "addi" | "subi" => 0x08,
+                    "addiu" | "subiu" => 0x09,
+                    "slti" => 0x0A,
+                    "sltiu" => 0x0B,
+                    "andi" => 0x0C,
+                    "ori" => 0x0D,
+                    _ => 0x0E,
+                };
+                let n = self.nargs(&[2, 3])?;
+                let rt = self.gpr(0)?;
+                let rs = if n == 3 { self.gpr(1)? } else { rt };
+                let mut imm = self.imm16(n - 1)?;
+                if mnemonic.starts_with("sub") {
+                    imm = -imm;
+                }
+                itype(op, rs, rt, imm)
+            }
+
+            "lui" => {
+                self.nargs(&[2])?;
+                itype(0x0F, 0, self.gpr(0)?, self.imm16(1)?)
+            }
+
+            "li" | "la" => {
+                self.nargs(&[2])?;
+                let rt = self.gpr(0)?;
+                let val = self.imm(1)?;
+                return Ok(vec![
+                    itype(0x0F, 0, rt, val >> 16),
+                    itype(0x0D, rt, rt, val & 0xFFFF),
+                ]);
+            }
+
+            "sll" | "srl" | "sra" => {
+                let func = match mnemonic {
+                    "sll" => 0x00,
+                    "srl" => 0x02,
+                    _ => 0x03,
+                };
+                let n = self.nargs(&[2, 3])?;
+                let rd = self.gpr(0)?;
+                let rt = if n == 3 { self.gpr(1)? } else { rd };
+                let sa = self.imm(n - 1)?;
+                if !(0..32).contains(&sa) {
+                    return Err(format!("invalid shift amount: {}", sa));
+                }
+                rtype(0, rt, rd, sa as u32, func)
+            }
+
+            "sllv" | "srlv" | "srav" => {
+                let func = match mnemonic {
+                    "sllv" => 0x04,
+                    "srlv" => 0x06,
+                    _ => 0x07,
+                };
+                self.nargs(&[3])?;
+                rtype(self.gpr(2)?, self.gpr(1)?, self.gpr(0)?, 0, func)
+            }
+
+            "lb" | "lh" | "lw" | "lbu" | "lhu" | "sb" | "sh" | "sw" => {
+                let op = match mnemonic {
+                    "lb" => 0x20,
+                    "lh" => 0x21,
+                    "lw" => 0x23,
+                    "lbu" => 0x24,
+                    "lhu" => 0x25,
+                    "sb" => 0x28,
+                    "sh" => 0x29,
+                    _ => 0x2B,
+                };
+                self.nargs(&[2])?;
+                let (off, base) = self.mem(1)?;
+                itype(op, base, self.gpr(0)?, off)
+            }
+
+            "beq" | "bne" => {
+                let op = if mnemonic == "beq" { 0x04 } else { 0x05 };
+                self.nargs(&[3])?;
+                itype(op, self.gpr(0)?, self.gpr(1)?, self.branch_offset(2)?)
+            }
+            "beqz" | "bnez" | "blez" | "bgtz" => {
+                let op = match mnemonic {
+                    "beqz" => 0x04,
+                    "bnez" => 0x05,
+                    "blez" => 0x06,
+                    _ => 0x07,
+                };
+                self.nargs(&[2])?;
+                itype(op, self.gpr(0)?, 0, self.branch_offset(1)?)
+            }
+            "bltz" | "bgez" => {
+                let rt = if mnemonic == "bltz" { 0x00 } else { 0x01 };
+                self.nargs(&[2])?;
+                itype(0x01, self.gpr(0)?, rt, self.branch_offset(1)?)
+            }
+            "b" => {
+                self.nargs(&[1])?;
+                itype(0x04, 0, 0, self.branch_offset(0)?)
+            }
+
+            "j" | "jal" => {
+                let op = if mnemonic == "j" { 0x02 } else { 0x03 };
+                self.nargs(&[1])?;
+                op << 26 | ((self.imm(0)? as u32 >> 2) & 0x3FF_FFFF)
+            }
+            "jr" => {
+                self.nargs(&[1])?;
+                rtype(self.gpr(0)?, 0, 0, 0, 0x08)
+            }
+            "jalr" => {
+                let n = self.nargs(&[1, 2])?;
+                let rd = if n == 2 { self.gpr(0)? } else { 31 };
+                rtype(self.gpr(n - 1)?, 0, rd, 0, 0x09)
+            }
+
+            "mfc0" | "mtc0" => {
+                let op = if mnemonic == "mfc0" { 0x00 } else { 0x04 };
+                self.nargs(&[2])?;
+                let rd = self.imm(1)?;
+                if !(0..32).contains(&rd) {
+                    return Err(format!("invalid COP0 register: {}", self.args[1]));
+                }
+                0x10 << 26 | op << 21 | self.gpr(0)? << 16 | (rd as u32) << 11
+            }
+            "mfc2" | "mtc2" => {
+                let op = if mnemonic == "mfc2" { 0x00 } else { 0x04 };
+                self.nargs(&[2])?;
+                let (vs, e) = self.vreg(1)?;
+                0x12 << 26 | op << 21 | self.gpr(0)? << 16 | vs << 11 | e << 7
+            }
+            "cfc2" | "ctc2" => {
+                let op = if mnemonic == "cfc2" { 0x02 } else { 0x06 };
+                self.nargs(&[2])?;
+                let rd = COP2_CONTROL
+                    .iter()
+                    .position(|&n| n == self.args[1])
+                    .ok_or_else(|| format!("invalid COP2 control register: {}", self.args[1]))?;
+                0x12 << 26 | op << 21 | self.gpr(0)? << 16 | (rd as u32) << 11
+            }
+
+            _ if mnemonic.len() == 3 && vmem_info(&mnemonic[1..]).is_some() => {
+                let op = match &mnemonic[..1] {
+                    "l" => 0x32,
+                    "s" => 0x3A,
+                    _ => return Err(format!("unknown opcode: {}", mnemonic)),
+                };
+                let (vop, size) = vmem_info(&mnemonic[1..]).unwrap();
+                self.nargs(&[2])?;
+                let (vt, e) = self.vreg(0)?;
+                let (off, base) = self.mem(1)?;
+                let off = off / size as i64;
+                if !(-64..64).contains(&off) {
+                    return Err(format!("offset out of range: {}", self.args[1]));
+                }
+                op << 26 | base << 21 | vt << 16 | vop << 11 | e << 7 | (off as u32 & 0x7F)
+            }
+
+            _ => match vector_func(mnemonic) {
+                Some(0x37) => {
+                    self.nargs(&[0])?;
+                    0x12 << 26 | 1 << 25 | 0x37
+                }
+                // VD[DE] = f(VT[E]): the VS field holds the destination
+                // element.
+                Some(func) if func >= 0x30 => {
+                    self.nargs(&[2])?;
+                    let (vd, de) = self.vreg(0)?;
+                    let (vt, e) = self.vreg(1)?;
+                    0x12 << 26 | 1 << 25 | e << 21 | vt << 16 | de << 11 | vd << 6 | func
+                }
+                // VD = VS op VT[E], where VS can be omitted if equal to VD.
+                Some(func) => {
+                    let n = self.nargs(&[2, 3])?;
+                    let (vd, _) = self.vreg(0)?;
+                    let (vs, _) = if n == 3 { self.vreg(1)? } else { (vd, 0) };
+                    let (vt, e) = self.vreg(n - 1)?;
+                    0x12 << 26 | 1 << 25 | e << 21 | vt << 16 | vs << 11 | vd << 6 | func
+                }
+                None => return Err(format!("unknown opcode: {}", mnemonic)),
+            },
+        };
+        Ok(vec![w])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(src: &str) -> Vec<u32> {
+        let bin = assemble_at(src, 0x8000_0400).unwrap();
+        bin.chunks(4)
+            .map(|c| {
+                u32::from(c[0]) << 24
+                    | u32::from(c[1]) << 16
+                    | u32::from(c[2]) << 8
+                    | u32::from(c[3])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn encodings() {
+        let src = "
+          Start:
+            li t0,$A4040000
+            addi s3,$20
+            subi t0,1
+            lw t0,$10(a0)
+            vmudn v0,v1[e0]
+            vrcp v0[e1],v0[e1]
+            sqv v2[e0],$10(a1)
+            bnez t0,Start
+            jal Start
+        ";
+        assert_eq!(
+            words(src),
+            vec![
+                0x3C08_A404,
+                0x3508_0000,
+                0x2273_0020,
+                0x2108_FFFF,
+                0x8C88_0010,
+                0x4A01_0006,
+                0x4A20_0830,
+                0xE8A2_2001,
+                0x1500_FFF7,
+                0x0C00_0100,
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let line = |src| assemble(src).unwrap_err().line;
+        assert_eq!(line("nop\nfoo t0"), 2);
+        assert_eq!(line("nop\n\nlw t0,$10"), 3);
+        assert_eq!(line("addi t0,$10000"), 1);
+        assert_eq!(line("bnez t0,Missing"), 1);
+        assert_eq!(line("L:\nL:"), 2);
+        assert_eq!(line("lqv v32[e0],$00(a0)"), 1);
+    }
+}
@@ -0,0 +1,303 @@
+//! A single ROM running all the golden test suites.
+//!
+//! The ROM holds the RSP code and the test vectors of every suite, and a
+//! driver program that runs them one after the other, like golden_test.asm
+//! does for a single suite. The results of all the suites are stored one
+//! after the other, followed by a marker word, and copied into the upper
+//! part of the 64drive cartridge memory, from where they are dumped at once.
+
+use crate::asm;
+use crate::suite::Suite;
+use byteorder::{BigEndian, ByteOrder};
+use elf2rom::{pack, Program, RomConfig};
+use failure::{bail, Error};
+use std::fs;
+
+// Address of the driver program, followed by the table describing the
+// suites, their code and test vectors, and finally the results.
+const IMAGE_ADDR: u32 = 0x8000_0400;
+
+// Results must end before the boot stub of elf2rom.
+const RESULTS_END: u32 = 0x8030_0000;
+
+// Size of an entry of the table describing the suites (code address and
+// length, test vectors address and count, input and output sizes, results
+// address), and of the table header (number of suites).
+const ENTRY_SIZE: usize = 0x20;
+const HEADER_SIZE: usize = 0x10;
+
+/// Offset of the 64drive cartridge memory where the results are copied.
+pub const DUMP_OFFSET: u32 = 0x100_0000;
+
+fn align(val: usize, align: usize) -> usize {
+    (val + align - 1) & !(align - 1)
+}
+
+fn phys(addr: u32) -> u32 {
+    addr & 0x1FFF_FFFF
+}
+
+/// Return the size of the results of all the suites, excluding the marker.
+pub fn results_size(suites: &[Suite]) -> u32 {
+    suites.iter().map(|s| s.golden_size()).sum()
+}
+
+/// Return the marker written after the results. It is a hash of the names
+/// of the suites and of the sizes of their results, so that a dump is never
+/// split according to a different list of suites.
+pub fn marker(suites: &[Suite]) -> u32 {
+    // FNV-1a
+    let mut hash: u32 = 0x811C_9DC5;
+    for s in suites {
+        let name = s.path.file_stem().unwrap().to_string_lossy();
+        let mut size = [0u8; 4];
+        BigEndian::write_u32(&mut size, s.golden_size());
+        for b in name.bytes().chain(size.iter().cloned()) {
+            hash = (hash ^ b as u32).wrapping_mul(0x0100_0193);
+        }
+    }
+    hash
+}
+
+// Source of the driver program. Registers: a0 = SP registers, a3 = SP PC,
+// s3 = current table entry, s4 = suites left, s0 = test vectors left,
+// s1 = current test vector, s2 = current results.
+fn driver_src(table: u32, results: u32, size: u32, marker: u32) -> String {
+    format!(
+        "
+  // Stop the PIF from resetting the console after 5 seconds.
+  li t1,$BFC00000
+  li t0,8
+  sw t0,$7FC(t1)
+
+  li a0,$A4040000
+  li a3,$A4080000
+  li s3,${table:08X}
+  lw s4,$00(s3)
+  addi s3,${header:X}
+
+SuiteLoop:
+  // Load the RSP code into IMEM.
+  li t0,$1000
+  sw t0,$00(a0)
+  lw t0,$00(s3)
+  sw t0,$04(a0)
+  lw t0,$04(s3)
+  subi t0,1
+  sw t0,$08(a0)
+  jal SPWait
+  nop
+
+  lw s0,$0C(s3)
+  lw s1,$08(s3)
+  lw s2,$18(s3)
+
+VectorLoop:
+  // Load the test vector into DMEM, and run the RSP until it halts.
+  sw zero,$00(a0)
+  sw s1,$04(a0)
+  lw t0,$10(s3)
+  subi t0,1
+  sw t0,$08(a0)
+  jal SPWait
+  nop
+
+  sw zero,$00(a3)
+  li t0,$AD // clear halt, broke, interrupt, single step, interrupt on break
+  sw t0,$10(a0)
+WaitHalt:
+  lw t0,$10(a0)
+  andi t0,1
+  beqz t0,WaitHalt
+  nop
+
+  // Copy the results from DMEM.
+  li t0,$800
+  sw t0,$00(a0)
+  sw s2,$04(a0)
+  lw t0,$14(s3)
+  subi t0,1
+  sw t0,$0C(a0)
+  jal SPWait
+  nop
+
+  lw t0,$10(s3)
+  add s1,t0
+  lw t0,$14(s3)
+  subi s0,1
+  bnez s0,VectorLoop
+  add s2,t0
+
+  subi s4,1
+  bnez s4,SuiteLoop
+  addi s3,${entry:X}
+
+  // Write the marker after the results.
+  li t1,${marker_addr:08X}
+  li t0,${marker:08X}
+  sw t0,$00(t1)
+
+  // Enable writes to the 64drive cartridge memory.
+  li t1,$B8000000
+  jal DriveWait
+  nop
+  li t0,$F0
+  sw t0,$208(t1)
+  jal DriveWait
+  nop
+
+  // Copy the results into the cartridge memory.
+  li a1,$A4600000
+  jal PIWait
+  nop
+  li t0,${results:08X}
+  sw t0,$00(a1)
+  li t0,${cart:08X}
+  sw t0,$04(a1)
+  li t0,${len:X}
+  sw t0,$08(a1)
+  jal PIWait
+  nop
+
+Halt:
+  j Halt
+  nop
+
+SPWait:
+  lw t0,$10(a0)
+  andi t0,$0C // DMA busy, DMA full
+  bnez t0,SPWait
+  nop
+  jr ra
+  nop
+
+DriveWait:
+  lw t0,$200(t1)
+  andi t0,$1000 // busy
+  bnez t0,DriveWait
+  nop
+  jr ra
+  nop
+
+PIWait:
+  lw t0,$10(a1)
+  andi t0,3 // DMA busy, IO busy
+  bnez t0,PIWait
+  nop
+  jr ra
+  nop
+",
+        table = 0xA000_0000 | phys(table),
+        header = HEADER_SIZE,
+        entry = ENTRY_SIZE,
+        marker_addr = 0xA000_0000 | phys(results + size),
+        marker = marker,
+        results = phys(results),
+        cart = 0x1000_0000 + DUMP_OFFSET,
+        len = size + 4 - 1,
+    )
+}
+
+// Append data to the image, aligned to 8 bytes, returning its address.
+fn append(image: &mut Vec<u8>, data: &[u8]) -> u32 {
+    image.resize(align(image.len(), 8), 0);
+    let addr = IMAGE_ADDR + image.len() as u32;
+    image.extend_from_slice(data);
+    addr
+}
+
+/// Build the ROM running all the suites, using the specified boot code.
+pub fn build(suites: &[Suite], bootcode: &[u8]) -> Result<Vec<u8>, Error> {
+    if suites.is_empty() {
+        bail!("no suites found");
+    }
+
+    // The size of the driver does not depend on the addresses.
+    let driver_len = asm::assemble_at(&driver_src(0, 0, 0, 0), IMAGE_ADDR)?.len();
+    let mut image = vec![0u8; align(driver_len, 16)];
+    let table = IMAGE_ADDR + image.len() as u32;
+    image.resize(image.len() + HEADER_SIZE + ENTRY_SIZE * suites.len(), 0);
+    let entry_off = |idx: usize| (table - IMAGE_ADDR) as usize + HEADER_SIZE + idx * ENTRY_SIZE;
+
+    for (idx, s) in suites.iter().enumerate() {
+        let code = append(&mut image, &s.code);
+        let mut input = Vec::new();
+        for tv in &s.tests {
+            for w in &tv.input {
+                let mut buf = [0u8; 4];
+                BigEndian::write_u32(&mut buf, *w);
+                input.extend_from_slice(&buf);
+            }
+        }
+        let vectors = append(&mut image, &input);
+
+        let off = entry_off(idx);
+        let fields = [
+            phys(code),
+            s.code.len() as u32,
+            phys(vectors),
+            s.tests.len() as u32,
+            s.input_size,
+            s.output_size,
+        ];
+        for (i, f) in fields.iter().enumerate() {
+            BigEndian::write_u32(&mut image[off + i * 4..], *f);
+        }
+    }
+
+    // Results follow the image.
+    let results = IMAGE_ADDR + align(image.len(), 16) as u32;
+    let size = results_size(suites);
+    if results + size + 4 > RESULTS_END {
+        bail!(
+            "results do not fit in memory ({} bytes): split the suites",
+            size
+        );
+    }
+    let mut addr = results;
+    for (idx, s) in suites.iter().enumerate() {
+        BigEndian::write_u32(&mut image[entry_off(idx) + 0x18..], phys(addr));
+        addr += s.golden_size();
+    }
+    let hdr = (table - IMAGE_ADDR) as usize;
+    BigEndian::write_u32(&mut image[hdr..], suites.len() as u32);
+
+    let src = driver_src(table, results, size, marker(suites));
+    let driver = asm::assemble_at(&src, IMAGE_ADDR)?;
+    assert_eq!(driver.len(), driver_len);
+    image[..driver.len()].copy_from_slice(&driver);
+
+    let cfg = RomConfig {
+        title: "R64EMU GOLDEN".into(),
+        ..RomConfig::default()
+    };
+    Ok(pack(
+        &Program::from_binary(&image, IMAGE_ADDR),
+        bootcode,
+        &cfg,
+    )?)
+}
+
+/// Split a dump of the cartridge memory (starting at DUMP_OFFSET) into the
+/// golden files of the suites.
+pub fn split(suites: &[Suite], dump: &[u8]) -> Result<(), Error> {
+    let size = results_size(suites) as usize;
+    if dump.len() < size + 4 {
+        bail!(
+            "dump too short ({} bytes, expected {})",
+            dump.len(),
+            size + 4
+        );
+    }
+    if BigEndian::read_u32(&dump[size..]) != marker(suites) {
+        bail!("marker not found after the results: the run did not complete, or the suites changed after building the ROM");
+    }
+
+    let mut off = 0;
+    for s in suites {
+        let len = s.golden_size() as usize;
+        fs::write(s.golden_path(), &dump[off..off + len])?;
+        off += len;
+    }
+    Ok(())
+}
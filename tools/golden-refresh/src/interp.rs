@@ -0,0 +1,60 @@
+//! Golden results computed by the RSP interpreter of r64emu (through
+//! rsprun), instead of the real hardware.
+//!
+//! This is meant for the suites whose results are defined by the
+//! architecture (eg: gpr_zero, since writes to r0 are discarded), so that
+//! they can be generated without a 64drive. The other suites check the
+//! interpreter itself, so their results must come from the hardware.
+
+use crate::suite::Suite;
+use byteorder::{BigEndian, ByteOrder};
+use failure::{bail, format_err, Error};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Offset of DMEM where the RSP code of the suites stores the results.
+const RESULTS_ADDR: usize = 0x800;
+
+/// Run all the test vectors of a suite with rsprun, and write its golden
+/// results.
+pub fn run(suite: &Suite, rsprun: &Path) -> Result<(), Error> {
+    fs::write(suite.rsp_path(), &suite.code)?;
+
+    let input = env::temp_dir().join("golden_refresh_input.bin");
+    let dmem = env::temp_dir().join("golden_refresh_dmem.bin");
+    let mut golden = Vec::with_capacity(suite.golden_size() as usize);
+    for tv in &suite.tests {
+        let mut buf = vec![0u8; suite.input_size as usize];
+        BigEndian::write_u32_into(&tv.input, &mut buf);
+        fs::write(&input, &buf)?;
+
+        let out = Command::new(rsprun)
+            .arg("--dmem")
+            .arg(&input)
+            .arg("--out-dmem")
+            .arg(&dmem)
+            .arg(suite.rsp_path())
+            .output()
+            .map_err(|e| format_err!("failed to execute rsprun: {}", e))?;
+        if !out.status.success() {
+            bail!(
+                "rsprun failed on test {}: {}",
+                tv.name,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        if !out.stdout.starts_with(b"BREAK") {
+            bail!("test {} did not reach BREAK", tv.name);
+        }
+
+        let end = RESULTS_ADDR + suite.output_size as usize;
+        golden.extend_from_slice(&fs::read(&dmem)?[RESULTS_ADDR..end]);
+    }
+    fs::remove_file(&input)?;
+    fs::remove_file(&dmem)?;
+
+    fs::write(suite.golden_path(), &golden)?;
+    Ok(())
+}
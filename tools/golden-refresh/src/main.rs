@@ -0,0 +1,202 @@
+//! Regenerate the golden results of all the RSP test suites on the real
+//! hardware, with a single run.
+//!
+//! `golden-refresh` walks all the golden test suites (the TOML files read by
+//! gengolden, including the ones extracted by tracegolden), regenerates their
+//! RSP binaries with its own assembler (compatible with bass), and batches
+//! them into a single ROM that runs all of them. The combined results are
+//! then dumped from the 64drive, and split back into the .golden files.
+//!
+//! `golden-refresh flash` does everything, driving the 64drive like run.sh;
+//! `build` and `split` run the two halves separately, eg: to flash the ROM
+//! with a different tool.
+//!
+//! `golden-refresh interp` computes the results of the specified suites with
+//! the RSP interpreter instead (see the interp module).
+
+mod asm;
+mod batch;
+mod interp;
+mod suite;
+
+use failure::{format_err, Error};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+use suite::Suite;
+
+#[derive(StructOpt)]
+#[structopt(name = "golden-refresh")]
+struct Cli {
+    /// Directory with the golden test suites
+    #[structopt(long = "dir", parse(from_os_str), default_value = "tests/gengolden")]
+    dir: PathBuf,
+
+    #[structopt(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(StructOpt)]
+enum Cmd {
+    /// Regenerate the RSP binaries, and build the ROM running all the suites
+    #[structopt(name = "build")]
+    Build {
+        /// ROM (or boot code alone) from which the boot code is taken
+        #[structopt(long = "bootcode", parse(from_os_str))]
+        bootcode: PathBuf,
+
+        /// Output ROM
+        #[structopt(
+            short = "o",
+            long = "out",
+            parse(from_os_str),
+            default_value = "golden_refresh.z64"
+        )]
+        out: PathBuf,
+    },
+
+    /// Split a dump of the results into the golden files of the suites
+    #[structopt(name = "split")]
+    Split {
+        /// Dump of the 64drive cartridge memory, from offset 0x1000000
+        #[structopt(parse(from_os_str))]
+        dump: PathBuf,
+    },
+
+    /// Build the ROM, run it on a 64drive, and split its results
+    #[structopt(name = "flash")]
+    Flash {
+        /// ROM (or boot code alone) from which the boot code is taken
+        #[structopt(long = "bootcode", parse(from_os_str))]
+        bootcode: PathBuf,
+    },
+
+    /// Compute the golden results of some suites with the RSP interpreter
+    #[structopt(name = "interp")]
+    Interp {
+        /// Path of the rsprun executable
+        #[structopt(long = "rsprun", parse(from_os_str), default_value = "rsprun")]
+        rsprun: PathBuf,
+
+        /// Names of the suites (eg: gpr_zero)
+        #[structopt(raw(required = "true"))]
+        names: Vec<String>,
+    },
+}
+
+fn load_suites(dir: &Path) -> Result<Vec<Suite>, Error> {
+    let mut suites = Vec::new();
+    for path in suite::find(dir)? {
+        let s = Suite::load(&path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        suites.push(s);
+    }
+    Ok(suites)
+}
+
+// Regenerate the RSP binaries, and build the ROM.
+fn build(suites: &[Suite], bootcode: &Path, out: &Path) -> Result<(), Error> {
+    for s in suites {
+        fs::write(s.rsp_path(), &s.code)?;
+    }
+    let bootcode = elf2rom::bootcode(&fs::read(bootcode)?)?;
+    fs::write(out, batch::build(suites, &bootcode)?)?;
+
+    let vectors: usize = suites.iter().map(|s| s.tests.len()).sum();
+    println!(
+        "Generated: {} ({} suites, {} test vectors)",
+        out.display(),
+        suites.len(),
+        vectors
+    );
+    Ok(())
+}
+
+// Size of the dump of the results, including the marker.
+fn dump_size(suites: &[Suite]) -> u32 {
+    let size = batch::results_size(suites) + 4;
+    (size + 4095) & !4095
+}
+
+fn split(suites: &[Suite], dump: &Path) -> Result<(), Error> {
+    batch::split(suites, &fs::read(dump)?)?;
+    for s in suites {
+        println!("Generated: {}", s.golden_path().display());
+    }
+    Ok(())
+}
+
+fn run_64drive(args: &[&str]) -> Result<(), Error> {
+    let status = Command::new("64drive")
+        .arg("-q")
+        .args(args)
+        .status()
+        .map_err(|e| format_err!("failed to execute 64drive: {}", e))?;
+    if !status.success() {
+        return Err(format_err!("64drive failed: {}", status));
+    }
+    Ok(())
+}
+
+fn flash(suites: &[Suite], bootcode: &Path) -> Result<(), Error> {
+    let rom = env::temp_dir().join("golden_refresh.z64");
+    let dump = env::temp_dir().join("golden_refresh.raw");
+    build(suites, bootcode, &rom)?;
+    run_64drive(&["-c", "auto", "-u", &rom.to_string_lossy()])?;
+
+    println!("Reset the N64 and press ENTER to continue...");
+    io::stdin().read_line(&mut String::new())?;
+    thread::sleep(Duration::from_secs(2));
+
+    let size = format!("{}", dump_size(suites));
+    let offset = format!("0x{:x}", batch::DUMP_OFFSET);
+    run_64drive(&["-o", &offset, "-s", &size, "-d", &dump.to_string_lossy()])?;
+    let res = split(suites, &dump);
+    fs::remove_file(&rom)?;
+    fs::remove_file(&dump)?;
+    res
+}
+
+fn interp(suites: &[Suite], names: &[String], rsprun: &Path) -> Result<(), Error> {
+    for name in names {
+        let s = suites
+            .iter()
+            .find(|s| s.path.file_stem().is_some_and(|n| n == name.as_str()))
+            .ok_or_else(|| format_err!("suite not found: {}", name))?;
+        interp::run(s, rsprun)?;
+        println!("Generated: {}", s.golden_path().display());
+    }
+    Ok(())
+}
+
+fn run(args: &Cli) -> Result<(), Error> {
+    let suites = load_suites(&args.dir)?;
+    match &args.cmd {
+        Cmd::Build { bootcode, out } => {
+            build(&suites, bootcode, out)?;
+            println!(
+                "Run it on a 64drive, then dump the results with:\n  \
+                 64drive -o 0x{:x} -s {} -d <DUMP>\nand split them with:\n  \
+                 golden-refresh split <DUMP>",
+                batch::DUMP_OFFSET,
+                dump_size(&suites)
+            );
+            Ok(())
+        }
+        Cmd::Split { dump } => split(&suites, dump),
+        Cmd::Flash { bootcode } => flash(&suites, bootcode),
+        Cmd::Interp { rsprun, names } => interp(&suites, names, rsprun),
+    }
+}
+
+fn main() {
+    let args = Cli::from_args();
+    if let Err(err) = run(&args) {
+        eprintln!("golden-refresh: {}", err);
+        std::process::exit(1);
+    }
+}
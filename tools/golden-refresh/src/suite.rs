@@ -0,0 +1,131 @@
+//! Golden test suites, as described by the TOML files read by gengolden.
+
+use crate::asm;
+use failure::{bail, Error};
+use serde_derive::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+pub struct TestVector {
+    pub name: String,
+    pub input: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+struct Testsuite {
+    rsp_code: String,
+    input_desc: Vec<String>,
+    output_desc: Vec<String>,
+    test: Vec<TestVector>,
+}
+
+/// A golden test suite, with its RSP code assembled.
+pub struct Suite {
+    pub path: PathBuf, // TOML description
+    pub code: Vec<u8>,
+    pub input_size: u32,  // size of a test vector
+    pub output_size: u32, // size of the results of a test vector
+    pub tests: Vec<TestVector>,
+}
+
+fn desc_size(desc: &[String]) -> Result<u32, Error> {
+    let mut size = 0;
+    for d in desc {
+        size += match d.split(':').next().unwrap() {
+            "v128" => 16,
+            "u32" => 4,
+            _ => bail!("invalid desc string: {}", d),
+        };
+    }
+    if size % 8 != 0 {
+        bail!("size must be multiple of 8 bytes (found: {})", size);
+    }
+    Ok(size)
+}
+
+impl Suite {
+    /// Load a suite, and assemble its RSP code.
+    pub fn load(path: &Path) -> Result<Suite, Error> {
+        let t: Testsuite = toml::from_str(&fs::read_to_string(path)?)?;
+        if !t.rsp_code.contains("break") {
+            bail!("break missing from RSP code");
+        }
+        let input_size = desc_size(&t.input_desc)?;
+        let output_size = desc_size(&t.output_desc)?;
+        for tv in &t.test {
+            if tv.input.len() * 4 != input_size as usize {
+                bail!(
+                    "test {} has invalid number of inputs ({} vs {})",
+                    tv.name,
+                    tv.input.len() * 4,
+                    input_size
+                );
+            }
+        }
+        if t.test.is_empty() {
+            bail!("no test vectors");
+        }
+
+        Ok(Suite {
+            path: path.to_owned(),
+            code: asm::assemble(&t.rsp_code)?,
+            input_size,
+            output_size,
+            tests: t.test,
+        })
+    }
+
+    /// Size of the golden results of the whole suite.
+    pub fn golden_size(&self) -> u32 {
+        self.output_size * self.tests.len() as u32
+    }
+
+    /// Path of the binary with the RSP code.
+    pub fn rsp_path(&self) -> PathBuf {
+        self.path.with_extension("rsp")
+    }
+
+    /// Path of the golden results.
+    pub fn golden_path(&self) -> PathBuf {
+        self.path.with_extension("golden")
+    }
+}
+
+/// Find all the suites in a directory and its subdirectories (eg: the
+/// tests extracted by tracegolden), in a stable order.
+pub fn find(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if path.file_name().is_some_and(|n| n != "target") {
+                paths.extend(find(&path)?);
+            }
+        } else if path.extension().is_some_and(|e| e == "toml")
+            && path.file_name().is_some_and(|n| n != "Cargo.toml")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_as_bass() {
+        // The committed binaries were generated by bass.
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../tests/gengolden");
+        let paths = find(&dir).unwrap();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let s = Suite::load(&path).unwrap();
+            let rsp = fs::read(s.rsp_path()).unwrap();
+            assert!(s.code == rsp, "{}: different binary", path.display());
+        }
+    }
+}
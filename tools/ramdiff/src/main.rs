@@ -0,0 +1,155 @@
+//! RDRAM comparison between savestates.
+//!
+//! `ramdiff` reads the RDRAM of two savestates of the same game (eg: taken
+//! before and after an action in the game), and prints the ranges of bytes
+//! that differ, at their KSEG0 addresses. Close ranges are merged, so that a
+//! structure whose fields changed is reported once. With `--symbols` (in the
+//! format produced by `nm`), each range is annotated with the nearest symbol
+//! that precedes it.
+
+use failure::{format_err, Error};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+// RDRAM is reported at its address in KSEG0.
+const KSEG0: u32 = 0x8000_0000;
+
+#[derive(StructOpt)]
+#[structopt(name = "ramdiff")]
+struct Cli {
+    /// Symbols of the game (ADDRESS [TYPE] NAME per line, as output by nm)
+    #[structopt(long = "symbols", parse(from_os_str))]
+    symbols: Option<PathBuf>,
+
+    /// Merge ranges separated by up to this number of equal bytes
+    #[structopt(long = "gap", default_value = "0")]
+    gap: usize,
+
+    /// Maximum number of bytes shown for each range
+    #[structopt(long = "bytes", default_value = "16")]
+    bytes: usize,
+
+    /// Savestate taken before the change
+    #[structopt(parse(from_os_str))]
+    before: PathBuf,
+
+    /// Savestate taken after the change
+    #[structopt(parse(from_os_str))]
+    after: PathBuf,
+}
+
+fn read_rdram(path: &Path) -> Result<Vec<u8>, Error> {
+    let f = File::open(path).map_err(|err| format_err!("{}: {}", path.display(), err))?;
+    r64emu::savestate::read_rdram(BufReader::new(f))
+        .map_err(|err| format_err!("{}: {}", path.display(), err))
+}
+
+// Load the symbols, sorted by address.
+fn read_symbols(path: &Path) -> Result<Vec<(u32, String)>, Error> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format_err!("cannot read symbols {}: {}", path.display(), err))?;
+    let mut syms = Vec::new();
+    // Each line is: ADDRESS [TYPE] NAME
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 2 {
+            continue;
+        }
+        // 64-bit addresses (sign-extended) are truncated to 32 bits.
+        if let Ok(addr) = u64::from_str_radix(fields[0], 16) {
+            syms.push((addr as u32, fields[fields.len() - 1].to_owned()));
+        }
+    }
+    syms.sort();
+    Ok(syms)
+}
+
+// Find the ranges of differing bytes, as (start, end) offsets. Ranges
+// separated by up to gap equal bytes are merged.
+fn diff_ranges(a: &[u8], b: &[u8], gap: usize) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (idx, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+        if x == y {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if idx - last.1 <= gap => last.1 = idx + 1,
+            _ => ranges.push((idx, idx + 1)),
+        }
+    }
+    ranges
+}
+
+// Format the nearest symbol at or before addr, as sym+0xoff.
+fn symbolize(syms: &[(u32, String)], addr: u32) -> Option<String> {
+    let idx = match syms.binary_search_by_key(&addr, |(a, _)| *a) {
+        Ok(idx) => idx,
+        Err(0) => return None,
+        Err(idx) => idx - 1,
+    };
+    let (base, name) = &syms[idx];
+    Some(if *base == addr {
+        name.clone()
+    } else {
+        format!("{}+0x{:x}", name, addr - base)
+    })
+}
+
+fn hex(bytes: &[u8], max: usize) -> String {
+    let mut s: Vec<String> = bytes
+        .iter()
+        .take(max)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if bytes.len() > max {
+        s.push("...".into());
+    }
+    s.join(" ")
+}
+
+fn run(args: &Cli) -> Result<(), Error> {
+    let a = read_rdram(&args.before)?;
+    let b = read_rdram(&args.after)?;
+    if a.len() != b.len() {
+        eprintln!(
+            "ramdiff: RDRAM size differs ({} vs {} bytes), comparing the first {}",
+            a.len(),
+            b.len(),
+            a.len().min(b.len())
+        );
+    }
+    let syms = match &args.symbols {
+        Some(path) => read_symbols(path)?,
+        None => Vec::new(),
+    };
+
+    let ranges = diff_ranges(&a, &b, args.gap);
+    for &(start, end) in &ranges {
+        let addr = KSEG0 + start as u32;
+        print!(
+            "{:08x}-{:08x} ({} bytes)",
+            addr,
+            KSEG0 + end as u32 - 1,
+            end - start
+        );
+        if let Some(sym) = symbolize(&syms, addr) {
+            print!(" {}", sym);
+        }
+        println!();
+        println!("  - {}", hex(&a[start..end], args.bytes));
+        println!("  + {}", hex(&b[start..end], args.bytes));
+    }
+    let total: usize = ranges.iter().map(|(s, e)| e - s).sum();
+    println!("{} ranges, {} bytes", ranges.len(), total);
+    Ok(())
+}
+
+fn main() {
+    let args = Cli::from_args();
+    if let Err(err) = run(&args) {
+        eprintln!("ramdiff: {}", err);
+        std::process::exit(1);
+    }
+}
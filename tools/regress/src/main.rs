@@ -0,0 +1,311 @@
+//! Regression farm for r64emu.
+//!
+//! `regress` runs every ROM found in a directory headless for a fixed number
+//! of frames, and writes a report (`report.json` and `report.html`) with the
+//! outcome of each ROM: whether it boots (that is, it displays something),
+//! the hash of the last frame, and a crash bundle for the ROMs that crashed.
+//!
+//! Each ROM runs in its own worker process (the same executable, invoked with
+//! `--worker`), so that crashes and hangs do not affect the other ROMs; the
+//! emulator also keeps its state in thread-local storage, so a process can
+//! only run a single ROM anyway.
+
+#[macro_use]
+extern crate serde_derive;
+
+mod report;
+
+use emu::bus::be::Device;
+use emu::gfx::{OwnedGfxBufferLE, Rgb888, Rgba8888};
+use emu::hw::OutputProducer;
+use emu::snd::{OwnedSndBuffer, S16_STEREO};
+use failure::Error;
+use image::png::PNGEncoder;
+use image::ColorType;
+use r64emu::cartridge::Cartridge;
+use r64emu::compat::CompatDb;
+use r64emu::N64Builder;
+use report::{RomResult, Status};
+use std::collections::VecDeque;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "regress")]
+struct Cli {
+    /// Path to the PIF ROM
+    #[structopt(long = "bios", parse(from_os_str), default_value = "bios/pifdata.bin")]
+    bios: PathBuf,
+
+    /// Number of frames to emulate for each ROM
+    #[structopt(long = "frames", default_value = "300")]
+    frames: usize,
+
+    /// Maximum running time for each ROM, in seconds
+    #[structopt(long = "timeout", default_value = "300")]
+    timeout: u64,
+
+    /// Number of ROMs to run in parallel
+    #[structopt(short = "j", long = "jobs", default_value = "4")]
+    jobs: usize,
+
+    /// Output directory for the reports, screenshots and crash bundles
+    #[structopt(
+        short = "o",
+        long = "out",
+        parse(from_os_str),
+        default_value = "regress"
+    )]
+    out: PathBuf,
+
+    /// Compatibility database to update with the results
+    #[structopt(long = "compat", parse(from_os_str))]
+    compat: Option<PathBuf>,
+
+    /// Run a single ROM (internal: used by the worker processes)
+    #[structopt(long = "worker", raw(hidden = "true"))]
+    worker: bool,
+
+    /// Directory containing the ROMs
+    #[structopt(parse(from_os_str))]
+    path: PathBuf,
+}
+
+const ROM_EXTENSIONS: [&str; 3] = ["z64", "n64", "v64"];
+
+fn is_rom(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ROM_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+fn rom_name(rom: &Path) -> String {
+    rom.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+fn save_png(path: &Path, screen: &OwnedGfxBufferLE<Rgb888>) -> Result<(), Error> {
+    let mut rgba = OwnedGfxBufferLE::<Rgba8888>::from_buf(&screen.buf());
+    let mut buf = rgba.buf_mut();
+    let (raw, _pitch) = buf.raw();
+    let f = fs::File::create(path)?;
+    PNGEncoder::new(f).encode(raw, 640, 480, ColorType::RGBA(8))?;
+    Ok(())
+}
+
+// Worker: run a single ROM, and save the result into the output directory.
+fn run_worker(args: &Cli) -> Result<(), Error> {
+    let rom = &args.path;
+    let name = rom_name(rom);
+    let start = Instant::now();
+    let mut result = RomResult::new(&name);
+
+    let logger = slog::Logger::root(slog::Discard, slog::o!());
+    let n64 = N64Builder::new(logger).rom(rom).bios(&args.bios).build();
+    let mut n64 = match n64 {
+        Ok(n64) => n64,
+        Err(err) => {
+            result.status = Status::LoadError;
+            result.error = Some(err.to_string());
+            return report::write_result(&args.out, &result);
+        }
+    };
+    result.game_code = Some(Cartridge::get().game_code());
+    result.title = Some(Cartridge::get().title());
+
+    let mut screen = OwnedGfxBufferLE::<Rgb888>::new(640, 480);
+    let mut sound = OwnedSndBuffer::<S16_STEREO>::with_capacity(4096);
+    let mut frames = 0;
+    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+        for _ in 0..args.frames {
+            n64.render_frame(&mut screen.buf_mut(), &mut sound.buf_mut());
+            frames += 1;
+        }
+    }));
+    result.frames = frames;
+    result.elapsed_ms = start.elapsed().as_millis() as u64;
+
+    // Hash the last frame; it is also used to detect whether the ROM boots.
+    let (hash, blank) = {
+        let buf = screen.buf();
+        let (raw, _pitch) = buf.raw();
+        (crc::crc32::checksum_ieee(raw), raw.iter().all(|&b| b == 0))
+    };
+    result.screenshot_hash = Some(format!("{:08x}", hash));
+
+    let shot = args.out.join("shots").join(format!("{}.png", name));
+    save_png(&shot, &screen)?;
+    result.screenshot = Some(shot.strip_prefix(&args.out)?.display().to_string());
+
+    match run {
+        Ok(()) => {
+            result.status = if blank { Status::NoBoot } else { Status::Boot };
+        }
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".into());
+            result.status = Status::Crash;
+            result.error = Some(msg.clone());
+
+            let bundle = report::crash_bundle(&args.out, &name)?;
+            fs::write(bundle.join("panic.txt"), &msg)?;
+            save_png(&bundle.join("screenshot.png"), &screen)?;
+            result.crash_bundle = Some(bundle.strip_prefix(&args.out)?.display().to_string());
+        }
+    }
+
+    report::write_result(&args.out, &result)
+}
+
+// Run a ROM in a worker process, and collect its result. If the worker does
+// not produce a result (it was killed, or it crashed without unwinding), a
+// crash bundle is created with its output.
+fn spawn_worker(args: &Cli, rom: &Path) -> Result<RomResult, Error> {
+    let name = rom_name(rom);
+    let logs = args.out.join("logs");
+    let stdout = logs.join(format!("{}.stdout", name));
+    let stderr = logs.join(format!("{}.stderr", name));
+    let _ = fs::remove_file(report::result_path(&args.out, &name));
+
+    let start = Instant::now();
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg("--worker")
+        .arg("--bios")
+        .arg(&args.bios)
+        .arg("--frames")
+        .arg(args.frames.to_string())
+        .arg("--out")
+        .arg(&args.out)
+        .arg(rom)
+        .stdin(Stdio::null())
+        .stdout(fs::File::create(&stdout)?)
+        .stderr(fs::File::create(&stderr)?)
+        .spawn()?;
+
+    let timeout = Duration::from_secs(args.timeout);
+    let exit = loop {
+        if let Some(exit) = child.try_wait()? {
+            break Some(exit);
+        }
+        if start.elapsed() > timeout {
+            child.kill()?;
+            child.wait()?;
+            break None;
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    if let Some(result) = report::read_result(&args.out, &name) {
+        return Ok(result);
+    }
+
+    let mut result = RomResult::new(&name);
+    result.elapsed_ms = start.elapsed().as_millis() as u64;
+    match exit {
+        None => {
+            result.status = Status::Timeout;
+            result.error = Some(format!("killed after {} seconds", args.timeout));
+        }
+        Some(exit) => {
+            result.status = Status::Crash;
+            result.error = Some(format!("worker failed ({})", exit));
+        }
+    }
+    let bundle = report::crash_bundle(&args.out, &name)?;
+    fs::copy(&stdout, bundle.join("stdout.txt"))?;
+    fs::copy(&stderr, bundle.join("stderr.txt"))?;
+    result.crash_bundle = Some(bundle.strip_prefix(&args.out)?.display().to_string());
+    Ok(result)
+}
+
+// Record the results into the compatibility database.
+fn update_compat(path: &Path, results: &[RomResult]) -> Result<(), Error> {
+    let mut db = if path.exists() {
+        CompatDb::load(path)?
+    } else {
+        CompatDb::default()
+    };
+    for r in results {
+        if let (Some(code), Some(status)) = (&r.game_code, r.status.compat()) {
+            let title = r.title.as_ref().unwrap_or(&r.rom);
+            db.record_run(code, title, status);
+        }
+    }
+    db.save(path)?;
+    println!("Compatibility database updated: {}", path.display());
+    Ok(())
+}
+
+fn run_farm(args: Cli) -> Result<(), Error> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(&args.path)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| is_rom(path))
+        .collect();
+    roms.sort();
+    println!("Running {} ROMs ({} frames each)", roms.len(), args.frames);
+
+    for dir in &["shots", "logs", "results", "crashes"] {
+        fs::create_dir_all(args.out.join(dir))?;
+    }
+
+    let args = Arc::new(args);
+    let queue = Arc::new(Mutex::new(roms.into_iter().collect::<VecDeque<_>>()));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let workers: Vec<_> = (0..args.jobs.max(1))
+        .map(|_| {
+            let (args, queue, results) = (args.clone(), queue.clone(), results.clone());
+            thread::spawn(move || loop {
+                let rom = match queue.lock().unwrap().pop_front() {
+                    Some(rom) => rom,
+                    None => break,
+                };
+                let result = spawn_worker(&args, &rom).unwrap_or_else(|err| {
+                    let mut result = RomResult::new(&rom_name(&rom));
+                    result.status = Status::Crash;
+                    result.error = Some(format!("cannot run worker: {}", err));
+                    result
+                });
+                println!("{:>10}  {}", result.status.to_string(), result.rom);
+                results.lock().unwrap().push(result);
+            })
+        })
+        .collect();
+    for w in workers {
+        w.join().unwrap();
+    }
+
+    let mut results = results.lock().unwrap().split_off(0);
+    results.sort_by(|a, b| a.rom.cmp(&b.rom));
+    report::write_report(&args.out, &results, args.frames)?;
+    if let Some(path) = &args.compat {
+        update_compat(path, &results)?;
+    }
+    println!("{}", report::summary(&results));
+    println!(
+        "Report written to {}",
+        args.out.join("report.html").display()
+    );
+    Ok(())
+}
+
+fn main() {
+    let args = Cli::from_args();
+    let res = if args.worker {
+        run_worker(&args)
+    } else {
+        run_farm(args)
+    };
+    if let Err(err) = res {
+        eprintln!("regress: {}", err);
+        std::process::exit(1);
+    }
+}
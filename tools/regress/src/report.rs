@@ -0,0 +1,189 @@
+use failure::Error;
+use r64emu::compat::CompatStatus;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Outcome of running a ROM.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Boot,      // The ROM ran for all the frames, and displayed something
+    NoBoot,    // The ROM ran for all the frames, but the screen is blank
+    Crash,     // The emulator panicked or the worker died
+    Timeout,   // The worker was killed after the timeout
+    LoadError, // The ROM could not be loaded
+}
+
+impl Status {
+    const ALL: [Status; 5] = [
+        Status::Boot,
+        Status::NoBoot,
+        Status::Crash,
+        Status::Timeout,
+        Status::LoadError,
+    ];
+
+    fn color(self) -> &'static str {
+        match self {
+            Status::Boot => "#8c8",
+            Status::NoBoot => "#ec6",
+            Status::Crash | Status::Timeout => "#e77",
+            Status::LoadError => "#aaa",
+        }
+    }
+
+    /// Return the corresponding status in the compatibility database.
+    pub fn compat(self) -> Option<CompatStatus> {
+        match self {
+            Status::Boot => Some(CompatStatus::Boots),
+            Status::NoBoot => Some(CompatStatus::NoBoot),
+            Status::Crash | Status::Timeout => Some(CompatStatus::Crash),
+            Status::LoadError => None,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Boot => "boot",
+            Status::NoBoot => "no-boot",
+            Status::Crash => "crash",
+            Status::Timeout => "timeout",
+            Status::LoadError => "load-error",
+        })
+    }
+}
+
+/// Result of running a ROM. Paths are relative to the output directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RomResult {
+    pub rom: String,
+    pub game_code: Option<String>,
+    pub title: Option<String>,
+    pub status: Status,
+    pub frames: usize,
+    pub elapsed_ms: u64,
+    pub screenshot_hash: Option<String>,
+    pub screenshot: Option<String>,
+    pub crash_bundle: Option<String>,
+    pub error: Option<String>,
+}
+
+impl RomResult {
+    pub fn new(rom: &str) -> RomResult {
+        RomResult {
+            rom: rom.to_owned(),
+            game_code: None,
+            title: None,
+            status: Status::Crash,
+            frames: 0,
+            elapsed_ms: 0,
+            screenshot_hash: None,
+            screenshot: None,
+            crash_bundle: None,
+            error: None,
+        }
+    }
+}
+
+pub fn result_path(out: &Path, rom: &str) -> PathBuf {
+    out.join("results").join(format!("{}.json", rom))
+}
+
+/// Save the result of a ROM (in the worker process).
+pub fn write_result(out: &Path, result: &RomResult) -> Result<(), Error> {
+    fs::write(
+        result_path(out, &result.rom),
+        serde_json::to_string_pretty(result)?,
+    )?;
+    Ok(())
+}
+
+/// Read the result saved by a worker process, if any.
+pub fn read_result(out: &Path, rom: &str) -> Option<RomResult> {
+    let data = fs::read(result_path(out, rom)).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+/// Create (or empty) the directory of the crash bundle of a ROM.
+pub fn crash_bundle(out: &Path, rom: &str) -> Result<PathBuf, Error> {
+    let dir = out.join("crashes").join(rom);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Return a one-line summary of the results (number of ROMs per status).
+pub fn summary(results: &[RomResult]) -> String {
+    Status::ALL
+        .iter()
+        .map(|&st| {
+            let n = results.iter().filter(|r| r.status == st).count();
+            format!("{}: {}", st, n)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Write the full report, both as JSON (for tools) and HTML (for humans).
+pub fn write_report(out: &Path, results: &[RomResult], frames: usize) -> Result<(), Error> {
+    fs::write(
+        out.join("report.json"),
+        serde_json::to_string_pretty(results)?,
+    )?;
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\n");
+    html.push_str("<title>r64emu regression report</title>\n<style>\n");
+    html.push_str("body { font-family: sans-serif; }\n");
+    html.push_str("table { border-collapse: collapse; }\n");
+    html.push_str("td, th { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\n");
+    html.push_str("img { width: 160px; }\n</style></head><body>\n");
+    html.push_str("<h1>r64emu regression report</h1>\n");
+    html.push_str(&format!(
+        "<p>{} ROMs, {} frames each. {}</p>\n",
+        results.len(),
+        frames,
+        escape(&summary(results))
+    ));
+    html.push_str("<table>\n<tr><th>ROM</th><th>Code</th><th>Status</th><th>Frames</th>");
+    html.push_str("<th>Time</th><th>Screenshot</th><th>Hash</th><th>Details</th></tr>\n");
+    for r in results {
+        let link = |path: &Option<String>, text: &str| match path {
+            Some(path) => format!("<a href=\"{}\">{}</a>", escape(path), text),
+            None => String::new(),
+        };
+        let shot = match &r.screenshot {
+            Some(path) => format!("<img src=\"{}\">", escape(path)),
+            None => String::new(),
+        };
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td style=\"background: {}\">{}</td><td>{}</td>\
+             <td>{:.1}s</td><td>{}</td><td><code>{}</code></td><td>{} {}</td></tr>\n",
+            escape(&r.rom),
+            escape(r.game_code.as_ref().map_or("", |s| s.as_str())),
+            r.status.color(),
+            r.status,
+            r.frames,
+            r.elapsed_ms as f64 / 1000.0,
+            shot,
+            r.screenshot_hash.as_ref().map_or("", |s| s.as_str()),
+            escape(r.error.as_ref().map_or("", |s| s.as_str())),
+            link(&r.crash_bundle, "crash bundle"),
+        ));
+    }
+    html.push_str("</table>\n</body></html>\n");
+    fs::write(out.join("report.html"), html)?;
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! Batch runner for RSP microcode.
+//!
+//! `rsprun` loads an RSP binary into IMEM (and optionally an image into
+//! DMEM), runs it on r64emu's RSP core until it executes BREAK or for a
+//! maximum number of steps, and dumps the final state (registers and DMEM),
+//! either as text or JSON. It is meant to be used by microcode developers
+//! from their build scripts and test suites, without running the whole
+//! emulator.
+
+#[macro_use]
+extern crate serde_derive;
+
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use failure::{bail, Error};
+use mips64::Cop;
+use r64emu::dp::Dp;
+use r64emu::mi::Mi;
+use r64emu::r4300::R4300;
+use r64emu::sp::{Sp, SpCop2, RSPCPU};
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "rsprun")]
+struct Cli {
+    /// Image loaded into DMEM before running (at most 4096 bytes)
+    #[structopt(long = "dmem", parse(from_os_str))]
+    dmem: Option<PathBuf>,
+
+    /// Initial value of the PC (offset within IMEM)
+    #[structopt(long = "pc", default_value = "0", parse(try_from_str = "parse_hex"))]
+    pc: u32,
+
+    /// Maximum number of steps (instructions) to run, if BREAK is not reached
+    #[structopt(short = "n", long = "steps", default_value = "1000000")]
+    steps: u64,
+
+    /// Dump the final state as JSON, instead of text
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// Also write the final contents of DMEM into this file
+    #[structopt(long = "out-dmem", parse(from_os_str))]
+    out_dmem: Option<PathBuf>,
+
+    /// RSP binary loaded into IMEM (at most 4096 bytes)
+    #[structopt(parse(from_os_str))]
+    ucode: PathBuf,
+}
+
+fn parse_hex(s: &str) -> Result<u32, std::num::ParseIntError> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    }
+}
+
+/// Final state of the RSP. Vector registers are formatted as hex strings,
+/// with lane 0 first (as shown by the debugger).
+#[derive(Serialize)]
+struct RspState {
+    steps: u64,
+    broke: bool, // true if BREAK was reached
+    pc: u32,
+    gpr: Vec<u32>,
+    vpr: Vec<String>,
+    vco: u16,
+    vcc: u16,
+    vce: u8,
+    accum: [String; 3], // high, middle, low
+    dmem: String,
+}
+
+// Create the devices required to run the RSP standalone, and map the SP
+// registers into the main CPU bus, so that the RSP can be controlled as the
+// main CPU would.
+fn make_sp() -> Result<(), Error> {
+    let logger = slog::Logger::root(slog::Discard, slog::o!());
+    R4300::new(logger.new(slog::o!())).register();
+    Mi::new(logger.new(slog::o!())).register();
+    Dp::new(logger.new(slog::o!())).register();
+    Sp::new(logger.new(slog::o!()))?.register();
+
+    let bus = &mut R4300::get_mut().bus;
+    bus.map_device(0x0400_0000, Sp::get(), 0)?;
+    bus.map_device(0x0404_0000, Sp::get(), 1)?;
+    bus.map_device(0x0408_0000, Sp::get(), 2)?;
+    RSPCPU::get_mut().map_bus()?;
+    Ok(())
+}
+
+fn load(mem: &mut [u8], path: &PathBuf) -> Result<(), Error> {
+    let data = fs::read(path)?;
+    if data.len() > mem.len() {
+        bail!(
+            "{}: too big ({} bytes, maximum is {})",
+            path.display(),
+            data.len(),
+            mem.len()
+        );
+    }
+    mem[..data.len()].copy_from_slice(&data);
+    Ok(())
+}
+
+// Run the RSP until it halts, or for the specified number of steps.
+// Returns the number of steps executed.
+fn run(pc: u32, max_steps: u64) -> u64 {
+    let main_bus = &mut R4300::get_mut().bus;
+    main_bus.write::<u32>(0x0408_0000, pc); // SP_PC
+    main_bus.write::<u32>(0x0404_0010, 1 << 0 | 1 << 2); // SP_STATUS: clear HALT and BROKE
+
+    let mut steps = 0;
+    while steps < max_steps && !Sp::get().halted() {
+        let cpu = RSPCPU::get_mut();
+        let clock = cpu.ctx().clock;
+        cpu.run(clock + 1, &Tracer::null()).unwrap();
+        steps += 1;
+    }
+    steps
+}
+
+fn state(steps: u64) -> RspState {
+    let cpu = RSPCPU::get();
+    let ctx = cpu.ctx();
+    let cop2 = |idx| cpu.cop2.reg(ctx, idx);
+    RspState {
+        steps,
+        broke: Sp::get().broke(),
+        pc: ctx.get_pc() as u32 & 0xFFF,
+        gpr: ctx.regs.iter().map(|&r| r as u32).collect(),
+        vpr: (0..32).map(|idx| format!("{:032x}", cop2(idx))).collect(),
+        vco: cop2(SpCop2::REG_VCO) as u16,
+        vcc: cop2(SpCop2::REG_VCC) as u16,
+        vce: cop2(SpCop2::REG_VCE) as u8,
+        accum: [
+            format!("{:032x}", cop2(SpCop2::REG_ACCUM_HI)),
+            format!("{:032x}", cop2(SpCop2::REG_ACCUM_MD)),
+            format!("{:032x}", cop2(SpCop2::REG_ACCUM_LO)),
+        ],
+        dmem: Sp::get()
+            .dmem
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    }
+}
+
+fn print_state(st: &RspState) {
+    println!(
+        "{} after {} steps, PC={:03x}",
+        if st.broke { "BREAK" } else { "Stopped" },
+        st.steps,
+        st.pc
+    );
+    println!();
+    for (idx, r) in st.gpr.iter().enumerate() {
+        print!("r{:<2} {:08x}", idx, r);
+        print!("{}", if idx % 4 == 3 { "\n" } else { "   " });
+    }
+    println!();
+    for (idx, v) in st.vpr.iter().enumerate() {
+        print!("v{:<2} ", idx);
+        for lane in 0..8 {
+            print!(" {}", &v[lane * 4..lane * 4 + 4]);
+        }
+        println!();
+    }
+    println!();
+    println!(
+        "VCO {:04x}   VCC {:04x}   VCE {:02x}",
+        st.vco, st.vcc, st.vce
+    );
+    for (name, acc) in ["ACC_HI", "ACC_MD", "ACC_LO"].iter().zip(st.accum.iter()) {
+        print!("{:<6}", name);
+        for lane in 0..8 {
+            print!(" {}", &acc[lane * 4..lane * 4 + 4]);
+        }
+        println!();
+    }
+    println!();
+    println!("DMEM:");
+    println!("{}", pretty_hex::pretty_hex(&&Sp::get().dmem[..]));
+}
+
+fn run_rsp(args: &Cli) -> Result<(), Error> {
+    make_sp()?;
+    {
+        let sp = Sp::get_mut();
+        load(&mut sp.imem, &args.ucode)?;
+        if let Some(dmem) = &args.dmem {
+            load(&mut sp.dmem, dmem)?;
+        }
+    }
+
+    let steps = run(args.pc, args.steps);
+    let st = state(steps);
+    if let Some(path) = &args.out_dmem {
+        fs::write(path, &Sp::get().dmem[..])?;
+    }
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&st)?);
+    } else {
+        print_state(&st);
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = Cli::from_args();
+    if let Err(err) = run_rsp(&args) {
+        eprintln!("rsprun: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,393 @@
+//! Turn a trace of an RSP vector opcode into a golden test.
+//!
+//! `tracegolden` reads a trace recorded with `r64emu --rsp-trace-op`, and
+//! writes a golden test (TOML description, RSP binary and golden results, as
+//! produced by gengolden) that loads the registers read by the opcode with the
+//! values found in the trace, executes it, and stores the registers it
+//! writes. The tests in tests/gengolden/traces are run automatically by
+//! tests/rsp_golden_test.rs.
+//!
+//! The golden results are the ones found in the trace, that is the ones
+//! computed by the emulator: when the trace shows a bug, regenerate them on
+//! the real hardware with gengolden before fixing it.
+
+use byteorder::{BigEndian, WriteBytesExt};
+use failure::{bail, Error};
+use r64emu::sp::{OpRecord, OpTrace};
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "tracegolden")]
+struct Cli {
+    /// Name of the test (default: the name of the trace file)
+    #[structopt(long = "name")]
+    name: Option<String>,
+
+    /// Directory where the test is written
+    #[structopt(
+        long = "out-dir",
+        parse(from_os_str),
+        default_value = "tests/gengolden/traces"
+    )]
+    out_dir: PathBuf,
+
+    /// Trace recorded with --rsp-trace-op
+    #[structopt(parse(from_os_str))]
+    trace: PathBuf,
+}
+
+// GPRs used by the test code.
+const R0: u32 = 0;
+const A0: u32 = 4;
+const A1: u32 = 5;
+const T0: u32 = 8;
+
+// COP2 control registers.
+const CONTROL_REGS: [(&str, u32); 3] = [("vco", 0), ("vcc", 1), ("vce", 2)];
+
+// How a vector opcode uses the registers.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Multiply, // writes the whole accumulator
+    Compute,  // writes the low slice of the accumulator
+    Single,   // VD[DE] = f(VT[E]); the VS field is the destination element
+}
+
+// Return the mnemonic (as accepted by bass) and the kind of a vector opcode.
+// Opcodes that depend on state that the test cannot load (the accumulator,
+// or the high half of a double-precision reciprocal) are not supported.
+fn opcode_info(func: u32) -> Option<(&'static str, Kind)> {
+    use self::Kind::*;
+    Some(match func {
+        0x00 => ("vmulf", Multiply),
+        0x01 => ("vmulu", Multiply),
+        0x04 => ("vmudl", Multiply),
+        0x05 => ("vmudm", Multiply),
+        0x06 => ("vmudn", Multiply),
+        0x07 => ("vmudh", Multiply),
+        0x10 => ("vadd", Compute),
+        0x11 => ("vsub", Compute),
+        0x13 => ("vabs", Compute),
+        0x14 => ("vaddc", Compute),
+        0x15 => ("vsubc", Compute),
+        0x20 => ("vlt", Compute),
+        0x21 => ("veq", Compute),
+        0x22 => ("vne", Compute),
+        0x23 => ("vge", Compute),
+        0x24 => ("vcl", Compute),
+        0x25 => ("vch", Compute),
+        0x26 => ("vcr", Compute),
+        0x27 => ("vmrg", Compute),
+        0x28 => ("vand", Compute),
+        0x29 => ("vnand", Compute),
+        0x2A => ("vor", Compute),
+        0x2B => ("vnor", Compute),
+        0x2C => ("vxor", Compute),
+        0x2D => ("vnxor", Compute),
+        0x30 => ("vrcp", Single),
+        0x33 => ("vmov", Single),
+        0x34 => ("vrsq", Single),
+        _ => return None,
+    })
+}
+
+fn itype(op: u32, rs: u32, rt: u32, imm: u32) -> u32 {
+    op << 26 | rs << 21 | rt << 16 | (imm & 0xFFFF)
+}
+
+fn cop2_move(op: u32, rt: u32, rd: u32) -> u32 {
+    0x12 << 26 | op << 21 | rt << 16 | rd << 11
+}
+
+// LQV/SQV with element 0.
+fn vmem_quad(op: u32, base: u32, vt: u32, offset: u32) -> u32 {
+    op << 26 | base << 21 | vt << 16 | 0x04 << 11 | ((offset >> 4) & 0x7F)
+}
+
+fn vector(func: u32, e: u32, vt: u32, vs: u32, vd: u32) -> u32 {
+    0x12 << 26 | 1 << 25 | e << 21 | vt << 16 | vs << 11 | vd << 6 | func
+}
+
+// The test code, as assembly (for gengolden) and binary.
+#[derive(Default)]
+struct Asm {
+    src: String,
+    code: Vec<u32>,
+}
+
+impl Asm {
+    fn emit(&mut self, src: &str, word: u32) {
+        writeln!(self.src, "  {}", src).unwrap();
+        self.code.push(word);
+    }
+
+    fn blank(&mut self) {
+        self.src.push('\n');
+    }
+}
+
+// The traced opcode, rewritten to read VS from v0, VT from v1 and VD from v2
+// (with the same elements), so that the test can load them.
+struct TestOp {
+    func: u32,
+    e: u32,
+    de: u32, // destination element (Single)
+    mnemonic: &'static str,
+    kind: Kind,
+}
+
+impl TestOp {
+    fn new(opcode: u32) -> Option<TestOp> {
+        if opcode >> 26 != 0x12 || opcode & (1 << 25) == 0 {
+            return None;
+        }
+        let func = opcode & 0x3F;
+        let (mnemonic, kind) = opcode_info(func)?;
+        Some(TestOp {
+            func,
+            e: (opcode >> 21) & 0xF,
+            de: if kind == Kind::Single {
+                (opcode >> 11) & 0x1F
+            } else {
+                0
+            },
+            mnemonic,
+            kind,
+        })
+    }
+
+    fn opcode(&self) -> u32 {
+        match self.kind {
+            Kind::Single => vector(self.func, self.e, 1, self.de, 2),
+            _ => vector(self.func, self.e, 1, 0, 2),
+        }
+    }
+
+    fn src(&self) -> String {
+        match self.kind {
+            Kind::Single => format!("{} v2[e{}],v1[e{}]", self.mnemonic, self.de, self.e),
+            _ => format!("{} v2,v0,v1[e{}]", self.mnemonic, self.e),
+        }
+    }
+
+    // Accumulator slices stored by the test, as (VSAR element, name).
+    fn accum(&self) -> &'static [(u32, &'static str)] {
+        match self.kind {
+            Kind::Multiply => &[(10, "accum_lo"), (9, "accum_md"), (8, "accum_hi")],
+            Kind::Compute => &[(10, "accum_lo")],
+            Kind::Single => &[],
+        }
+    }
+
+    fn input_desc(&self) -> Vec<String> {
+        let mut desc = vec!["v128:vs", "v128:vt", "v128:vd"];
+        desc.extend(&["u32:vco", "u32:vcc", "u32:vce", "u32:dummy"]);
+        desc.into_iter().map(String::from).collect()
+    }
+
+    fn output_desc(&self) -> Vec<String> {
+        let mut desc = vec!["v128:res".to_owned()];
+        desc.extend(
+            self.accum()
+                .iter()
+                .map(|(_, name)| format!("v128:{}", name)),
+        );
+        desc.extend(CONTROL_REGS.iter().map(|(name, _)| format!("u32:{}", name)));
+        desc.push("u32:padding".to_owned());
+        desc
+    }
+
+    // Generate the test code: load the inputs from DMEM 0x000, execute the
+    // opcode, and store the outputs at DMEM 0x800.
+    fn code(&self, comment: &str) -> Asm {
+        let mut asm = Asm::default();
+        asm.emit("li a0,$0", itype(0x0D, R0, A0, 0));
+        asm.emit("li a1,$800", itype(0x0D, R0, A1, 0x800));
+        asm.blank();
+
+        let mut off = 0x30;
+        for &(name, reg) in CONTROL_REGS.iter() {
+            asm.emit(&format!("lw t0,${:02x}(a0)", off), itype(0x23, A0, T0, off));
+            asm.emit(&format!("ctc2 t0,{}", name), cop2_move(0x06, T0, reg));
+            off += 4;
+        }
+        for vreg in 0..3 {
+            let off = vreg * 0x10;
+            let src = format!("lqv v{}[e0],${:02x}(a0)", vreg, off);
+            asm.emit(&src, vmem_quad(0x32, A0, vreg, off));
+        }
+        asm.blank();
+
+        asm.emit(&format!("{} // {}", self.src(), comment), self.opcode());
+        asm.blank();
+
+        asm.emit("sqv v2[e0],$00(a1)", vmem_quad(0x3A, A1, 2, 0));
+        let mut off = 0x10;
+        for &(e, name) in self.accum() {
+            let src = format!("vsar v3,v3[e{}] // -> {}", e, name);
+            asm.emit(&src, vector(0x1D, e, 3, 3, 3));
+            let src = format!("sqv v3[e0],${:02x}(a1)", off);
+            asm.emit(&src, vmem_quad(0x3A, A1, 3, off));
+            off += 0x10;
+        }
+        for &(name, reg) in CONTROL_REGS.iter() {
+            asm.emit("li t0,0", itype(0x0D, R0, T0, 0));
+            asm.emit(&format!("cfc2 t0,{}", name), cop2_move(0x02, T0, reg));
+            asm.emit(&format!("sw t0,${:02x}(a1)", off), itype(0x2B, A1, T0, off));
+            off += 4;
+        }
+        asm.blank();
+        asm.emit("break", 0x0D);
+        asm
+    }
+
+    // Return the inputs of the test for a record: the registers before the
+    // execution.
+    fn input(&self, rec: &OpRecord) -> Vec<u32> {
+        let reg = |shift: u32| rec.pre.vreg(((rec.opcode >> shift) & 0x1F) as usize);
+        let vs = if self.kind == Kind::Single {
+            0
+        } else {
+            reg(11)
+        };
+        let mut input = Vec::new();
+        for &v in [vs, reg(16), reg(6)].iter() {
+            input.extend(&words(v));
+        }
+        let pre = &rec.pre;
+        input.extend(&[pre.vco as u32, pre.vcc as u32, pre.vce as u32, 0]);
+        input
+    }
+
+    // Return the expected outputs of the test for a record: the registers
+    // after the execution.
+    fn output(&self, rec: &OpRecord) -> Vec<u32> {
+        let post = &rec.post;
+        let mut output = words(post.vreg(((rec.opcode >> 6) & 0x1F) as usize)).to_vec();
+        for &(e, _) in self.accum() {
+            output.extend(&words(post.accum((e - 8) as usize)));
+        }
+        output.extend(&[post.vco as u32, post.vcc as u32, post.vce as u32, 0]);
+        output
+    }
+}
+
+// Split a vector register into words, as stored into DMEM.
+fn words(v: u128) -> [u32; 4] {
+    [
+        (v >> 96) as u32,
+        (v >> 64) as u32,
+        (v >> 32) as u32,
+        v as u32,
+    ]
+}
+
+fn toml_list(out: &mut String, key: &str, items: &[String]) {
+    writeln!(out, "{} = [", key).unwrap();
+    for item in items {
+        writeln!(out, "  \"{}\",", item).unwrap();
+    }
+    writeln!(out, "]\n").unwrap();
+}
+
+fn run(args: &Cli) -> Result<(), Error> {
+    let records = OpTrace::load(&args.trace)?;
+    let first = match records.first() {
+        Some(rec) => rec,
+        None => bail!("{}: empty trace", args.trace.display()),
+    };
+    let op = match TestOp::new(first.opcode) {
+        Some(op) => op,
+        None => bail!("unsupported opcode: {}", first.insn),
+    };
+
+    // All the executions with the same elements become test vectors of the
+    // same test.
+    let (records, skipped): (Vec<_>, Vec<_>) = records
+        .iter()
+        .partition(|rec| TestOp::new(rec.opcode).map(|t| t.opcode()) == Some(op.opcode()));
+
+    let name = match &args.name {
+        Some(name) => name.clone(),
+        None => args
+            .trace
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let path = args.out_dir.join(&name).with_extension("toml");
+    let comment = format!("traced: {} at PC {:03x}", first.insn, first.pc);
+    let asm = op.code(&comment);
+
+    let mut toml = String::new();
+    writeln!(
+        toml,
+        "# Extracted by tracegolden from {} ({} executions). The golden results\n\
+         # are the ones computed by the emulator: regenerate them on the real\n\
+         # hardware with gengolden before fixing a bug.\n",
+        args.trace.display(),
+        records.len()
+    )?;
+    toml_list(&mut toml, "input_desc", &op.input_desc());
+    toml_list(&mut toml, "output_desc", &op.output_desc());
+    writeln!(toml, "rsp_code = \"\"\"\n{}\"\"\"", asm.src)?;
+
+    let mut rsp = Vec::new();
+    for word in asm.code.iter() {
+        rsp.write_u32::<BigEndian>(*word)?;
+    }
+    let mut golden = Vec::new();
+    for (idx, rec) in records.iter().enumerate() {
+        writeln!(
+            toml,
+            "\n[[test]]\nname = \"pc{:03x}_{}\"\ninput = [",
+            rec.pc, idx
+        )?;
+        let input = op.input(rec);
+        for (chunk, name) in input[..12].chunks(4).zip(&["vs", "vt", "vd"]) {
+            let chunk: Vec<_> = chunk
+                .iter()
+                .map(|w| format!("0x{:04X}_{:04X}", w >> 16, w & 0xFFFF))
+                .collect();
+            writeln!(toml, "  {},  # {}", chunk.join(", "), name)?;
+        }
+        for (w, name) in input[12..].iter().zip(&["VCO", "VCC", "VCE", "dummy"]) {
+            writeln!(toml, "  0x{:04X}, # {}", w, name)?;
+        }
+        writeln!(toml, "]")?;
+
+        for word in op.output(rec) {
+            golden.write_u32::<BigEndian>(word)?;
+        }
+    }
+
+    fs::create_dir_all(&args.out_dir)?;
+    fs::write(&path, toml)?;
+    fs::write(path.with_extension("rsp"), rsp)?;
+    fs::write(path.with_extension("golden"), golden)?;
+
+    println!(
+        "Generated: {} ({} test vectors)",
+        path.display(),
+        records.len()
+    );
+    if !skipped.is_empty() {
+        println!(
+            "Skipped {} executions with different elements",
+            skipped.len()
+        );
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = Cli::from_args();
+    if let Err(err) = run(&args) {
+        eprintln!("tracegolden: {}", err);
+        std::process::exit(1);
+    }
+}